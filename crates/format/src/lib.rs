@@ -0,0 +1,435 @@
+//! Formatting of preprocessing directives.
+//!
+//! This crate implements a purely lexical rewriting pass over a source file, which normalizes the
+//! indentation of directives according to their conditional nesting depth, aligns the line
+//! continuations in multi-line macro definitions, and sorts blocks of adjacent `#include`
+//! directives. All other code is left untouched.
+//!
+//! The changes are produced as [suggestions](RenderedSuggestion), which can be applied with
+//! [`Fixits`](source::fixit::Fixits).
+
+#![warn(rust_2018_idioms)]
+
+use lex::raw::{split_lines, RawLine, RawToken, RawTokenKind};
+use lex::PunctKind;
+use source::diag::{Applicability, RenderedSuggestion};
+use source::{SourceId, SourceMap};
+
+/// Controls where directive indentation is placed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndentStyle {
+    /// Remove all directive indentation.
+    None,
+    /// Indent between the `#` and the directive name (`#  if`).
+    AfterHash,
+    /// Indent before the `#` (`  #if`).
+    BeforeHash,
+}
+
+/// Options controlling the formatting of directives.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The indentation style to use for nested directives.
+    pub indent_style: IndentStyle,
+    /// The number of spaces used per nesting level.
+    pub indent_width: usize,
+    /// Whether to align the `\` continuations in multi-line macro definitions.
+    pub align_continuations: bool,
+    /// Whether to sort blocks of adjacent `#include` directives.
+    pub sort_includes: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            indent_style: IndentStyle::AfterHash,
+            indent_width: 2,
+            align_continuations: true,
+            sort_includes: true,
+        }
+    }
+}
+
+/// Formats all directives in the file `id` according to `opts`, returning the edits to apply.
+///
+/// Every edit replaces the contents of a single logical line, leaving its terminating newline
+/// untouched. Edits moving an `#include` directive to another line are marked
+/// [`Applicability::MaybeIncorrect`], as the included headers may depend on their order; they
+/// should only be applied once the preprocessed output of the file is known to be unaffected.
+///
+/// # Panics
+///
+/// Panics if `id` does not refer to a file.
+pub fn format_directives(
+    smap: &SourceMap,
+    id: SourceId,
+    opts: &Options,
+) -> Vec<RenderedSuggestion> {
+    let source = smap.get_source(id);
+    let file_range = source.range;
+    let src = source
+        .as_file()
+        .expect("directives can only be formatted in files")
+        .contents
+        .src();
+
+    let raw_lines = split_lines(src);
+    let mut depth = 0usize;
+
+    let mut lines: Vec<_> = raw_lines
+        .iter()
+        .enumerate()
+        .map(|(idx, line)| {
+            let directive = match parse_directive(src, line) {
+                Some(directive) => directive,
+                None => return FormattedLine::verbatim(idx, &src[line.range]),
+            };
+
+            let line_depth = match directive.name.as_str() {
+                "if" | "ifdef" | "ifndef" => {
+                    depth += 1;
+                    depth - 1
+                }
+                "elif" | "else" => depth.saturating_sub(1),
+                "endif" => {
+                    depth = depth.saturating_sub(1);
+                    depth
+                }
+                _ => depth,
+            };
+
+            format_directive(src, idx, line, &directive, line_depth, opts)
+        })
+        .collect();
+
+    if opts.sort_includes {
+        sort_include_blocks(&mut lines);
+    }
+
+    raw_lines
+        .iter()
+        .zip(lines)
+        .enumerate()
+        .filter(|(_, (raw_line, line))| src[raw_line.range] != line.text)
+        .map(|(idx, (raw_line, line))| {
+            let applicability = if line.orig_idx == idx {
+                Applicability::MachineApplicable
+            } else {
+                Applicability::MaybeIncorrect
+            };
+
+            RenderedSuggestion::new(file_range.subrange(raw_line.range), line.text)
+                .with_applicability(applicability)
+        })
+        .collect()
+}
+
+/// The interesting parts of a directive line.
+struct Directive {
+    /// The spelling of the introducing `#` (or `%:`).
+    hash: String,
+    /// The (cleaned) name of the directive.
+    name: String,
+    /// The offset of the directive name within the source.
+    name_off: usize,
+    /// Whether the line spans multiple physical lines.
+    multiline: bool,
+    /// Whether any escaped newline in the line lies within a literal or comment, in which case
+    /// the whitespace surrounding it is significant.
+    protected_splice: bool,
+    /// Whether the line contains a comment.
+    has_comment: bool,
+}
+
+fn parse_directive(src: &str, line: &RawLine<'_>) -> Option<Directive> {
+    let mut tokens = line
+        .tokens
+        .iter()
+        .filter(|tok| tok.kind != RawTokenKind::Ws);
+
+    let hash = tokens.next()?;
+    if hash.kind != RawTokenKind::Punct(PunctKind::Hash) || hash.content.tainted {
+        return None;
+    }
+
+    let name = tokens.next()?;
+    if name.kind != RawTokenKind::Ident {
+        return None;
+    }
+
+    // Splices within the whitespace surrounding the `#` would be lost when re-indenting.
+    let name_off: usize = name.content.off.into();
+    if src[usize::from(line.range.start())..name_off].contains("\\\n") {
+        return None;
+    }
+
+    let is_comment = |tok: &RawToken<'_>| {
+        matches!(
+            tok.kind,
+            RawTokenKind::LineComment | RawTokenKind::BlockComment { .. }
+        )
+    };
+
+    Some(Directive {
+        hash: hash.content.str.to_owned(),
+        name: name.content.cleaned_str().into_owned(),
+        name_off,
+        multiline: src[line.range].contains("\\\n"),
+        protected_splice: line.tokens.iter().any(|tok| {
+            tok.content.tainted
+                && (is_comment(tok)
+                    || matches!(
+                        tok.kind,
+                        RawTokenKind::Str { .. } | RawTokenKind::Char { .. }
+                    ))
+        }),
+        has_comment: line.tokens.iter().any(is_comment),
+    })
+}
+
+/// A line of output, along with any information needed to sort include blocks.
+struct FormattedLine {
+    text: String,
+    /// The index of the source line from which this line was formatted.
+    orig_idx: usize,
+    /// If this line is an include that can be freely reordered, the nesting depth and header name
+    /// it sorts by.
+    include_key: Option<(usize, String)>,
+}
+
+impl FormattedLine {
+    fn verbatim(orig_idx: usize, text: &str) -> Self {
+        Self {
+            text: text.to_owned(),
+            orig_idx,
+            include_key: None,
+        }
+    }
+}
+
+fn format_directive(
+    src: &str,
+    orig_idx: usize,
+    line: &RawLine<'_>,
+    directive: &Directive,
+    depth: usize,
+    opts: &Options,
+) -> FormattedLine {
+    let indent = " ".repeat(depth * opts.indent_width);
+    let (before, after) = match opts.indent_style {
+        IndentStyle::None => ("", ""),
+        IndentStyle::AfterHash => ("", indent.as_str()),
+        IndentStyle::BeforeHash => (indent.as_str(), ""),
+    };
+
+    let body = &src[directive.name_off..usize::from(line.range.end())];
+    let mut text = format!("{}{}{}{}", before, directive.hash, after, body);
+
+    if directive.multiline
+        && opts.align_continuations
+        && directive.name == "define"
+        && !directive.protected_splice
+    {
+        text = align_continuations(&text);
+    }
+
+    let include_key = Some(&directive.name)
+        .filter(|&name| name == "include" && !directive.multiline && !directive.has_comment)
+        .map(|_| (depth, body["include".len()..].trim().to_owned()));
+
+    FormattedLine {
+        text,
+        orig_idx,
+        include_key,
+    }
+}
+
+/// Pads every physical line but the last in `text` so that the continuation backslashes all lie
+/// one column past the longest line.
+fn align_continuations(text: &str) -> String {
+    let segments: Vec<_> = text.split("\\\n").collect();
+    let (last, continued) = segments.split_last().unwrap();
+
+    let continued: Vec<_> = continued.iter().map(|seg| seg.trim_end()).collect();
+    let width = continued.iter().map(|seg| seg.len()).max().unwrap_or(0);
+
+    let mut ret = String::new();
+    for seg in continued {
+        ret.push_str(&format!("{:width$} \\\n", seg, width = width));
+    }
+    ret.push_str(last);
+    ret
+}
+
+/// Sorts runs of adjacent, freely reorderable `#include` lines at the same nesting depth.
+fn sort_include_blocks(lines: &mut [FormattedLine]) {
+    let mut start = 0;
+
+    while start < lines.len() {
+        let depth = match &lines[start].include_key {
+            Some((depth, _)) => *depth,
+            None => {
+                start += 1;
+                continue;
+            }
+        };
+
+        let len = lines[start..]
+            .iter()
+            .take_while(|line| matches!(&line.include_key, Some((d, _)) if *d == depth))
+            .count();
+
+        lines[start..start + len].sort_by(|a, b| a.include_key.cmp(&b.include_key));
+        start += len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use source::fixit::Fixits;
+    use source::smap::{FileContents, FileName};
+
+    use super::*;
+
+    fn check(src: &str, expected: &str) {
+        check_with(src, expected, &Options::default());
+    }
+
+    fn format(src: &str, opts: &Options) -> (SourceMap, SourceId, Vec<RenderedSuggestion>) {
+        let mut smap = SourceMap::new();
+        let id = smap
+            .create_file(FileName::synth("test.c"), FileContents::new(src), None)
+            .unwrap();
+        let edits = format_directives(&smap, id, opts);
+        (smap, id, edits)
+    }
+
+    fn check_with(src: &str, expected: &str, opts: &Options) {
+        let (smap, id, edits) = format(src, opts);
+
+        let mut fixits = Fixits::new();
+        for edit in &edits {
+            fixits.add(&smap, edit);
+        }
+
+        let fixed = fixits.apply(&smap, id);
+        assert!(fixed.conflicts.is_empty());
+        assert_eq!(fixed.contents, expected);
+    }
+
+    #[test]
+    fn untouched() {
+        check("int x;\n  int y;\n", "int x;\n  int y;\n");
+        check("", "");
+    }
+
+    #[test]
+    fn indent_after_hash() {
+        check(
+            "#ifdef A\n#if B\n# define C 1\n#else\n   #define C 2\n#endif\n#endif\n",
+            "#ifdef A\n#  if B\n#    define C 1\n#  else\n#    define C 2\n#  endif\n#endif\n",
+        );
+    }
+
+    #[test]
+    fn indent_before_hash() {
+        let opts = Options {
+            indent_style: IndentStyle::BeforeHash,
+            ..Options::default()
+        };
+        check_with(
+            "#if A\n#  define B\n#elif C\n#endif",
+            "#if A\n  #define B\n#elif C\n#endif",
+            &opts,
+        );
+    }
+
+    #[test]
+    fn indent_none() {
+        let opts = Options {
+            indent_style: IndentStyle::None,
+            ..Options::default()
+        };
+        check_with(
+            "#if A\n  #  define B\n#endif",
+            "#if A\n#define B\n#endif",
+            &opts,
+        );
+    }
+
+    #[test]
+    fn unbalanced_endif() {
+        check("#endif\n#define A", "#endif\n#define A");
+    }
+
+    #[test]
+    fn digraph_hash() {
+        check(
+            "%:if A\n%:define B\n%:endif",
+            "%:if A\n%:  define B\n%:endif",
+        );
+    }
+
+    #[test]
+    fn align() {
+        check(
+            "#define A(x) \\\n  do { \\\n    f(x);   \\\n  } while (0)\n",
+            "#define A(x) \\\n  do {       \\\n    f(x);    \\\n  } while (0)\n",
+        );
+    }
+
+    #[test]
+    fn align_skips_literals() {
+        let src = "#define A \"abc   \\\n def\"\n";
+        check(src, src);
+    }
+
+    #[test]
+    fn sort_includes() {
+        check(
+            "#include <b.h>\n#include \"z.h\"\n#include <a.h>\n\n#include <c.h>\n",
+            "#include \"z.h\"\n#include <a.h>\n#include <b.h>\n\n#include <c.h>\n",
+        );
+    }
+
+    #[test]
+    fn sort_includes_maybe_incorrect() {
+        let (smap, _, edits) = format(
+            "#include <b.h>\n#include <a.h>\n#if X\n#define Y\n#endif\n",
+            &Options::default(),
+        );
+
+        let applicabilities: Vec<_> = edits
+            .iter()
+            .map(|edit| {
+                (
+                    smap.get_spelling(edit.replacement_range),
+                    edit.applicability,
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            applicabilities,
+            [
+                ("#include <b.h>", Applicability::MaybeIncorrect),
+                ("#include <a.h>", Applicability::MaybeIncorrect),
+                ("#define Y", Applicability::MachineApplicable),
+            ]
+        );
+    }
+
+    #[test]
+    fn sort_includes_keeps_commented() {
+        let src = "#include <b.h> // needed first\n#include <a.h>\n";
+        check(src, src);
+    }
+
+    #[test]
+    fn sort_includes_respects_depth() {
+        check(
+            "#include <b.h>\n#if X\n#include <d.h>\n#include <c.h>\n#endif\n#include <a.h>\n",
+            "#include <b.h>\n#if X\n#  include <c.h>\n#  include <d.h>\n#endif\n#include <a.h>\n",
+        );
+    }
+}
@@ -4,23 +4,71 @@ use std::borrow::{Borrow, Cow};
 use std::hash::BuildHasherDefault;
 use std::hash::Hash;
 use std::marker::PhantomData;
+use std::mem;
+use std::num::NonZeroU32;
 use std::ops::Index;
+use std::{error, fmt};
 
 use indexmap::IndexSet;
 use rustc_hash::FxHasher;
 
+pub use sync::SyncInterner;
+
+mod sync;
+
+/// The maximum number of distinct values an interner can hold.
+pub const MAX_SYMBOLS: usize = u32::MAX as usize;
+
 /// Opaque type used to refer to interned data.
+///
+/// Symbols are 32 bits wide, and `Option<Symbol<T>>` is no larger than `Symbol<T>` itself.
 #[derive(Debug, PartialEq, Eq, Hash)]
 pub struct Symbol<T: ToOwned + ?Sized> {
-    idx: usize,
+    /// The index of the symbol's value in its interner, plus 1.
+    raw: NonZeroU32,
     marker: PhantomData<fn(&Interner<T>) -> &T>,
 }
 
 impl<T: ToOwned + ?Sized> Symbol<T> {
-    fn new(idx: usize) -> Self {
-        Self {
-            idx,
-            marker: PhantomData,
+    /// Creates a symbol referring to the value at index `idx`, which must be less than
+    /// [`MAX_SYMBOLS`].
+    const fn new(idx: usize) -> Self {
+        assert!(idx < MAX_SYMBOLS, "symbol index out of range");
+        Self::from_u32(idx as u32)
+    }
+
+    /// Returns the index of the symbol's value within its interner.
+    fn idx(self) -> usize {
+        self.as_u32() as usize
+    }
+
+    /// Returns the symbol assigned to `vals[idx]` by [`Interner::prefill(vals)`](Interner::prefill).
+    ///
+    /// This allows symbols of well-known values to be named as constants.
+    pub const fn prefilled(idx: usize) -> Self {
+        Self::new(idx)
+    }
+
+    /// Returns a dense numeric representation of the symbol, suitable for indexing side tables.
+    ///
+    /// The symbols returned by an [`Interner`] are numbered consecutively from 0, in the order in
+    /// which their values were first interned.
+    pub const fn as_u32(self) -> u32 {
+        self.raw.get() - 1
+    }
+
+    /// Recreates a symbol from a value returned by [`as_u32()`](#method.as_u32).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `val` is `u32::MAX`, which is never returned by `as_u32()`.
+    pub const fn from_u32(val: u32) -> Self {
+        match NonZeroU32::new(val.wrapping_add(1)) {
+            Some(raw) => Self {
+                raw,
+                marker: PhantomData,
+            },
+            None => panic!("symbol index out of range"),
         }
     }
 }
@@ -34,12 +82,27 @@ impl<T: ToOwned + ?Sized> Clone for Symbol<T> {
     }
 }
 
+/// Error type indicating that a value could not be interned because the interner already holds
+/// [`MAX_SYMBOLS`] values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InternerFullError;
+
+impl fmt::Display for InternerFullError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "interner capacity of {} symbols exceeded", MAX_SYMBOLS)
+    }
+}
+
+impl error::Error for InternerFullError {}
+
 type FxIndexSet<T> = IndexSet<T, BuildHasherDefault<FxHasher>>;
 
 /// A simple interner for types implementing `ToOwned`.
 #[derive(Default)]
 pub struct Interner<T: ToOwned + ?Sized> {
     pool: FxIndexSet<T::Owned>,
+    /// The address and length of the slice passed to `prefill()`, identifying it.
+    prefill: (usize, usize),
 }
 
 impl<T: ToOwned + ?Sized> Interner<T>
@@ -51,17 +114,78 @@ where
     pub fn new() -> Self {
         Self {
             pool: FxIndexSet::with_capacity_and_hasher(0, Default::default()),
+            prefill: (0, 0),
         }
     }
 
+    /// Creates a new interner holding the values in `vals`, where `vals[i]` is identified by
+    /// [`Symbol::prefilled(i)`](Symbol::prefilled).
+    ///
+    /// Symbols of these values can then be recognized with
+    /// [`prefilled_index()`](#method.prefilled_index) instead of comparing the values themselves.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `vals` contains duplicates.
+    pub fn prefill(vals: &'static [&'static T]) -> Self
+    where
+        T: 'static,
+    {
+        let mut pool = FxIndexSet::with_capacity_and_hasher(vals.len(), Default::default());
+        for &val in vals {
+            assert!(
+                pool.insert(val.to_owned()),
+                "duplicate value in interner prefill"
+            );
+        }
+
+        Self {
+            pool,
+            prefill: slice_id(vals),
+        }
+    }
+
+    /// Returns the index of the value identified by `sym` within `vals`, if this interner was
+    /// created by [`prefill(vals)`](#method.prefill) and `sym` refers to one of them.
+    ///
+    /// This check never inspects any interned values.
+    pub fn prefilled_index(&self, sym: Symbol<T>, vals: &'static [&'static T]) -> Option<usize>
+    where
+        T: 'static,
+    {
+        let prefilled = self.is_prefilled_with(vals) && sym.idx() < vals.len();
+        prefilled.then_some(sym.idx())
+    }
+
+    /// Checks whether this interner was created by [`prefill(vals)`](#method.prefill).
+    ///
+    /// `vals` is compared by address only.
+    pub fn is_prefilled_with(&self, vals: &'static [&'static T]) -> bool
+    where
+        T: 'static,
+    {
+        self.prefill == slice_id(vals)
+    }
+
     /// Interns the provided value, upgrading it to an owned one if necessary.
     ///
     /// Returns a symbol uniquely identifying the interned value. If the same value is interned
     /// multiple times, the same symbol will be returned every time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `val` is new and the interner already holds [`MAX_SYMBOLS`] values; see
+    /// [`try_intern()`](#method.try_intern) for a non-panicking version.
     pub fn intern(&mut self, val: &T) -> Symbol<T> {
         self.intern_cow(Cow::Borrowed(val))
     }
 
+    /// Interns the provided value like [`intern()`](#method.intern), returning an error if it is
+    /// new and the interner is full.
+    pub fn try_intern(&mut self, val: &T) -> Result<Symbol<T>, InternerFullError> {
+        self.try_intern_cow(Cow::Borrowed(val))
+    }
+
     /// Interns the provided value, storing it as an owned one if necessary.
     ///
     /// This method enables less potential allocations than [`intern()`](#method.intern) if `val` is
@@ -69,13 +193,54 @@ where
     ///
     /// Returns a symbol uniquely identifying the interned value. If the same value is interned
     /// multiple times, the same symbol will be returned every time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `val` is new and the interner already holds [`MAX_SYMBOLS`] values; see
+    /// [`try_intern_cow()`](#method.try_intern_cow) for a non-panicking version.
     pub fn intern_cow(&mut self, val: Cow<T>) -> Symbol<T> {
+        match self.try_intern_cow(val) {
+            Ok(sym) => sym,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Interns the provided value like [`intern_cow()`](#method.intern_cow), returning an error if
+    /// it is new and the interner is full.
+    pub fn try_intern_cow(&mut self, val: Cow<T>) -> Result<Symbol<T>, InternerFullError> {
         let idx = match self.pool.get_full(&*val) {
             Some((idx, _)) => idx,
+            None if self.pool.len() >= MAX_SYMBOLS => return Err(InternerFullError),
             None => self.pool.insert_full(val.into_owned()).0,
         };
 
-        Symbol::new(idx)
+        Ok(Symbol::new(idx))
+    }
+
+    /// Returns the symbol identifying `val`, if it has already been interned.
+    ///
+    /// Unlike [`intern()`](#method.intern), this never modifies the interner.
+    pub fn get(&self, val: &T) -> Option<Symbol<T>> {
+        self.pool.get_index_of(val).map(Symbol::new)
+    }
+
+    /// Returns an iterator over all interned values and their symbols, in the order in which they
+    /// were first interned.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (Symbol<T>, &T)> + '_ {
+        self.pool
+            .iter()
+            .enumerate()
+            .map(|(idx, val)| (Symbol::new(idx), val.borrow()))
+    }
+
+    /// Returns the number of distinct values interned so far.
+    pub fn len(&self) -> usize {
+        self.pool.len()
+    }
+
+    /// Returns `true` if no values have been interned yet.
+    pub fn is_empty(&self) -> bool {
+        self.pool.is_empty()
     }
 
     /// Resolves the symbol to its interned content.
@@ -86,12 +251,24 @@ where
     /// different interner.
     pub fn resolve(&self, sym: Symbol<T>) -> &T {
         self.pool
-            .get_index(sym.idx)
+            .get_index(sym.idx())
             .expect("symbol used with wrong interner")
             .borrow()
     }
 }
 
+impl Interner<str> {
+    /// Returns an estimate of the number of bytes of heap memory used by the interner, including
+    /// the interned strings and the lookup table.
+    pub fn heap_size(&self) -> usize {
+        // Every entry stores its hash alongside the value, and the table holds an index to it.
+        let entry_size = mem::size_of::<String>() + 2 * mem::size_of::<usize>();
+        let strings_size: usize = self.pool.iter().map(String::capacity).sum();
+
+        self.pool.capacity() * entry_size + strings_size
+    }
+}
+
 impl<T: ToOwned + ?Sized> Index<Symbol<T>> for Interner<T>
 where
     T: Hash + Eq,
@@ -104,6 +281,11 @@ where
     }
 }
 
+/// Returns a value identifying `vals` for comparison purposes.
+fn slice_id<T: ?Sized>(vals: &[&T]) -> (usize, usize) {
+    (vals.as_ptr() as usize, vals.len())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -121,4 +303,59 @@ mod tests {
         assert_eq!(&interner[hi], "hi");
         assert_eq!(&interner[bye], "bye");
     }
+
+    #[test]
+    fn get_and_iter() {
+        let mut interner = Interner::new();
+
+        let hi = interner.intern("hi");
+        let bye = interner.intern("bye");
+
+        assert_eq!(interner.get("hi"), Some(hi));
+        assert_eq!(interner.get("what"), None);
+        assert_eq!(interner.len(), 2);
+
+        let entries: Vec<_> = interner.iter().collect();
+        assert_eq!(entries, [(hi, "hi"), (bye, "bye")]);
+    }
+
+    #[test]
+    fn symbol_repr() {
+        assert_eq!(mem::size_of::<Symbol<str>>(), 4);
+        assert_eq!(mem::size_of::<Option<Symbol<str>>>(), 4);
+
+        let mut interner = Interner::new();
+        let a = interner.intern("a");
+        let b = interner.intern("b");
+
+        assert_eq!(a.as_u32(), 0);
+        assert_eq!(b.as_u32(), 1);
+        assert_eq!(Symbol::from_u32(b.as_u32()), b);
+        assert_eq!(interner.len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "symbol index out of range")]
+    fn symbol_from_u32_max() {
+        Symbol::<str>::from_u32(u32::MAX);
+    }
+
+    #[test]
+    fn prefill() {
+        static KEYWORDS: &[&str] = &["if", "else", "while"];
+        const ELSE: Symbol<str> = Symbol::prefilled(1);
+
+        let mut interner = Interner::prefill(KEYWORDS);
+
+        assert_eq!(interner.intern("else"), ELSE);
+        assert_eq!(&interner[ELSE], "else");
+        assert_eq!(interner.prefilled_index(ELSE, KEYWORDS), Some(1));
+
+        let ident = interner.intern("x");
+        assert_eq!(interner.prefilled_index(ident, KEYWORDS), None);
+
+        let other = Interner::<str>::new();
+        assert!(!other.is_prefilled_with(KEYWORDS));
+        assert_eq!(other.prefilled_index(ELSE, KEYWORDS), None);
+    }
 }
@@ -0,0 +1,193 @@
+//! A thread-safe variant of [`Interner`](crate::Interner).
+
+use std::borrow::{Borrow, Cow};
+use std::hash::{Hash, Hasher};
+use std::sync::RwLock;
+
+use rustc_hash::FxHasher;
+
+use crate::{FxIndexSet, InternerFullError, Symbol, MAX_SYMBOLS};
+
+/// The number of bits of a symbol's index used to identify the shard holding its value.
+const SHARD_BITS: u32 = 4;
+const SHARD_COUNT: usize = 1 << SHARD_BITS;
+
+/// The maximum number of values each shard can hold, so that all symbol indices are in range.
+const MAX_SHARD_SYMBOLS: usize = MAX_SYMBOLS >> SHARD_BITS;
+
+/// An interner for types implementing `ToOwned` that can be shared by several threads.
+///
+/// Values are distributed among independently locked shards according to their hashes, so that
+/// threads interning different values rarely contend with each other. As other threads may be
+/// interning values at any time, interned values are accessed through a callback (see
+/// [`Self::with_resolved()`]) rather than by reference.
+///
+/// Symbols returned by a `SyncInterner` cannot be used with an [`Interner`](crate::Interner), and
+/// vice versa.
+pub struct SyncInterner<T: ToOwned + ?Sized> {
+    shards: Vec<RwLock<FxIndexSet<T::Owned>>>,
+}
+
+impl<T: ToOwned + ?Sized> Default for SyncInterner<T> {
+    fn default() -> Self {
+        Self {
+            shards: (0..SHARD_COUNT)
+                .map(|_| RwLock::new(FxIndexSet::default()))
+                .collect(),
+        }
+    }
+}
+
+impl<T: ToOwned + ?Sized> SyncInterner<T>
+where
+    T: Hash + Eq,
+    T::Owned: Hash + Eq,
+{
+    /// Creates a new, empty interner.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Interns the provided value, upgrading it to an owned one if necessary.
+    ///
+    /// Returns a symbol uniquely identifying the interned value. If the same value is interned
+    /// multiple times (possibly from different threads), the same symbol will be returned every
+    /// time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `val` is new and the interner has run out of symbols.
+    pub fn intern(&self, val: &T) -> Symbol<T> {
+        self.intern_cow(Cow::Borrowed(val))
+    }
+
+    /// Interns the provided value, storing it as an owned one if necessary.
+    ///
+    /// Returns a symbol uniquely identifying the interned value. If the same value is interned
+    /// multiple times (possibly from different threads), the same symbol will be returned every
+    /// time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `val` is new and the interner has run out of symbols.
+    pub fn intern_cow(&self, val: Cow<'_, T>) -> Symbol<T> {
+        let shard_idx = shard_of(&*val);
+        let shard = &self.shards[shard_idx];
+
+        let existing = shard.read().unwrap().get_index_of(&*val);
+        let local_idx = match existing {
+            Some(idx) => idx,
+            // Another thread may have inserted the value in the meantime, in which case its index
+            // is returned here.
+            None => {
+                let mut shard = shard.write().unwrap();
+                if shard.len() >= MAX_SHARD_SYMBOLS && !shard.contains(&*val) {
+                    panic!("{}", InternerFullError);
+                }
+                shard.insert_full(val.into_owned()).0
+            }
+        };
+
+        Symbol::new(local_idx << SHARD_BITS | shard_idx)
+    }
+
+    /// Returns the symbol identifying `val`, if it has already been interned.
+    pub fn get(&self, val: &T) -> Option<Symbol<T>> {
+        let shard_idx = shard_of(val);
+        let local_idx = self.shards[shard_idx].read().unwrap().get_index_of(val)?;
+        Some(Symbol::new(local_idx << SHARD_BITS | shard_idx))
+    }
+
+    /// Invokes `f` with the value identified by `sym`, returning its result.
+    ///
+    /// The shard holding the value cannot be modified while `f` runs, so `f` should avoid
+    /// interning values itself.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sym` has no associated data in this interner. This can happen if it came from a
+    /// different interner.
+    pub fn with_resolved<R>(&self, sym: Symbol<T>, f: impl FnOnce(&T) -> R) -> R {
+        let shard = self.shards[sym.idx() & (SHARD_COUNT - 1)].read().unwrap();
+        let val = shard
+            .get_index(sym.idx() >> SHARD_BITS)
+            .expect("symbol used with wrong interner");
+        f(val.borrow())
+    }
+
+    /// Returns an owned copy of the value identified by `sym`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sym` has no associated data in this interner. This can happen if it came from a
+    /// different interner.
+    pub fn resolve_owned(&self, sym: Symbol<T>) -> T::Owned {
+        self.with_resolved(sym, T::to_owned)
+    }
+
+    /// Returns the number of distinct values interned so far.
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.read().unwrap().len())
+            .sum()
+    }
+
+    /// Returns `true` if no values have been interned yet.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Selects the shard in which `val` is stored.
+fn shard_of<T: Hash + ?Sized>(val: &T) -> usize {
+    let mut hasher = FxHasher::default();
+    val.hash(&mut hasher);
+
+    // The low bits of the hash select the bucket within the shard's table, so use the high ones
+    // here to avoid crowding values into a fraction of the buckets.
+    (hasher.finish() >> (64 - SHARD_BITS)) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn basic_str() {
+        let interner = SyncInterner::new();
+
+        let hi = interner.intern("hi");
+        let bye = interner.intern("bye");
+        let hi2 = interner.intern("hi");
+
+        assert_eq!(hi, hi2);
+        assert_ne!(hi, bye);
+        assert_eq!(interner.get("bye"), Some(bye));
+        assert_eq!(interner.get("what"), None);
+        assert_eq!(interner.resolve_owned(hi), "hi");
+        assert_eq!(interner.with_resolved(bye, str::len), 3);
+        assert_eq!(interner.len(), 2);
+    }
+
+    #[test]
+    fn concurrent() {
+        let interner = SyncInterner::<str>::new();
+        let words: Vec<_> = (0..200).map(|i| format!("word{}", i)).collect();
+
+        let syms: Vec<Vec<_>> = thread::scope(|s| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| s.spawn(|| words.iter().map(|w| interner.intern(w)).collect()))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        assert!(syms.windows(2).all(|pair| pair[0] == pair[1]));
+        assert_eq!(interner.len(), words.len());
+        for (word, &sym) in words.iter().zip(&syms[0]) {
+            assert_eq!(&interner.resolve_owned(sym), word);
+        }
+    }
+}
@@ -0,0 +1,334 @@
+//! Conversion of preprocessing tokens to C tokens (translation phase 7, §5.1.1.2p7).
+//!
+//! This classifies identifiers as keywords and interprets the spellings of literals, reporting any
+//! that are malformed.
+
+use std::borrow::Cow;
+
+use source::{DResult, SourceRange};
+
+use crate::literal::{self, ByteInterner, CharLiteral, NumberLiteral, StrLiteral};
+use crate::literal::{FloatLiteral, IntLiteral};
+use crate::{get_cleaned_spelling, Keyword, LexCtx, PunctKind, Symbol, Token, TokenKind};
+
+/// The kinds of C tokens, as produced by [`convert_token()`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CTokenKind {
+    Unknown,
+    Eof,
+
+    Punct(PunctKind),
+    Ident(Symbol),
+    Keyword(Keyword),
+
+    Int(IntLiteral),
+    Float(FloatLiteral),
+    Char(CharLiteral),
+    Str(StrLiteral),
+}
+
+/// A token produced by [`convert_token()`].
+pub type CToken = Token<CTokenKind>;
+
+/// Converts the preprocessing token `tok` to a C token, reporting any errors in its literal value.
+///
+/// The contents of string literals are interned into `bytes`. Note that adjacent string literals
+/// are not concatenated.
+pub fn convert_token(
+    ctx: &mut LexCtx<'_, '_>,
+    bytes: &mut ByteInterner,
+    tok: Token,
+) -> DResult<CToken> {
+    let kind = match tok.data {
+        // Comments and header names carry no meaning past preprocessing, so any that reach this
+        // point are treated like stray characters.
+        TokenKind::Unknown | TokenKind::Comment(_) | TokenKind::HeaderName(_) => {
+            CTokenKind::Unknown
+        }
+        TokenKind::Eof => CTokenKind::Eof,
+        TokenKind::Punct(punct) => CTokenKind::Punct(punct),
+
+        TokenKind::Ident(ident) => match Keyword::from_symbol(ident, ctx.interner) {
+            Some(kw) => CTokenKind::Keyword(kw),
+            None => CTokenKind::Ident(ident),
+        },
+
+        TokenKind::Number(sym) => {
+            let spelling = literal_spelling(ctx, sym, tok.range);
+            match literal::parse_number(ctx, &spelling, tok.range)? {
+                NumberLiteral::Int(lit) => CTokenKind::Int(lit),
+                NumberLiteral::Float(lit) => CTokenKind::Float(lit),
+            }
+        }
+
+        TokenKind::Char(sym) => {
+            let spelling = literal_spelling(ctx, sym, tok.range);
+            CTokenKind::Char(literal::parse_char(ctx, &spelling, tok.range)?)
+        }
+
+        TokenKind::Str(sym) => {
+            let spelling = literal_spelling(ctx, sym, tok.range);
+            CTokenKind::Str(literal::parse_str(ctx, bytes, &spelling, tok.range)?)
+        }
+    };
+
+    Ok(Token::new(kind, tok.range))
+}
+
+/// Returns the full spelling of the literal `sym` covering `range`, even if only a preview of it
+/// was interned.
+fn literal_spelling(ctx: &LexCtx<'_, '_>, sym: Symbol, range: SourceRange) -> String {
+    let spelling = if ctx.is_literal_truncated(range) {
+        get_cleaned_spelling(ctx.smap, range)
+    } else {
+        Cow::Borrowed(&ctx.interner[sym])
+    };
+    spelling.into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use source::diag::{DiagnosticId, RecordingSink};
+    use source::{DiagManager, SourceMap};
+
+    use crate::literal::{Encoding, FloatType, IntType};
+    use crate::{Interner, SyntheticSource};
+
+    use super::*;
+
+    /// Converts the single token spelled `src`, returning its kind, the contents of string
+    /// literals and the diagnostics reported.
+    fn convert(src: &str) -> (CTokenKind, Vec<u8>, Vec<String>) {
+        convert_with(src, |_| {})
+    }
+
+    /// Like [`convert()`], but allows `configure` to set additional options on the diagnostic
+    /// manager.
+    fn convert_with(
+        src: &str,
+        configure: impl FnOnce(&mut DiagManager<'_>),
+    ) -> (CTokenKind, Vec<u8>, Vec<String>) {
+        let diags = RefCell::new(Vec::new());
+        let mut manager = DiagManager::new(RecordingSink(&diags), None);
+        configure(&mut manager);
+        let mut interner = Interner::new();
+        let mut smap = SourceMap::new();
+        let mut bytes = ByteInterner::new();
+
+        let mut ctx = LexCtx::new(&mut interner, &mut manager, &mut smap);
+        let tokens = SyntheticSource::builder(&mut ctx)
+            .tokens([src])
+            .build()
+            .unwrap()
+            .into_tokens();
+        assert_eq!(tokens.len(), 1);
+
+        let kind = convert_token(&mut ctx, &mut bytes, tokens[0]).unwrap().data;
+        let contents = match kind {
+            CTokenKind::Str(lit) => bytes[lit.contents].to_vec(),
+            _ => Vec::new(),
+        };
+
+        drop(manager);
+        (kind, contents, diags.into_inner())
+    }
+
+    fn check_int(src: &str, value: u64, ty: IntType) {
+        let (kind, _, diags) = convert(src);
+        assert_eq!(kind, CTokenKind::Int(IntLiteral { value, ty }), "{}", src);
+        assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+    }
+
+    fn check_float(src: &str, value: f64, ty: FloatType) {
+        let (kind, _, diags) = convert(src);
+        assert_eq!(
+            kind,
+            CTokenKind::Float(FloatLiteral { value, ty }),
+            "{}",
+            src
+        );
+        assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+    }
+
+    fn check_char(src: &str, encoding: Encoding, value: u32) {
+        let (kind, _, diags) = convert(src);
+        assert_eq!(
+            kind,
+            CTokenKind::Char(CharLiteral { encoding, value }),
+            "{}",
+            src
+        );
+        assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+    }
+
+    fn check_str(src: &str, encoding: Encoding, contents: &[u8]) {
+        let (kind, actual, diags) = convert(src);
+        assert!(
+            matches!(kind, CTokenKind::Str(lit) if lit.encoding == encoding),
+            "{}",
+            src
+        );
+        assert_eq!(actual, contents, "{}", src);
+        assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+    }
+
+    fn check_diags(src: &str, expected: &[&str]) {
+        let (_, _, diags) = convert(src);
+        assert_eq!(diags, expected, "{}", src);
+    }
+
+    #[test]
+    fn keywords() {
+        assert_eq!(convert("while").0, CTokenKind::Keyword(Keyword::While));
+        assert_eq!(
+            convert("_Static_assert").0,
+            CTokenKind::Keyword(Keyword::StaticAssert)
+        );
+        assert!(matches!(convert("whilst").0, CTokenKind::Ident(_)));
+    }
+
+    #[test]
+    fn int_literals() {
+        check_int("0", 0, IntType::Int);
+        check_int("42", 42, IntType::Int);
+        check_int("0x1F", 31, IntType::Int);
+        check_int("017", 15, IntType::Int);
+        check_int("2147483648", 2147483648, IntType::Long);
+        check_int("0x80000000", 0x80000000, IntType::UInt);
+        check_int("0xFFFFFFFFFFFFFFFF", u64::MAX, IntType::ULong);
+        check_int("1u", 1, IntType::UInt);
+        check_int("1l", 1, IntType::Long);
+        check_int("1LL", 1, IntType::LongLong);
+        check_int("1ull", 1, IntType::ULongLong);
+        check_int("1LLU", 1, IntType::ULongLong);
+        check_int("1Lu", 1, IntType::ULong);
+    }
+
+    #[test]
+    fn invalid_int_literals() {
+        check_diags("08", &["error: invalid digit '8' in octal literal"]);
+        check_diags("0x", &["error: hexadecimal literal has no digits"]);
+        check_diags("1lL", &["error: invalid suffix 'lL' on integer literal"]);
+        check_diags("1uu", &["error: invalid suffix 'uu' on integer literal"]);
+        check_diags("12abc", &["error: invalid suffix 'abc' on integer literal"]);
+        check_diags(
+            "18446744073709551616",
+            &["error: integer literal is too large to be represented in any integer type"],
+        );
+        check_diags(
+            "9223372036854775808",
+            &[
+                "warning: integer literal is too large to be represented in a signed integer \
+               type, interpreting as unsigned",
+            ],
+        );
+    }
+
+    #[test]
+    fn implicitly_unsigned_disabled() {
+        let disable = |manager: &mut DiagManager<'_>| {
+            manager.set_enabled(DiagnosticId::ImplicitlyUnsignedLiteral, false)
+        };
+
+        let (kind, _, diags) = convert_with("9223372036854775808", disable);
+        assert_eq!(
+            kind,
+            CTokenKind::Int(IntLiteral {
+                value: 9223372036854775808,
+                ty: IntType::ULongLong
+            })
+        );
+        assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+
+        let (_, _, diags) = convert_with("18446744073709551616", disable);
+        assert_eq!(
+            diags,
+            ["error: integer literal is too large to be represented in any integer type"]
+        );
+    }
+
+    #[test]
+    fn float_literals() {
+        check_float("1.5", 1.5, FloatType::Double);
+        check_float("1.", 1.0, FloatType::Double);
+        check_float(".25f", 0.25, FloatType::Float);
+        check_float("1e3", 1000.0, FloatType::Double);
+        check_float("2.5E-1L", 0.25, FloatType::LongDouble);
+        check_float("0x1p4", 16.0, FloatType::Double);
+        check_float("0x1.8p1", 3.0, FloatType::Double);
+        check_float("0X.8P0f", 0.5, FloatType::Float);
+    }
+
+    #[test]
+    fn invalid_float_literals() {
+        check_diags("1e", &["error: exponent has no digits"]);
+        check_diags("1e+", &["error: exponent has no digits"]);
+        check_diags(
+            "0x1.8",
+            &["error: hexadecimal floating literal requires an exponent"],
+        );
+        check_diags("1.0q", &["error: invalid suffix 'q' on floating literal"]);
+        check_diags(
+            "1e999",
+            &["warning: floating literal is too large for type 'double'"],
+        );
+        check_diags(
+            "1e39f",
+            &["warning: floating literal is too large for type 'float'"],
+        );
+    }
+
+    #[test]
+    fn char_literals() {
+        check_char("'a'", Encoding::Plain, 0x61);
+        check_char(r"'\n'", Encoding::Plain, 0x0a);
+        check_char(r"'\0'", Encoding::Plain, 0);
+        check_char(r"'\377'", Encoding::Plain, 0xff);
+        check_char(r"'\x7f'", Encoding::Plain, 0x7f);
+        check_char(r"'\''", Encoding::Plain, 0x27);
+        check_char("L'\u{3b1}'", Encoding::Wide, 0x3b1);
+        check_char(r"u'é'", Encoding::Utf16, 0xe9);
+        check_char(r"U'\U0001F600'", Encoding::Utf32, 0x1f600);
+    }
+
+    #[test]
+    fn invalid_char_literals() {
+        check_diags("''", &["error: empty character literal"]);
+        check_diags("'ab'", &["warning: multi-character character literal"]);
+        check_diags(
+            "u'ab'",
+            &["warning: extraneous characters in character literal ignored"],
+        );
+        check_diags(r"'\x100'", &["error: hex escape sequence out of range"]);
+        check_diags(r"'\x'", &["error: \\x used with no following hex digits"]);
+        check_diags(r"'\u12'", &["error: incomplete universal character name"]);
+        check_diags(r"'\uD800'", &["error: invalid universal character"]);
+        check_diags(r"'\q'", &["warning: unknown escape sequence '\\q'"]);
+    }
+
+    #[test]
+    fn str_literals() {
+        check_str(r#""""#, Encoding::Plain, b"");
+        check_str(r#""a\tb""#, Encoding::Plain, b"a\tb");
+        check_str(r#""\x41\102\"""#, Encoding::Plain, b"AB\"");
+        check_str("u8\"\u{e9}\"", Encoding::Utf8, &[0xc3, 0xa9]);
+        check_str(
+            r#"u"a\U0001F600""#,
+            Encoding::Utf16,
+            &[0x61, 0, 0x3d, 0xd8, 0x00, 0xde],
+        );
+        check_str(r#"L"a""#, Encoding::Wide, &[0x61, 0, 0, 0]);
+        check_str(r#"U"\xFFFFFFFF""#, Encoding::Utf32, &[0xff; 4]);
+    }
+
+    #[test]
+    fn invalid_str_literals() {
+        check_diags(r#""\400""#, &["error: octal escape sequence out of range"]);
+        check_diags(
+            r#"u"\x10000""#,
+            &["error: hex escape sequence out of range"],
+        );
+    }
+}
@@ -0,0 +1,249 @@
+//! Token stream diffing, for golden tests and comparisons against other compilers.
+//!
+//! Comparing two preprocessed outputs as strings is brittle, as it is sensitive to whitespace and
+//! line breaks. Instead, [`diff_token_streams()`] compares the streams token-by-token (by
+//! spelling) and produces a minimal list of aligned edits.
+
+use std::fmt;
+use std::ops::Range;
+
+use crate::{LexCtx, Token};
+
+/// The different kinds of edits that can appear in a token diff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenDiffKind {
+    /// Tokens present only in the new stream.
+    Insert,
+    /// Tokens present only in the old stream.
+    Delete,
+    /// Tokens in the old stream replaced by different tokens in the new stream.
+    Replace,
+}
+
+/// A single edit transforming a run of tokens in the old stream into a run of tokens in the new
+/// stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenDiff {
+    /// The kind of edit.
+    pub kind: TokenDiffKind,
+    /// The (index) range of affected tokens in the old stream. This is empty for insertions, and
+    /// indicates the position at which the tokens should be inserted.
+    pub old_range: Range<usize>,
+    /// The (index) range of affected tokens in the new stream. This is empty for deletions.
+    pub new_range: Range<usize>,
+    /// The spellings of the tokens in `old_range`.
+    pub old_spellings: Vec<String>,
+    /// The spellings of the tokens in `new_range`.
+    pub new_spellings: Vec<String>,
+}
+
+impl fmt::Display for TokenDiff {
+    /// Formats the edit as a unified-diff-style hunk, with one token per line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "@@ -{},{} +{},{} @@",
+            self.old_range.start + 1,
+            self.old_range.len(),
+            self.new_range.start + 1,
+            self.new_range.len()
+        )?;
+
+        for spelling in &self.old_spellings {
+            writeln!(f, "- {}", spelling)?;
+        }
+
+        for spelling in &self.new_spellings {
+            writeln!(f, "+ {}", spelling)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns an object that implements [`fmt::Display`] for printing all of `diffs` in sequence.
+pub fn display_token_diffs(diffs: &[TokenDiff]) -> DisplayDiffs<'_> {
+    DisplayDiffs { diffs }
+}
+
+/// Helper for printing a list of token diffs, returned by [`display_token_diffs()`].
+pub struct DisplayDiffs<'a> {
+    diffs: &'a [TokenDiff],
+}
+
+impl fmt::Display for DisplayDiffs<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.diffs.iter().try_for_each(|diff| write!(f, "{}", diff))
+    }
+}
+
+/// Collects the spellings of `tokens`, for use with [`diff_token_streams()`].
+pub fn spell_tokens(ctx: &LexCtx<'_, '_>, tokens: &[Token]) -> Vec<String> {
+    tokens
+        .iter()
+        .map(|tok| tok.display(ctx).to_string())
+        .collect()
+}
+
+/// Computes a minimal list of edits transforming the token spellings in `old` into those in `new`.
+///
+/// Adjacent deletions and insertions are merged into a single replacement. An empty list is
+/// returned if the streams are identical.
+pub fn diff_token_streams<A: AsRef<str>, B: AsRef<str>>(old: &[A], new: &[B]) -> Vec<TokenDiff> {
+    let eq = |i: usize, j: usize| old[i].as_ref() == new[j].as_ref();
+
+    // Trim the common prefix and suffix first, as they are usually the bulk of the streams and
+    // the quadratic part of the algorithm below need not see them.
+    let prefix = (0..old.len().min(new.len()))
+        .take_while(|&i| eq(i, i))
+        .count();
+    let suffix = (0..old.len().min(new.len()) - prefix)
+        .take_while(|&i| eq(old.len() - 1 - i, new.len() - 1 - i))
+        .count();
+
+    let old_mid = prefix..old.len() - suffix;
+    let new_mid = prefix..new.len() - suffix;
+    let (n, m) = (old_mid.len(), new_mid.len());
+
+    // `lcs[i][j]` holds the length of the longest common subsequence of `old_mid[i..]` and
+    // `new_mid[j..]`.
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if eq(prefix + i, prefix + j) {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diffs = Vec::new();
+    let mut pending: Option<(Range<usize>, Range<usize>)> = None;
+
+    let mut flush = |pending: &mut Option<(Range<usize>, Range<usize>)>| {
+        if let Some((old_range, new_range)) = pending.take() {
+            diffs.push(make_diff(old, new, old_range, new_range));
+        }
+    };
+
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && eq(prefix + i, prefix + j) {
+            flush(&mut pending);
+            i += 1;
+            j += 1;
+            continue;
+        }
+
+        let (old_range, new_range) =
+            pending.get_or_insert((prefix + i..prefix + i, prefix + j..prefix + j));
+
+        if j == m || (i < n && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            old_range.end += 1;
+            i += 1;
+        } else {
+            new_range.end += 1;
+            j += 1;
+        }
+    }
+    flush(&mut pending);
+
+    diffs
+}
+
+fn make_diff<A: AsRef<str>, B: AsRef<str>>(
+    old: &[A],
+    new: &[B],
+    old_range: Range<usize>,
+    new_range: Range<usize>,
+) -> TokenDiff {
+    let kind = match (old_range.is_empty(), new_range.is_empty()) {
+        (true, _) => TokenDiffKind::Insert,
+        (_, true) => TokenDiffKind::Delete,
+        _ => TokenDiffKind::Replace,
+    };
+
+    TokenDiff {
+        kind,
+        old_spellings: old[old_range.clone()]
+            .iter()
+            .map(|s| s.as_ref().to_owned())
+            .collect(),
+        new_spellings: new[new_range.clone()]
+            .iter()
+            .map(|s| s.as_ref().to_owned())
+            .collect(),
+        old_range,
+        new_range,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(old: &str, new: &str) -> Vec<TokenDiff> {
+        let old: Vec<_> = old.split_whitespace().collect();
+        let new: Vec<_> = new.split_whitespace().collect();
+        diff_token_streams(&old, &new)
+    }
+
+    #[test]
+    fn identical() {
+        assert!(diff("int x = 5 ;", "int x = 5 ;").is_empty());
+        assert!(diff("", "").is_empty());
+    }
+
+    #[test]
+    fn insert() {
+        let diffs = diff("int x ;", "int x = 5 ;");
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].kind, TokenDiffKind::Insert);
+        assert_eq!(diffs[0].old_range, 2..2);
+        assert_eq!(diffs[0].new_range, 2..4);
+        assert_eq!(diffs[0].new_spellings, vec!["=", "5"]);
+    }
+
+    #[test]
+    fn delete() {
+        let diffs = diff("a b c d", "a d");
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].kind, TokenDiffKind::Delete);
+        assert_eq!(diffs[0].old_range, 1..3);
+        assert_eq!(diffs[0].new_range, 1..1);
+        assert_eq!(diffs[0].old_spellings, vec!["b", "c"]);
+    }
+
+    #[test]
+    fn replace() {
+        let diffs = diff("f ( 1 , 2 )", "f ( 3 , 2 )");
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].kind, TokenDiffKind::Replace);
+        assert_eq!(diffs[0].old_range, 2..3);
+        assert_eq!(diffs[0].new_range, 2..3);
+    }
+
+    #[test]
+    fn multiple_hunks() {
+        let diffs = diff("a b c d e", "x b c e y");
+        let kinds: Vec<_> = diffs.iter().map(|diff| diff.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenDiffKind::Replace,
+                TokenDiffKind::Delete,
+                TokenDiffKind::Insert
+            ]
+        );
+    }
+
+    #[test]
+    fn format() {
+        let diffs = diff("a b c", "a x c");
+        assert_eq!(
+            display_token_diffs(&diffs).to_string(),
+            "@@ -2,1 +2,1 @@\n- b\n+ x\n"
+        );
+    }
+}
@@ -0,0 +1,160 @@
+//! C keywords (§6.4.1).
+
+use crate::{Interner, Symbol};
+
+/// A C11 keyword.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Keyword {
+    Alignof,
+    Auto,
+    Break,
+    Case,
+    Char,
+    Const,
+    Continue,
+    Default,
+    Do,
+    Double,
+    Else,
+    Enum,
+    Extern,
+    Float,
+    For,
+    Goto,
+    If,
+    Inline,
+    Int,
+    Long,
+    Register,
+    Restrict,
+    Return,
+    Short,
+    Signed,
+    Sizeof,
+    Static,
+    Struct,
+    Switch,
+    Typedef,
+    Union,
+    Unsigned,
+    Void,
+    Volatile,
+    While,
+    Alignas,
+    Atomic,
+    Bool,
+    Complex,
+    Generic,
+    Imaginary,
+    Noreturn,
+    StaticAssert,
+    ThreadLocal,
+}
+
+macro_rules! keywords {
+    ($($spelling:literal => $kw:ident,)*) => {
+        /// The spellings of all keywords, which are pre-interned by [`keyword_interner()`].
+        static SPELLINGS: &[&str] = &[$($spelling),*];
+
+        /// The keywords spelled by the corresponding entries of `SPELLINGS`.
+        static KEYWORDS: &[Keyword] = &[$(Keyword::$kw),*];
+
+        impl Keyword {
+            /// Returns the keyword spelled `spelling`, if any.
+            pub fn from_spelling(spelling: &str) -> Option<Self> {
+                let kw = match spelling {
+                    $($spelling => Self::$kw,)*
+                    _ => return None,
+                };
+
+                Some(kw)
+            }
+        }
+    };
+}
+
+keywords! {
+    "alignof" => Alignof,
+    "auto" => Auto,
+    "break" => Break,
+    "case" => Case,
+    "char" => Char,
+    "const" => Const,
+    "continue" => Continue,
+    "default" => Default,
+    "do" => Do,
+    "double" => Double,
+    "else" => Else,
+    "enum" => Enum,
+    "extern" => Extern,
+    "float" => Float,
+    "for" => For,
+    "goto" => Goto,
+    "if" => If,
+    "inline" => Inline,
+    "int" => Int,
+    "long" => Long,
+    "register" => Register,
+    "restrict" => Restrict,
+    "return" => Return,
+    "short" => Short,
+    "signed" => Signed,
+    "sizeof" => Sizeof,
+    "static" => Static,
+    "struct" => Struct,
+    "switch" => Switch,
+    "typedef" => Typedef,
+    "union" => Union,
+    "unsigned" => Unsigned,
+    "void" => Void,
+    "volatile" => Volatile,
+    "while" => While,
+    "_Alignas" => Alignas,
+    "_Alignof" => Alignof,
+    "_Atomic" => Atomic,
+    "_Bool" => Bool,
+    "_Complex" => Complex,
+    "_Generic" => Generic,
+    "_Imaginary" => Imaginary,
+    "_Noreturn" => Noreturn,
+    "_Static_assert" => StaticAssert,
+    "_Thread_local" => ThreadLocal,
+}
+
+impl Keyword {
+    /// Returns the keyword spelled by the identifier `sym`, if any.
+    ///
+    /// If `interner` was created by [`keyword_interner()`], this does not need to look at the
+    /// identifier's spelling at all.
+    pub fn from_symbol(sym: Symbol, interner: &Interner) -> Option<Self> {
+        if interner.is_prefilled_with(SPELLINGS) {
+            interner
+                .prefilled_index(sym, SPELLINGS)
+                .map(|idx| KEYWORDS[idx])
+        } else {
+            Self::from_spelling(&interner[sym])
+        }
+    }
+}
+
+/// Creates an interner in which all keywords have been pre-interned, so that identifiers can be
+/// classified as keywords by [`Keyword::from_symbol()`] without comparing strings.
+pub fn keyword_interner() -> Interner {
+    Interner::prefill(SPELLINGS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_symbol() {
+        for mut interner in [Interner::new(), keyword_interner()] {
+            let kw = interner.intern("_Alignof");
+            let ident = interner.intern("whilst");
+
+            assert_eq!(Keyword::from_symbol(kw, &interner), Some(Keyword::Alignof));
+            assert_eq!(Keyword::from_symbol(ident, &interner), None);
+        }
+    }
+}
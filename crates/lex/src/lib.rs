@@ -4,21 +4,43 @@
 
 use std::borrow::Cow;
 
-use source::{DResult, DiagManager, DiagReporter, LocalOff, SourceMap, SourcePos, SourceRange};
+use source::diag::{DiagnosticId, Message};
+use source::{
+    DResult, DiagManager, DiagReporter, FrontendLimits, LocalOff, SourceMap, SourcePos, SourceRange,
+};
 
+pub use ctoken::{convert_token, CToken, CTokenKind};
+pub use diff::{diff_token_streams, TokenDiff, TokenDiffKind};
+pub use keyword::{keyword_interner, Keyword};
+pub use lookahead::Lookahead;
 pub use punct::PunctKind;
 use raw::{RawToken, RawTokenKind};
-pub use token::{ConvertedToken, ConvertedTokenKind, Token, TokenKind};
+pub use synth::{SyntheticSource, SyntheticSourceBuilder};
+pub use token::{ConvertedToken, ConvertedTokenKind, SourceSpelling, Token, TokenKind};
+pub use writer::{Spacing, TokenWriter};
 
+mod ctoken;
+pub mod diff;
+mod keyword;
+pub mod literal;
+mod lookahead;
 mod punct;
 pub mod raw;
+mod synth;
 mod token;
+mod ucn;
+mod writer;
 
 /// A string interner type, used to hold identifiers and literals.
 pub type Interner = intern::Interner<str>;
 /// A symbol for use with `Interner`.
 pub type Symbol = intern::Symbol<str>;
 
+pub use source::limits::DEFAULT_MAX_LITERAL_LEN;
+
+/// The length of the preview interned in place of an overlong literal's spelling.
+const LITERAL_PREVIEW_LEN: usize = 32;
+
 /// Trait representing a source of tokens.
 pub trait Lex {
     /// Lexes the next token from the stream.
@@ -27,6 +49,12 @@ pub trait Lex {
     fn next(&mut self, ctx: &mut LexCtx<'_, '_>) -> DResult<Token>;
 }
 
+impl<L: Lex + ?Sized> Lex for &mut L {
+    fn next(&mut self, ctx: &mut LexCtx<'_, '_>) -> DResult<Token> {
+        (**self).next(ctx)
+    }
+}
+
 /// A context structure passed to lexers, tying together different pieces of state.
 pub struct LexCtx<'a, 'h> {
     /// The interner into which the lexer should place lexed identifiers and literals.
@@ -35,6 +63,12 @@ pub struct LexCtx<'a, 'h> {
     pub diags: &'a mut DiagManager<'h>,
     /// The source map, for use with `diags` and for generating token locations.
     pub smap: &'a mut SourceMap,
+    /// The limits to enforce while lexing and preprocessing.
+    ///
+    /// The full spelling of a literal truncated due to
+    /// [`max_literal_len`](FrontendLimits::max_literal_len) can still be retrieved from its range
+    /// with [`get_cleaned_spelling()`].
+    pub limits: FrontendLimits,
 }
 
 impl<'a, 'h> LexCtx<'a, 'h> {
@@ -48,9 +82,27 @@ impl<'a, 'h> LexCtx<'a, 'h> {
             interner,
             diags,
             smap,
+            limits: FrontendLimits::default(),
         }
     }
 
+    /// Returns `true` if a literal token covering `range` exceeds
+    /// [`max_literal_len`](FrontendLimits::max_literal_len), meaning that only a preview of its spelling was
+    /// interned.
+    pub fn is_literal_truncated(&self, range: SourceRange) -> bool {
+        u32::from(range.len()) as usize > self.limits.max_literal_len
+    }
+
+    /// Returns the original spelling of a punctuator of kind `kind` covering `range`.
+    ///
+    /// This differs from [`PunctKind::as_str()`] when the punctuator was spelled as a digraph.
+    pub fn punct_spelling(&self, kind: PunctKind, range: SourceRange) -> &'static str {
+        let digraph = kind
+            .digraph()
+            .is_some_and(|digraph| get_cleaned_spelling(self.smap, range) == digraph);
+        kind.spelling(digraph)
+    }
+
     /// Returns a reporter for emitting diagnostics.
     pub fn reporter(&mut self) -> DiagReporter<'_, 'h> {
         self.diags.reporter(self.smap)
@@ -80,6 +132,41 @@ pub fn convert_raw(
     let intern_content =
         |ctx: &mut LexCtx<'_, '_>| ctx.interner.intern_cow(raw.content.cleaned_str());
 
+    let intern_literal = |ctx: &mut LexCtx<'_, '_>, kind: &str| {
+        let len = raw.content.str.len();
+        let max_len = ctx.limits.max_literal_len;
+        if len <= max_len {
+            return Ok(intern_content(ctx));
+        }
+
+        ctx.reporter()
+            .warn(
+                SourceRange::new(pos, LocalOff::of(raw.content.str)),
+                Message::new("{kind} is {len} bytes long, exceeding the limit of {max}")
+                    .arg("kind", kind)
+                    .arg("len", len)
+                    .arg("max", max_len),
+            )
+            .set_id(DiagnosticId::LongLiteral)
+            .emit()?;
+
+        Ok(ctx
+            .interner
+            .intern(&literal_preview(&raw.content.cleaned_str())))
+    };
+
+    if raw.content.trigraphs && raw.content.tainted {
+        for (off, c) in raw::find_trigraphs(raw.content.str) {
+            ctx.reporter()
+                .warn(
+                    SourceRange::new(pos.offset(off), 3.into()),
+                    format!("trigraph converted to '{}' character", c),
+                )
+                .set_id(DiagnosticId::Trigraphs)
+                .emit()?;
+        }
+    }
+
     let kind = match raw.kind {
         RawTokenKind::Unknown => ConvertedTokenKind::Real(TokenKind::Unknown),
 
@@ -93,17 +180,31 @@ pub fn convert_raw(
         }
 
         RawTokenKind::Punct(punct) => ConvertedTokenKind::Real(TokenKind::Punct(punct)),
-        RawTokenKind::Ident => ConvertedTokenKind::Real(TokenKind::Ident(intern_content(ctx))),
+        RawTokenKind::Ident => {
+            let spelling = ucn::canonicalize_ident(
+                ctx,
+                raw.content.cleaned_str(),
+                pos,
+                LocalOff::of(raw.content.str),
+                !raw.content.tainted,
+            )?;
+            ConvertedTokenKind::Real(TokenKind::Ident(ctx.interner.intern_cow(spelling)))
+        }
         RawTokenKind::Number => ConvertedTokenKind::Real(TokenKind::Number(intern_content(ctx))),
 
         RawTokenKind::Str { terminated } => {
             check_terminated(ctx, terminated, "string literal")?;
-            ConvertedTokenKind::Real(TokenKind::Str(intern_content(ctx)))
+            ConvertedTokenKind::Real(TokenKind::Str(intern_literal(ctx, "string literal")?))
         }
 
         RawTokenKind::Char { terminated } => {
             check_terminated(ctx, terminated, "character literal")?;
-            ConvertedTokenKind::Real(TokenKind::Char(intern_content(ctx)))
+            ConvertedTokenKind::Real(TokenKind::Char(intern_literal(ctx, "character literal")?))
+        }
+
+        RawTokenKind::HeaderName { terminated } => {
+            check_terminated(ctx, terminated, "header name")?;
+            ConvertedTokenKind::Real(TokenKind::HeaderName(intern_content(ctx)))
         }
     };
 
@@ -118,6 +219,15 @@ pub fn convert_raw(
     Ok(ConvertedToken { data: kind, range })
 }
 
+/// Returns a truncated preview of the overlong literal `spelling`, keeping its opening quote.
+fn literal_preview(spelling: &str) -> String {
+    let end = (0..=LITERAL_PREVIEW_LEN)
+        .rev()
+        .find(|&i| spelling.is_char_boundary(i))
+        .unwrap();
+    format!("{}...", &spelling[..end])
+}
+
 /// Retrieves the source code snippet indicated by `range` from `smap`, cleaning out any escaped
 /// newlines.
 ///
@@ -130,3 +240,71 @@ pub fn get_cleaned_spelling(smap: &SourceMap, range: SourceRange) -> Cow<'_, str
         Cow::Borrowed(spelling)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use source::diag::{Level, RenderedDiagnostic, RenderedSink};
+
+    use super::*;
+
+    struct LevelSink<'a>(&'a RefCell<Vec<Level>>);
+
+    impl RenderedSink for LevelSink<'_> {
+        fn report(&mut self, diag: &RenderedDiagnostic, _smap: Option<&SourceMap>) {
+            self.0.borrow_mut().push(diag.level());
+        }
+    }
+
+    fn lex_literal(src: &str, max_literal_len: usize) -> (String, Vec<Level>) {
+        let levels = RefCell::new(Vec::new());
+        let mut diags = DiagManager::new(LevelSink(&levels), None);
+        let mut interner = Interner::new();
+        let mut smap = SourceMap::new();
+
+        let mut ctx = LexCtx::new(&mut interner, &mut diags, &mut smap);
+        ctx.limits.max_literal_len = max_literal_len;
+
+        let tokens = SyntheticSource::builder(&mut ctx)
+            .tokens([src])
+            .build()
+            .unwrap()
+            .into_tokens();
+        let tok = tokens[0];
+
+        let sym = match tok.data {
+            TokenKind::Str(sym) | TokenKind::Char(sym) => sym,
+            _ => panic!("expected a literal"),
+        };
+        assert_eq!(tok.display(&ctx).to_string(), src);
+
+        let interned = ctx.interner[sym].to_owned();
+        drop(diags);
+        (interned, levels.into_inner())
+    }
+
+    #[test]
+    fn literal_within_limit() {
+        let (interned, levels) = lex_literal(r#""hello""#, 7);
+        assert_eq!(interned, r#""hello""#);
+        assert!(levels.is_empty());
+    }
+
+    #[test]
+    fn literal_over_limit() {
+        let src = format!("\"{}\"", "a".repeat(100));
+        let (interned, levels) = lex_literal(&src, 50);
+        assert_eq!(interned, format!("\"{}...", "a".repeat(31)));
+        assert_eq!(levels, vec![Level::Warning]);
+    }
+
+    #[test]
+    fn literal_preview_char_boundary() {
+        let spelling = format!("'{}'", "\u{3b1}".repeat(20));
+        assert_eq!(
+            literal_preview(&spelling),
+            format!("'{}...", "\u{3b1}".repeat(15))
+        );
+    }
+}
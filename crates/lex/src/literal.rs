@@ -0,0 +1,786 @@
+//! Interpretation of numeric, character and string literals (§6.4.4, §6.4.5).
+//!
+//! The target is assumed to have a 32-bit `int`, 64-bit `long` and `long long`, a signed `char`
+//! and a 32-bit `wchar_t`.
+
+use std::convert::TryFrom;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use source::diag::{DiagnosticId, Message};
+use source::{DResult, SourceRange};
+
+use crate::LexCtx;
+
+/// An interner for the contents of string literals, which need not be valid UTF-8.
+pub type ByteInterner = intern::Interner<[u8]>;
+/// A symbol for use with [`ByteInterner`].
+pub type ByteSymbol = intern::Symbol<[u8]>;
+
+/// The type of an integer literal (§6.4.4.1p5).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntType {
+    Int,
+    UInt,
+    Long,
+    ULong,
+    LongLong,
+    ULongLong,
+}
+
+impl IntType {
+    /// Returns whether the type is signed.
+    pub fn is_signed(self) -> bool {
+        matches!(self, IntType::Int | IntType::Long | IntType::LongLong)
+    }
+
+    /// Returns the largest value representable in the type.
+    pub fn max_value(self) -> u64 {
+        match self {
+            IntType::Int => i32::MAX as u64,
+            IntType::UInt => u32::MAX as u64,
+            IntType::Long | IntType::LongLong => i64::MAX as u64,
+            IntType::ULong | IntType::ULongLong => u64::MAX,
+        }
+    }
+
+    /// Returns the name of the type, as spelled in C.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            IntType::Int => "int",
+            IntType::UInt => "unsigned int",
+            IntType::Long => "long",
+            IntType::ULong => "unsigned long",
+            IntType::LongLong => "long long",
+            IntType::ULongLong => "unsigned long long",
+        }
+    }
+}
+
+/// An integer literal (§6.4.4.1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntLiteral {
+    /// The value of the literal, which is always representable in `ty`.
+    pub value: u64,
+    /// The type of the literal, determined by its value, base and suffix.
+    pub ty: IntType,
+}
+
+/// The type of a floating literal (§6.4.4.2p4).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatType {
+    Float,
+    Double,
+    LongDouble,
+}
+
+impl FloatType {
+    /// Returns the name of the type, as spelled in C.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FloatType::Float => "float",
+            FloatType::Double => "double",
+            FloatType::LongDouble => "long double",
+        }
+    }
+}
+
+/// A floating literal (§6.4.4.2).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FloatLiteral {
+    /// The value of the literal. Values of type `long double` are only held with the precision of
+    /// a `double`.
+    pub value: f64,
+    /// The type of the literal, determined by its suffix.
+    pub ty: FloatType,
+}
+
+/// A numeric literal, which is either an integer or a floating literal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NumberLiteral {
+    Int(IntLiteral),
+    Float(FloatLiteral),
+}
+
+/// The encoding of a character or string literal, as determined by its prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// No prefix.
+    Plain,
+    /// `u8`
+    Utf8,
+    /// `L`
+    Wide,
+    /// `u`
+    Utf16,
+    /// `U`
+    Utf32,
+}
+
+impl Encoding {
+    /// Returns the size of a single code unit in the encoding, in bytes.
+    pub fn code_unit_size(self) -> usize {
+        match self {
+            Encoding::Plain | Encoding::Utf8 => 1,
+            Encoding::Utf16 => 2,
+            Encoding::Wide | Encoding::Utf32 => 4,
+        }
+    }
+
+    /// Returns the largest value of a single code unit in the encoding.
+    fn max_code_unit(self) -> u32 {
+        match self.code_unit_size() {
+            1 => u8::MAX.into(),
+            2 => u16::MAX.into(),
+            _ => u32::MAX,
+        }
+    }
+
+    /// Returns the prefix of literals with this encoding.
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Encoding::Plain => "",
+            Encoding::Utf8 => "u8",
+            Encoding::Wide => "L",
+            Encoding::Utf16 => "u",
+            Encoding::Utf32 => "U",
+        }
+    }
+}
+
+/// A character literal (§6.4.4.4).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CharLiteral {
+    /// The encoding of the literal.
+    pub encoding: Encoding,
+    /// The value of the code unit in the literal. For unprefixed literals containing several
+    /// characters, this combines all of them, with the first character in the most significant
+    /// position.
+    pub value: u32,
+}
+
+/// A string literal (§6.4.5).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StrLiteral {
+    /// The encoding of the literal.
+    pub encoding: Encoding,
+    /// The code units of the literal, without a terminating null character. Code units larger than
+    /// a byte are stored in little-endian order.
+    pub contents: ByteSymbol,
+}
+
+/// The parameters required for reporting diagnostics within a literal.
+struct LiteralLoc<'s> {
+    spelling: &'s str,
+    range: SourceRange,
+}
+
+impl LiteralLoc<'_> {
+    /// Returns the range covering `len` bytes at offset `off` in the spelling.
+    ///
+    /// If the literal was split by escaped newlines, the offsets in the spelling do not correspond
+    /// to the source, so the entire literal is returned instead.
+    fn sub_range(&self, off: usize, len: usize) -> SourceRange {
+        if u32::from(self.range.len()) as usize != self.spelling.len() {
+            return self.range;
+        }
+
+        let off = u32::try_from(off).unwrap();
+        let len = u32::try_from(len).unwrap();
+        SourceRange::new(self.range.start().offset(off.into()), len.into())
+    }
+}
+
+/// Interprets `spelling` (the spelling of a preprocessing number covering `range`) as an integer
+/// or floating literal.
+///
+/// Invalid literals are reported, and a best-effort value is returned for them.
+pub fn parse_number(
+    ctx: &mut LexCtx<'_, '_>,
+    spelling: &str,
+    range: SourceRange,
+) -> DResult<NumberLiteral> {
+    let loc = LiteralLoc { spelling, range };
+    let lower = spelling.to_ascii_lowercase();
+
+    let is_hex = lower.starts_with("0x");
+    let is_float = if is_hex {
+        lower.contains(['.', 'p'])
+    } else {
+        lower.contains(['.', 'e'])
+    };
+
+    if is_float {
+        parse_float(ctx, &loc, is_hex).map(NumberLiteral::Float)
+    } else {
+        parse_int(ctx, &loc, is_hex).map(NumberLiteral::Int)
+    }
+}
+
+fn parse_int(ctx: &mut LexCtx<'_, '_>, loc: &LiteralLoc<'_>, is_hex: bool) -> DResult<IntLiteral> {
+    let invalid = IntLiteral {
+        value: 0,
+        ty: IntType::Int,
+    };
+
+    let spelling = loc.spelling;
+    let (radix, digits_start) = if is_hex {
+        (16, 2)
+    } else if spelling.starts_with('0') {
+        (8, 0)
+    } else {
+        (10, 0)
+    };
+
+    let digits_len = spelling[digits_start..]
+        .find(|c: char| !c.is_ascii_hexdigit() || (radix != 16 && !c.is_ascii_digit()))
+        .unwrap_or(spelling.len() - digits_start);
+    let digits = &spelling[digits_start..digits_start + digits_len];
+    let suffix = &spelling[digits_start + digits_len..];
+
+    if digits.is_empty() {
+        ctx.reporter()
+            .error(
+                loc.sub_range(0, spelling.len()),
+                "hexadecimal literal has no digits",
+            )
+            .emit()?;
+        return Ok(invalid);
+    }
+
+    if let Some(off) = digits.find(|c: char| !c.is_digit(radix)) {
+        ctx.reporter()
+            .error(
+                loc.sub_range(digits_start + off, 1),
+                Message::new("invalid digit '{digit}' in octal literal")
+                    .arg("digit", &digits[off..off + 1]),
+            )
+            .emit()?;
+        return Ok(invalid);
+    }
+
+    let (unsigned, longs) = match parse_int_suffix(suffix) {
+        Some(suffix) => suffix,
+        None => {
+            ctx.reporter()
+                .error(
+                    loc.sub_range(spelling.len() - suffix.len(), suffix.len()),
+                    Message::new("invalid suffix '{suffix}' on integer literal")
+                        .arg("suffix", suffix),
+                )
+                .emit()?;
+            return Ok(invalid);
+        }
+    };
+
+    let value = digits.chars().try_fold(0u64, |value, c| {
+        value
+            .checked_mul(radix.into())?
+            .checked_add(c.to_digit(radix).unwrap().into())
+    });
+
+    let value = match value {
+        Some(value) => value,
+        None => {
+            ctx.reporter()
+                .error(
+                    loc.range,
+                    "integer literal is too large to be represented in any integer type",
+                )
+                .emit()?;
+            return Ok(invalid);
+        }
+    };
+
+    // Decimal literals without a `u` suffix are never given unsigned types (§6.4.4.1p5).
+    let decimal = radix == 10;
+    let candidates: &[IntType] = match (unsigned, longs, decimal) {
+        (false, 0, true) => &[IntType::Int, IntType::Long, IntType::LongLong],
+        (false, 0, false) => &[
+            IntType::Int,
+            IntType::UInt,
+            IntType::Long,
+            IntType::ULong,
+            IntType::LongLong,
+            IntType::ULongLong,
+        ],
+        (false, 1, true) => &[IntType::Long, IntType::LongLong],
+        (false, 1, false) => &[
+            IntType::Long,
+            IntType::ULong,
+            IntType::LongLong,
+            IntType::ULongLong,
+        ],
+        (false, _, true) => &[IntType::LongLong],
+        (false, _, false) => &[IntType::LongLong, IntType::ULongLong],
+        (true, 0, _) => &[IntType::UInt, IntType::ULong, IntType::ULongLong],
+        (true, 1, _) => &[IntType::ULong, IntType::ULongLong],
+        (true, _, _) => &[IntType::ULongLong],
+    };
+
+    let ty = match candidates.iter().find(|ty| value <= ty.max_value()) {
+        Some(&ty) => ty,
+        None => {
+            ctx.reporter()
+                .warn(
+                    loc.range,
+                    "integer literal is too large to be represented in a signed integer type, \
+                     interpreting as unsigned",
+                )
+                .set_id(DiagnosticId::ImplicitlyUnsignedLiteral)
+                .emit()?;
+            IntType::ULongLong
+        }
+    };
+
+    Ok(IntLiteral { value, ty })
+}
+
+/// Parses an integer suffix (§6.4.4.1p1), returning whether it contains `u` and the number of `l`s
+/// it contains.
+fn parse_int_suffix(suffix: &str) -> Option<(bool, u8)> {
+    let (unsigned, rest) = match suffix.strip_prefix(['u', 'U']) {
+        Some(rest) => (true, rest),
+        None => (false, suffix),
+    };
+
+    let (longs, rest) =
+        if let Some(rest) = rest.strip_prefix("ll").or_else(|| rest.strip_prefix("LL")) {
+            (2, rest)
+        } else if let Some(rest) = rest.strip_prefix(['l', 'L']) {
+            (1, rest)
+        } else {
+            (0, rest)
+        };
+
+    match rest {
+        "" => Some((unsigned, longs)),
+        "u" | "U" if !unsigned && longs > 0 => Some((true, longs)),
+        _ => None,
+    }
+}
+
+fn parse_float(
+    ctx: &mut LexCtx<'_, '_>,
+    loc: &LiteralLoc<'_>,
+    is_hex: bool,
+) -> DResult<FloatLiteral> {
+    let spelling = loc.spelling;
+    let (radix, start) = if is_hex { (16, 2) } else { (10, 0) };
+
+    let count_digits = |off: usize| {
+        spelling[off..]
+            .find(|c: char| !c.is_digit(radix))
+            .unwrap_or(spelling.len() - off)
+    };
+
+    let int_len = count_digits(start);
+    let mut end = start + int_len;
+
+    let mut frac_len = 0;
+    if spelling[end..].starts_with('.') {
+        frac_len = count_digits(end + 1);
+        end += 1 + frac_len;
+    }
+
+    let mut exp_start = None;
+    let exp_chars: &[char] = if is_hex { &['p', 'P'] } else { &['e', 'E'] };
+    if spelling[end..].starts_with(exp_chars) {
+        let mut digits_start = end + 1;
+        if spelling[digits_start..].starts_with(['+', '-']) {
+            digits_start += 1;
+        }
+
+        let exp_len = spelling[digits_start..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(spelling.len() - digits_start);
+
+        if exp_len == 0 {
+            ctx.reporter()
+                .error(
+                    loc.sub_range(end, digits_start - end),
+                    "exponent has no digits",
+                )
+                .emit()?;
+            return Ok(invalid_float());
+        }
+
+        exp_start = Some(end + 1);
+        end = digits_start + exp_len;
+    } else if is_hex {
+        ctx.reporter()
+            .error(
+                loc.sub_range(end, 0),
+                "hexadecimal floating literal requires an exponent",
+            )
+            .emit()?;
+        return Ok(invalid_float());
+    }
+
+    if int_len + frac_len == 0 {
+        ctx.reporter()
+            .error(
+                loc.sub_range(0, spelling.len()),
+                "floating literal has no digits",
+            )
+            .emit()?;
+        return Ok(invalid_float());
+    }
+
+    let suffix = &spelling[end..];
+    let ty = match suffix {
+        "" => FloatType::Double,
+        "f" | "F" => FloatType::Float,
+        "l" | "L" => FloatType::LongDouble,
+        _ => {
+            ctx.reporter()
+                .error(
+                    loc.sub_range(end, suffix.len()),
+                    Message::new("invalid suffix '{suffix}' on floating literal")
+                        .arg("suffix", suffix),
+                )
+                .emit()?;
+            return Ok(invalid_float());
+        }
+    };
+
+    let value = if is_hex {
+        let exp = exp_start.map_or(0, |exp_start| {
+            spelling[exp_start..end].parse::<i32>().unwrap_or_else(|_| {
+                if spelling[exp_start..].starts_with('-') {
+                    i32::MIN
+                } else {
+                    i32::MAX
+                }
+            })
+        });
+
+        let int_digits = &spelling[start..start + int_len];
+        let frac_start = start + int_len + 1;
+        let frac_digits = spelling
+            .get(frac_start..frac_start + frac_len)
+            .unwrap_or("");
+        hex_float_value(int_digits, frac_digits, exp)
+    } else {
+        spelling[..end].parse().unwrap()
+    };
+
+    let overflows = match ty {
+        FloatType::Float => (value as f32).is_infinite(),
+        FloatType::Double | FloatType::LongDouble => value.is_infinite(),
+    };
+
+    if overflows {
+        ctx.reporter()
+            .warn(
+                loc.range,
+                format!("floating literal is too large for type '{}'", ty.as_str()),
+            )
+            .set_id(DiagnosticId::LiteralRange)
+            .emit()?;
+    }
+
+    Ok(FloatLiteral { value, ty })
+}
+
+fn invalid_float() -> FloatLiteral {
+    FloatLiteral {
+        value: 0.0,
+        ty: FloatType::Double,
+    }
+}
+
+/// Computes the value of a hexadecimal floating literal with the specified integer and fraction
+/// digits and binary exponent.
+fn hex_float_value(int_digits: &str, frac_digits: &str, exp: i32) -> f64 {
+    let mut mantissa = 0u64;
+    let mut exp = i64::from(exp);
+
+    for (idx, c) in int_digits.chars().chain(frac_digits.chars()).enumerate() {
+        let is_frac = idx >= int_digits.len();
+
+        // Once the mantissa is full, additional integer digits only scale the value, and
+        // additional fraction digits are too small to matter.
+        if mantissa >> 60 != 0 {
+            if !is_frac {
+                exp += 4;
+            }
+            continue;
+        }
+
+        mantissa = mantissa * 16 + u64::from(c.to_digit(16).unwrap());
+        if is_frac {
+            exp -= 4;
+        }
+    }
+
+    let exp = i32::try_from(exp.clamp(i32::MIN.into(), i32::MAX.into())).unwrap();
+    mantissa as f64 * 2f64.powi(exp)
+}
+
+/// Splits the spelling of a character or string literal into its encoding and the text following
+/// the opening quote.
+fn split_prefix(spelling: &str, quote: char) -> (Encoding, usize) {
+    let quote_off = spelling.find(quote).unwrap_or(0);
+    let encoding = match &spelling[..quote_off] {
+        "u8" => Encoding::Utf8,
+        "L" => Encoding::Wide,
+        "u" => Encoding::Utf16,
+        "U" => Encoding::Utf32,
+        _ => Encoding::Plain,
+    };
+    (encoding, quote_off + 1)
+}
+
+/// Interprets `spelling` (the spelling of a character literal covering `range`) as a character
+/// literal.
+///
+/// Invalid literals are reported, and a best-effort value is returned for them.
+pub fn parse_char(
+    ctx: &mut LexCtx<'_, '_>,
+    spelling: &str,
+    range: SourceRange,
+) -> DResult<CharLiteral> {
+    let loc = LiteralLoc { spelling, range };
+    let (encoding, body_start) = split_prefix(spelling, '\'');
+    let (units, valid) = decode_units(ctx, &loc, body_start, '\'', encoding)?;
+
+    let value = match (units.as_slice(), encoding) {
+        ([], _) if !valid => 0,
+        ([], _) => {
+            ctx.reporter()
+                .error(range, "empty character literal")
+                .emit()?;
+            0
+        }
+        ([unit], _) => *unit,
+        (units, Encoding::Plain) => {
+            ctx.reporter()
+                .warn(range, "multi-character character literal")
+                .set_id(DiagnosticId::Multichar)
+                .emit()?;
+            units.iter().fold(0, |value, &unit| (value << 8) | unit)
+        }
+        ([unit, ..], _) => {
+            ctx.reporter()
+                .warn(range, "extraneous characters in character literal ignored")
+                .set_id(DiagnosticId::CharLiteralTooLong)
+                .emit()?;
+            *unit
+        }
+    };
+
+    Ok(CharLiteral { encoding, value })
+}
+
+/// Interprets `spelling` (the spelling of a string literal covering `range`) as a string literal,
+/// interning its contents into `bytes`.
+///
+/// Invalid escape sequences are reported and skipped.
+pub fn parse_str(
+    ctx: &mut LexCtx<'_, '_>,
+    bytes: &mut ByteInterner,
+    spelling: &str,
+    range: SourceRange,
+) -> DResult<StrLiteral> {
+    let loc = LiteralLoc { spelling, range };
+    let (encoding, body_start) = split_prefix(spelling, '"');
+    let (units, _) = decode_units(ctx, &loc, body_start, '"', encoding)?;
+
+    let size = encoding.code_unit_size();
+    let contents: Vec<u8> = units
+        .iter()
+        .flat_map(|unit| {
+            unit.to_le_bytes()
+                .iter()
+                .copied()
+                .take(size)
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    Ok(StrLiteral {
+        encoding,
+        contents: bytes.intern(&contents),
+    })
+}
+
+/// Decodes the contents of a character or string literal starting at `body_start` and ending at
+/// the closing `quote` (if any) into code units of `encoding`, interpreting escape sequences
+/// (§6.4.4.4).
+///
+/// Invalid escape sequences are reported and skipped, in which case `false` is returned alongside
+/// the code units.
+fn decode_units(
+    ctx: &mut LexCtx<'_, '_>,
+    loc: &LiteralLoc<'_>,
+    body_start: usize,
+    quote: char,
+    encoding: Encoding,
+) -> DResult<(Vec<u32>, bool)> {
+    let body = &loc.spelling[body_start..];
+    let mut units = Vec::new();
+    let mut valid = true;
+    let mut chars = body.char_indices().peekable();
+
+    while let Some((off, c)) = chars.next() {
+        if c == quote {
+            break;
+        }
+
+        if c != '\\' {
+            push_char(&mut units, c, encoding);
+            continue;
+        }
+
+        let escape_off = body_start + off;
+        let (_, kind) = match chars.next() {
+            Some(next) => next,
+            None => break,
+        };
+
+        let simple = match kind {
+            '\'' | '"' | '?' | '\\' => Some(kind as u32),
+            'a' => Some(0x07),
+            'b' => Some(0x08),
+            'f' => Some(0x0c),
+            'n' => Some(0x0a),
+            'r' => Some(0x0d),
+            't' => Some(0x09),
+            'v' => Some(0x0b),
+            _ => None,
+        };
+
+        if let Some(unit) = simple {
+            units.push(unit);
+            continue;
+        }
+
+        match kind {
+            '0'..='7' => {
+                let mut value = kind.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|&(_, c)| c.to_digit(8)) {
+                        Some(digit) => {
+                            value = value * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+
+                if value > encoding.max_code_unit() {
+                    ctx.reporter()
+                        .error(
+                            loc.sub_range(escape_off, escape_len(body, off, &mut chars)),
+                            "octal escape sequence out of range",
+                        )
+                        .emit()?;
+                    valid = false;
+                } else {
+                    units.push(value);
+                }
+            }
+
+            'x' => {
+                let mut value = Some(0u32);
+                let mut digits = 0;
+                while let Some(digit) = chars.peek().and_then(|&(_, c)| c.to_digit(16)) {
+                    value = value
+                        .and_then(|value| value.checked_mul(16))
+                        .map(|value| value + digit);
+                    digits += 1;
+                    chars.next();
+                }
+
+                let range = loc.sub_range(escape_off, escape_len(body, off, &mut chars));
+                match value {
+                    _ if digits == 0 => {
+                        ctx.reporter()
+                            .error(range, "\\x used with no following hex digits")
+                            .emit()?;
+                        valid = false;
+                    }
+                    Some(value) if value <= encoding.max_code_unit() => units.push(value),
+                    _ => {
+                        ctx.reporter()
+                            .error(range, "hex escape sequence out of range")
+                            .emit()?;
+                        valid = false;
+                    }
+                }
+            }
+
+            'u' | 'U' => {
+                let expected = if kind == 'u' { 4 } else { 8 };
+                let mut value = 0;
+                let mut digits = 0;
+                while digits < expected {
+                    match chars.peek().and_then(|&(_, c)| c.to_digit(16)) {
+                        Some(digit) => {
+                            value = value * 16 + digit;
+                            digits += 1;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+
+                let range = loc.sub_range(escape_off, escape_len(body, off, &mut chars));
+                if digits < expected {
+                    ctx.reporter()
+                        .error(range, "incomplete universal character name")
+                        .emit()?;
+                    valid = false;
+                    continue;
+                }
+
+                match char::from_u32(value) {
+                    Some(c) => push_char(&mut units, c, encoding),
+                    None => {
+                        ctx.reporter()
+                            .error(range, "invalid universal character")
+                            .emit()?;
+                        valid = false;
+                    }
+                }
+            }
+
+            _ => {
+                let len = escape_len(body, off, &mut chars);
+                ctx.reporter()
+                    .warn(
+                        loc.sub_range(escape_off, len),
+                        format!("unknown escape sequence '\\{}'", kind),
+                    )
+                    .set_id(DiagnosticId::UnknownEscapeSequence)
+                    .emit()?;
+                push_char(&mut units, kind, encoding);
+            }
+        }
+    }
+
+    Ok((units, valid))
+}
+
+/// Returns the length of the escape sequence starting at offset `start` in `body`, given the
+/// remaining characters following it.
+fn escape_len(body: &str, start: usize, rest: &mut Peekable<CharIndices<'_>>) -> usize {
+    let end = rest.peek().map_or(body.len(), |&(off, _)| off);
+    end - start
+}
+
+/// Appends the code units encoding `c` in `encoding` to `units`.
+fn push_char(units: &mut Vec<u32>, c: char, encoding: Encoding) {
+    match encoding {
+        Encoding::Plain | Encoding::Utf8 => {
+            let mut buf = [0; 4];
+            units.extend(c.encode_utf8(&mut buf).bytes().map(u32::from));
+        }
+        Encoding::Utf16 => {
+            let mut buf = [0; 2];
+            units.extend(c.encode_utf16(&mut buf).iter().map(|&unit| u32::from(unit)));
+        }
+        Encoding::Wide | Encoding::Utf32 => units.push(c.into()),
+    }
+}
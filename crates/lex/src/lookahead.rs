@@ -0,0 +1,126 @@
+use std::collections::VecDeque;
+
+use source::DResult;
+
+use crate::{Lex, LexCtx, Token, TokenKind};
+
+/// An adaptor adding arbitrary lookahead to a [`Lex`] implementation, for consumers that need to
+/// inspect upcoming tokens before deciding how to handle the current one.
+///
+/// Tokens are requested from the underlying lexer only when they are first looked at, and are
+/// buffered until consumed with [`Lex::next()`]. The underlying lexer is never asked for tokens
+/// past the end of the input: looking beyond it yields the end-of-file token again.
+pub struct Lookahead<L> {
+    inner: L,
+    buf: VecDeque<Token>,
+}
+
+impl<L: Lex> Lookahead<L> {
+    /// Creates a new adaptor reading tokens from `inner`.
+    pub fn new(inner: L) -> Self {
+        Self {
+            inner,
+            buf: VecDeque::new(),
+        }
+    }
+
+    /// Returns the next token without consuming it.
+    pub fn peek(&mut self, ctx: &mut LexCtx<'_, '_>) -> DResult<Token> {
+        self.peek_nth(ctx, 0)
+    }
+
+    /// Returns the token `n` tokens ahead of the current position (with `0` referring to the next
+    /// token) without consuming anything.
+    pub fn peek_nth(&mut self, ctx: &mut LexCtx<'_, '_>, n: usize) -> DResult<Token> {
+        while self.buf.len() <= n {
+            let tok = match self.buf.back() {
+                Some(&tok) if tok.data == TokenKind::Eof => tok,
+                _ => self.inner.next(ctx)?,
+            };
+            self.buf.push_back(tok);
+        }
+
+        Ok(self.buf[n])
+    }
+
+    /// Returns a reference to the underlying lexer.
+    pub fn inner(&self) -> &L {
+        &self.inner
+    }
+
+    /// Consumes the adaptor, returning the underlying lexer. Any tokens that have been looked at
+    /// but not consumed are lost.
+    pub fn into_inner(self) -> L {
+        self.inner
+    }
+}
+
+impl<L: Lex> Lex for Lookahead<L> {
+    fn next(&mut self, ctx: &mut LexCtx<'_, '_>) -> DResult<Token> {
+        match self.buf.pop_front() {
+            Some(tok) => Ok(tok),
+            None => self.inner.next(ctx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use source::smap::{FileContents, FileName};
+    use source::{DiagManager, SourceMap, SourceRange};
+
+    use crate::Interner;
+
+    use super::*;
+
+    /// A lexer returning a fixed sequence of numbers, followed by end-of-file tokens. The number
+    /// of tokens requested from it is recorded.
+    struct NumberLexer {
+        range: SourceRange,
+        count: u32,
+        requested: usize,
+    }
+
+    impl Lex for NumberLexer {
+        fn next(&mut self, ctx: &mut LexCtx<'_, '_>) -> DResult<Token> {
+            self.requested += 1;
+            let data = if self.requested as u32 <= self.count {
+                TokenKind::Number(ctx.interner.intern(&self.requested.to_string()))
+            } else {
+                TokenKind::Eof
+            };
+            Ok(Token::new(data, self.range))
+        }
+    }
+
+    #[test]
+    fn peek_and_next() {
+        let mut interner = Interner::new();
+        let mut diags = DiagManager::new_annotating(None);
+        let mut smap = SourceMap::new();
+        let file = smap
+            .create_file(FileName::synth("t"), FileContents::new("x"), None)
+            .unwrap();
+        let range = smap.get_source(file).range;
+        let mut ctx = LexCtx::new(&mut interner, &mut diags, &mut smap);
+
+        let mut lexer = Lookahead::new(NumberLexer {
+            range,
+            count: 2,
+            requested: 0,
+        });
+        let one = TokenKind::Number(ctx.interner.intern("1"));
+        let two = TokenKind::Number(ctx.interner.intern("2"));
+
+        assert_eq!(lexer.peek_nth(&mut ctx, 1).unwrap().data, two);
+        assert_eq!(lexer.inner().requested, 2);
+        assert_eq!(lexer.peek(&mut ctx).unwrap().data, one);
+        assert_eq!(lexer.next(&mut ctx).unwrap().data, one);
+
+        assert_eq!(lexer.peek_nth(&mut ctx, 5).unwrap().data, TokenKind::Eof);
+        assert_eq!(lexer.inner().requested, 3);
+
+        assert_eq!(lexer.next(&mut ctx).unwrap().data, two);
+        assert_eq!(lexer.next(&mut ctx).unwrap().data, TokenKind::Eof);
+    }
+}
@@ -114,6 +114,30 @@ impl PunctKind {
             GreaterGreaterEq => ">>=",
         }
     }
+
+    /// Returns the alternative spelling of `self` as a digraph (§6.4.6p3), if it has one.
+    pub fn digraph(self) -> Option<&'static str> {
+        use PunctKind::*;
+
+        match self {
+            LSquare => Some("<:"),
+            RSquare => Some(":>"),
+            LCurly => Some("<%"),
+            RCurly => Some("%>"),
+            Hash => Some("%:"),
+            HashHash => Some("%:%:"),
+            _ => None,
+        }
+    }
+
+    /// Returns the spelling of `self`, using its digraph spelling if `digraph` is set and it has
+    /// one.
+    pub fn spelling(self, digraph: bool) -> &'static str {
+        match self.digraph() {
+            Some(spelling) if digraph => spelling,
+            _ => self.as_str(),
+        }
+    }
 }
 
 impl fmt::Display for PunctKind {
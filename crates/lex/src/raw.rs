@@ -10,7 +10,8 @@ use std::convert::TryFrom;
 
 use source::{LocalOff, LocalRange};
 
-use super::PunctKind;
+use crate::ucn;
+use crate::PunctKind;
 
 #[cfg(test)]
 mod tests;
@@ -43,6 +44,13 @@ pub enum RawTokenKind {
     Char {
         terminated: bool,
     },
+
+    /// A header name, such as `<stdio.h>` or `"foo.h"`. Header names are only recognized within
+    /// `#include` directives and similar contexts (§6.4.7p4), and are therefore produced only by
+    /// [`Tokenizer::next_header_name()`].
+    HeaderName {
+        terminated: bool,
+    },
 }
 
 /// A slice of the actual source string.
@@ -53,17 +61,23 @@ pub struct RawContent<'a> {
     /// The relevant slice of the source string.
     pub str: &'a str,
     /// Indicates whether the slice contains escaped newlines that should be deleted before use,
-    /// as per translation phase 2.
+    /// as per translation phase 2, or trigraphs that should be replaced, as per translation phase
+    /// 1.
     pub tainted: bool,
+    /// Indicates whether trigraphs in the slice are to be replaced when cleaning it.
+    pub trigraphs: bool,
 }
 
 impl<'a> RawContent<'a> {
-    /// Returns the string corresponding to this slice with escaped newlines deleted.
+    /// Returns the string corresponding to this slice with escaped newlines deleted (and trigraphs
+    /// replaced, if enabled).
     pub fn cleaned_str(&self) -> Cow<'a, str> {
-        if self.tainted {
-            Cow::Owned(clean(self.str))
-        } else {
+        if !self.tainted {
             Cow::Borrowed(self.str)
+        } else if self.trigraphs {
+            Cow::Owned(clean_trigraphs(self.str))
+        } else {
+            Cow::Owned(clean(self.str))
         }
     }
 }
@@ -83,29 +97,93 @@ pub fn clean(tok: &str) -> String {
     tok.replace("\\\n", "")
 }
 
+/// Replaces trigraphs in `tok` as specified in translation phase 1, and then deletes escaped
+/// newlines as specified in translation phase 2 (§5.1.1.2).
+pub fn clean_trigraphs(tok: &str) -> String {
+    let mut iter = SkipEscapedNewlines::new(tok);
+    iter.trigraphs = true;
+    iter.collect()
+}
+
+/// Returns the character replacing the trigraph `??c`, if it is one (§5.2.1.1).
+fn trigraph_replacement(c: char) -> Option<char> {
+    let replacement = match c {
+        '=' => '#',
+        '(' => '[',
+        '/' => '\\',
+        ')' => ']',
+        '\'' => '^',
+        '<' => '{',
+        '!' => '|',
+        '>' => '}',
+        '-' => '~',
+        _ => return None,
+    };
+
+    Some(replacement)
+}
+
+/// Returns the character replacing the trigraph at the start of `s`, if there is one.
+fn trigraph_at(s: &str) -> Option<char> {
+    s.strip_prefix("??")
+        .and_then(|rest| rest.chars().next())
+        .and_then(trigraph_replacement)
+}
+
+/// Returns an iterator over the trigraphs in `s`, yielding the offset of each one along with the
+/// character replacing it.
+pub fn find_trigraphs(s: &str) -> impl Iterator<Item = (LocalOff, char)> + '_ {
+    let bytes = s.as_bytes();
+    let mut off = 0;
+
+    std::iter::from_fn(move || {
+        while off + 2 < bytes.len() {
+            let start = off;
+            let replacement = match bytes[start..start + 3] {
+                [b'?', b'?', c] => trigraph_replacement(c.into()),
+                _ => None,
+            };
+
+            match replacement {
+                Some(c) => {
+                    off += 3;
+                    return Some((LocalOff::try_from(start).unwrap(), c));
+                }
+                None => off += 1,
+            }
+        }
+        None
+    })
+}
+
 /// Checks whether `c` is a non-newline whitespace character, as per §6.4.
 fn is_line_ws(c: char) -> bool {
     [' ', '\t', '\x0b', '\x0c'].contains(&c)
 }
 
 /// Checks whether `c` is the start of an identifier (identifier-nondigit), as per §6.4.2.1.
+///
+/// Besides the basic nondigits, this accepts the extended characters listed in Annex D.
 fn is_ident_start(c: char) -> bool {
-    c.is_ascii_alphabetic() || c == '_'
+    c.is_ascii_alphabetic() || c == '_' || (!c.is_ascii() && ucn::is_extended_ident_start(c))
 }
 
 /// Checks whether `c` is an identifier continuation character, as per §6.4.2.1.
 fn is_ident_continue(c: char) -> bool {
-    is_ident_start(c) || c.is_ascii_digit()
+    c.is_ascii_alphanumeric() || c == '_' || (!c.is_ascii() && ucn::is_extended_ident_continue(c))
 }
 
-/// An iterator through the characters of a string that skips escaped newlines within it.
+/// An iterator through the characters of a string that skips escaped newlines within it, and
+/// optionally replaces trigraphs.
 ///
-/// The iterator also tracks whether it has seen any escaped newlines and become "tainted".
+/// The iterator also tracks whether it has seen any escaped newlines or trigraphs and become
+/// "tainted".
 #[derive(Clone)]
 struct SkipEscapedNewlines<'a> {
     input: &'a str,
     off: LocalOff,
     tainted: bool,
+    trigraphs: bool,
 }
 
 impl<'a> SkipEscapedNewlines<'a> {
@@ -115,6 +193,7 @@ impl<'a> SkipEscapedNewlines<'a> {
             input,
             off: 0.into(),
             tainted: false,
+            trigraphs: false,
         }
     }
 
@@ -133,8 +212,8 @@ impl<'a> SkipEscapedNewlines<'a> {
         self.off
     }
 
-    /// Returns whether any escaped newlines have been encountered since the last call to
-    /// `untaint()`.
+    /// Returns whether any escaped newlines or trigraphs have been encountered since the last call
+    /// to `untaint()`.
     pub fn tainted(&self) -> bool {
         self.tainted
     }
@@ -150,9 +229,25 @@ impl Iterator for SkipEscapedNewlines<'_> {
 
     #[inline]
     fn next(&mut self) -> Option<char> {
-        while self.remaining().starts_with("\\\n") {
+        loop {
+            let skip = if self.remaining().starts_with("\\\n") {
+                2
+            } else if self.trigraphs && self.remaining().starts_with("??/\n") {
+                4
+            } else {
+                break;
+            };
+
             self.tainted = true;
-            self.off += LocalOff::from(2);
+            self.off += LocalOff::from(skip);
+        }
+
+        if self.trigraphs {
+            if let Some(c) = trigraph_at(self.remaining()) {
+                self.tainted = true;
+                self.off += LocalOff::from(3);
+                return Some(c);
+            }
         }
 
         let next = self.remaining().chars().next();
@@ -166,7 +261,8 @@ impl Iterator for SkipEscapedNewlines<'_> {
 /// A utility for reading content from a source string.
 ///
 /// `Reader` also implements translation phase 2 (§5.1.1.2) and transparently skips any `\`
-/// characters immediately followed by a newline in the source.
+/// characters immediately followed by a newline in the source. If enabled, it replaces trigraphs
+/// as well (translation phase 1).
 #[derive(Clone)]
 pub struct Reader<'a> {
     /// The underlying character iterator.
@@ -201,9 +297,16 @@ impl<'a> Reader<'a> {
             off: self.start,
             str: &self.iter.input()[LocalRange::new(self.start, self.off())],
             tainted: self.iter.tainted(),
+            trigraphs: self.iter.trigraphs,
         }
     }
 
+    /// Sets whether trigraphs are replaced by the characters they represent (translation phase 1,
+    /// §5.2.1.1). Trigraphs are ignored by default.
+    pub fn set_trigraphs(&mut self, enabled: bool) {
+        self.iter.trigraphs = enabled;
+    }
+
     /// Consumes and returns the next character from the source string.
     pub fn bump(&mut self) -> Option<char> {
         self.iter.next()
@@ -213,7 +316,7 @@ impl<'a> Reader<'a> {
     pub fn bump_if(&mut self, mut pred: impl FnMut(char) -> bool) -> Option<char> {
         let mut iter = self.iter.clone();
         let c = iter.next();
-        if c.map_or(false, &mut pred) {
+        if c.is_some_and(&mut pred) {
             self.iter = iter;
             return c;
         }
@@ -229,6 +332,13 @@ impl<'a> Reader<'a> {
         self.iter.untaint();
     }
 
+    /// Moves the reader directly to `off`, which must lie on a character boundary after the
+    /// current offset. Escaped newlines and trigraphs in between are not inspected.
+    fn skip_to(&mut self, off: LocalOff) {
+        debug_assert!(off >= self.off());
+        self.iter.off = off;
+    }
+
     /// Consumes the next character from the source if it is exactly `c`.
     ///
     /// Returns whether a character was consumed.
@@ -290,19 +400,122 @@ impl<'a> Reader<'a> {
     }
 }
 
+/// The language dialect being tokenized, for the few cases in which C and C++ tokenize
+/// differently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    #[default]
+    C,
+    Cpp,
+}
+
+/// Options controlling how source code is split into raw tokens, which differ between language
+/// standards and modes.
+///
+/// The default configuration matches GNU C: digraphs and `//` comments are recognized, while
+/// trigraphs and `u8` character literals are not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LexerConfig {
+    /// The language being tokenized.
+    pub dialect: Dialect,
+    /// Whether trigraphs are replaced (translation phase 1, §5.2.1.1).
+    pub trigraphs: bool,
+    /// Whether the digraphs `<:`, `:>`, `<%`, `%>`, `%:` and `%:%:` are recognized as
+    /// punctuators (§6.4.6p3).
+    pub digraphs: bool,
+    /// Whether `//` begins a comment extending to the end of the line.
+    pub line_comments: bool,
+    /// Whether `u8'c'` is a character literal, rather than the identifier `u8` followed by one.
+    pub u8_char_literals: bool,
+}
+
+impl Default for LexerConfig {
+    fn default() -> Self {
+        Self {
+            dialect: Dialect::C,
+            trigraphs: false,
+            digraphs: true,
+            line_comments: true,
+            u8_char_literals: false,
+        }
+    }
+}
+
+impl LexerConfig {
+    /// Returns the configuration for strict C90, which has neither digraphs nor `//` comments.
+    pub fn c90() -> Self {
+        Self {
+            trigraphs: true,
+            digraphs: false,
+            line_comments: false,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the configuration for strict C99, C11 and C17.
+    pub fn c99() -> Self {
+        Self {
+            trigraphs: true,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the configuration for strict C23, which removes trigraphs and adds `u8` character
+    /// literals.
+    pub fn c23() -> Self {
+        Self {
+            u8_char_literals: true,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the configuration for C++.
+    pub fn cpp() -> Self {
+        Self {
+            dialect: Dialect::Cpp,
+            u8_char_literals: true,
+            ..Self::default()
+        }
+    }
+}
+
 /// Reads raw tokens out of a string.
 pub struct Tokenizer<'a> {
     /// The underlying reader used to tokenize the string.
     pub reader: Reader<'a>,
+    config: LexerConfig,
 }
 
 impl<'a> Tokenizer<'a> {
-    /// Creates a new tokenizer with the specified source string.
+    /// Creates a new tokenizer with the specified source string, tokenizing it as C with the
+    /// default configuration.
     #[inline]
     pub fn new(input: &'a str) -> Self {
-        Self {
-            reader: Reader::new(input),
-        }
+        Self::with_config(input, LexerConfig::default())
+    }
+
+    /// Creates a new tokenizer with the specified source string, tokenizing it as `dialect`.
+    #[inline]
+    pub fn with_dialect(input: &'a str, dialect: Dialect) -> Self {
+        let config = match dialect {
+            Dialect::C => LexerConfig::default(),
+            Dialect::Cpp => LexerConfig::cpp(),
+        };
+        Self::with_config(input, config)
+    }
+
+    /// Creates a new tokenizer with the specified source string, tokenizing it according to
+    /// `config`.
+    #[inline]
+    pub fn with_config(input: &'a str, config: LexerConfig) -> Self {
+        let mut reader = Reader::new(input);
+        reader.set_trigraphs(config.trigraphs);
+        Self { reader, config }
+    }
+
+    /// Returns the configuration used by this tokenizer.
+    pub fn config(&self) -> LexerConfig {
+        self.config
     }
 
     /// Reads the next token using `self.reader`.
@@ -323,7 +536,7 @@ impl<'a> Tokenizer<'a> {
 
             'U' | 'L' => self.handle_encoding_prefix(true),
             'u' => {
-                let allow_char = !self.reader.eat('8');
+                let allow_char = !self.reader.eat('8') || self.config.u8_char_literals;
                 self.handle_encoding_prefix(allow_char)
             }
 
@@ -342,17 +555,177 @@ impl<'a> Tokenizer<'a> {
 
             c if is_ident_start(c) => self.handle_ident(),
             d if d.is_ascii_digit() => self.handle_number(),
+            '\\' if self.eat_ucn_body() => self.handle_ident(),
 
             c => self.handle_punct(c),
         }
     }
 
+    /// Reads a header name using `self.reader`, if one starts at the current position.
+    ///
+    /// Unlike string literals, header names contain no escape sequences: a `\` is just another
+    /// character in the name. If the terminating delimiter is not found before the end of the line,
+    /// the newline is left unconsumed and the token is marked as unterminated.
+    ///
+    /// Returns `None` without consuming anything if the input does not start with `<` or `"`.
+    pub fn next_header_name(&mut self) -> Option<RawToken<'a>> {
+        self.reader.begin_tok();
+
+        let term = if self.reader.eat('<') {
+            '>'
+        } else if self.reader.eat('"') {
+            '"'
+        } else {
+            return None;
+        };
+
+        self.reader.eat_while(|c| c != '\n' && c != term);
+        let terminated = self.reader.eat(term);
+        Some(self.tok(RawTokenKind::HeaderName { terminated }))
+    }
+
+    /// Skips lines until one whose first token is `#` is found, consuming the `#` itself and
+    /// returning its offset. Returns `None` if the end of the input is reached first.
+    ///
+    /// `line_start` indicates whether the tokenizer is currently at the start of a line (ignoring
+    /// whitespace and comments), and is updated to reflect the state in which the tokenizer was
+    /// left.
+    ///
+    /// This produces the same result as calling [`next_token()`](Self::next_token()) repeatedly,
+    /// but is much faster on long stretches of code: only the first token on each line is lexed,
+    /// and the rest of the line is scanned for nothing but the comments, literals and escaped
+    /// newlines that could hide or extend a line break.
+    pub fn skip_to_directive(&mut self, line_start: &mut bool) -> Option<LocalOff> {
+        loop {
+            if !*line_start {
+                if !self.skip_rest_of_line() {
+                    return None;
+                }
+                *line_start = true;
+            }
+
+            let tok = self.next_token();
+            match tok.kind {
+                RawTokenKind::Eof => return None,
+                RawTokenKind::Newline
+                | RawTokenKind::Ws
+                | RawTokenKind::LineComment
+                | RawTokenKind::BlockComment { .. } => {}
+                RawTokenKind::Punct(PunctKind::Hash) => return Some(tok.content.off),
+                _ => *line_start = false,
+            }
+        }
+    }
+
+    /// Skips to just after the newline ending the current line, returning `false` if the end of the
+    /// input is reached first.
+    ///
+    /// Literals and block comments are consumed as whole tokens, so that any newline or comment
+    /// delimiter inside them is ignored.
+    fn skip_rest_of_line(&mut self) -> bool {
+        let input = self.reader.iter.input();
+        let bytes = input.as_bytes();
+        let mut off = usize::from(self.reader.off());
+
+        while off < bytes.len() {
+            match bytes[off] {
+                b'\n' => {
+                    self.reader.skip_to(LocalOff::try_from(off + 1).unwrap());
+                    return true;
+                }
+                b'\\' if bytes.get(off + 1) == Some(&b'\n') => off += 2,
+                b'?' if self.config.trigraphs && trigraph_at(&input[off..]).is_some() => {
+                    // Step over the trigraph as a whole, as its last character could otherwise be
+                    // mistaken for the start of a literal or comment.
+                    off += if input[off..].starts_with("??/\n") {
+                        4
+                    } else {
+                        3
+                    };
+                }
+                b'"' | b'\'' | b'/' => {
+                    self.reader.skip_to(LocalOff::try_from(off).unwrap());
+                    self.skip_literal_or_comment();
+                    off = self.reader.off().into();
+                }
+                _ => off += 1,
+            }
+        }
+
+        self.reader.skip_to(LocalOff::of(input));
+        false
+    }
+
+    /// Consumes the literal or comment starting at the current position, or just the character at
+    /// the current position if it does not start one.
+    fn skip_literal_or_comment(&mut self) {
+        self.reader.begin_tok();
+
+        match self.reader.bump() {
+            Some('"') => {
+                self.handle_str();
+            }
+            Some('\'') => {
+                self.handle_char();
+            }
+            Some('/') => {
+                if self.reader.eat('*') {
+                    self.handle_block_comment();
+                } else if self.config.line_comments && self.reader.eat('/') {
+                    self.handle_line_comment();
+                }
+            }
+            _ => {}
+        }
+    }
+
     /// Finishes consuming and returns an identifier token.
+    ///
+    /// Universal character names within the identifier are consumed as well, but their values are
+    /// not validated here.
     fn handle_ident(&mut self) -> RawToken<'a> {
-        self.reader.eat_while(is_ident_continue);
+        loop {
+            self.reader.eat_while(is_ident_continue);
+            if !self.eat_ucn() {
+                break;
+            }
+        }
         self.tok(RawTokenKind::Ident)
     }
 
+    /// Consumes the next characters if they form a syntactically valid universal character name
+    /// (§6.4.3).
+    ///
+    /// Returns `true` if characters were consumed.
+    fn eat_ucn(&mut self) -> bool {
+        let saved = self.reader.clone();
+        if self.reader.eat('\\') && self.eat_ucn_body() {
+            return true;
+        }
+
+        self.reader = saved;
+        false
+    }
+
+    /// Consumes the rest of a universal character name whose `\` has already been consumed, if
+    /// it is syntactically valid.
+    ///
+    /// Returns `true` if characters were consumed.
+    fn eat_ucn_body(&mut self) -> bool {
+        let mut reader = self.reader.clone();
+        let digits = match reader.bump().and_then(ucn::ucn_digits) {
+            Some(digits) => digits,
+            None => return false,
+        };
+
+        if (0..digits).all(|_| reader.eat_if(|c| c.is_ascii_hexdigit())) {
+            self.reader = reader;
+            true
+        } else {
+            false
+        }
+    }
+
     /// Finishes consuming and returns a preprocessing number token.
     fn handle_number(&mut self) -> RawToken<'a> {
         while self.eat_number_char() {}
@@ -371,7 +744,7 @@ impl<'a> Tokenizer<'a> {
             return true;
         }
 
-        self.reader.eat_if(|c| c == '.' || is_ident_continue(c))
+        self.reader.eat_if(|c| c == '.' || is_ident_continue(c)) || self.eat_ucn()
     }
 
     /// Reacts to a possible encoding prefix (`L`, `u8`, etc.) and returns either a string,
@@ -422,6 +795,20 @@ impl<'a> Tokenizer<'a> {
         self.tok(f(false))
     }
 
+    /// Checks whether a `<` that has just been consumed must be a token by itself even though it
+    /// is followed by a `:`.
+    ///
+    /// In C++, `<::` not followed by `:` or `>` is lexed as `<` followed by `::`, so that
+    /// expressions like `std::vector<::std::string>` work as expected ([lex.pptoken]p3).
+    fn is_cpp_template_less(&self) -> bool {
+        if self.config.dialect != Dialect::Cpp {
+            return false;
+        }
+
+        let mut reader = self.reader.clone();
+        reader.eat_str("::") && !reader.eat_if(|c| c == ':' || c == '>')
+    }
+
     /// Handles a suspected punctuator character `c`, and returns either the appropriate punctuator
     /// or an `Unknown` token.
     fn handle_punct(&mut self, c: char) -> RawToken<'a> {
@@ -446,7 +833,7 @@ impl<'a> Tokenizer<'a> {
                 }
             }
             ':' => {
-                if self.reader.eat('>') {
+                if self.eat_digraph('>') {
                     self.punct(RSquare)
                 } else {
                     self.punct(Colon)
@@ -480,7 +867,7 @@ impl<'a> Tokenizer<'a> {
                 }
             }
             '/' => {
-                if self.reader.eat('/') {
+                if self.config.line_comments && self.reader.eat('/') {
                     self.handle_line_comment()
                 } else if self.reader.eat('*') {
                     self.handle_block_comment()
@@ -491,13 +878,13 @@ impl<'a> Tokenizer<'a> {
                 }
             }
             '%' => {
-                if self.reader.eat(':') {
+                if self.eat_digraph(':') {
                     if self.reader.eat_str("%:") {
                         self.punct(HashHash)
                     } else {
                         self.punct(Hash)
                     }
-                } else if self.reader.eat('>') {
+                } else if self.eat_digraph('>') {
                     self.punct(RCurly)
                 } else if self.reader.eat('=') {
                     self.punct(PercEq)
@@ -538,9 +925,11 @@ impl<'a> Tokenizer<'a> {
                 }
             }
             '<' => {
-                if self.reader.eat(':') {
+                if self.is_cpp_template_less() {
+                    self.punct(Less)
+                } else if self.eat_digraph(':') {
                     self.punct(LSquare)
-                } else if self.reader.eat('%') {
+                } else if self.eat_digraph('%') {
                     self.punct(LCurly)
                 } else if self.reader.eat('<') {
                     if self.reader.eat('=') {
@@ -555,9 +944,9 @@ impl<'a> Tokenizer<'a> {
                 }
             }
             '>' => {
-                if self.reader.eat(':') {
+                if self.eat_digraph(':') {
                     self.punct(RSquare)
-                } else if self.reader.eat('%') {
+                } else if self.eat_digraph('%') {
                     self.punct(RCurly)
                 } else if self.reader.eat('>') {
                     if self.reader.eat('=') {
@@ -582,6 +971,14 @@ impl<'a> Tokenizer<'a> {
         }
     }
 
+    /// Consumes the second character `c` of a digraph, if digraphs are enabled and it is next in
+    /// the source.
+    ///
+    /// Returns whether a character was consumed.
+    fn eat_digraph(&mut self, c: char) -> bool {
+        self.config.digraphs && self.reader.eat(c)
+    }
+
     /// Consumes and emits a line comment token.
     ///
     /// The terminating newline is not consumed, to allow it to be emitted as a separate token.
@@ -617,3 +1014,58 @@ impl<'a> Tokenizer<'a> {
         }
     }
 }
+
+/// A logical source line, possibly spanning several physical lines through escaped newlines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawLine<'a> {
+    /// The range of the line within the source, excluding the terminating newline.
+    pub range: LocalRange,
+    /// The range of the line within the source, including the terminating newline (if any).
+    pub full_range: LocalRange,
+    /// The raw tokens making up the line, excluding the terminating newline.
+    pub tokens: Vec<RawToken<'a>>,
+}
+
+impl<'a> RawLine<'a> {
+    /// Returns an iterator over the tokens on this line, skipping whitespace and comments.
+    pub fn significant(&self) -> impl Iterator<Item = &RawToken<'a>> {
+        self.tokens.iter().filter(|tok| {
+            !matches!(
+                tok.kind,
+                RawTokenKind::Ws | RawTokenKind::LineComment | RawTokenKind::BlockComment { .. }
+            )
+        })
+    }
+}
+
+/// Splits `src` into logical lines, tokenizing it as C with the default configuration.
+///
+/// Note that block comments spanning several lines are kept within the line on which they start.
+pub fn split_lines(src: &str) -> Vec<RawLine<'_>> {
+    let mut tokenizer = Tokenizer::new(src);
+    let mut lines = Vec::new();
+    let mut start = LocalOff::from(0);
+    let mut tokens = Vec::new();
+
+    loop {
+        let tok = tokenizer.next_token();
+
+        match tok.kind {
+            RawTokenKind::Newline | RawTokenKind::Eof => {
+                let end = tok.content.off + LocalOff::of(tok.content.str);
+                lines.push(RawLine {
+                    range: LocalRange::new(start, tok.content.off),
+                    full_range: LocalRange::new(start, end),
+                    tokens: std::mem::take(&mut tokens),
+                });
+
+                if tok.kind == RawTokenKind::Eof {
+                    break lines;
+                }
+
+                start = end;
+            }
+            _ => tokens.push(tok),
+        }
+    }
+}
@@ -77,6 +77,19 @@ fn ident() {
     check_single_token("_1", RawTokenKind::Ident);
 }
 
+#[test]
+fn extended_ident() {
+    check_single_token("caf\u{e9}", RawTokenKind::Ident);
+    check_single_token("\u{3b1}\u{3b2}", RawTokenKind::Ident);
+    check_single_token("caf\\u00e9", RawTokenKind::Ident);
+    check_single_token("\\U0001F600x", RawTokenKind::Ident);
+    check_single_token("\\u0041", RawTokenKind::Ident);
+    check_first_token("a\\u12 b", "a", RawTokenKind::Ident);
+    check_first_token("\\u12", "\\", RawTokenKind::Unknown);
+    check_first_token("\u{300}a", "\u{300}", RawTokenKind::Unknown);
+    check_single_token("1\\u00e9", RawTokenKind::Number);
+}
+
 #[test]
 fn number() {
     check_single_token("123", RawTokenKind::Number);
@@ -118,6 +131,29 @@ fn str_like() {
     check_single_token(r#"'\t\'"#, RawTokenKind::Char { terminated: false });
 }
 
+#[test]
+fn header_name() {
+    fn check(input: &str, tok_str: &str, terminated: bool) {
+        let tok = Tokenizer::new(input).next_header_name().unwrap();
+        assert_eq!(tok.kind, RawTokenKind::HeaderName { terminated });
+        assert_eq!(tok.content.str, tok_str);
+    }
+
+    check("<stdio.h>", "<stdio.h>", true);
+    check("<sys/types.h> x", "<sys/types.h>", true);
+    check(r#""foo.h""#, r#""foo.h""#, true);
+    check(r#""dir\"foo.h""#, r#""dir\""#, true);
+    check(r#"<dir\foo.h>"#, r#"<dir\foo.h>"#, true);
+    check("<a\\\n.h>", "<a\\\n.h>", true);
+    check("<stdio.h\n>", "<stdio.h", false);
+    check(r#""foo.h"#, r#""foo.h"#, false);
+    check("< a > b >", "< a >", true);
+
+    let mut tokenizer = Tokenizer::new("foo.h");
+    assert_eq!(tokenizer.next_header_name(), None);
+    assert_eq!(tokenizer.next_token().content.str, "foo");
+}
+
 #[test]
 fn simple_punct() {
     fn check(punct: char, kind: PunctKind) {
@@ -215,3 +251,199 @@ fn digraphs() {
     check("%:", PunctKind::Hash);
     check("%:%:", PunctKind::HashHash);
 }
+
+#[test]
+fn cpp_template_less() {
+    fn check_cpp(input: &str, tok_str: &str, kind: PunctKind) {
+        let tok = Tokenizer::with_dialect(input, Dialect::Cpp).next_token();
+        assert_eq!(tok.kind, RawTokenKind::Punct(kind));
+        assert_eq!(tok.content.str, tok_str);
+    }
+
+    check_cpp("<::std", "<", PunctKind::Less);
+    check_cpp("<::", "<", PunctKind::Less);
+    check_cpp("<:\\\n:x", "<", PunctKind::Less);
+    check_cpp("<:::", "<:", PunctKind::LSquare);
+    check_cpp("<::>", "<:", PunctKind::LSquare);
+    check_cpp("<:x", "<:", PunctKind::LSquare);
+
+    // The rule does not apply to C.
+    check_first_token("<::std", "<:", RawTokenKind::Punct(PunctKind::LSquare));
+}
+
+#[test]
+fn trigraphs() {
+    let mut tokenizer = Tokenizer::new("??=define X a??(0??) ??/\n??'");
+    tokenizer.reader.set_trigraphs(true);
+
+    let hash = tokenizer.next_token();
+    assert_eq!(hash.kind, RawTokenKind::Punct(PunctKind::Hash));
+    assert!(hash.content.tainted);
+    assert_eq!(hash.content.cleaned_str(), "#");
+
+    let kinds: Vec<_> = std::iter::from_fn(|| {
+        let tok = tokenizer.next_token();
+        (tok.kind != RawTokenKind::Eof).then(|| (tok.kind, tok.content.cleaned_str()))
+    })
+    .filter(|(kind, _)| *kind != RawTokenKind::Ws)
+    .collect();
+
+    assert_eq!(
+        kinds,
+        [
+            (RawTokenKind::Ident, "define".into()),
+            (RawTokenKind::Ident, "X".into()),
+            (RawTokenKind::Ident, "a".into()),
+            (RawTokenKind::Punct(PunctKind::LSquare), "[".into()),
+            (RawTokenKind::Number, "0".into()),
+            (RawTokenKind::Punct(PunctKind::RSquare), "]".into()),
+            (RawTokenKind::Punct(PunctKind::Caret), "^".into()),
+        ]
+    );
+}
+
+#[test]
+fn trigraphs_disabled() {
+    check_first_token("??=", "?", RawTokenKind::Punct(PunctKind::Question));
+}
+
+#[test]
+fn find_trigraphs_in_str() {
+    let found: Vec<_> = find_trigraphs("a???=b??/\n??x").collect();
+    assert_eq!(found, [(2.into(), '#'), (6.into(), '\\')]);
+    assert_eq!(clean_trigraphs("a???=b??/\nc"), "a?#bc");
+}
+
+#[test]
+fn config() {
+    let check = |input: &str, config: LexerConfig, tok_str: &str, kind: RawTokenKind| {
+        let tok = Tokenizer::with_config(input, config).next_token();
+        assert_eq!((tok.kind, tok.content.str), (kind, tok_str), "{}", input);
+    };
+
+    let u8_chars = LexerConfig {
+        u8_char_literals: true,
+        ..LexerConfig::default()
+    };
+    check("u8'a'", LexerConfig::default(), "u8", RawTokenKind::Ident);
+    check(
+        "u8'a'",
+        u8_chars,
+        "u8'a'",
+        RawTokenKind::Char { terminated: true },
+    );
+
+    let c90 = LexerConfig::c90();
+    check("// a", c90, "/", RawTokenKind::Punct(PunctKind::Slash));
+    check("<:", c90, "<", RawTokenKind::Punct(PunctKind::Less));
+    check("%:", c90, "%", RawTokenKind::Punct(PunctKind::Perc));
+    check("??=", c90, "??=", RawTokenKind::Punct(PunctKind::Hash));
+}
+
+/// Skips to every directive in `input` with [`Tokenizer::skip_to_directive()`], checking that the
+/// results match those of lexing every token.
+fn check_skip_to_directive(input: &str, config: LexerConfig) -> Vec<LocalOff> {
+    let mut fast = Tokenizer::with_config(input, config);
+    let mut slow = Tokenizer::with_config(input, config);
+    let mut fast_line_start = true;
+    let mut slow_line_start = true;
+    let mut hashes = Vec::new();
+
+    loop {
+        let fast_hash = fast.skip_to_directive(&mut fast_line_start);
+        let slow_hash = loop {
+            let tok = slow.next_token();
+            match tok.kind {
+                RawTokenKind::Eof => break None,
+                RawTokenKind::Newline => slow_line_start = true,
+                RawTokenKind::Ws
+                | RawTokenKind::LineComment
+                | RawTokenKind::BlockComment { .. } => {}
+                RawTokenKind::Punct(PunctKind::Hash) if slow_line_start => {
+                    break Some(tok.content.off)
+                }
+                _ => slow_line_start = false,
+            }
+        };
+
+        assert_eq!(fast_hash, slow_hash, "{:?}", input);
+        assert_eq!(fast.reader.off(), slow.reader.off(), "{:?}", input);
+
+        match fast_hash {
+            Some(off) => {
+                hashes.push(off);
+                fast_line_start = false;
+                slow_line_start = false;
+            }
+            None => {
+                assert_eq!(fast_line_start, slow_line_start, "{:?}", input);
+                return hashes;
+            }
+        }
+    }
+}
+
+#[test]
+fn skip_to_directive() {
+    let default = LexerConfig::default();
+    let offs = |offs: &[u32]| {
+        offs.iter()
+            .map(|&off| off.into())
+            .collect::<Vec<LocalOff>>()
+    };
+
+    assert_eq!(
+        check_skip_to_directive("a b\n  # x\nc # y\n\t%: z", default),
+        offs(&[6, 17])
+    );
+    assert_eq!(
+        check_skip_to_directive("a \"\n#x\nb '#\n#y \\\n#z", default),
+        offs(&[4, 12])
+    );
+    assert_eq!(
+        check_skip_to_directive("a /* \n#x */ #y\n/* a\n*/ #z // \\\n#w", default),
+        offs(&[23])
+    );
+    assert_eq!(
+        check_skip_to_directive("a \"\\\"\n#x\" \\\n#y\n'\\\\'\n#z", default),
+        offs(&[6, 20])
+    );
+    assert_eq!(
+        check_skip_to_directive(
+            "a ??/\n#x\nb ??'\n#y\n??= z\nc ??//\n#w",
+            LexerConfig::c99()
+        ),
+        offs(&[15, 18, 31])
+    );
+    assert_eq!(
+        check_skip_to_directive("a // x\n#y\nb //\n%:z", LexerConfig::c90()),
+        offs(&[7])
+    );
+    assert_eq!(check_skip_to_directive("a\n\n", default), offs(&[]));
+    assert_eq!(check_skip_to_directive("a /*\n", default), offs(&[]));
+}
+
+#[test]
+fn split_into_lines() {
+    let src = "#define A \\\n 1 /* a\nb */\n\nx";
+    let lines = split_lines(src);
+
+    let ranges: Vec<_> = lines
+        .iter()
+        .map(|line| (&src[line.range], &src[line.full_range]))
+        .collect();
+    assert_eq!(
+        ranges,
+        [
+            (
+                "#define A \\\n 1 /* a\nb */",
+                "#define A \\\n 1 /* a\nb */\n"
+            ),
+            ("", "\n"),
+            ("x", "x"),
+        ]
+    );
+
+    let significant: Vec<_> = lines[0].significant().map(|tok| tok.content.str).collect();
+    assert_eq!(significant, ["#", "define", "A", "1"]);
+}
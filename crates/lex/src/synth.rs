@@ -0,0 +1,164 @@
+//! Token streams backed by synthesized sources.
+//!
+//! Tests and code generators often need tokens with valid ranges, which in turn requires a source
+//! in the source map containing their spellings. [`SyntheticSource`] takes care of creating such a
+//! source and lexing it.
+//!
+//! Sources that are to be preprocessed should be created in the source map directly instead, as
+//! the builder lexes (and reports diagnostics for) everything it is given up front.
+
+use std::mem;
+
+use source::diag::Level;
+use source::smap::{FileContents, FileName};
+use source::sync::Lrc;
+use source::{DResult, SourceId};
+
+use crate::raw::Tokenizer;
+use crate::{convert_raw, ConvertedTokenKind, LexCtx, Token, TokenKind};
+
+/// A synthesized file source, along with the tokens lexed from it.
+#[derive(Debug, Clone)]
+pub struct SyntheticSource {
+    id: SourceId,
+    tokens: Vec<Token>,
+}
+
+impl SyntheticSource {
+    /// Returns a builder for creating a new synthetic source in `ctx.smap`.
+    pub fn builder<'a, 'b, 'h>(ctx: &'a mut LexCtx<'b, 'h>) -> SyntheticSourceBuilder<'a, 'b, 'h> {
+        SyntheticSourceBuilder::new(ctx)
+    }
+
+    /// Returns the ID of the created source.
+    pub fn id(&self) -> SourceId {
+        self.id
+    }
+
+    /// Returns the tokens lexed from the source. These do not include a trailing `Eof` token.
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    /// Consumes the source, returning the tokens lexed from it.
+    pub fn into_tokens(self) -> Vec<Token> {
+        self.tokens
+    }
+}
+
+/// Helper structure implementing the builder pattern for constructing a new [`SyntheticSource`].
+pub struct SyntheticSourceBuilder<'a, 'b, 'h> {
+    ctx: &'a mut LexCtx<'b, 'h>,
+    name: String,
+    src: String,
+}
+
+impl<'a, 'b, 'h> SyntheticSourceBuilder<'a, 'b, 'h> {
+    /// Creates a new builder for a synthetic source in `ctx.smap`.
+    pub fn new(ctx: &'a mut LexCtx<'b, 'h>) -> Self {
+        Self {
+            ctx,
+            name: "synthetic".to_owned(),
+            src: String::new(),
+        }
+    }
+
+    /// Sets the name of the synthesized file. It is displayed in diagnostics enclosed in angle
+    /// brackets (see [`FileName::Synth`]).
+    pub fn name(&mut self, name: impl Into<String>) -> &mut Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Appends tokens with the specified spellings to the source, separated by single spaces.
+    ///
+    /// Each spelling is lexed normally, so it may end up producing several tokens (or none, if it
+    /// consists only of whitespace and comments).
+    pub fn tokens<S: AsRef<str>>(&mut self, spellings: impl IntoIterator<Item = S>) -> &mut Self {
+        for spelling in spellings {
+            if !self.src.is_empty() && !self.src.ends_with('\n') {
+                self.src.push(' ');
+            }
+            self.src.push_str(spelling.as_ref());
+        }
+        self
+    }
+
+    /// Appends a line break to the source, so that subsequent tokens start on a new line.
+    pub fn newline(&mut self) -> &mut Self {
+        self.src.push('\n');
+        self
+    }
+
+    /// Creates the source and lexes it, reporting any errors encountered through `ctx`.
+    pub fn build(&mut self) -> DResult<SyntheticSource> {
+        let contents = FileContents::from_string(mem::take(&mut self.src));
+        let ctx = &mut *self.ctx;
+
+        let id = ctx
+            .smap
+            .create_file(
+                FileName::synth(mem::take(&mut self.name)),
+                Lrc::clone(&contents),
+                None,
+            )
+            .map_err(|_| {
+                ctx.diags
+                    .report_anon(Level::Fatal, "synthetic source too large")
+                    .emit()
+                    .unwrap_err()
+            })?;
+
+        let base_pos = ctx.smap.get_source(id).range.start();
+        let mut tokenizer = Tokenizer::new(contents.src());
+        let mut tokens = Vec::new();
+
+        loop {
+            let tok = convert_raw(ctx, &tokenizer.next_token(), base_pos)?;
+            match tok.data {
+                ConvertedTokenKind::Real(TokenKind::Eof) => break,
+                ConvertedTokenKind::Real(kind) => tokens.push(Token::new(kind, tok.range)),
+                ConvertedTokenKind::Newline | ConvertedTokenKind::Trivia => {}
+            }
+        }
+
+        Ok(SyntheticSource { id, tokens })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use source::{DiagManager, SourceMap};
+
+    use crate::{Interner, PunctKind};
+
+    use super::*;
+
+    #[test]
+    fn build() {
+        let mut interner = Interner::new();
+        let mut diags = DiagManager::new_annotating(None);
+        let mut smap = SourceMap::new();
+        let mut ctx = LexCtx::new(&mut interner, &mut diags, &mut smap);
+
+        let synth = SyntheticSource::builder(&mut ctx)
+            .name("test")
+            .tokens(["int", "x"])
+            .newline()
+            .tokens(["= 5;"])
+            .build()
+            .unwrap();
+
+        let spellings: Vec<_> = synth
+            .tokens()
+            .iter()
+            .map(|tok| ctx.smap.get_spelling(tok.range))
+            .collect();
+        assert_eq!(spellings, ["int", "x", "=", "5", ";"]);
+
+        assert_eq!(synth.tokens()[2].data, TokenKind::Punct(PunctKind::Eq));
+
+        let source = ctx.smap.get_source(synth.id());
+        assert_eq!(source.as_file().unwrap().contents.src(), "int x\n= 5;");
+    }
+}
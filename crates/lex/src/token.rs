@@ -1,8 +1,9 @@
+use std::borrow::Cow;
 use std::fmt;
 
 use source::SourceRange;
 
-use super::{get_cleaned_spelling, LexCtx, PunctKind, Symbol};
+use super::{get_cleaned_spelling, raw, ucn, LexCtx, PunctKind, Symbol};
 
 /// Enum representing token types.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -18,6 +19,14 @@ pub enum TokenKind {
     Number(Symbol),
     Str(Symbol),
     Char(Symbol),
+
+    /// A header name, including its delimiters. Header names are produced only when lexing the
+    /// operand of `#include` and similar directives, and never reach the parser.
+    HeaderName(Symbol),
+
+    /// A comment, holding its full text (including the delimiters). Comments are normally
+    /// discarded as whitespace, and are only produced by lexers that have been asked to keep them.
+    Comment(Symbol),
 }
 
 /// Represents the possible token types returned by
@@ -66,11 +75,72 @@ impl<D> Token<D> {
 /// Converted token returned by [`crate::convert_raw()`].
 pub type ConvertedToken = Token<ConvertedTokenKind>;
 
+/// How much of the original source text is kept when printing a token as it was spelled (see
+/// [`Token::display_spelled()`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceSpelling {
+    /// The source text with escaped newlines removed (translation phase 2), but with trigraphs and
+    /// universal character names kept as written.
+    Cleaned,
+    /// The source text exactly as written, including escaped newlines.
+    Verbatim,
+}
+
+impl SourceSpelling {
+    /// Applies this mode to the source text `s`.
+    pub fn apply(self, s: &str) -> Cow<'_, str> {
+        match self {
+            SourceSpelling::Cleaned if s.contains("\\\n") => Cow::Owned(raw::clean(s)),
+            _ => Cow::Borrowed(s),
+        }
+    }
+}
+
 impl Token {
     /// Returns an object that implements [`fmt::Display`] for printing the token.
     pub fn display<'t, 'a, 'h>(&'t self, ctx: &'t LexCtx<'a, 'h>) -> Display<'t, 'a, 'h> {
         Display { tok: self, ctx }
     }
+
+    /// Returns an object that implements [`fmt::Display`] for printing the token as it was
+    /// written in the source, keeping as much of the original text as indicated by `spelling`.
+    ///
+    /// Tokens whose range does not actually spell them, such as those produced by stringizing,
+    /// builtin macros or expansions that were not tracked in the source map, are printed as by
+    /// [`Self::display()`].
+    pub fn display_spelled<'t, 'a, 'h>(
+        &'t self,
+        ctx: &'t LexCtx<'a, 'h>,
+        spelling: SourceSpelling,
+    ) -> DisplaySpelled<'t, 'a, 'h> {
+        DisplaySpelled {
+            tok: self,
+            ctx,
+            spelling,
+        }
+    }
+
+    /// Returns the text spelling this token in the source, if its range spells it at all.
+    fn source_text<'s>(&self, ctx: &'s LexCtx<'_, '_>) -> Option<&'s str> {
+        if self.data == TokenKind::Eof {
+            return None;
+        }
+
+        let text = ctx.smap.try_get_spelling(self.range).ok()?;
+        let canonical = self.display(ctx).to_string();
+
+        let denotes_token = |cleaned: String| {
+            cleaned == canonical
+                || matches!(self.data, TokenKind::Ident(_))
+                    && ucn::decode_ucns(&cleaned) == canonical
+        };
+
+        if denotes_token(raw::clean(text)) || denotes_token(raw::clean_trigraphs(text)) {
+            Some(text)
+        } else {
+            None
+        }
+    }
 }
 
 pub struct Display<'t, 'a, 'h> {
@@ -85,11 +155,35 @@ impl fmt::Display for Display<'_, '_, '_> {
             TokenKind::Unknown => {
                 write!(f, "{}", get_cleaned_spelling(self.ctx.smap, self.tok.range))
             }
-            TokenKind::Punct(kind) => write!(f, "{}", kind),
+            TokenKind::Punct(kind) => {
+                write!(f, "{}", self.ctx.punct_spelling(kind, self.tok.range))
+            }
+            TokenKind::Str(_) | TokenKind::Char(_)
+                if self.ctx.is_literal_truncated(self.tok.range) =>
+            {
+                write!(f, "{}", get_cleaned_spelling(self.ctx.smap, self.tok.range))
+            }
             TokenKind::Ident(sym)
             | TokenKind::Number(sym)
             | TokenKind::Str(sym)
-            | TokenKind::Char(sym) => write!(f, "{}", &self.ctx.interner[sym]),
+            | TokenKind::Char(sym)
+            | TokenKind::HeaderName(sym)
+            | TokenKind::Comment(sym) => write!(f, "{}", &self.ctx.interner[sym]),
+        }
+    }
+}
+
+pub struct DisplaySpelled<'t, 'a, 'h> {
+    tok: &'t Token,
+    ctx: &'t LexCtx<'a, 'h>,
+    spelling: SourceSpelling,
+}
+
+impl fmt::Display for DisplaySpelled<'_, '_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.tok.source_text(self.ctx) {
+            Some(text) => write!(f, "{}", self.spelling.apply(text)),
+            None => write!(f, "{}", self.tok.display(self.ctx)),
         }
     }
 }
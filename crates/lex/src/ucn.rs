@@ -0,0 +1,278 @@
+//! Universal character names and extended characters in identifiers (§6.4.2.1, §6.4.3, Annex D).
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+use source::{DResult, LocalOff, SourcePos, SourceRange};
+
+use crate::LexCtx;
+
+/// The ranges of characters allowed in identifiers, as listed in Annex D.1.
+static ALLOWED: &[(u32, u32)] = &[
+    (0xa8, 0xa8),
+    (0xaa, 0xaa),
+    (0xad, 0xad),
+    (0xaf, 0xaf),
+    (0xb2, 0xb5),
+    (0xb7, 0xba),
+    (0xbc, 0xbe),
+    (0xc0, 0xd6),
+    (0xd8, 0xf6),
+    (0xf8, 0xff),
+    (0x100, 0x167f),
+    (0x1681, 0x180d),
+    (0x180f, 0x1fff),
+    (0x200b, 0x200d),
+    (0x202a, 0x202e),
+    (0x203f, 0x2040),
+    (0x2054, 0x2054),
+    (0x2060, 0x206f),
+    (0x2070, 0x218f),
+    (0x2460, 0x24ff),
+    (0x2776, 0x2793),
+    (0x2c00, 0x2dff),
+    (0x2e80, 0x2fff),
+    (0x3004, 0x3007),
+    (0x3021, 0x302f),
+    (0x3031, 0x303f),
+    (0x3040, 0xd7ff),
+    (0xf900, 0xfd3d),
+    (0xfd40, 0xfdcf),
+    (0xfdf0, 0xfe44),
+    (0xfe47, 0xfffd),
+    (0x10000, 0x1fffd),
+    (0x20000, 0x2fffd),
+    (0x30000, 0x3fffd),
+    (0x40000, 0x4fffd),
+    (0x50000, 0x5fffd),
+    (0x60000, 0x6fffd),
+    (0x70000, 0x7fffd),
+    (0x80000, 0x8fffd),
+    (0x90000, 0x9fffd),
+    (0xa0000, 0xafffd),
+    (0xb0000, 0xbfffd),
+    (0xc0000, 0xcfffd),
+    (0xd0000, 0xdfffd),
+    (0xe0000, 0xefffd),
+];
+
+/// The ranges of characters that may not begin an identifier, as listed in Annex D.2.
+static NOT_INITIAL: &[(u32, u32)] = &[
+    (0x300, 0x36f),
+    (0x1dc0, 0x1dff),
+    (0x20d0, 0x20ff),
+    (0xfe20, 0xfe2f),
+];
+
+/// Checks whether `c` lies in one of the sorted, inclusive `ranges`.
+fn in_ranges(ranges: &[(u32, u32)], c: char) -> bool {
+    let c = u32::from(c);
+    ranges
+        .binary_search_by(|&(start, end)| {
+            if end < c {
+                Ordering::Less
+            } else if start > c {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+/// Checks whether the non-basic character `c` may appear in an identifier.
+pub fn is_extended_ident_continue(c: char) -> bool {
+    in_ranges(ALLOWED, c)
+}
+
+/// Checks whether the non-basic character `c` may begin an identifier.
+pub fn is_extended_ident_start(c: char) -> bool {
+    is_extended_ident_continue(c) && !in_ranges(NOT_INITIAL, c)
+}
+
+/// Returns the number of hex digits following `\u` or `\U` in a universal character name.
+pub fn ucn_digits(kind: char) -> Option<usize> {
+    match kind {
+        'u' => Some(4),
+        'U' => Some(8),
+        _ => None,
+    }
+}
+
+/// Replaces every universal character name in `spelling` that designates a valid character by that
+/// character, without reporting anything.
+///
+/// This approximates [`canonicalize_ident()`] closely enough to check whether a source spelling
+/// denotes a given identifier.
+pub fn decode_ucns(spelling: &str) -> String {
+    let mut decoded = String::with_capacity(spelling.len());
+    let mut rest = spelling;
+
+    while let Some(idx) = rest.find('\\') {
+        decoded.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+
+        let ucn = rest[1..]
+            .chars()
+            .next()
+            .and_then(ucn_digits)
+            .and_then(|digits| {
+                let value = u32::from_str_radix(rest.get(2..2 + digits)?, 16).ok()?;
+                Some((char::from_u32(value)?, 2 + digits))
+            });
+
+        let len = match ucn {
+            Some((c, len)) => {
+                decoded.push(c);
+                len
+            }
+            None => {
+                decoded.push('\\');
+                1
+            }
+        };
+        rest = &rest[len..];
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Returns the canonical spelling of the identifier `spelling` beginning at `pos`, in which every
+/// universal character name is replaced by the character it designates. Invalid universal
+/// character names are reported and left as they are.
+///
+/// If `precise` is not set, `spelling` does not correspond exactly to the source (e.g., because
+/// escaped newlines were removed from it), and diagnostics cover the entire identifier instead.
+pub fn canonicalize_ident<'s>(
+    ctx: &mut LexCtx<'_, '_>,
+    spelling: Cow<'s, str>,
+    pos: SourcePos,
+    src_len: LocalOff,
+    precise: bool,
+) -> DResult<Cow<'s, str>> {
+    if !spelling.contains('\\') {
+        return Ok(spelling);
+    }
+
+    let mut canonical = String::with_capacity(spelling.len());
+    let mut rest = &spelling[..];
+
+    while let Some(idx) = rest.find('\\') {
+        canonical.push_str(&rest[..idx]);
+        let ucn_off = spelling.len() - rest.len() + idx;
+
+        // The raw lexer only includes complete universal character names in identifiers.
+        let digits = rest[idx + 1..].chars().next().and_then(ucn_digits).unwrap();
+        let ucn_len = 2 + digits;
+        let ucn = &rest[idx..idx + ucn_len];
+        let value = u32::from_str_radix(&ucn[2..], 16).unwrap();
+        rest = &rest[idx + ucn_len..];
+
+        let range = if precise {
+            SourceRange::new(
+                pos.offset(LocalOff::from(ucn_off as u32)),
+                LocalOff::from(ucn_len as u32),
+            )
+        } else {
+            SourceRange::new(pos, src_len)
+        };
+
+        let msg = match char::from_u32(value) {
+            None => Some("invalid universal character".to_owned()),
+            Some(c) if value < 0xa0 && !['$', '@', '`'].contains(&c) => Some(format!(
+                "character '{}' cannot be specified by a universal character name",
+                c.escape_default()
+            )),
+            Some(c) if !is_extended_ident_continue(c) => Some(format!(
+                "character <U+{:04X}> not allowed in an identifier",
+                value
+            )),
+            Some(c) if ucn_off == 0 && !is_extended_ident_start(c) => Some(format!(
+                "character <U+{:04X}> not allowed at the start of an identifier",
+                value
+            )),
+            Some(c) => {
+                canonical.push(c);
+                None
+            }
+        };
+
+        if let Some(msg) = msg {
+            ctx.reporter().error(range, msg).emit()?;
+            canonical.push_str(ucn);
+        }
+    }
+
+    canonical.push_str(rest);
+    Ok(Cow::Owned(canonical))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use source::diag::RecordingSink;
+    use source::{DiagManager, SourceMap};
+
+    use crate::{Interner, SyntheticSource, TokenKind};
+
+    use super::*;
+
+    /// Lexes the identifiers in `src`, returning their interned spellings and the diagnostics
+    /// reported.
+    fn lex_idents(src: &str) -> (Vec<String>, Vec<String>) {
+        let diags = RefCell::new(Vec::new());
+        let mut manager = DiagManager::new(RecordingSink(&diags), None);
+        let mut interner = Interner::new();
+        let mut smap = SourceMap::new();
+
+        let mut ctx = LexCtx::new(&mut interner, &mut manager, &mut smap);
+        let tokens = SyntheticSource::builder(&mut ctx)
+            .tokens([src])
+            .build()
+            .unwrap()
+            .into_tokens();
+
+        let idents = tokens
+            .iter()
+            .map(|tok| match tok.data {
+                TokenKind::Ident(sym) => ctx.interner[sym].to_owned(),
+                _ => panic!("expected an identifier"),
+            })
+            .collect();
+
+        drop(manager);
+        (idents, diags.into_inner())
+    }
+
+    #[test]
+    fn canonical_spelling() {
+        let (idents, diags) = lex_idents("caf\\u00e9 caf\u{e9} \\U000003B1x a\\u0300");
+        assert_eq!(idents, ["caf\u{e9}", "caf\u{e9}", "\u{3b1}x", "a\u{300}"]);
+        assert!(diags.is_empty());
+    }
+
+    #[test]
+    fn invalid_ucns() {
+        let (idents, diags) = lex_idents("\\u0041bc a\\uD800 b\\u2000 \\u0300a");
+        assert_eq!(idents, ["\\u0041bc", "a\\uD800", "b\\u2000", "\\u0300a"]);
+        assert_eq!(
+            diags,
+            [
+                "error: character 'A' cannot be specified by a universal character name",
+                "error: invalid universal character",
+                "error: character <U+2000> not allowed in an identifier",
+                "error: character <U+0300> not allowed at the start of an identifier",
+            ]
+        );
+    }
+
+    #[test]
+    fn extended_char_classes() {
+        assert!(is_extended_ident_start('\u{e9}'));
+        assert!(is_extended_ident_continue('\u{300}'));
+        assert!(!is_extended_ident_start('\u{300}'));
+        assert!(!is_extended_ident_continue('\u{d7}'));
+    }
+}
@@ -0,0 +1,210 @@
+use std::io::{self, Write};
+
+use crate::raw::{LexerConfig, Tokenizer};
+use crate::{LexCtx, Token};
+
+/// The whitespace separating a token from the one preceding it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Spacing {
+    /// The token immediately follows the previous one.
+    Joint,
+    /// The token is separated from the previous one by whitespace or comments on the same line.
+    Space,
+    /// The token is the first on its line.
+    Newline,
+}
+
+/// Writes tokens to an output stream as text, inserting only as much whitespace as is needed for
+/// the output to lex back into the same tokens.
+///
+/// Tokens are separated by a single space where their [`Spacing`] requests it, and also wherever
+/// writing them next to each other would paste them together or change how they are lexed (such
+/// as `+` followed by `+`, or `/` followed by `*`).
+pub struct TokenWriter<W> {
+    out: W,
+    config: LexerConfig,
+    /// The spellings of (at most) the last two tokens written, if nothing separates them from the
+    /// next token.
+    tail: String,
+    /// The length of the spelling of the last token in `tail`.
+    last_len: usize,
+    /// Whether nothing has been written on the current line yet.
+    line_empty: bool,
+}
+
+impl<W: Write> TokenWriter<W> {
+    /// Creates a new writer writing to `out`, which checks for pasting using the default lexer
+    /// configuration.
+    pub fn new(out: W) -> Self {
+        Self::with_config(out, LexerConfig::default())
+    }
+
+    /// Creates a new writer writing to `out`, which checks for pasting as if the output will be
+    /// lexed according to `config`.
+    pub fn with_config(out: W, config: LexerConfig) -> Self {
+        Self {
+            out,
+            config,
+            tail: String::new(),
+            last_len: 0,
+            line_empty: true,
+        }
+    }
+
+    /// Writes `tok`, preceded by a newline or a space as indicated by `spacing`.
+    ///
+    /// No space is written at the start of a line, and no newline is written if the current line
+    /// is still empty.
+    pub fn write_token(
+        &mut self,
+        ctx: &LexCtx<'_, '_>,
+        tok: Token,
+        spacing: Spacing,
+    ) -> io::Result<()> {
+        let spelling = tok.display(ctx).to_string();
+
+        match spacing {
+            Spacing::Newline => {
+                self.end_line()?;
+            }
+            Spacing::Space if !self.line_empty => {
+                self.write_separator(" ")?;
+            }
+            _ => {
+                if self.pastes(&spelling) {
+                    self.write_separator(" ")?;
+                }
+            }
+        }
+
+        self.out.write_all(spelling.as_bytes())?;
+        self.line_empty = false;
+
+        let keep = self.tail.len() - self.last_len;
+        self.tail.drain(..keep);
+        self.tail.push_str(&spelling);
+        self.last_len = spelling.len();
+
+        Ok(())
+    }
+
+    /// Writes `s` verbatim, such as indentation or a line marker. The next token written is
+    /// assumed not to interact with `s` or anything before it.
+    pub fn write_raw(&mut self, s: &str) -> io::Result<()> {
+        if s.is_empty() {
+            return Ok(());
+        }
+
+        self.write_separator(s)?;
+        self.line_empty = s.ends_with('\n');
+        Ok(())
+    }
+
+    /// Terminates the current line if anything has been written on it, returning whether a
+    /// newline was written.
+    pub fn end_line(&mut self) -> io::Result<bool> {
+        if self.line_empty {
+            return Ok(false);
+        }
+
+        self.write_separator("\n")?;
+        self.line_empty = true;
+        Ok(true)
+    }
+
+    /// Returns whether nothing has been written on the current line yet.
+    pub fn is_line_empty(&self) -> bool {
+        self.line_empty
+    }
+
+    /// Returns a mutable reference to the underlying output stream.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    /// Consumes the writer, returning the underlying output stream.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /// Writes `s`, which separates the tokens on either side of it.
+    fn write_separator(&mut self, s: &str) -> io::Result<()> {
+        self.tail.clear();
+        self.last_len = 0;
+        self.out.write_all(s.as_bytes())
+    }
+
+    /// Checks whether writing `spelling` directly after the last tokens would change how they are
+    /// lexed.
+    fn pastes(&self, spelling: &str) -> bool {
+        if self.tail.is_empty() {
+            return false;
+        }
+
+        let joined = format!("{}{}", self.tail, spelling);
+        let mut tokenizer = Tokenizer::with_config(&joined, self.config);
+
+        let mut end = 0;
+        while end < self.tail.len() {
+            end += tokenizer.next_token().content.str.len();
+        }
+
+        end != self.tail.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use source::{DiagManager, SourceMap};
+
+    use crate::{Interner, SyntheticSource};
+
+    use super::*;
+
+    /// Writes tokens with the given spellings and spacing, returning the output.
+    fn write(toks: &[(&str, Spacing)]) -> String {
+        let mut interner = Interner::new();
+        let mut diags = DiagManager::new_annotating(None);
+        let mut smap = SourceMap::new();
+        let mut ctx = LexCtx::new(&mut interner, &mut diags, &mut smap);
+
+        let tokens = SyntheticSource::builder(&mut ctx)
+            .tokens(toks.iter().map(|&(spelling, _)| spelling))
+            .build()
+            .unwrap()
+            .into_tokens();
+        assert_eq!(tokens.len(), toks.len());
+
+        let mut writer = TokenWriter::new(Vec::new());
+        for (tok, &(_, spacing)) in tokens.into_iter().zip(toks) {
+            writer.write_token(&ctx, tok, spacing).unwrap();
+        }
+
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn avoids_pasting() {
+        use Spacing::*;
+
+        assert_eq!(
+            write(&[("+", Joint), ("+", Joint), ("-", Joint), ("a", Joint)]),
+            "+ +-a"
+        );
+        assert_eq!(write(&[("a", Joint), ("b", Joint), ("c", Space)]), "a b c");
+        assert_eq!(write(&[("/", Joint), ("/", Joint), ("*", Joint)]), "/ / *");
+        assert_eq!(write(&[(".", Joint), (".", Joint), (".", Joint)]), ".. .");
+        assert_eq!(write(&[("<", Joint), (":", Joint)]), "< :");
+    }
+
+    #[test]
+    fn lines() {
+        use Spacing::*;
+
+        assert_eq!(
+            write(&[("a", Newline), ("b", Space), ("c", Newline), ("d", Joint)]),
+            "a b\nc d"
+        );
+        assert_eq!(write(&[("a", Space), ("(", Joint), (")", Joint)]), "a()");
+    }
+}
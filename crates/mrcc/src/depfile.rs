@@ -0,0 +1,124 @@
+//! Makefile-style dependency files, as written with `-MD`.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Describes the dependency file to write for an input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DepFile {
+    /// The path to which the dependency file should be written.
+    pub path: PathBuf,
+    /// The target of the rule in the dependency file.
+    pub target: String,
+}
+
+impl DepFile {
+    /// Creates a description of the default dependency file for `input`.
+    ///
+    /// If `output` is provided, it is used as the target and the dependency file is placed next to
+    /// it. Otherwise, the target is the object file that would be built from `input` in the
+    /// current directory, and the dependency file is placed there as well.
+    pub fn for_input(input: &Path, output: Option<&Path>) -> Self {
+        let stem = Path::new(input.file_stem().unwrap_or_default());
+
+        match output {
+            Some(output) => Self {
+                path: output.with_extension("d"),
+                target: output.display().to_string(),
+            },
+            None => Self {
+                path: stem.with_extension("d"),
+                target: stem.with_extension("o").display().to_string(),
+            },
+        }
+    }
+
+    /// Writes the dependency file, listing `main` (if any) followed by `deps`.
+    pub fn write<'a>(
+        &self,
+        main: Option<&Path>,
+        deps: impl Iterator<Item = &'a Path>,
+    ) -> io::Result<()> {
+        fs::write(&self.path, format_rule(&self.target, main, deps))
+    }
+}
+
+/// Formats a Makefile rule stating that `target` depends on `main` and `deps`, with `main` on the
+/// first line and one further prerequisite per line.
+fn format_rule<'a>(
+    target: &str,
+    main: Option<&Path>,
+    deps: impl Iterator<Item = &'a Path>,
+) -> String {
+    let mut rule = format!("{}:", escape(target));
+
+    if let Some(main) = main {
+        rule.push(' ');
+        rule.push_str(&escape(&main.display().to_string()));
+    }
+
+    for dep in deps {
+        rule.push_str(" \\\n  ");
+        rule.push_str(&escape(&dep.display().to_string()));
+    }
+
+    rule.push('\n');
+    rule
+}
+
+/// Escapes the characters in `path` that are special to `make`.
+fn escape(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+
+    for c in path.chars() {
+        match c {
+            ' ' | '#' => escaped.push('\\'),
+            '$' => escaped.push('$'),
+            _ => {}
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_paths() {
+        assert_eq!(
+            DepFile::for_input(Path::new("src/main.c"), None),
+            DepFile {
+                path: "main.d".into(),
+                target: "main.o".into()
+            }
+        );
+        assert_eq!(
+            DepFile::for_input(Path::new("src/main.c"), Some(Path::new("out/main.i"))),
+            DepFile {
+                path: "out/main.d".into(),
+                target: "out/main.i".into()
+            }
+        );
+    }
+
+    #[test]
+    fn rule() {
+        let deps = [Path::new("inc/a.h"), Path::new("my dir/$x#.h")];
+        assert_eq!(
+            format_rule("main.o", Some(Path::new("main.c")), deps.iter().copied()),
+            "main.o: main.c \\\n  inc/a.h \\\n  my\\ dir/$$x\\#.h\n"
+        );
+        assert_eq!(
+            format_rule("a b.o", Some(Path::new("a b.c")), std::iter::empty()),
+            "a\\ b.o: a\\ b.c\n"
+        );
+        assert_eq!(
+            format_rule("-.o", None, deps[..1].iter().copied()),
+            "-.o: \\\n  inc/a.h\n"
+        );
+    }
+}
@@ -0,0 +1,86 @@
+//! Handling of environment variables that affect preprocessing.
+
+use std::env;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+/// Include directories specified in the environment.
+///
+/// As in gcc, directories from `CPATH` are searched as if they had been passed with `-I` (after
+/// any explicit ones), while `C_INCLUDE_PATH` directories are searched as system directories.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct EnvIncludeDirs {
+    /// The directories listed in `CPATH`.
+    pub user: Vec<PathBuf>,
+    /// The directories listed in `C_INCLUDE_PATH`.
+    pub system: Vec<PathBuf>,
+}
+
+/// Collects the include directories specified in the environment.
+pub fn include_dirs() -> EnvIncludeDirs {
+    include_dirs_from(|var| env::var_os(var))
+}
+
+/// Collects the include directories specified by the variables returned from `lookup`.
+pub fn include_dirs_from(lookup: impl Fn(&str) -> Option<OsString>) -> EnvIncludeDirs {
+    EnvIncludeDirs {
+        user: split_dirs(lookup("CPATH")),
+        system: split_dirs(lookup("C_INCLUDE_PATH")),
+    }
+}
+
+/// Splits the value of an include path variable into its directories.
+///
+/// The value is interpreted as a list of paths separated by the platform's path separator. An
+/// empty element refers to the current directory.
+fn split_dirs(val: Option<OsString>) -> Vec<PathBuf> {
+    val.filter(|val| !val.is_empty())
+        .map(|val| {
+            env::split_paths(&val)
+                .map(|dir| {
+                    if dir.as_os_str().is_empty() {
+                        PathBuf::from(".")
+                    } else {
+                        dir
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn check(vars: &[(&str, &str)], user: &[&str], system: &[&str]) {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        let dirs = include_dirs_from(|var| vars.get(var).map(OsString::from));
+        let to_paths = |dirs: &[&str]| dirs.iter().map(PathBuf::from).collect::<Vec<_>>();
+        assert_eq!(dirs.user, to_paths(user));
+        assert_eq!(dirs.system, to_paths(system));
+    }
+
+    #[test]
+    fn unset() {
+        check(&[], &[], &[]);
+        check(&[("CPATH", "")], &[], &[]);
+    }
+
+    #[test]
+    fn user_and_system() {
+        check(
+            &[("C_INCLUDE_PATH", "/sys/a"), ("CPATH", "/usr/a")],
+            &["/usr/a"],
+            &["/sys/a"],
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn split() {
+        check(&[("CPATH", "/a:/b::/c")], &["/a", "/b", ".", "/c"], &[]);
+    }
+}
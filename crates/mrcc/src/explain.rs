@@ -0,0 +1,386 @@
+//! Standalone HTML explanations of macro expansions, for use with `--explain-expansion`.
+
+use std::fmt::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use lex::LexCtx;
+use pp::{ExpansionStage, ExpansionStep, ExpansionTrace, FileSystem, PpToken};
+use source::smap::FileName;
+use source::{LineCol, SourceMap};
+
+/// A (1-based) location in a file, as passed to `--explain-expansion`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpansionLoc {
+    pub file: PathBuf,
+    pub line: u32,
+    pub col: u32,
+}
+
+impl FromStr for ExpansionLoc {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("invalid location '{}', expected 'file:line:col'", s);
+
+        let mut parts = s.rsplitn(3, ':');
+        let col = parts.next().and_then(|col| col.parse().ok());
+        let line = parts.next().and_then(|line| line.parse().ok());
+        let file = parts.next().filter(|file| !file.is_empty());
+
+        match (file, line, col) {
+            (Some(file), Some(line), Some(col)) if line > 0 && col > 0 => Ok(Self {
+                file: file.into(),
+                line,
+                col,
+            }),
+            _ => Err(err()),
+        }
+    }
+}
+
+impl fmt::Display for ExpansionLoc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file.display(), self.line, self.col)
+    }
+}
+
+/// Finds the top-level step in `trace` expanding the macro invocation containing `loc`.
+///
+/// File names are compared after canonicalizing them through `fs`.
+pub fn find_invocation(
+    smap: &SourceMap,
+    fs: &dyn FileSystem,
+    trace: &ExpansionTrace,
+    loc: &ExpansionLoc,
+) -> Option<usize> {
+    let pos = LineCol {
+        line: loc.line - 1,
+        col: loc.col - 1,
+    };
+
+    trace.roots().find(|&idx| {
+        let interp = smap.get_interpreted_range(trace.steps()[idx].replacement_range);
+        let start = interp.start_linecol();
+        let end = interp.end_linecol();
+
+        start <= pos
+            && pos < end
+            && match interp.filename() {
+                FileName::Real(path) => is_same_file(fs, path, &loc.file),
+                FileName::Synth(_) => false,
+            }
+    })
+}
+
+/// Checks whether `a` and `b` refer to the same file, even if they are spelled differently.
+fn is_same_file(fs: &dyn FileSystem, a: &Path, b: &Path) -> bool {
+    a == b
+        || matches!(
+            (fs.canonicalize(a), fs.canonicalize(b)),
+            (Ok(a), Ok(b)) if a == b
+        )
+}
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; }
+code, pre { font-family: monospace; }
+pre { background: #f4f4f4; padding: 0.5em; white-space: pre-wrap; }
+ol { list-style: none; padding-left: 1.5em; border-left: 2px solid #ddd; }
+summary { cursor: pointer; }
+table { border-collapse: collapse; margin: 0.5em 0; }
+th, td { border: 1px solid #ccc; padding: 0.25em 0.5em; text-align: left; }
+.loc { color: #666; }
+.none { color: #999; font-style: italic; }
+";
+
+/// Renders a standalone HTML page explaining the top-level expansion step `root` in `trace`.
+///
+/// The page shows the invocation as written, a tree of every replacement performed (with the
+/// arguments of function-like macros before and after pre-expansion, and the tokens produced by
+/// substitution before rescanning), and the final token stream.
+pub fn render_html(ctx: &LexCtx<'_, '_>, trace: &ExpansionTrace, root: usize) -> String {
+    let mut out = String::new();
+    write_html(&mut out, ctx, trace, root).unwrap();
+    out
+}
+
+fn write_html(
+    out: &mut String,
+    ctx: &LexCtx<'_, '_>,
+    trace: &ExpansionTrace,
+    root: usize,
+) -> fmt::Result {
+    let step = &trace.steps()[root];
+    let name = &ctx.interner[step.name_tok.data()];
+    let location = describe_location(ctx.smap, step);
+
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html>")?;
+    writeln!(out, "<head>")?;
+    writeln!(out, "<meta charset=\"utf-8\">")?;
+    writeln!(
+        out,
+        "<title>Expansion of {} at {}</title>",
+        escape(name),
+        escape(&location)
+    )?;
+    writeln!(out, "<style>\n{}</style>", STYLE)?;
+    writeln!(out, "</head>")?;
+    writeln!(out, "<body>")?;
+    writeln!(out, "<h1>Expansion of <code>{}</code></h1>", escape(name))?;
+    writeln!(out, "<p class=\"loc\">{}</p>", escape(&location))?;
+
+    writeln!(out, "<h2>Invocation</h2>")?;
+    writeln!(
+        out,
+        "<pre>{}</pre>",
+        escape(ctx.smap.get_spelling(step.replacement_range))
+    )?;
+
+    writeln!(out, "<h2>Steps</h2>")?;
+    writeln!(out, "<ol>")?;
+    write_step(out, ctx, trace, root)?;
+    writeln!(out, "</ol>")?;
+
+    writeln!(out, "<h2>Result</h2>")?;
+    write_tokens(out, ctx, &step.result)?;
+
+    writeln!(out, "</body>")?;
+    writeln!(out, "</html>")
+}
+
+/// Writes the step `idx` as a list item, followed by any nested steps.
+fn write_step(
+    out: &mut String,
+    ctx: &LexCtx<'_, '_>,
+    trace: &ExpansionTrace,
+    idx: usize,
+) -> fmt::Result {
+    let step = &trace.steps()[idx];
+
+    writeln!(out, "<li><details open>")?;
+    writeln!(
+        out,
+        "<summary><code>{}</code> <span class=\"loc\">({})</span></summary>",
+        escape(&describe_invocation(ctx, step)),
+        escape(&describe_location(ctx.smap, step))
+    )?;
+
+    if !step.args.is_empty() {
+        writeln!(out, "<table>")?;
+        writeln!(
+            out,
+            "<tr><th>Parameter</th><th>Argument</th><th>Pre-expanded</th></tr>"
+        )?;
+
+        for arg in &step.args {
+            let pre_expanded = match &arg.pre_expanded {
+                Some(tokens) => format!("<code>{}</code>", escape(&spell(ctx, tokens))),
+                None => "<span class=\"none\">not expanded</span>".to_owned(),
+            };
+
+            writeln!(
+                out,
+                "<tr><td><code>{}</code></td><td><code>{}</code></td><td>{}</td></tr>",
+                escape(&ctx.interner[arg.param]),
+                escape(&spell(ctx, &arg.tokens)),
+                pre_expanded
+            )?;
+        }
+
+        writeln!(out, "</table>")?;
+
+        for (arg_idx, arg) in step.args.iter().enumerate() {
+            let title = format!(
+                "Pre-expansion of <code>{}</code>",
+                escape(&ctx.interner[arg.param])
+            );
+            write_children(
+                out,
+                ctx,
+                trace,
+                idx,
+                ExpansionStage::ArgPreExpansion(arg_idx),
+                &title,
+            )?;
+        }
+    }
+
+    writeln!(out, "<h3>After substitution</h3>")?;
+    write_tokens(out, ctx, &step.replacement)?;
+    write_children(out, ctx, trace, idx, ExpansionStage::Rescan, "Rescanning")?;
+
+    writeln!(out, "</details></li>")
+}
+
+/// Writes the steps performed during `stage` of the step `parent` under the heading `title`, if
+/// there are any.
+fn write_children(
+    out: &mut String,
+    ctx: &LexCtx<'_, '_>,
+    trace: &ExpansionTrace,
+    parent: usize,
+    stage: ExpansionStage,
+    title: &str,
+) -> fmt::Result {
+    let mut children = trace.children(parent, stage).peekable();
+    if children.peek().is_none() {
+        return Ok(());
+    }
+
+    writeln!(out, "<h3>{}</h3>", title)?;
+    writeln!(out, "<ol>")?;
+    for child in children {
+        write_step(out, ctx, trace, child)?;
+    }
+    writeln!(out, "</ol>")
+}
+
+fn write_tokens(out: &mut String, ctx: &LexCtx<'_, '_>, tokens: &[PpToken]) -> fmt::Result {
+    if tokens.is_empty() {
+        writeln!(out, "<p class=\"none\">no tokens</p>")
+    } else {
+        writeln!(out, "<pre>{}</pre>", escape(&spell(ctx, tokens)))
+    }
+}
+
+/// Describes the invocation replaced by `step`, such as `F(a, b)`.
+fn describe_invocation(ctx: &LexCtx<'_, '_>, step: &ExpansionStep) -> String {
+    let name = &ctx.interner[step.name_tok.data()];
+
+    // Only function-like macro invocations replace more than their name.
+    if step.replacement_range == step.name_tok.range() {
+        return name.to_owned();
+    }
+
+    let args: Vec<_> = step
+        .args
+        .iter()
+        .map(|arg| spell(ctx, &arg.tokens))
+        .collect();
+    format!("{}({})", name, args.join(", "))
+}
+
+/// Describes the location at which the macro name replaced by `step` is spelled.
+fn describe_location(smap: &SourceMap, step: &ExpansionStep) -> String {
+    let pos = smap.get_spelling_pos(step.name_tok.range().start());
+    let interp = smap.get_interpreted_range(pos.into());
+    let linecol = interp.presumed_start_linecol();
+
+    format!(
+        "{}:{}:{}",
+        interp.presumed_filename(),
+        linecol.line + 1,
+        linecol.col + 1
+    )
+}
+
+/// Spells `tokens`, separating them with spaces where they were originally separated by
+/// whitespace.
+fn spell(ctx: &LexCtx<'_, '_>, tokens: &[PpToken]) -> String {
+    let spelling: String = tokens
+        .iter()
+        .map(|tok| tok.display(ctx).to_string())
+        .collect();
+    spelling.trim_start().to_owned()
+}
+
+/// Escapes `s` for inclusion in HTML text or attribute values.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use lex::{Interner, TokenKind};
+    use pp::PreprocessorBuilder;
+    use source::smap::FileContents;
+    use source::DiagManager;
+
+    use super::*;
+
+    fn loc(file: &str, line: u32, col: u32) -> ExpansionLoc {
+        ExpansionLoc {
+            file: file.into(),
+            line,
+            col,
+        }
+    }
+
+    /// Preprocesses `src` as the file `t.c`, returning the explanation of the invocation at `loc`.
+    fn explain(src: &str, loc: &ExpansionLoc) -> Option<String> {
+        let mut diags = DiagManager::new_annotating(None);
+        let mut interner = Interner::new();
+        let mut smap = SourceMap::new();
+        let main_id = smap
+            .create_file(FileName::real("t.c"), FileContents::new(src), None)
+            .unwrap();
+
+        let mut ctx = LexCtx::new(&mut interner, &mut diags, &mut smap);
+        let mut pp = PreprocessorBuilder::new(&mut ctx, main_id)
+            .trace_expansions(true)
+            .build();
+        while pp.next_pp(&mut ctx).unwrap().data() != TokenKind::Eof {}
+
+        let trace = pp.expansion_trace().unwrap();
+        find_invocation(ctx.smap, pp.file_system(), trace, loc)
+            .map(|root| render_html(&ctx, trace, root))
+    }
+
+    #[test]
+    fn parse_location() {
+        assert_eq!("t.c:3:7".parse(), Ok(loc("t.c", 3, 7)));
+        assert_eq!("C:/src/t.c:1:1".parse(), Ok(loc("C:/src/t.c", 1, 1)));
+
+        for invalid in &["t.c", "t.c:3", ":3:7", "t.c:0:1", "t.c:x:1"] {
+            assert!(invalid.parse::<ExpansionLoc>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn explain_function_macro() {
+        let src = "#define ONE 1\n#define F(x, y) x < #y\nint a = F(ONE, ONE);\n";
+        let html = explain(src, &loc("t.c", 3, 12)).unwrap();
+
+        let expected = [
+            "<title>Expansion of F at t.c:3:9</title>",
+            "<pre>F(ONE, ONE)</pre>",
+            "<tr><td><code>x</code></td><td><code>ONE</code></td><td><code>1</code></td></tr>",
+            "<tr><td><code>y</code></td><td><code>ONE</code></td>\
+             <td><span class=\"none\">not expanded</span></td></tr>",
+            "<h3>Pre-expansion of <code>x</code></h3>",
+            "<summary><code>ONE</code> <span class=\"loc\">(t.c:3:11)</span></summary>",
+            "<h3>After substitution</h3>\n<pre>1 &lt; &quot;ONE&quot;</pre>",
+            "<h2>Result</h2>\n<pre>1 &lt; &quot;ONE&quot;</pre>",
+        ];
+
+        for fragment in &expected {
+            assert!(
+                html.contains(fragment),
+                "missing {:?} in:\n{}",
+                fragment,
+                html
+            );
+        }
+        assert!(!html.contains("Pre-expansion of <code>y</code>"));
+    }
+
+    #[test]
+    fn no_invocation() {
+        let src = "#define A 1\nint a = A;\n";
+        assert!(explain(src, &loc("t.c", 2, 9)).is_some());
+        assert!(explain(src, &loc("t.c", 2, 8)).is_none());
+        assert!(explain(src, &loc("u.c", 2, 9)).is_none());
+    }
+}
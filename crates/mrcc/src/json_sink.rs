@@ -0,0 +1,431 @@
+//! Machine-readable diagnostic output, for consumption by editors and other tools.
+
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use source::diag::{
+    MessageArg, RenderedDiagnostic, RenderedSink, RenderedSubDiagnostic, RenderedSuggestion,
+};
+use source::{SourceMap, SourceRange};
+
+/// A serializable description of a reported diagnostic or note.
+#[derive(Serialize)]
+pub struct DiagnosticInfo {
+    level: &'static str,
+    message: String,
+    /// The template from which `message` was formatted, if it has any arguments.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    template: Option<String>,
+    /// The arguments substituted into `template`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    args: Vec<ArgInfo>,
+    /// The name of the diagnostic's ID, if it has one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<&'static str>,
+    /// The presumed location of the start of the primary range, which takes `#line` directives
+    /// into account.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    location: Option<Location>,
+    /// The primary range, within the file in which it actually resides.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    range: Option<RangeInfo>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    subranges: Vec<SubrangeInfo>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    suggestions: Vec<SuggestionInfo>,
+    /// The macro expansions leading to the primary range, from innermost to outermost.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    expansions: Vec<RangeInfo>,
+    /// The include trace leading to the file of the primary range, from outermost to innermost.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    includes: Vec<Location>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    notes: Vec<DiagnosticInfo>,
+}
+
+impl DiagnosticInfo {
+    /// Creates a description of `diag`, using `smap` to resolve its locations.
+    ///
+    /// Expansion trace notes are attached to the subdiagnostics they belong to rather than being
+    /// listed as separate notes.
+    pub fn new(diag: &RenderedDiagnostic, smap: Option<&SourceMap>) -> Self {
+        let mut info = Self::from_subdiag(diag.level().as_str(), diag.main(), smap);
+        info.id = diag.id().map(|id| id.name());
+
+        if let Some(smap) = smap {
+            info.includes = diag
+                .includes
+                .iter()
+                .map(|&pos| Location::new(smap, pos.into()))
+                .collect();
+        }
+
+        for note in diag.notes() {
+            if note.is_expansion_trace() {
+                let owner = match info.notes.last_mut() {
+                    Some(note) => note,
+                    None => &mut info,
+                };
+                owner.expansions.extend(
+                    smap.zip(note.ranges.as_ref())
+                        .map(|(smap, ranges)| RangeInfo::new(smap, ranges.primary_range)),
+                );
+            } else {
+                info.notes.push(Self::from_subdiag("note", note, smap));
+            }
+        }
+
+        info
+    }
+
+    fn from_subdiag(
+        level: &'static str,
+        subdiag: &RenderedSubDiagnostic,
+        smap: Option<&SourceMap>,
+    ) -> Self {
+        let mut info = Self {
+            level,
+            message: subdiag.msg.to_string(),
+            template: Some(subdiag.msg.template().to_owned())
+                .filter(|_| !subdiag.msg.args().is_empty()),
+            args: subdiag
+                .msg
+                .args()
+                .iter()
+                .map(|(name, val)| ArgInfo::new(name, val))
+                .collect(),
+            id: None,
+            location: None,
+            range: None,
+            subranges: Vec::new(),
+            suggestions: Vec::new(),
+            expansions: Vec::new(),
+            includes: Vec::new(),
+            notes: Vec::new(),
+        };
+
+        if let Some((smap, ranges)) = smap.zip(subdiag.ranges.as_ref()) {
+            info.location = Some(Location::new(smap, ranges.primary_range));
+            info.range = Some(RangeInfo::new(smap, ranges.primary_range));
+            info.subranges = ranges
+                .subranges
+                .iter()
+                .map(|(range, label)| SubrangeInfo {
+                    range: RangeInfo::new(smap, *range),
+                    label: Some(label.clone()).filter(|label| !label.is_empty()),
+                })
+                .collect();
+            info.suggestions = subdiag
+                .suggestions
+                .iter()
+                .map(|suggestion| SuggestionInfo::new(smap, suggestion))
+                .collect();
+        }
+
+        info
+    }
+}
+
+/// A (1-based) location in a file.
+#[derive(Serialize)]
+struct Location {
+    file: String,
+    line: u32,
+    col: u32,
+}
+
+impl Location {
+    /// Returns the presumed location of the start of `range`.
+    fn new(smap: &SourceMap, range: SourceRange) -> Self {
+        let interp = smap.get_interpreted_range(range);
+        let linecol = interp.presumed_start_linecol();
+        Self {
+            file: interp.presumed_filename().to_string(),
+            line: linecol.line + 1,
+            col: linecol.col + 1,
+        }
+    }
+}
+
+/// A (1-based) position in a file.
+#[derive(Serialize)]
+struct Position {
+    line: u32,
+    col: u32,
+}
+
+/// A range in a file, with an exclusive end position.
+#[derive(Serialize)]
+struct RangeInfo {
+    file: String,
+    start: Position,
+    end: Position,
+}
+
+impl RangeInfo {
+    fn new(smap: &SourceMap, range: SourceRange) -> Self {
+        let interp = smap.get_interpreted_range(range);
+        let start = interp.start_linecol();
+        let end = interp.end_linecol();
+
+        Self {
+            file: interp.filename().to_string(),
+            start: Position {
+                line: start.line + 1,
+                col: start.col + 1,
+            },
+            end: Position {
+                line: end.line + 1,
+                col: end.col + 1,
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SubrangeInfo {
+    #[serde(flatten)]
+    range: RangeInfo,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+}
+
+/// A named argument of a diagnostic message.
+#[derive(Serialize)]
+struct ArgInfo {
+    name: &'static str,
+    #[serde(flatten)]
+    value: ArgValue,
+}
+
+impl ArgInfo {
+    fn new(name: &'static str, arg: &MessageArg) -> Self {
+        let value = match arg {
+            MessageArg::Symbol(name) => ArgValue::Symbol(name.clone()),
+            MessageArg::Path(path) => ArgValue::Path(path.display().to_string()),
+            MessageArg::Int(val) => ArgValue::Int(*val),
+            MessageArg::Str(text) => ArgValue::Str(text.clone()),
+        };
+
+        Self { name, value }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", content = "value", rename_all = "lowercase")]
+enum ArgValue {
+    Symbol(String),
+    Path(String),
+    Int(i128),
+    Str(String),
+}
+
+/// A suggested replacement of the text in `range` with `text`.
+#[derive(Serialize)]
+struct SuggestionInfo {
+    range: RangeInfo,
+    text: String,
+    /// Whether the suggestion can be applied automatically, as returned by
+    /// [`Applicability::as_str()`](source::diag::Applicability::as_str()).
+    applicability: &'static str,
+}
+
+impl SuggestionInfo {
+    fn new(smap: &SourceMap, suggestion: &RenderedSuggestion) -> Self {
+        Self {
+            range: RangeInfo::new(smap, suggestion.replacement_range),
+            text: suggestion.insert_text.clone(),
+            applicability: suggestion.applicability.as_str(),
+        }
+    }
+}
+
+/// A diagnostic sink writing every diagnostic to a stream as a single line of JSON.
+///
+/// Each line holds a serialized [`DiagnosticInfo`].
+pub struct JsonSink<W> {
+    out: W,
+}
+
+impl<W: Write> JsonSink<W> {
+    /// Creates a new sink writing to `out`.
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+
+    fn write(&mut self, info: &DiagnosticInfo) -> io::Result<()> {
+        serde_json::to_writer(&mut self.out, info)?;
+        writeln!(self.out)
+    }
+}
+
+impl<W: Write> RenderedSink for JsonSink<W> {
+    fn report(&mut self, diag: &RenderedDiagnostic, smap: Option<&SourceMap>) {
+        self.write(&DiagnosticInfo::new(diag, smap))
+            .expect("failed to write diagnostic");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use source::diag::{
+        render, Applicability, Level, Message, RawDiagnostic, RawSubDiagnostic, RawSuggestion,
+    };
+    use source::smap::{ExpansionKind, FileContents, FileName};
+    use source::LocalRange;
+
+    use super::*;
+
+    fn report(raw: &RawDiagnostic, smap: Option<&SourceMap>) -> String {
+        let mut out = Vec::new();
+        JsonSink::new(&mut out).report(&render(raw, smap), smap);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn anon() {
+        let raw = RawDiagnostic {
+            level: Level::Warning,
+            id: None,
+            main: RawSubDiagnostic::new_anon("no input"),
+            notes: Vec::new(),
+        };
+
+        assert_eq!(
+            report(&raw, None),
+            r#"{"level":"warning","message":"no input"}"#.to_owned() + "\n"
+        );
+    }
+
+    #[test]
+    fn message_args() {
+        let raw = RawDiagnostic {
+            level: Level::Error,
+            id: None,
+            main: RawSubDiagnostic::new_anon(
+                Message::new("macro '{name}' in '{file}' has {count} parameters")
+                    .arg("name", MessageArg::symbol("FOO"))
+                    .arg("file", std::path::Path::new("a.h"))
+                    .arg("count", 2u32),
+            ),
+            notes: Vec::new(),
+        };
+
+        assert_eq!(
+            report(&raw, None),
+            concat!(
+                r#"{"level":"error","message":"macro 'FOO' in 'a.h' has 2 parameters","#,
+                r#""template":"macro '{name}' in '{file}' has {count} parameters","#,
+                r#""args":[{"name":"name","kind":"symbol","value":"FOO"},"#,
+                r#"{"name":"file","kind":"path","value":"a.h"},"#,
+                r#"{"name":"count","kind":"int","value":2}]}"#,
+                "\n"
+            )
+        );
+    }
+
+    #[test]
+    fn ranges_and_suggestions() {
+        let mut smap = SourceMap::new();
+        let file = smap
+            .create_file(
+                FileName::real("t.c"),
+                FileContents::new("int x = f(a)\n"),
+                None,
+            )
+            .unwrap();
+        let file_range = smap.get_source(file).range;
+
+        let raw = RawDiagnostic {
+            level: Level::Error,
+            id: None,
+            main: RawSubDiagnostic::new(
+                "bad call",
+                file_range
+                    .subrange(LocalRange::at(8.into(), 1.into()))
+                    .into(),
+            )
+            .with_labeled_range(
+                file_range
+                    .subrange(LocalRange::at(10.into(), 1.into()))
+                    .into(),
+                "arg",
+            )
+            .with_suggestion(RawSuggestion::new(
+                file_range.subrange(LocalRange::at(12.into(), 0.into())),
+                ";",
+            ))
+            .with_suggestion(
+                RawSuggestion::new(
+                    file_range.subrange(LocalRange::at(10.into(), 1.into())),
+                    "b",
+                )
+                .with_applicability(Applicability::MaybeIncorrect),
+            ),
+            notes: vec![RawSubDiagnostic::new_anon("see here")],
+        };
+
+        assert_eq!(
+            report(&raw, Some(&smap)),
+            concat!(
+                r#"{"level":"error","message":"bad call","#,
+                r#""location":{"file":"t.c","line":1,"col":9},"#,
+                r#""range":{"file":"t.c","start":{"line":1,"col":9},"end":{"line":1,"col":10}},"#,
+                r#""subranges":[{"file":"t.c","start":{"line":1,"col":11},"#,
+                r#""end":{"line":1,"col":12},"label":"arg"}],"#,
+                r#""suggestions":[{"range":{"file":"t.c","start":{"line":1,"col":13},"#,
+                r#""end":{"line":1,"col":13}},"text":";","applicability":"machine-applicable"},"#,
+                r#"{"range":{"file":"t.c","start":{"line":1,"col":11},"#,
+                r#""end":{"line":1,"col":12}},"text":"b","applicability":"maybe-incorrect"}],"#,
+                r#""notes":[{"level":"note","message":"see here"}]}"#,
+                "\n"
+            )
+        );
+    }
+
+    #[test]
+    fn expansion_trace() {
+        let mut smap = SourceMap::new();
+        let file = smap
+            .create_file(
+                FileName::real("t.c"),
+                FileContents::new("#define A 1 +\nint x = A;\n"),
+                None,
+            )
+            .unwrap();
+        let file_range = smap.get_source(file).range;
+        let exp = smap
+            .create_expansion(
+                file_range.subrange(LocalRange::at(10.into(), 3.into())),
+                file_range.subrange(LocalRange::at(22.into(), 1.into())),
+                ExpansionKind::Macro,
+            )
+            .unwrap();
+        let exp_range = smap.get_source(exp).range;
+
+        let raw = RawDiagnostic {
+            level: Level::Error,
+            id: None,
+            main: RawSubDiagnostic::new(
+                "expected an expression",
+                exp_range
+                    .subrange(LocalRange::at(2.into(), 1.into()))
+                    .into(),
+            ),
+            notes: Vec::new(),
+        };
+
+        assert_eq!(
+            report(&raw, Some(&smap)),
+            concat!(
+                r#"{"level":"error","message":"expected an expression","#,
+                r#""location":{"file":"t.c","line":2,"col":9},"#,
+                r#""range":{"file":"t.c","start":{"line":2,"col":9},"end":{"line":2,"col":10}},"#,
+                r#""expansions":[{"file":"t.c","start":{"line":1,"col":13},"#,
+                r#""end":{"line":1,"col":14}}]}"#,
+                "\n"
+            )
+        );
+    }
+}
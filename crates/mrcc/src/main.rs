@@ -1,63 +1,1327 @@
 #![warn(rust_2018_idioms)]
 
-use std::fs;
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
+use std::{fs, mem};
 
+use structopt::clap::ArgMatches;
 use structopt::StructOpt;
 
-use lex::{Interner, LexCtx, TokenKind};
-use pp::PreprocessorBuilder;
+use lex::diff::spell_tokens;
+use lex::raw::{LexerConfig, Tokenizer};
+use lex::{keyword_interner, ConvertedTokenKind, LexCtx, Spacing, Token, TokenKind, TokenWriter};
+use pp::{
+    CancellationToken, ExpansionTracking, File, FileCache, LangOptions, LangStandard, MacroDefKind,
+    MemoryStats, PpError, PpEvent, PpResult, PpToken, Pragma, Preprocessor, PreprocessorBuilder,
+};
+use source::diag::{
+    AnnotatingSink, Applicability, ColorChoice, DiagnosticId, Level, RenderedDiagnostic,
+    RenderedSink, RenderedSuggestion,
+};
+use source::fixit::{FixitSink, Fixits};
+use source::limits::DEFAULT_MACRO_BACKTRACE_LIMIT;
 use source::smap::{FileContents, FileName, SourceMap};
-use source::{diag::Level, DResult, DiagManager};
+use source::sync::Lrc;
+use source::{DResult, DiagManager, FrontendLimits, SourceId};
+
+use crate::depfile::DepFile;
+use crate::env::EnvIncludeDirs;
+use crate::explain::ExpansionLoc;
+use crate::json_sink::JsonSink;
+use crate::preprocessed::PreprocessedWriter;
+use crate::sarif_sink::SarifSink;
+
+mod depfile;
+mod env;
+mod explain;
+mod json_sink;
+mod preprocessed;
+mod sarif_sink;
+mod serve;
 
 #[derive(StructOpt)]
 struct Opts {
-    pub filename: PathBuf,
+    /// The files to process, each as a separate translation unit. A filename of `-` reads the
+    /// source from standard input.
+    #[structopt(required_unless = "serve")]
+    pub filenames: Vec<PathBuf>,
+
+    /// Run as a server, reading JSON preprocessing requests from standard input (one per line) and
+    /// writing JSON responses to standard output.
+    ///
+    /// Loaded files are cached across requests.
+    #[structopt(long, conflicts_with = "filenames")]
+    pub serve: bool,
+
+    /// Ignore the `CPATH` and `C_INCLUDE_PATH` environment variables.
+    ///
+    /// Directories from these variables are otherwise searched after any directories specified on
+    /// the command line.
+    #[structopt(long)]
+    pub no_env_includes: bool,
+
+    /// Search the specified directory for included files, before the directories specified in the
+    /// environment (`-I`).
+    #[structopt(short = "I", number_of_values = 1, value_name = "dir")]
+    pub include_dirs: Vec<PathBuf>,
+
+    /// Search the specified directory for included files as a system directory, after all other
+    /// include directories (`-isystem`).
+    #[structopt(long, number_of_values = 1, value_name = "dir")]
+    pub isystem: Vec<PathBuf>,
+
+    /// Define a macro before preprocessing (`-D`). `NAME` defines it as `1`, while `NAME=VALUE` and
+    /// `NAME(PARAMS)=VALUE` specify its replacement list.
+    #[structopt(short = "D", number_of_values = 1, value_name = "NAME[=VALUE]")]
+    pub defines: Vec<String>,
+
+    /// Undefine a macro before preprocessing, which may be a predefined one (`-U`).
+    ///
+    /// `-D` and `-U` options are processed in the order in which they are passed.
+    #[structopt(short = "U", number_of_values = 1, value_name = "NAME")]
+    pub undefs: Vec<String>,
+
+    /// Include the specified file before the first line of every input (`-include`).
+    ///
+    /// The file is looked up in the current directory first, and then in the include directories.
+    #[structopt(long = "include", number_of_values = 1, value_name = "file")]
+    pub forced_includes: Vec<PathBuf>,
+
+    /// The `-D` and `-U` options, in the order in which they were passed.
+    #[structopt(skip)]
+    pub macro_options: Vec<MacroOption>,
+
+    /// The language standard to follow (`-std`): one of `c90`, `c99`, `c11`, `c17` and `c23`, or
+    /// the corresponding `gnu` modes, which enable GNU extensions.
+    ///
+    /// The strict modes enable trigraphs (except for C23), and C90 additionally disables digraphs
+    /// and `//` comments.
+    #[structopt(long, default_value = "gnu17", parse(try_from_str = parse_std))]
+    pub std: LangOptions,
+
+    /// Warn about constructs not supported by the language standard selected with `-std`
+    /// (`-pedantic`).
+    #[structopt(long)]
+    pub pedantic: bool,
+
+    /// Replace trigraphs (such as `??=` for `#`) with the characters they represent, regardless
+    /// of the language standard (`-trigraphs`).
+    #[structopt(long)]
+    pub trigraphs: bool,
+
+    /// Print the input file with its preprocessing directives reformatted, instead of
+    /// preprocessing it.
+    ///
+    /// Blocks of includes are only sorted if that leaves the preprocessed output unchanged.
+    #[structopt(long)]
+    pub format_directives: bool,
+
+    /// Print the input file with the `#define` directive for the specified macro removed, and
+    /// report the equivalent `-D` flag.
+    #[structopt(long, value_name = "NAME", conflicts_with = "inline-macro")]
+    pub extract_define: Option<String>,
+
+    /// Print the input file with every use of the specified object-like macro replaced by its
+    /// replacement list, and its definition removed.
+    #[structopt(long, value_name = "NAME")]
+    pub inline_macro: Option<String>,
+
+    /// Write the preprocessed input in a form that can be compiled again, with line markers
+    /// recording the presumed location of every line.
+    #[structopt(short = "E", conflicts_with = "token-limit")]
+    pub preprocess_only: bool,
+
+    /// Dump information about the preprocessed input instead of printing tokens.
+    ///
+    /// Only `-dM` is supported, which prints `#define` directives for all macros defined at the end
+    /// of the input, including predefined ones.
+    #[structopt(short = "d", value_name = "M", parse(try_from_str = parse_dump))]
+    pub dump: Option<Dump>,
+
+    /// The order in which `-dM` prints macros: `definition` or `name`.
+    #[structopt(long, default_value = "definition", parse(try_from_str = parse_macro_order))]
+    pub macro_order: MacroOrder,
+
+    /// Write a Makefile-style dependency file listing the input and every file it includes or
+    /// embeds, in addition to the usual output.
+    ///
+    /// Unless `-MF` is passed, the dependency file is named after the `-o` output if there is one,
+    /// and after the input otherwise (in the current directory), with a `.d` extension.
+    #[structopt(long = "MD")]
+    pub write_deps: bool,
+
+    /// The path of the dependency file written by `-MD`.
+    #[structopt(long = "MF", value_name = "FILE", requires = "write-deps")]
+    pub dep_file: Option<PathBuf>,
+
+    /// The target of the rule in the dependency file written by `-MD`.
+    ///
+    /// The default is the `-o` output if there is one, and the object file corresponding to the
+    /// input otherwise.
+    #[structopt(long = "MT", value_name = "TARGET", requires = "write-deps")]
+    pub dep_target: Option<String>,
+
+    /// Keep comments in the output of `-E`, except for those inside directives. Comments inside
+    /// macro invocations are treated as whitespace.
+    #[structopt(short = "C", requires = "preprocess-only")]
+    pub keep_comments: bool,
+
+    /// Write the output of `-E` to the specified file instead of standard output.
+    #[structopt(short = "o", value_name = "FILE", requires = "preprocess-only")]
+    pub output: Option<PathBuf>,
+
+    /// Print a standalone HTML page showing the step-by-step expansion of the macro invocation at
+    /// the specified location, given as `file:line:col`.
+    #[structopt(long, value_name = "FILE:LINE:COL")]
+    pub explain_expansion: Option<ExpansionLoc>,
+
+    /// The maximum length of a string or character literal, in bytes, beyond which only a
+    /// truncated preview of it is retained.
+    #[structopt(long)]
+    pub max_literal_len: Option<usize>,
+
+    /// The maximum size of a resource embedded with `#embed`, in bytes, unless it is truncated by
+    /// a `limit` parameter.
+    #[structopt(long)]
+    pub max_embed_size: Option<usize>,
+
+    /// How precisely to track the locations of macro expansion tokens, from 0 to 2.
+    ///
+    /// Level 0 attributes all expansion tokens to the outermost macro invocation, level 1 tracks
+    /// macro bodies but not substituted arguments, and level 2 tracks every token.
+    #[structopt(long, default_value = "2", parse(try_from_str = parse_expansion_tracking))]
+    pub track_macro_expansion: ExpansionTracking,
+
+    /// Stop after printing this many tokens, reporting where processing stopped.
+    #[structopt(long)]
+    pub token_limit: Option<u64>,
+
+    /// Stop after the specified phase: `read`, `lex` or `preprocess`.
+    ///
+    /// When stopping after `lex`, the tokens of the input file are printed without being
+    /// preprocessed.
+    #[structopt(long, default_value = "preprocess", parse(try_from_str = parse_phase))]
+    pub stop_after: Phase,
+
+    /// Show at most this many diagnostics for any single source line, summarizing the rest with a
+    /// note.
+    #[structopt(long)]
+    pub max_diagnostics_per_line: Option<u32>,
+
+    /// Stop after this many errors have been reported (`-ferror-limit`). A limit of 0 means no
+    /// limit.
+    #[structopt(long = "ferror-limit", value_name = "N")]
+    pub error_limit: Option<u32>,
+
+    /// Show at most this many macro expansions in the trace of any diagnostic
+    /// (`-fmacro-backtrace-limit`), skipping the ones in the middle. A limit of 0 means no limit.
+    #[structopt(long = "fmacro-backtrace-limit", value_name = "N")]
+    pub macro_backtrace_limit: Option<u32>,
+
+    /// Report all warnings as errors (`-Werror`).
+    #[structopt(long = "Werror")]
+    pub warnings_as_errors: bool,
+
+    /// Report diagnostics for constructs that are not strictly conforming, such as defining macros
+    /// with reserved names, as errors instead of warnings (`-pedantic-errors`). This implies
+    /// `-pedantic`.
+    #[structopt(long)]
+    pub pedantic_errors: bool,
+
+    /// Suppress all warnings. This takes precedence over `-Werror`.
+    #[structopt(short = "w")]
+    pub ignore_warnings: bool,
+
+    /// Suppress the diagnostics with the specified ID, such as `macro-redefined` (`-Wno-<ID>`).
+    #[structopt(
+        long = "Wno",
+        value_name = "ID",
+        number_of_values = 1,
+        parse(try_from_str = parse_diagnostic_id)
+    )]
+    pub disabled_diagnostics: Vec<DiagnosticId>,
+
+    /// When to color diagnostics: `always`, `auto` (if standard error is a terminal) or `never`.
+    #[structopt(long, default_value = "auto", parse(try_from_str = parse_color_choice))]
+    pub color: ColorChoice,
+
+    /// The format in which to print diagnostics: `text`, `json` or `sarif`.
+    ///
+    /// With `json`, every diagnostic is written to standard error as a single line containing a
+    /// JSON object. With `sarif`, a single SARIF 2.1.0 log containing all diagnostics is written to
+    /// standard error once processing finishes.
+    #[structopt(long, default_value = "text", parse(try_from_str = parse_diagnostic_format))]
+    pub diagnostic_format: DiagnosticFormat,
+
+    /// Apply the fix-its suggested by diagnostics to the input file, rewriting it in place.
+    ///
+    /// Fix-its for other files are not applied, and neither are fix-its conflicting with earlier
+    /// ones. Line endings in the rewritten file are normalized to `\n`.
+    #[structopt(long)]
+    pub fix: bool,
+
+    /// Print an estimate of the peak memory used by each part of the preprocessor, along with
+    /// statistics about the loaded sources, to standard error once preprocessing finishes.
+    #[structopt(long)]
+    pub report_memory: bool,
+
+    /// Print the time spent lexing, handling directives, expanding macros and loading included
+    /// files, along with counts of the tokens, directives, expansions and includes processed, to
+    /// standard error once preprocessing finishes (`-ftime-report`, `-stats`).
+    #[structopt(long = "ftime-report", alias = "stats")]
+    pub time_report: bool,
+}
+
+/// A `-D` or `-U` option.
+#[derive(Debug, Clone)]
+enum MacroOption {
+    Define(String),
+    Undef(String),
+}
+
+/// Collects the `-D` and `-U` options in `opts`, ordering them by their positions in `matches`.
+fn ordered_macro_options(opts: &Opts, matches: &ArgMatches<'_>) -> Vec<MacroOption> {
+    let indices = |name| matches.indices_of(name).into_iter().flatten();
+
+    let defines = indices("defines").zip(
+        opts.defines
+            .iter()
+            .map(|def| MacroOption::Define(def.clone())),
+    );
+    let undefs = indices("undefs").zip(
+        opts.undefs
+            .iter()
+            .map(|name| MacroOption::Undef(name.clone())),
+    );
+
+    let mut options: Vec<_> = defines.chain(undefs).collect();
+    options.sort_by_key(|&(idx, _)| idx);
+    options.into_iter().map(|(_, option)| option).collect()
+}
+
+/// The phases of processing, for use with `--stop-after`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Read,
+    Lex,
+    Preprocess,
+}
+
+fn parse_phase(phase: &str) -> Result<Phase, String> {
+    match phase {
+        "read" => Ok(Phase::Read),
+        "lex" => Ok(Phase::Lex),
+        "preprocess" => Ok(Phase::Preprocess),
+        _ => Err(format!("invalid phase '{}'", phase)),
+    }
+}
+
+fn parse_color_choice(choice: &str) -> Result<ColorChoice, String> {
+    match choice {
+        "always" => Ok(ColorChoice::Always),
+        "auto" => Ok(ColorChoice::Auto),
+        "never" => Ok(ColorChoice::Never),
+        _ => Err(format!("invalid color choice '{}'", choice)),
+    }
+}
+
+fn parse_diagnostic_id(name: &str) -> Result<DiagnosticId, String> {
+    DiagnosticId::from_name(name).ok_or_else(|| format!("unknown diagnostic '{}'", name))
+}
+
+/// The formats in which diagnostics can be printed, for use with `--diagnostic-format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DiagnosticFormat {
+    Text,
+    Json,
+    Sarif,
+}
+
+fn parse_diagnostic_format(format: &str) -> Result<DiagnosticFormat, String> {
+    match format {
+        "text" => Ok(DiagnosticFormat::Text),
+        "json" => Ok(DiagnosticFormat::Json),
+        "sarif" => Ok(DiagnosticFormat::Sarif),
+        _ => Err(format!("invalid diagnostic format '{}'", format)),
+    }
+}
+
+/// The kinds of information that can be dumped with `-d`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dump {
+    Macros,
+}
+
+fn parse_dump(dump: &str) -> Result<Dump, String> {
+    match dump {
+        "M" => Ok(Dump::Macros),
+        _ => Err(format!("invalid dump kind '{}'", dump)),
+    }
+}
+
+/// The orders in which macros can be printed by `-dM`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MacroOrder {
+    Definition,
+    Name,
+}
+
+fn parse_macro_order(order: &str) -> Result<MacroOrder, String> {
+    match order {
+        "definition" => Ok(MacroOrder::Definition),
+        "name" => Ok(MacroOrder::Name),
+        _ => Err(format!("invalid macro order '{}'", order)),
+    }
+}
+
+/// Options controlling how a file is preprocessed, shared between the command line and server
+/// mode.
+struct PpOptions {
+    pub include_dirs: Vec<PathBuf>,
+    pub system_include_dirs: Vec<PathBuf>,
+    /// The macros to define or undefine before preprocessing, in order.
+    pub macro_options: Vec<MacroOption>,
+    /// The files to include before the first line of the main file.
+    pub forced_includes: Vec<PathBuf>,
+    pub expansion_tracking: ExpansionTracking,
+    pub limits: FrontendLimits,
+    pub cancellation_token: Option<CancellationToken>,
+    pub report_memory: bool,
+    /// Whether timings and statistics should be printed once preprocessing finishes.
+    pub time_report: bool,
+    /// The language being preprocessed.
+    pub lang: LangOptions,
+    /// The options used when tokenizing source files, overriding those implied by `lang`.
+    pub lexer_config: LexerConfig,
+    /// Whether comments should be kept as tokens in the preprocessed output.
+    pub keep_comments: bool,
+    /// Whether non-conforming constructs should be reported as errors instead of warnings.
+    pub pedantic_errors: bool,
+    /// The dependency file to write once preprocessing succeeds, if any.
+    pub dep_file: Option<DepFile>,
 }
 
-fn run(diags: &mut DiagManager<'_>) -> DResult<()> {
-    let opts = Opts::from_args();
+/// Parses the name of a language standard, as passed to `-std`, into the matching language
+/// options.
+fn parse_std(std: &str) -> Result<LangOptions, String> {
+    let (gnu_extensions, version) = match std.strip_prefix("gnu") {
+        Some(version) => (true, version),
+        None => (false, std.strip_prefix('c').unwrap_or("")),
+    };
 
-    let main_src = fs::read_to_string(&opts.filename).map_err(|err| {
+    let standard = match version {
+        "89" | "90" => LangStandard::C89,
+        "99" => LangStandard::C99,
+        "11" => LangStandard::C11,
+        "17" | "18" => LangStandard::C17,
+        "23" => LangStandard::C23,
+        _ => return Err(format!("unknown language standard '{}'", std)),
+    };
+
+    Ok(LangOptions {
+        standard,
+        gnu_extensions,
+        pedantic: false,
+    })
+}
+
+fn parse_expansion_tracking(level: &str) -> Result<ExpansionTracking, String> {
+    level
+        .parse()
+        .ok()
+        .and_then(expansion_tracking_from_level)
+        .ok_or_else(|| format!("invalid tracking level '{}'", level))
+}
+
+/// Builds the limits to use when processing a file, falling back to the defaults for any limits
+/// that were not specified.
+fn frontend_limits(
+    max_literal_len: Option<usize>,
+    max_embed_size: Option<usize>,
+    token_limit: Option<u64>,
+    max_diagnostics_per_line: Option<u32>,
+) -> FrontendLimits {
+    let defaults = FrontendLimits::default();
+    FrontendLimits {
+        max_literal_len: max_literal_len.unwrap_or(defaults.max_literal_len),
+        max_embed_size: max_embed_size.unwrap_or(defaults.max_embed_size),
+        token_limit,
+        max_diagnostics_per_line,
+        ..defaults
+    }
+}
+
+/// Converts a numeric tracking level, as accepted by `--track-macro-expansion`, to an
+/// [`ExpansionTracking`].
+fn expansion_tracking_from_level(level: u8) -> Option<ExpansionTracking> {
+    match level {
+        0 => Some(ExpansionTracking::None),
+        1 => Some(ExpansionTracking::Macros),
+        2 => Some(ExpansionTracking::Full),
+        _ => None,
+    }
+}
+
+/// Processes the input file `filename` as specified by `opts`.
+///
+/// If `--fix` was passed, `fixits` should be receiving the suggestions of all diagnostics reported
+/// to `diags`.
+fn run(
+    opts: &Opts,
+    filename: &Path,
+    limits: FrontendLimits,
+    diags: &mut DiagManager<'_>,
+    fixits: &RefCell<Fixits>,
+) -> DResult<()> {
+    let mut interner = keyword_interner();
+    let mut smap = SourceMap::new();
+    let mut file_cache = FileCache::new();
+
+    let (main_id, main_file) = load_main_file(diags, &mut smap, &mut file_cache, filename)?;
+
+    let env_dirs = if opts.no_env_includes {
+        EnvIncludeDirs::default()
+    } else {
+        env::include_dirs()
+    };
+
+    let pp_opts = PpOptions {
+        include_dirs: opts
+            .include_dirs
+            .iter()
+            .cloned()
+            .chain(env_dirs.user)
+            .collect(),
+        system_include_dirs: opts
+            .isystem
+            .iter()
+            .cloned()
+            .chain(env_dirs.system)
+            .collect(),
+        macro_options: opts.macro_options.clone(),
+        forced_includes: opts.forced_includes.clone(),
+        expansion_tracking: opts.track_macro_expansion,
+        limits,
+        cancellation_token: None,
+        report_memory: opts.report_memory,
+        time_report: opts.time_report,
+        lang: LangOptions {
+            pedantic: opts.pedantic,
+            ..opts.std
+        },
+        lexer_config: LexerConfig {
+            trigraphs: opts.std.lexer_config().trigraphs || opts.trigraphs,
+            ..opts.std.lexer_config()
+        },
+        keep_comments: opts.keep_comments,
+        pedantic_errors: opts.pedantic_errors,
+        dep_file: if opts.write_deps {
+            let mut dep_file = DepFile::for_input(filename, opts.output.as_deref());
+            if let Some(path) = &opts.dep_file {
+                dep_file.path = path.clone();
+            }
+            if let Some(target) = &opts.dep_target {
+                dep_file.target = target.clone();
+            }
+            Some(dep_file)
+        } else {
+            None
+        },
+    };
+
+    let mut ctx = LexCtx::new(&mut interner, diags, &mut smap);
+    ctx.limits = pp_opts.limits;
+
+    if opts.format_directives {
+        return format_directives(
+            &mut ctx,
+            main_id,
+            &main_file,
+            &pp_opts,
+            &mut file_cache,
+            filename,
+        );
+    }
+
+    if let Some(name) = &opts.extract_define {
+        return extract_define(
+            &mut ctx,
+            main_id,
+            &main_file,
+            &pp_opts,
+            &mut file_cache,
+            name,
+        )
+        .map_err(|err| match err {
+            PpError::Fatal(fatal) => fatal,
+            PpError::Cancelled => unreachable!("no cancellation token was provided"),
+        });
+    }
+
+    if let Some(name) = &opts.inline_macro {
+        return inline_macro(
+            &mut ctx,
+            main_id,
+            &main_file,
+            &pp_opts,
+            &mut file_cache,
+            name,
+        )
+        .map_err(|err| match err {
+            PpError::Fatal(fatal) => fatal,
+            PpError::Cancelled => unreachable!("no cancellation token was provided"),
+        });
+    }
+
+    if let Some(loc) = &opts.explain_expansion {
+        return explain_expansion(
+            &mut ctx,
+            main_id,
+            &main_file,
+            &pp_opts,
+            &mut file_cache,
+            loc,
+        )
+        .map_err(|err| match err {
+            PpError::Fatal(fatal) => fatal,
+            PpError::Cancelled => unreachable!("no cancellation token was provided"),
+        });
+    }
+
+    if opts.dump == Some(Dump::Macros) {
+        return dump_macros(
+            &mut ctx,
+            main_id,
+            &main_file,
+            &pp_opts,
+            &mut file_cache,
+            opts.macro_order,
+        )
+        .map_err(|err| match err {
+            PpError::Fatal(fatal) => fatal,
+            PpError::Cancelled => unreachable!("no cancellation token was provided"),
+        });
+    }
+
+    if opts.preprocess_only {
+        let out: Box<dyn Write> = match &opts.output {
+            Some(path) => match fs::File::create(path) {
+                Ok(file) => Box::new(io::BufWriter::new(file)),
+                Err(err) => {
+                    return ctx
+                        .diags
+                        .report_anon(
+                            Level::Fatal,
+                            format!("failed to write '{}': {}", path.display(), err),
+                        )
+                        .emit();
+                }
+            },
+            None => Box::new(io::stdout()),
+        };
+
+        return write_preprocessed(
+            &mut ctx,
+            main_id,
+            &main_file,
+            &pp_opts,
+            &mut file_cache,
+            out,
+        )
+        .map_err(|err| match err {
+            PpError::Fatal(fatal) => fatal,
+            PpError::Cancelled => unreachable!("no cancellation token was provided"),
+        });
+    }
+
+    let mut printer = TokenPrinter::new(io::stdout(), pp_opts.limits.token_limit);
+
+    let res = match opts.stop_after {
+        Phase::Read => Ok(()),
+        Phase::Lex => lex_file(&mut ctx, main_id, pp_opts.lexer_config, &mut printer),
+        Phase::Preprocess => preprocess_file(
+            &mut ctx,
+            main_id,
+            &main_file,
+            &pp_opts,
+            &mut file_cache,
+            &mut printer,
+        )
+        .map_err(|err| match err {
+            PpError::Fatal(fatal) => fatal,
+            PpError::Cancelled => unreachable!("no cancellation token was provided"),
+        }),
+    };
+
+    printer.finish();
+
+    if opts.fix {
+        // Take the fix-its, as any diagnostics reported while applying them are also recorded.
+        apply_fixits(&mut ctx, main_id, filename, &fixits.take())?;
+    }
+
+    res
+}
+
+/// Applies the fix-its in `fixits` to the file `main_id`, rewriting it at `path`.
+///
+/// A warning is reported for every fix-it that was skipped due to a conflict. Files whose source code
+/// differs from their raw contents are left untouched, with an error.
+fn apply_fixits(
+    ctx: &mut LexCtx<'_, '_>,
+    main_id: SourceId,
+    path: &Path,
+    fixits: &Fixits,
+) -> DResult<()> {
+    if fixits.file_edits(main_id).is_empty() {
+        return Ok(());
+    }
+
+    let fixed = match fixits.apply_verbatim(ctx.smap, main_id) {
+        Some(fixed) => fixed,
+        None => {
+            return ctx
+                .diags
+                .report_anon(
+                    Level::Error,
+                    format!(
+                        "cannot apply fix-its to '{}' without altering its encoding, byte order \
+                         mark, line endings or invalid sequences",
+                        path.display()
+                    ),
+                )
+                .emit();
+        }
+    };
+    let file_range = ctx.smap.get_source(main_id).range;
+
+    for conflict in &fixed.conflicts {
+        ctx.reporter()
+            .warn(
+                file_range.subrange(conflict.range),
+                "fix-it conflicts with another fix-it and was not applied",
+            )
+            .set_id(DiagnosticId::FixitConflict)
+            .emit()?;
+    }
+
+    if let Err(err) = fs::write(path, &fixed.contents) {
+        return ctx
+            .diags
+            .report_anon(
+                Level::Fatal,
+                format!("failed to write '{}': {}", path.display(), err),
+            )
+            .emit();
+    }
+
+    ctx.diags
+        .report_anon(
+            Level::Note,
+            format!(
+                "applied {} fix-it{} to '{}'",
+                fixed.applied,
+                if fixed.applied == 1 { "" } else { "s" },
+                path.display()
+            ),
+        )
+        .emit()
+}
+
+/// Checks whether `path` is `-`, which refers to the standard input.
+fn is_stdin(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Loads the main source file at `path` through `file_cache` and creates a source for it in
+/// `smap`.
+///
+/// If `path` is `-`, the source is read from the standard input instead, and quoted includes in it
+/// are resolved relative to the current directory.
+fn load_main_file(
+    diags: &mut DiagManager<'_>,
+    smap: &mut SourceMap,
+    file_cache: &mut FileCache,
+    path: &Path,
+) -> DResult<(SourceId, Rc<File>)> {
+    let (name, loaded) = if is_stdin(path) {
+        (
+            FileName::synth("stdin"),
+            File::from_reader(io::stdin().lock(), Some(PathBuf::new())),
+        )
+    } else {
+        (FileName::real(path), file_cache.load(path))
+    };
+
+    let file = loaded.map_err(|err| {
         diags
             .report_anon(
                 Level::Fatal,
-                format!("failed to read '{}': {}", opts.filename.display(), err),
+                format!("failed to read '{}': {}", path.display(), err),
             )
             .emit()
             .unwrap_err()
     })?;
 
-    let mut interner = Interner::new();
-    let mut smap = SourceMap::new();
-
-    let main_id = smap
-        .create_file(
-            FileName::real(opts.filename.clone()),
-            FileContents::new(&main_src),
-            None,
-        )
+    let id = smap
+        .create_file(name, Lrc::clone(&file.contents), None)
         .map_err(|_| {
             diags
-                .report_anon(Level::Fatal, "file too large".into())
+                .report_anon(Level::Fatal, "file too large")
                 .emit()
                 .unwrap_err()
         })?;
 
-    let mut ctx = LexCtx::new(&mut interner, diags, &mut smap);
+    Ok((id, file))
+}
+
+/// Preprocesses the file `main_id`, printing the resulting tokens with `printer`.
+///
+/// Included files are loaded through `file_cache`, which retains them afterwards (even if
+/// preprocessing fails or is cancelled).
+fn preprocess_file(
+    ctx: &mut LexCtx<'_, '_>,
+    main_id: SourceId,
+    main_file: &File,
+    opts: &PpOptions,
+    file_cache: &mut FileCache,
+    printer: &mut TokenPrinter<impl Write>,
+) -> PpResult<()> {
+    let mut pp = configure_preprocessor(ctx, main_id, main_file, opts, file_cache).build();
+
+    let res = (|| -> PpResult<()> {
+        loop {
+            let ppt = match pp.next_pp_event(ctx)? {
+                PpEvent::Tok(ppt) => ppt,
+                PpEvent::Pragma(pragma) => {
+                    printer.print_pragma(ctx, &pragma);
+                    continue;
+                }
+                PpEvent::EnterFile(_) | PpEvent::ExitFile(_) => continue,
+            };
+
+            if ppt.data() == TokenKind::Eof || !printer.print(ctx, ppt)? {
+                break Ok(());
+            }
+        }
+    })()
+    .and_then(|()| Ok(write_dependencies(ctx, main_file, &pp, opts)?));
+
+    if opts.report_memory {
+        eprint!("{}", MemoryStats::collect(ctx, &pp));
+        eprint!("{}", ctx.smap.stats());
+    }
+
+    if let Some(stats) = pp.stats() {
+        eprint!("{}", stats);
+    }
+
+    *file_cache = pp.into_file_cache();
+    res
+}
 
-    let mut pp = PreprocessorBuilder::new(&mut ctx, main_id)
-        .parent_dir(opts.filename.parent().unwrap().into())
+/// Preprocesses the file `main_id` and prints the macros defined at the end of it, in `order`.
+///
+/// Builtin macros whose expansions are computed dynamically (such as `__LINE__`) are omitted.
+fn dump_macros(
+    ctx: &mut LexCtx<'_, '_>,
+    main_id: SourceId,
+    main_file: &File,
+    opts: &PpOptions,
+    file_cache: &mut FileCache,
+    order: MacroOrder,
+) -> PpResult<()> {
+    let mut pp = configure_preprocessor(ctx, main_id, main_file, opts, file_cache).build();
+
+    let res = (|| -> PpResult<()> {
+        while pp.next_pp(ctx)?.data() != TokenKind::Eof {}
+
+        let mut defs: Vec<_> = pp
+            .macro_defs()
+            .filter(|def| !matches!(def.kind, MacroDefKind::Builtin(_)))
+            .collect();
+        if order == MacroOrder::Name {
+            defs.sort_by_key(|def| &ctx.interner[def.name_tok.data]);
+        }
+
+        for def in defs {
+            println!("{}", def.display(ctx));
+        }
+
+        Ok(())
+    })()
+    .and_then(|()| Ok(write_dependencies(ctx, main_file, &pp, opts)?));
+
+    *file_cache = pp.into_file_cache();
+    res
+}
+
+/// Preprocesses the file `main_id`, writing output suitable for compiling again to `out`.
+fn write_preprocessed(
+    ctx: &mut LexCtx<'_, '_>,
+    main_id: SourceId,
+    main_file: &File,
+    opts: &PpOptions,
+    file_cache: &mut FileCache,
+    out: impl Write,
+) -> PpResult<()> {
+    let mut pp = configure_preprocessor(ctx, main_id, main_file, opts, file_cache)
+        .file_boundaries(true)
         .build();
+    let mut writer = PreprocessedWriter::new(out, ctx.smap, main_id, opts.lexer_config);
+
+    let res = (|| -> PpResult<()> {
+        loop {
+            match pp.next_pp_event(ctx)? {
+                PpEvent::Tok(ppt) if ppt.data() == TokenKind::Eof => break Ok(()),
+                PpEvent::Tok(ppt) => writer.write_token(ctx, ppt),
+                PpEvent::Pragma(pragma) => writer.write_pragma(ctx, &pragma),
+                PpEvent::EnterFile(id) => writer.enter_file(ctx.smap, id),
+                PpEvent::ExitFile(id) => writer.exit_file(ctx.smap, id),
+            }
+        }
+    })()
+    .and_then(|()| Ok(write_dependencies(ctx, main_file, &pp, opts)?));
+
+    writer.finish();
+
+    if opts.report_memory {
+        eprint!("{}", MemoryStats::collect(ctx, &pp));
+        eprint!("{}", ctx.smap.stats());
+    }
+
+    if let Some(stats) = pp.stats() {
+        eprint!("{}", stats);
+    }
+
+    *file_cache = pp.into_file_cache();
+    res
+}
+
+/// Writes the dependency file requested in `opts`, if any, listing `main_file` (unless it was read
+/// from the standard input) and every file loaded by `pp`.
+fn write_dependencies(
+    ctx: &mut LexCtx<'_, '_>,
+    main_file: &File,
+    pp: &Preprocessor,
+    opts: &PpOptions,
+) -> DResult<()> {
+    let dep_file = match &opts.dep_file {
+        Some(dep_file) => dep_file,
+        None => return Ok(()),
+    };
+
+    match dep_file.write(main_file.path.as_deref(), pp.dependencies()) {
+        Ok(()) => Ok(()),
+        Err(err) => ctx
+            .diags
+            .report_anon(
+                Level::Fatal,
+                format!("failed to write '{}': {}", dep_file.path.display(), err),
+            )
+            .emit(),
+    }
+}
+
+/// Creates a builder for preprocessing the file `main_id` as specified by `opts`, moving
+/// `file_cache` into it.
+fn configure_preprocessor<'a, 'b, 'h>(
+    ctx: &'a mut LexCtx<'b, 'h>,
+    main_id: SourceId,
+    main_file: &File,
+    opts: &PpOptions,
+    file_cache: &mut FileCache,
+) -> PreprocessorBuilder<'a, 'b, 'h> {
+    let mut builder = PreprocessorBuilder::new(ctx, main_id);
+    builder
+        .include_dirs(opts.include_dirs.clone())
+        .system_include_dirs(opts.system_include_dirs.clone())
+        .expansion_tracking(opts.expansion_tracking)
+        .lang_options(opts.lang)
+        .lexer_config(opts.lexer_config)
+        .keep_comments(opts.keep_comments)
+        .pedantic_errors(opts.pedantic_errors)
+        .collect_stats(opts.time_report)
+        .file_cache(mem::take(file_cache));
+
+    if let Some(parent_dir) = &main_file.parent_dir {
+        builder.parent_dir(parent_dir.clone());
+    }
+
+    for option in &opts.macro_options {
+        match option {
+            MacroOption::Define(def) => builder.define(def.clone()),
+            MacroOption::Undef(name) => builder.undef(name.clone()),
+        };
+    }
+
+    for path in &opts.forced_includes {
+        builder.include_file(path.clone());
+    }
+
+    if let Some(token) = &opts.cancellation_token {
+        builder.cancellation_token(token.clone());
+    }
+
+    builder
+}
+
+/// Prints the file `main_id` with its directives formatted.
+///
+/// Blocks of includes are only sorted if doing so leaves the output of preprocessing the file as
+/// specified by `opts` unchanged, as the included headers may depend on their order. Files whose
+/// source code differs from their raw contents are left untouched, with an error.
+fn format_directives(
+    ctx: &mut LexCtx<'_, '_>,
+    main_id: SourceId,
+    main_file: &File,
+    opts: &PpOptions,
+    file_cache: &mut FileCache,
+    path: &Path,
+) -> DResult<()> {
+    let apply = |smap: &SourceMap, edits: &[RenderedSuggestion]| {
+        let mut fixits = Fixits::new();
+        for edit in edits {
+            fixits.add(smap, edit);
+        }
+        fixits.apply_verbatim(smap, main_id)
+    };
+
+    let mut format_opts = format::Options::default();
+    let edits = format::format_directives(ctx.smap, main_id, &format_opts);
+
+    let mut fixed = match apply(ctx.smap, &edits) {
+        Some(fixed) => fixed,
+        None => {
+            return ctx
+                .diags
+                .report_anon(
+                    Level::Error,
+                    format!(
+                        "cannot format directives in '{}' without altering its encoding, byte \
+                         order mark, line endings or invalid sequences",
+                        path.display()
+                    ),
+                )
+                .emit();
+        }
+    };
+
+    let sorts_includes = edits
+        .iter()
+        .any(|edit| edit.applicability != Applicability::MachineApplicable);
+
+    if sorts_includes
+        && !preserves_preprocessed_output(
+            ctx,
+            main_id,
+            main_file,
+            opts,
+            file_cache,
+            &fixed.contents,
+        )
+    {
+        ctx.diags
+            .report_anon(
+                Level::Warning,
+                format!(
+                    "not sorting includes in '{}', as that changes its preprocessed output",
+                    path.display()
+                ),
+            )
+            .emit()?;
+
+        format_opts.sort_includes = false;
+        let edits = format::format_directives(ctx.smap, main_id, &format_opts);
+        fixed = apply(ctx.smap, &edits).expect("file contents already checked");
+    }
+
+    print!("{}", fixed.contents);
+    Ok(())
+}
+
+/// Checks whether preprocessing `new_src` in place of the file `main_id` produces the same tokens
+/// as preprocessing the file itself.
+///
+/// Diagnostics reported while preprocessing are discarded, and the outputs are considered
+/// different if either run reports an error.
+fn preserves_preprocessed_output(
+    ctx: &mut LexCtx<'_, '_>,
+    main_id: SourceId,
+    main_file: &File,
+    opts: &PpOptions,
+    file_cache: &mut FileCache,
+    new_src: &str,
+) -> bool {
+    let filename = ctx
+        .smap
+        .get_source(main_id)
+        .as_file()
+        .expect("main source is not a file")
+        .filename
+        .clone();
+    let new_file = File::new(FileContents::new(new_src), main_file.parent_dir.clone());
+    let new_id = match ctx
+        .smap
+        .create_file(filename, Lrc::clone(&new_file.contents), None)
+    {
+        Ok(id) => id,
+        Err(_) => return false,
+    };
+
+    let mut diags = DiagManager::new(NullSink, None);
+    let mut quiet_ctx = LexCtx::new(ctx.interner, &mut diags, ctx.smap);
+    quiet_ctx.limits = ctx.limits;
+
+    // Keep `__DATE__` and `__TIME__` consistent between the runs.
+    let date_time = SystemTime::now();
+    let mut spell = |id: SourceId, file: &File| -> Option<Vec<String>> {
+        let mut pp = configure_preprocessor(&mut quiet_ctx, id, file, opts, file_cache)
+            .date_time(date_time)
+            .build();
+
+        let res = (|| -> PpResult<Vec<Token>> {
+            let mut tokens = Vec::new();
+            loop {
+                let ppt = pp.next_pp(&mut quiet_ctx)?;
+                if ppt.data() == TokenKind::Eof {
+                    break Ok(tokens);
+                }
+                tokens.push(ppt.tok);
+            }
+        })();
+
+        *file_cache = pp.into_file_cache();
+        res.ok().map(|tokens| spell_tokens(&quiet_ctx, &tokens))
+    };
+
+    let old_spellings = spell(main_id, main_file);
+    let new_spellings = spell(new_id, &new_file);
+
+    old_spellings.is_some() && old_spellings == new_spellings && diags.error_count() == 0
+}
+
+/// A diagnostic sink discarding all diagnostics.
+struct NullSink;
+
+impl RenderedSink for NullSink {
+    fn report(&mut self, _diag: &RenderedDiagnostic, _smap: Option<&SourceMap>) {}
+}
+
+/// Prints the file `main_id` with the definition of the macro `name` removed, and reports the
+/// command-line flag that can be used in its place.
+///
+/// The file is preprocessed as specified by `opts` to check that the flag is equivalent to the
+/// definition.
+fn extract_define(
+    ctx: &mut LexCtx<'_, '_>,
+    main_id: SourceId,
+    main_file: &File,
+    opts: &PpOptions,
+    file_cache: &mut FileCache,
+    name: &str,
+) -> PpResult<()> {
+    let mut pp = configure_preprocessor(ctx, main_id, main_file, opts, file_cache).build();
+
+    let res = pp::extract_define(ctx, &mut pp, main_id, name);
+    *file_cache = pp.into_file_cache();
+
+    if let Some(extracted) = res? {
+        print!("{}", pp::apply_edits(ctx.smap, main_id, &[extracted.edit]));
+
+        ctx.diags
+            .report_anon(
+                Level::Note,
+                format!(
+                    "pass {} to preserve the definition",
+                    shell_quote(&extracted.flag)
+                ),
+            )
+            .emit()?;
+    }
+
+    Ok(())
+}
+
+/// Quotes `arg` for a POSIX shell, if it contains any characters that the shell would interpret.
+fn shell_quote(arg: &str) -> Cow<'_, str> {
+    let is_plain = |c: char| c.is_ascii_alphanumeric() || "_-+=.,/:@%".contains(c);
+
+    if !arg.is_empty() && arg.chars().all(is_plain) {
+        Cow::Borrowed(arg)
+    } else {
+        Cow::Owned(format!("'{}'", arg.replace('\'', "'\\''")))
+    }
+}
+
+/// Prints the file `main_id` with the object-like macro `name` inlined into all of its uses.
+///
+/// The file is preprocessed in order to find the uses, with full expansion tracking regardless of
+/// the level specified in `opts`.
+fn inline_macro(
+    ctx: &mut LexCtx<'_, '_>,
+    main_id: SourceId,
+    main_file: &File,
+    opts: &PpOptions,
+    file_cache: &mut FileCache,
+    name: &str,
+) -> PpResult<()> {
+    let mut pp = configure_preprocessor(ctx, main_id, main_file, opts, file_cache)
+        .expansion_tracking(ExpansionTracking::Full)
+        .build();
+
+    let res = pp::inline_macro(ctx, &mut pp, main_id, name);
+    *file_cache = pp.into_file_cache();
+
+    if let Some(edits) = res? {
+        print!("{}", pp::apply_edits(ctx.smap, main_id, &edits));
+    }
+
+    Ok(())
+}
+
+/// Preprocesses the file `main_id` while tracing macro expansions, and prints an HTML page
+/// explaining the expansion of the macro invocation at `loc`.
+fn explain_expansion(
+    ctx: &mut LexCtx<'_, '_>,
+    main_id: SourceId,
+    main_file: &File,
+    opts: &PpOptions,
+    file_cache: &mut FileCache,
+    loc: &ExpansionLoc,
+) -> PpResult<()> {
+    let mut pp = configure_preprocessor(ctx, main_id, main_file, opts, file_cache)
+        .expansion_tracking(ExpansionTracking::Full)
+        .trace_expansions(true)
+        .build();
+
+    let res = (|| -> PpResult<()> {
+        while pp.next_pp(ctx)?.data() != TokenKind::Eof {}
+
+        let trace = pp.expansion_trace().unwrap();
+        match explain::find_invocation(ctx.smap, pp.file_system(), trace, loc) {
+            Some(root) => print!("{}", explain::render_html(ctx, trace, root)),
+            None => ctx
+                .diags
+                .report_anon(
+                    Level::Error,
+                    format!("no macro invocation found at {}", loc),
+                )
+                .emit()?,
+        }
+
+        Ok(())
+    })();
+
+    *file_cache = pp.into_file_cache();
+    res
+}
+
+/// Prints the tokens of the file `id`, tokenized according to `config`, without preprocessing
+/// them.
+fn lex_file(
+    ctx: &mut LexCtx<'_, '_>,
+    id: SourceId,
+    config: LexerConfig,
+    printer: &mut TokenPrinter<impl Write>,
+) -> DResult<()> {
+    let source = ctx.smap.get_source(id);
+    let contents = Lrc::clone(&source.as_file().unwrap().contents);
+    let base_pos = source.range.start();
+
+    let mut tokenizer = Tokenizer::with_config(contents.src(), config);
+    let mut line_start = true;
+    let mut leading_trivia = false;
 
     loop {
-        let ppt = pp.next_pp(&mut ctx)?;
-        if ppt.data() == TokenKind::Eof {
-            break;
+        let tok = lex::convert_raw(ctx, &tokenizer.next_token(), base_pos)?;
+
+        let kind = match tok.data {
+            ConvertedTokenKind::Real(TokenKind::Eof) => break Ok(()),
+            ConvertedTokenKind::Real(kind) => kind,
+            ConvertedTokenKind::Newline => {
+                line_start = true;
+                leading_trivia = false;
+                continue;
+            }
+            ConvertedTokenKind::Trivia => {
+                leading_trivia = true;
+                continue;
+            }
+        };
+
+        let ppt = PpToken {
+            tok: Token::new(kind, tok.range),
+            line_start,
+            leading_trivia,
+            leading_trivia_range: None,
+        };
+
+        if !printer.print(ctx, ppt)? {
+            break Ok(());
+        }
+
+        line_start = false;
+        leading_trivia = false;
+    }
+}
+
+/// Prints tokens to an output stream, approximately preserving their original layout.
+struct TokenPrinter<W> {
+    writer: TokenWriter<W>,
+    limit: Option<u64>,
+    count: u64,
+}
+
+impl<W: Write> TokenPrinter<W> {
+    /// Creates a new printer writing to `out`, which will stop after `limit` tokens if provided.
+    fn new(out: W, limit: Option<u64>) -> Self {
+        Self {
+            writer: TokenWriter::new(out),
+            limit,
+            count: 0,
+        }
+    }
+
+    /// Terminates the last line of output and flushes the output stream, returning it.
+    fn finish(mut self) -> W {
+        self.writer.end_line().expect("failed to write output");
+        let mut out = self.writer.into_inner();
+        out.flush().expect("failed to write output");
+        out
+    }
+
+    /// Prints `ppt`, returning `false` if the token limit has already been reached.
+    ///
+    /// When the limit is reached, the output is flushed and a note is reported at `ppt`,
+    /// indicating where processing stopped.
+    fn print(&mut self, ctx: &mut LexCtx<'_, '_>, ppt: PpToken) -> DResult<bool> {
+        if self.limit == Some(self.count) {
+            self.writer.end_line().expect("failed to write output");
+            self.writer
+                .get_mut()
+                .flush()
+                .expect("failed to write output");
+
+            ctx.reporter()
+                .report(
+                    Level::Note,
+                    ppt.range(),
+                    format!("stopped after {} tokens", self.count),
+                )
+                .emit()?;
+            return Ok(false);
         }
 
-        if ppt.line_start {
-            println!();
+        self.count += 1;
+
+        let spacing = if ppt.line_start {
+            self.writer.end_line().expect("failed to write output");
 
             // Preserve indentation by advancing to the start column first.
             let col = ctx
@@ -66,22 +1330,168 @@ fn run(diags: &mut DiagManager<'_>) -> DResult<()> {
                 .start_linecol()
                 .col;
 
-            print!("{}", " ".repeat(col as usize));
-
-            // We've already handled the leading whitespace ourselves, output the token directly.
-            print!("{}", ppt.tok.display(&ctx))
+            self.writer
+                .write_raw(&" ".repeat(col as usize))
+                .expect("failed to write output");
+            Spacing::Joint
         } else {
-            print!("{}", ppt.display(&ctx));
+            ppt.spacing()
+        };
+
+        self.writer
+            .write_token(ctx, ppt.tok, spacing)
+            .expect("failed to write output");
+        Ok(true)
+    }
+
+    /// Prints `pragma` on a line of its own, so that it is preserved in the output.
+    fn print_pragma(&mut self, ctx: &LexCtx<'_, '_>, pragma: &Pragma) {
+        self.writer.end_line().expect("failed to write output");
+        self.writer
+            .write_raw("#pragma")
+            .expect("failed to write output");
+
+        for ppt in &pragma.tokens {
+            self.writer
+                .write_token(ctx, ppt.tok, Spacing::Space)
+                .expect("failed to write output");
         }
+
+        self.writer.end_line().expect("failed to write output");
     }
+}
 
-    Ok(())
+/// Rewrites the GCC-style options `-MD`, `-MF`, `-MT`, `-isystem`, `-include`, `-trigraphs`,
+/// `-Werror`, `-pedantic`, `-pedantic-errors`, `-ftime-report`, `-stats`, `-std=...`,
+/// `-ferror-limit=...`, `-fmacro-backtrace-limit=...` and `-Wno-...`, which cannot be declared as
+/// short options, into their long forms.
+fn normalize_args(args: impl Iterator<Item = OsString>) -> impl Iterator<Item = OsString> {
+    args.map(|arg| match arg.to_str() {
+        Some(opt) if opt.starts_with("-Wno-") => format!("--Wno={}", &opt["-Wno-".len()..]).into(),
+        Some(opt)
+            if opt.starts_with("-std=")
+                || opt.starts_with("-ferror-limit=")
+                || opt.starts_with("-fmacro-backtrace-limit=") =>
+        {
+            format!("-{}", opt).into()
+        }
+        Some(
+            opt @ ("-MD" | "-MF" | "-MT" | "-isystem" | "-include" | "-trigraphs" | "-Werror"
+            | "-pedantic" | "-pedantic-errors" | "-ftime-report" | "-stats"),
+        ) => format!("-{}", opt).into(),
+        _ => arg,
+    })
 }
 
 fn main() {
-    let mut diags = DiagManager::new_annotating(None);
+    let matches = Opts::clap().get_matches_from(normalize_args(std::env::args_os()));
+    let mut opts = Opts::from_clap(&matches);
+    opts.macro_options = ordered_macro_options(&opts, &matches);
 
-    if run(&mut diags).is_err() || diags.error_count() > 0 {
+    if opts.filenames.len() > 1 {
+        let single_output = [
+            ("-o", opts.output.is_some()),
+            ("-MF", opts.dep_file.is_some()),
+            ("-MT", opts.dep_target.is_some()),
+        ];
+        if let Some((flag, _)) = single_output.iter().find(|(_, present)| *present) {
+            eprintln!("error: cannot specify '{}' with multiple input files", flag);
+            std::process::exit(1);
+        }
+    }
+
+    if opts.fix && opts.filenames.iter().any(|filename| is_stdin(filename)) {
+        eprintln!("error: cannot apply fix-its to standard input");
         std::process::exit(1);
     }
+
+    if opts.serve {
+        if let Err(err) = serve::serve() {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let limits = FrontendLimits {
+        error_limit: opts.error_limit.filter(|&limit| limit != 0),
+        macro_backtrace_limit: match opts.macro_backtrace_limit {
+            Some(0) => None,
+            Some(limit) => Some(limit),
+            None => Some(DEFAULT_MACRO_BACKTRACE_LIMIT),
+        },
+        warnings_as_errors: opts.warnings_as_errors,
+        ignore_warnings: opts.ignore_warnings,
+        ..frontend_limits(
+            opts.max_literal_len,
+            opts.max_embed_size,
+            opts.token_limit,
+            opts.max_diagnostics_per_line,
+        )
+    };
+    let sink: Box<dyn RenderedSink> = match opts.diagnostic_format {
+        DiagnosticFormat::Text => Box::new(AnnotatingSink::new(opts.color)),
+        DiagnosticFormat::Json => Box::new(JsonSink::new(io::stderr())),
+        DiagnosticFormat::Sarif => Box::new(SarifSink::new(io::stderr())),
+    };
+
+    let fixits = RefCell::new(Fixits::new());
+    let mut sink = if opts.fix {
+        Box::new(FixitSink::new(sink, &fixits))
+    } else {
+        sink
+    };
+
+    let mut failed = false;
+    let mut warning_count = 0;
+    let mut error_count = 0;
+
+    // Every input is a separate translation unit, so give each one a fresh diagnostics manager:
+    // error limits, deduplication and statistics should not carry over between them.
+    for filename in &opts.filenames {
+        let mut diags = DiagManager::with_limits(&mut sink, &limits);
+        // Don't repeat diagnostics in macro definitions for every expansion of the macro.
+        diags.set_deduplicate(true);
+        for &id in &opts.disabled_diagnostics {
+            diags.set_enabled(id, false);
+        }
+
+        let res = run(&opts, filename, limits, &mut diags, &fixits);
+        failed |= res.is_err() || diags.error_count() > 0;
+        warning_count += diags.warning_count();
+        error_count += diags.error_count();
+    }
+
+    // Sinks like `SarifSink` only write their output once dropped, which `exit` would skip.
+    drop(sink);
+
+    if opts.filenames.len() > 1
+        && opts.diagnostic_format == DiagnosticFormat::Text
+        && (warning_count > 0 || error_count > 0)
+    {
+        eprintln!(
+            "{} generated.",
+            summarize_counts(warning_count, error_count)
+        );
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+/// Describes the numbers of warnings and errors reported, as in `2 warnings and 1 error`.
+fn summarize_counts(warnings: u32, errors: u32) -> String {
+    let describe =
+        |count: u32, noun: &str| format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" });
+
+    match (warnings, errors) {
+        (0, errors) => describe(errors, "error"),
+        (warnings, 0) => describe(warnings, "warning"),
+        (warnings, errors) => format!(
+            "{} and {}",
+            describe(warnings, "warning"),
+            describe(errors, "error")
+        ),
+    }
 }
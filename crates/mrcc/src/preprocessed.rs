@@ -0,0 +1,303 @@
+//! Preprocessed output, as produced by `-E`.
+//!
+//! The output is meant to be fed back into a compiler: tokens are separated wherever they would
+//! otherwise lex differently, and GCC-style line markers (`# <line> "<file>" <flags>`) record the
+//! presumed location of every line.
+
+use std::io::Write;
+
+use lex::raw::LexerConfig;
+use lex::{LexCtx, Spacing, TokenWriter};
+use pp::{PpToken, Pragma};
+use source::smap::FileName;
+use source::{SourceId, SourceMap, SourcePos};
+
+/// The maximum number of blank lines written to reach the next line of output; larger gaps are
+/// bridged with a line marker instead.
+const MAX_BLANK_LINES: u32 = 8;
+
+/// Flags appended to line markers, as understood by GCC-compatible compilers.
+#[derive(Clone, Copy)]
+enum MarkerFlag {
+    None,
+    /// The start of a new file.
+    Enter,
+    /// The return to a file after an include.
+    Return,
+}
+
+/// Writes preprocessed tokens to an output stream, preserving their presumed locations with line
+/// markers.
+pub struct PreprocessedWriter<W> {
+    writer: TokenWriter<W>,
+    /// The files currently being processed, with the main file at the bottom.
+    files: Vec<SourceId>,
+    /// The presumed filename of the current output line.
+    cur_file: FileName,
+    /// The presumed (zero-based) line number of the current output line.
+    cur_line: u32,
+}
+
+impl<W: Write> PreprocessedWriter<W> {
+    /// Creates a new writer for the output of preprocessing the file `main_id`, writing to `out`.
+    ///
+    /// Tokens are separated as necessary for the output to be lexed according to `config`.
+    pub fn new(out: W, smap: &SourceMap, main_id: SourceId, config: LexerConfig) -> Self {
+        let mut writer = Self {
+            writer: TokenWriter::with_config(out, config),
+            files: vec![main_id],
+            cur_file: smap.get_source(main_id).as_file().unwrap().filename.clone(),
+            cur_line: 0,
+        };
+        writer.write_marker(MarkerFlag::None);
+        writer
+    }
+
+    /// Records the start of the included file `id`.
+    pub fn enter_file(&mut self, smap: &SourceMap, id: SourceId) {
+        self.end_line();
+        self.files.push(id);
+        self.cur_file = smap.get_source(id).as_file().unwrap().filename.clone();
+        self.cur_line = 0;
+        self.write_marker(MarkerFlag::Enter);
+    }
+
+    /// Records the end of the included file `id`, after which output continues on the line
+    /// following the corresponding `#include` directive.
+    pub fn exit_file(&mut self, smap: &SourceMap, id: SourceId) {
+        self.end_line();
+        self.files.pop();
+
+        let include_pos = smap.get_source(id).as_file().unwrap().include_pos.unwrap();
+        let (filename, line) = presumed_line(smap, include_pos);
+        self.cur_file = filename;
+        self.cur_line = line + 1;
+        self.write_marker(MarkerFlag::Return);
+    }
+
+    /// Writes `ppt`, starting a new line first if it began a line in the source.
+    pub fn write_token(&mut self, ctx: &LexCtx<'_, '_>, ppt: PpToken) {
+        let spacing = if ppt.line_start || self.writer.is_line_empty() {
+            let pos = self.move_to(ctx.smap, ppt.range().start());
+
+            // Preserve indentation by advancing to the start column first, unless the token
+            // comes from a synthesized source.
+            if pos == ctx.smap.get_replacement_range(ppt.range()).start() {
+                let col = ctx
+                    .smap
+                    .get_interpreted_range(pos.into())
+                    .start_linecol()
+                    .col;
+                self.write(&" ".repeat(col as usize));
+            }
+
+            Spacing::Joint
+        } else {
+            ppt.spacing()
+        };
+
+        self.writer
+            .write_token(ctx, ppt.tok, spacing)
+            .expect("failed to write output");
+    }
+
+    /// Writes `pragma` on a line of its own, so that it is preserved in the output.
+    pub fn write_pragma(&mut self, ctx: &LexCtx<'_, '_>, pragma: &Pragma) {
+        self.move_to(ctx.smap, pragma.range.start());
+        self.write("#pragma");
+
+        for ppt in &pragma.tokens {
+            self.writer
+                .write_token(ctx, ppt.tok, Spacing::Space)
+                .expect("failed to write output");
+        }
+
+        // Keep subsequent tokens off the pragma's line, even those that shared a line with it in
+        // the source (as is possible with the `_Pragma` operator).
+        self.end_line();
+    }
+
+    /// Terminates the last line of output and flushes the output stream, returning it.
+    pub fn finish(mut self) -> W {
+        self.end_line();
+        let mut out = self.writer.into_inner();
+        out.flush().expect("failed to write output");
+        out
+    }
+
+    /// Starts a new line corresponding to the presumed line of `pos`, writing blank lines or a line
+    /// marker as necessary.
+    ///
+    /// Returns the position in the current file to which `pos` was mapped.
+    fn move_to(&mut self, smap: &SourceMap, pos: SourcePos) -> SourcePos {
+        let pos = self.file_pos(smap, pos);
+        let (filename, line) = presumed_line(smap, pos);
+
+        self.end_line();
+
+        if filename != self.cur_file
+            || line < self.cur_line
+            || line - self.cur_line > MAX_BLANK_LINES
+        {
+            self.cur_file = filename;
+            self.cur_line = line;
+            self.write_marker(MarkerFlag::None);
+        } else {
+            while self.cur_line < line {
+                self.write("\n");
+                self.cur_line += 1;
+            }
+        }
+
+        pos
+    }
+
+    /// Maps `pos` to a position in the innermost file currently being processed, going through
+    /// macro invocations and synthesized sources (such as those created by `#embed`).
+    fn file_pos(&self, smap: &SourceMap, pos: SourcePos) -> SourcePos {
+        let cur_id = *self.files.last().unwrap();
+        let mut pos = smap.get_replacement_range(pos.into()).start();
+
+        while smap.lookup_source_id(pos) != cur_id {
+            match smap.get_source(smap.lookup_source_id(pos)).as_file() {
+                Some(file) if file.include_pos.is_some() => {
+                    pos = smap
+                        .get_replacement_range(file.include_pos.unwrap().into())
+                        .start();
+                }
+                _ => break,
+            }
+        }
+
+        pos
+    }
+
+    /// Terminates the current line of output if anything has been written on it.
+    fn end_line(&mut self) {
+        if self.writer.end_line().expect("failed to write output") {
+            self.cur_line += 1;
+        }
+    }
+
+    /// Writes a line marker indicating that the next line of output is the current line.
+    fn write_marker(&mut self, flag: MarkerFlag) {
+        let flag = match flag {
+            MarkerFlag::None => "",
+            MarkerFlag::Enter => " 1",
+            MarkerFlag::Return => " 2",
+        };
+
+        let marker = format!(
+            "# {} \"{}\"{}\n",
+            self.cur_line + 1,
+            escape(&self.cur_file.to_string()),
+            flag
+        );
+        self.write(&marker);
+    }
+
+    fn write(&mut self, s: &str) {
+        self.writer.write_raw(s).expect("failed to write output");
+    }
+}
+
+/// Returns the presumed filename and (zero-based) line of `pos`.
+fn presumed_line(smap: &SourceMap, pos: SourcePos) -> (FileName, u32) {
+    let interp = smap.get_interpreted_range(pos.into());
+    (
+        interp.presumed_filename().clone(),
+        interp.presumed_start_linecol().line,
+    )
+}
+
+/// Escapes `filename` for use in a line marker.
+fn escape(filename: &str) -> String {
+    filename.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use lex::{Interner, TokenKind};
+    use pp::{PpEvent, PreprocessorBuilder};
+    use source::smap::FileContents;
+    use source::DiagManager;
+
+    use super::*;
+
+    /// Preprocesses `src` as the file `t.c`, returning the preprocessed output.
+    fn preprocess(src: &str) -> String {
+        let mut diags = DiagManager::new_annotating(None);
+        let mut interner = Interner::new();
+        let mut smap = SourceMap::new();
+        let main_id = smap
+            .create_file(FileName::real("t.c"), FileContents::new(src), None)
+            .unwrap();
+
+        let mut ctx = LexCtx::new(&mut interner, &mut diags, &mut smap);
+        let mut pp = PreprocessorBuilder::new(&mut ctx, main_id)
+            .file_boundaries(true)
+            .build();
+        let mut writer =
+            PreprocessedWriter::new(Vec::new(), ctx.smap, main_id, LexerConfig::default());
+
+        loop {
+            match pp.next_pp_event(&mut ctx).unwrap() {
+                PpEvent::Tok(ppt) if ppt.data() == TokenKind::Eof => break,
+                PpEvent::Tok(ppt) => writer.write_token(&ctx, ppt),
+                PpEvent::Pragma(pragma) => writer.write_pragma(&ctx, &pragma),
+                PpEvent::EnterFile(id) => writer.enter_file(ctx.smap, id),
+                PpEvent::ExitFile(id) => writer.exit_file(ctx.smap, id),
+            }
+        }
+
+        String::from_utf8(writer.finish()).unwrap()
+    }
+
+    #[test]
+    fn preserves_lines() {
+        assert_eq!(
+            preprocess("#define A 1 +\nint x =\n    A 2;\n\n\nint y;\n"),
+            "# 1 \"t.c\"\n\nint x =\n    1 + 2;\n\n\nint y;\n"
+        );
+    }
+
+    #[test]
+    fn line_markers() {
+        assert_eq!(
+            preprocess("a\n\n\n\n\n\n\n\n\n\n\nb\n#line 20 \"u.c\"\nc\n#pragma x y\nd\n"),
+            "# 1 \"t.c\"\na\n# 12 \"t.c\"\nb\n# 20 \"u.c\"\nc\n#pragma x y\nd\n"
+        );
+    }
+
+    #[test]
+    fn pragma_operator() {
+        assert_eq!(
+            preprocess("#define P(x) _Pragma(#x)\na P(x y) b\nc\n"),
+            "# 1 \"t.c\"\n\na\n# 2 \"t.c\"\n#pragma x y\n# 2 \"t.c\"\n         b\nc\n"
+        );
+    }
+
+    #[test]
+    fn multiline_invocation() {
+        assert_eq!(
+            preprocess("#define F(x, y) x y\nF(a,\n  b) c\nd\n"),
+            "# 1 \"t.c\"\n\na b c\n\nd\n"
+        );
+    }
+
+    #[test]
+    fn avoids_pasting() {
+        assert_eq!(
+            preprocess("#define P +\n#define E\n#define S /\nP+ -E- S/ x/**/y .P 1 E.2\n"),
+            "# 1 \"t.c\"\n\n\n\n+ + - - / / x y .+ 1 .2\n"
+        );
+    }
+
+    #[test]
+    fn escapes_filenames() {
+        assert_eq!(
+            preprocess("#line 1 \"a\\\\\\\"b.c\"\nx\n"),
+            "# 1 \"t.c\"\n# 1 \"a\\\\\\\"b.c\"\nx\n"
+        );
+    }
+}
@@ -0,0 +1,458 @@
+//! Diagnostic output in the Static Analysis Results Interchange Format (SARIF) 2.1.0, as consumed
+//! by code scanning services.
+//!
+//! Unlike the other sinks, a SARIF log is a single JSON document, so results are collected as they
+//! are reported and written out when the sink is dropped.
+
+use std::io::Write;
+
+use serde::Serialize;
+
+use source::diag::{
+    Level, RenderedDiagnostic, RenderedSink, RenderedSubDiagnostic, RenderedSuggestion,
+};
+use source::smap::FileName;
+use source::{SourceMap, SourceRange};
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+const SARIF_VERSION: &str = "2.1.0";
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Log<'a> {
+    #[serde(rename = "$schema")]
+    schema: &'static str,
+    version: &'static str,
+    runs: [Run<'a>; 1],
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Run<'a> {
+    tool: Tool<'a>,
+    results: &'a [SarifResult],
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Tool<'a> {
+    driver: ToolDriver<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ToolDriver<'a> {
+    name: &'static str,
+    version: &'static str,
+    rules: &'a [Rule],
+}
+
+/// A diagnostic ID that appears in the results.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Rule {
+    id: &'static str,
+}
+
+/// A single reported diagnostic.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SarifResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rule_id: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rule_index: Option<usize>,
+    level: &'static str,
+    message: Message,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    locations: Vec<SarifLocation>,
+    /// Subranges, notes and expansion traces attached to the diagnostic.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    related_locations: Vec<SarifLocation>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fixes: Vec<Fix>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Message {
+    text: String,
+}
+
+impl Message {
+    fn new(text: impl Into<String>) -> Self {
+        Self { text: text.into() }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SarifLocation {
+    physical_location: PhysicalLocation,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<Message>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PhysicalLocation {
+    artifact_location: ArtifactLocation,
+    region: Region,
+}
+
+impl PhysicalLocation {
+    /// Describes `range`, returning `None` if it does not lie in a real file.
+    fn new(smap: &SourceMap, range: SourceRange) -> Option<Self> {
+        let interp = smap.get_interpreted_range(range);
+        let uri = match interp.filename() {
+            FileName::Real(path) => path.display().to_string(),
+            FileName::Synth(_) => return None,
+        };
+
+        let start = interp.start_linecol();
+        let end = interp.end_linecol();
+
+        Some(Self {
+            artifact_location: ArtifactLocation { uri },
+            region: Region {
+                start_line: start.line + 1,
+                start_column: start.col + 1,
+                end_line: end.line + 1,
+                end_column: end.col + 1,
+            },
+        })
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ArtifactLocation {
+    uri: String,
+}
+
+/// A (1-based) region in a file, with an exclusive end column.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Region {
+    start_line: u32,
+    start_column: u32,
+    end_line: u32,
+    end_column: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Fix {
+    description: Message,
+    artifact_changes: [ArtifactChange; 1],
+    properties: FixProperties,
+}
+
+impl Fix {
+    fn new(smap: &SourceMap, suggestion: &RenderedSuggestion) -> Option<Self> {
+        let PhysicalLocation {
+            artifact_location,
+            region,
+        } = PhysicalLocation::new(smap, suggestion.replacement_range)?;
+
+        let description = if suggestion.insert_text.is_empty() {
+            "remove this code".to_owned()
+        } else {
+            format!("insert `{}`", suggestion.insert_text)
+        };
+
+        Some(Self {
+            description: Message::new(description),
+            artifact_changes: [ArtifactChange {
+                artifact_location,
+                replacements: [Replacement {
+                    deleted_region: region,
+                    inserted_content: Message::new(suggestion.insert_text.clone()),
+                }],
+            }],
+            properties: FixProperties {
+                applicability: suggestion.applicability.as_str(),
+            },
+        })
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ArtifactChange {
+    artifact_location: ArtifactLocation,
+    replacements: [Replacement; 1],
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Replacement {
+    deleted_region: Region,
+    inserted_content: Message,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FixProperties {
+    applicability: &'static str,
+}
+
+/// Returns the physical location of the primary range of `subdiag`, if it has one.
+fn primary_location(
+    subdiag: &RenderedSubDiagnostic,
+    smap: Option<&SourceMap>,
+) -> Option<PhysicalLocation> {
+    let (smap, ranges) = smap.zip(subdiag.ranges.as_ref())?;
+    PhysicalLocation::new(smap, ranges.primary_range)
+}
+
+/// A diagnostic sink collecting diagnostics into a SARIF log, which is written to a stream when
+/// the sink is dropped.
+///
+/// Notes with locations are reported as related locations of the diagnostic they are attached to;
+/// the messages of notes without locations are appended to the diagnostic's message.
+pub struct SarifSink<W: Write> {
+    out: W,
+    rules: Vec<Rule>,
+    results: Vec<SarifResult>,
+}
+
+impl<W: Write> SarifSink<W> {
+    /// Creates a new sink writing to `out`.
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            rules: Vec::new(),
+            results: Vec::new(),
+        }
+    }
+
+    /// Returns the index of the rule `id`, adding it if it has not been seen before.
+    fn rule_index(&mut self, id: &'static str) -> usize {
+        match self.rules.iter().position(|rule| rule.id == id) {
+            Some(idx) => idx,
+            None => {
+                self.rules.push(Rule { id });
+                self.rules.len() - 1
+            }
+        }
+    }
+}
+
+impl<W: Write> RenderedSink for SarifSink<W> {
+    fn report(&mut self, diag: &RenderedDiagnostic, smap: Option<&SourceMap>) {
+        let rule_id = diag.id().map(|id| id.name());
+        let rule_index = rule_id.map(|id| self.rule_index(id));
+
+        let mut text = diag.main().msg.to_string();
+        let mut related_locations = Vec::new();
+
+        let main_ranges = smap.zip(diag.main().ranges.as_ref());
+        if let Some((smap, ranges)) = main_ranges {
+            related_locations.extend(ranges.subranges.iter().filter_map(|(range, label)| {
+                Some(SarifLocation {
+                    physical_location: PhysicalLocation::new(smap, *range)?,
+                    message: Some(Message::new(label.clone())).filter(|msg| !msg.text.is_empty()),
+                })
+            }));
+        }
+
+        for note in diag.notes() {
+            match primary_location(note, smap) {
+                Some(physical_location) => related_locations.push(SarifLocation {
+                    physical_location,
+                    message: Some(Message::new(note.msg.to_string())),
+                }),
+                None => {
+                    text.push_str("\nnote: ");
+                    text.push_str(&note.msg.to_string());
+                }
+            }
+        }
+
+        let fixes = smap
+            .map(|smap| {
+                std::iter::once(diag.main())
+                    .chain(diag.notes())
+                    .flat_map(|subdiag| &subdiag.suggestions)
+                    .filter_map(|suggestion| Fix::new(smap, suggestion))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        self.results.push(SarifResult {
+            rule_id,
+            rule_index,
+            level: match diag.level() {
+                Level::Note => "note",
+                Level::Warning => "warning",
+                Level::Error | Level::Fatal => "error",
+            },
+            message: Message::new(text),
+            locations: primary_location(diag.main(), smap)
+                .map(|physical_location| SarifLocation {
+                    physical_location,
+                    message: None,
+                })
+                .into_iter()
+                .collect(),
+            related_locations,
+            fixes,
+        });
+    }
+}
+
+impl<W: Write> Drop for SarifSink<W> {
+    fn drop(&mut self) {
+        let log = Log {
+            schema: SARIF_SCHEMA,
+            version: SARIF_VERSION,
+            runs: [Run {
+                tool: Tool {
+                    driver: ToolDriver {
+                        name: env!("CARGO_PKG_NAME"),
+                        version: env!("CARGO_PKG_VERSION"),
+                        rules: &self.rules,
+                    },
+                },
+                results: &self.results,
+            }],
+        };
+
+        serde_json::to_writer(&mut self.out, &log).expect("failed to write SARIF log");
+        writeln!(self.out).expect("failed to write SARIF log");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use source::diag::{
+        render, Applicability, DiagnosticId, RawDiagnostic, RawSubDiagnostic, RawSuggestion,
+    };
+    use source::smap::FileContents;
+    use source::LocalRange;
+
+    use super::*;
+
+    fn report_all(diags: &[RawDiagnostic], smap: Option<&SourceMap>) -> serde_json::Value {
+        let mut out = Vec::new();
+        {
+            let mut sink = SarifSink::new(&mut out);
+            for diag in diags {
+                sink.report(&render(diag, smap), smap);
+            }
+        }
+        serde_json::from_slice(&out).unwrap()
+    }
+
+    #[test]
+    fn empty_log() {
+        let log = report_all(&[], None);
+        assert_eq!(log["version"], "2.1.0");
+        assert_eq!(log["runs"][0]["tool"]["driver"]["name"], "mrcc");
+        assert_eq!(log["runs"][0]["results"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn locations_and_fixes() {
+        let mut smap = SourceMap::new();
+        let file = smap
+            .create_file(
+                FileName::real("t.c"),
+                FileContents::new("int x = f(a)\n"),
+                None,
+            )
+            .unwrap();
+        let file_range = smap.get_source(file).range;
+        let at = |off: u32, len: u32| file_range.subrange(LocalRange::at(off.into(), len.into()));
+
+        let diags = [
+            RawDiagnostic {
+                level: Level::Fatal,
+                id: None,
+                main: RawSubDiagnostic::new("bad call", at(8, 1).into())
+                    .with_labeled_range(at(10, 1).into(), "arg")
+                    .with_suggestion(RawSuggestion::new(at(12, 0), ";"))
+                    .with_suggestion(
+                        RawSuggestion::new_deletion(at(0, 4))
+                            .with_applicability(Applicability::MaybeIncorrect),
+                    ),
+                notes: vec![
+                    RawSubDiagnostic::new("declared here", at(4, 1).into()),
+                    RawSubDiagnostic::new_anon("see the manual"),
+                ],
+            },
+            RawDiagnostic {
+                level: Level::Warning,
+                id: Some(DiagnosticId::Comma),
+                main: RawSubDiagnostic::new("comma", at(3, 1).into()),
+                notes: Vec::new(),
+            },
+        ];
+
+        let log = report_all(&diags, Some(&smap));
+        let run = &log["runs"][0];
+        assert_eq!(
+            run["tool"]["driver"]["rules"],
+            serde_json::json!([{ "id": "comma" }])
+        );
+
+        assert_eq!(
+            run["results"][0],
+            serde_json::json!({
+                "level": "error",
+                "message": { "text": "bad call\nnote: see the manual" },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": "t.c" },
+                        "region": { "startLine": 1, "startColumn": 9, "endLine": 1, "endColumn": 10 }
+                    }
+                }],
+                "relatedLocations": [
+                    {
+                        "physicalLocation": {
+                            "artifactLocation": { "uri": "t.c" },
+                            "region": { "startLine": 1, "startColumn": 11, "endLine": 1, "endColumn": 12 }
+                        },
+                        "message": { "text": "arg" }
+                    },
+                    {
+                        "physicalLocation": {
+                            "artifactLocation": { "uri": "t.c" },
+                            "region": { "startLine": 1, "startColumn": 5, "endLine": 1, "endColumn": 6 }
+                        },
+                        "message": { "text": "declared here" }
+                    }
+                ],
+                "fixes": [
+                    {
+                        "description": { "text": "insert `;`" },
+                        "artifactChanges": [{
+                            "artifactLocation": { "uri": "t.c" },
+                            "replacements": [{
+                                "deletedRegion": { "startLine": 1, "startColumn": 13, "endLine": 1, "endColumn": 13 },
+                                "insertedContent": { "text": ";" }
+                            }]
+                        }],
+                        "properties": { "applicability": "machine-applicable" }
+                    },
+                    {
+                        "description": { "text": "remove this code" },
+                        "artifactChanges": [{
+                            "artifactLocation": { "uri": "t.c" },
+                            "replacements": [{
+                                "deletedRegion": { "startLine": 1, "startColumn": 1, "endLine": 1, "endColumn": 5 },
+                                "insertedContent": { "text": "" }
+                            }]
+                        }],
+                        "properties": { "applicability": "maybe-incorrect" }
+                    }
+                ]
+            })
+        );
+
+        assert_eq!(run["results"][1]["ruleId"], "comma");
+        assert_eq!(run["results"][1]["ruleIndex"], 0);
+        assert_eq!(run["results"][1]["level"], "warning");
+    }
+}
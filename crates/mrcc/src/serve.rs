@@ -0,0 +1,356 @@
+//! Server mode, answering preprocessing requests read from standard input.
+//!
+//! Each request is a JSON object on a single line, such as:
+//!
+//! ```text
+//! {"id": 1, "file": "main.c", "include_dirs": ["include"], "track_macro_expansion": 1}
+//! ```
+//!
+//! Every request is answered by a single line on standard output containing a JSON object with the
+//! same `id`, the preprocessed output and any diagnostics reported. The interner and the cache of
+//! loaded files are kept across requests, so files are only read again once they change on disk.
+//!
+//! Requests are processed in order, but a request can be cancelled before or while it is being
+//! processed by sending a line of the form:
+//!
+//! ```text
+//! {"cancel": 1}
+//! ```
+//!
+//! Cancellation messages are not answered themselves; instead, the cancelled request is answered
+//! with an error. The IDs of outstanding requests should be unique for cancellation to work
+//! reliably.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use lex::raw::LexerConfig;
+use lex::{Interner, LexCtx};
+use pp::{CancellationToken, FileCache, LangOptions, PpError, PpResult};
+use source::diag::{RenderedDiagnostic, RenderedSink};
+use source::smap::SourceMap;
+use source::DiagManager;
+
+use crate::env::EnvIncludeDirs;
+use crate::json_sink::DiagnosticInfo;
+use crate::{
+    expansion_tracking_from_level, frontend_limits, load_main_file, preprocess_file, PpOptions,
+    TokenPrinter,
+};
+
+/// A request to preprocess a file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Request {
+    /// An arbitrary value identifying the request, echoed back in the response.
+    #[serde(default)]
+    id: Value,
+    /// The file to preprocess.
+    file: PathBuf,
+    /// Additional include directories, searched before those from the environment.
+    #[serde(default)]
+    include_dirs: Vec<PathBuf>,
+    /// Additional system include directories, as with `-isystem`.
+    #[serde(default)]
+    system_include_dirs: Vec<PathBuf>,
+    /// Whether to ignore include directories specified in the environment.
+    #[serde(default)]
+    no_env_includes: bool,
+    /// Whether to replace trigraphs, as with `--trigraphs`.
+    #[serde(default)]
+    trigraphs: bool,
+    /// The macro expansion tracking level, as with `--track-macro-expansion`.
+    track_macro_expansion: Option<u8>,
+    /// The maximum literal length, as with `--max-literal-len`.
+    max_literal_len: Option<usize>,
+    /// The maximum size of an embedded resource, as with `--max-embed-size`.
+    max_embed_size: Option<usize>,
+    /// The maximum number of tokens to output, as with `--token-limit`.
+    token_limit: Option<u64>,
+    /// The maximum number of diagnostics to include for any single source line, as with
+    /// `--max-diagnostics-per-line`. By default, every diagnostic is included.
+    max_diagnostics_per_line: Option<u32>,
+}
+
+/// A request to cancel the outstanding request with the specified ID.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CancelRequest {
+    cancel: Value,
+}
+
+/// The response to a single request.
+#[derive(Serialize)]
+struct Response {
+    id: Value,
+    /// Whether the file was preprocessed without errors.
+    success: bool,
+    /// A description of why the request itself could not be processed, if applicable.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// The preprocessed output.
+    output: String,
+    /// The diagnostics reported while preprocessing.
+    diagnostics: Vec<DiagnosticInfo>,
+}
+
+impl Response {
+    fn invalid(id: Value, error: String) -> Self {
+        Self {
+            id,
+            success: false,
+            error: Some(error),
+            output: String::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+}
+
+/// A diagnostic sink collecting diagnostics for inclusion in a response.
+struct CollectingSink<'a>(&'a RefCell<Vec<DiagnosticInfo>>);
+
+impl RenderedSink for CollectingSink<'_> {
+    fn report(&mut self, diag: &RenderedDiagnostic, smap: Option<&SourceMap>) {
+        self.0.borrow_mut().push(DiagnosticInfo::new(diag, smap));
+    }
+}
+
+/// Tracks the cancellation tokens of outstanding requests, keyed by their (serialized) IDs.
+type PendingRequests = Arc<Mutex<HashMap<String, CancellationToken>>>;
+
+/// State retained across requests.
+struct Server {
+    interner: Interner,
+    file_cache: FileCache,
+    env_include_dirs: EnvIncludeDirs,
+}
+
+impl Server {
+    fn handle_line(&mut self, line: &str, cancellation_token: CancellationToken) -> Response {
+        let req: Request = match serde_json::from_str(line) {
+            Ok(req) => req,
+            Err(err) => {
+                // Try to recover the ID so that the client can still match up the response.
+                let id = serde_json::from_str::<Value>(line)
+                    .ok()
+                    .and_then(|val| val.get("id").cloned())
+                    .unwrap_or_default();
+                return Response::invalid(id, format!("invalid request: {}", err));
+            }
+        };
+
+        let expansion_tracking = match req.track_macro_expansion {
+            Some(level) => match expansion_tracking_from_level(level) {
+                Some(tracking) => tracking,
+                None => {
+                    let msg = format!("invalid tracking level '{}'", level);
+                    return Response::invalid(req.id, msg);
+                }
+            },
+            None => Default::default(),
+        };
+
+        let mut include_dirs = req.include_dirs.clone();
+        let mut system_include_dirs = req.system_include_dirs.clone();
+        if !req.no_env_includes {
+            include_dirs.extend(self.env_include_dirs.user.iter().cloned());
+            system_include_dirs.extend(self.env_include_dirs.system.iter().cloned());
+        }
+
+        let opts = PpOptions {
+            include_dirs,
+            system_include_dirs,
+            macro_options: Vec::new(),
+            forced_includes: Vec::new(),
+            expansion_tracking,
+            limits: frontend_limits(
+                req.max_literal_len,
+                req.max_embed_size,
+                req.token_limit,
+                req.max_diagnostics_per_line,
+            ),
+            cancellation_token: Some(cancellation_token),
+            report_memory: false,
+            time_report: false,
+            lang: LangOptions::default(),
+            lexer_config: LexerConfig {
+                trigraphs: req.trigraphs,
+                ..LexerConfig::default()
+            },
+            keep_comments: false,
+            pedantic_errors: false,
+            dep_file: None,
+        };
+
+        let diags = RefCell::new(Vec::new());
+        let mut manager = DiagManager::with_limits(CollectingSink(&diags), &opts.limits);
+        let mut printer = TokenPrinter::new(Vec::new(), opts.limits.token_limit);
+
+        let res = self.preprocess(&req, &opts, &mut manager, &mut printer);
+        let success = res.is_ok() && manager.error_count() == 0;
+        drop(manager);
+
+        let error = match res {
+            Err(PpError::Cancelled) => Some("request cancelled".to_owned()),
+            _ => None,
+        };
+
+        Response {
+            id: req.id,
+            success,
+            error,
+            output: String::from_utf8_lossy(&printer.finish()).into_owned(),
+            diagnostics: diags.into_inner(),
+        }
+    }
+
+    fn preprocess(
+        &mut self,
+        req: &Request,
+        opts: &PpOptions,
+        diags: &mut DiagManager<'_>,
+        printer: &mut TokenPrinter<Vec<u8>>,
+    ) -> PpResult<()> {
+        // Files may have been edited since the last request.
+        self.file_cache.revalidate();
+
+        let mut smap = SourceMap::new();
+        let (main_id, main_file) =
+            load_main_file(diags, &mut smap, &mut self.file_cache, &req.file)?;
+
+        let mut ctx = LexCtx::new(&mut self.interner, diags, &mut smap);
+        ctx.limits = opts.limits;
+        preprocess_file(
+            &mut ctx,
+            main_id,
+            &main_file,
+            opts,
+            &mut self.file_cache,
+            printer,
+        )
+    }
+}
+
+/// Runs the server until standard input is closed.
+pub fn serve() -> io::Result<()> {
+    let mut server = Server {
+        interner: Interner::new(),
+        file_cache: FileCache::new(),
+        env_include_dirs: crate::env::include_dirs(),
+    };
+
+    let pending = PendingRequests::default();
+    let requests = spawn_reader(Arc::clone(&pending));
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    for req in requests {
+        let (line, key, cancellation_token) = req?;
+
+        let resp = server.handle_line(&line, cancellation_token);
+        if let Some(key) = key {
+            pending.lock().unwrap().remove(&key);
+        }
+
+        serde_json::to_writer(&mut stdout, &resp)?;
+        writeln!(stdout)?;
+        stdout.flush()?;
+    }
+
+    Ok(())
+}
+
+/// A request line, along with the key under which it is registered in [`PendingRequests`] (if it
+/// has an ID) and its cancellation token.
+type QueuedRequest = (String, Option<String>, CancellationToken);
+
+/// Spawns a thread reading lines from standard input, so that cancellation messages can be
+/// handled while a request is being processed.
+///
+/// Cancellation messages are handled directly on the reader thread; all other lines are
+/// registered in `pending` and queued in the returned channel.
+fn spawn_reader(pending: PendingRequests) -> Receiver<io::Result<QueuedRequest>> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => {
+                    let _ = sender.send(Err(err));
+                    return;
+                }
+            };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if let Ok(cancel) = serde_json::from_str::<CancelRequest>(&line) {
+                if let Some(token) = pending.lock().unwrap().get(&cancel.cancel.to_string()) {
+                    token.cancel();
+                }
+                continue;
+            }
+
+            let token = CancellationToken::new();
+            let key = serde_json::from_str::<Value>(&line)
+                .ok()
+                .and_then(|val| val.get("id").map(Value::to_string));
+
+            if let Some(key) = &key {
+                pending.lock().unwrap().insert(key.clone(), token.clone());
+            }
+
+            if sender.send(Ok((line, key, token))).is_err() {
+                return;
+            }
+        }
+    });
+
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn reloads_edited_files() {
+        let dir = std::env::temp_dir().join(format!("mrcc-serve-reload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("s.c"), "#include \"a.h\"\nint v1;\n").unwrap();
+        fs::write(dir.join("a.h"), "int a1;\n").unwrap();
+
+        let mut server = Server {
+            interner: Interner::new(),
+            file_cache: FileCache::new(),
+            env_include_dirs: EnvIncludeDirs::default(),
+        };
+        let req = serde_json::json!({ "id": 1, "file": dir.join("s.c") }).to_string();
+
+        let first = server.handle_line(&req, CancellationToken::new());
+        assert!(first.success);
+        assert_eq!(first.output, "int a1;\nint v1;\n");
+
+        fs::write(dir.join("s.c"), "#include \"a.h\"\nint v2_changed;\n").unwrap();
+        fs::write(dir.join("a.h"), "int a2_changed;\n").unwrap();
+
+        let second = server.handle_line(&req, CancellationToken::new());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(second.success);
+        assert_eq!(second.output, "int a2_changed;\nint v2_changed;\n");
+    }
+}
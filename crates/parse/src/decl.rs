@@ -0,0 +1,754 @@
+//! Declarations (§6.7) and external definitions (§6.9).
+
+use lex::{PunctKind, Symbol};
+use source::DResult;
+use syntax::{Keyword, NodeKind, Token, TokenKind};
+
+use crate::{is_punct, Parser};
+
+/// Information gathered from a list of declaration specifiers.
+#[derive(Default)]
+struct DeclSpecs {
+    is_typedef: bool,
+}
+
+/// The kinds of declarators that can appear in a given context.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DeclaratorKind {
+    /// A declarator that must name an identifier, as in most declarations.
+    Concrete,
+    /// A declarator that does not name an identifier, as in type names.
+    Abstract,
+    /// A declarator that may or may not name an identifier, as in parameter declarations.
+    Either,
+}
+
+/// The derivations that a declarator can apply to the type of the entity it declares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Derivation {
+    Ptr,
+    Array,
+    Function,
+}
+
+/// Information gathered from a parsed declarator.
+#[derive(Default)]
+struct DeclaratorInfo {
+    /// The identifier declared, if any.
+    name: Option<Symbol>,
+    /// The derivation applied directly to the declared entity, if any.
+    ///
+    /// In `int *f(void)`, this is [`Derivation::Function`], as `f` is a function (returning a
+    /// pointer).
+    first_derivation: Option<Derivation>,
+    /// The names of the declared function's parameters, if `first_derivation` is
+    /// [`Derivation::Function`].
+    params: Vec<Symbol>,
+}
+
+impl DeclaratorInfo {
+    fn derive(&mut self, derivation: Derivation) {
+        self.first_derivation.get_or_insert(derivation);
+    }
+}
+
+impl Parser<'_, '_, '_> {
+    /// Parses a declaration, or a function definition if `allow_fn_def` is set.
+    ///
+    /// The current token must be one for which [`Self::is_decl_start()`] returns `true`.
+    pub(crate) fn parse_decl(&mut self, allow_fn_def: bool) -> DResult<()> {
+        if self.at_keyword(Keyword::StaticAssert)? {
+            return self.parse_static_assert();
+        }
+
+        let checkpoint = self.builder.checkpoint();
+        let specs = self.parse_decl_specifiers()?;
+
+        if self.eat_punct(PunctKind::Semi)?.is_none() {
+            let mut first = true;
+
+            loop {
+                let declarator_checkpoint = self.builder.checkpoint();
+                let info = match self.parse_declarator(DeclaratorKind::Concrete)? {
+                    Some(info) => info,
+                    None => {
+                        self.skip_to_sync()?;
+                        break;
+                    }
+                };
+
+                if first
+                    && allow_fn_def
+                    && info.first_derivation == Some(Derivation::Function)
+                    && self.at_punct(PunctKind::LCurly)?
+                {
+                    self.builder
+                        .start_node_at(checkpoint, NodeKind::FunctionDef);
+                    if let Some(name) = info.name {
+                        self.declare(name, false);
+                    }
+
+                    // Parameters are visible in (and may be shadowed by) the function body.
+                    self.push_scope();
+                    for &param in &info.params {
+                        self.declare(param, false);
+                    }
+                    self.parse_block_stmt()?;
+                    self.pop_scope();
+
+                    self.builder.finish_node();
+                    return Ok(());
+                }
+                first = false;
+
+                self.builder
+                    .start_node_at(declarator_checkpoint, NodeKind::InitDeclarator);
+                if let Some(name) = info.name {
+                    self.declare(name, specs.is_typedef);
+                }
+                if self.eat_punct(PunctKind::Eq)?.is_some() {
+                    self.parse_initializer()?;
+                }
+                self.builder.finish_node();
+
+                if self.eat_punct(PunctKind::Comma)?.is_none() {
+                    self.expect_punct(PunctKind::Semi)?;
+                    break;
+                }
+            }
+        }
+
+        self.builder.start_node_at(checkpoint, NodeKind::PlainDecl);
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Returns `true` if `tok` can start a declaration.
+    pub(crate) fn is_decl_start(&mut self, tok: Token) -> DResult<bool> {
+        let kw = match tok.data {
+            TokenKind::Keyword(kw) => kw,
+            _ => return self.is_type_name_start(tok),
+        };
+
+        Ok(is_storage_specifier(kw)
+            || is_function_specifier(kw)
+            || matches!(kw, Keyword::Alignas | Keyword::StaticAssert)
+            || self.is_type_name_start(tok)?)
+    }
+
+    /// Returns `true` if `tok` can start a type name (or a specifier-qualifier list).
+    pub(crate) fn is_type_name_start(&mut self, tok: Token) -> DResult<bool> {
+        Ok(match tok.data {
+            TokenKind::Keyword(kw) => {
+                is_plain_type_specifier(kw)
+                    || is_type_qualifier(kw)
+                    || matches!(kw, Keyword::Struct | Keyword::Union | Keyword::Enum)
+            }
+            _ => self.is_typedef_tok(tok),
+        })
+    }
+
+    /// Parses a static assertion declaration (`_Static_assert(expr, "message");`).
+    fn parse_static_assert(&mut self) -> DResult<()> {
+        self.builder.start_node(NodeKind::StaticAssertDecl);
+        self.bump()?;
+
+        if let Some(open) = self.expect_punct(PunctKind::LParen)? {
+            self.parse_conditional_expr()?;
+            if self.eat_punct(PunctKind::Comma)?.is_some() {
+                let tok = self.peek()?;
+                if matches!(tok.data, TokenKind::Plain(lex::TokenKind::Str(_))) {
+                    self.parse_primary_expr()?;
+                } else {
+                    self.reporter()
+                        .error(tok.range, "expected a string literal")
+                        .emit()?;
+                }
+            }
+            self.expect_closing(PunctKind::RParen, open)?;
+        }
+
+        self.expect_punct(PunctKind::Semi)?;
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Parses a (nonempty) list of declaration specifiers.
+    fn parse_decl_specifiers(&mut self) -> DResult<DeclSpecs> {
+        let mut specs = DeclSpecs::default();
+        let mut seen_type = false;
+
+        loop {
+            let tok = self.peek()?;
+            match tok.data {
+                TokenKind::Keyword(kw) if is_storage_specifier(kw) => {
+                    specs.is_typedef |= kw == Keyword::Typedef;
+                    self.bump_node(NodeKind::StorageSpecifier)?;
+                }
+                TokenKind::Keyword(kw) if is_function_specifier(kw) => {
+                    self.bump_node(NodeKind::FunctionSpecifier)?;
+                }
+                TokenKind::Keyword(Keyword::Alignas) => self.parse_alignment_specifier()?,
+                _ => {
+                    if !self.parse_specifier_or_qualifier(&mut seen_type)? {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(specs)
+    }
+
+    /// Parses a specifier-qualifier list, as found in type names and member declarations.
+    fn parse_specifier_qualifier_list(&mut self) -> DResult<()> {
+        self.builder.start_node(NodeKind::SpecifierQualifierList);
+
+        let mut seen_type = false;
+        loop {
+            if self.at_keyword(Keyword::Alignas)? {
+                self.parse_alignment_specifier()?;
+            } else if !self.parse_specifier_or_qualifier(&mut seen_type)? {
+                break;
+            }
+        }
+
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Parses a single type specifier or qualifier, returning `false` if the current token does
+    /// not start one.
+    ///
+    /// `seen_type` tracks whether a type specifier has already been parsed, in which case an
+    /// identifier is never interpreted as a typedef name: in `int T;`, `T` is declared even if it
+    /// was previously a typedef.
+    fn parse_specifier_or_qualifier(&mut self, seen_type: &mut bool) -> DResult<bool> {
+        let tok = self.peek()?;
+        let kw = match tok.data {
+            TokenKind::Keyword(kw) => kw,
+            _ if !*seen_type && self.is_typedef_tok(tok) => {
+                *seen_type = true;
+                self.bump_node(NodeKind::TypedefName)?;
+                return Ok(true);
+            }
+            _ => return Ok(false),
+        };
+
+        match kw {
+            Keyword::Atomic if is_punct(self.peek_nth(1)?, PunctKind::LParen) => {
+                *seen_type = true;
+                self.builder.start_node(NodeKind::AtomicTypeSpecifier);
+                self.bump()?;
+                self.parse_paren_type_name()?;
+                self.builder.finish_node();
+            }
+            _ if is_type_qualifier(kw) => {
+                self.bump_node(NodeKind::TypeQualifier)?;
+            }
+            _ if is_plain_type_specifier(kw) => {
+                *seen_type = true;
+                self.bump_node(NodeKind::PlainTypeSpecifier)?;
+            }
+            Keyword::Struct => {
+                *seen_type = true;
+                self.parse_struct_specifier(NodeKind::StructSpecifier)?;
+            }
+            Keyword::Union => {
+                *seen_type = true;
+                self.parse_struct_specifier(NodeKind::UnionSpecifier)?;
+            }
+            Keyword::Enum => {
+                *seen_type = true;
+                self.parse_enum_specifier()?;
+            }
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Parses an alignment specifier (`_Alignas(type)` or `_Alignas(expr)`).
+    fn parse_alignment_specifier(&mut self) -> DResult<()> {
+        self.builder.start_node(NodeKind::AlignmentSpecifier);
+        self.bump()?;
+
+        if let Some(open) = self.expect_punct(PunctKind::LParen)? {
+            let tok = self.peek()?;
+            if self.is_type_name_start(tok)? {
+                self.nested(Self::parse_type_name)?;
+            } else {
+                self.nested(Self::parse_conditional_expr)?;
+            }
+            self.expect_closing(PunctKind::RParen, open)?;
+        }
+
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Parses a parenthesized type name, as in `_Atomic(int)` or `_Alignof(int)`.
+    pub(crate) fn parse_paren_type_name(&mut self) -> DResult<()> {
+        if let Some(open) = self.expect_punct(PunctKind::LParen)? {
+            let tok = self.peek()?;
+            if self.is_type_name_start(tok)? {
+                self.nested(Self::parse_type_name)?;
+            } else {
+                self.reporter().error(tok.range, "expected a type").emit()?;
+            }
+            self.expect_closing(PunctKind::RParen, open)?;
+        }
+        Ok(())
+    }
+
+    /// Parses a struct or union specifier, creating a node of kind `kind`.
+    fn parse_struct_specifier(&mut self, kind: NodeKind) -> DResult<()> {
+        self.builder.start_node(kind);
+        self.bump()?;
+
+        let has_name = self.eat_ident()?;
+        if self.at_punct(PunctKind::LCurly)? {
+            self.nested(Self::parse_struct_decl_list)?;
+        } else if !has_name {
+            let tok = self.peek()?;
+            self.reporter()
+                .error(tok.range, "expected an identifier or '{'")
+                .emit()?;
+        }
+
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Parses the braced list of member declarations in a struct or union specifier.
+    fn parse_struct_decl_list(&mut self) -> DResult<()> {
+        self.builder.start_node(NodeKind::StructDeclList);
+        let open = self.bump()?;
+
+        loop {
+            let tok = self.peek()?;
+            match tok.data {
+                TokenKind::Plain(lex::TokenKind::Eof)
+                | TokenKind::Plain(lex::TokenKind::Punct(PunctKind::RCurly)) => break,
+                TokenKind::Keyword(Keyword::StaticAssert) => self.parse_static_assert()?,
+                _ if self.is_type_name_start(tok)? => self.parse_struct_field_decl()?,
+                _ => {
+                    self.reporter()
+                        .error(tok.range, "expected a member declaration")
+                        .emit()?;
+                    self.skip_to_sync()?;
+                }
+            }
+        }
+
+        self.expect_closing(PunctKind::RCurly, open)?;
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Parses a member declaration, such as `int x, y : 3;`.
+    fn parse_struct_field_decl(&mut self) -> DResult<()> {
+        self.builder.start_node(NodeKind::StructFieldDecl);
+        self.parse_specifier_qualifier_list()?;
+
+        if self.eat_punct(PunctKind::Semi)?.is_none() {
+            loop {
+                let checkpoint = self.builder.checkpoint();
+
+                if !self.at_punct(PunctKind::Colon)?
+                    && self.parse_declarator(DeclaratorKind::Concrete)?.is_none()
+                {
+                    self.skip_to_sync()?;
+                    break;
+                }
+
+                if self.at_punct(PunctKind::Colon)? {
+                    self.builder
+                        .start_node_at(checkpoint, NodeKind::BitfieldDeclarator);
+                    self.bump()?;
+                    self.parse_conditional_expr()?;
+                    self.builder.finish_node();
+                }
+
+                if self.eat_punct(PunctKind::Comma)?.is_none() {
+                    self.expect_punct(PunctKind::Semi)?;
+                    break;
+                }
+            }
+        }
+
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Parses an enum specifier.
+    fn parse_enum_specifier(&mut self) -> DResult<()> {
+        self.builder.start_node(NodeKind::EnumSpecifier);
+        self.bump()?;
+
+        let has_name = self.eat_ident()?;
+        if self.at_punct(PunctKind::LCurly)? {
+            self.parse_enumerator_list()?;
+        } else if !has_name {
+            let tok = self.peek()?;
+            self.reporter()
+                .error(tok.range, "expected an identifier or '{'")
+                .emit()?;
+        }
+
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Parses the braced list of enumerators in an enum specifier.
+    fn parse_enumerator_list(&mut self) -> DResult<()> {
+        self.builder.start_node(NodeKind::EnumeratorList);
+        let open = self.bump()?;
+
+        while !self.at_punct(PunctKind::RCurly)? {
+            let tok = self.peek()?;
+            let name = match tok.data {
+                TokenKind::Plain(lex::TokenKind::Ident(name)) => name,
+                _ => {
+                    self.reporter()
+                        .error(tok.range, "expected an identifier")
+                        .emit()?;
+                    break;
+                }
+            };
+
+            self.builder.start_node(NodeKind::Enumerator);
+            self.bump()?;
+            if self.eat_punct(PunctKind::Eq)?.is_some() {
+                self.parse_conditional_expr()?;
+            }
+            self.builder.finish_node();
+
+            // Enumeration constants are visible immediately after their declaration.
+            self.declare(name, false);
+
+            if self.eat_punct(PunctKind::Comma)?.is_none() {
+                break;
+            }
+        }
+
+        self.expect_closing(PunctKind::RCurly, open)?;
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Parses a declarator of kind `kind`.
+    ///
+    /// Returns `None` if a concrete declarator was required but could not be parsed, in which case
+    /// an error will already have been reported. Abstract declarators may be empty, in which case
+    /// nothing is consumed.
+    fn parse_declarator(&mut self, kind: DeclaratorKind) -> DResult<Option<DeclaratorInfo>> {
+        if self.at_punct(PunctKind::Star)? {
+            self.builder.start_node(NodeKind::PtrDeclarator);
+            self.bump()?;
+            self.parse_type_qualifier_list()?;
+            let inner = self.nested(|this| this.parse_declarator(kind))?;
+            self.builder.finish_node();
+
+            return Ok(inner.map(|mut info| {
+                info.derive(Derivation::Ptr);
+                info
+            }));
+        }
+
+        let mut checkpoint = self.builder.checkpoint();
+        let tok = self.peek()?;
+
+        let mut info = match tok.data {
+            TokenKind::Plain(lex::TokenKind::Ident(name)) if kind != DeclaratorKind::Abstract => {
+                // Any typedef name in this position has already been parsed as a type specifier
+                // if it could be one, so this must be the declared identifier.
+                self.bump_node(NodeKind::IdentDeclarator)?;
+                DeclaratorInfo {
+                    name: Some(name),
+                    ..Default::default()
+                }
+            }
+
+            TokenKind::Plain(lex::TokenKind::Punct(PunctKind::LParen))
+                if self.is_paren_declarator(kind)? =>
+            {
+                self.builder.start_node(NodeKind::ParenDeclarator);
+                let open = self.bump()?;
+                let inner = self.nested(|this| this.parse_declarator(kind))?;
+                self.expect_closing(PunctKind::RParen, open)?;
+                self.builder.finish_node();
+
+                match inner {
+                    Some(inner) => inner,
+                    None => return Ok(None),
+                }
+            }
+
+            _ if kind == DeclaratorKind::Concrete => {
+                self.reporter()
+                    .error(tok.range, "expected an identifier or '('")
+                    .emit()?;
+                return Ok(None);
+            }
+
+            _ => DeclaratorInfo::default(),
+        };
+
+        loop {
+            let tok = self.peek()?;
+            if is_punct(tok, PunctKind::LSquare) {
+                self.builder
+                    .start_node_at(checkpoint, NodeKind::ArrayDeclarator);
+                self.nested(Self::parse_array_declarator_suffix)?;
+                info.derive(Derivation::Array);
+            } else if is_punct(tok, PunctKind::LParen) {
+                self.builder
+                    .start_node_at(checkpoint, NodeKind::FunctionDeclarator);
+                let params = self.nested(Self::parse_param_list)?;
+                if info.first_derivation.is_none() {
+                    info.params = params;
+                }
+                info.derive(Derivation::Function);
+            } else {
+                break;
+            }
+
+            checkpoint = self.builder.finish_node();
+        }
+
+        Ok(Some(info))
+    }
+
+    /// Returns `true` if the current `(` token starts a parenthesized declarator of kind `kind`
+    /// rather than a parameter list.
+    fn is_paren_declarator(&mut self, kind: DeclaratorKind) -> DResult<bool> {
+        if kind == DeclaratorKind::Concrete {
+            return Ok(true);
+        }
+
+        let next = self.peek_nth(1)?;
+        Ok(match next.data {
+            TokenKind::Plain(lex::TokenKind::Punct(punct)) => matches!(
+                punct,
+                PunctKind::Star | PunctKind::LParen | PunctKind::LSquare
+            ),
+            TokenKind::Plain(lex::TokenKind::Ident(name)) => {
+                kind == DeclaratorKind::Either && !self.is_typedef_name(name)
+            }
+            _ => false,
+        })
+    }
+
+    /// Parses the bracketed part of an array declarator, such as `[static const 5]` or `[*]`.
+    fn parse_array_declarator_suffix(&mut self) -> DResult<()> {
+        let open = self.bump()?;
+
+        if self.at_keyword(Keyword::Static)? {
+            self.bump()?;
+        }
+        self.parse_type_qualifier_list()?;
+        if self.at_keyword(Keyword::Static)? {
+            self.bump()?;
+        }
+
+        if self.at_punct(PunctKind::Star)? && is_punct(self.peek_nth(1)?, PunctKind::RSquare) {
+            self.bump()?;
+        } else if !self.at_punct(PunctKind::RSquare)? {
+            self.parse_assignment_expr()?;
+        }
+
+        self.expect_closing(PunctKind::RSquare, open)
+    }
+
+    /// Parses a (possibly empty) list of type qualifiers following a `*`.
+    fn parse_type_qualifier_list(&mut self) -> DResult<()> {
+        let checkpoint = self.builder.checkpoint();
+        let mut empty = true;
+
+        loop {
+            let tok = self.peek()?;
+            match tok.data {
+                TokenKind::Keyword(kw) if is_type_qualifier(kw) => {
+                    self.bump_node(NodeKind::TypeQualifier)?;
+                    empty = false;
+                }
+                _ => break,
+            }
+        }
+
+        if !empty {
+            self.builder
+                .start_node_at(checkpoint, NodeKind::TypeQualifierList);
+            self.builder.finish_node();
+        }
+
+        Ok(())
+    }
+
+    /// Parses the parenthesized parameter list of a function declarator, returning the names of
+    /// the parameters.
+    fn parse_param_list(&mut self) -> DResult<Vec<Symbol>> {
+        self.builder.start_node(NodeKind::ParamList);
+        let open = self.bump()?;
+        let mut params = Vec::new();
+
+        if !self.at_punct(PunctKind::RParen)? {
+            loop {
+                if self.eat_punct(PunctKind::Ellipsis)?.is_some() {
+                    break;
+                }
+
+                let tok = self.peek()?;
+                if !self.is_decl_start(tok)? {
+                    self.reporter()
+                        .error(tok.range, "expected a parameter declaration")
+                        .emit()?;
+                    break;
+                }
+
+                self.builder.start_node(NodeKind::ParamDecl);
+                self.parse_decl_specifiers()?;
+                if let Some(DeclaratorInfo {
+                    name: Some(name), ..
+                }) = self.parse_declarator(DeclaratorKind::Either)?
+                {
+                    params.push(name);
+                }
+                self.builder.finish_node();
+
+                if self.eat_punct(PunctKind::Comma)?.is_none() {
+                    break;
+                }
+            }
+        }
+
+        self.expect_closing(PunctKind::RParen, open)?;
+        self.builder.finish_node();
+        Ok(params)
+    }
+
+    /// Parses a type name, such as `const char *`.
+    ///
+    /// The current token must be one for which [`Self::is_type_name_start()`] returns `true`.
+    pub(crate) fn parse_type_name(&mut self) -> DResult<()> {
+        self.builder.start_node(NodeKind::TypeName);
+        self.parse_specifier_qualifier_list()?;
+        self.parse_declarator(DeclaratorKind::Abstract)?;
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Parses an initializer, which is either an expression or a braced initializer list.
+    fn parse_initializer(&mut self) -> DResult<()> {
+        if self.at_punct(PunctKind::LCurly)? {
+            self.nested(Self::parse_init_list)
+        } else {
+            self.parse_assignment_expr()
+        }
+    }
+
+    /// Parses a braced initializer list, such as `{ 1, .x = 2, [3] = { 4 } }`.
+    pub(crate) fn parse_init_list(&mut self) -> DResult<()> {
+        self.builder.start_node(NodeKind::StructInitList);
+        let open = self.bump()?;
+
+        while !self.at_punct(PunctKind::RCurly)? {
+            let tok = self.peek()?;
+            if is_punct(tok, PunctKind::Dot) || is_punct(tok, PunctKind::LSquare) {
+                self.parse_designator_list()?;
+            }
+
+            self.parse_initializer()?;
+
+            if self.eat_punct(PunctKind::Comma)?.is_none() {
+                break;
+            }
+        }
+
+        self.expect_closing(PunctKind::RCurly, open)?;
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Parses a list of designators followed by `=`, such as `.a[2].b =`.
+    fn parse_designator_list(&mut self) -> DResult<()> {
+        self.builder.start_node(NodeKind::DesignatorList);
+
+        loop {
+            let tok = self.peek()?;
+            if is_punct(tok, PunctKind::Dot) {
+                self.builder.start_node(NodeKind::FieldDesignator);
+                self.bump()?;
+                self.expect_ident("expected a field name")?;
+                self.builder.finish_node();
+            } else if is_punct(tok, PunctKind::LSquare) {
+                self.builder.start_node(NodeKind::ArrayDesignator);
+                let open = self.bump()?;
+                self.parse_conditional_expr()?;
+                self.expect_closing(PunctKind::RSquare, open)?;
+                self.builder.finish_node();
+            } else {
+                break;
+            }
+        }
+
+        self.expect_punct(PunctKind::Eq)?;
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Consumes the current token if it is an identifier, returning whether it did.
+    fn eat_ident(&mut self) -> DResult<bool> {
+        if matches!(
+            self.peek()?.data,
+            TokenKind::Plain(lex::TokenKind::Ident(_))
+        ) {
+            self.bump()?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+fn is_storage_specifier(kw: Keyword) -> bool {
+    matches!(
+        kw,
+        Keyword::Typedef
+            | Keyword::Extern
+            | Keyword::Static
+            | Keyword::ThreadLocal
+            | Keyword::Auto
+            | Keyword::Register
+    )
+}
+
+fn is_function_specifier(kw: Keyword) -> bool {
+    matches!(kw, Keyword::Inline | Keyword::Noreturn)
+}
+
+fn is_plain_type_specifier(kw: Keyword) -> bool {
+    matches!(
+        kw,
+        Keyword::Void
+            | Keyword::Char
+            | Keyword::Short
+            | Keyword::Int
+            | Keyword::Long
+            | Keyword::Float
+            | Keyword::Double
+            | Keyword::Signed
+            | Keyword::Unsigned
+            | Keyword::Bool
+            | Keyword::Complex
+            | Keyword::Imaginary
+    )
+}
+
+fn is_type_qualifier(kw: Keyword) -> bool {
+    matches!(
+        kw,
+        Keyword::Const | Keyword::Restrict | Keyword::Volatile | Keyword::Atomic
+    )
+}
@@ -0,0 +1,354 @@
+//! Expressions (§6.5).
+
+use lex::PunctKind;
+use source::DResult;
+use syntax::{Checkpoint, Keyword, NodeKind, Token, TokenKind};
+
+use crate::Parser;
+
+impl Parser<'_, '_, '_> {
+    /// Parses an expression, including comma operators.
+    pub(crate) fn parse_expr(&mut self) -> DResult<()> {
+        let mut checkpoint = self.builder.checkpoint();
+        self.parse_assignment_expr()?;
+
+        while self.at_punct(PunctKind::Comma)? {
+            self.builder.start_node_at(checkpoint, NodeKind::BinExpr);
+            self.bump()?;
+            self.parse_assignment_expr()?;
+            checkpoint = self.builder.finish_node();
+        }
+
+        Ok(())
+    }
+
+    /// Parses an assignment expression, which is the operand of a comma operator.
+    pub(crate) fn parse_assignment_expr(&mut self) -> DResult<()> {
+        let checkpoint = self.builder.checkpoint();
+        self.parse_conditional_expr()?;
+
+        if let TokenKind::Plain(lex::TokenKind::Punct(punct)) = self.peek()?.data {
+            if is_assignment_op(punct) {
+                self.builder
+                    .start_node_at(checkpoint, NodeKind::AssignmentExpr);
+                self.bump()?;
+                self.nested(Self::parse_assignment_expr)?;
+                self.builder.finish_node();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parses a conditional expression, which is also the grammar used for constant expressions.
+    pub(crate) fn parse_conditional_expr(&mut self) -> DResult<()> {
+        let checkpoint = self.builder.checkpoint();
+        self.parse_binary_expr(0)?;
+
+        if self.at_punct(PunctKind::Question)? {
+            self.builder
+                .start_node_at(checkpoint, NodeKind::ConditionalExpr);
+            self.bump()?;
+            self.nested(Self::parse_expr)?;
+            self.expect_punct(PunctKind::Colon)?;
+            self.nested(Self::parse_conditional_expr)?;
+            self.builder.finish_node();
+        }
+
+        Ok(())
+    }
+
+    /// Parses a chain of binary operators whose precedence is at least `min_prec`.
+    fn parse_binary_expr(&mut self, min_prec: u8) -> DResult<()> {
+        let mut checkpoint = self.builder.checkpoint();
+        self.parse_cast_expr()?;
+
+        loop {
+            let prec = match binary_prec(self.peek()?) {
+                Some(prec) if prec >= min_prec => prec,
+                _ => break,
+            };
+
+            self.builder.start_node_at(checkpoint, NodeKind::BinExpr);
+            self.bump()?;
+            // All binary operators are left-associative.
+            self.parse_binary_expr(prec + 1)?;
+            checkpoint = self.builder.finish_node();
+        }
+
+        Ok(())
+    }
+
+    /// Parses a cast expression or compound literal, or a unary expression if the current token
+    /// does not start a parenthesized type name.
+    fn parse_cast_expr(&mut self) -> DResult<()> {
+        if !self.at_paren_type_name()? {
+            return self.parse_unary_expr();
+        }
+
+        let checkpoint = self.builder.checkpoint();
+        let open = self.bump()?;
+        self.nested(Self::parse_type_name)?;
+        self.expect_closing(PunctKind::RParen, open)?;
+
+        if self.at_punct(PunctKind::LCurly)? {
+            self.nested(Self::parse_init_list)?;
+            self.builder
+                .start_node_at(checkpoint, NodeKind::CompoundLiteralExpr);
+            let checkpoint = self.builder.finish_node();
+            self.parse_postfix_suffixes(checkpoint)
+        } else {
+            self.nested(Self::parse_cast_expr)?;
+            self.builder.start_node_at(checkpoint, NodeKind::CastExpr);
+            self.builder.finish_node();
+            Ok(())
+        }
+    }
+
+    fn parse_unary_expr(&mut self) -> DResult<()> {
+        let tok = self.peek()?;
+
+        match tok.data {
+            TokenKind::Plain(lex::TokenKind::Punct(PunctKind::PlusPlus))
+            | TokenKind::Plain(lex::TokenKind::Punct(PunctKind::MinusMinus)) => {
+                self.builder.start_node(NodeKind::PreIncrExpr);
+                self.bump()?;
+                self.nested(Self::parse_unary_expr)?;
+                self.builder.finish_node();
+            }
+
+            TokenKind::Plain(lex::TokenKind::Punct(punct)) if is_unary_op(punct) => {
+                self.builder.start_node(NodeKind::UnaryExpr);
+                self.bump()?;
+                self.nested(Self::parse_cast_expr)?;
+                self.builder.finish_node();
+            }
+
+            TokenKind::Keyword(Keyword::Sizeof) => {
+                let checkpoint = self.builder.checkpoint();
+                self.bump()?;
+
+                let kind = if self.at_paren_type_name()? {
+                    // This could still be the start of a compound literal.
+                    let operand_checkpoint = self.builder.checkpoint();
+                    let open = self.bump()?;
+                    self.nested(Self::parse_type_name)?;
+                    self.expect_closing(PunctKind::RParen, open)?;
+
+                    if self.at_punct(PunctKind::LCurly)? {
+                        self.nested(Self::parse_init_list)?;
+                        self.builder
+                            .start_node_at(operand_checkpoint, NodeKind::CompoundLiteralExpr);
+                        let operand_checkpoint = self.builder.finish_node();
+                        self.parse_postfix_suffixes(operand_checkpoint)?;
+                        NodeKind::SizeofValExpr
+                    } else {
+                        NodeKind::SizeofTypeExpr
+                    }
+                } else {
+                    self.nested(Self::parse_unary_expr)?;
+                    NodeKind::SizeofValExpr
+                };
+
+                self.builder.start_node_at(checkpoint, kind);
+                self.builder.finish_node();
+            }
+
+            TokenKind::Keyword(Keyword::Alignof) => {
+                self.builder.start_node(NodeKind::AlignofExpr);
+                self.bump()?;
+                self.parse_paren_type_name()?;
+                self.builder.finish_node();
+            }
+
+            _ => {
+                let checkpoint = self.builder.checkpoint();
+                self.parse_primary_expr()?;
+                self.parse_postfix_suffixes(checkpoint)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parses any postfix operators applied to the expression beginning at `checkpoint`.
+    fn parse_postfix_suffixes(&mut self, mut checkpoint: Checkpoint) -> DResult<()> {
+        while let TokenKind::Plain(lex::TokenKind::Punct(punct)) = self.peek()?.data {
+            match punct {
+                PunctKind::LSquare => {
+                    self.builder.start_node_at(checkpoint, NodeKind::IndexExpr);
+                    let open = self.bump()?;
+                    self.nested(Self::parse_expr)?;
+                    self.expect_closing(PunctKind::RSquare, open)?;
+                }
+                PunctKind::LParen => {
+                    self.builder.start_node_at(checkpoint, NodeKind::CallExpr);
+                    self.nested(Self::parse_arg_list)?;
+                }
+                PunctKind::Dot | PunctKind::Arrow => {
+                    let kind = if punct == PunctKind::Dot {
+                        NodeKind::MemberExpr
+                    } else {
+                        NodeKind::DerefMemberExpr
+                    };
+                    self.builder.start_node_at(checkpoint, kind);
+                    self.bump()?;
+                    self.expect_ident("expected a field name")?;
+                }
+                PunctKind::PlusPlus | PunctKind::MinusMinus => {
+                    self.builder
+                        .start_node_at(checkpoint, NodeKind::PostIncrExpr);
+                    self.bump()?;
+                }
+                _ => break,
+            }
+
+            checkpoint = self.builder.finish_node();
+        }
+
+        Ok(())
+    }
+
+    /// Parses the parenthesized argument list of a function call.
+    fn parse_arg_list(&mut self) -> DResult<()> {
+        self.builder.start_node(NodeKind::ArgList);
+        let open = self.bump()?;
+
+        if !self.at_punct(PunctKind::RParen)? {
+            loop {
+                self.parse_assignment_expr()?;
+                if self.eat_punct(PunctKind::Comma)?.is_none() {
+                    break;
+                }
+            }
+        }
+
+        self.expect_closing(PunctKind::RParen, open)?;
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Parses a primary expression: an identifier, a literal or a parenthesized expression.
+    ///
+    /// If the current token does not start an expression, an error is reported and nothing is
+    /// consumed.
+    pub(crate) fn parse_primary_expr(&mut self) -> DResult<()> {
+        let tok = self.peek()?;
+
+        match tok.data {
+            TokenKind::Plain(lex::TokenKind::Ident(_)) if !self.is_typedef_tok(tok) => {
+                self.bump_node(NodeKind::IdentExpr)?;
+            }
+            TokenKind::Plain(lex::TokenKind::Number(_)) => {
+                self.bump_node(NodeKind::NumberLiteralExpr)?;
+            }
+            TokenKind::Plain(lex::TokenKind::Char(_)) => {
+                self.bump_node(NodeKind::CharLiteralExpr)?;
+            }
+            TokenKind::Plain(lex::TokenKind::Str(_)) => {
+                // Adjacent string literals are concatenated (translation phase 6).
+                self.builder.start_node(NodeKind::StrLiteralExpr);
+                while matches!(self.peek()?.data, TokenKind::Plain(lex::TokenKind::Str(_))) {
+                    self.bump()?;
+                }
+                self.builder.finish_node();
+            }
+            TokenKind::Plain(lex::TokenKind::Punct(PunctKind::LParen)) => {
+                self.builder.start_node(NodeKind::ParenExpr);
+                let open = self.bump()?;
+                self.nested(Self::parse_expr)?;
+                self.expect_closing(PunctKind::RParen, open)?;
+                self.builder.finish_node();
+            }
+            _ => {
+                self.reporter()
+                    .error(tok.range, "expected an expression")
+                    .emit()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns `true` if `tok` can start an expression.
+    pub(crate) fn is_expr_start(&self, tok: Token) -> bool {
+        match tok.data {
+            TokenKind::Plain(lex::TokenKind::Ident(_)) => !self.is_typedef_tok(tok),
+            TokenKind::Plain(lex::TokenKind::Number(_))
+            | TokenKind::Plain(lex::TokenKind::Char(_))
+            | TokenKind::Plain(lex::TokenKind::Str(_)) => true,
+            TokenKind::Plain(lex::TokenKind::Punct(punct)) => {
+                is_unary_op(punct)
+                    || matches!(
+                        punct,
+                        PunctKind::LParen | PunctKind::PlusPlus | PunctKind::MinusMinus
+                    )
+            }
+            TokenKind::Keyword(kw) => matches!(kw, Keyword::Sizeof | Keyword::Alignof),
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the current token is a `(` starting a parenthesized type name.
+    fn at_paren_type_name(&mut self) -> DResult<bool> {
+        if !self.at_punct(PunctKind::LParen)? {
+            return Ok(false);
+        }
+        let next = self.peek_nth(1)?;
+        self.is_type_name_start(next)
+    }
+}
+
+/// Returns the precedence of the binary operator `tok`, or `None` if it is not a binary operator
+/// (excluding assignment and comma operators).
+fn binary_prec(tok: Token) -> Option<u8> {
+    let punct = match tok.data {
+        TokenKind::Plain(lex::TokenKind::Punct(punct)) => punct,
+        _ => return None,
+    };
+
+    let prec = match punct {
+        PunctKind::PipePipe => 0,
+        PunctKind::AmpAmp => 1,
+        PunctKind::Pipe => 2,
+        PunctKind::Caret => 3,
+        PunctKind::Amp => 4,
+        PunctKind::EqEq | PunctKind::BangEq => 5,
+        PunctKind::Less | PunctKind::LessEq | PunctKind::Greater | PunctKind::GreaterEq => 6,
+        PunctKind::LessLess | PunctKind::GreaterGreater => 7,
+        PunctKind::Plus | PunctKind::Minus => 8,
+        PunctKind::Star | PunctKind::Slash | PunctKind::Perc => 9,
+        _ => return None,
+    };
+
+    Some(prec)
+}
+
+fn is_unary_op(punct: PunctKind) -> bool {
+    matches!(
+        punct,
+        PunctKind::Amp
+            | PunctKind::Star
+            | PunctKind::Plus
+            | PunctKind::Minus
+            | PunctKind::Tilde
+            | PunctKind::Bang
+    )
+}
+
+fn is_assignment_op(punct: PunctKind) -> bool {
+    matches!(
+        punct,
+        PunctKind::Eq
+            | PunctKind::StarEq
+            | PunctKind::SlashEq
+            | PunctKind::PercEq
+            | PunctKind::PlusEq
+            | PunctKind::MinusEq
+            | PunctKind::LessLessEq
+            | PunctKind::GreaterGreaterEq
+            | PunctKind::AmpEq
+            | PunctKind::PipeEq
+            | PunctKind::CaretEq
+    )
+}
@@ -0,0 +1,289 @@
+//! A recursive-descent parser for C, building [`syntax`] trees from a stream of preprocessed
+//! tokens.
+
+#![warn(rust_2018_idioms)]
+
+use rustc_hash::FxHashMap;
+
+use lex::{Lex, LexCtx, Lookahead, PunctKind, Symbol};
+use source::diag::{Message, RawSubDiagnostic};
+use source::{DResult, DiagReporter};
+use syntax::{Keyword, Node, NodeKind, Token, TokenKind, TreeBuilder};
+
+mod decl;
+mod expr;
+mod stmt;
+
+#[cfg(test)]
+mod tests;
+
+/// Parses an entire translation unit from the tokens produced by `lexer` (usually a
+/// [`Preprocessor`](../pp/struct.Preprocessor.html)).
+///
+/// Syntax errors are reported through `ctx` and recovered from, so that a tree is produced for
+/// any input; tokens skipped during recovery are placed in [`NodeKind::Error`] nodes. The root of
+/// the returned tree is a [`NodeKind::TranslationUnit`] node, ending with the `Eof` token.
+pub fn parse_translation_unit(ctx: &mut LexCtx<'_, '_>, lexer: &mut dyn Lex) -> DResult<Node> {
+    let mut parser = Parser::new(ctx, lexer);
+    parser.parse_translation_unit()?;
+    Ok(parser.builder.finish())
+}
+
+/// State shared by the different parts of the parser.
+struct Parser<'a, 'b, 'h> {
+    ctx: &'a mut LexCtx<'b, 'h>,
+    lexer: Lookahead<&'a mut dyn Lex>,
+    builder: TreeBuilder,
+    /// The ordinary identifiers declared in each enclosing scope, innermost last, along with
+    /// whether they name typedefs.
+    scopes: Vec<FxHashMap<Symbol, bool>>,
+    /// The number of nested constructs currently being parsed, limited by
+    /// [`max_bracket_depth`](source::FrontendLimits::max_bracket_depth).
+    depth: usize,
+}
+
+impl<'a, 'b, 'h> Parser<'a, 'b, 'h> {
+    fn new(ctx: &'a mut LexCtx<'b, 'h>, lexer: &'a mut dyn Lex) -> Self {
+        Self {
+            ctx,
+            lexer: Lookahead::new(lexer),
+            builder: TreeBuilder::new(),
+            scopes: vec![FxHashMap::default()],
+            depth: 0,
+        }
+    }
+
+    fn parse_translation_unit(&mut self) -> DResult<()> {
+        self.builder.start_node(NodeKind::TranslationUnit);
+
+        loop {
+            let tok = self.peek()?;
+            match tok.data {
+                TokenKind::Plain(lex::TokenKind::Eof) => break,
+                TokenKind::Plain(lex::TokenKind::Punct(PunctKind::Semi)) => {
+                    self.bump()?;
+                }
+                TokenKind::Plain(lex::TokenKind::Punct(PunctKind::RCurly)) => {
+                    self.reporter()
+                        .error(tok.range, "extraneous closing brace")
+                        .emit()?;
+                    self.bump()?;
+                }
+                _ if self.is_decl_start(tok)? => self.parse_decl(true)?,
+                _ => {
+                    self.reporter()
+                        .error(tok.range, "expected a declaration")
+                        .emit()?;
+                    self.skip_to_sync()?;
+                }
+            }
+        }
+
+        self.bump()?;
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Returns the token `n` tokens ahead of the current one without consuming anything.
+    fn peek_nth(&mut self, n: usize) -> DResult<Token> {
+        let tok = self.lexer.peek_nth(self.ctx, n)?;
+        Ok(Token::new(
+            TokenKind::from_plain(tok.data, self.ctx.interner),
+            tok.range,
+        ))
+    }
+
+    /// Returns the current token without consuming it.
+    fn peek(&mut self) -> DResult<Token> {
+        self.peek_nth(0)
+    }
+
+    /// Consumes the current token, adding it to the tree.
+    fn bump(&mut self) -> DResult<Token> {
+        let tok = self.peek()?;
+        self.lexer.next(self.ctx)?;
+        self.builder.token(tok);
+        Ok(tok)
+    }
+
+    /// Consumes the current token as the sole child of a new node of kind `kind`.
+    fn bump_node(&mut self, kind: NodeKind) -> DResult<Token> {
+        self.builder.start_node(kind);
+        let tok = self.bump()?;
+        self.builder.finish_node();
+        Ok(tok)
+    }
+
+    fn at_punct(&mut self, punct: PunctKind) -> DResult<bool> {
+        Ok(is_punct(self.peek()?, punct))
+    }
+
+    fn at_keyword(&mut self, kw: Keyword) -> DResult<bool> {
+        Ok(self.peek()?.data == TokenKind::Keyword(kw))
+    }
+
+    /// Consumes the current token if it is the punctuator `punct`, returning it.
+    fn eat_punct(&mut self, punct: PunctKind) -> DResult<Option<Token>> {
+        if self.at_punct(punct)? {
+            self.bump().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Consumes the punctuator `punct`, reporting an error if the current token is something else.
+    fn expect_punct(&mut self, punct: PunctKind) -> DResult<Option<Token>> {
+        let tok = self.eat_punct(punct)?;
+        if tok.is_none() {
+            self.report_expected_punct(punct, None)?;
+        }
+        Ok(tok)
+    }
+
+    /// Consumes the closing delimiter `punct` matching the opening delimiter `open`, reporting an
+    /// error if the current token is something else.
+    fn expect_closing(&mut self, punct: PunctKind, open: Token) -> DResult<()> {
+        if self.eat_punct(punct)?.is_none() {
+            let open_punct = match open.data {
+                TokenKind::Plain(lex::TokenKind::Punct(open_punct)) => open_punct,
+                _ => unreachable!("opening delimiter is not a punctuator"),
+            };
+
+            let note = RawSubDiagnostic::new(
+                format!("to match this '{}'", open_punct.as_str()),
+                open.range.into(),
+            );
+            self.report_expected_punct(punct, Some(note))?;
+        }
+        Ok(())
+    }
+
+    /// Consumes an identifier, reporting `msg` as an error if the current token is something else.
+    fn expect_ident(&mut self, msg: &str) -> DResult<Option<Symbol>> {
+        let tok = self.peek()?;
+        match tok.data {
+            TokenKind::Plain(lex::TokenKind::Ident(ident)) => {
+                self.bump()?;
+                Ok(Some(ident))
+            }
+            _ => {
+                self.reporter().error(tok.range, msg).emit()?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Reports an error that `punct` was expected at the current token, attaching `note` if
+    /// provided.
+    fn report_expected_punct(
+        &mut self,
+        punct: PunctKind,
+        note: Option<RawSubDiagnostic>,
+    ) -> DResult<()> {
+        let pos = self.peek()?.range.start();
+        let delim = punct.as_str().chars().next().unwrap();
+
+        let mut reporter = self.reporter();
+        let mut builder = reporter.error_expected_delim(pos, delim);
+        if let Some(note) = note {
+            builder = builder.add_note(note);
+        }
+        builder.emit()
+    }
+
+    /// Skips tokens up to and including the next `;` (or up to the next unmatched `}`), placing
+    /// them in an [`NodeKind::Error`] node.
+    ///
+    /// Nothing is skipped if the current token is already a `}` or the end of the input.
+    fn skip_to_sync(&mut self) -> DResult<()> {
+        if self.at_punct(PunctKind::RCurly)? || is_eof(self.peek()?) {
+            return Ok(());
+        }
+
+        self.builder.start_node(NodeKind::Error);
+
+        let mut depth = 0usize;
+        loop {
+            let tok = self.peek()?;
+            match tok.data {
+                TokenKind::Plain(lex::TokenKind::Eof) => break,
+                TokenKind::Plain(lex::TokenKind::Punct(punct)) => match punct {
+                    PunctKind::LCurly => depth += 1,
+                    PunctKind::RCurly if depth == 0 => break,
+                    PunctKind::RCurly => depth -= 1,
+                    PunctKind::Semi if depth == 0 => {
+                        self.bump()?;
+                        break;
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+            self.bump()?;
+        }
+
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Parses a construct nested within the one currently being parsed with `f`, reporting a fatal
+    /// error at the current token if this would exceed the maximum nesting depth.
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> DResult<T>) -> DResult<T> {
+        let max = self.ctx.limits.max_bracket_depth;
+        if self.depth >= max {
+            let range = self.peek()?.range;
+            let msg =
+                Message::new("code nested too deeply (maximum depth is {max})").arg("max", max);
+            return Err(self.reporter().fatal(range, msg).emit().unwrap_err());
+        }
+
+        self.depth += 1;
+        let res = f(self);
+        self.depth -= 1;
+        res
+    }
+
+    fn push_scope(&mut self) {
+        self.scopes.push(FxHashMap::default());
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Declares the ordinary identifier `name` in the current scope, possibly shadowing an outer
+    /// declaration.
+    fn declare(&mut self, name: Symbol, is_typedef: bool) {
+        self.scopes.last_mut().unwrap().insert(name, is_typedef);
+    }
+
+    /// Returns `true` if `name` currently refers to a typedef.
+    fn is_typedef_name(&self, name: Symbol) -> bool {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// Returns `true` if `tok` is an identifier referring to a typedef.
+    fn is_typedef_tok(&self, tok: Token) -> bool {
+        match tok.data {
+            TokenKind::Plain(lex::TokenKind::Ident(ident)) => self.is_typedef_name(ident),
+            _ => false,
+        }
+    }
+
+    fn reporter(&mut self) -> DiagReporter<'_, 'h> {
+        self.ctx.reporter()
+    }
+}
+
+fn is_punct(tok: Token, punct: PunctKind) -> bool {
+    tok.data == TokenKind::Plain(lex::TokenKind::Punct(punct))
+}
+
+fn is_eof(tok: Token) -> bool {
+    tok.data == TokenKind::Plain(lex::TokenKind::Eof)
+}
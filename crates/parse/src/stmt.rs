@@ -0,0 +1,226 @@
+//! Statements (§6.8).
+
+use lex::PunctKind;
+use source::DResult;
+use syntax::{Keyword, NodeKind, TokenKind};
+
+use crate::{is_eof, is_punct, Parser};
+
+impl Parser<'_, '_, '_> {
+    /// Parses a statement.
+    ///
+    /// If the current token cannot start a statement, an error is reported and tokens are skipped
+    /// up to the next `;` or `}`.
+    fn parse_stmt(&mut self) -> DResult<()> {
+        let tok = self.peek()?;
+
+        match tok.data {
+            TokenKind::Keyword(Keyword::If) => self.parse_if_stmt(),
+            TokenKind::Keyword(Keyword::Switch) => self.parse_cond_stmt(NodeKind::SwitchStmt),
+            TokenKind::Keyword(Keyword::While) => self.parse_cond_stmt(NodeKind::WhileStmt),
+            TokenKind::Keyword(Keyword::Do) => self.parse_do_while_stmt(),
+            TokenKind::Keyword(Keyword::For) => self.parse_for_stmt(),
+
+            TokenKind::Keyword(Keyword::Goto) => {
+                self.builder.start_node(NodeKind::GotoStmt);
+                self.bump()?;
+                self.expect_ident("expected an identifier")?;
+                self.expect_punct(PunctKind::Semi)?;
+                self.builder.finish_node();
+                Ok(())
+            }
+            TokenKind::Keyword(Keyword::Continue) => {
+                self.parse_keyword_stmt(NodeKind::ContinueStmt)
+            }
+            TokenKind::Keyword(Keyword::Break) => self.parse_keyword_stmt(NodeKind::BreakStmt),
+            TokenKind::Keyword(Keyword::Return) => {
+                self.builder.start_node(NodeKind::ReturnStmt);
+                self.bump()?;
+                if !self.at_punct(PunctKind::Semi)? {
+                    self.parse_expr()?;
+                }
+                self.expect_punct(PunctKind::Semi)?;
+                self.builder.finish_node();
+                Ok(())
+            }
+
+            TokenKind::Keyword(Keyword::Case) => {
+                self.builder.start_node(NodeKind::CaseStmt);
+                self.bump()?;
+                self.parse_conditional_expr()?;
+                self.parse_labeled_stmt_body()
+            }
+            TokenKind::Keyword(Keyword::Default) => {
+                self.builder.start_node(NodeKind::DefaultCaseStmt);
+                self.bump()?;
+                self.parse_labeled_stmt_body()
+            }
+            TokenKind::Plain(lex::TokenKind::Ident(_))
+                if is_punct(self.peek_nth(1)?, PunctKind::Colon) =>
+            {
+                self.builder.start_node(NodeKind::LabeledStmt);
+                self.bump()?;
+                self.parse_labeled_stmt_body()
+            }
+
+            TokenKind::Plain(lex::TokenKind::Punct(PunctKind::LCurly)) => self.parse_block_stmt(),
+            TokenKind::Plain(lex::TokenKind::Punct(PunctKind::Semi)) => {
+                self.bump_node(NodeKind::ExprStmt)?;
+                Ok(())
+            }
+
+            _ if self.is_expr_start(tok) => {
+                self.builder.start_node(NodeKind::ExprStmt);
+                self.parse_expr()?;
+                self.expect_punct(PunctKind::Semi)?;
+                self.builder.finish_node();
+                Ok(())
+            }
+
+            _ => {
+                self.reporter()
+                    .error(tok.range, "expected a statement")
+                    .emit()?;
+                self.skip_to_sync()
+            }
+        }
+    }
+
+    /// Parses a compound statement (`{ ... }`), which introduces a new scope.
+    pub(crate) fn parse_block_stmt(&mut self) -> DResult<()> {
+        self.builder.start_node(NodeKind::BlockStmt);
+        let open = self.bump()?;
+        self.push_scope();
+
+        loop {
+            let tok = self.peek()?;
+            if is_eof(tok) || is_punct(tok, PunctKind::RCurly) {
+                break;
+            }
+
+            // Labels take precedence over typedef names, as they live in a separate namespace.
+            let is_label = matches!(tok.data, TokenKind::Plain(lex::TokenKind::Ident(_)))
+                && is_punct(self.peek_nth(1)?, PunctKind::Colon);
+
+            if !is_label && self.is_decl_start(tok)? {
+                self.parse_decl(false)?;
+            } else {
+                self.nested(Self::parse_stmt)?;
+            }
+        }
+
+        self.pop_scope();
+        self.expect_closing(PunctKind::RCurly, open)?;
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Parses the `:` and statement following a label, finishing the labeled statement node.
+    fn parse_labeled_stmt_body(&mut self) -> DResult<()> {
+        self.expect_punct(PunctKind::Colon)?;
+        self.nested(Self::parse_stmt)?;
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Parses a statement consisting only of a keyword and a `;`, such as `break;`.
+    fn parse_keyword_stmt(&mut self, kind: NodeKind) -> DResult<()> {
+        self.builder.start_node(kind);
+        self.bump()?;
+        self.expect_punct(PunctKind::Semi)?;
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    fn parse_if_stmt(&mut self) -> DResult<()> {
+        self.builder.start_node(NodeKind::IfStmt);
+        self.bump()?;
+        self.parse_paren_cond()?;
+        self.nested(Self::parse_stmt)?;
+
+        if self.at_keyword(Keyword::Else)? {
+            self.bump()?;
+            self.nested(Self::parse_stmt)?;
+        }
+
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Parses a statement of the form `keyword (expr) stmt`, such as a `while` or `switch`
+    /// statement.
+    fn parse_cond_stmt(&mut self, kind: NodeKind) -> DResult<()> {
+        self.builder.start_node(kind);
+        self.bump()?;
+        self.parse_paren_cond()?;
+        self.nested(Self::parse_stmt)?;
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    fn parse_do_while_stmt(&mut self) -> DResult<()> {
+        self.builder.start_node(NodeKind::DoWhileStmt);
+        self.bump()?;
+        self.nested(Self::parse_stmt)?;
+
+        if self.at_keyword(Keyword::While)? {
+            self.bump()?;
+            self.parse_paren_cond()?;
+            self.expect_punct(PunctKind::Semi)?;
+        } else {
+            let tok = self.peek()?;
+            self.reporter()
+                .error(tok.range, "expected 'while' in do/while loop")
+                .emit()?;
+        }
+
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    fn parse_for_stmt(&mut self) -> DResult<()> {
+        self.builder.start_node(NodeKind::ForStmt);
+        self.bump()?;
+
+        if let Some(open) = self.expect_punct(PunctKind::LParen)? {
+            // Declarations in the first clause are scoped to the loop.
+            self.push_scope();
+
+            let tok = self.peek()?;
+            if self.is_decl_start(tok)? {
+                self.parse_decl(false)?;
+            } else {
+                self.parse_optional_expr(PunctKind::Semi)?;
+                self.expect_punct(PunctKind::Semi)?;
+            }
+
+            self.parse_optional_expr(PunctKind::Semi)?;
+            self.expect_punct(PunctKind::Semi)?;
+            self.parse_optional_expr(PunctKind::RParen)?;
+            self.expect_closing(PunctKind::RParen, open)?;
+
+            self.nested(Self::parse_stmt)?;
+            self.pop_scope();
+        }
+
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Parses an expression unless the current token is `terminator`.
+    fn parse_optional_expr(&mut self, terminator: PunctKind) -> DResult<()> {
+        if !self.at_punct(terminator)? {
+            self.parse_expr()?;
+        }
+        Ok(())
+    }
+
+    /// Parses the parenthesized condition of an `if`, `switch` or loop statement.
+    fn parse_paren_cond(&mut self) -> DResult<()> {
+        if let Some(open) = self.expect_punct(PunctKind::LParen)? {
+            self.parse_expr()?;
+            self.expect_closing(PunctKind::RParen, open)?;
+        }
+        Ok(())
+    }
+}
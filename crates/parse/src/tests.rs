@@ -0,0 +1,394 @@
+use std::cell::RefCell;
+use std::fmt::Write;
+
+use lex::LexCtx;
+use pp::PreprocessorBuilder;
+use source::diag::RecordingSink;
+use source::limits::DEFAULT_MAX_BRACKET_DEPTH;
+use source::smap::{FileContents, FileName};
+use source::{DResult, DiagManager, SourceMap};
+use syntax::{Element, Node};
+
+use crate::Parser;
+
+/// Preprocesses and parses `src` with `parse`, returning a dump of the resulting tree and the
+/// diagnostics reported.
+///
+/// Nodes are dumped as parenthesized lists of their kind and children, with tokens represented by
+/// their spellings. Nothing is dumped if parsing stops due to a fatal error.
+fn parse_with(
+    src: &str,
+    parse: impl FnOnce(&mut Parser<'_, '_, '_>) -> DResult<()>,
+) -> (String, Vec<String>) {
+    let diags = RefCell::new(Vec::new());
+    let mut manager = DiagManager::new(RecordingSink(&diags), None);
+    let mut interner = lex::Interner::new();
+    let mut smap = SourceMap::new();
+
+    let main_id = smap
+        .create_file(FileName::synth("test.c"), FileContents::new(src), None)
+        .unwrap();
+
+    let mut ctx = LexCtx::new(&mut interner, &mut manager, &mut smap);
+    let mut pp = PreprocessorBuilder::new(&mut ctx, main_id).build();
+
+    let mut parser = Parser::new(&mut ctx, &mut pp);
+    let mut output = String::new();
+    if parse(&mut parser).is_ok() {
+        let root = parser.builder.finish();
+        dump(&root, ctx.smap, &mut output);
+    }
+
+    drop(manager);
+    (output, diags.into_inner())
+}
+
+fn dump(node: &Node, smap: &SourceMap, output: &mut String) {
+    write!(output, "({:?}", node.kind()).unwrap();
+    for child in node.children() {
+        match child {
+            Element::Node(node) => {
+                output.push(' ');
+                dump(node, smap, output);
+            }
+            Element::Token(tok) => {
+                // Skip the empty `Eof` token ending the translation unit.
+                let spelling = smap.get_spelling(tok.range);
+                if !spelling.is_empty() {
+                    output.push(' ');
+                    output.push_str(spelling);
+                }
+            }
+        }
+    }
+    output.push(')');
+}
+
+/// Parses `src` as a translation unit, returning the dumps of its top-level children (one per
+/// line) and the diagnostics reported.
+fn parse_tu(src: &str) -> (String, Vec<String>) {
+    let (output, diags) = parse_with(src, |parser| parser.parse_translation_unit());
+    let inner = output
+        .strip_prefix("(TranslationUnit")
+        .and_then(|inner| inner.strip_suffix(')'))
+        .unwrap();
+    let children = split_top_level(inner.trim_start());
+    (children.join("\n"), diags)
+}
+
+/// Splits a sequence of dumped elements separated by spaces.
+fn split_top_level(dumped: &str) -> Vec<&str> {
+    let mut children = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in dumped.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ' ' if depth == 0 => {
+                children.push(&dumped[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    if start < dumped.len() {
+        children.push(&dumped[start..]);
+    }
+    children
+}
+
+fn check(src: &str, expected: &str) {
+    let (output, diags) = parse_tu(src);
+    assert_eq!(output, expected);
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+}
+
+fn check_diags(src: &str, expected: &str, expected_diags: &[&str]) {
+    let (output, diags) = parse_tu(src);
+    assert_eq!(output, expected);
+    assert_eq!(diags, expected_diags);
+}
+
+fn check_expr(src: &str, expected: &str) {
+    let (output, diags) = parse_with(src, |parser| parser.parse_expr());
+    assert_eq!(output, expected);
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+}
+
+#[test]
+fn primary_exprs() {
+    check_expr("x", "(IdentExpr x)");
+    check_expr("42", "(NumberLiteralExpr 42)");
+    check_expr("'c'", "(CharLiteralExpr 'c')");
+    check_expr(r#""a" "b""#, r#"(StrLiteralExpr "a" "b")"#);
+    check_expr("(x)", "(ParenExpr ( (IdentExpr x) ))");
+}
+
+#[test]
+fn binary_precedence() {
+    check_expr(
+        "a + b * c",
+        "(BinExpr (IdentExpr a) + (BinExpr (IdentExpr b) * (IdentExpr c)))",
+    );
+    check_expr(
+        "a - b - c",
+        "(BinExpr (BinExpr (IdentExpr a) - (IdentExpr b)) - (IdentExpr c))",
+    );
+    check_expr(
+        "a || b && c == d",
+        "(BinExpr (IdentExpr a) || (BinExpr (IdentExpr b) && (BinExpr (IdentExpr c) == (IdentExpr d))))",
+    );
+    check_expr("a, b", "(BinExpr (IdentExpr a) , (IdentExpr b))");
+}
+
+#[test]
+fn assignment_and_conditional() {
+    check_expr(
+        "a = b += c",
+        "(AssignmentExpr (IdentExpr a) = (AssignmentExpr (IdentExpr b) += (IdentExpr c)))",
+    );
+    check_expr(
+        "a ? b : c ? d : e",
+        "(ConditionalExpr (IdentExpr a) ? (IdentExpr b) : (ConditionalExpr (IdentExpr c) ? (IdentExpr d) : (IdentExpr e)))",
+    );
+}
+
+#[test]
+fn unary_and_postfix_exprs() {
+    check_expr(
+        "-*p++",
+        "(UnaryExpr - (UnaryExpr * (PostIncrExpr (IdentExpr p) ++)))",
+    );
+    check_expr("--x", "(PreIncrExpr -- (IdentExpr x))");
+    check_expr(
+        "a[1].b->c(2, 3)",
+        "(CallExpr (DerefMemberExpr (MemberExpr (IndexExpr (IdentExpr a) [ (NumberLiteralExpr 1) ]) . b) -> c) \
+         (ArgList ( (NumberLiteralExpr 2) , (NumberLiteralExpr 3) )))",
+    );
+    check_expr("f()", "(CallExpr (IdentExpr f) (ArgList ( )))");
+}
+
+#[test]
+fn type_operand_exprs() {
+    check_expr(
+        "(int)x",
+        "(CastExpr ( (TypeName (SpecifierQualifierList (PlainTypeSpecifier int))) ) (IdentExpr x))",
+    );
+    check_expr(
+        "sizeof(char *)",
+        "(SizeofTypeExpr sizeof ( (TypeName (SpecifierQualifierList (PlainTypeSpecifier char)) (PtrDeclarator *)) ))",
+    );
+    check_expr("sizeof x", "(SizeofValExpr sizeof (IdentExpr x))");
+    check_expr(
+        "sizeof (int){1}",
+        "(SizeofValExpr sizeof (CompoundLiteralExpr ( (TypeName (SpecifierQualifierList (PlainTypeSpecifier int))) ) \
+         (StructInitList { (NumberLiteralExpr 1) })))",
+    );
+    check_expr(
+        "_Alignof(long)",
+        "(AlignofExpr _Alignof ( (TypeName (SpecifierQualifierList (PlainTypeSpecifier long))) ))",
+    );
+}
+
+#[test]
+fn simple_decls() {
+    check(
+        "int x, *y = 0;",
+        "(PlainDecl (PlainTypeSpecifier int) (InitDeclarator (IdentDeclarator x)) , \
+         (InitDeclarator (PtrDeclarator * (IdentDeclarator y)) = (NumberLiteralExpr 0)) ;)",
+    );
+    check(
+        "static const char *const s[4];",
+        "(PlainDecl (StorageSpecifier static) (TypeQualifier const) (PlainTypeSpecifier char) \
+         (InitDeclarator (PtrDeclarator * (TypeQualifierList (TypeQualifier const)) \
+         (ArrayDeclarator (IdentDeclarator s) [ (NumberLiteralExpr 4) ]))) ;)",
+    );
+    check(
+        "int (*fp)(int, ...);",
+        "(PlainDecl (PlainTypeSpecifier int) (InitDeclarator (FunctionDeclarator \
+         (ParenDeclarator ( (PtrDeclarator * (IdentDeclarator fp)) )) \
+         (ParamList ( (ParamDecl (PlainTypeSpecifier int)) , ... )))) ;)",
+    );
+}
+
+#[test]
+fn typedef_names() {
+    check(
+        "typedef int T; T x; void f(T); void g(int T) { T; }",
+        "(PlainDecl (StorageSpecifier typedef) (PlainTypeSpecifier int) (InitDeclarator (IdentDeclarator T)) ;)\n\
+         (PlainDecl (TypedefName T) (InitDeclarator (IdentDeclarator x)) ;)\n\
+         (PlainDecl (PlainTypeSpecifier void) (InitDeclarator (FunctionDeclarator (IdentDeclarator f) \
+         (ParamList ( (ParamDecl (TypedefName T)) )))) ;)\n\
+         (FunctionDef (PlainTypeSpecifier void) (FunctionDeclarator (IdentDeclarator g) \
+         (ParamList ( (ParamDecl (PlainTypeSpecifier int) (IdentDeclarator T)) ))) \
+         (BlockStmt { (ExprStmt (IdentExpr T) ;) }))",
+    );
+    check(
+        "typedef int T; void f(void) { int T = 1; T * 2; }",
+        "(PlainDecl (StorageSpecifier typedef) (PlainTypeSpecifier int) (InitDeclarator (IdentDeclarator T)) ;)\n\
+         (FunctionDef (PlainTypeSpecifier void) (FunctionDeclarator (IdentDeclarator f) \
+         (ParamList ( (ParamDecl (PlainTypeSpecifier void)) ))) \
+         (BlockStmt { (PlainDecl (PlainTypeSpecifier int) (InitDeclarator (IdentDeclarator T) = (NumberLiteralExpr 1)) ;) \
+         (ExprStmt (BinExpr (IdentExpr T) * (NumberLiteralExpr 2)) ;) }))",
+    );
+}
+
+#[test]
+fn tag_specifiers() {
+    check(
+        "struct S { int a : 3, b; struct S *next; };",
+        "(PlainDecl (StructSpecifier struct S (StructDeclList { \
+         (StructFieldDecl (SpecifierQualifierList (PlainTypeSpecifier int)) \
+         (BitfieldDeclarator (IdentDeclarator a) : (NumberLiteralExpr 3)) , (IdentDeclarator b) ;) \
+         (StructFieldDecl (SpecifierQualifierList (StructSpecifier struct S)) (PtrDeclarator * (IdentDeclarator next)) ;) })) ;)",
+    );
+    check(
+        "enum E { A, B = A + 1, };",
+        "(PlainDecl (EnumSpecifier enum E (EnumeratorList { (Enumerator A) , \
+         (Enumerator B = (BinExpr (IdentExpr A) + (NumberLiteralExpr 1))) , })) ;)",
+    );
+}
+
+#[test]
+fn initializers() {
+    check(
+        "int a[] = { 1, [2] = 3, .x.y = { 4 } };",
+        "(PlainDecl (PlainTypeSpecifier int) (InitDeclarator (ArrayDeclarator (IdentDeclarator a) [ ]) = \
+         (StructInitList { (NumberLiteralExpr 1) , \
+         (DesignatorList (ArrayDesignator [ (NumberLiteralExpr 2) ]) =) (NumberLiteralExpr 3) , \
+         (DesignatorList (FieldDesignator . x) (FieldDesignator . y) =) (StructInitList { (NumberLiteralExpr 4) }) })) ;)",
+    );
+}
+
+#[test]
+fn static_assert() {
+    check(
+        r#"_Static_assert(1, "msg");"#,
+        r#"(StaticAssertDecl _Static_assert ( (NumberLiteralExpr 1) , (StrLiteralExpr "msg") ) ;)"#,
+    );
+}
+
+#[test]
+fn statements() {
+    check(
+        "void f(int n) { for (int i = 0; i < n; i++) if (i) continue; else break; \
+         while (n) n--; do ; while (0); switch (n) { case 1: default: return; } \
+         l: goto l; }",
+        "(FunctionDef (PlainTypeSpecifier void) (FunctionDeclarator (IdentDeclarator f) \
+         (ParamList ( (ParamDecl (PlainTypeSpecifier int) (IdentDeclarator n)) ))) (BlockStmt { \
+         (ForStmt for ( (PlainDecl (PlainTypeSpecifier int) (InitDeclarator (IdentDeclarator i) = (NumberLiteralExpr 0)) ;) \
+         (BinExpr (IdentExpr i) < (IdentExpr n)) ; (PostIncrExpr (IdentExpr i) ++) ) \
+         (IfStmt if ( (IdentExpr i) ) (ContinueStmt continue ;) else (BreakStmt break ;))) \
+         (WhileStmt while ( (IdentExpr n) ) (ExprStmt (PostIncrExpr (IdentExpr n) --) ;)) \
+         (DoWhileStmt do (ExprStmt ;) while ( (NumberLiteralExpr 0) ) ;) \
+         (SwitchStmt switch ( (IdentExpr n) ) (BlockStmt { (CaseStmt case (NumberLiteralExpr 1) : \
+         (DefaultCaseStmt default : (ReturnStmt return ;))) })) \
+         (LabeledStmt l : (GotoStmt goto l ;)) }))",
+    );
+}
+
+#[test]
+fn preprocessed_input() {
+    check(
+        "#define DECL(name) int name;\nDECL(x)",
+        "(PlainDecl (PlainTypeSpecifier int) (InitDeclarator (IdentDeclarator x)) ;)",
+    );
+}
+
+#[test]
+fn missing_semicolon() {
+    check_diags(
+        "int x\nint y;",
+        "(PlainDecl (PlainTypeSpecifier int) (InitDeclarator (IdentDeclarator x)))\n\
+         (PlainDecl (PlainTypeSpecifier int) (InitDeclarator (IdentDeclarator y)) ;)",
+        &["error: expected a ';'"],
+    );
+}
+
+#[test]
+fn unbalanced_parens() {
+    check_diags(
+        "void f(void) { g(1; }",
+        "(FunctionDef (PlainTypeSpecifier void) (FunctionDeclarator (IdentDeclarator f) \
+         (ParamList ( (ParamDecl (PlainTypeSpecifier void)) ))) (BlockStmt { \
+         (ExprStmt (CallExpr (IdentExpr g) (ArgList ( (NumberLiteralExpr 1))) ;) }))",
+        &["error: expected a ')'"],
+    );
+}
+
+#[test]
+fn error_recovery() {
+    check_diags(
+        "int 3 + x; int y; } 4; int z;",
+        "(PlainDecl (PlainTypeSpecifier int) (Error 3 + x ;))\n\
+         (PlainDecl (PlainTypeSpecifier int) (InitDeclarator (IdentDeclarator y)) ;)\n\
+         }\n\
+         (Error 4 ;)\n\
+         (PlainDecl (PlainTypeSpecifier int) (InitDeclarator (IdentDeclarator z)) ;)",
+        &[
+            "error: expected an identifier or '('",
+            "error: extraneous closing brace",
+            "error: expected a declaration",
+        ],
+    );
+    check_diags(
+        "void f(void) { x = ; ) y; }",
+        "(FunctionDef (PlainTypeSpecifier void) (FunctionDeclarator (IdentDeclarator f) \
+         (ParamList ( (ParamDecl (PlainTypeSpecifier void)) ))) (BlockStmt { \
+         (ExprStmt (AssignmentExpr (IdentExpr x) =) ;) (Error ) y ;) }))",
+        &[
+            "error: expected an expression",
+            "error: expected a statement",
+        ],
+    );
+}
+
+#[test]
+fn unterminated_block() {
+    check_diags(
+        "void f(void) { if (1) {",
+        "(FunctionDef (PlainTypeSpecifier void) (FunctionDeclarator (IdentDeclarator f) \
+         (ParamList ( (ParamDecl (PlainTypeSpecifier void)) ))) (BlockStmt { \
+         (IfStmt if ( (NumberLiteralExpr 1) ) (BlockStmt {))))",
+        &["error: expected a '}'", "error: expected a '}'"],
+    );
+}
+
+#[test]
+fn nesting_depth_limit() {
+    let nested = |open: &str, inner: &str, close: &str, depth| {
+        format!("{}{}{}", open.repeat(depth), inner, close.repeat(depth))
+    };
+    let sources = |depth| {
+        [
+            format!("int x = {};", nested("(", "1", ")", depth)),
+            format!("int x = {};", nested("- ", "1", "", depth)),
+            format!("int x = {};", nested("f(", "1", ")", depth)),
+            format!("int {};", nested("(", "x", ")", depth)),
+            format!("int {};", nested("*", "x", "", depth)),
+            format!("void f(void) {{ {} }}", nested("{", "", "}", depth)),
+        ]
+    };
+
+    for src in sources(DEFAULT_MAX_BRACKET_DEPTH) {
+        let (_, diags) = parse_tu(&src);
+        assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+    }
+
+    for depth in [DEFAULT_MAX_BRACKET_DEPTH + 1, 100_000] {
+        for src in sources(depth) {
+            let (output, diags) = parse_with(&src, |parser| parser.parse_translation_unit());
+            assert_eq!(output, "");
+            assert_eq!(
+                diags,
+                [format!(
+                    "fatal: code nested too deeply (maximum depth is {})",
+                    DEFAULT_MAX_BRACKET_DEPTH
+                )]
+            );
+        }
+    }
+}
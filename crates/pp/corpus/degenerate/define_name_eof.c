@@ -0,0 +1 @@
+#define A
\ No newline at end of file
@@ -0,0 +1,3 @@
+#define F(x) [x]
+#define G(x, y) [x|y]
+F() G(,) G( , ) F(F())
\ No newline at end of file
@@ -0,0 +1,3 @@
+#define F()
+F() F( ) F(
+)
\ No newline at end of file
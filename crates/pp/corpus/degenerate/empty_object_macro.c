@@ -0,0 +1,2 @@
+#define A
+A A A
\ No newline at end of file
@@ -0,0 +1,3 @@
+#define S(x) #x
+#define V(...) #__VA_ARGS__
+S() V() V(,)
\ No newline at end of file
@@ -0,0 +1,2 @@
+#define F(...) __VA_OPT__()
+F() F(1)
\ No newline at end of file
@@ -0,0 +1 @@
+#if
\ No newline at end of file
@@ -0,0 +1 @@
+#if 1
\ No newline at end of file
@@ -0,0 +1,2 @@
+#ifdef
+#endif
\ No newline at end of file
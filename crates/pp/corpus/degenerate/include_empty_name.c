@@ -0,0 +1,2 @@
+#include ""
+#include <>
\ No newline at end of file
@@ -0,0 +1 @@
+#pragma
\ No newline at end of file
@@ -0,0 +1,2 @@
+#define F(x) x
+F(
\ No newline at end of file
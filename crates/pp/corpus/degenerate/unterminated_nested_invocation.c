@@ -0,0 +1,3 @@
+#define F(x) x
+#define G F(
+G
\ No newline at end of file
@@ -0,0 +1 @@
+#define F(
\ No newline at end of file
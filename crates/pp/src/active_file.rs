@@ -1,17 +1,26 @@
 use std::path::PathBuf;
 use std::rc::Rc;
 
-use lex::LexCtx;
-use source::smap::{FileName, SourcesTooLargeError};
-use source::{DResult, SourceId, SourceMap, SourcePos, SourceRange};
+use lex::{LexCtx, Symbol};
+use source::smap::{FileContents, FileName, SourcesTooLargeError};
+use source::sync::Lrc;
+use source::{SourceId, SourceMap, SourcePos, SourceRange};
 
-use crate::expand::MacroState;
-use crate::file::{File, IncludeKind};
-use crate::PpToken;
+use crate::embed::EmbedParams;
+use crate::expand::{MacroOrigin, MacroState};
+use crate::file::{File, IncludeKind, IncludeLoader, ResolvedInclude};
+use crate::stats::StatsRecorder;
+use crate::{CancellationToken, FeatureTable, PpCallbacks, PpResult, PpToken, Pragma};
 
-use next::NextEventCtx;
+use conditional::ConditionalStack;
+use guard::GuardDetector;
+use next::{FileState, NextEventCtx};
 use processor::{Processor, ProcessorState};
 
+pub use processor::ProcessorOptions;
+
+mod conditional;
+mod guard;
 mod lexer;
 mod next;
 mod processor;
@@ -21,16 +30,45 @@ mod processor;
 /// Generally, most directives can be handled internally while processing the file and need not be
 /// reported through an event. Includes, however, are special, as they need to modify the list of
 /// active files itself. This cannot happen while the file is being processed, so it must be
-/// propagated to the caller.
+/// propagated to the caller. Embeds and pragmas are propagated as well, as the resource loader and
+/// pragma handlers are owned by the preprocessor.
 pub enum Event {
     /// Preprocessing has produced another output token.
     Tok(PpToken),
     /// An include directive has been encountered and should be handled.
     Include {
+        filename: PathBuf,
+        kind: IncludeKind,
+        /// Whether this is an `#include_next` directive.
+        next: bool,
+        range: SourceRange,
+    },
+    /// An embed directive has been encountered and its resource should be loaded.
+    Embed {
         filename: PathBuf,
         kind: IncludeKind,
         range: SourceRange,
+        params: EmbedParams,
     },
+    /// A pragma directive has been encountered and should be handled.
+    Pragma(Pragma),
+}
+
+/// The parts of the preprocessor's state consulted while processing a file.
+pub struct FileEnv<'a> {
+    /// The callbacks notified of the directives processed and macros expanded.
+    pub callbacks: &'a mut dyn PpCallbacks,
+    /// The loader consulted by `__has_include` and `__has_include_next`.
+    pub include_loader: &'a mut IncludeLoader,
+    /// The position at which `__has_include_next` resumes the include search, or `None` in the
+    /// main source file.
+    pub next_from: Option<usize>,
+    /// The table consulted by `__has_feature` and `__has_extension`.
+    pub features: &'a FeatureTable,
+    /// The recorder in which time spent and events encountered are accumulated.
+    pub stats: &'a StatsRecorder,
+    /// The token polled while skipping conditional groups and rescanning macro expansions, if any.
+    pub cancellation_token: Option<&'a CancellationToken>,
 }
 
 /// A file that is currently being processed by the preprocessor.
@@ -38,17 +76,33 @@ pub enum Event {
 /// In addition to the file itself, this tracks the current offset and conditional state.
 pub struct ActiveFile {
     file: Rc<File>,
+    id: SourceId,
     start_pos: SourcePos,
+    macro_origin: MacroOrigin,
+    search_pos: Option<usize>,
     processor_state: ProcessorState,
+    conditionals: ConditionalStack,
+    guard: GuardDetector,
 }
 
 impl ActiveFile {
-    /// Creates a new active file with the specified content and base position.
-    fn new(file: Rc<File>, start_pos: SourcePos) -> ActiveFile {
+    /// Creates a new active file with the specified content and source, in which macros are
+    /// defined with origin `macro_origin`.
+    fn new(
+        file: Rc<File>,
+        smap: &SourceMap,
+        id: SourceId,
+        macro_origin: MacroOrigin,
+    ) -> ActiveFile {
         ActiveFile {
             file,
-            start_pos,
+            id,
+            start_pos: smap.get_source(id).range.start(),
+            macro_origin,
+            search_pos: None,
             processor_state: ProcessorState::new(),
+            conditionals: ConditionalStack::new(),
+            guard: GuardDetector::new(),
         }
     }
 
@@ -57,22 +111,71 @@ impl ActiveFile {
         &self.file
     }
 
-    /// Resumes processing of the file and returns the next interesting event
-    pub fn next_event(
-        &mut self,
-        ctx: &mut LexCtx<'_, '_>,
-        macro_state: &mut MacroState,
-    ) -> DResult<Event> {
-        NextEventCtx::new(ctx, macro_state, self.processor()).next_event()
+    /// Returns the source created for the file in the source map.
+    pub fn id(&self) -> SourceId {
+        self.id
     }
 
-    /// Returns a processor for reading tokens and text from the file.
-    fn processor(&mut self) -> Processor<'_> {
-        Processor::new(
+    /// Returns the origin of macros defined in the file.
+    pub fn macro_origin(&self) -> MacroOrigin {
+        self.macro_origin
+    }
+
+    /// Returns the position in the include search order at which the file was found, if any.
+    pub fn search_pos(&self) -> Option<usize> {
+        self.search_pos
+    }
+
+    /// Returns the macro guarding the entire file against multiple inclusion, if any.
+    ///
+    /// This is only meaningful once the end of the file has been reached.
+    pub fn controlling_macro(&self) -> Option<Symbol> {
+        self.guard.controlling_macro()
+    }
+
+    /// Returns the ranges of the lines skipped by conditional compilation since the last call to
+    /// this function.
+    pub fn take_skipped_regions(&mut self) -> Vec<SourceRange> {
+        self.conditionals.take_skipped()
+    }
+
+    /// Resumes processing of the file and returns the next interesting event
+    ///
+    ///
+    /// The file is tokenized according to `opts`. If comments are kept, those outside of
+    /// directives and macro invocations are returned as [`TokenKind::Comment`] tokens.
+    ///
+    /// `env.callbacks` is notified of the directives processed and macros expanded.
+    ///
+    /// [`TokenKind::Comment`]: lex::TokenKind::Comment
+    pub fn next_event<'a>(
+        &'a mut self,
+        ctx: &'a mut LexCtx<'_, '_>,
+        macro_state: &'a mut MacroState,
+        opts: ProcessorOptions,
+        env: FileEnv<'a>,
+    ) -> PpResult<Event> {
+        let processor = Processor::new(
             &mut self.processor_state,
-            &self.file.contents.src,
+            self.file.contents.src(),
             self.start_pos,
+            opts,
+            env.stats,
+        );
+
+        NextEventCtx::new(
+            ctx,
+            macro_state,
+            processor,
+            FileState {
+                file: &self.file,
+                macro_origin: self.macro_origin,
+                conditionals: &mut self.conditionals,
+                guard: &mut self.guard,
+            },
+            env,
         )
+        .next_event()
     }
 }
 
@@ -99,8 +202,10 @@ impl ActiveFiles {
 
         ActiveFiles {
             main: ActiveFile::new(
-                File::new(Rc::clone(&file.contents), parent_dir),
-                source.range.start(),
+                File::new(Lrc::clone(&file.contents), parent_dir),
+                smap,
+                main_id,
+                MacroOrigin::Source,
             ),
             includes: vec![],
         }
@@ -116,28 +221,64 @@ impl ActiveFiles {
         !self.includes.is_empty()
     }
 
-    /// Pushes a new file onto the include stack, creating an entry for it in the source map.
+    /// Returns the number of files included (directly or indirectly) by the main source file that
+    /// are on the stack, ignoring synthesized buffers.
+    pub fn include_depth(&self) -> usize {
+        self.includes
+            .iter()
+            .filter(|file| file.macro_origin == MacroOrigin::Source)
+            .count()
+    }
+
+    /// Pushes a new file onto the include stack, creating an entry for it in the source map and
+    /// returning its ID.
     pub fn push_include(
         &mut self,
         smap: &mut SourceMap,
         filename: PathBuf,
-        file: Rc<File>,
+        resolved: ResolvedInclude,
         include_pos: SourcePos,
-    ) -> Result<(), SourcesTooLargeError> {
+    ) -> Result<SourceId, SourcesTooLargeError> {
+        let ResolvedInclude { file, search_pos } = resolved;
         let id = smap.create_file(
             FileName::real(filename),
-            Rc::clone(&file.contents),
+            Lrc::clone(&file.contents),
             Some(include_pos),
         )?;
-        self.includes
-            .push(ActiveFile::new(file, smap.get_source(id).range.start()));
+
+        let mut active_file = ActiveFile::new(file, smap, id, MacroOrigin::Source);
+        active_file.search_pos = search_pos;
+        self.includes.push(active_file);
+        Ok(id)
+    }
+
+    /// Pushes a synthesized buffer containing macro definitions with origin `origin` onto the
+    /// stack, creating a source for it named `name`.
+    ///
+    /// Quoted includes in the buffer are resolved against the current directory first.
+    pub fn push_predefines(
+        &mut self,
+        smap: &mut SourceMap,
+        name: &str,
+        contents: &str,
+        origin: MacroOrigin,
+    ) -> Result<(), SourcesTooLargeError> {
+        let contents = FileContents::new(contents);
+        let id = smap.create_file(FileName::synth(name), Lrc::clone(&contents), None)?;
+
+        self.includes.push(ActiveFile::new(
+            File::new(contents, Some(PathBuf::new())),
+            smap,
+            id,
+            origin,
+        ));
         Ok(())
     }
 
-    /// Pops the topmost include on the stack.
+    /// Pops the topmost include on the stack, returning it.
     ///
     /// This has no effect if there are no includes; the main file will not be popped.
-    pub fn pop_include(&mut self) {
-        self.includes.pop();
+    pub fn pop_include(&mut self) -> Option<ActiveFile> {
+        self.includes.pop()
     }
 }
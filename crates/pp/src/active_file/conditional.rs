@@ -0,0 +1,76 @@
+use std::mem;
+
+use source::SourceRange;
+
+/// The state of a single conditional inclusion block (`#if`...`#endif`).
+#[derive(Debug, Clone, Copy)]
+pub struct Conditional {
+    /// The range of the directive name that opened this block, used when reporting unterminated
+    /// conditionals.
+    pub if_range: SourceRange,
+    /// The range of the `#else` directive in this block, if one has been encountered.
+    pub else_range: Option<SourceRange>,
+    /// Whether one of the groups in this block has already been included. Once this is set, all
+    /// remaining groups in the block are skipped.
+    pub taken: bool,
+}
+
+/// A stack of the conditional blocks currently open in a file - last is innermost.
+///
+/// Conditional blocks never span several files, so each active file has its own stack.
+pub struct ConditionalStack {
+    stack: Vec<Conditional>,
+    /// The ranges skipped since the last call to [`take_skipped()`](Self::take_skipped()).
+    skipped: Vec<SourceRange>,
+}
+
+impl ConditionalStack {
+    /// Creates a new stack with no open conditionals.
+    pub fn new() -> Self {
+        Self {
+            stack: Vec::new(),
+            skipped: Vec::new(),
+        }
+    }
+
+    /// Opens a new conditional block started at `if_range`, whose first group is included iff
+    /// `taken` is set.
+    pub fn push(&mut self, if_range: SourceRange, taken: bool) {
+        self.stack.push(Conditional {
+            if_range,
+            else_range: None,
+            taken,
+        });
+    }
+
+    /// Closes the innermost conditional block, returning it.
+    pub fn pop(&mut self) -> Option<Conditional> {
+        self.stack.pop()
+    }
+
+    /// Returns the innermost open conditional block, if any.
+    pub fn top_mut(&mut self) -> Option<&mut Conditional> {
+        self.stack.last_mut()
+    }
+
+    /// Returns the number of open conditional blocks.
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Closes all open conditional blocks, returning them from outermost to innermost.
+    pub fn drain(&mut self) -> impl Iterator<Item = Conditional> + '_ {
+        self.stack.drain(..)
+    }
+
+    /// Records that the lines in `range` have been skipped.
+    pub fn record_skipped(&mut self, range: SourceRange) {
+        self.skipped.push(range);
+    }
+
+    /// Returns the ranges recorded with [`record_skipped()`](Self::record_skipped()) since the last
+    /// call to this function.
+    pub fn take_skipped(&mut self) -> Vec<SourceRange> {
+        mem::take(&mut self.skipped)
+    }
+}
@@ -0,0 +1,70 @@
+use lex::Symbol;
+
+/// The progress of a file towards matching the include guard pattern.
+#[derive(Debug, Clone, Copy)]
+enum State {
+    /// Nothing of interest has been encountered in the file yet.
+    Start,
+    /// The file is inside the `#ifndef` block guarded by the specified macro.
+    Guarded(Symbol),
+    /// The guard block has been closed, and nothing has appeared after it yet.
+    Closed(Symbol),
+    /// The file does not match the pattern.
+    Unguarded,
+}
+
+/// Detects whether the entire contents of a file are wrapped in an include guard of the form
+///
+/// ```c
+/// #ifndef GUARD
+/// ...
+/// #endif
+/// ```
+///
+/// A file matching this pattern produces nothing when included while `GUARD` is defined, so it need
+/// not be processed again. Only the file's top level, outside of any conditionals, is of interest.
+pub struct GuardDetector {
+    state: State,
+}
+
+impl GuardDetector {
+    /// Creates a new detector for a file that has not been processed yet.
+    pub fn new() -> Self {
+        Self {
+            state: State::Start,
+        }
+    }
+
+    /// Records a token or directive appearing at the top level of the file, which is not part of
+    /// the guard.
+    pub fn invalidate(&mut self) {
+        self.state = State::Unguarded;
+    }
+
+    /// Records a top-level `#ifndef` directive testing `name`, whose first group is included iff
+    /// `taken` is set.
+    pub fn open(&mut self, name: Symbol, taken: bool) {
+        self.state = match self.state {
+            State::Start if taken => State::Guarded(name),
+            _ => State::Unguarded,
+        };
+    }
+
+    /// Records the `#endif` directive closing a top-level conditional.
+    pub fn close(&mut self) {
+        self.state = match self.state {
+            State::Guarded(name) => State::Closed(name),
+            _ => State::Unguarded,
+        };
+    }
+
+    /// Returns the macro guarding the file, if the file matched the pattern in its entirety.
+    ///
+    /// This should only be called once the end of the file has been reached.
+    pub fn controlling_macro(&self) -> Option<Symbol> {
+        match self.state {
+            State::Closed(name) => Some(name),
+            _ => None,
+        }
+    }
+}
@@ -1,23 +1,53 @@
+use std::convert::TryFrom;
 use std::fmt::Write;
 use std::path::PathBuf;
 
-use lex::{LexCtx, PunctKind, Symbol, Token, TokenKind};
-use source::SourceRange;
+use lex::{LexCtx, PunctKind, Symbol, SyntheticSource, Token, TokenKind};
+use source::smap::FileName;
 use source::{
-    diag::{RawSubDiagnostic, RawSuggestion, Reporter},
+    diag::{DiagnosticId, Level, Message, MessageArg, RawSubDiagnostic, RawSuggestion, Reporter},
     DResult,
 };
+use source::{FragmentedSourceRange, SourcePos, SourceRange};
 
-use crate::expand::{MacroDef, MacroDefKind, MacroState, ReplacementList};
+use crate::cancel::{self, CancellationToken, PpError, PpResult};
+use crate::embed::EmbedParams;
+use crate::expand::{
+    MacroDef, MacroDefKind, MacroOrigin, MacroState, ReplacementLexer, ReplacementList,
+};
+use crate::expr_eval::{self, Value};
+use crate::file::{File, IncludeLoader};
+use crate::stats::{StatsPhase, StatsRecorder};
+use crate::{FeatureTable, LangStandard, PpCallbacks, Pragma};
 
+use super::conditional::ConditionalStack;
+use super::guard::GuardDetector;
 use super::lexer::{DirectiveLexer, MacroArgLexer};
 use super::processor::{FileToken, Processor};
-use super::{Event, IncludeKind, PpToken};
+use super::{Event, FileEnv, IncludeKind, PpToken};
 
 pub struct NextEventCtx<'a, 'b, 's, 'h> {
     ctx: &'a mut LexCtx<'b, 'h>,
     macro_state: &'a mut MacroState,
+    conditionals: &'a mut ConditionalStack,
+    guard: &'a mut GuardDetector,
     processor: Processor<'s>,
+    file: &'a File,
+    macro_origin: MacroOrigin,
+    callbacks: &'a mut dyn PpCallbacks,
+    include_loader: &'a mut IncludeLoader,
+    next_from: Option<usize>,
+    features: &'a FeatureTable,
+    stats: &'a StatsRecorder,
+    cancellation_token: Option<&'a CancellationToken>,
+}
+
+/// The state of the file being processed, excluding its contents.
+pub struct FileState<'a> {
+    pub file: &'a File,
+    pub macro_origin: MacroOrigin,
+    pub conditionals: &'a mut ConditionalStack,
+    pub guard: &'a mut GuardDetector,
 }
 
 impl<'a, 'b, 's, 'h> NextEventCtx<'a, 'b, 's, 'h> {
@@ -25,43 +55,131 @@ impl<'a, 'b, 's, 'h> NextEventCtx<'a, 'b, 's, 'h> {
         ctx: &'a mut LexCtx<'b, 'h>,
         macro_state: &'a mut MacroState,
         processor: Processor<'s>,
+        state: FileState<'a>,
+        env: FileEnv<'a>,
     ) -> Self {
         Self {
             ctx,
             macro_state,
+            conditionals: state.conditionals,
+            guard: state.guard,
             processor,
+            file: state.file,
+            macro_origin: state.macro_origin,
+            callbacks: env.callbacks,
+            include_loader: env.include_loader,
+            next_from: env.next_from,
+            features: env.features,
+            stats: env.stats,
+            cancellation_token: env.cancellation_token,
         }
     }
 
-    pub fn next_event(&mut self) -> DResult<Event> {
+    pub fn next_event(&mut self) -> PpResult<Event> {
         loop {
-            if let Some(ppt) = self.next_expansion_token()? {
+            if let Some(ppt) = self.next_expansion_token(self.cancellation_token)? {
+                // Comments read while collecting macro arguments are treated as whitespace.
+                self.processor.discard_comments();
+
+                if self.is_pragma_operator(ppt) {
+                    match self.handle_pragma_operator(ppt)? {
+                        Some(event) => break Ok(event),
+                        None => continue,
+                    }
+                }
+
                 break Ok(Event::Tok(ppt));
             }
 
+            if let Some(comment) = self.next_comment()? {
+                break Ok(Event::Tok(comment));
+            }
+
             let ppt = self.next_real_token()?;
 
             if ppt.is_directive_start() {
-                if let Some(event) = self.handle_directive()? {
+                let stats = self.stats;
+                stats.count(|stats| stats.directives_processed += 1);
+                let event = stats.time(StatsPhase::Directives, || self.handle_directive())?;
+                // Comments are dropped along with the directive containing them.
+                self.processor.discard_comments();
+                if let Some(event) = event {
                     break Ok(event);
                 }
-            } else if !self.begin_expansion(ppt)? {
+            } else if ppt.data() == TokenKind::Eof {
+                self.check_unterminated_conditionals()?;
                 break Ok(Event::Tok(ppt));
+            } else {
+                if self.conditionals.depth() == 0 {
+                    self.guard.invalidate();
+                }
+
+                if !self.begin_expansion(ppt)? {
+                    if self.is_pragma_operator(ppt) {
+                        match self.handle_pragma_operator(ppt)? {
+                            Some(event) => break Ok(event),
+                            None => continue,
+                        }
+                    }
+
+                    break Ok(Event::Tok(ppt));
+                }
+                self.processor.discard_comments();
+                // Long runs of macros expanding to nothing produce no tokens.
+                cancel::check_cancelled(self.cancellation_token)?;
+            }
+        }
+    }
+
+    /// Returns the next comment preceding the upcoming token, if comments are being kept.
+    fn next_comment(&mut self) -> DResult<Option<PpToken>> {
+        if !self.processor.keeps_comments() {
+            return Ok(None);
+        }
+
+        loop {
+            // Lex up to the next real token so that any comments before it are collected, without
+            // consuming it.
+            let tok = self.processor.peek_token(self.ctx)?;
+
+            if let Some(comment) = self.processor.take_comment() {
+                return Ok(Some(comment));
+            }
+            if tok.real().is_some() {
+                return Ok(None);
             }
+            self.processor.next_token(self.ctx)?;
         }
     }
 
-    fn next_expansion_token(&mut self) -> DResult<Option<PpToken>> {
-        self.macro_state
-            .next_expansion_token(self.ctx, MacroArgLexer::new(&mut self.processor))
+    /// Returns the next token of the macro expansion in progress, if any, polling
+    /// `cancellation_token` while rescanning.
+    fn next_expansion_token(
+        &mut self,
+        cancellation_token: Option<&CancellationToken>,
+    ) -> PpResult<Option<PpToken>> {
+        self.stats.time(StatsPhase::MacroExpansion, || {
+            self.macro_state.next_expansion_token(
+                self.ctx,
+                self.callbacks,
+                MacroArgLexer::new(&mut self.processor),
+                cancellation_token,
+            )
+        })
     }
 
     fn begin_expansion(&mut self, ppt: PpToken) -> DResult<bool> {
-        self.macro_state
-            .begin_expansion(self.ctx, ppt, MacroArgLexer::new(&mut self.processor))
+        self.stats.time(StatsPhase::MacroExpansion, || {
+            self.macro_state.begin_expansion(
+                self.ctx,
+                self.callbacks,
+                ppt,
+                MacroArgLexer::new(&mut self.processor),
+            )
+        })
     }
 
-    fn handle_directive(&mut self) -> DResult<Option<Event>> {
+    fn handle_directive(&mut self) -> PpResult<Option<Event>> {
         let ppt = self.next_directive_token()?;
 
         let ident = match ppt.data() {
@@ -74,7 +192,12 @@ impl<'a, 'b, 's, 'h> NextEventCtx<'a, 'b, 's, 'h> {
         };
         self.processor.reader().eat_line_ws();
 
-        match &self.ctx.interner[ident] {
+        let name = &self.ctx.interner[ident];
+        if self.conditionals.depth() == 0 && name != "ifndef" {
+            self.guard.invalidate();
+        }
+
+        match name {
             "define" => {
                 self.handle_define_directive()?;
                 Ok(None)
@@ -83,9 +206,48 @@ impl<'a, 'b, 's, 'h> NextEventCtx<'a, 'b, 's, 'h> {
                 self.handle_undef_directive()?;
                 Ok(None)
             }
-            "include" => self.handle_include_directive(),
+            "include" => Ok(self.handle_include_directive(false)?),
+            "include_next" => Ok(self.handle_include_directive(true)?),
+            "embed" => Ok(self.handle_embed_directive()?),
+            "pragma" => Ok(Some(self.handle_pragma_directive(ppt.range())?)),
+            "line" => {
+                self.handle_line_directive()?;
+                Ok(None)
+            }
             "error" => {
-                self.handle_error_directive(ppt.range())?;
+                let level = self.processor.error_directive_level();
+                self.handle_diagnostic_directive(ppt.range(), level)?;
+                Ok(None)
+            }
+            "warning" => {
+                if self.processor.lang().standard < LangStandard::C23 {
+                    self.report_extension(ppt.range(), "'#warning' is a C23 extension")?;
+                }
+                self.handle_diagnostic_directive(ppt.range(), Level::Warning)?;
+                Ok(None)
+            }
+            "if" => {
+                self.handle_if_directive(ppt.range())?;
+                Ok(None)
+            }
+            "ifdef" => {
+                self.handle_ifdef_directive(ppt.range(), true)?;
+                Ok(None)
+            }
+            "ifndef" => {
+                self.handle_ifdef_directive(ppt.range(), false)?;
+                Ok(None)
+            }
+            "elif" => {
+                self.handle_elif_directive(ppt.range())?;
+                Ok(None)
+            }
+            "else" => {
+                self.handle_else_directive(ppt.range())?;
+                Ok(None)
+            }
+            "endif" => {
+                self.handle_endif_directive(ppt.range())?;
                 Ok(None)
             }
             _ => {
@@ -105,24 +267,32 @@ impl<'a, 'b, 's, 'h> NextEventCtx<'a, 'b, 's, 'h> {
             None => return Ok(()),
         };
 
+        self.check_reserved_macro_name(name_tok)?;
+
         let def = match self.consume_macro_def(name_tok)? {
             Some(def) => def,
             _ => return Ok(()),
         };
 
-        if let Some(prev) = self.macro_state.define(def) {
-            let prev_range = prev.name_tok.range;
-            let msg = format!(
-                "redefinition of macro '{}'",
-                &self.ctx.interner[name_tok.data]
+        self.callbacks.macro_defined(self.ctx, &def)?;
+
+        if let Some(prev) = self.macro_state.define(self.ctx, def) {
+            // Predefined macros may be freely overridden from the command line.
+            if prev.origin == MacroOrigin::Builtin && self.macro_origin == MacroOrigin::CommandLine
+            {
+                return Ok(());
+            }
+
+            let msg = Message::new("redefinition of macro '{name}'").arg(
+                "name",
+                MessageArg::symbol(&self.ctx.interner[name_tok.data]),
             );
+            let note = prev.def_note(self.ctx.interner, "previous definition here");
 
             self.reporter()
                 .error(name_tok.range, msg)
-                .add_note(RawSubDiagnostic::new(
-                    "previous definition here",
-                    prev_range.into(),
-                ))
+                .add_note(note)
+                .set_id(DiagnosticId::MacroRedefined)
                 .emit()?;
         }
 
@@ -132,20 +302,29 @@ impl<'a, 'b, 's, 'h> NextEventCtx<'a, 'b, 's, 'h> {
     fn consume_macro_def(&mut self, name_tok: Token<Symbol>) -> DResult<Option<MacroDef>> {
         let mut tokens = Vec::new();
 
-        if let Some(ppt) = self.next_token()?.non_eod() {
+        let body = if let Some(ppt) = self.next_token()?.non_eod() {
             if !ppt.leading_trivia {
                 if ppt.data() == TokenKind::Punct(PunctKind::LParen) {
-                    let params = match self.consume_macro_params()? {
+                    let (params, variadic) = match self.consume_macro_params()? {
                         Some(params) => params,
                         None => return Ok(None),
                     };
 
+                    let replacement = self.consume_macro_body(tokens, variadic)?;
+                    if !self.check_stringize_operands(replacement.tokens(), &params, variadic)?
+                        || (variadic && !self.check_va_opt_groups(replacement.tokens())?)
+                    {
+                        return Ok(None);
+                    }
+
                     return Ok(Some(MacroDef {
                         name_tok,
                         kind: MacroDefKind::Function {
                             params,
-                            replacement: self.consume_macro_body(tokens)?,
+                            variadic,
+                            replacement,
                         },
+                        origin: self.macro_origin,
                     }));
                 }
 
@@ -154,79 +333,254 @@ impl<'a, 'b, 's, 'h> NextEventCtx<'a, 'b, 's, 'h> {
                         ppt.range(),
                         "object-like macros require whitespace after the macro name",
                     )
-                    .set_suggestion(RawSuggestion::new(ppt.range().start(), " "))
+                    .add_suggestion(RawSuggestion::new(ppt.range().start(), " "))
+                    .set_id(DiagnosticId::MissingMacroWhitespace)
                     .emit()?;
             }
 
-            tokens.push(ppt)
-        }
+            tokens.push(ppt);
+            self.consume_macro_body(tokens, false)?
+        } else {
+            // The end of the directive has already been consumed, so the body is empty.
+            ReplacementList::new(tokens)
+        };
 
         Ok(Some(MacroDef {
             name_tok,
-            kind: MacroDefKind::Object(self.consume_macro_body(tokens)?),
+            kind: MacroDefKind::Object(body),
+            origin: self.macro_origin,
         }))
     }
 
-    fn consume_macro_params(&mut self) -> DResult<Option<Vec<Symbol>>> {
+    /// Consumes the parameter list of a function-like macro definition, after the opening
+    /// parenthesis.
+    ///
+    /// Returns the names of the parameters and whether the macro is variadic, in which case the
+    /// last parameter is named `__VA_ARGS__` (§6.10.3p12).
+    fn consume_macro_params(&mut self) -> DResult<Option<(Vec<Symbol>, bool)>> {
         let mut params = Vec::new();
 
-        let ppt = self.next_directive_token()?;
-        match ppt.data() {
-            TokenKind::Punct(PunctKind::RParen) => return Ok(Some(params)),
-            TokenKind::Ident(param) => params.push(param),
-            _ => {
-                self.report_and_advance(ppt, "expected a parameter name or ')'")?;
-                return Ok(None);
-            }
+        let mut ppt = self.next_directive_token()?;
+        if ppt.data() == TokenKind::Punct(PunctKind::RParen) {
+            return Ok(Some((params, false)));
         }
 
         loop {
-            let ppt = self.next_directive_token()?;
             match ppt.data() {
-                TokenKind::Punct(PunctKind::Comma) => {}
-                TokenKind::Punct(PunctKind::RParen) => break Ok(Some(params)),
-                _ => {
-                    self.report_and_advance(ppt, "expected a ')'")?;
-                    break Ok(None);
+                TokenKind::Punct(PunctKind::Ellipsis) => {
+                    if self.processor.lang().standard < LangStandard::C99 {
+                        self.report_extension(ppt.range(), "variadic macros are a C99 feature")?;
+                    }
+                    params.push(self.ctx.interner.intern("__VA_ARGS__"));
+
+                    let ppt = self.next_directive_token()?;
+                    if ppt.data() != TokenKind::Punct(PunctKind::RParen) {
+                        self.report_and_advance(ppt, "expected a ')' after '...'")?;
+                        return Ok(None);
+                    }
+
+                    break Ok(Some((params, true)));
                 }
-            }
 
-            let ppt = self.next_directive_token()?;
-            match ppt.data() {
                 TokenKind::Ident(param) => {
+                    let name = &self.ctx.interner[param];
+                    if name == "__VA_ARGS__" {
+                        self.report_and_advance(
+                            ppt,
+                            "'__VA_ARGS__' can only appear in the expansion of a variadic macro",
+                        )?;
+                        return Ok(None);
+                    }
+
                     if params.contains(&param) {
-                        let msg =
-                            format!("duplicate macro parameter '{}'", &self.ctx.interner[param]);
-                        self.report_and_advance(ppt, &msg)?;
+                        let msg = Message::new("duplicate macro parameter '{name}'")
+                            .arg("name", MessageArg::symbol(name));
+                        self.report_and_advance(ppt, msg)?;
                         return Ok(None);
                     }
 
                     params.push(param);
                 }
+
                 _ => {
-                    self.report_and_advance(ppt, "expected a parameter name")?;
+                    let msg = if params.is_empty() {
+                        "expected a parameter name, '...' or ')'"
+                    } else {
+                        "expected a parameter name or '...'"
+                    };
+                    self.report_and_advance(ppt, msg)?;
                     break Ok(None);
                 }
             }
+
+            let next = self.next_directive_token()?;
+            match next.data() {
+                TokenKind::Punct(PunctKind::Comma) => {}
+                TokenKind::Punct(PunctKind::RParen) => break Ok(Some((params, false))),
+                _ => {
+                    self.report_and_advance(next, "expected a ')'")?;
+                    break Ok(None);
+                }
+            }
+
+            ppt = self.next_directive_token()?;
         }
     }
 
-    fn consume_macro_body(&mut self, mut tokens: Vec<PpToken>) -> DResult<ReplacementList> {
+    /// Consumes the remainder of a macro definition's replacement list, appending it to `tokens`.
+    ///
+    /// `variadic` indicates whether the macro being defined is variadic; if it isn't, any uses of
+    /// `__VA_ARGS__` and `__VA_OPT__` in the replacement list are reported.
+    fn consume_macro_body(
+        &mut self,
+        mut tokens: Vec<PpToken>,
+        variadic: bool,
+    ) -> DResult<ReplacementList> {
         while let Some(ppt) = self.next_token()?.non_eod() {
             tokens.push(ppt);
         }
 
+        if !variadic {
+            self.check_no_va_uses(&tokens)?;
+        }
+
         Ok(ReplacementList::new(tokens))
     }
 
+    /// Checks that every `#` in the replacement list `tokens` of a function-like macro is followed
+    /// by one of `params` (§6.10.3.2p1), reporting an error otherwise.
+    ///
+    /// If the macro is `variadic`, `#` may also be followed by a `__VA_OPT__` (C23 §6.10.4.1).
+    fn check_stringize_operands(
+        &mut self,
+        tokens: &[PpToken],
+        params: &[Symbol],
+        variadic: bool,
+    ) -> DResult<bool> {
+        let va_opt = if variadic {
+            self.ctx.interner.get("__VA_OPT__")
+        } else {
+            None
+        };
+        let mut tokens = tokens.iter();
+
+        while let Some(ppt) = tokens.next() {
+            if ppt.data() != TokenKind::Punct(PunctKind::Hash) {
+                continue;
+            }
+
+            let is_param = |ppt: &PpToken| match ppt.data() {
+                TokenKind::Ident(ident) => params.contains(&ident) || Some(ident) == va_opt,
+                _ => false,
+            };
+
+            if !tokens.next().is_some_and(is_param) {
+                self.reporter()
+                    .error(ppt.range(), "'#' is not followed by a macro parameter")
+                    .emit()?;
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Reports any uses of `__VA_ARGS__` or `__VA_OPT__` in `tokens`, which is the replacement
+    /// list of a macro that is not variadic.
+    ///
+    /// These identifiers may only appear in the replacement lists of variadic macros (§6.10.3p5,
+    /// C23 §6.10.4.1).
+    fn check_no_va_uses(&mut self, tokens: &[PpToken]) -> DResult<()> {
+        for ppt in tokens {
+            if let TokenKind::Ident(ident) = ppt.data() {
+                let name = &self.ctx.interner[ident];
+                if name == "__VA_ARGS__" || name == "__VA_OPT__" {
+                    let msg = Message::new(
+                        "'{name}' can only appear in the expansion of a variadic macro",
+                    )
+                    .arg("name", MessageArg::symbol(name));
+                    self.reporter()
+                        .warn(ppt.range(), msg)
+                        .set_id(DiagnosticId::VaArgsOutsideVariadic)
+                        .emit()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that every `__VA_OPT__` in `tokens` (the replacement list of a variadic macro) is
+    /// followed by a parenthesized group of tokens, which does not itself contain `__VA_OPT__`.
+    ///
+    /// Uses of `__VA_OPT__` before C23 are also reported if pedantic diagnostics are enabled.
+    fn check_va_opt_groups(&mut self, tokens: &[PpToken]) -> DResult<bool> {
+        let va_opt = match self.ctx.interner.get("__VA_OPT__") {
+            Some(va_opt) => va_opt,
+            // If the identifier has never been seen, it cannot appear in `tokens`.
+            None => return Ok(true),
+        };
+        let mut tokens = tokens.iter();
+
+        while let Some(va_opt_tok) = tokens.next() {
+            if va_opt_tok.data() != TokenKind::Ident(va_opt) {
+                continue;
+            }
+
+            if self.processor.lang().standard < LangStandard::C23 {
+                self.report_extension(va_opt_tok.range(), "'__VA_OPT__' is a C23 feature")?;
+            }
+
+            match tokens.next() {
+                Some(ppt) if ppt.data() == TokenKind::Punct(PunctKind::LParen) => {}
+                _ => {
+                    self.reporter()
+                        .error(va_opt_tok.range(), "'__VA_OPT__' must be followed by '('")
+                        .emit()?;
+                    return Ok(false);
+                }
+            }
+
+            let mut paren_level = 1;
+            while paren_level > 0 {
+                let ppt = match tokens.next() {
+                    Some(ppt) => ppt,
+                    None => {
+                        self.reporter()
+                            .error(va_opt_tok.range(), "unterminated '__VA_OPT__'")
+                            .emit()?;
+                        return Ok(false);
+                    }
+                };
+
+                match ppt.data() {
+                    TokenKind::Punct(PunctKind::LParen) => paren_level += 1,
+                    TokenKind::Punct(PunctKind::RParen) => paren_level -= 1,
+                    TokenKind::Ident(ident) if ident == va_opt => {
+                        self.reporter()
+                            .error(ppt.range(), "'__VA_OPT__' cannot be nested")
+                            .emit()?;
+                        return Ok(false);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
     fn handle_undef_directive(&mut self) -> DResult<()> {
-        let name = match self.expect_macro_name()? {
+        let name_tok = match self.expect_macro_name()? {
             Some(tok) => tok,
             None => return Ok(()),
-        }
-        .data;
+        };
+
+        self.check_reserved_macro_name(name_tok)?;
 
-        self.macro_state.undef(name);
+        let prev = self.macro_state.undef(name_tok.data);
+        self.callbacks
+            .macro_undefined(self.ctx, name_tok, prev.as_ref())?;
         self.finish_directive()
     }
 
@@ -242,17 +596,40 @@ impl<'a, 'b, 's, 'h> NextEventCtx<'a, 'b, 's, 'h> {
         }
     }
 
-    fn handle_include_directive(&mut self) -> DResult<Option<Event>> {
+    /// Reports a diagnostic if a directive in the source defines or undefines `name_tok`, and its
+    /// name is reserved to the implementation.
+    ///
+    /// Predefined and command-line macros are exempt, as they are expected to use such names.
+    fn check_reserved_macro_name(&mut self, name_tok: Token<Symbol>) -> DResult<()> {
+        let name = &self.ctx.interner[name_tok.data];
+        if self.macro_origin != MacroOrigin::Source || !is_reserved_macro_name(name) {
+            return Ok(());
+        }
+
+        let msg = Message::new("macro name '{name}' is reserved to the implementation")
+            .arg("name", MessageArg::symbol(name));
+        let level = self.processor.pedantic_level().unwrap_or(Level::Warning);
+
+        self.ctx
+            .reporter()
+            .report(level, name_tok.range, msg)
+            .set_id(DiagnosticId::ReservedMacroIdentifier)
+            .emit()
+    }
+
+    fn handle_include_directive(&mut self, next: bool) -> DResult<Option<Event>> {
         let start = self.processor.pos();
-        let reader = self.processor.reader();
 
-        let (filename, kind) = if reader.eat('<') {
-            (self.consume_include_name('>')?, IncludeKind::Angled)
-        } else if reader.eat('"') {
-            (self.consume_include_name('"')?, IncludeKind::Quoted)
+        let (filename, kind) = if let Some(filename_kind) = self.consume_header_name()? {
+            self.finish_directive()?;
+            filename_kind
         } else {
-            match self.consume_token_include_name()? {
-                Some(filename_kind) => filename_kind,
+            match self.consume_expanded_header_name()? {
+                Some(filename_kind) => {
+                    let ppt = self.next_expanded_directive_token()?;
+                    self.finish_expanded_directive(ppt)?;
+                    filename_kind
+                }
                 None => return Ok(None),
             }
         };
@@ -260,93 +637,954 @@ impl<'a, 'b, 's, 'h> NextEventCtx<'a, 'b, 's, 'h> {
         let len = self.processor.pos().offset_from(start);
 
         Ok(Some(Event::Include {
+            filename,
+            kind,
+            next,
+            range: SourceRange::new(start, len),
+        }))
+    }
+
+    fn handle_embed_directive(&mut self) -> DResult<Option<Event>> {
+        let start = self.processor.pos();
+
+        let (filename, kind) = if let Some(filename_kind) = self.consume_header_name()? {
+            filename_kind
+        } else {
+            match self.consume_expanded_header_name()? {
+                Some(filename_kind) => filename_kind,
+                None => return Ok(None),
+            }
+        };
+
+        let len = self.processor.pos().offset_from(start);
+
+        let params = match self.consume_embed_params()? {
+            Some(params) => params,
+            None => return Ok(None),
+        };
+
+        Ok(Some(Event::Embed {
             filename,
             kind,
             range: SourceRange::new(start, len),
+            params,
         }))
     }
 
-    fn consume_include_name(&mut self, term: char) -> DResult<PathBuf> {
-        let reader = self.processor.reader();
+    /// Consumes the header name of an `#include` or `#embed` directive that does not match either of
+    /// the literal forms. The directive's tokens are macro-expanded, and should then form either a
+    /// string literal or a sequence of tokens enclosed in `<` and `>` (§6.10.2p4).
+    fn consume_expanded_header_name(&mut self) -> DResult<Option<(PathBuf, IncludeKind)>> {
+        let ppt = self.next_expanded_directive_token()?;
 
-        reader.begin_tok();
-        reader.eat_while(|c| c != '\n' && c != term);
-        let filename = reader.cur_content().cleaned_str().into_owned().into();
+        match ppt.data() {
+            TokenKind::Str(lit) if is_plain_str(&self.ctx.interner[lit]) => {
+                let spelling = &self.ctx.interner[lit];
+                let filename = spelling[1..spelling.len() - 1].into();
+                Ok(Some((filename, IncludeKind::Quoted)))
+            }
+
+            TokenKind::Punct(PunctKind::Less) => {
+                let mut filename = String::new();
+                loop {
+                    let ppt = self.next_expanded_directive_token()?;
+                    match ppt.data() {
+                        TokenKind::Punct(PunctKind::Greater) => break,
+                        TokenKind::Eof => {
+                            self.reporter()
+                                .error_expected_delim(ppt.range().start(), '>')
+                                .emit()?;
+                            return Ok(None);
+                        }
+                        _ => write!(filename, "{}", ppt.display(self.ctx)).unwrap(),
+                    }
+                }
+                Ok(Some((filename.into(), IncludeKind::Angled)))
+            }
 
-        if !reader.eat(term) {
-            let pos = self.processor.pos();
-            self.reporter().error_expected_delim(pos, term).emit()?;
+            _ => {
+                self.reporter()
+                    .error(ppt.range(), r#"expected "filename" or <filename>"#)
+                    .emit()?;
+                self.skip_expanded_directive_tokens(ppt)?;
+                Ok(None)
+            }
         }
+    }
 
-        self.finish_directive()?;
-        Ok(filename)
+    /// Consumes the (macro-expanded) parameters of an `#embed` directive, up to the end of the
+    /// line (C23 §6.10.3.2).
+    ///
+    /// Returns `None` if the parameters are invalid, in which case an error has been reported and
+    /// the rest of the directive has been consumed.
+    fn consume_embed_params(&mut self) -> DResult<Option<EmbedParams>> {
+        let mut params = EmbedParams::default();
+
+        loop {
+            let name_tok = self.next_expanded_directive_token()?;
+            let name = match name_tok.data() {
+                TokenKind::Eof => break Ok(Some(params)),
+                TokenKind::Ident(name) => name,
+                _ => {
+                    self.reporter()
+                        .error(name_tok.range(), "expected an embed parameter")
+                        .emit()?;
+                    self.skip_expanded_directive_tokens(name_tok)?;
+                    return Ok(None);
+                }
+            };
+
+            // Every standard parameter may also be spelled surrounded by double underscores.
+            let name = &self.ctx.interner[name];
+            let name = name
+                .strip_prefix("__")
+                .and_then(|name| name.strip_suffix("__"))
+                .unwrap_or(name)
+                .to_owned();
+
+            if !matches!(&*name, "limit" | "prefix" | "suffix" | "if_empty") {
+                self.reporter()
+                    .error(
+                        name_tok.range(),
+                        Message::new("unknown embed parameter '{name}'")
+                            .arg("name", MessageArg::symbol(name)),
+                    )
+                    .emit()?;
+                self.skip_expanded_directive_tokens(name_tok)?;
+                return Ok(None);
+            }
+
+            let (clause, rparen) = match self.consume_embed_param_clause(&name)? {
+                Some(clause) => clause,
+                None => return Ok(None),
+            };
+
+            let duplicate = match &*name {
+                "limit" => {
+                    let limit = match expr_eval::eval(self.ctx, &clause, rparen.range().start())? {
+                        Some(Value::Signed(val)) => u64::try_from(val).ok(),
+                        Some(Value::Unsigned(val)) => Some(val),
+                        None => {
+                            self.skip_expanded_directive_tokens(rparen)?;
+                            return Ok(None);
+                        }
+                    };
+
+                    let limit = match limit {
+                        Some(limit) => limit,
+                        None => {
+                            self.reporter()
+                                .error(
+                                    name_tok.range(),
+                                    "the 'limit' parameter must not be negative",
+                                )
+                                .emit()?;
+                            self.skip_expanded_directive_tokens(rparen)?;
+                            return Ok(None);
+                        }
+                    };
+
+                    params.limit.replace(limit).is_some()
+                }
+                "prefix" => params.prefix.replace(clause).is_some(),
+                "suffix" => params.suffix.replace(clause).is_some(),
+                _ => params.if_empty.replace(clause).is_some(),
+            };
+
+            if duplicate {
+                self.reporter()
+                    .error(
+                        name_tok.range(),
+                        Message::new("duplicate embed parameter '{name}'")
+                            .arg("name", MessageArg::symbol(name)),
+                    )
+                    .emit()?;
+                self.skip_expanded_directive_tokens(rparen)?;
+                return Ok(None);
+            }
+        }
     }
 
-    fn consume_token_include_name(&mut self) -> DResult<Option<(PathBuf, IncludeKind)>> {
-        let Token {
-            range,
-            data: content,
-        } = self.consume_expanded_directive_string()?;
+    /// Consumes the parenthesized clause of the embed parameter `name`, returning the tokens
+    /// within it along with the closing parenthesis.
+    fn consume_embed_param_clause(
+        &mut self,
+        name: &str,
+    ) -> DResult<Option<(Vec<PpToken>, PpToken)>> {
+        let lparen = self.next_expanded_directive_token()?;
+        if lparen.data() != TokenKind::Punct(PunctKind::LParen) {
+            self.reporter()
+                .error(
+                    lparen.range(),
+                    Message::new("expected '(' after embed parameter '{name}'")
+                        .arg("name", MessageArg::symbol(name)),
+                )
+                .emit()?;
+            self.skip_expanded_directive_tokens(lparen)?;
+            return Ok(None);
+        }
 
-        let (kind, term) = if content.starts_with('"') {
-            (IncludeKind::Quoted, '"')
-        } else if content.starts_with('<') {
-            (IncludeKind::Angled, '>')
-        } else {
+        let mut tokens = Vec::new();
+        let mut paren_level = 0;
+
+        loop {
+            let ppt = self.next_expanded_directive_token()?;
+            match ppt.data() {
+                TokenKind::Punct(PunctKind::LParen) => paren_level += 1,
+                TokenKind::Punct(PunctKind::RParen) if paren_level == 0 => {
+                    break Ok(Some((tokens, ppt)));
+                }
+                TokenKind::Punct(PunctKind::RParen) => paren_level -= 1,
+                TokenKind::Eof => {
+                    self.reporter()
+                        .error_expected_delim(ppt.range().start(), ')')
+                        .add_note(RawSubDiagnostic::new(
+                            "to match this '('",
+                            lparen.range().into(),
+                        ))
+                        .emit()?;
+                    return Ok(None);
+                }
+                _ => {}
+            }
+
+            tokens.push(ppt);
+        }
+    }
+
+    fn handle_pragma_directive(&mut self, id_range: SourceRange) -> DResult<Event> {
+        let mut tokens = Vec::new();
+        while let Some(ppt) = self.next_token()?.non_eod() {
+            tokens.push(ppt);
+        }
+
+        Ok(Event::Pragma(Pragma {
+            range: id_range,
+            tokens,
+        }))
+    }
+
+    fn is_pragma_operator(&self, ppt: PpToken) -> bool {
+        matches!(ppt.data(), TokenKind::Ident(name) if &self.ctx.interner[name] == "_Pragma")
+    }
+
+    /// Handles the `_Pragma` operator (§6.10.9) named by `pragma_tok`, whose operands are read
+    /// from the macro-expanded token stream.
+    ///
+    /// The string literal operand is destringized and lexed as the contents of a pragma
+    /// directive. Returns `None` if the operator was malformed.
+    fn handle_pragma_operator(&mut self, pragma_tok: PpToken) -> DResult<Option<Event>> {
+        let lparen = self.next_pragma_operand_token()?;
+        if lparen.data() != TokenKind::Punct(PunctKind::LParen) {
+            self.report_malformed_pragma_operator(lparen)?;
+            return Ok(None);
+        }
+
+        let lit = self.next_pragma_operand_token()?;
+        let lit_sym = match lit.data() {
+            TokenKind::Str(sym) => sym,
+            _ => {
+                self.report_malformed_pragma_operator(lit)?;
+                return Ok(None);
+            }
+        };
+
+        let rparen = self.next_pragma_operand_token()?;
+        if rparen.data() != TokenKind::Punct(PunctKind::RParen) {
             self.reporter()
-                .error(range, r#"expected "filename" or <filename>"#)
+                .error_expected_delim(rparen.range().start(), ')')
+                .add_note(RawSubDiagnostic::new(
+                    "to match this '('",
+                    lparen.range().into(),
+                ))
                 .emit()?;
             return Ok(None);
+        }
+
+        // The lexer has already reported unterminated literals.
+        if self.ctx.is_literal_truncated(lit.range()) {
+            return Ok(None);
+        }
+
+        let contents = destringize(&self.ctx.interner[lit_sym]);
+        let synth = SyntheticSource::builder(self.ctx)
+            .name("_Pragma")
+            .tokens([contents])
+            .build()?;
+
+        let mut prev_end = None;
+        let tokens = synth
+            .into_tokens()
+            .into_iter()
+            .map(|tok| {
+                let leading_trivia = prev_end.is_some_and(|end| end != tok.range.start());
+                prev_end = Some(tok.range.end());
+
+                PpToken {
+                    tok,
+                    line_start: false,
+                    leading_trivia,
+                    leading_trivia_range: None,
+                }
+            })
+            .collect();
+
+        Ok(Some(Event::Pragma(Pragma {
+            range: pragma_tok.range(),
+            tokens,
+        })))
+    }
+
+    /// Returns the next macro-expanded token in the operands of a `_Pragma` operator.
+    ///
+    /// Operands never extend into a preprocessing directive or past the end of the file: an `Eof`
+    /// token is returned in those cases, and the token responsible for it is not consumed.
+    fn next_pragma_operand_token(&mut self) -> DResult<PpToken> {
+        loop {
+            if let Some(ppt) = self
+                .next_expansion_token(None)
+                .map_err(PpError::expect_fatal)?
+            {
+                break Ok(ppt);
+            }
+
+            let ppt = MacroArgLexer::new(&mut self.processor).peek(self.ctx)?;
+            if ppt.is_directive_start() {
+                break Ok(ppt.map(|_| TokenKind::Eof));
+            }
+            if ppt.data() == TokenKind::Eof {
+                break Ok(ppt);
+            }
+
+            let ppt = self.next_real_token()?;
+            if !self.begin_expansion(ppt)? {
+                break Ok(ppt);
+            }
+        }
+    }
+
+    fn report_malformed_pragma_operator(&mut self, ppt: PpToken) -> DResult<()> {
+        self.reporter()
+            .error(
+                ppt.range(),
+                "'_Pragma' takes a parenthesized string literal",
+            )
+            .emit()
+    }
+
+    /// Consumes a header name token written directly in the directive, returning `None` if there
+    /// is none, in which case the operand should be macro-expanded instead (§6.10.2p4).
+    fn consume_header_name(&mut self) -> DResult<Option<(PathBuf, IncludeKind)>> {
+        let ppt = match self.processor.next_header_name(self.ctx)? {
+            Some(ppt) => ppt,
+            None => return Ok(None),
         };
 
-        let content = &content[1..];
+        match ppt.data() {
+            TokenKind::HeaderName(name) => Ok(Some(split_header_name(&self.ctx.interner[name]))),
+            _ => unreachable!("expected a header name token"),
+        }
+    }
+
+    fn handle_line_directive(&mut self) -> DResult<()> {
+        let ppt = self.next_expanded_directive_token()?;
+        let line = match self.parse_line_number(ppt)? {
+            Some(line) => line,
+            None => return self.skip_expanded_directive_tokens(ppt),
+        };
 
-        let name = match content.find(term) {
-            Some(end) => &content[..end],
-            None => {
+        let mut ppt = self.next_expanded_directive_token()?;
+        let filename = match ppt.data() {
+            TokenKind::Eof => None,
+            TokenKind::Str(lit) if is_plain_str(&self.ctx.interner[lit]) => {
+                let spelling = &self.ctx.interner[lit];
+                let filename = unescape_line_filename(&spelling[1..spelling.len() - 1]);
+                ppt = self.next_expanded_directive_token()?;
+                Some(FileName::real(filename))
+            }
+            _ => {
                 self.reporter()
-                    .error_expected_delim(range.end(), term)
+                    .error(ppt.range(), "invalid filename for '#line' directive")
                     .emit()?;
-                content
+                return self.skip_expanded_directive_tokens(ppt);
             }
         };
 
-        Ok(Some((name.into(), kind)))
+        self.finish_expanded_directive(ppt)?;
+
+        // The end of the directive has been consumed, so we are now at the start of the next line.
+        let next_line = self.processor.pos();
+        self.ctx
+            .smap
+            .add_line_override(next_line, line - 1, filename);
+        Ok(())
     }
 
-    fn consume_expanded_directive_string(&mut self) -> DResult<Token<String>> {
-        let mut contents = String::new();
+    /// Parses the line number in a `#line` directive from `ppt`, which must be a simple digit
+    /// sequence in the range [1, 2147483647] (§6.10.4p3).
+    fn parse_line_number(&mut self, ppt: PpToken) -> DResult<Option<u32>> {
+        let spelling = match ppt.data() {
+            TokenKind::Number(num) => &self.ctx.interner[num],
+            _ => {
+                self.reporter()
+                    .error(
+                        ppt.range(),
+                        "'#line' directive requires a positive integer argument",
+                    )
+                    .emit()?;
+                return Ok(None);
+            }
+        };
+
+        if !spelling.bytes().all(|c| c.is_ascii_digit()) {
+            self.reporter()
+                .error(
+                    ppt.range(),
+                    "'#line' directive requires a simple digit sequence",
+                )
+                .emit()?;
+            return Ok(None);
+        }
+
+        match spelling.parse() {
+            Ok(line) if (1..=2147483647).contains(&line) => Ok(Some(line)),
+            _ => {
+                self.reporter()
+                    .error(ppt.range(), "line number out of range in '#line' directive")
+                    .emit()?;
+                Ok(None)
+            }
+        }
+    }
 
-        let start_pos = self.processor.pos();
-        let end_pos = loop {
+    /// Handles an `#error` or `#warning` directive, reporting its message at the specified level.
+    ///
+    /// The message is the raw spelling of the rest of the line, which need not consist of valid
+    /// tokens. Interior whitespace and comments are preserved; only escaped newlines and trailing
+    /// whitespace are removed.
+    fn handle_diagnostic_directive(&mut self, id_range: SourceRange, level: Level) -> DResult<()> {
+        let start = self.processor.pos();
+        self.processor.reader().eat_while(|c| c != '\n');
+        let range = SourceRange::new(start, self.processor.pos().offset_from(start));
+
+        let msg = lex::get_cleaned_spelling(self.ctx.smap, range)
+            .trim_end()
+            .to_owned();
+        let mut reporter = self.ctx.reporter();
+        let diag = reporter.report(level, id_range, msg);
+        if level == Level::Warning {
+            diag.set_id(DiagnosticId::PoundWarning).emit()?;
+        } else {
+            diag.emit()?;
+        }
+        self.advance_to_eod()
+    }
+
+    fn handle_if_directive(&mut self, id_range: SourceRange) -> PpResult<()> {
+        let value = self.eval_condition()?;
+        self.enter_conditional(id_range, value)
+    }
+
+    fn handle_ifdef_directive(&mut self, id_range: SourceRange, expected: bool) -> PpResult<()> {
+        let name = self.expect_macro_name()?;
+        let value = match name {
+            Some(name_tok) => {
+                self.finish_directive()?;
+                self.is_defined(name_tok.data) == expected
+            }
+            None => false,
+        };
+
+        if self.conditionals.depth() == 0 {
+            match name {
+                Some(name_tok) if !expected => self.guard.open(name_tok.data, value),
+                _ => self.guard.invalidate(),
+            }
+        }
+
+        self.enter_conditional(id_range, value)
+    }
+
+    fn handle_elif_directive(&mut self, id_range: SourceRange) -> PpResult<()> {
+        // The condition is never evaluated here: we only get here when the previous group was
+        // included, so the remainder of the block is skipped.
+        self.advance_to_eod()?;
+
+        if self.conditionals.depth() == 1 {
+            self.guard.invalidate();
+        }
+
+        if self.check_in_conditional(id_range, "#elif")? {
+            // A group of the block has already been included, even if this follows an `#else`.
+            self.check_no_else(id_range, "#elif")?;
+            self.skip_conditional_block()?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_else_directive(&mut self, id_range: SourceRange) -> PpResult<()> {
+        self.finish_directive()?;
+
+        if self.conditionals.depth() == 1 {
+            self.guard.invalidate();
+        }
+
+        if self.check_in_conditional(id_range, "#else")? {
+            if self.check_no_else(id_range, "#else")? {
+                self.conditionals.top_mut().unwrap().else_range = Some(id_range);
+            }
+            // A group of the block has already been included, even if this follows an `#else`.
+            self.skip_conditional_block()?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_endif_directive(&mut self, id_range: SourceRange) -> DResult<()> {
+        self.finish_directive()?;
+
+        if self.conditionals.depth() == 1 {
+            self.guard.close();
+        }
+
+        if self.check_in_conditional(id_range, "#endif")? {
+            self.conditionals.pop();
+        }
+
+        Ok(())
+    }
+
+    /// Opens a new conditional block, skipping its first group unless `value` is set.
+    fn enter_conditional(&mut self, id_range: SourceRange, value: bool) -> PpResult<()> {
+        self.conditionals.push(id_range, value);
+
+        if !value {
+            self.skip_conditional_block()?;
+        }
+
+        Ok(())
+    }
+
+    /// Skips groups in the innermost conditional block until one that should be included is found
+    /// or the block is closed.
+    ///
+    /// The skipped groups are only scanned for directives, which are used to track nested
+    /// conditionals. The skipped lines are recorded and reported to the callbacks.
+    fn skip_conditional_block(&mut self) -> PpResult<()> {
+        let start = self.processor.pos();
+
+        let end = match self.skip_conditional_groups()? {
+            Some(end) => end,
+            None => self.processor.pos(),
+        };
+
+        let range = SourceRange::new(start, end.offset_from(start));
+        self.conditionals.record_skipped(range);
+        self.callbacks.region_skipped(self.ctx, range)?;
+        Ok(())
+    }
+
+    /// Skips groups as described in [`skip_conditional_block()`](Self::skip_conditional_block()),
+    /// returning the position of the directive ending the skipped groups, if any.
+    fn skip_conditional_groups(&mut self) -> PpResult<Option<SourcePos>> {
+        let mut depth = 0;
+
+        while let Some(hash_pos) = self.processor.skip_to_directive() {
+            cancel::check_cancelled(self.cancellation_token)?;
+            let ppt = self.next_directive_token()?;
+
+            let name = match ppt.data() {
+                TokenKind::Ident(name) => &self.ctx.interner[name],
+                _ => {
+                    self.advance_to_eod()?;
+                    continue;
+                }
+            };
+
+            match name {
+                "if" | "ifdef" | "ifndef" => {
+                    depth += 1;
+                    self.advance_to_eod()?;
+                }
+                "endif" if depth > 0 => {
+                    depth -= 1;
+                    self.advance_to_eod()?;
+                }
+                "endif" => {
+                    self.finish_directive()?;
+                    self.conditionals.pop();
+                    return Ok(Some(hash_pos));
+                }
+                "elif" if depth == 0 => {
+                    if !self.check_no_else(ppt.range(), "#elif")?
+                        || self.conditionals.top_mut().unwrap().taken
+                    {
+                        self.advance_to_eod()?;
+                    } else if self.eval_condition()? {
+                        self.conditionals.top_mut().unwrap().taken = true;
+                        return Ok(Some(hash_pos));
+                    }
+                }
+                "else" if depth == 0 => {
+                    self.finish_directive()?;
+
+                    if self.check_no_else(ppt.range(), "#else")? {
+                        let cond = self.conditionals.top_mut().unwrap();
+                        cond.else_range = Some(ppt.range());
+
+                        if !cond.taken {
+                            cond.taken = true;
+                            return Ok(Some(hash_pos));
+                        }
+                    }
+                }
+                _ => self.advance_to_eod()?,
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Evaluates the condition of an `#if` or `#elif` directive, consuming the remainder of the
+    /// line.
+    ///
+    /// Invalid conditions are reported and evaluate to `false`.
+    fn eval_condition(&mut self) -> DResult<bool> {
+        let mut tokens = Vec::new();
+
+        let eod = loop {
             let ppt = self.next_expanded_directive_token()?;
-            if ppt.data() == TokenKind::Eof {
-                break ppt.range().start();
+
+            match ppt.data() {
+                TokenKind::Eof => break ppt,
+                TokenKind::Ident(name) => {
+                    let result = match &self.ctx.interner[name] {
+                        "defined" => self.consume_defined(ppt)?,
+                        "__has_include" => self.consume_has_include(ppt, false)?,
+                        "__has_include_next" => self.consume_has_include(ppt, true)?,
+                        "__has_feature" => self.consume_has_feature(ppt, false)?,
+                        "__has_extension" => self.consume_has_feature(ppt, true)?,
+                        _ => Some(ppt),
+                    };
+
+                    match result {
+                        Some(ppt) => tokens.push(ppt),
+                        None => return Ok(false),
+                    }
+                }
+                _ => tokens.push(ppt),
             }
+        };
+
+        let value = expr_eval::eval(self.ctx, &tokens, eod.range().start())?;
+        Ok(value.is_some_and(|value| value.is_nonzero()))
+    }
 
-            write!(contents, "{}", ppt.display(self.ctx)).unwrap();
+    /// Consumes the operand of the `defined` operator `defined_tok`, returning a token holding `1`
+    /// or `0` depending on whether the named macro is defined.
+    ///
+    /// The operand is never macro-expanded. Returns `None` if the operand is invalid, in which case
+    /// an error has been reported and the rest of the directive has been consumed.
+    fn consume_defined(&mut self, defined_tok: PpToken) -> DResult<Option<PpToken>> {
+        let mut ppt = self.next_unexpanded_directive_token()?;
+
+        let lparen = if ppt.data() == TokenKind::Punct(PunctKind::LParen) {
+            let lparen = ppt;
+            ppt = self.next_unexpanded_directive_token()?;
+            Some(lparen)
+        } else {
+            None
+        };
+
+        let name = match ppt.data() {
+            TokenKind::Ident(name) => name,
+            _ => {
+                self.reporter()
+                    .error(ppt.range(), "macro name must be an identifier")
+                    .emit()?;
+                self.skip_expanded_directive_tokens(ppt)?;
+                return Ok(None);
+            }
         };
 
-        Ok(Token::new(
-            contents,
-            SourceRange::new(start_pos, end_pos.offset_from(start_pos)),
-        ))
+        let mut end = ppt.range().end();
+
+        if let Some(lparen) = lparen {
+            let rparen = self.next_unexpanded_directive_token()?;
+            if rparen.data() != TokenKind::Punct(PunctKind::RParen) {
+                self.reporter()
+                    .error_expected_delim(rparen.range().start(), ')')
+                    .add_note(RawSubDiagnostic::new(
+                        "to match this '('",
+                        lparen.range().into(),
+                    ))
+                    .emit()?;
+                self.skip_expanded_directive_tokens(rparen)?;
+                return Ok(None);
+            }
+            end = rparen.range().end();
+        }
+
+        let value = self.is_defined(name);
+        Ok(Some(self.operator_result(defined_tok, end, value)))
     }
 
-    fn handle_error_directive(&mut self, id_range: SourceRange) -> DResult<()> {
-        let mut msg = String::new();
-        while let Some(ppt) = self.next_token()?.non_eod() {
-            write!(msg, "{}", ppt.display(self.ctx)).unwrap();
+    /// Checks whether `name` should be considered defined by `defined`, `#ifdef` and `#ifndef`.
+    ///
+    /// This includes the special operators available in `#if` expressions, so that their presence
+    /// can be tested for.
+    fn is_defined(&self, name: Symbol) -> bool {
+        self.macro_state.is_defined(name)
+            || matches!(
+                &self.ctx.interner[name],
+                "__has_include" | "__has_include_next" | "__has_feature" | "__has_extension"
+            )
+    }
+
+    /// Consumes the parenthesized header name following the `__has_include` (or
+    /// `__has_include_next`, if `next` is set) operator `op_tok`, returning a token holding `1` or
+    /// `0` depending on whether the header can be found.
+    ///
+    /// When written directly in the directive, the header name is read as in an `#include`
+    /// directive; otherwise, it is assembled from the macro-expanded tokens. Returns `None` if the
+    /// operand is invalid, in which case an error has been reported and the rest of the directive
+    /// has been consumed.
+    fn consume_has_include(&mut self, op_tok: PpToken, next: bool) -> DResult<Option<PpToken>> {
+        let from_file = !self.macro_state.has_pending_tokens();
+
+        let lparen = match self.expect_operator_lparen()? {
+            Some(lparen) => lparen,
+            None => return Ok(None),
+        };
+
+        if from_file {
+            self.processor.reader().eat_line_ws();
+        }
+
+        let header_name = if from_file {
+            self.consume_header_name()?
+        } else {
+            None
+        };
+
+        let (filename, kind) = if let Some(filename_kind) = header_name {
+            filename_kind
+        } else {
+            match self.consume_expanded_header_name()? {
+                Some(filename_kind) => filename_kind,
+                None => return Ok(None),
+            }
+        };
+
+        let rparen = match self.expect_operator_rparen(lparen)? {
+            Some(rparen) => rparen,
+            None => return Ok(None),
+        };
+
+        let next_from = if next && self.next_from.is_none() {
+            self.reporter()
+                .warn(op_tok.range(), "'__has_include_next' in main file")
+                .set_id(DiagnosticId::OutsideHeader)
+                .emit()?;
+            None
+        } else if next {
+            self.next_from
+        } else {
+            None
+        };
+
+        let found = self.stats.time(StatsPhase::IncludeIo, || {
+            self.include_loader
+                .probe(&filename, kind, self.file, next_from)
+        });
+        Ok(Some(self.operator_result(
+            op_tok,
+            rparen.range().end(),
+            found,
+        )))
+    }
+
+    /// Consumes the parenthesized feature name following the `__has_feature` (or
+    /// `__has_extension`, if `extension` is set) operator `op_tok`, returning a token holding `1`
+    /// or `0` depending on whether the feature is supported.
+    ///
+    /// The operand is never macro-expanded. Returns `None` if the operand is invalid, in which case
+    /// an error has been reported and the rest of the directive has been consumed.
+    fn consume_has_feature(
+        &mut self,
+        op_tok: PpToken,
+        extension: bool,
+    ) -> DResult<Option<PpToken>> {
+        let lparen = match self.expect_operator_lparen()? {
+            Some(lparen) => lparen,
+            None => return Ok(None),
+        };
+
+        let ppt = self.next_unexpanded_directive_token()?;
+        let name = match ppt.data() {
+            TokenKind::Ident(name) => name,
+            _ => {
+                self.reporter()
+                    .error(ppt.range(), "feature name must be an identifier")
+                    .emit()?;
+                self.skip_expanded_directive_tokens(ppt)?;
+                return Ok(None);
+            }
+        };
+
+        let rparen = match self.expect_operator_rparen(lparen)? {
+            Some(rparen) => rparen,
+            None => return Ok(None),
+        };
+
+        let name = &self.ctx.interner[name];
+        let supported = if extension {
+            self.features.has_extension(name)
+        } else {
+            self.features.has_feature(name)
+        };
+
+        Ok(Some(self.operator_result(
+            op_tok,
+            rparen.range().end(),
+            supported,
+        )))
+    }
+
+    /// Consumes the (unexpanded) opening parenthesis following an operator in an `#if`
+    /// expression, reporting an error and consuming the rest of the directive if it is missing.
+    fn expect_operator_lparen(&mut self) -> DResult<Option<PpToken>> {
+        let ppt = self.next_unexpanded_directive_token()?;
+        if ppt.data() == TokenKind::Punct(PunctKind::LParen) {
+            return Ok(Some(ppt));
+        }
+
+        self.reporter()
+            .error_expected_delim(ppt.range().start(), '(')
+            .emit()?;
+        self.skip_expanded_directive_tokens(ppt)?;
+        Ok(None)
+    }
+
+    /// Consumes the (unexpanded) closing parenthesis matching `lparen`, reporting an error and
+    /// consuming the rest of the directive if it is missing.
+    fn expect_operator_rparen(&mut self, lparen: PpToken) -> DResult<Option<PpToken>> {
+        let ppt = self.next_unexpanded_directive_token()?;
+        if ppt.data() == TokenKind::Punct(PunctKind::RParen) {
+            return Ok(Some(ppt));
+        }
+
+        self.reporter()
+            .error_expected_delim(ppt.range().start(), ')')
+            .add_note(RawSubDiagnostic::new(
+                "to match this '('",
+                lparen.range().into(),
+            ))
+            .emit()?;
+        self.skip_expanded_directive_tokens(ppt)?;
+        Ok(None)
+    }
+
+    /// Returns a token holding `1` or `0` depending on `value`, to replace the operator `op_tok`
+    /// and its operands (ending at `end`) in an `#if` expression.
+    fn operator_result(&mut self, op_tok: PpToken, end: SourcePos, value: bool) -> PpToken {
+        let value = if value { "1" } else { "0" };
+
+        // Attribute the result to the entire expression when it was written contiguously.
+        let range = self
+            .ctx
+            .smap
+            .get_unfragmented_range(FragmentedSourceRange::new(op_tok.range().start(), end))
+            .unwrap_or_else(|| op_tok.range());
+
+        PpToken {
+            tok: Token::new(TokenKind::Number(self.ctx.interner.intern(value)), range),
+            line_start: false,
+            leading_trivia: op_tok.leading_trivia,
+            leading_trivia_range: op_tok.leading_trivia_range,
+        }
+    }
+
+    /// Consumes and discards the remaining (macro-expanded) tokens of the current directive,
+    /// including any pending expansion tokens. `last` should be the last token consumed.
+    /// Warns if `ppt`, the macro-expanded token following the operands of a directive, is not the
+    /// end of the directive, and skips any remaining tokens.
+    fn finish_expanded_directive(&mut self, ppt: PpToken) -> DResult<()> {
+        if ppt.data() != TokenKind::Eof {
+            self.reporter()
+                .warn(ppt.range(), "extra tokens after preprocessing directive")
+                .set_id(DiagnosticId::ExtraTokens)
+                .emit()?;
+            self.skip_expanded_directive_tokens(ppt)?;
+        }
+
+        Ok(())
+    }
+
+    fn skip_expanded_directive_tokens(&mut self, mut last: PpToken) -> DResult<()> {
+        while last.data() != TokenKind::Eof {
+            last = self.next_expanded_directive_token()?;
+        }
+        Ok(())
+    }
+
+    /// Checks that there is an open conditional block for the directive `name`, reporting an error
+    /// if there is not.
+    fn check_in_conditional(&mut self, id_range: SourceRange, name: &str) -> DResult<bool> {
+        if self.conditionals.top_mut().is_some() {
+            return Ok(true);
+        }
+
+        self.reporter()
+            .error(
+                id_range,
+                Message::new("'{directive}' without '#if'").arg("directive", name),
+            )
+            .emit()?;
+        Ok(false)
+    }
+
+    /// Checks that the innermost conditional block has not had an `#else` yet, reporting an error
+    /// on the directive `name` if it has.
+    fn check_no_else(&mut self, id_range: SourceRange, name: &str) -> DResult<bool> {
+        let else_range = match self.conditionals.top_mut().unwrap().else_range {
+            Some(range) => range,
+            None => return Ok(true),
+        };
+
+        self.reporter()
+            .error(
+                id_range,
+                Message::new("'{directive}' after '#else'").arg("directive", name),
+            )
+            .add_note(RawSubDiagnostic::new(
+                "previous '#else' here",
+                else_range.into(),
+            ))
+            .emit()?;
+        Ok(false)
+    }
+
+    /// Reports any conditional blocks left open at the end of the file.
+    fn check_unterminated_conditionals(&mut self) -> DResult<()> {
+        let unterminated: Vec<_> = self.conditionals.drain().collect();
+
+        for cond in unterminated.into_iter().rev() {
+            self.reporter()
+                .error(cond.if_range, "unterminated conditional directive")
+                .emit()?;
         }
 
-        self.ctx.reporter().error(id_range, msg).emit()
+        Ok(())
     }
 
     fn finish_directive(&mut self) -> DResult<()> {
         if let Some(ppt) = self.next_token()?.non_eod() {
             self.reporter()
                 .warn(ppt.range(), "extra tokens after preprocessing directive")
-                .set_suggestion(RawSuggestion::new(ppt.range().start(), "// "))
+                .add_suggestion(RawSuggestion::new(ppt.range().start(), "// "))
+                .set_id(DiagnosticId::ExtraTokens)
                 .emit()?;
             self.advance_to_eod()?;
         }
@@ -357,25 +1595,44 @@ impl<'a, 'b, 's, 'h> NextEventCtx<'a, 'b, 's, 'h> {
     fn next_expanded_directive_token(&mut self) -> DResult<PpToken> {
         loop {
             if let Some(ppt) = self
-                .macro_state
-                .next_expansion_token(self.ctx, DirectiveLexer::new(&mut self.processor))?
+                .stats
+                .time(StatsPhase::MacroExpansion, || {
+                    self.macro_state.next_expansion_token(
+                        self.ctx,
+                        self.callbacks,
+                        DirectiveLexer::new(&mut self.processor),
+                        None,
+                    )
+                })
+                .map_err(PpError::expect_fatal)?
             {
                 break Ok(ppt);
             }
 
             let ppt = self.next_directive_token()?;
 
-            if !self.macro_state.begin_expansion(
-                self.ctx,
-                ppt,
-                DirectiveLexer::new(&mut self.processor),
-            )? {
+            if !self.stats.time(StatsPhase::MacroExpansion, || {
+                self.macro_state.begin_expansion(
+                    self.ctx,
+                    self.callbacks,
+                    ppt,
+                    DirectiveLexer::new(&mut self.processor),
+                )
+            })? {
                 break Ok(ppt);
             }
         }
     }
 
-    fn report_and_advance(&mut self, ppt: PpToken, msg: impl Into<String>) -> DResult<()> {
+    fn next_unexpanded_directive_token(&mut self) -> DResult<PpToken> {
+        self.macro_state.next_unexpanded_token(
+            self.ctx,
+            self.callbacks,
+            DirectiveLexer::new(&mut self.processor),
+        )
+    }
+
+    fn report_and_advance(&mut self, ppt: PpToken, msg: impl Into<Message>) -> DResult<()> {
         self.processor.report_and_advance(self.ctx, ppt, msg.into())
     }
 
@@ -395,7 +1652,95 @@ impl<'a, 'b, 's, 'h> NextEventCtx<'a, 'b, 's, 'h> {
         self.processor.next_directive_token(self.ctx)
     }
 
+    /// Reports a construct that is not supported by the selected language standard, if pedantic
+    /// diagnostics are enabled.
+    fn report_extension(&mut self, range: SourceRange, msg: &str) -> DResult<()> {
+        match self.processor.pedantic_level() {
+            Some(level) => self
+                .ctx
+                .reporter()
+                .report(level, range, msg)
+                .set_id(DiagnosticId::Pedantic)
+                .emit(),
+            None => Ok(()),
+        }
+    }
+
     fn reporter(&mut self) -> Reporter<'_, 'h> {
         self.ctx.reporter()
     }
 }
+
+/// Destringizes the string literal `lit` as specified in §6.10.9: any encoding prefix and the
+/// enclosing quotes are removed, and the escape sequences `\"` and `\\` are replaced by the
+/// characters they denote.
+fn destringize(lit: &str) -> String {
+    let start = lit.find('"').map_or(0, |idx| idx + 1);
+    let contents = lit[start..].strip_suffix('"').unwrap_or(&lit[start..]);
+
+    let mut ret = String::with_capacity(contents.len());
+    let mut chars = contents.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(&next @ ('"' | '\\')) = chars.peek() {
+                ret.push(next);
+                chars.next();
+                continue;
+            }
+        }
+        ret.push(c);
+    }
+
+    ret
+}
+
+/// Checks whether `spelling` is a string literal without an encoding prefix that is closed by a
+/// `"`, so that its contents lie between its first and last characters.
+fn is_plain_str(spelling: &str) -> bool {
+    spelling.len() >= 2 && spelling.starts_with('"') && spelling.ends_with('"')
+}
+
+/// Splits the spelling of a header name token into the filename it names and the kind of include
+/// its delimiters indicate. Unterminated header names name everything following the opening
+/// delimiter.
+fn split_header_name(spelling: &str) -> (PathBuf, IncludeKind) {
+    let (kind, term) = match spelling.as_bytes()[0] {
+        b'<' => (IncludeKind::Angled, '>'),
+        _ => (IncludeKind::Quoted, '"'),
+    };
+
+    let filename = &spelling[1..];
+    let filename = filename.strip_suffix(term).unwrap_or(filename);
+    (filename.into(), kind)
+}
+
+/// Interprets the escape sequences in the contents of the string literal naming the file in a
+/// `#line` directive.
+///
+/// Every backslash escapes the character following it, which covers the common cases of `\\` and
+/// `\"`.
+fn unescape_line_filename(contents: &str) -> String {
+    let mut filename = String::with_capacity(contents.len());
+    let mut chars = contents.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => filename.extend(chars.next()),
+            c => filename.push(c),
+        }
+    }
+
+    filename
+}
+
+/// Checks whether `name` is reserved to the implementation as a macro name: `defined`, or an
+/// identifier beginning with two underscores or with an underscore and an uppercase letter
+/// (§7.1.3).
+fn is_reserved_macro_name(name: &str) -> bool {
+    name == "defined"
+        || name.starts_with("__")
+        || name
+            .strip_prefix('_')
+            .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_uppercase()))
+}
@@ -1,12 +1,15 @@
+use std::collections::VecDeque;
 use std::mem;
 
-use lex::raw::{Reader, Tokenizer};
-use lex::{ConvertedTokenKind, LexCtx, TokenKind};
-use source::{DResult, LocalOff, SourcePos};
+use lex::raw::{LexerConfig, RawTokenKind, Reader, Tokenizer};
+use lex::{ConvertedTokenKind, LexCtx, PunctKind, Token, TokenKind};
+use source::diag::{Level, Message};
+use source::{DResult, LocalOff, SourcePos, SourceRange};
 
-use crate::PpToken;
+use crate::stats::{StatsPhase, StatsRecorder};
+use crate::{LangOptions, PpToken};
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum FileTokenKind {
     Real(TokenKind),
     Newline,
@@ -49,10 +52,63 @@ impl FileToken {
     }
 }
 
+/// Options controlling how the contents of a file are lexed and processed.
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessorOptions {
+    /// The configuration of the underlying tokenizer.
+    pub lexer_config: LexerConfig,
+    /// Whether comments should be collected for retrieval with [`Processor::take_comment()`].
+    pub keep_comments: bool,
+    /// Whether tokens should record the ranges of their leading trivia.
+    pub capture_trivia: bool,
+    /// The level of the diagnostic reported by `#error` directives.
+    pub error_directive_level: Level,
+    /// The language being preprocessed.
+    pub lang: LangOptions,
+    /// Whether diagnostics for constructs that are not strictly conforming should be reported as
+    /// errors instead of warnings.
+    pub pedantic_errors: bool,
+}
+
+impl Default for ProcessorOptions {
+    fn default() -> Self {
+        Self {
+            lexer_config: LexerConfig::default(),
+            keep_comments: false,
+            capture_trivia: false,
+            error_directive_level: Level::Error,
+            lang: LangOptions::default(),
+            pedantic_errors: false,
+        }
+    }
+}
+
+impl ProcessorOptions {
+    /// Returns the level at which constructs not supported by the selected language should be
+    /// reported, or `None` if they should be accepted silently.
+    ///
+    /// Such diagnostics are only enabled in pedantic mode, and are escalated to errors if
+    /// `pedantic_errors` is set.
+    pub fn pedantic_level(&self) -> Option<Level> {
+        if self.pedantic_errors {
+            Some(Level::Error)
+        } else if self.lang.pedantic {
+            Some(Level::Warning)
+        } else {
+            None
+        }
+    }
+}
+
 pub struct ProcessorState {
     off: LocalOff,
     line_start: bool,
     lookahead: Option<FileToken>,
+    /// Comments that have been lexed as trivia but not yet retrieved with
+    /// [`Processor::take_comment()`].
+    comments: VecDeque<PpToken>,
+    /// The start of the trivia lexed since the last real token, if any.
+    trivia_start: Option<SourcePos>,
 }
 
 impl ProcessorState {
@@ -61,6 +117,8 @@ impl ProcessorState {
             off: 0.into(),
             line_start: true,
             lookahead: None,
+            comments: VecDeque::new(),
+            trivia_start: None,
         }
     }
 }
@@ -69,6 +127,8 @@ pub struct Processor<'a> {
     state: &'a mut ProcessorState,
     tokenizer: Tokenizer<'a>,
     base_pos: SourcePos,
+    opts: ProcessorOptions,
+    stats: &'a StatsRecorder,
 }
 
 impl Drop for Processor<'_> {
@@ -78,14 +138,22 @@ impl Drop for Processor<'_> {
 }
 
 impl<'a> Processor<'a> {
-    pub fn new(state: &'a mut ProcessorState, src: &'a str, start_pos: SourcePos) -> Self {
-        let tokenizer = Tokenizer::new(&src[state.off.into()..]);
+    pub fn new(
+        state: &'a mut ProcessorState,
+        src: &'a str,
+        start_pos: SourcePos,
+        opts: ProcessorOptions,
+        stats: &'a StatsRecorder,
+    ) -> Self {
+        let tokenizer = Tokenizer::with_config(&src[state.off.into()..], opts.lexer_config);
         let base_pos = start_pos.offset(state.off);
 
         Self {
             state,
             tokenizer,
             base_pos,
+            opts,
+            stats,
         }
     }
 
@@ -119,11 +187,73 @@ impl<'a> Processor<'a> {
         self.next_token(ctx).map(|tok| tok.as_directive_token())
     }
 
+    /// Lexes a header name starting at the current position, returning `None` without consuming
+    /// anything if there is none.
+    ///
+    /// This should only be called where a header name is expected, such as at the start of the
+    /// operand of an `#include` directive: anywhere else, the same characters form ordinary tokens.
+    pub fn next_header_name(&mut self, ctx: &mut LexCtx<'_, '_>) -> DResult<Option<PpToken>> {
+        let stats = self.stats;
+        stats.time(StatsPhase::Lexing, || self.lex_header_name(ctx))
+    }
+
+    fn lex_header_name(&mut self, ctx: &mut LexCtx<'_, '_>) -> DResult<Option<PpToken>> {
+        let raw = match self.tokenizer_mut().next_header_name() {
+            Some(raw) => raw,
+            None => return Ok(None),
+        };
+
+        let converted = lex::convert_raw(ctx, &raw, self.base_pos)?;
+        let kind = match converted.data {
+            ConvertedTokenKind::Real(kind) => kind,
+            _ => unreachable!("header name converted to trivia"),
+        };
+
+        Ok(Some(PpToken {
+            tok: Token::new(kind, converted.range),
+            line_start: false,
+            leading_trivia: false,
+            leading_trivia_range: None,
+        }))
+    }
+
+    /// Returns whether comments are being kept as tokens.
+    pub fn keeps_comments(&self) -> bool {
+        self.opts.keep_comments
+    }
+
+    /// Returns the level of the diagnostic that should be reported by `#error` directives.
+    pub fn error_directive_level(&self) -> Level {
+        self.opts.error_directive_level
+    }
+
+    /// Returns the language being preprocessed.
+    pub fn lang(&self) -> LangOptions {
+        self.opts.lang
+    }
+
+    /// Returns the level at which constructs not supported by the selected language should be
+    /// reported, if at all.
+    pub fn pedantic_level(&self) -> Option<Level> {
+        self.opts.pedantic_level()
+    }
+
+    /// Returns the oldest comment skipped over while lexing tokens, if comments are being kept.
+    pub fn take_comment(&mut self) -> Option<PpToken> {
+        self.state.comments.pop_front()
+    }
+
+    /// Drops any comments skipped over so far, so that they will not be returned by
+    /// [`take_comment()`](Self::take_comment()).
+    pub fn discard_comments(&mut self) {
+        self.state.comments.clear();
+    }
+
     pub fn report_and_advance(
         &mut self,
         ctx: &mut LexCtx<'_, '_>,
         ppt: PpToken,
-        msg: String,
+        msg: Message,
     ) -> DResult<()> {
         ctx.reporter().error(ppt.range(), msg).emit()?;
 
@@ -139,7 +269,25 @@ impl<'a> Processor<'a> {
         Ok(())
     }
 
+    /// Skips lines until one starting with a `#` is found, consuming the `#` itself and returning
+    /// its position. Returns `None` if the end of the file is reached first.
+    ///
+    /// The skipped tokens are not converted, so that no diagnostics are reported for them.
+    pub fn skip_to_directive(&mut self) -> Option<SourcePos> {
+        let mut line_start = self.state.line_start;
+        let stats = self.stats;
+        let hash_off = stats.time(StatsPhase::Lexing, || {
+            self.tokenizer_mut().skip_to_directive(&mut line_start)
+        });
+
+        self.state.line_start = hash_off.is_none() && line_start;
+        self.state.trivia_start = None;
+        hash_off.map(|off| self.base_pos.offset(off))
+    }
+
     pub fn reader(&mut self) -> &mut Reader<'a> {
+        // Anything read directly is not trivia.
+        self.state.trivia_start = None;
         &mut self.tokenizer_mut().reader
     }
 
@@ -149,33 +297,87 @@ impl<'a> Processor<'a> {
     }
 
     fn lex_next_token(&mut self, ctx: &mut LexCtx<'_, '_>) -> DResult<FileToken> {
+        let stats = self.stats;
+        stats.time(StatsPhase::Lexing, || self.lex_token(ctx))
+    }
+
+    fn lex_token(&mut self, ctx: &mut LexCtx<'_, '_>) -> DResult<FileToken> {
         let mut leading_trivia = false;
+        let mut comment_line_start = self.state.line_start;
 
         let (tok, new_line_start) = loop {
-            let converted =
-                lex::convert_raw(ctx, &self.tokenizer_mut().next_token(), self.base_pos)?;
+            let raw = self.tokenizer_mut().next_token();
+            let converted = lex::convert_raw(ctx, &raw, self.base_pos)?;
+            let start = converted.range.start();
+
             match converted.data {
                 ConvertedTokenKind::Real(kind) => {
                     break (converted.map(|_| FileTokenKind::Real(kind)), false)
                 }
 
                 ConvertedTokenKind::Newline => {
+                    self.state.trivia_start.get_or_insert(start);
                     break (converted.map(|_| FileTokenKind::Newline), true);
                 }
 
                 ConvertedTokenKind::Trivia => {
+                    if self.opts.keep_comments
+                        && matches!(
+                            raw.kind,
+                            RawTokenKind::LineComment | RawTokenKind::BlockComment { .. }
+                        )
+                    {
+                        let text = ctx.interner.intern_cow(raw.content.cleaned_str());
+                        let leading_trivia_range = self.take_trivia(start);
+                        self.state.comments.push_back(PpToken {
+                            tok: Token::new(TokenKind::Comment(text), converted.range),
+                            line_start: mem::replace(&mut comment_line_start, false),
+                            leading_trivia,
+                            leading_trivia_range,
+                        });
+                    } else {
+                        self.state.trivia_start.get_or_insert(start);
+                    }
+
                     leading_trivia = true;
                 }
             }
         };
 
+        let mut line_start = mem::replace(&mut self.state.line_start, new_line_start);
+
+        // A token following a kept comment is no longer the first on its line, but a `#` must still
+        // be recognized as the start of a directive.
+        if line_start
+            && !comment_line_start
+            && tok.data != FileTokenKind::Real(TokenKind::Punct(PunctKind::Hash))
+        {
+            line_start = false;
+        }
+
+        // Trivia before a newline is attributed to the next real token, together with the newline.
+        let leading_trivia_range = match tok.data {
+            FileTokenKind::Real(_) => self.take_trivia(tok.range.start()),
+            FileTokenKind::Newline => None,
+        };
+
         Ok(FileToken {
             tok,
-            line_start: mem::replace(&mut self.state.line_start, new_line_start),
+            line_start,
             leading_trivia,
+            leading_trivia_range,
         })
     }
 
+    /// Returns the range of the trivia lexed since the last real token and ending at `end`, if
+    /// trivia is being captured.
+    fn take_trivia(&mut self, end: SourcePos) -> Option<SourceRange> {
+        let start = self.state.trivia_start.take()?;
+        self.opts
+            .capture_trivia
+            .then(|| SourceRange::new(start, end.offset_from(start)))
+    }
+
     fn off(&self) -> LocalOff {
         self.tokenizer.reader.off()
     }
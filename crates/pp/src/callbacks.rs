@@ -0,0 +1,82 @@
+//! Hooks for observing the actions taken by the preprocessor.
+
+use std::path::Path;
+
+use lex::{LexCtx, Symbol, Token};
+use source::{DResult, SourceId, SourceRange};
+
+use crate::{File, IncludeKind, MacroDef};
+
+/// An observer notified of directives processed and macros expanded during preprocessing.
+///
+/// Callbacks are registered with
+/// [`PreprocessorBuilder::callbacks()`](crate::PreprocessorBuilder::callbacks). Every method has an
+/// empty default implementation, so implementors only need to override the ones they are interested
+/// in. Callbacks may report diagnostics through `ctx`.
+pub trait PpCallbacks {
+    /// Called when preprocessing of the included file `id` starts. The location of the include
+    /// directive is recorded in the source map.
+    fn file_entered(&mut self, _ctx: &mut LexCtx<'_, '_>, _id: SourceId) -> DResult<()> {
+        Ok(())
+    }
+
+    /// Called when the end of the included file `id` is reached.
+    fn file_exited(&mut self, _ctx: &mut LexCtx<'_, '_>, _id: SourceId) -> DResult<()> {
+        Ok(())
+    }
+
+    /// Called when the file spelled `filename` in an include directive of the specified `kind` at
+    /// `range` has been located.
+    ///
+    /// This is called even if the file is not entered because it is guarded against multiple
+    /// inclusion.
+    fn include_resolved(
+        &mut self,
+        _ctx: &mut LexCtx<'_, '_>,
+        _filename: &Path,
+        _kind: IncludeKind,
+        _range: SourceRange,
+        _file: &File,
+    ) -> DResult<()> {
+        Ok(())
+    }
+
+    /// Called when a macro is defined with `def`, including macros defined on the command line and
+    /// predefined ones. `def` may redefine an existing macro.
+    fn macro_defined(&mut self, _ctx: &mut LexCtx<'_, '_>, _def: &MacroDef) -> DResult<()> {
+        Ok(())
+    }
+
+    /// Called when the macro named by `name_tok` is undefined. `prev` holds its definition, if it
+    /// was defined.
+    fn macro_undefined(
+        &mut self,
+        _ctx: &mut LexCtx<'_, '_>,
+        _name_tok: Token<Symbol>,
+        _prev: Option<&MacroDef>,
+    ) -> DResult<()> {
+        Ok(())
+    }
+
+    /// Called when the invocation of the macro defined by `def` named by `name_tok` is about to be
+    /// replaced, including invocations produced by other expansions and in `#if` conditions.
+    fn macro_expanded(
+        &mut self,
+        _ctx: &mut LexCtx<'_, '_>,
+        _name_tok: Token<Symbol>,
+        _def: &MacroDef,
+    ) -> DResult<()> {
+        Ok(())
+    }
+
+    /// Called when the lines in `range` are skipped by conditional compilation. The range ends
+    /// before the directive that ends the skipped groups, or at the end of the file.
+    fn region_skipped(&mut self, _ctx: &mut LexCtx<'_, '_>, _range: SourceRange) -> DResult<()> {
+        Ok(())
+    }
+}
+
+/// The callbacks used when none have been registered, which do nothing.
+pub struct NoCallbacks;
+
+impl PpCallbacks for NoCallbacks {}
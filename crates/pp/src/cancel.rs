@@ -0,0 +1,81 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use lex::LexCtx;
+use source::diag::{FatalErrorEmitted, Level};
+
+/// A shared flag used to request that preprocessing be stopped early.
+///
+/// Clones of a token share the same flag, so one clone can be handed to a
+/// [`Preprocessor`](crate::Preprocessor) while another is used to cancel it from a different
+/// thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Creates a new token that has not been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests cancellation of any preprocessing using this token.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Checks whether cancellation has been requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// An error that stopped preprocessing.
+#[derive(Debug, Clone, Copy)]
+pub enum PpError {
+    /// A fatal diagnostic has been emitted.
+    Fatal(FatalErrorEmitted),
+    /// Preprocessing was cancelled through a [`CancellationToken`].
+    Cancelled,
+}
+
+/// Returns [`PpError::Cancelled`] if `token` is present and has been cancelled.
+pub(crate) fn check_cancelled(token: Option<&CancellationToken>) -> PpResult<()> {
+    match token {
+        Some(token) if token.is_cancelled() => Err(PpError::Cancelled),
+        _ => Ok(()),
+    }
+}
+
+impl PpError {
+    /// Returns the fatal error behind this error, which must stem from an operation that was not
+    /// given a cancellation token.
+    pub(crate) fn expect_fatal(self) -> FatalErrorEmitted {
+        match self {
+            PpError::Fatal(fatal) => fatal,
+            PpError::Cancelled => unreachable!("no cancellation token was provided"),
+        }
+    }
+
+    /// Converts this error into a fatal error, reporting cancellation as a fatal diagnostic
+    /// through `ctx`.
+    pub(crate) fn into_fatal(self, ctx: &mut LexCtx<'_, '_>) -> FatalErrorEmitted {
+        match self {
+            PpError::Fatal(fatal) => fatal,
+            PpError::Cancelled => ctx
+                .diags
+                .report_anon(Level::Fatal, "preprocessing cancelled")
+                .emit()
+                .unwrap_err(),
+        }
+    }
+}
+
+impl From<FatalErrorEmitted> for PpError {
+    fn from(fatal: FatalErrorEmitted) -> Self {
+        PpError::Fatal(fatal)
+    }
+}
+
+pub type PpResult<T> = Result<T, PpError>;
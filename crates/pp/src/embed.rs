@@ -0,0 +1,141 @@
+//! Support for `#embed` directives (C23 §6.10.3).
+
+use std::vec;
+
+use lex::{LexCtx, PunctKind, Symbol, Token, TokenKind};
+use source::smap::{FileContents, FileName, SourcesTooLargeError};
+use source::{SourcePos, SourceRange};
+
+use crate::PpToken;
+
+/// The parameters of an `#embed` directive (C23 §6.10.3.2).
+#[derive(Default)]
+pub struct EmbedParams {
+    /// The maximum number of bytes to embed, as specified by the `limit` parameter.
+    pub limit: Option<u64>,
+    /// The tokens to place before the embedded data if it is not empty.
+    pub prefix: Option<Vec<PpToken>>,
+    /// The tokens to place after the embedded data if it is not empty.
+    pub suffix: Option<Vec<PpToken>>,
+    /// The tokens to replace the directive with if the embedded data is empty.
+    pub if_empty: Option<Vec<PpToken>>,
+}
+
+/// The stream of tokens replacing an `#embed` directive.
+///
+/// The embedded bytes are presented as a comma-separated list of integer literals, spelled in a
+/// synthesized source. These tokens are produced lazily, as resources can be quite large.
+pub struct EmbedTokens {
+    prefix: vec::IntoIter<PpToken>,
+    data: Vec<u8>,
+    byte_syms: Vec<Symbol>,
+    next_elem: usize,
+    pos: SourcePos,
+    suffix: vec::IntoIter<PpToken>,
+    line_start: bool,
+}
+
+impl EmbedTokens {
+    /// Creates the replacement of an `#embed` directive of the resource `filename` at
+    /// `embed_pos`, whose (possibly truncated) contents are `data`.
+    ///
+    /// If `data` is not empty, a synthesized source spelling it is created in `ctx.smap`.
+    pub fn new(
+        ctx: &mut LexCtx<'_, '_>,
+        filename: &str,
+        data: Vec<u8>,
+        params: EmbedParams,
+        embed_pos: SourcePos,
+    ) -> Result<Self, SourcesTooLargeError> {
+        if data.is_empty() {
+            return Ok(Self {
+                prefix: params.if_empty.unwrap_or_default().into_iter(),
+                data,
+                byte_syms: Vec::new(),
+                next_elem: 0,
+                pos: embed_pos,
+                suffix: Vec::new().into_iter(),
+                line_start: true,
+            });
+        }
+
+        let spelling = data
+            .iter()
+            .map(|byte| byte.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let id = ctx.smap.create_file(
+            FileName::synth(format!("embed {}", filename)),
+            FileContents::from_string(spelling),
+            Some(embed_pos),
+        )?;
+
+        let byte_syms = (0..=u8::MAX)
+            .map(|byte| ctx.interner.intern(&byte.to_string()))
+            .collect();
+
+        Ok(Self {
+            prefix: params.prefix.unwrap_or_default().into_iter(),
+            data,
+            byte_syms,
+            next_elem: 0,
+            pos: ctx.smap.get_source(id).range.start(),
+            suffix: params.suffix.unwrap_or_default().into_iter(),
+            line_start: true,
+        })
+    }
+
+    /// Returns the next token of the embedded data itself, if any remain.
+    fn next_data_token(&mut self) -> Option<PpToken> {
+        // Every byte is followed by a comma, except for the last one.
+        if self.next_elem >= (self.data.len() * 2).saturating_sub(1) {
+            return None;
+        }
+
+        let (kind, len) = if self.next_elem.is_multiple_of(2) {
+            let byte = self.data[self.next_elem / 2];
+            let len: u32 = match byte {
+                0..=9 => 1,
+                10..=99 => 2,
+                _ => 3,
+            };
+            (TokenKind::Number(self.byte_syms[byte as usize]), len)
+        } else {
+            (TokenKind::Punct(PunctKind::Comma), 1)
+        };
+
+        let len = len.into();
+        let range = SourceRange::new(self.pos, len);
+        self.pos = self.pos.offset(len);
+        self.next_elem += 1;
+
+        Some(PpToken {
+            tok: Token::new(kind, range),
+            line_start: false,
+            leading_trivia: false,
+            leading_trivia_range: None,
+        })
+    }
+}
+
+impl Iterator for EmbedTokens {
+    type Item = PpToken;
+
+    fn next(&mut self) -> Option<PpToken> {
+        let mut ppt = self
+            .prefix
+            .next()
+            .or_else(|| self.next_data_token())
+            .or_else(|| self.suffix.next())?;
+
+        // The directive is replaced in its entirety, so the first token starts a new line.
+        ppt.line_start = self.line_start;
+        if self.line_start {
+            ppt.leading_trivia = false;
+            self.line_start = false;
+        }
+
+        Some(ppt)
+    }
+}
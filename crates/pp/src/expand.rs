@@ -1,62 +1,242 @@
 use lex::{LexCtx, Symbol};
+use source::diag::Level;
 use source::DResult;
 
-use crate::PpToken;
+use crate::{CancellationToken, PpCallbacks, PpResult, PpToken};
 
 use def::MacroTable;
-use replace::{PendingReplacements, ReplacementCtx};
+use replace::{BuiltinState, ExpansionRecords, MacroEnv, PendingReplacements, ReplacementCtx};
 
-pub use def::{MacroDef, MacroDefKind, ReplacementList};
+pub use def::{BuiltinMacro, DisplayDef, MacroDef, MacroDefKind, MacroOrigin, ReplacementList};
 pub use replace::ReplacementLexer;
+pub use trace::{
+    ExpansionParent, ExpansionSite, ExpansionStage, ExpansionStep, ExpansionTrace, TracedArg,
+};
 
 mod def;
 mod replace;
+mod trace;
+
+/// Controls how precisely the origins of tokens produced by macro expansion are recorded in the
+/// source map.
+///
+/// Lower levels create fewer expansion sources, saving memory on large translation units at the
+/// cost of less precise locations in diagnostics. Tokens whose spelling must be read back from the
+/// source map (such as unknown characters) are always tracked precisely.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum ExpansionTracking {
+    /// No expansion sources are created; all tokens produced by a macro expansion are attributed
+    /// to the outermost invocation.
+    None,
+    /// Expansion sources are created for macro replacement lists, but tokens substituted from
+    /// macro arguments are attributed to the parameter they replace.
+    Macros,
+    /// Every expanded token is tracked back to its spelling.
+    #[default]
+    Full,
+}
 
 /// Tracks macro definitions and expansion state.
 pub struct MacroState {
     defs: MacroTable,
     replacements: PendingReplacements,
+    builtins: BuiltinState,
+    empty_arg_level: Option<Level>,
+    omitted_va_args_level: Option<Level>,
+    tracking: ExpansionTracking,
+    trace: Option<ExpansionTrace>,
+    sites: Option<Vec<ExpansionSite>>,
+    expansion_count: u64,
 }
 
 impl MacroState {
-    /// Creates a new state with no definitions and no pending expansion tokens.
-    pub fn new() -> Self {
+    /// Creates a new state with no definitions and no pending expansion tokens, which tracks
+    /// expansions as specified by `tracking`.
+    ///
+    /// If `trace` is set, every replacement performed is recorded in an [`ExpansionTrace`]. If
+    /// `record_sites` is set, every macro invocation expanded is recorded as an [`ExpansionSite`].
+    pub fn new(tracking: ExpansionTracking, trace: bool, record_sites: bool) -> Self {
         Self {
             defs: MacroTable::new(),
             replacements: PendingReplacements::new(),
+            builtins: BuiltinState::default(),
+            empty_arg_level: None,
+            omitted_va_args_level: None,
+            tracking,
+            trace: if trace {
+                Some(ExpansionTrace::default())
+            } else {
+                None
+            },
+            sites: if record_sites { Some(Vec::new()) } else { None },
+            expansion_count: 0,
         }
     }
 
+    /// Returns the trace of the replacements performed so far, if tracing is enabled.
+    pub fn trace(&self) -> Option<&ExpansionTrace> {
+        self.trace.as_ref()
+    }
+
+    /// Returns the macro invocations expanded so far, if recording them is enabled.
+    pub fn sites(&self) -> Option<&[ExpansionSite]> {
+        self.sites.as_deref()
+    }
+
+    /// Returns the number of macro invocations expanded so far, including nested ones.
+    pub fn expansion_count(&self) -> u64 {
+        self.expansion_count
+    }
+
+    /// Sets the name of the main source file, to which `__BASE_FILE__` expands.
+    pub fn set_base_file(&mut self, name: String) {
+        self.builtins.base_file = name;
+    }
+
+    /// Sets the nesting depth of the file currently being processed, to which `__INCLUDE_LEVEL__`
+    /// expands.
+    pub fn set_include_level(&mut self, level: usize) {
+        self.builtins.include_level = level;
+    }
+
+    /// Sets the level at which empty macro arguments should be reported, or `None` if they are
+    /// supported by the language being preprocessed. By default, they are not reported.
+    pub fn set_empty_arg_level(&mut self, level: Option<Level>) {
+        self.empty_arg_level = level;
+    }
+
+    /// Sets the level at which invocations of variadic macros omitting the variable arguments
+    /// entirely should be reported, or `None` if they are supported by the language being
+    /// preprocessed. By default, they are not reported.
+    pub fn set_omitted_va_args_level(&mut self, level: Option<Level>) {
+        self.omitted_va_args_level = level;
+    }
+
     /// Records the specified macro definition.
     ///
-    /// If `def` redefines an existing macro (using the rules in §6.10.3p2), the previous definition
-    /// is returned.
-    pub fn define(&mut self, def: MacroDef) -> Option<MacroDef> {
-        self.defs.define(def)
+    /// If `def` redefines an existing macro with a definition that is not identical to it (using
+    /// the rules in §6.10.3p2), the previous definition is returned. Identical redefinitions are
+    /// permitted by the standard and return `None`.
+    pub fn define(&mut self, ctx: &LexCtx<'_, '_>, def: MacroDef) -> Option<MacroDef> {
+        self.defs.define(ctx, def)
     }
 
-    /// Removes any macro definition associated with `name`.
+    /// Removes any macro definition associated with `name`, returning it.
     ///
     /// This has no effect if `name` is not defined as a macro.
-    pub fn undef(&mut self, name: Symbol) {
+    pub fn undef(&mut self, name: Symbol) -> Option<MacroDef> {
         self.defs.undef(name)
     }
 
+    /// Returns an estimate of the largest number of bytes of heap memory used by the macro table
+    /// at any point.
+    pub fn peak_macro_table_size(&self) -> usize {
+        self.defs.peak_heap_size()
+    }
+
+    /// Returns the largest number of bytes of heap memory used by pending replacement tokens at
+    /// any point.
+    pub fn peak_replacements_size(&self) -> usize {
+        self.replacements.peak_heap_size()
+    }
+
+    /// Returns all active macro definitions, in the order in which the macros were defined.
+    pub fn defs(&self) -> impl Iterator<Item = &MacroDef> {
+        self.defs.defs()
+    }
+
+    /// Returns the current definition of the macro `name`, if any.
+    pub fn lookup(&self, name: Symbol) -> Option<&MacroDef> {
+        self.defs.lookup(name)
+    }
+
+    /// Checks whether `name` is currently defined as a macro.
+    pub fn is_defined(&self, name: Symbol) -> bool {
+        self.defs.lookup(name).is_some()
+    }
+
+    /// Checks whether any tokens produced by macro expansion are still pending, meaning that the
+    /// next token will not be read directly from the lexer.
+    pub fn has_pending_tokens(&self) -> bool {
+        !self.replacements.is_empty()
+    }
+
     /// Returns the next pending macro expansion token, if any.
     ///
     /// The tokens returned by this function have already been (recursively)
     /// rescanned as defined in §6.10.3.4, and should not be passed again to `begin_expansion`.
     ///
+    /// `callbacks` is notified of every macro expanded while rescanning.
+    ///
     /// `lexer` may be necessary in certain edge cases when a recursive expansion produces a call to
     /// a function-like macro and additional argument tokens need to be lexed.
+    ///
+    /// `cancellation_token`, if provided, is polled while rescanning.
     pub fn next_expansion_token(
         &mut self,
         ctx: &mut LexCtx<'_, '_>,
+        callbacks: &mut dyn PpCallbacks,
+        mut lexer: impl ReplacementLexer,
+        cancellation_token: Option<&CancellationToken>,
+    ) -> PpResult<Option<PpToken>> {
+        let ppt = ReplacementCtx::new(
+            ctx,
+            MacroEnv {
+                defs: &self.defs,
+                builtins: &mut self.builtins,
+                empty_arg_level: self.empty_arg_level,
+                omitted_va_args_level: self.omitted_va_args_level,
+            },
+            &mut self.replacements,
+            self.tracking,
+            ExpansionRecords {
+                trace: self.trace.as_mut(),
+                sites: self.sites.as_mut(),
+                count: &mut self.expansion_count,
+            },
+            callbacks,
+            &mut lexer,
+        )
+        .next_expansion_token(cancellation_token)?
+        .map(|tok| tok.ppt);
+
+        if let (Some(trace), Some(ppt)) = (&mut self.trace, ppt) {
+            trace.push_result(ppt);
+        }
+
+        Ok(ppt)
+    }
+
+    /// Returns the next token without macro-expanding it.
+    ///
+    /// The token is taken from any pending expansion, falling back to `lexer` if there is none.
+    /// This is used for the operand of `defined`, which must not be expanded.
+    pub fn next_unexpanded_token(
+        &mut self,
+        ctx: &mut LexCtx<'_, '_>,
+        callbacks: &mut dyn PpCallbacks,
         mut lexer: impl ReplacementLexer,
-    ) -> DResult<Option<PpToken>> {
-        ReplacementCtx::new(ctx, &self.defs, &mut self.replacements, &mut lexer)
-            .next_expansion_token()
-            .map(|res| res.map(|tok| tok.ppt))
+    ) -> DResult<PpToken> {
+        ReplacementCtx::new(
+            ctx,
+            MacroEnv {
+                defs: &self.defs,
+                builtins: &mut self.builtins,
+                empty_arg_level: self.empty_arg_level,
+                omitted_va_args_level: self.omitted_va_args_level,
+            },
+            &mut self.replacements,
+            self.tracking,
+            ExpansionRecords {
+                trace: self.trace.as_mut(),
+                sites: self.sites.as_mut(),
+                count: &mut self.expansion_count,
+            },
+            callbacks,
+            &mut lexer,
+        )
+        .next_token()
+        .map(|tok| tok.ppt)
     }
 
     /// Attempts to start macro-expanding `ppt`, returning whether expansion is now taking place.
@@ -66,14 +246,33 @@ impl MacroState {
     /// function should not be called again until `next_expansion_token` has returned `None` - it
     /// may behave unexpectedly otherwise.
     ///
-    /// `lexer` will be used to read function-like macro arguments, if necessary.
+    /// `lexer` will be used to read function-like macro arguments, if necessary, and `callbacks` is
+    /// notified if the expansion begins.
     pub fn begin_expansion(
         &mut self,
         ctx: &mut LexCtx<'_, '_>,
+        callbacks: &mut dyn PpCallbacks,
         ppt: PpToken,
         mut lexer: impl ReplacementLexer,
     ) -> DResult<bool> {
-        ReplacementCtx::new(ctx, &self.defs, &mut self.replacements, &mut lexer)
-            .begin_expansion(&mut ppt.into())
+        ReplacementCtx::new(
+            ctx,
+            MacroEnv {
+                defs: &self.defs,
+                builtins: &mut self.builtins,
+                empty_arg_level: self.empty_arg_level,
+                omitted_va_args_level: self.omitted_va_args_level,
+            },
+            &mut self.replacements,
+            self.tracking,
+            ExpansionRecords {
+                trace: self.trace.as_mut(),
+                sites: self.sites.as_mut(),
+                count: &mut self.expansion_count,
+            },
+            callbacks,
+            &mut lexer,
+        )
+        .begin_expansion(&mut ppt.into())
     }
 }
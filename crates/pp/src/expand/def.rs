@@ -1,9 +1,11 @@
 use std::collections::hash_map::Entry;
+use std::fmt;
 use std::mem;
 
 use rustc_hash::FxHashMap;
 
-use lex::{Symbol, Token};
+use lex::{Interner, LexCtx, Symbol, Token, TokenKind};
+use source::diag::{Message, MessageArg, RawSubDiagnostic};
 use source::SourceRange;
 
 use crate::PpToken;
@@ -34,6 +36,11 @@ impl ReplacementList {
         &self.tokens
     }
 
+    /// Returns the number of bytes of heap memory used by this replacement list.
+    pub fn heap_size(&self) -> usize {
+        self.tokens.capacity() * mem::size_of::<PpToken>()
+    }
+
     /// Returns the range covered by this replacement list's tokens, or `None` if it is empty.
     pub fn spelling_range(&self) -> Option<SourceRange> {
         self.tokens.first().map(|first| {
@@ -46,11 +53,19 @@ impl ReplacementList {
     }
 
     /// Determines whether this replacement list is identical to `rhs` using the rules laid out in
-    /// §6.10.3p1 (same tokens and whitespace separation).
+    /// §6.10.3p1 (same token spellings and whitespace separation).
     ///
-    /// This is used when checking for macro redefinitions.
-    pub fn is_identical_to(&self, rhs: &ReplacementList) -> bool {
-        let translate = |ppt: &PpToken| (ppt.data(), ppt.leading_trivia);
+    /// This is used when checking for macro redefinitions. The amount of whitespace separating
+    /// tokens is irrelevant, as is the form it takes (spaces or comments). Punctuators must be
+    /// spelled identically, so a digraph does not match the punctuator it stands for.
+    pub fn is_identical_to(&self, rhs: &ReplacementList, ctx: &LexCtx<'_, '_>) -> bool {
+        let translate = |ppt: &PpToken| {
+            let punct_spelling = match ppt.data() {
+                TokenKind::Punct(kind) => Some(ctx.punct_spelling(kind, ppt.range())),
+                _ => None,
+            };
+            (ppt.data(), punct_spelling, ppt.leading_trivia)
+        };
 
         self.tokens
             .iter()
@@ -59,38 +74,92 @@ impl ReplacementList {
     }
 }
 
+/// A builtin macro whose expansion is computed when it is expanded (§6.10.8.1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuiltinMacro {
+    /// `__FILE__`, which expands to the name of the current source file.
+    File,
+    /// `__LINE__`, which expands to the current line number.
+    Line,
+    /// `__COUNTER__` (a GNU extension), which expands to an integer that increases by one with
+    /// every expansion, starting at `0`.
+    Counter,
+    /// `__BASE_FILE__` (a GNU extension), which expands to the name of the main source file.
+    BaseFile,
+    /// `__INCLUDE_LEVEL__` (a GNU extension), which expands to the nesting depth of the current
+    /// include, with the main source file at depth `0`.
+    IncludeLevel,
+}
+
 /// The data associated with a macro definition.
 #[derive(Debug, Clone)]
 pub enum MacroDefKind {
     Object(ReplacementList),
     Function {
+        /// The names of the macro's parameters. For variadic macros, the last of these is always
+        /// `__VA_ARGS__`.
         params: Vec<Symbol>,
+        /// Whether the parameter list ends with an ellipsis.
+        variadic: bool,
         replacement: ReplacementList,
     },
+    Builtin(BuiltinMacro),
 }
 
 impl MacroDefKind {
     /// Determines whether this definition is identical to `rhs` using the rules laid out in
-    /// §6.10.3p2.
-    pub fn is_identical_to(&self, rhs: &MacroDefKind) -> bool {
+    /// §6.10.3p2: both must be of the same kind, with identical parameter lists (including the
+    /// spellings of the parameters) and identical replacement lists.
+    pub fn is_identical_to(&self, rhs: &MacroDefKind, ctx: &LexCtx<'_, '_>) -> bool {
         match (self, rhs) {
-            (MacroDefKind::Object(lhs), MacroDefKind::Object(rhs)) => lhs.is_identical_to(rhs),
+            (MacroDefKind::Object(lhs), MacroDefKind::Object(rhs)) => lhs.is_identical_to(rhs, ctx),
             (
                 MacroDefKind::Function {
                     params: lhs_params,
+                    variadic: lhs_variadic,
                     replacement: lhs_replacement,
                 },
                 MacroDefKind::Function {
                     params: rhs_params,
+                    variadic: rhs_variadic,
                     replacement: rhs_replacement,
                 },
-            ) => lhs_params == rhs_params && lhs_replacement.is_identical_to(rhs_replacement),
+            ) => {
+                lhs_params == rhs_params
+                    && lhs_variadic == rhs_variadic
+                    && lhs_replacement.is_identical_to(rhs_replacement, ctx)
+            }
+            (MacroDefKind::Builtin(lhs), MacroDefKind::Builtin(rhs)) => lhs == rhs,
             _ => false,
         }
     }
+
+    /// Returns the number of bytes of heap memory owned by this definition.
+    pub fn heap_size(&self) -> usize {
+        match self {
+            MacroDefKind::Object(replacement) => replacement.heap_size(),
+            MacroDefKind::Function {
+                params,
+                replacement,
+                ..
+            } => params.capacity() * mem::size_of::<Symbol>() + replacement.heap_size(),
+            MacroDefKind::Builtin(_) => 0,
+        }
+    }
 }
 
-/// Represents a macro definition read from the source code.
+/// Indicates where a macro definition came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacroOrigin {
+    /// The macro was defined by a `#define` directive in the source code.
+    Source,
+    /// The macro was defined on the command line (e.g. with `-D`).
+    CommandLine,
+    /// The macro is predefined by the preprocessor itself.
+    Builtin,
+}
+
+/// Represents a macro definition.
 #[derive(Debug, Clone)]
 pub struct MacroDef {
     /// The name of the macro and its location in the source code.
@@ -98,11 +167,108 @@ pub struct MacroDef {
 
     /// The data associated with this definition.
     pub kind: MacroDefKind,
+
+    /// Where this definition came from.
+    pub origin: MacroOrigin,
+}
+
+impl MacroDef {
+    /// Creates a diagnostic note pointing at this definition.
+    ///
+    /// `msg` is used for definitions from the source code. Definitions from other origins get an
+    /// unlocated note describing their origin instead.
+    pub fn def_note(&self, interner: &Interner, msg: impl Into<Message>) -> RawSubDiagnostic {
+        let name = &interner[self.name_tok.data];
+
+        match self.origin {
+            MacroOrigin::Source => RawSubDiagnostic::new(msg, self.name_tok.range.into()),
+            MacroOrigin::CommandLine => RawSubDiagnostic::new_anon(
+                Message::new("macro '{name}' defined on the command line")
+                    .arg("name", MessageArg::symbol(name)),
+            ),
+            MacroOrigin::Builtin => RawSubDiagnostic::new_anon(
+                Message::new("macro '{name}' is predefined").arg("name", MessageArg::symbol(name)),
+            ),
+        }
+    }
+
+    /// Returns an object that implements [`fmt::Display`] for printing this definition as a
+    /// `#define` directive.
+    ///
+    /// Builtin macros whose expansions are computed dynamically are printed without a replacement
+    /// list.
+    pub fn display<'d, 'a, 'h>(&'d self, ctx: &'d LexCtx<'a, 'h>) -> DisplayDef<'d, 'a, 'h> {
+        DisplayDef { def: self, ctx }
+    }
+}
+
+pub struct DisplayDef<'d, 'a, 'h> {
+    def: &'d MacroDef,
+    ctx: &'d LexCtx<'a, 'h>,
+}
+
+impl fmt::Display for DisplayDef<'_, '_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let interner = &*self.ctx.interner;
+        write!(f, "#define {}", &interner[self.def.name_tok.data])?;
+
+        let replacement = match &self.def.kind {
+            MacroDefKind::Object(replacement) => replacement,
+            MacroDefKind::Function {
+                params,
+                variadic,
+                replacement,
+            } => {
+                let named = if *variadic {
+                    &params[..params.len() - 1]
+                } else {
+                    &params[..]
+                };
+
+                write!(f, "(")?;
+                for (idx, &param) in named.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", &interner[param])?;
+                }
+                if *variadic {
+                    write!(f, "{}...", if named.is_empty() { "" } else { "," })?;
+                }
+                write!(f, ")")?;
+
+                replacement
+            }
+            MacroDefKind::Builtin(_) => return Ok(()),
+        };
+
+        if !replacement.tokens().is_empty() {
+            write!(f, " ")?;
+        }
+        for ppt in replacement.tokens() {
+            write!(f, "{}", ppt.display(self.ctx))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A definition stored in a [`MacroTable`].
+struct TableEntry {
+    def: MacroDef,
+    /// The position of the definition in the order in which macros were defined.
+    seq: u64,
 }
 
 /// Holds a table of currently defined macros.
 pub struct MacroTable {
-    map: FxHashMap<Symbol, MacroDef>,
+    map: FxHashMap<Symbol, TableEntry>,
+    /// The sequence number to assign to the next new definition.
+    next_seq: u64,
+    /// The number of bytes of heap memory owned by the definitions currently in the table.
+    defs_size: usize,
+    /// The largest value `defs_size` has reached.
+    peak_defs_size: usize,
 }
 
 impl MacroTable {
@@ -110,18 +276,26 @@ impl MacroTable {
     pub fn new() -> Self {
         Self {
             map: Default::default(),
+            next_seq: 0,
+            defs_size: 0,
+            peak_defs_size: 0,
         }
     }
 
     /// Adds `def` to the table.
     ///
-    /// If `def` redefines an existing macro (using the rules in §6.10.3p2), the previous definition
-    /// is returned.
-    pub fn define(&mut self, def: MacroDef) -> Option<MacroDef> {
+    /// If `def` redefines an existing macro with a definition that is not identical to it (using
+    /// the rules in §6.10.3p2), the previous definition is returned. Token spellings are read from
+    /// `ctx` for the comparison.
+    pub fn define(&mut self, ctx: &LexCtx<'_, '_>, def: MacroDef) -> Option<MacroDef> {
+        self.defs_size += def.kind.heap_size();
+        self.peak_defs_size = self.peak_defs_size.max(self.defs_size);
+
         match self.map.entry(def.name_tok.data) {
             Entry::Occupied(ent) => {
-                let prev = ent.into_mut();
-                let identical = prev.kind.is_identical_to(&def.kind);
+                let prev = &mut ent.into_mut().def;
+                self.defs_size -= prev.kind.heap_size();
+                let identical = prev.kind.is_identical_to(&def.kind, ctx);
 
                 // The standard allows redefinition iff the replacement lists are identical - always
                 // redefine here to try to make things more accurate later, but report the previous
@@ -130,21 +304,44 @@ impl MacroTable {
             }
 
             Entry::Vacant(ent) => {
-                ent.insert(def);
+                ent.insert(TableEntry {
+                    def,
+                    seq: self.next_seq,
+                });
+                self.next_seq += 1;
                 None
             }
         }
     }
 
-    /// Removes any stored definition associated with `name`.
+    /// Removes any stored definition associated with `name`, returning it.
     ///
     /// This has no effect if `name` is not defined.
-    pub fn undef(&mut self, name: Symbol) {
-        self.map.remove(&name);
+    pub fn undef(&mut self, name: Symbol) -> Option<MacroDef> {
+        let entry = self.map.remove(&name)?;
+        self.defs_size -= entry.def.kind.heap_size();
+        Some(entry.def)
     }
 
     /// Looks up the definition assoicated with `name`.
     pub fn lookup(&self, name: Symbol) -> Option<&MacroDef> {
-        self.map.get(&name)
+        self.map.get(&name).map(|entry| &entry.def)
+    }
+
+    /// Returns all stored definitions, in the order in which the macros were first defined.
+    ///
+    /// Redefining a macro does not change its position, but undefining and then defining it again
+    /// does.
+    pub fn defs(&self) -> impl Iterator<Item = &MacroDef> {
+        let mut entries: Vec<_> = self.map.values().collect();
+        entries.sort_unstable_by_key(|entry| entry.seq);
+        entries.into_iter().map(|entry| &entry.def)
+    }
+
+    /// Returns an estimate of the largest number of bytes of heap memory used by the table at any
+    /// point.
+    pub fn peak_heap_size(&self) -> usize {
+        let table_size = self.map.capacity() * mem::size_of::<(Symbol, TableEntry)>();
+        table_size + self.peak_defs_size
     }
 }
@@ -1,16 +1,22 @@
 use std::collections::VecDeque;
 use std::{iter, mem};
 
-use itertools::Itertools;
+use itertools::{Either, Itertools};
 use rustc_hash::FxHashSet;
 
 use lex::{LexCtx, PunctKind, Symbol, Token, TokenKind};
-use source::{diag::RawSubDiagnostic, DResult};
+use source::diag::{DiagnosticId, Level, Message, MessageArg, RawSubDiagnostic};
+use source::DResult;
 use source::{smap::ExpansionKind, FragmentedSourceRange, SourceId, SourceRange};
 
-use crate::PpToken;
+use crate::cancel::{self, CancellationToken, PpResult};
+use crate::{PpCallbacks, PpToken};
 
-use super::def::{MacroDefKind, MacroTable, ReplacementList};
+use super::def::{BuiltinMacro, MacroDef, MacroDefKind, MacroTable, ReplacementList};
+use super::trace::{
+    ExpansionParent, ExpansionSite, ExpansionStage, ExpansionStep, ExpansionTrace, TracedArg,
+};
+use super::ExpansionTracking;
 
 /// An abstraction over a token stream necessary for handling function-like macros during
 /// replacement.
@@ -53,38 +59,148 @@ impl From<PpToken> for ReplacementToken {
     }
 }
 
+/// The state of a function-like macro invocation whose arguments are being substituted into its
+/// replacement list.
+///
+/// Substitution is suspended whenever an argument must be pre-expanded first, so that nested
+/// invocations in macro arguments are handled iteratively rather than recursively.
+struct Substitution<'a> {
+    /// The name of the macro being invoked.
+    name_tok: PpToken<Symbol>,
+    /// The parameters of the macro being invoked.
+    params: &'a [Symbol],
+    /// The arguments of the invocation, each terminated by an `Eof`.
+    args: Vec<VecDeque<ReplacementToken>>,
+    /// The result of pre-expanding every argument that has been pre-expanded so far.
+    pre_expanded: Vec<Option<Vec<ReplacementToken>>>,
+    /// The traced replacement step, if tracing is enabled.
+    step: Option<usize>,
+    /// The remaining tokens of the replacement list, not yet substituted.
+    body: VecDeque<ReplacementToken>,
+    /// The `__VA_OPT__` identifier, if the macro is variadic.
+    va_opt: Option<TokenKind>,
+    /// Whether the variable arguments are empty, in which case `__VA_OPT__(...)` is removed
+    /// (C23 §6.10.4.1).
+    va_args_empty: bool,
+    /// The parenthesis nesting level within the `__VA_OPT__` currently being substituted, if any.
+    va_opt_level: Option<u32>,
+    /// A pending placemarker replacing an empty argument or removed `__VA_OPT__` (§6.10.3.3p2).
+    ///
+    /// Placemarkers never reach the output, but any whitespace preceding them is passed on to the
+    /// next token actually substituted.
+    placemarker: Option<PpToken<()>>,
+    /// The tokens substituted so far.
+    tokens: VecDeque<ReplacementToken>,
+    /// The index of the argument currently being pre-expanded, along with the tokens it has
+    /// produced so far.
+    pre_expanding: Option<(usize, Vec<ReplacementToken>)>,
+}
+
+impl Substitution<'_> {
+    /// Returns the index of the parameter named by `tok`, if any.
+    fn param_idx(&self, tok: ReplacementToken) -> Option<usize> {
+        match tok.ppt.data() {
+            TokenKind::Ident(ident) => self.params.iter().position(|&name| name == ident),
+            _ => None,
+        }
+    }
+}
+
+/// The optional records kept of the expansions performed.
+pub struct ExpansionRecords<'a> {
+    /// The trace in which every replacement performed is recorded.
+    pub trace: Option<&'a mut ExpansionTrace>,
+    /// The list in which every macro invocation expanded is recorded.
+    pub sites: Option<&'a mut Vec<ExpansionSite>>,
+    /// The number of macro invocations expanded so far.
+    pub count: &'a mut u64,
+}
+
+/// The state from which the expansions of builtin macros are computed, beyond the location of
+/// the invocation.
+#[derive(Debug, Default)]
+pub struct BuiltinState {
+    /// The value to which `__COUNTER__` will expand next.
+    pub counter: u32,
+    /// The name of the main source file.
+    pub base_file: String,
+    /// The nesting depth of the file currently being processed.
+    pub include_level: usize,
+}
+
+/// The macros available for replacement.
+pub struct MacroEnv<'a> {
+    /// The current macro definitions.
+    pub defs: &'a MacroTable,
+    /// The state from which the expansions of builtin macros are computed.
+    pub builtins: &'a mut BuiltinState,
+    /// The level at which empty macro arguments should be reported, if at all.
+    pub empty_arg_level: Option<Level>,
+    /// The level at which omitting the variable arguments of a variadic macro should be reported,
+    /// if at all.
+    pub omitted_va_args_level: Option<Level>,
+}
+
 /// A structure pointing to the state necessary for macro replacement.
 pub struct ReplacementCtx<'a, 'b, 'h> {
     ctx: &'a mut LexCtx<'b, 'h>,
     defs: &'a MacroTable,
     replacements: &'a mut PendingReplacements,
+    builtins: &'a mut BuiltinState,
+    empty_arg_level: Option<Level>,
+    omitted_va_args_level: Option<Level>,
+    tracking: ExpansionTracking,
+    records: ExpansionRecords<'a>,
+    callbacks: &'a mut dyn PpCallbacks,
     lexer: &'a mut dyn ReplacementLexer,
+    /// The function-like macro invocations whose substitution is suspended while one of their
+    /// arguments is pre-expanded, innermost last.
+    substitutions: Vec<Substitution<'a>>,
 }
 
 impl<'a, 'b, 'h> ReplacementCtx<'a, 'b, 'h> {
     /// Creates a new context with the specified state.
+    ///
+    /// The expansions performed are recorded in `records`, and `callbacks` is notified of every
+    /// macro expanded.
     pub fn new(
         ctx: &'a mut LexCtx<'b, 'h>,
-        defs: &'a MacroTable,
+        env: MacroEnv<'a>,
         replacements: &'a mut PendingReplacements,
+        tracking: ExpansionTracking,
+        records: ExpansionRecords<'a>,
+        callbacks: &'a mut dyn PpCallbacks,
         lexer: &'a mut dyn ReplacementLexer,
     ) -> Self {
         Self {
             ctx,
-            defs,
+            defs: env.defs,
             replacements,
+            builtins: env.builtins,
+            empty_arg_level: env.empty_arg_level,
+            omitted_va_args_level: env.omitted_va_args_level,
+            tracking,
+            records,
+            callbacks,
             lexer,
+            substitutions: Vec::new(),
         }
     }
 
     /// Returns the next pending replacement token, if any.
     ///
-    /// This also handles rescanning of the expanded token stream, as per §6.10.3.4.
-    pub fn next_expansion_token(&mut self) -> DResult<Option<ReplacementToken>> {
+    /// This also handles rescanning of the expanded token stream, as per §6.10.3.4. Rescanning
+    /// stops with [`PpError::Cancelled`](crate::PpError::Cancelled) if `cancellation_token` is
+    /// cancelled.
+    pub fn next_expansion_token(
+        &mut self,
+        cancellation_token: Option<&CancellationToken>,
+    ) -> PpResult<Option<ReplacementToken>> {
         while let Some(mut tok) = self.replacements.next_token() {
             if !self.begin_expansion(&mut tok)? {
                 return Ok(Some(tok));
             }
+            cancel::check_cancelled(cancellation_token)?;
         }
 
         Ok(None)
@@ -116,53 +232,155 @@ impl<'a, 'b, 'h> ReplacementCtx<'a, 'b, 'h> {
         }
 
         if let Some(def) = self.defs.lookup(name) {
+            if self.replacements.depth() >= self.ctx.limits.max_expansion_depth {
+                return self.report_depth_exceeded(name_tok.tok, def);
+            }
+
+            // This must be determined before any further tokens are consumed, as doing so may pop
+            // the replacement containing `tok`.
+            let parent = self.replacements.trace_parent();
+
             match &def.kind {
                 MacroDefKind::Object(replacement) => {
-                    self.push_object_macro(name_tok, replacement)?;
+                    self.record_expansion(name_tok.tok, def)?;
+                    self.push_object_macro(name_tok, replacement, parent)?;
                     return Ok(true);
                 }
 
-                MacroDefKind::Function {
-                    params,
-                    replacement,
+                &MacroDefKind::Function {
+                    ref params,
+                    variadic,
+                    ref replacement,
                 } => {
                     return self.try_push_function_macro(
                         name_tok,
-                        def.name_tok,
+                        def,
                         params,
+                        variadic,
                         replacement,
+                        parent,
                     );
                 }
+
+                &MacroDefKind::Builtin(builtin) => {
+                    self.record_expansion(name_tok.tok, def)?;
+                    self.push_builtin_macro(name_tok, builtin, parent);
+                    return Ok(true);
+                }
             }
         }
 
         Ok(false)
     }
 
+    /// Reports a fatal error indicating that expanding the invocation of the macro defined by
+    /// `def` named by `name_tok` would exceed the maximum expansion depth.
+    fn report_depth_exceeded(&mut self, name_tok: Token<Symbol>, def: &MacroDef) -> DResult<bool> {
+        let note = self.macro_def_note(def);
+        let max = self.ctx.limits.max_expansion_depth;
+
+        Err(self
+            .ctx
+            .reporter()
+            .fatal(
+                name_tok.range,
+                Message::new("macro expansion nested too deeply (maximum depth is {max})")
+                    .arg("max", max),
+            )
+            .add_note(note)
+            .emit()
+            .unwrap_err())
+    }
+
+    /// Records that the invocation of the macro defined by `def` named by `name_tok` is being
+    /// expanded, notifying the callbacks.
+    fn record_expansion(&mut self, name_tok: Token<Symbol>, def: &MacroDef) -> DResult<()> {
+        *self.records.count += 1;
+
+        if let Some(sites) = self.records.sites.as_deref_mut() {
+            sites.push(ExpansionSite {
+                name_tok,
+                def_range: def.name_tok.range,
+            });
+        }
+
+        self.callbacks.macro_expanded(self.ctx, name_tok, def)
+    }
+
     /// Pushes an object-like macro expansion replacing `name_tok` with `replacement_list`.
+    ///
+    /// `parent` indicates the traced expansion during which this replacement takes place.
     fn push_object_macro(
         &mut self,
         name_tok: PpToken<Symbol>,
         replacement_list: &ReplacementList,
+        parent: Option<ExpansionParent>,
     ) -> DResult<()> {
+        let step = self.begin_trace_step(name_tok, name_tok.range(), parent);
+
         let tokens = match self.map_replacement_tokens(name_tok.map(|_| ()), replacement_list)? {
             Some(iter) => iter.collect(),
             None => return Ok(()),
         };
-        self.replacements.push(Some(name_tok.data()), tokens);
+        self.push_replacement(name_tok.data(), tokens, step);
         Ok(())
     }
 
+    /// Pushes the expansion of the builtin macro `builtin`, replacing `name_tok`.
+    ///
+    /// The expansion is computed from the location of the outermost macro invocation containing
+    /// `name_tok` (or `name_tok` itself, if it was not produced by a macro expansion).
+    fn push_builtin_macro(
+        &mut self,
+        name_tok: PpToken<Symbol>,
+        builtin: BuiltinMacro,
+        parent: Option<ExpansionParent>,
+    ) {
+        let step = self.begin_trace_step(name_tok, name_tok.range(), parent);
+
+        let smap = &self.ctx.smap;
+        let interp = smap.get_interpreted_range(smap.get_replacement_range(name_tok.range()));
+
+        let kind = match builtin {
+            BuiltinMacro::File => {
+                let filename = interp.presumed_filename().to_string();
+                TokenKind::Str(self.ctx.interner.intern(&quote_str(&filename)))
+            }
+            BuiltinMacro::Line => {
+                let line = interp.presumed_start_linecol().line + 1;
+                TokenKind::Number(self.ctx.interner.intern(&line.to_string()))
+            }
+            BuiltinMacro::Counter => {
+                let counter = self.builtins.counter;
+                self.builtins.counter += 1;
+                TokenKind::Number(self.ctx.interner.intern(&counter.to_string()))
+            }
+            BuiltinMacro::BaseFile => {
+                let quoted = quote_str(&self.builtins.base_file);
+                TokenKind::Str(self.ctx.interner.intern(&quoted))
+            }
+            BuiltinMacro::IncludeLevel => {
+                let level = self.builtins.include_level;
+                TokenKind::Number(self.ctx.interner.intern(&level.to_string()))
+            }
+        };
+
+        let tok = name_tok.map(|_| kind).into();
+        self.push_replacement(name_tok.data(), iter::once(tok).collect(), step);
+    }
+
     /// If the next token is an opening parenthesis, parses and pushes a function-like macro
     /// expansion replacing `name_tok`, returning `true`. Otherwise, returns `false`.
     ///
-    /// `def_tok` should point to the name written in the macro definition.
+    /// `def` should be the definition of the macro being invoked.
     fn try_push_function_macro(
         &mut self,
         name_tok: PpToken<Symbol>,
-        def_tok: Token<Symbol>,
-        params: &[Symbol],
+        def: &MacroDef,
+        params: &'a [Symbol],
+        variadic: bool,
         replacement_list: &ReplacementList,
+        parent: Option<ExpansionParent>,
     ) -> DResult<bool> {
         let peeked = self.peek_token()?;
 
@@ -173,16 +391,45 @@ impl<'a, 'b, 'h> ReplacementCtx<'a, 'b, 'h> {
         // Consume the peeked lparen.
         self.next_token()?;
 
-        let args = match self.parse_macro_args(name_tok.tok, def_tok)? {
+        let va_idx = if variadic {
+            Some(params.len() - 1)
+        } else {
+            None
+        };
+
+        let mut args = match self.parse_macro_args(name_tok.tok, def, va_idx)? {
             Some(args) => args,
             None => return Ok(true),
         };
 
-        if !self.check_arity(name_tok.tok, def_tok, params, &args)? {
+        let va_args_omitted = va_idx == Some(args.len());
+        if va_args_omitted {
+            // The variable arguments have been omitted entirely, together with the comma
+            // preceding them (C23 §6.10.4p12). Treat them as empty.
+            let eof = *args.last().unwrap().back().unwrap();
+            args.push(iter::once(eof).collect());
+        }
+
+        if !self.check_arity(name_tok.tok, def, params, &args)? {
             return Ok(true);
         }
 
-        self.push_parsed_function_macro(name_tok, replacement_list, params, args)?;
+        if va_args_omitted {
+            self.check_omitted_va_args(&args)?;
+        } else if !params.is_empty() {
+            self.check_empty_args(&args)?;
+        }
+
+        self.record_expansion(name_tok.tok, def)?;
+
+        self.push_parsed_function_macro(
+            name_tok,
+            replacement_list,
+            params,
+            variadic,
+            args,
+            parent,
+        )?;
         Ok(true)
     }
 
@@ -190,7 +437,9 @@ impl<'a, 'b, 'h> ReplacementCtx<'a, 'b, 'h> {
     /// parenthesis has been consumed.
     ///
     /// The arguments are parsed according to the rules in §6.10.3p11, skipping nested pairs of
-    /// balanced parentheses.
+    /// balanced parentheses. If `va_idx` is provided, the argument at that index and everything
+    /// after it are merged into a single argument, including the commas separating them
+    /// (§6.10.3p12).
     ///
     /// Every returned argument will be terminated by an `Eof` token indicating where the argument
     /// was terminated, to simplify preexpansion and error reporting later.
@@ -200,23 +449,25 @@ impl<'a, 'b, 'h> ReplacementCtx<'a, 'b, 'h> {
     fn parse_macro_args(
         &mut self,
         name_tok: Token<Symbol>,
-        def_tok: Token<Symbol>,
+        def: &MacroDef,
+        va_idx: Option<usize>,
     ) -> DResult<Option<Vec<VecDeque<ReplacementToken>>>> {
         let mut args = Vec::new();
         let mut cur_arg = VecDeque::new();
         let mut paren_level = 1; // We've already consumed the opening lparen.
 
-        let mut finish_arg = |arg: &mut VecDeque<ReplacementToken>, mut tok: ReplacementToken| {
-            tok.ppt = tok.ppt.map(|_| TokenKind::Eof);
-            arg.push_back(tok);
-            args.push(mem::take(arg))
-        };
+        let finish_arg =
+            |args: &mut Vec<_>, arg: &mut VecDeque<ReplacementToken>, mut tok: ReplacementToken| {
+                tok.ppt = tok.ppt.map(|_| TokenKind::Eof);
+                arg.push_back(tok);
+                args.push(mem::take(arg))
+            };
 
         loop {
             // Make sure that we don't consume the EOF token (if one exists), which could be crucial
             // when using directive lexers or pre-expanding macro arguments.
             if self.peek_token()?.ppt.data() == TokenKind::Eof {
-                let note = self.macro_def_note(def_tok);
+                let note = self.macro_def_note(def);
 
                 self.ctx
                     .reporter()
@@ -226,7 +477,15 @@ impl<'a, 'b, 'h> ReplacementCtx<'a, 'b, 'h> {
                 return Ok(None);
             }
 
-            let tok = self.next_token()?;
+            let mut tok = self.next_token()?;
+
+            // The replacement containing `tok` may be popped before the argument is pre-expanded,
+            // so names that are currently being expanded must be marked now (§6.10.3.4p2).
+            if let TokenKind::Ident(name) = tok.ppt.data() {
+                if self.replacements.is_active(name) {
+                    tok.allow_expansion = false;
+                }
+            }
 
             match tok.ppt.data() {
                 TokenKind::Punct(PunctKind::LParen) => {
@@ -236,14 +495,16 @@ impl<'a, 'b, 'h> ReplacementCtx<'a, 'b, 'h> {
                 TokenKind::Punct(PunctKind::RParen) => {
                     paren_level -= 1;
                     if paren_level == 0 {
-                        finish_arg(&mut cur_arg, tok);
+                        finish_arg(&mut args, &mut cur_arg, tok);
                         break;
                     }
                     cur_arg.push_back(tok);
                 }
 
-                TokenKind::Punct(PunctKind::Comma) if paren_level == 1 => {
-                    finish_arg(&mut cur_arg, tok);
+                TokenKind::Punct(PunctKind::Comma)
+                    if paren_level == 1 && va_idx != Some(args.len()) =>
+                {
+                    finish_arg(&mut args, &mut cur_arg, tok);
                 }
 
                 _ => cur_arg.push_back(tok),
@@ -260,7 +521,7 @@ impl<'a, 'b, 'h> ReplacementCtx<'a, 'b, 'h> {
     fn check_arity(
         &mut self,
         name_tok: Token<Symbol>,
-        def_tok: Token<Symbol>,
+        def: &MacroDef,
         params: &[Symbol],
         args: &[VecDeque<ReplacementToken>],
     ) -> DResult<bool> {
@@ -275,13 +536,14 @@ impl<'a, 'b, 'h> ReplacementCtx<'a, 'b, 'h> {
                 ("few", *args.last().unwrap().back().unwrap())
             };
 
-            let note = self.macro_def_note(def_tok);
+            let note = self.macro_def_note(def);
 
             self.ctx
                 .reporter()
                 .error(
                     name_tok.range,
-                    format!("too {} arguments provided to macro invocation", quantifier),
+                    Message::new("too {quantifier} arguments provided to macro invocation")
+                        .arg("quantifier", quantifier),
                 )
                 .add_range(arg_tok.ppt.range().into())
                 .add_note(note)
@@ -292,60 +554,398 @@ impl<'a, 'b, 'h> ReplacementCtx<'a, 'b, 'h> {
         Ok(true)
     }
 
+    /// Reports every empty argument in `args` if empty macro arguments are not supported by the
+    /// selected language (they were introduced in C99).
+    fn check_empty_args(&mut self, args: &[VecDeque<ReplacementToken>]) -> DResult<()> {
+        let level = match self.empty_arg_level {
+            Some(level) => level,
+            None => return Ok(()),
+        };
+
+        for arg in args.iter().filter(|arg| arg.len() == 1) {
+            self.ctx
+                .reporter()
+                .report(
+                    level,
+                    arg[0].ppt.range(),
+                    "empty macro arguments are a C99 feature",
+                )
+                .set_id(DiagnosticId::Pedantic)
+                .emit()?;
+        }
+
+        Ok(())
+    }
+
+    /// Reports the omission of the variable arguments, which are the last of `args`, if the language
+    /// being preprocessed does not support it.
+    ///
+    /// Any other empty arguments are reported as well.
+    fn check_omitted_va_args(&mut self, args: &[VecDeque<ReplacementToken>]) -> DResult<()> {
+        let (va_args, args) = args.split_last().unwrap();
+        self.check_empty_args(args)?;
+
+        if let Some(level) = self.omitted_va_args_level {
+            self.ctx
+                .reporter()
+                .report(
+                    level,
+                    va_args[0].ppt.range(),
+                    "omitting the variable macro arguments is a C23 feature",
+                )
+                .set_id(DiagnosticId::Pedantic)
+                .emit()?;
+        }
+
+        Ok(())
+    }
+
     /// Pushes a function-like macro replacing `name_tok` with `replacement_list`.
     ///
-    /// This also handles pre-expansion and substitution of macro arguments.
+    /// This also handles pre-expansion and substitution of macro arguments, as well as
+    /// `__VA_OPT__` if the macro is `variadic`.
     fn push_parsed_function_macro(
         &mut self,
         name_tok: PpToken<Symbol>,
         replacement_list: &ReplacementList,
-        params: &[Symbol],
+        params: &'a [Symbol],
+        variadic: bool,
         args: Vec<VecDeque<ReplacementToken>>,
+        parent: Option<ExpansionParent>,
     ) -> DResult<()> {
-        enum ArgState {
-            Raw(VecDeque<ReplacementToken>),
-            PreExpanded(Vec<ReplacementToken>),
+        let mut replacement_tok = name_tok.map(|_| ());
+        replacement_tok.tok.range = self.get_function_replacement_range(name_tok, &args);
+
+        let step = self.begin_trace_step(name_tok, replacement_tok.range(), parent);
+        if let (Some(trace), Some(step)) = (self.records.trace.as_deref_mut(), step) {
+            trace.step_mut(step).args = params
+                .iter()
+                .zip(&args)
+                .map(|(&param, arg)| TracedArg {
+                    param,
+                    tokens: untraced_tokens(
+                        arg.iter()
+                            .take_while(|tok| tok.ppt.data() != TokenKind::Eof),
+                    ),
+                    pre_expanded: None,
+                })
+                .collect();
+        }
+
+        let body = match self.map_replacement_tokens(replacement_tok, replacement_list)? {
+            Some(iter) => iter.collect(),
+            None => return Ok(()),
+        };
+
+        // `__VA_OPT__(...)` is replaced by the tokens between its parentheses if the variable
+        // arguments are nonempty, and removed otherwise (C23 §6.10.4.1).
+        let va_opt = if variadic {
+            Some(TokenKind::Ident(self.ctx.interner.intern("__VA_OPT__")))
+        } else {
+            None
+        };
+        let va_args_empty = variadic && args.last().unwrap().len() == 1;
+
+        self.substitutions.push(Substitution {
+            name_tok,
+            params,
+            pre_expanded: vec![None; args.len()],
+            args,
+            step,
+            body,
+            va_opt,
+            va_args_empty,
+            va_opt_level: None,
+            placemarker: None,
+            tokens: VecDeque::new(),
+            pre_expanding: None,
+        });
+
+        // Invocations nested in the arguments of another are driven by the outermost one, so that
+        // arbitrarily deep nesting does not consume native stack.
+        if self.substitutions.len() == 1 {
+            self.run_substitutions()?;
         }
 
-        fn get_pre_expanded_arg<'c>(
-            this: &mut ReplacementCtx<'_, '_, '_>,
-            arg: &'c mut ArgState,
-        ) -> DResult<impl Iterator<Item = ReplacementToken> + 'c> {
-            if let ArgState::Raw(unexp) = arg {
-                *arg = ArgState::PreExpanded(this.pre_expand_macro_arg(mem::take(unexp))?);
+        Ok(())
+    }
+
+    /// Drives the pending substitutions until all of them have been completed, pre-expanding their
+    /// arguments as needed.
+    ///
+    /// Each argument is expanded as if it formed the remainder of the file (§6.10.3.1p1), which
+    /// relies on the trailing `Eof` added by `parse_macro_args` as a sentinel. Macros invoked while
+    /// pre-expanding an argument push their own substitutions, which are completed first.
+    fn run_substitutions(&mut self) -> DResult<()> {
+        while let Some(sub) = self.substitutions.last() {
+            if sub.pre_expanding.is_some() {
+                let tok = match self.replacements.next_token() {
+                    Some(mut tok) => {
+                        if self.begin_expansion(&mut tok)? {
+                            continue;
+                        }
+                        Some(tok).filter(|tok| tok.ppt.data() != TokenKind::Eof)
+                    }
+                    None => None,
+                };
+
+                let sub = self.substitutions.last_mut().unwrap();
+                match tok {
+                    Some(tok) => sub.pre_expanding.as_mut().unwrap().1.push(tok),
+                    None => {
+                        let (idx, tokens) = sub.pre_expanding.take().unwrap();
+                        sub.pre_expanded[idx] = Some(tokens);
+                    }
+                }
+                continue;
             }
 
-            match arg {
-                ArgState::PreExpanded(preexp) => Ok(preexp.iter().copied()),
-                ArgState::Raw(_) => unreachable!(),
+            let mut sub = self.substitutions.pop().unwrap();
+            match self.substitute(&mut sub)? {
+                Some(idx) => {
+                    let parent = sub.step.map(|step| ExpansionParent {
+                        step,
+                        stage: ExpansionStage::ArgPreExpansion(idx),
+                    });
+                    self.replacements.push(None, sub.args[idx].clone(), parent);
+                    sub.pre_expanding = Some((idx, Vec::new()));
+                    self.substitutions.push(sub);
+                }
+                None => {
+                    if let (Some(trace), Some(step)) = (self.records.trace.as_deref_mut(), sub.step)
+                    {
+                        for (arg, preexp) in
+                            trace.step_mut(step).args.iter_mut().zip(sub.pre_expanded)
+                        {
+                            arg.pre_expanded = preexp.map(|preexp| untraced_tokens(&preexp));
+                        }
+                    }
+
+                    self.push_replacement(sub.name_tok.data(), sub.tokens, sub.step);
+                }
             }
         }
 
-        let mut replacement_tok = name_tok.map(|_| ());
-        replacement_tok.tok.range = self.get_function_replacement_range(name_tok, &args);
+        Ok(())
+    }
 
-        let body_tokens = match self.map_replacement_tokens(replacement_tok, replacement_list)? {
-            Some(iter) => iter,
-            None => return Ok(()),
-        };
+    /// Continues substituting the arguments of `sub` into its replacement list, returning the index
+    /// of an argument that must be pre-expanded before substitution can proceed, if any.
+    fn substitute(&mut self, sub: &mut Substitution<'_>) -> DResult<Option<usize>> {
+        while let Some(tok) = sub.body.pop_front() {
+            if let Some(level) = sub.va_opt_level {
+                sub.va_opt_level = match tok.ppt.data() {
+                    TokenKind::Punct(PunctKind::LParen) => Some(level + 1),
+                    TokenKind::Punct(PunctKind::RParen) if level == 1 => None,
+                    TokenKind::Punct(PunctKind::RParen) => Some(level - 1),
+                    _ => Some(level),
+                };
+
+                if sub.va_opt_level.is_none() {
+                    // Drop the closing parenthesis of the `__VA_OPT__`.
+                    continue;
+                }
+            }
 
-        let mut args: Vec<_> = args.into_iter().map(ArgState::Raw).collect();
-        let mut tokens = VecDeque::new();
+            if Some(tok.ppt.data()) == sub.va_opt {
+                // The definition has already been checked to ensure that every `__VA_OPT__` is
+                // followed by a balanced, parenthesized group.
+                sub.body.pop_front();
+                add_placemarker(&mut sub.placemarker, tok.ppt.map(|_| ()));
+
+                if sub.va_args_empty {
+                    let mut level = 1;
+                    while level > 0 {
+                        match sub.body.pop_front().unwrap().ppt.data() {
+                            TokenKind::Punct(PunctKind::LParen) => level += 1,
+                            TokenKind::Punct(PunctKind::RParen) => level -= 1,
+                            _ => {}
+                        }
+                    }
+                } else {
+                    sub.va_opt_level = Some(1);
+                }
+
+                continue;
+            }
 
-        for tok in body_tokens {
-            if let TokenKind::Ident(ident) = tok.ppt.data() {
-                if let Some(idx) = params.iter().position(|&name| name == ident) {
-                    let preexp = get_pre_expanded_arg(self, &mut args[idx])?;
-                    tokens.extend(self.map_arg_tokens(tok.ppt.map(|_| ()), preexp)?);
+            if tok.ppt.data() == TokenKind::Punct(PunctKind::Hash) {
+                // The definition has already been checked to ensure that every `#` is followed by
+                // a parameter or a `__VA_OPT__`.
+                let operand = sub.body.pop_front().unwrap();
+                let str_tok = if Some(operand.ppt.data()) == sub.va_opt {
+                    match self.stringize_va_opt(sub, tok, operand) {
+                        Ok(str_tok) => str_tok,
+                        Err(idx) => return Ok(Some(idx)),
+                    }
+                } else {
+                    let idx = sub.param_idx(operand).unwrap();
+                    self.stringize_arg(tok.ppt, operand.ppt, &sub.args[idx])
+                };
+                extend_after_placemarker(
+                    &mut sub.tokens,
+                    &mut sub.placemarker,
+                    iter::once(str_tok),
+                );
+                continue;
+            }
+
+            if let Some(idx) = sub.param_idx(tok) {
+                // Arguments are only pre-expanded when they are actually substituted somewhere
+                // other than the operand of a `#`, and at most once.
+                let preexp = match &sub.pre_expanded[idx] {
+                    Some(preexp) => preexp,
+                    None => {
+                        // Resume from this parameter once the argument has been pre-expanded.
+                        sub.body.push_front(tok);
+                        return Ok(Some(idx));
+                    }
+                };
+                if preexp.is_empty() {
+                    add_placemarker(&mut sub.placemarker, tok.ppt.map(|_| ()));
                     continue;
                 }
+
+                let arg_tokens =
+                    self.map_arg_tokens(tok.ppt.map(|_| ()), preexp.iter().copied())?;
+                extend_after_placemarker(&mut sub.tokens, &mut sub.placemarker, arg_tokens);
+                continue;
             }
 
-            tokens.push_back(tok);
+            extend_after_placemarker(&mut sub.tokens, &mut sub.placemarker, iter::once(tok));
         }
 
-        self.replacements.push(Some(name_tok.data()), tokens);
-        Ok(())
+        Ok(None)
+    }
+
+    /// Applies the `#` operator (§6.10.3.2) to the unexpanded argument `arg`, returning a string
+    /// literal spelling it.
+    ///
+    /// `hash_tok` and `param_tok` should be the `#` and parameter tokens in the expansion, which
+    /// determine the range of the resulting token.
+    fn stringize_arg(
+        &mut self,
+        hash_tok: PpToken,
+        param_tok: PpToken,
+        arg: &VecDeque<ReplacementToken>,
+    ) -> ReplacementToken {
+        self.stringize(
+            hash_tok,
+            param_tok,
+            arg.iter()
+                .map(|tok| tok.ppt)
+                .take_while(|ppt| ppt.data() != TokenKind::Eof),
+        )
+    }
+
+    /// Applies the `#` operator to the `__VA_OPT__` at the front of the remaining replacement list
+    /// of `sub` (C23 §6.10.4.1), returning a string literal token spanning from `hash_tok` to the
+    /// closing parenthesis.
+    ///
+    /// The contents of the `__VA_OPT__` are substituted as usual before being stringized, and
+    /// become an empty string if the variable arguments are empty. If an argument substituted
+    /// there has not yet been pre-expanded, the tokens are returned to `sub` and the index of the
+    /// argument is returned instead.
+    fn stringize_va_opt(
+        &mut self,
+        sub: &mut Substitution<'_>,
+        hash_tok: ReplacementToken,
+        va_opt_tok: ReplacementToken,
+    ) -> Result<ReplacementToken, usize> {
+        // The definition has already been checked to ensure that every `__VA_OPT__` is followed by
+        // a balanced, parenthesized group.
+        let mut group = vec![sub.body.pop_front().unwrap()];
+        let mut level = 1;
+        while level > 0 {
+            let tok = sub.body.pop_front().unwrap();
+            match tok.ppt.data() {
+                TokenKind::Punct(PunctKind::LParen) => level += 1,
+                TokenKind::Punct(PunctKind::RParen) => level -= 1,
+                _ => {}
+            }
+            group.push(tok);
+        }
+
+        let rparen_tok = group[group.len() - 1];
+        let contents = if sub.va_args_empty {
+            &[][..]
+        } else {
+            &group[1..group.len() - 1]
+        };
+
+        let missing = contents.iter().enumerate().find_map(|(i, &tok)| {
+            let stringized =
+                i > 0 && contents[i - 1].ppt.data() == TokenKind::Punct(PunctKind::Hash);
+            sub.param_idx(tok)
+                .filter(|&idx| !stringized && sub.pre_expanded[idx].is_none())
+        });
+        if let Some(idx) = missing {
+            for tok in group.into_iter().rev().chain([va_opt_tok, hash_tok]) {
+                sub.body.push_front(tok);
+            }
+            return Err(idx);
+        }
+
+        let mut tokens = Vec::new();
+        let mut contents = contents.iter().copied();
+        while let Some(tok) = contents.next() {
+            if tok.ppt.data() == TokenKind::Punct(PunctKind::Hash) {
+                let param_tok = contents.next().unwrap();
+                let idx = sub.param_idx(param_tok).unwrap();
+                tokens.push(
+                    self.stringize_arg(tok.ppt, param_tok.ppt, &sub.args[idx])
+                        .ppt,
+                );
+            } else if let Some(idx) = sub.param_idx(tok) {
+                let preexp = sub.pre_expanded[idx].as_deref().unwrap_or_default();
+                tokens.extend(preexp.iter().enumerate().map(|(i, arg_tok)| {
+                    let mut ppt = arg_tok.ppt;
+                    if i == 0 {
+                        ppt.leading_trivia = tok.ppt.leading_trivia;
+                    }
+                    ppt
+                }));
+            } else {
+                tokens.push(tok.ppt);
+            }
+        }
+
+        Ok(self.stringize(hash_tok.ppt, rparen_tok.ppt, tokens))
+    }
+
+    /// Creates a string literal token spelling `tokens` as specified for the `#` operator
+    /// (§6.10.3.2p2), with a range spanning from `hash_tok` to `last_tok`.
+    fn stringize(
+        &mut self,
+        hash_tok: PpToken,
+        last_tok: PpToken,
+        tokens: impl IntoIterator<Item = PpToken>,
+    ) -> ReplacementToken {
+        let mut spelling = String::from('"');
+
+        for (idx, ppt) in tokens.into_iter().enumerate() {
+            // Each nonempty run of whitespace between tokens becomes a single space.
+            if idx > 0 && ppt.leading_trivia {
+                spelling.push(' ');
+            }
+
+            let tok_spelling = ppt.tok.display(self.ctx).to_string();
+            match ppt.data() {
+                TokenKind::Str(_) | TokenKind::Char(_) => {
+                    escape_str_into(&mut spelling, &tok_spelling)
+                }
+                _ => spelling.push_str(&tok_spelling),
+            }
+        }
+
+        spelling.push('"');
+
+        let begin = hash_tok.range().start();
+        let end = last_tok.range().end();
+
+        let mut ppt = hash_tok.map(|_| TokenKind::Str(self.ctx.interner.intern(&spelling)));
+        ppt.tok.range = SourceRange::new(begin, end.offset_from(begin));
+        ppt.into()
     }
 
     /// Computes the [replacement range](source::smap::ExpansionSourceInfo::replacement_range)
@@ -371,33 +971,11 @@ impl<'a, 'b, 'h> ReplacementCtx<'a, 'b, 'h> {
             .expect("macro invocation spans multiple files")
     }
 
-    /// Expands the tokens in `arg` as if they form the remainder of the file.
-    ///
-    /// This step is performed before substituting the argument into the expansion of a
-    /// function-like macro.
-    ///
-    /// It is critical here that `arg` have a trailing `Eof` (as added by `parse_macro_args`); it is
-    /// used as a sentinel to stop expansion.
-    /// The returned tokens will no longer have a trailing `Eof`.
-    fn pre_expand_macro_arg(
-        &mut self,
-        arg: VecDeque<ReplacementToken>,
-    ) -> DResult<Vec<ReplacementToken>> {
-        self.replacements.push(None, arg);
-
-        itertools::process_results(
-            iter::from_fn(|| self.next_expansion_token().transpose()),
-            |iter| {
-                iter.take_while(|tok| tok.ppt.data() != TokenKind::Eof)
-                    .collect()
-            },
-        )
-    }
-
     /// Maps every token in `tokens` to a new one with a range indicating that it came from a macro
     /// argument expansion into `replacement_tok`.
     ///
-    /// The tokens need not be contiguous or lie in the same source.
+    /// The tokens need not be contiguous or lie in the same source. Every run of tokens from a
+    /// single source is given its own expansion source, and these are all created at once.
     fn map_arg_tokens(
         &mut self,
         replacement_tok: PpToken<()>,
@@ -411,8 +989,7 @@ impl<'a, 'b, 'h> ReplacementCtx<'a, 'b, 'h> {
         }
 
         let mut tokens = tokens.peekable();
-        let mut ret = Vec::new();
-        let mut first = true;
+        let mut runs = Vec::new();
 
         while let Some(tok) = tokens.next() {
             let mut run = vec![tok];
@@ -424,14 +1001,31 @@ impl<'a, 'b, 'h> ReplacementCtx<'a, 'b, 'h> {
             let end = run.last().unwrap().ppt.range().end();
 
             let spelling_range = SourceRange::new(begin, end.offset_from(begin));
+            let track = self.tracking >= ExpansionTracking::Full
+                || run.iter().any(|tok| self.needs_spelling(tok.ppt));
+
+            runs.push((run, spelling_range, track));
+        }
+
+        let tracked_ranges: Vec<_> = runs
+            .iter()
+            .filter(|&&(_, _, track)| track)
+            .map(|&(_, spelling_range, _)| spelling_range)
+            .collect();
+        let mut exp_ranges = self
+            .create_expansions(replacement_tok, &tracked_ranges, ExpansionKind::MacroArg)?
+            .into_iter();
 
-            ret.extend(self.map_tokens(
+        let mut ret = Vec::new();
+        for (idx, (run, spelling_range, track)) in runs.into_iter().enumerate() {
+            let exp_range = if track { exp_ranges.next() } else { None };
+            ret.extend(relocate_tokens(
                 replacement_tok,
-                mem::replace(&mut first, false),
+                idx == 0,
                 run,
                 spelling_range,
-                ExpansionKind::MacroArg,
-            )?);
+                exp_range,
+            ));
         }
 
         Ok(ret)
@@ -471,6 +1065,10 @@ impl<'a, 'b, 'h> ReplacementCtx<'a, 'b, 'h> {
     /// `replacement_tok`; the first of them will inherit whitespace and line properties from
     /// `replacement_tok`.
     ///
+    /// If expansions of kind `expansion_kind` are not tracked (see [`ExpansionTracking`]), no
+    /// expansion source is created and the tokens are moved to the range of `replacement_tok`
+    /// instead.
+    ///
     /// # Panics
     ///
     /// Panics if any of the tokens does not lie entirely within `spelling_range`.
@@ -482,23 +1080,54 @@ impl<'a, 'b, 'h> ReplacementCtx<'a, 'b, 'h> {
         spelling_range: SourceRange,
         expansion_kind: ExpansionKind,
     ) -> DResult<impl Iterator<Item = ReplacementToken> + 'c> {
-        fn move_subrange(
-            subrange: SourceRange,
-            old_range: SourceRange,
-            new_range: SourceRange,
-        ) -> SourceRange {
-            new_range.subrange(
-                old_range
-                    .local_range(subrange)
-                    .expect("range not in spelling range"),
-            )
+        let required_tracking = match expansion_kind {
+            ExpansionKind::Macro => ExpansionTracking::Macros,
+            _ => ExpansionTracking::Full,
+        };
+
+        let (tokens, track) = if self.tracking >= required_tracking {
+            (Either::Left(tokens.into_iter()), true)
+        } else {
+            let tokens: Vec<_> = tokens.into_iter().collect();
+            let track = tokens.iter().any(|tok| self.needs_spelling(tok.ppt));
+            (Either::Right(tokens.into_iter()), track)
+        };
+
+        let exp_range = if track {
+            self.create_expansions(replacement_tok, &[spelling_range], expansion_kind)?
+                .pop()
+        } else {
+            None
+        };
+
+        Ok(relocate_tokens(
+            replacement_tok,
+            first,
+            tokens,
+            spelling_range,
+            exp_range,
+        ))
+    }
+
+    /// Creates an expansion source of kind `kind` into `replacement_tok` for every range in
+    /// `spelling_ranges`, returning their ranges.
+    ///
+    /// Reports a fatal error if the source map has no room for the expansions.
+    fn create_expansions(
+        &mut self,
+        replacement_tok: PpToken<()>,
+        spelling_ranges: &[SourceRange],
+        kind: ExpansionKind,
+    ) -> DResult<Vec<SourceRange>> {
+        if spelling_ranges.is_empty() {
+            return Ok(Vec::new());
         }
 
         let ctx = &mut self.ctx;
 
-        let exp_id = ctx
+        let ids = ctx
             .smap
-            .create_expansion(spelling_range, replacement_tok.range(), expansion_kind)
+            .create_expansions(spelling_ranges, replacement_tok.range(), kind)
             .map_err(|_| {
                 ctx.reporter()
                     .fatal(
@@ -509,36 +1138,67 @@ impl<'a, 'b, 'h> ReplacementCtx<'a, 'b, 'h> {
                     .unwrap_err()
             })?;
 
-        let exp_range = ctx.smap.get_source(exp_id).range;
+        Ok(ids
+            .into_iter()
+            .map(|id| ctx.smap.get_source(id).range)
+            .collect())
+    }
 
-        Ok(tokens.into_iter().enumerate().map(move |(idx, mut tok)| {
-            let ppt = &mut tok.ppt;
-            if first && idx == 0 {
-                // The first replacement token inherits `line_start` and `leading_trivia`
-                // from the replaced token.
-                ppt.line_start = replacement_tok.line_start;
-                ppt.leading_trivia = replacement_tok.leading_trivia;
-            } else {
-                ppt.line_start = false;
-            }
+    /// Records the start of a replacement of `name_tok` covering `replacement_range` if tracing is
+    /// enabled, returning the index of the new step.
+    fn begin_trace_step(
+        &mut self,
+        name_tok: PpToken<Symbol>,
+        replacement_range: SourceRange,
+        parent: Option<ExpansionParent>,
+    ) -> Option<usize> {
+        self.records
+            .trace
+            .as_deref_mut()
+            .map(|trace| trace.push(ExpansionStep::new(name_tok, replacement_range, parent)))
+    }
 
-            // Move every token to point into the newly-created expansion source.
-            ppt.tok.range = move_subrange(ppt.tok.range, spelling_range, exp_range);
+    /// Pushes `tokens` as the replacement of the macro `name`, recording them in the trace step
+    /// `step` if provided.
+    fn push_replacement(
+        &mut self,
+        name: Symbol,
+        tokens: VecDeque<ReplacementToken>,
+        step: Option<usize>,
+    ) {
+        if let (Some(trace), Some(step)) = (self.records.trace.as_deref_mut(), step) {
+            trace.step_mut(step).replacement = untraced_tokens(&tokens);
+        }
 
-            tok
-        }))
+        let parent = step.map(|step| ExpansionParent {
+            step,
+            stage: ExpansionStage::Rescan,
+        });
+        self.replacements.push(Some(name), tokens, parent);
+    }
+
+    /// Checks whether the spelling of `ppt` is retrieved from the source map (rather than the
+    /// interner) when it is displayed, meaning that its range cannot be replaced.
+    fn needs_spelling(&self, ppt: PpToken) -> bool {
+        match ppt.data() {
+            TokenKind::Unknown => true,
+            TokenKind::Str(_) | TokenKind::Char(_) => self.ctx.is_literal_truncated(ppt.range()),
+            TokenKind::Punct(kind) => self.ctx.punct_spelling(kind, ppt.range()) != kind.as_str(),
+            _ => false,
+        }
     }
 
     /// Creates a diagnostic note indicating the specified macro definition.
-    fn macro_def_note(&self, def_tok: Token<Symbol>) -> RawSubDiagnostic {
-        RawSubDiagnostic::new(
-            format!("macro '{}' defined here", &self.ctx.interner[def_tok.data]),
-            def_tok.range.into(),
-        )
+    fn macro_def_note(&self, def: &MacroDef) -> RawSubDiagnostic {
+        let msg = Message::new("macro '{name}' defined here").arg(
+            "name",
+            MessageArg::symbol(&self.ctx.interner[def.name_tok.data]),
+        );
+        def.def_note(self.ctx.interner, msg)
     }
 
     /// Advances to the next pending expansion token, falling back to the lexer if there is none.
-    fn next_token(&mut self) -> DResult<ReplacementToken> {
+    pub fn next_token(&mut self) -> DResult<ReplacementToken> {
         self.next_or_lex(
             |replacements| replacements.next_token(),
             |lexer, ctx| lexer.next(ctx),
@@ -560,7 +1220,118 @@ impl<'a, 'b, 'h> ReplacementCtx<'a, 'b, 'h> {
         next: impl FnOnce(&mut PendingReplacements) -> Option<ReplacementToken>,
         lex: impl FnOnce(&mut dyn ReplacementLexer, &mut LexCtx<'_, '_>) -> DResult<PpToken>,
     ) -> DResult<ReplacementToken> {
-        next(&mut self.replacements).map_or_else(|| lex(self.lexer, self.ctx).map(Into::into), Ok)
+        next(self.replacements).map_or_else(|| lex(self.lexer, self.ctx).map(Into::into), Ok)
+    }
+}
+
+/// Moves `tokens`, which were spelled in `spelling_range`, into the expansion source range
+/// `exp_range` if there is one, or onto `replacement_tok` otherwise.
+///
+/// If `first` is set, the first token inherits whitespace and line properties from
+/// `replacement_tok`.
+///
+/// # Panics
+///
+/// Panics if any of the tokens does not lie entirely within `spelling_range`.
+fn relocate_tokens(
+    replacement_tok: PpToken<()>,
+    first: bool,
+    tokens: impl IntoIterator<Item = ReplacementToken>,
+    spelling_range: SourceRange,
+    exp_range: Option<SourceRange>,
+) -> impl Iterator<Item = ReplacementToken> {
+    fn move_subrange(
+        subrange: SourceRange,
+        old_range: SourceRange,
+        new_range: SourceRange,
+    ) -> SourceRange {
+        new_range.subrange(
+            old_range
+                .local_range(subrange)
+                .expect("range not in spelling range"),
+        )
+    }
+
+    tokens.into_iter().enumerate().map(move |(idx, mut tok)| {
+        let ppt = &mut tok.ppt;
+        if first && idx == 0 {
+            // The first replacement token inherits `line_start` and its leading trivia from the
+            // replaced token.
+            ppt.line_start = replacement_tok.line_start;
+            ppt.leading_trivia = replacement_tok.leading_trivia;
+            ppt.leading_trivia_range = replacement_tok.leading_trivia_range;
+        } else {
+            ppt.line_start = false;
+            ppt.leading_trivia_range = None;
+        }
+
+        // Move every token to point into the newly-created expansion source, or attribute it to
+        // the replaced token if there is none.
+        ppt.tok.range = match exp_range {
+            Some(exp_range) => move_subrange(ppt.tok.range, spelling_range, exp_range),
+            None => replacement_tok.range(),
+        };
+
+        tok
+    })
+}
+
+/// Records a placemarker replacing `tok`, merging it into the pending `placemarker` (if any).
+fn add_placemarker(placemarker: &mut Option<PpToken<()>>, tok: PpToken<()>) {
+    match placemarker {
+        Some(placemarker) => absorb_placemarker(placemarker, tok),
+        None => *placemarker = Some(tok),
+    }
+}
+
+/// Appends `new_tokens` to `tokens`, removing the pending `placemarker` (if any) by having the first
+/// of them absorb it.
+fn extend_after_placemarker(
+    tokens: &mut VecDeque<ReplacementToken>,
+    placemarker: &mut Option<PpToken<()>>,
+    new_tokens: impl IntoIterator<Item = ReplacementToken>,
+) {
+    let start = tokens.len();
+    tokens.extend(new_tokens);
+
+    if let Some(first) = tokens.get_mut(start) {
+        if let Some(placemarker) = placemarker.take() {
+            absorb_placemarker(&mut first.ppt, placemarker);
+        }
+    }
+}
+
+/// Has `ppt` inherit the line and whitespace properties of `placemarker`, as if the placemarker had
+/// been removed from before it.
+fn absorb_placemarker<T>(ppt: &mut PpToken<T>, placemarker: PpToken<()>) {
+    ppt.line_start |= placemarker.line_start;
+    ppt.leading_trivia |= placemarker.leading_trivia;
+    ppt.leading_trivia_range = placemarker
+        .leading_trivia_range
+        .or(ppt.leading_trivia_range);
+}
+
+/// Strips the expansion state from `tokens` for inclusion in an expansion trace.
+fn untraced_tokens<'t>(tokens: impl IntoIterator<Item = &'t ReplacementToken>) -> Vec<PpToken> {
+    tokens.into_iter().map(|tok| tok.ppt).collect()
+}
+
+/// Returns a string literal spelling `s`, escaping characters as necessary.
+fn quote_str(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    escape_str_into(&mut quoted, s);
+    quoted.push('"');
+    quoted
+}
+
+/// Appends `s` to `buf`, escaping every `"` and `\` so that it can appear in a string literal.
+fn escape_str_into(buf: &mut String, s: &str) {
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            buf.push('\\');
+        }
+        buf.push(c);
     }
 }
 
@@ -571,6 +1342,8 @@ struct PendingReplacement {
     name: Option<Symbol>,
     /// The tokens remaining in this replacement.
     tokens: VecDeque<ReplacementToken>,
+    /// The traced expansion stage producing these tokens, if tracing is enabled.
+    trace_parent: Option<ExpansionParent>,
 }
 
 impl PendingReplacement {
@@ -591,6 +1364,10 @@ pub struct PendingReplacements {
     replacements: Vec<PendingReplacement>,
     /// Tracks which names are currently being expanded.
     active_names: FxHashSet<Symbol>,
+    /// The number of bytes of heap memory used by the token buffers of the active replacements.
+    buffers_size: usize,
+    /// The largest value `buffers_size` has reached.
+    peak_buffers_size: usize,
 }
 
 impl PendingReplacements {
@@ -599,9 +1376,29 @@ impl PendingReplacements {
         Self {
             replacements: Vec::new(),
             active_names: Default::default(),
+            buffers_size: 0,
+            peak_buffers_size: 0,
         }
     }
 
+    /// Returns the largest number of bytes of heap memory used by the replacement token buffers
+    /// at any point.
+    pub fn peak_heap_size(&self) -> usize {
+        self.peak_buffers_size
+    }
+
+    /// Checks whether any replacement tokens remain on the stack.
+    pub fn is_empty(&self) -> bool {
+        self.replacements
+            .iter()
+            .all(|replacement| replacement.tokens.is_empty())
+    }
+
+    /// Returns the number of replacements currently in flight.
+    fn depth(&self) -> usize {
+        self.replacements.len()
+    }
+
     /// Checks whether `name` is currently being expanded.
     fn is_active(&self, name: Symbol) -> bool {
         self.active_names.contains(&name)
@@ -617,14 +1414,37 @@ impl PendingReplacements {
         self.next(|replacement| replacement.peek_token())
     }
 
+    /// Returns the traced expansion stage producing the topmost replacement, if any.
+    ///
+    /// Any expansion begun by a token just taken from the stack takes place during this stage.
+    fn trace_parent(&self) -> Option<ExpansionParent> {
+        self.replacements
+            .last()
+            .and_then(|replacement| replacement.trace_parent)
+    }
+
     /// Pushes a new replacement onto the stack.
     ///
     /// If `name` is provided, it will be considered active until the replacement is popped.
-    fn push(&mut self, name: Option<Symbol>, tokens: VecDeque<ReplacementToken>) {
+    /// `trace_parent` should indicate the traced expansion stage producing the tokens, if any.
+    fn push(
+        &mut self,
+        name: Option<Symbol>,
+        tokens: VecDeque<ReplacementToken>,
+        trace_parent: Option<ExpansionParent>,
+    ) {
         if let Some(name) = name {
             self.active_names.insert(name);
         }
-        self.replacements.push(PendingReplacement { name, tokens });
+
+        self.buffers_size += buffer_size(&tokens);
+        self.peak_buffers_size = self.peak_buffers_size.max(self.buffers_size);
+
+        self.replacements.push(PendingReplacement {
+            name,
+            tokens,
+            trace_parent,
+        });
     }
 
     /// Invokes `f` on the topmost replacement, popping replacements and retrying until it returns
@@ -652,6 +1472,13 @@ impl PendingReplacements {
             if let Some(name) = replacement.name {
                 self.active_names.remove(&name);
             }
+
+            self.buffers_size -= buffer_size(&replacement.tokens);
         }
     }
 }
+
+/// Returns the number of bytes of heap memory used by `tokens`.
+fn buffer_size(tokens: &VecDeque<ReplacementToken>) -> usize {
+    tokens.capacity() * mem::size_of::<ReplacementToken>()
+}
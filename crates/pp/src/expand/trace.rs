@@ -0,0 +1,155 @@
+use lex::{Symbol, Token};
+use source::SourceRange;
+
+use crate::PpToken;
+
+/// A record of the individual steps taken while expanding macros, for use in debugging and
+/// teaching tools.
+///
+/// Every macro replacement performed by the preprocessor (including those performed while
+/// pre-expanding macro arguments and rescanning other expansions) is recorded as an
+/// [`ExpansionStep`]. Steps form a forest: nested expansions refer to the step during which they
+/// took place through [`ExpansionStep::parent`].
+///
+/// Tracing is disabled by default, and can be enabled with
+/// [`PreprocessorBuilder::trace_expansions()`](crate::PreprocessorBuilder::trace_expansions).
+#[derive(Debug, Default)]
+pub struct ExpansionTrace {
+    steps: Vec<ExpansionStep>,
+    /// The most recently started top-level step, to which fully-expanded tokens are attributed.
+    cur_root: Option<usize>,
+}
+
+impl ExpansionTrace {
+    /// Returns all recorded steps, in the order in which they were started.
+    pub fn steps(&self) -> &[ExpansionStep] {
+        &self.steps
+    }
+
+    /// Returns the indices of the top-level steps, which were not performed as part of any other
+    /// expansion.
+    pub fn roots(&self) -> impl Iterator<Item = usize> + '_ {
+        self.steps
+            .iter()
+            .enumerate()
+            .filter(|(_, step)| step.parent.is_none())
+            .map(|(idx, _)| idx)
+    }
+
+    /// Returns the indices of the steps performed during `stage` of the step `parent`.
+    pub fn children(
+        &self,
+        parent: usize,
+        stage: ExpansionStage,
+    ) -> impl Iterator<Item = usize> + '_ {
+        let key = ExpansionParent {
+            step: parent,
+            stage,
+        };
+
+        self.steps
+            .iter()
+            .enumerate()
+            .skip(parent + 1)
+            .filter(move |(_, step)| step.parent == Some(key))
+            .map(|(idx, _)| idx)
+    }
+
+    /// Records a new step, returning its index.
+    pub(super) fn push(&mut self, step: ExpansionStep) -> usize {
+        let idx = self.steps.len();
+        if step.parent.is_none() {
+            self.cur_root = Some(idx);
+        }
+        self.steps.push(step);
+        idx
+    }
+
+    /// Returns a mutable reference to the step at `idx`.
+    pub(super) fn step_mut(&mut self, idx: usize) -> &mut ExpansionStep {
+        &mut self.steps[idx]
+    }
+
+    /// Attributes the fully-expanded token `ppt` to the current top-level step.
+    pub(super) fn push_result(&mut self, ppt: PpToken) {
+        if let Some(root) = self.cur_root {
+            self.steps[root].result.push(ppt);
+        }
+    }
+}
+
+/// The stage of an expansion during which a nested expansion took place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpansionStage {
+    /// The pre-expansion of the argument with the specified index (§6.10.3.1).
+    ArgPreExpansion(usize),
+    /// The rescanning of the replacement list (§6.10.3.4).
+    Rescan,
+}
+
+/// Identifies the step and stage during which a nested expansion took place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExpansionParent {
+    /// The index of the parent step.
+    pub step: usize,
+    /// The stage of the parent step.
+    pub stage: ExpansionStage,
+}
+
+/// A single macro replacement recorded in an [`ExpansionTrace`].
+#[derive(Debug, Clone)]
+pub struct ExpansionStep {
+    /// The name of the replaced macro, as it appeared in the invocation.
+    pub name_tok: PpToken<Symbol>,
+    /// The range replaced by this step: the name of an object-like macro, or the entire invocation
+    /// of a function-like macro.
+    pub replacement_range: SourceRange,
+    /// The step during which this step was performed, if any.
+    pub parent: Option<ExpansionParent>,
+    /// The arguments of a function-like macro invocation, in order.
+    pub args: Vec<TracedArg>,
+    /// The replacement list after argument substitution, which is then rescanned.
+    pub replacement: Vec<PpToken>,
+    /// The fully-expanded tokens produced by a top-level step. This is empty for nested steps.
+    pub result: Vec<PpToken>,
+}
+
+impl ExpansionStep {
+    pub(super) fn new(
+        name_tok: PpToken<Symbol>,
+        replacement_range: SourceRange,
+        parent: Option<ExpansionParent>,
+    ) -> Self {
+        Self {
+            name_tok,
+            replacement_range,
+            parent,
+            args: Vec::new(),
+            replacement: Vec::new(),
+            result: Vec::new(),
+        }
+    }
+}
+
+/// An argument passed to a function-like macro, as recorded in an [`ExpansionStep`].
+#[derive(Debug, Clone)]
+pub struct TracedArg {
+    /// The name of the corresponding parameter.
+    pub param: Symbol,
+    /// The tokens of the argument, as written in the invocation.
+    pub tokens: Vec<PpToken>,
+    /// The fully macro-expanded tokens of the argument, if they were substituted anywhere.
+    ///
+    /// Arguments that are only used as operands of `#` or not used at all are never pre-expanded.
+    pub pre_expanded: Option<Vec<PpToken>>,
+}
+
+/// A macro invocation recorded while preprocessing, if enabled with
+/// [`PreprocessorBuilder::record_expansion_sites()`](crate::PreprocessorBuilder::record_expansion_sites).
+#[derive(Debug, Clone, Copy)]
+pub struct ExpansionSite {
+    /// The name of the expanded macro, as it appeared in the invocation.
+    pub name_tok: Token<Symbol>,
+    /// The range of the name in the definition used for the expansion.
+    pub def_range: SourceRange,
+}
@@ -0,0 +1,902 @@
+//! Evaluation of preprocessing constant expressions, as used by `#if` and `#elif` (§6.10.1).
+//!
+//! The evaluator operates on tokens that have already been macro-expanded and in which all uses of
+//! the `defined` operator have been replaced by `0` or `1`. All arithmetic is performed in
+//! `intmax_t` or `uintmax_t` (§6.10.1p4), which are 64 bits wide here.
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+
+use lex::{LexCtx, PunctKind, TokenKind};
+use source::diag::{DiagnosticId, FatalErrorEmitted, Message, RawSubDiagnostic};
+use source::{DResult, FragmentedSourceRange, SourcePos};
+
+use crate::PpToken;
+
+/// The value of a preprocessing expression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    /// A value of type `intmax_t`.
+    Signed(i64),
+    /// A value of type `uintmax_t`.
+    Unsigned(u64),
+}
+
+impl Value {
+    /// Returns whether the value is nonzero.
+    pub fn is_nonzero(self) -> bool {
+        match self {
+            Value::Signed(val) => val != 0,
+            Value::Unsigned(val) => val != 0,
+        }
+    }
+
+    /// Returns whether the value is of an unsigned type.
+    fn is_unsigned(self) -> bool {
+        matches!(self, Value::Unsigned(_))
+    }
+
+    /// Returns the bit pattern of the value, reinterpreted as unsigned.
+    fn as_unsigned(self) -> u64 {
+        match self {
+            Value::Signed(val) => val as u64,
+            Value::Unsigned(val) => val,
+        }
+    }
+
+    /// Creates an `int` value holding the truth value of `val`, as produced by the logical and
+    /// relational operators.
+    fn from_bool(val: bool) -> Self {
+        Value::Signed(val as i64)
+    }
+}
+
+/// Evaluates the expression consisting of `tokens`, reporting any errors encountered.
+///
+/// `eod_pos` should be the position of the end of the directive, and is used when reporting that
+/// the expression ended unexpectedly. Returns `None` if the expression is invalid.
+pub fn eval(
+    ctx: &mut LexCtx<'_, '_>,
+    tokens: &[PpToken],
+    eod_pos: SourcePos,
+) -> DResult<Option<Value>> {
+    let mut evaluator = Evaluator {
+        ctx,
+        tokens,
+        pos: 0,
+        eod_pos,
+        live: true,
+        depth: 0,
+    };
+
+    match evaluator.eval_full() {
+        Ok(operand) => Ok(Some(operand.value)),
+        Err(EvalError::Invalid) => Ok(None),
+        Err(EvalError::Fatal(fatal)) => Err(fatal),
+    }
+}
+
+/// The reason evaluation was stopped.
+enum EvalError {
+    /// The expression is invalid, and an error has already been reported.
+    Invalid,
+    /// A fatal error was emitted.
+    Fatal(FatalErrorEmitted),
+}
+
+impl From<FatalErrorEmitted> for EvalError {
+    fn from(fatal: FatalErrorEmitted) -> Self {
+        EvalError::Fatal(fatal)
+    }
+}
+
+type EvalResult<T> = Result<T, EvalError>;
+
+/// An evaluated subexpression, along with its location.
+#[derive(Clone, Copy)]
+struct Operand {
+    value: Value,
+    range: FragmentedSourceRange,
+}
+
+/// The binary operators supported in preprocessing expressions.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BinOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Less,
+    Greater,
+    LessEq,
+    GreaterEq,
+    Eq,
+    NotEq,
+    BitAnd,
+    BitXor,
+    BitOr,
+    LogAnd,
+    LogOr,
+}
+
+impl BinOp {
+    /// Returns the binary operator corresponding to `punct`, if any.
+    fn from_punct(punct: PunctKind) -> Option<Self> {
+        let op = match punct {
+            PunctKind::Star => BinOp::Mul,
+            PunctKind::Slash => BinOp::Div,
+            PunctKind::Perc => BinOp::Rem,
+            PunctKind::Plus => BinOp::Add,
+            PunctKind::Minus => BinOp::Sub,
+            PunctKind::LessLess => BinOp::Shl,
+            PunctKind::GreaterGreater => BinOp::Shr,
+            PunctKind::Less => BinOp::Less,
+            PunctKind::Greater => BinOp::Greater,
+            PunctKind::LessEq => BinOp::LessEq,
+            PunctKind::GreaterEq => BinOp::GreaterEq,
+            PunctKind::EqEq => BinOp::Eq,
+            PunctKind::BangEq => BinOp::NotEq,
+            PunctKind::Amp => BinOp::BitAnd,
+            PunctKind::Caret => BinOp::BitXor,
+            PunctKind::Pipe => BinOp::BitOr,
+            PunctKind::AmpAmp => BinOp::LogAnd,
+            PunctKind::PipePipe => BinOp::LogOr,
+            _ => return None,
+        };
+
+        Some(op)
+    }
+
+    /// Returns the precedence of the operator - higher binds more tightly.
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::Mul | BinOp::Div | BinOp::Rem => 10,
+            BinOp::Add | BinOp::Sub => 9,
+            BinOp::Shl | BinOp::Shr => 8,
+            BinOp::Less | BinOp::Greater | BinOp::LessEq | BinOp::GreaterEq => 7,
+            BinOp::Eq | BinOp::NotEq => 6,
+            BinOp::BitAnd => 5,
+            BinOp::BitXor => 4,
+            BinOp::BitOr => 3,
+            BinOp::LogAnd => 2,
+            BinOp::LogOr => 1,
+        }
+    }
+}
+
+/// The lowest precedence of a binary operator, used when parsing the operands of the conditional
+/// operator.
+const MIN_BINOP_PRECEDENCE: u8 = 1;
+
+/// A recursive-descent evaluator for preprocessing expressions.
+struct Evaluator<'a, 'b, 'h, 't> {
+    ctx: &'a mut LexCtx<'b, 'h>,
+    tokens: &'t [PpToken],
+    pos: usize,
+    eod_pos: SourcePos,
+    /// Whether the subexpression currently being evaluated is actually evaluated, as opposed to
+    /// being skipped by `&&`, `||` or `?:`. Warnings and errors that depend on operand values are
+    /// suppressed in unevaluated subexpressions.
+    live: bool,
+    /// The number of enclosing parenthesized subexpressions, unary operators and conditional
+    /// operators, which is limited to keep the recursion bounded.
+    depth: usize,
+}
+
+impl Evaluator<'_, '_, '_, '_> {
+    /// Evaluates the entire expression, checking that no tokens remain.
+    fn eval_full(&mut self) -> EvalResult<Operand> {
+        let operand = self.eval_comma()?;
+
+        if let Some(ppt) = self.peek() {
+            self.ctx
+                .reporter()
+                .error(
+                    ppt.range(),
+                    "token is not a valid binary operator in a preprocessor subexpression",
+                )
+                .emit()?;
+            return Err(EvalError::Invalid);
+        }
+
+        Ok(operand)
+    }
+
+    /// Evaluates a comma expression, which may only appear in unevaluated subexpressions
+    /// (§6.6p3).
+    fn eval_comma(&mut self) -> EvalResult<Operand> {
+        let mut operand = self.eval_conditional()?;
+
+        while let Some(comma) = self.eat_punct(PunctKind::Comma) {
+            if self.live {
+                self.ctx
+                    .reporter()
+                    .warn(comma.range(), "comma operator in operand of '#if'")
+                    .set_id(DiagnosticId::Comma)
+                    .emit()?;
+            }
+
+            let rhs = self.eval_conditional()?;
+            operand = Operand {
+                value: rhs.value,
+                range: join(operand.range, rhs.range),
+            };
+        }
+
+        Ok(operand)
+    }
+
+    /// Evaluates a conditional expression (`a ? b : c`).
+    fn eval_conditional(&mut self) -> EvalResult<Operand> {
+        let cond = self.eval_binary(MIN_BINOP_PRECEDENCE)?;
+
+        let question = match self.eat_punct(PunctKind::Question) {
+            Some(question) => question,
+            None => return Ok(cond),
+        };
+
+        let taken = cond.value.is_nonzero();
+
+        let lhs = self.nested(question, |this| this.eval_live_if(taken, Self::eval_comma))?;
+        if self.eat_punct(PunctKind::Colon).is_none() {
+            let pos = self.next_pos();
+            self.ctx
+                .reporter()
+                .error(pos, "expected ':' in conditional expression")
+                .add_note(RawSubDiagnostic::new(
+                    "to match this '?'",
+                    question.range().into(),
+                ))
+                .emit()?;
+            return Err(EvalError::Invalid);
+        }
+        let rhs = self.nested(question, |this| {
+            this.eval_live_if(!taken, Self::eval_conditional)
+        })?;
+
+        // The result has the common type of both branches (§6.5.15p5).
+        let chosen = if taken { lhs.value } else { rhs.value };
+        let value = if lhs.value.is_unsigned() || rhs.value.is_unsigned() {
+            Value::Unsigned(chosen.as_unsigned())
+        } else {
+            chosen
+        };
+
+        Ok(Operand {
+            value,
+            range: join(cond.range, rhs.range),
+        })
+    }
+
+    /// Evaluates a chain of binary operators binding at least as tightly as `min_precedence`.
+    fn eval_binary(&mut self, min_precedence: u8) -> EvalResult<Operand> {
+        let mut lhs = self.eval_unary()?;
+
+        while let Some((op, op_tok)) = self.peek_binop() {
+            let precedence = op.precedence();
+            if precedence < min_precedence {
+                break;
+            }
+            self.pos += 1;
+
+            // The right-hand side of `&&` and `||` is not evaluated when the left-hand side
+            // already determines the result.
+            let rhs_live = match op {
+                BinOp::LogAnd => lhs.value.is_nonzero(),
+                BinOp::LogOr => !lhs.value.is_nonzero(),
+                _ => true,
+            };
+
+            let rhs = self.eval_live_if(rhs_live, |this| this.eval_binary(precedence + 1))?;
+            let range = join(lhs.range, rhs.range);
+            let value = self.apply_binop(op, op_tok, lhs, rhs)?;
+
+            lhs = Operand { value, range };
+        }
+
+        Ok(lhs)
+    }
+
+    /// Evaluates a unary expression.
+    fn eval_unary(&mut self) -> EvalResult<Operand> {
+        let ppt = self.peek();
+
+        let punct = match ppt.map(|ppt| ppt.data()) {
+            Some(TokenKind::Punct(
+                punct @ (PunctKind::Plus | PunctKind::Minus | PunctKind::Tilde | PunctKind::Bang),
+            )) => punct,
+            _ => return self.eval_primary(),
+        };
+
+        let op_tok = ppt.unwrap();
+        self.pos += 1;
+
+        let operand = self.nested(op_tok, Self::eval_unary)?;
+        let value = match (punct, operand.value) {
+            (PunctKind::Plus, value) => value,
+            (PunctKind::Minus, Value::Signed(val)) => {
+                let (res, overflow) = val.overflowing_neg();
+                if overflow {
+                    self.report_overflow(op_tok, operand.range)?;
+                }
+                Value::Signed(res)
+            }
+            (PunctKind::Minus, Value::Unsigned(val)) => Value::Unsigned(val.wrapping_neg()),
+            (PunctKind::Tilde, Value::Signed(val)) => Value::Signed(!val),
+            (PunctKind::Tilde, Value::Unsigned(val)) => Value::Unsigned(!val),
+            (PunctKind::Bang, value) => Value::from_bool(!value.is_nonzero()),
+            _ => unreachable!(),
+        };
+
+        Ok(Operand {
+            value,
+            range: join(op_tok.range().into(), operand.range),
+        })
+    }
+
+    /// Evaluates a primary expression: a literal, an identifier or a parenthesized expression.
+    fn eval_primary(&mut self) -> EvalResult<Operand> {
+        let ppt = match self.peek() {
+            Some(ppt) => ppt,
+            None => {
+                let pos = self.eod_pos;
+                self.ctx
+                    .reporter()
+                    .error(pos, "expected value in expression")
+                    .emit()?;
+                return Err(EvalError::Invalid);
+            }
+        };
+        self.pos += 1;
+
+        let value = match ppt.data() {
+            TokenKind::Number(num) => {
+                let lit = self.ctx.interner[num].to_owned();
+                self.eval_int_literal(ppt, &lit)?
+            }
+            TokenKind::Char(lit) => {
+                let lit = self.ctx.interner[lit].to_owned();
+                self.eval_char_literal(ppt, &lit)?
+            }
+
+            // Identifiers remaining after macro expansion are replaced with `0` (§6.10.1p4).
+            TokenKind::Ident(_) => Value::Signed(0),
+
+            TokenKind::Punct(PunctKind::LParen) => {
+                let inner = self.nested(ppt, Self::eval_comma)?;
+
+                match self.eat_punct(PunctKind::RParen) {
+                    Some(rparen) => {
+                        return Ok(Operand {
+                            value: inner.value,
+                            range: FragmentedSourceRange::new(
+                                ppt.range().start(),
+                                rparen.range().end(),
+                            ),
+                        })
+                    }
+                    None => {
+                        let pos = self.next_pos();
+                        self.ctx
+                            .reporter()
+                            .error_expected_delim(pos, ')')
+                            .add_note(RawSubDiagnostic::new(
+                                "to match this '('",
+                                ppt.range().into(),
+                            ))
+                            .emit()?;
+                        return Err(EvalError::Invalid);
+                    }
+                }
+            }
+
+            _ => {
+                self.ctx
+                    .reporter()
+                    .error(
+                        ppt.range(),
+                        "invalid token at start of a preprocessor expression",
+                    )
+                    .emit()?;
+                return Err(EvalError::Invalid);
+            }
+        };
+
+        Ok(Operand {
+            value,
+            range: ppt.range().into(),
+        })
+    }
+
+    /// Evaluates a subexpression nested within `tok` with `f`, reporting a fatal error if this
+    /// would exceed the maximum nesting depth.
+    fn nested(
+        &mut self,
+        tok: PpToken,
+        f: impl FnOnce(&mut Self) -> EvalResult<Operand>,
+    ) -> EvalResult<Operand> {
+        let max = self.ctx.limits.max_bracket_depth;
+        if self.depth >= max {
+            let msg = Message::new("expression nested too deeply (maximum depth is {max})")
+                .arg("max", max);
+            return Err(self
+                .ctx
+                .reporter()
+                .fatal(tok.range(), msg)
+                .emit()
+                .unwrap_err()
+                .into());
+        }
+
+        self.depth += 1;
+        let res = f(self);
+        self.depth -= 1;
+        res
+    }
+
+    /// Applies the binary operator `op` to `lhs` and `rhs`, reporting any overflow or division by
+    /// zero.
+    fn apply_binop(
+        &mut self,
+        op: BinOp,
+        op_tok: PpToken,
+        lhs: Operand,
+        rhs: Operand,
+    ) -> EvalResult<Value> {
+        let range = join(lhs.range, rhs.range);
+
+        match op {
+            BinOp::LogAnd => {
+                return Ok(Value::from_bool(
+                    lhs.value.is_nonzero() && rhs.value.is_nonzero(),
+                ))
+            }
+            BinOp::LogOr => {
+                return Ok(Value::from_bool(
+                    lhs.value.is_nonzero() || rhs.value.is_nonzero(),
+                ))
+            }
+
+            // The result of a shift has the type of its left operand (§6.5.7p3).
+            BinOp::Shl | BinOp::Shr => return self.apply_shift(op, op_tok, lhs, rhs),
+
+            _ => {}
+        }
+
+        if matches!(op, BinOp::Div | BinOp::Rem) && !rhs.value.is_nonzero() {
+            if !self.live {
+                return Ok(lhs.value);
+            }
+
+            self.ctx
+                .reporter()
+                .error(
+                    op_tok.range(),
+                    "division by zero in preprocessor expression",
+                )
+                .add_range(rhs.range)
+                .emit()?;
+            return Err(EvalError::Invalid);
+        }
+
+        // Perform the usual arithmetic conversions (§6.3.1.8): if either operand is unsigned,
+        // both are converted to `uintmax_t`.
+        if lhs.value.is_unsigned() || rhs.value.is_unsigned() {
+            let (lhs, rhs) = (lhs.value.as_unsigned(), rhs.value.as_unsigned());
+
+            let value = match op {
+                BinOp::Mul => lhs.wrapping_mul(rhs),
+                BinOp::Div => lhs / rhs,
+                BinOp::Rem => lhs % rhs,
+                BinOp::Add => lhs.wrapping_add(rhs),
+                BinOp::Sub => lhs.wrapping_sub(rhs),
+                BinOp::BitAnd => lhs & rhs,
+                BinOp::BitXor => lhs ^ rhs,
+                BinOp::BitOr => lhs | rhs,
+                _ => return Ok(compare(op, lhs.cmp(&rhs))),
+            };
+
+            return Ok(Value::Unsigned(value));
+        }
+
+        let (l, r) = match (lhs.value, rhs.value) {
+            (Value::Signed(l), Value::Signed(r)) => (l, r),
+            _ => unreachable!(),
+        };
+
+        let (value, overflow) = match op {
+            BinOp::Mul => l.overflowing_mul(r),
+            BinOp::Div => l.overflowing_div(r),
+            BinOp::Rem => l.overflowing_rem(r),
+            BinOp::Add => l.overflowing_add(r),
+            BinOp::Sub => l.overflowing_sub(r),
+            BinOp::BitAnd => (l & r, false),
+            BinOp::BitXor => (l ^ r, false),
+            BinOp::BitOr => (l | r, false),
+            _ => return Ok(compare(op, l.cmp(&r))),
+        };
+
+        if overflow {
+            self.report_overflow(op_tok, range)?;
+        }
+
+        Ok(Value::Signed(value))
+    }
+
+    /// Applies the shift operator `op` to `lhs` and `rhs`.
+    ///
+    /// Negative shift counts shift in the opposite direction, and counts exceeding the width of
+    /// the type shift out all bits; both are warned about.
+    fn apply_shift(
+        &mut self,
+        op: BinOp,
+        op_tok: PpToken,
+        lhs: Operand,
+        rhs: Operand,
+    ) -> EvalResult<Value> {
+        let (left, count) = match rhs.value {
+            Value::Signed(count) if count < 0 => {
+                self.warn_live(
+                    op_tok,
+                    rhs.range,
+                    DiagnosticId::ShiftCountNegative,
+                    "shift count is negative",
+                )?;
+                (op == BinOp::Shr, count.unsigned_abs())
+            }
+            value => (op == BinOp::Shl, value.as_unsigned()),
+        };
+
+        if count >= 64 {
+            self.warn_live(
+                op_tok,
+                rhs.range,
+                DiagnosticId::ShiftCountOverflow,
+                "shift count >= width of type",
+            )?;
+        }
+
+        let value = match lhs.value {
+            Value::Unsigned(val) => Value::Unsigned(match (left, count) {
+                (_, 64..) => 0,
+                (true, count) => val << count,
+                (false, count) => val >> count,
+            }),
+            Value::Signed(val) => {
+                let res = match (left, count) {
+                    (true, 64..) => 0,
+                    (false, 64..) => val >> 63,
+                    (true, count) => val << count,
+                    (false, count) => val >> count,
+                };
+
+                // Left shifts overflow when bits (including the sign bit) are lost.
+                if left && (count >= 64 && val != 0 || count < 64 && res >> count != val) {
+                    self.report_overflow(op_tok, join(lhs.range, rhs.range))?;
+                }
+
+                Value::Signed(res)
+            }
+        };
+
+        Ok(value)
+    }
+
+    /// Evaluates the integer literal `lit`, spelled by `ppt`.
+    fn eval_int_literal(&mut self, ppt: PpToken, lit: &str) -> EvalResult<Value> {
+        let (digits, radix) =
+            if let Some(hex) = lit.strip_prefix("0x").or_else(|| lit.strip_prefix("0X")) {
+                (hex, 16)
+            } else if let Some(bin) = lit.strip_prefix("0b").or_else(|| lit.strip_prefix("0B")) {
+                (bin, 2)
+            } else if lit.starts_with('0') {
+                (lit, 8)
+            } else {
+                (lit, 10)
+            };
+
+        let digits_end = digits
+            .find(|c: char| !c.is_digit(radix))
+            .unwrap_or(digits.len());
+        let (digits, suffix) = digits.split_at(digits_end);
+
+        let is_float = match radix {
+            16 => suffix.starts_with(['.', 'p', 'P']),
+            2 => false,
+            _ => suffix.starts_with(['.', 'e', 'E']),
+        };
+
+        if is_float {
+            return self.invalid(ppt, "floating constant in preprocessor expression");
+        }
+
+        let unsigned = match parse_int_suffix(suffix) {
+            Some(unsigned) if !digits.is_empty() => unsigned,
+            _ => {
+                let msg = Message::new("invalid integer constant '{literal}'").arg("literal", lit);
+                return self.invalid(ppt, msg);
+            }
+        };
+
+        let value = match digits.chars().try_fold(0u64, |acc, c| {
+            acc.checked_mul(radix as u64)?
+                .checked_add(c.to_digit(radix).unwrap() as u64)
+        }) {
+            Some(value) => value,
+            None => {
+                return self.invalid(ppt, "integer constant is too large for its type");
+            }
+        };
+
+        if unsigned {
+            return Ok(Value::Unsigned(value));
+        }
+
+        match i64::try_from(value) {
+            Ok(value) => Ok(Value::Signed(value)),
+            Err(_) => {
+                // Octal and hexadecimal constants silently become unsigned if they do not fit in
+                // a signed type (§6.4.4.1p5).
+                if radix == 10 {
+                    self.ctx
+                        .reporter()
+                        .warn(
+                            ppt.range(),
+                            "integer constant is so large that it is unsigned",
+                        )
+                        .set_id(DiagnosticId::ImplicitlyUnsignedLiteral)
+                        .emit()?;
+                }
+                Ok(Value::Unsigned(value))
+            }
+        }
+    }
+
+    /// Evaluates the character constant `lit`, spelled by `ppt`.
+    fn eval_char_literal(&mut self, ppt: PpToken, lit: &str) -> EvalResult<Value> {
+        if self.ctx.is_literal_truncated(ppt.range()) {
+            return self.invalid(ppt, "character constant is too long for its type");
+        }
+
+        let (prefix, body) = lit.split_at(lit.find('\'').unwrap());
+        let body = match body
+            .strip_prefix('\'')
+            .and_then(|body| body.strip_suffix('\''))
+        {
+            Some(body) => body,
+            None => return self.invalid(ppt, "unterminated character constant"),
+        };
+
+        // Plain character constants are encoded in UTF-8, so non-ASCII characters in them occupy
+        // several bytes.
+        let chars = match unescape_char_literal(body, prefix.is_empty()) {
+            Ok(chars) => chars,
+            Err(msg) => return self.invalid(ppt, msg),
+        };
+
+        let value = match chars.as_slice() {
+            [] => return self.invalid(ppt, "empty character constant"),
+
+            // Plain character constants have type `int`, with the value of a (signed) `char`
+            // (§6.4.4.4p10).
+            [c] if prefix.is_empty() => Value::Signed(*c as u8 as i8 as i64),
+            [c] => Value::Signed(*c as i64),
+
+            [..] if prefix.is_empty() => {
+                self.ctx
+                    .reporter()
+                    .warn(ppt.range(), "multi-character character constant")
+                    .set_id(DiagnosticId::Multichar)
+                    .emit()?;
+
+                // Mirror the implementation-defined behavior of GCC and Clang, which pack the
+                // characters into an `int`.
+                let packed = chars
+                    .iter()
+                    .fold(0u32, |acc, &c| (acc << 8) | (c as u8 as u32));
+                Value::Signed(packed as i32 as i64)
+            }
+
+            [.., last] => {
+                self.ctx
+                    .reporter()
+                    .warn(ppt.range(), "character constant too long for its type")
+                    .set_id(DiagnosticId::CharLiteralTooLong)
+                    .emit()?;
+                Value::Signed(*last as i64)
+            }
+        };
+
+        Ok(value)
+    }
+
+    /// Evaluates `f` with the liveness of the current subexpression restricted by `live`.
+    fn eval_live_if(
+        &mut self,
+        live: bool,
+        f: impl FnOnce(&mut Self) -> EvalResult<Operand>,
+    ) -> EvalResult<Operand> {
+        let prev_live = self.live;
+        self.live = prev_live && live;
+        let res = f(self);
+        self.live = prev_live;
+        res
+    }
+
+    /// Reports an integer overflow at `op_tok`, if the current subexpression is evaluated.
+    fn report_overflow(&mut self, op_tok: PpToken, range: FragmentedSourceRange) -> EvalResult<()> {
+        self.warn_live(
+            op_tok,
+            range,
+            DiagnosticId::IntegerOverflow,
+            "integer overflow in preprocessor expression",
+        )
+    }
+
+    /// Reports a warning with the specified ID at `op_tok` highlighting `range`, if the current
+    /// subexpression is evaluated.
+    fn warn_live(
+        &mut self,
+        op_tok: PpToken,
+        range: FragmentedSourceRange,
+        id: DiagnosticId,
+        msg: &str,
+    ) -> EvalResult<()> {
+        if self.live {
+            self.ctx
+                .reporter()
+                .warn(op_tok.range(), msg)
+                .add_range(range)
+                .set_id(id)
+                .emit()?;
+        }
+
+        Ok(())
+    }
+
+    /// Reports an error at `ppt` and stops evaluation.
+    fn invalid<T>(&mut self, ppt: PpToken, msg: impl Into<Message>) -> EvalResult<T> {
+        self.ctx.reporter().error(ppt.range(), msg).emit()?;
+        Err(EvalError::Invalid)
+    }
+
+    /// Returns the binary operator at the current position, if any.
+    fn peek_binop(&self) -> Option<(BinOp, PpToken)> {
+        let ppt = self.peek()?;
+        match ppt.data() {
+            TokenKind::Punct(punct) => BinOp::from_punct(punct).map(|op| (op, ppt)),
+            _ => None,
+        }
+    }
+
+    /// Consumes the next token if it is the punctuator `punct`.
+    fn eat_punct(&mut self, punct: PunctKind) -> Option<PpToken> {
+        let ppt = self.peek()?;
+        if ppt.data() == TokenKind::Punct(punct) {
+            self.pos += 1;
+            Some(ppt)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the position of the next token, or of the end of the directive if there is none.
+    fn next_pos(&self) -> SourcePos {
+        self.peek().map_or(self.eod_pos, |ppt| ppt.range().start())
+    }
+
+    fn peek(&self) -> Option<PpToken> {
+        self.tokens.get(self.pos).copied()
+    }
+}
+
+/// Returns the result of the relational or equality operator `op`, given the ordering of its
+/// operands.
+fn compare(op: BinOp, ordering: Ordering) -> Value {
+    let res = match op {
+        BinOp::Less => ordering.is_lt(),
+        BinOp::Greater => ordering.is_gt(),
+        BinOp::LessEq => ordering.is_le(),
+        BinOp::GreaterEq => ordering.is_ge(),
+        BinOp::Eq => ordering.is_eq(),
+        BinOp::NotEq => ordering.is_ne(),
+        _ => unreachable!("not a comparison operator"),
+    };
+
+    Value::from_bool(res)
+}
+
+/// Returns a range spanning from the start of `first` to the end of `last`.
+fn join(first: FragmentedSourceRange, last: FragmentedSourceRange) -> FragmentedSourceRange {
+    FragmentedSourceRange::new(first.start, last.end)
+}
+
+/// Parses the integer suffix `suffix`, returning whether it makes the constant unsigned.
+///
+/// Returns `None` if the suffix is invalid.
+fn parse_int_suffix(suffix: &str) -> Option<bool> {
+    let unsigned_first = suffix.starts_with(['u', 'U']);
+    let rest = if unsigned_first { &suffix[1..] } else { suffix };
+
+    let (long, rest) = ["ll", "LL", "l", "L"]
+        .iter()
+        .find_map(|long| rest.strip_prefix(long).map(|rest| (true, rest)))
+        .unwrap_or((false, rest));
+
+    match rest {
+        "" => Some(unsigned_first),
+        "u" | "U" if !unsigned_first && long => Some(true),
+        _ => None,
+    }
+}
+
+/// Unescapes the body of a character constant (without its quotes), returning the values of the
+/// characters it contains.
+///
+/// If `utf8` is set, characters spelled directly in the body are split into the bytes of their
+/// UTF-8 encoding.
+fn unescape_char_literal(body: &str, utf8: bool) -> Result<Vec<u32>, String> {
+    let mut chars = body.chars().peekable();
+    let mut values = Vec::new();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            if utf8 {
+                values.extend(c.encode_utf8(&mut [0; 4]).bytes().map(u32::from));
+            } else {
+                values.push(c as u32);
+            }
+            continue;
+        }
+
+        let escape = chars
+            .next()
+            .ok_or_else(|| "incomplete escape sequence".to_owned())?;
+
+        let value = match escape {
+            '\'' | '"' | '?' | '\\' => escape as u32,
+            'a' => 0x07,
+            'b' => 0x08,
+            'f' => 0x0c,
+            'n' => 0x0a,
+            'r' => 0x0d,
+            't' => 0x09,
+            'v' => 0x0b,
+            '0'..='7' => {
+                let mut value = escape.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            value = value * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                value
+            }
+            'x' => {
+                let mut value: Option<u32> = None;
+                while let Some(digit) = chars.peek().and_then(|c| c.to_digit(16)) {
+                    value = Some(
+                        value
+                            .unwrap_or(0)
+                            .checked_mul(16)
+                            .and_then(|value| value.checked_add(digit))
+                            .ok_or_else(|| "hex escape sequence out of range".to_owned())?,
+                    );
+                    chars.next();
+                }
+                value.ok_or_else(|| "\\x used with no following hex digits".to_owned())?
+            }
+            _ => return Err(format!("unknown escape sequence '\\{}'", escape)),
+        };
+
+        values.push(value);
+    }
+
+    Ok(values)
+}
@@ -0,0 +1,68 @@
+//! Support for the `__has_feature` and `__has_extension` operators in `#if` expressions.
+
+use rustc_hash::FxHashMap;
+
+/// The level at which a feature is supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Support {
+    /// The feature is part of the language being compiled, so both `__has_feature` and
+    /// `__has_extension` report it.
+    Feature,
+    /// The feature is available as an extension, so only `__has_extension` reports it.
+    Extension,
+}
+
+/// A table of the features reported by the `__has_feature` and `__has_extension` operators.
+///
+/// Feature names may be queried with or without surrounding double underscores, so that
+/// `__has_feature(__foo__)` is equivalent to `__has_feature(foo)`.
+///
+/// Set the table used by the preprocessor with
+/// [`PreprocessorBuilder::features()`](crate::PreprocessorBuilder::features). By default, no
+/// features are reported.
+#[derive(Debug, Clone, Default)]
+pub struct FeatureTable {
+    features: FxHashMap<String, Support>,
+}
+
+impl FeatureTable {
+    /// Creates an empty table.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Records `name` as a feature, reported by both `__has_feature` and `__has_extension`.
+    pub fn add_feature(&mut self, name: &str) -> &mut Self {
+        self.features
+            .insert(normalize_name(name).to_owned(), Support::Feature);
+        self
+    }
+
+    /// Records `name` as an extension, reported only by `__has_extension`.
+    ///
+    /// This has no effect if `name` has already been recorded as a feature.
+    pub fn add_extension(&mut self, name: &str) -> &mut Self {
+        self.features
+            .entry(normalize_name(name).to_owned())
+            .or_insert(Support::Extension);
+        self
+    }
+
+    /// Checks whether `__has_feature(name)` should evaluate to `1`.
+    pub fn has_feature(&self, name: &str) -> bool {
+        self.features.get(normalize_name(name)) == Some(&Support::Feature)
+    }
+
+    /// Checks whether `__has_extension(name)` should evaluate to `1`.
+    pub fn has_extension(&self, name: &str) -> bool {
+        self.features.contains_key(normalize_name(name))
+    }
+}
+
+/// Strips surrounding double underscores from the feature name `name`, if present.
+fn normalize_name(name: &str) -> &str {
+    name.strip_prefix("__")
+        .and_then(|name| name.strip_suffix("__"))
+        .filter(|name| !name.is_empty())
+        .unwrap_or(name)
+}
@@ -1,16 +1,20 @@
-use std::borrow::Borrow;
 use std::collections::hash_map::Entry;
 use std::fs;
-use std::io;
+use std::io::{self, Read};
 use std::path::{Component, Path, PathBuf};
 use std::rc::Rc;
 
+use indexmap::IndexSet;
 use rustc_hash::FxHashMap;
 
+use lex::Symbol;
 use source::smap::FileContents;
+use source::sync::Lrc;
+
+use crate::vfs::{FileStamp, FileSystem, RealFileSystem};
 
 /// Represents the two kinds of `#include` directives.
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum IncludeKind {
     /// `#include "filename"`
     Quoted,
@@ -21,51 +25,176 @@ pub enum IncludeKind {
 /// Represents a source file loaded by the preprocessor.
 pub struct File {
     /// The contents of the file.
-    pub contents: Rc<FileContents>,
+    pub contents: Lrc<FileContents>,
     /// The parent directory of the file, for use when resolving quoted `#include` directives.
     pub parent_dir: Option<PathBuf>,
+    /// The path from which the file was read, if it came from the file system. This is recorded
+    /// as a dependency when the file is included.
+    pub path: Option<PathBuf>,
 }
 
 impl File {
-    /// Creates a new file with the specified data.
-    pub fn new(contents: Rc<FileContents>, parent_dir: Option<PathBuf>) -> Rc<Self> {
+    /// Creates a new file with the specified data, which did not come from the file system.
+    pub fn new(contents: Lrc<FileContents>, parent_dir: Option<PathBuf>) -> Rc<Self> {
         Rc::new(File {
             contents,
             parent_dir,
+            path: None,
+        })
+    }
+
+    /// Creates a new file with the specified contents, which were read from `path`.
+    pub fn from_path(contents: Lrc<FileContents>, path: PathBuf) -> Rc<Self> {
+        Rc::new(File {
+            contents,
+            parent_dir: path.parent().map(|p| p.into()),
+            path: Some(path),
         })
     }
+
+    /// Creates a new file with the contents read from `reader`, such as the standard input. The
+    /// contents are decoded as with [`FileContents::decode()`].
+    ///
+    /// As the file did not come from the file system, quoted includes are resolved relative to
+    /// `parent_dir` if provided. Passing an empty path resolves them relative to the current
+    /// directory, while passing `None` searches only the include directories.
+    pub fn from_reader(mut reader: impl Read, parent_dir: Option<PathBuf>) -> io::Result<Rc<Self>> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Ok(File::new(FileContents::decode(bytes), parent_dir))
+    }
 }
 
 /// A path-based cache of loaded files.
-struct FileCache {
+///
+/// A cache can be reused across several preprocessor instances (see
+/// [`PreprocessorBuilder::file_cache()`](crate::PreprocessorBuilder::file_cache)) to avoid reading
+/// the same headers repeatedly. Cached files are not reloaded when they change on disk unless
+/// [`revalidate()`](Self::revalidate()) is called, as is appropriate when the cache outlives a
+/// single build.
+///
+/// Files reached through different paths (for example, through symbolic links or `..` components)
+/// share the same contents, which are only read and split into lines once.
+///
+/// Files are read from the real file system by default; use [`FileCache::with_file_system()`] to
+/// read them from elsewhere.
+pub struct FileCache {
+    fs: Box<dyn FileSystem>,
     files: FxHashMap<PathBuf, Rc<File>>,
+    /// The contents of every file read so far, keyed by canonical path, along with the stamp of the
+    /// version read.
+    contents: FxHashMap<PathBuf, (Lrc<FileContents>, Option<FileStamp>)>,
+    stats: FileCacheStats,
+}
+
+impl Default for FileCache {
+    fn default() -> Self {
+        Self::with_file_system(RealFileSystem::new())
+    }
+}
+
+/// Counts of the ways in which loads from a [`FileCache`] were satisfied.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FileCacheStats {
+    /// Loads of a path that had already been loaded.
+    pub hits: usize,
+    /// Loads of a new path referring to a file that had already been read through a different
+    /// path.
+    pub aliases: usize,
+    /// Loads that read a file from disk.
+    pub reads: usize,
 }
 
 impl FileCache {
-    /// Creates a new, empty cache.
+    /// Creates a new, empty cache reading files from the real file system.
     pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new, empty cache reading files from `fs`.
+    pub fn with_file_system(fs: impl FileSystem + 'static) -> Self {
         Self {
+            fs: Box::new(fs),
             files: FxHashMap::default(),
+            contents: FxHashMap::default(),
+            stats: FileCacheStats::default(),
         }
     }
 
+    /// Returns the file system from which files are read.
+    pub fn file_system(&self) -> &dyn FileSystem {
+        &*self.fs
+    }
+
+    /// Returns the number of files currently in the cache.
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Returns `true` if the cache contains no files.
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Returns statistics about the loads performed through this cache so far.
+    pub fn stats(&self) -> FileCacheStats {
+        self.stats
+    }
+
     /// Loads the file at `path` into the cache and returns it.
     ///
     /// Subsequent loads of `path` will return the existing cached file.
     pub fn load(&mut self, path: &Path) -> io::Result<Rc<File>> {
         let path = weakly_normalize(path);
-        match self.files.entry(path) {
-            Entry::Occupied(ent) => Ok(ent.get().clone()),
+        if let Some(file) = self.files.get(&path) {
+            self.stats.hits += 1;
+            return Ok(Rc::clone(file));
+        }
+
+        let contents = match self.contents.entry(self.fs.canonicalize(&path)?) {
+            Entry::Occupied(ent) => {
+                self.stats.aliases += 1;
+                Lrc::clone(&ent.get().0)
+            }
             Entry::Vacant(ent) => {
-                let path = ent.key();
-                let file = File::new(
-                    FileContents::new(&fs::read_to_string(&path)?),
-                    path.parent().map(|p| p.into()),
-                );
-                ent.insert(file.clone());
-                Ok(file)
+                // Take the stamp first, so that any change made while reading is detected later.
+                let stamp = self.fs.stamp(ent.key());
+                let contents = self.fs.load_contents(ent.key())?;
+                self.stats.reads += 1;
+                Lrc::clone(&ent.insert((contents, stamp)).0)
             }
+        };
+
+        let file = File::from_path(contents, path.clone());
+        self.files.insert(path, Rc::clone(&file));
+        Ok(file)
+    }
+
+    /// Evicts every cached file that has changed on disk since it was read, according to
+    /// [`FileSystem::stamp()`], so that the next load reads it again. Returns the number of files
+    /// evicted.
+    ///
+    /// Files already handed out by the cache keep their old contents.
+    pub fn revalidate(&mut self) -> usize {
+        let fs = &self.fs;
+        let mut evicted = Vec::new();
+        self.contents.retain(|path, (contents, stamp)| {
+            let fresh = fs.stamp(path) == *stamp;
+            if !fresh {
+                evicted.push(Lrc::clone(contents));
+            }
+            fresh
+        });
+
+        if !evicted.is_empty() {
+            self.files.retain(|_, file| {
+                !evicted
+                    .iter()
+                    .any(|contents| Lrc::ptr_eq(contents, &file.contents))
+            });
         }
+
+        evicted.len()
     }
 }
 
@@ -76,77 +205,356 @@ fn weakly_normalize(path: &Path) -> PathBuf {
 }
 
 /// Represents the errors that can occur when including a file.
+#[derive(Debug)]
 pub enum IncludeError {
     /// The file was not found after searching all include paths.
     NotFound,
     /// An IO error occurred when reading the file.
     Io {
+        /// The path of the file that could not be read.
         full_path: PathBuf,
+        /// The error encountered.
         error: io::Error,
     },
 }
 
+/// A hook for reading the binary resources embedded with `#embed`.
+///
+/// Resources are located in the same way as included files, but are not cached. The default
+/// loader, [`FsResourceLoader`], reads them from the file system.
+pub trait ResourceLoader {
+    /// Reads at most `max_len` bytes from the start of the resource at `path`.
+    ///
+    /// An error of kind [`io::ErrorKind::NotFound`] indicates that the search for the resource
+    /// should continue in the next directory.
+    fn load(&mut self, path: &Path, max_len: usize) -> io::Result<Vec<u8>>;
+}
+
+/// A [`ResourceLoader`] that reads resources from the file system.
+#[derive(Default)]
+pub struct FsResourceLoader;
+
+impl ResourceLoader for FsResourceLoader {
+    fn load(&mut self, path: &Path, max_len: usize) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        fs::File::open(path)?
+            .take(max_len as u64)
+            .read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// A file located by an [`IncludeResolver`].
+pub struct ResolvedInclude {
+    /// The located file.
+    pub file: Rc<File>,
+    /// The position in the resolver's search order at which the file was found, or `None` if it
+    /// was found some other way (such as relative to the includer).
+    ///
+    /// This is passed back to the resolver for any `#include_next` directives in the file.
+    pub search_pos: Option<usize>,
+}
+
+/// A strategy for locating the files requested by `#include` directives.
+///
+/// Implementations can integrate build-system-specific layouts (such as header maps or generated
+/// files) with the preprocessor. The default resolver, [`DirectoryResolver`], searches a list of
+/// include directories.
+///
+/// Included files are identified by the address of the returned [`File`], so a resolver should
+/// return the same `Rc` every time it resolves the same file; `#pragma once` relies on this.
+pub trait IncludeResolver {
+    /// Resolves the file spelled `filename` in an include directive of the specified `kind`
+    /// appearing in `includer`.
+    ///
+    /// For `#include_next` directives, `next_from` holds the position in the search order at which
+    /// the search should start; locations outside the search order (such as the includer's
+    /// directory) should not be considered. `cache` is the preprocessor's file cache, which can be
+    /// used to load files from disk.
+    fn resolve(
+        &mut self,
+        filename: &Path,
+        kind: IncludeKind,
+        includer: &File,
+        next_from: Option<usize>,
+        cache: &mut FileCache,
+    ) -> Result<ResolvedInclude, IncludeError>;
+}
+
+/// An [`IncludeResolver`] that searches lists of directories on the file system.
+///
+/// Quoted includes are first searched for in the includer's parent directory. After that, user
+/// include directories are searched, followed by system include directories. A directory that
+/// appears in both lists is only searched as a system directory.
+#[derive(Default)]
+pub struct DirectoryResolver {
+    search_dirs: Vec<PathBuf>,
+}
+
+impl DirectoryResolver {
+    /// Creates a new resolver searching `include_dirs` and then `system_dirs`, in order.
+    pub fn new(include_dirs: Vec<PathBuf>, system_dirs: Vec<PathBuf>) -> Self {
+        let mut search_dirs: Vec<_> = include_dirs
+            .into_iter()
+            .filter(|dir| !system_dirs.contains(dir))
+            .collect();
+        search_dirs.extend(system_dirs);
+
+        Self { search_dirs }
+    }
+
+    /// Returns the directories searched by this resolver, in order. Positions in the search order
+    /// are indices into this list.
+    pub fn search_dirs(&self) -> &[PathBuf] {
+        &self.search_dirs
+    }
+}
+
+impl IncludeResolver for DirectoryResolver {
+    fn resolve(
+        &mut self,
+        filename: &Path,
+        kind: IncludeKind,
+        includer: &File,
+        next_from: Option<usize>,
+        cache: &mut FileCache,
+    ) -> Result<ResolvedInclude, IncludeError> {
+        search(
+            &self.search_dirs,
+            filename,
+            kind,
+            includer,
+            next_from,
+            |path| cache.load(path),
+        )
+        .map(|(file, search_pos)| ResolvedInclude { file, search_pos })
+    }
+}
+
+/// Describes when an included file can be skipped because including it again would have no effect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IncludeGuard {
+    /// The file has been marked with `#pragma once`, and should never be included again.
+    Once,
+    /// The entire file is wrapped in an `#ifndef` block testing the specified macro, so it can be
+    /// skipped as long as the macro is defined.
+    Macro(Symbol),
+}
+
+/// Identifies an included file for the purpose of tracking its [`IncludeGuard`].
+#[derive(PartialEq, Eq, Hash)]
+enum FileKey {
+    /// The canonical path of a file that came from the file system.
+    Path(PathBuf),
+    /// The address of a file that did not come from the file system.
+    Addr(*const File),
+}
+
+impl FileKey {
+    fn of(file: &Rc<File>, fs: &dyn FileSystem) -> Self {
+        match &file.path {
+            Some(path) => FileKey::Path(fs.canonicalize(path).unwrap_or_else(|_| path.clone())),
+            None => FileKey::Addr(Rc::as_ptr(file)),
+        }
+    }
+}
+
 /// A structure responsible for finding and caching included files.
 pub struct IncludeLoader {
     cache: FileCache,
     include_dirs: Vec<PathBuf>,
+    resolver: Box<dyn IncludeResolver>,
+    resource_loader: Box<dyn ResourceLoader>,
+    dependencies: IndexSet<PathBuf>,
+    guards: FxHashMap<FileKey, IncludeGuard>,
 }
 
 impl IncludeLoader {
-    /// Creates a new include loader with the specified include directories.
+    /// Creates a new include loader resolving included files with `resolver`, loading files into
+    /// `cache` and embedded resources through `resource_loader`.
     ///
-    /// These will be searched in order when attempting to load an included file.
-    pub fn new(include_dirs: Vec<PathBuf>) -> Self {
+    /// The include directories will be searched in order when attempting to load an embedded
+    /// resource.
+    pub fn new(
+        include_dirs: Vec<PathBuf>,
+        cache: FileCache,
+        resolver: Box<dyn IncludeResolver>,
+        resource_loader: Box<dyn ResourceLoader>,
+    ) -> Self {
         Self {
-            cache: FileCache::new(),
+            cache,
             include_dirs,
+            resolver,
+            resource_loader,
+            dependencies: IndexSet::new(),
+            guards: FxHashMap::default(),
         }
     }
 
-    /// Attempts to load the requested file, searching all include directories in order.
+    /// Returns the guard recorded for `file`, if any.
+    ///
+    /// Files are identified by their canonical paths, so that the guard applies no matter how the
+    /// file is reached.
+    pub fn guard(&self, file: &Rc<File>) -> Option<IncludeGuard> {
+        self.guards
+            .get(&FileKey::of(file, self.cache.file_system()))
+            .copied()
+    }
+
+    /// Records that `file` is guarded by `guard`.
     ///
-    /// If the include is a quoted include, the includer's parent directory is searched as well.
+    /// A file marked with `#pragma once` remains so even if a controlling macro is recorded for it
+    /// later.
+    pub fn set_guard(&mut self, file: &Rc<File>, guard: IncludeGuard) {
+        let key = FileKey::of(file, self.cache.file_system());
+        match self.guards.entry(key) {
+            Entry::Occupied(mut ent) => {
+                if *ent.get() != IncludeGuard::Once {
+                    ent.insert(guard);
+                }
+            }
+            Entry::Vacant(ent) => {
+                ent.insert(guard);
+            }
+        }
+    }
+
+    /// Returns the file system from which included files are read.
+    pub fn file_system(&self) -> &dyn FileSystem {
+        self.cache.file_system()
+    }
+
+    /// Returns statistics about the files loaded through the loader's file cache.
+    pub fn cache_stats(&self) -> FileCacheStats {
+        self.cache.stats()
+    }
+
+    /// Consumes the loader, returning its file cache.
+    pub fn into_cache(self) -> FileCache {
+        self.cache
+    }
+
+    /// Returns the paths of all files and resources loaded so far, in the order in which they were
+    /// first loaded.
+    ///
+    /// Included files that did not come from the file system are not listed.
+    pub fn dependencies(&self) -> impl Iterator<Item = &Path> {
+        self.dependencies.iter().map(|path| path.as_path())
+    }
+
+    /// Attempts to load the requested file through the include resolver, starting at the search
+    /// position `next_from` for `#include_next` directives.
     pub fn load(
         &mut self,
         filename: &Path,
         kind: IncludeKind,
         includer: &File,
-    ) -> Result<Rc<File>, IncludeError> {
-        fn do_load(
-            cache: &mut FileCache,
-            full_path: impl Borrow<Path> + Into<PathBuf>,
-        ) -> Result<Rc<File>, IncludeError> {
-            cache.load(full_path.borrow()).map_err(|e| {
-                if e.kind() == io::ErrorKind::NotFound {
-                    IncludeError::NotFound
-                } else {
-                    IncludeError::Io {
-                        full_path: full_path.into(),
-                        error: e,
-                    }
-                }
-            })
-        }
+        next_from: Option<usize>,
+    ) -> Result<ResolvedInclude, IncludeError> {
+        let resolved =
+            self.resolver
+                .resolve(filename, kind, includer, next_from, &mut self.cache)?;
 
-        if filename.is_absolute() {
-            // Avoid repeatedly looking up the same file.
-            return do_load(&mut self.cache, filename);
+        if let Some(path) = &resolved.file.path {
+            if !self.dependencies.contains(path) {
+                self.dependencies.insert(path.clone());
+            }
         }
 
-        let initial_dir = includer
-            .parent_dir
-            .as_ref()
-            .filter(|_| kind == IncludeKind::Quoted);
+        Ok(resolved)
+    }
 
-        let dirs = initial_dir.into_iter().chain(self.include_dirs.iter());
+    /// Checks whether the requested file can be found through the include resolver, as with
+    /// [`Self::load()`], without recording it as a dependency.
+    ///
+    /// Files that exist but cannot be read are treated as missing.
+    pub fn probe(
+        &mut self,
+        filename: &Path,
+        kind: IncludeKind,
+        includer: &File,
+        next_from: Option<usize>,
+    ) -> bool {
+        self.resolver
+            .resolve(filename, kind, includer, next_from, &mut self.cache)
+            .is_ok()
+    }
+
+    /// Attempts to read at most `max_len` bytes of the resource requested by an `#embed`
+    /// directive, searching all include directories in order.
+    ///
+    /// If the directive is a quoted one, the includer's parent directory is searched as well.
+    pub fn load_resource(
+        &mut self,
+        filename: &Path,
+        kind: IncludeKind,
+        includer: &File,
+        max_len: usize,
+    ) -> Result<Vec<u8>, IncludeError> {
+        let loader = &mut self.resource_loader;
+        let dependencies = &mut self.dependencies;
+        search(&self.include_dirs, filename, kind, includer, None, |path| {
+            let data = loader.load(path, max_len)?;
+            if !dependencies.contains(path) {
+                dependencies.insert(path.into());
+            }
+            Ok(data)
+        })
+        .map(|(data, _)| data)
+    }
+}
 
-        for dir in dirs {
-            match do_load(&mut self.cache, dir.join(filename)) {
-                Err(IncludeError::NotFound) => continue,
-                ret => return ret,
+/// Searches for `filename` by calling `load` with its path in every candidate directory in order,
+/// until it returns something other than a "not found" error.
+///
+/// If `next_from` is provided, the search starts at that index in `include_dirs` and the includer's
+/// directory is skipped. On success, the index of the directory in which the file was found is
+/// returned as well, if it is one of `include_dirs`.
+fn search<T>(
+    include_dirs: &[PathBuf],
+    filename: &Path,
+    kind: IncludeKind,
+    includer: &File,
+    next_from: Option<usize>,
+    mut load: impl FnMut(&Path) -> io::Result<T>,
+) -> Result<(T, Option<usize>), IncludeError> {
+    let mut do_load = |full_path: &Path| {
+        load(full_path).map_err(|e| {
+            if e.kind() == io::ErrorKind::NotFound {
+                IncludeError::NotFound
+            } else {
+                IncludeError::Io {
+                    full_path: full_path.into(),
+                    error: e,
+                }
             }
-        }
+        })
+    };
 
-        Err(IncludeError::NotFound)
+    if filename.is_absolute() {
+        // Avoid repeatedly looking up the same file.
+        return do_load(filename).map(|ret| (ret, None));
     }
+
+    let initial_dir = includer
+        .parent_dir
+        .as_ref()
+        .filter(|_| kind == IncludeKind::Quoted && next_from.is_none());
+
+    let dirs = initial_dir.map(|dir| (None, dir)).into_iter().chain(
+        include_dirs
+            .iter()
+            .enumerate()
+            .skip(next_from.unwrap_or(0))
+            .map(|(idx, dir)| (Some(idx), dir)),
+    );
+
+    for (pos, dir) in dirs {
+        match do_load(&dir.join(filename)) {
+            Err(IncludeError::NotFound) => continue,
+            ret => return ret.map(|ret| (ret, pos)),
+        }
+    }
+
+    Err(IncludeError::NotFound)
 }
@@ -0,0 +1,83 @@
+//! Options describing the language version being preprocessed.
+
+use lex::raw::LexerConfig;
+
+/// A revision of the C standard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LangStandard {
+    /// ANSI C89 / ISO C90.
+    C89,
+    /// ISO C99.
+    C99,
+    /// ISO C11.
+    C11,
+    /// ISO C17.
+    C17,
+    /// ISO C23.
+    C23,
+}
+
+impl LangStandard {
+    /// Returns the value of `__STDC_VERSION__` for this standard, or `None` if the standard does
+    /// not define the macro.
+    pub fn stdc_version(self) -> Option<&'static str> {
+        match self {
+            LangStandard::C89 => None,
+            LangStandard::C99 => Some("199901L"),
+            LangStandard::C11 => Some("201112L"),
+            LangStandard::C17 => Some("201710L"),
+            LangStandard::C23 => Some("202311L"),
+        }
+    }
+}
+
+/// The language accepted by the preprocessor, selected with
+/// [`PreprocessorBuilder::lang_options()`](crate::PreprocessorBuilder::lang_options).
+///
+/// The default is C11 without GNU extensions or pedantic diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LangOptions {
+    /// The revision of the standard being followed.
+    pub standard: LangStandard,
+    /// Whether GNU extensions are enabled, as with the `gnu` modes of `-std`.
+    ///
+    /// Currently, this defines the builtin macros `__COUNTER__`, `__BASE_FILE__` and
+    /// `__INCLUDE_LEVEL__`, and keeps `//` comments in C89.
+    pub gnu_extensions: bool,
+    /// Whether warnings should be reported for constructs not supported by `standard`, as with
+    /// `-pedantic`.
+    pub pedantic: bool,
+}
+
+impl Default for LangOptions {
+    fn default() -> Self {
+        Self {
+            standard: LangStandard::C11,
+            gnu_extensions: false,
+            pedantic: false,
+        }
+    }
+}
+
+impl LangOptions {
+    /// Returns the tokenizer configuration matching these options.
+    ///
+    /// The strict modes enable trigraphs (except for C23), and strict C89 additionally disables
+    /// digraphs and `//` comments.
+    pub fn lexer_config(&self) -> LexerConfig {
+        let config = match self.standard {
+            LangStandard::C89 => LexerConfig::c90(),
+            LangStandard::C99 | LangStandard::C11 | LangStandard::C17 => LexerConfig::c99(),
+            LangStandard::C23 => LexerConfig::c23(),
+        };
+
+        if self.gnu_extensions {
+            LexerConfig {
+                u8_char_literals: config.u8_char_literals,
+                ..LexerConfig::default()
+            }
+        } else {
+            config
+        }
+    }
+}
@@ -2,22 +2,70 @@
 
 #![warn(rust_2018_idioms)]
 
+use std::collections::VecDeque;
+use std::convert::TryFrom;
 use std::mem;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
 
-use lex::{Lex, LexCtx, Token, TokenKind};
+use rustc_hash::FxHashMap;
+
+use lex::raw::LexerConfig;
+use lex::{Lex, LexCtx, Symbol, Token, TokenKind};
+use source::diag::{DiagnosticId, Level, Message};
 use source::{DResult, SourceId, SourceRange};
 
-use active_file::{ActiveFiles, Event};
+use active_file::{ActiveFiles, Event, FileEnv, ProcessorOptions};
+use callbacks::NoCallbacks;
+use embed::{EmbedParams, EmbedTokens};
 use expand::MacroState;
-use file::{IncludeError, IncludeKind, IncludeLoader};
+use file::{IncludeGuard, IncludeLoader};
+use predef::MacroAction;
+use stats::StatsRecorder;
 
+pub use callbacks::PpCallbacks;
+pub use cancel::{CancellationToken, PpError, PpResult};
+pub use expand::{
+    BuiltinMacro, DisplayDef, ExpansionParent, ExpansionSite, ExpansionStage, ExpansionStep,
+    ExpansionTrace, ExpansionTracking, MacroDef, MacroDefKind, MacroOrigin, ReplacementList,
+    TracedArg,
+};
+pub use features::FeatureTable;
+pub use file::{
+    DirectoryResolver, File, FileCache, FileCacheStats, FsResourceLoader, IncludeError,
+    IncludeKind, IncludeResolver, ResolvedInclude, ResourceLoader,
+};
+pub use lang::{LangOptions, LangStandard};
+pub use pragma::{Pragma, PragmaHandler};
+pub use refactor::{apply_edits, extract_define, inline_macro, ExtractedDefine};
+pub use source::limits::{
+    DEFAULT_MAX_EMBED_SIZE, DEFAULT_MAX_EXPANSION_DEPTH, DEFAULT_MAX_INCLUDE_DEPTH,
+};
+pub use stats::{MemoryStats, PpStats, StatsPhase};
+pub use stream::{Checkpoint, TokenStream};
 pub use token::PpToken;
+pub use vfs::{FileStamp, FileSystem, InMemoryFileSystem, RealFileSystem};
 
 mod active_file;
+mod callbacks;
+mod cancel;
+mod embed;
 mod expand;
+mod expr_eval;
+mod features;
 mod file;
+mod lang;
+mod pragma;
+mod predef;
+mod refactor;
+mod stats;
+mod stream;
 mod token;
+mod vfs;
+
+#[cfg(test)]
+mod tests;
 
 /// Helper structure implementing the builder pattern for constructing a new [`Preprocessor`].
 pub struct PreprocessorBuilder<'a, 'b, 'h> {
@@ -25,8 +73,30 @@ pub struct PreprocessorBuilder<'a, 'b, 'h> {
     main_id: SourceId,
     parent_dir: Option<PathBuf>,
     include_dirs: Vec<PathBuf>,
+    system_include_dirs: Vec<PathBuf>,
+    expansion_tracking: ExpansionTracking,
+    trace_expansions: bool,
+    record_expansion_sites: bool,
+    file_boundaries: bool,
+    processor_options: ProcessorOptions,
+    missing_include_level: Level,
+    collect_stats: bool,
+    file_cache: FileCache,
+    cancellation_token: Option<CancellationToken>,
+    macro_actions: Vec<MacroAction>,
+    forced_includes: Vec<PathBuf>,
+    date_time: Option<SystemTime>,
+    features: FeatureTable,
+    pragma_handlers: FxHashMap<Symbol, Box<dyn PragmaHandler>>,
+    include_resolver: Option<Box<dyn IncludeResolver>>,
+    resource_loader: Option<Box<dyn ResourceLoader>>,
+    callbacks: Option<Box<dyn PpCallbacks>>,
 }
 
+/// The number of tokens returned by [`Preprocessor::next_pp()`] between checks of the cancellation
+/// token.
+const CANCELLATION_CHECK_INTERVAL: u32 = 256;
+
 impl<'a, 'b, 'h> PreprocessorBuilder<'a, 'b, 'h> {
     /// Creates a new builder for preprocessing the source file specified by `main_id` in
     /// `ctx.smap`.
@@ -38,6 +108,24 @@ impl<'a, 'b, 'h> PreprocessorBuilder<'a, 'b, 'h> {
             main_id,
             parent_dir: None,
             include_dirs: Vec::new(),
+            system_include_dirs: Vec::new(),
+            expansion_tracking: ExpansionTracking::default(),
+            trace_expansions: false,
+            record_expansion_sites: false,
+            file_boundaries: false,
+            processor_options: ProcessorOptions::default(),
+            missing_include_level: Level::Error,
+            collect_stats: false,
+            file_cache: FileCache::new(),
+            cancellation_token: None,
+            macro_actions: Vec::new(),
+            forced_includes: Vec::new(),
+            date_time: None,
+            features: FeatureTable::new(),
+            pragma_handlers: FxHashMap::default(),
+            include_resolver: None,
+            resource_loader: None,
+            callbacks: None,
         }
     }
 
@@ -48,23 +136,292 @@ impl<'a, 'b, 'h> PreprocessorBuilder<'a, 'b, 'h> {
         self
     }
 
-    /// Sets the include directories for use in `#include <filename>` resolution. These directories
-    /// will be scanned from first to last.
+    /// Sets the include directories for use in `#include <filename>` and `#embed <filename>`
+    /// resolution. These directories will be scanned from first to last.
+    ///
+    /// The directories are ignored for `#include` directives if a custom
+    /// [include resolver](Self::include_resolver()) is set.
     pub fn include_dirs(&mut self, dirs: Vec<PathBuf>) -> &mut Self {
         self.include_dirs = dirs;
         self
     }
 
+    /// Sets the system include directories, which are scanned from first to last after the
+    /// [include directories](Self::include_dirs()). A directory appearing in both lists is only
+    /// scanned as a system directory, as with `-isystem`.
+    ///
+    /// Like the include directories, these are ignored for `#include` directives if a custom
+    /// [include resolver](Self::include_resolver()) is set.
+    pub fn system_include_dirs(&mut self, dirs: Vec<PathBuf>) -> &mut Self {
+        self.system_include_dirs = dirs;
+        self
+    }
+
+    /// Sets how precisely the origins of macro expansion tokens should be tracked. The default is
+    /// [`ExpansionTracking::Full`].
+    pub fn expansion_tracking(&mut self, tracking: ExpansionTracking) -> &mut Self {
+        self.expansion_tracking = tracking;
+        self
+    }
+
+    /// Sets whether every macro replacement performed should be recorded in an
+    /// [`ExpansionTrace`], which can be retrieved with [`Preprocessor::expansion_trace()`]. Tracing
+    /// is disabled by default.
+    pub fn trace_expansions(&mut self, trace: bool) -> &mut Self {
+        self.trace_expansions = trace;
+        self
+    }
+
+    /// Sets whether the location of every macro invocation expanded should be recorded together
+    /// with that of the definition used, for retrieval with [`Preprocessor::expansion_sites()`].
+    /// This is disabled by default.
+    pub fn record_expansion_sites(&mut self, record: bool) -> &mut Self {
+        self.record_expansion_sites = record;
+        self
+    }
+
+    /// Sets whether the time spent in each [`StatsPhase`] and counts of the tokens, directives,
+    /// expansions and includes processed should be recorded, for retrieval with
+    /// [`Preprocessor::stats()`]. This is disabled by default, as timing every phase has a
+    /// measurable cost.
+    pub fn collect_stats(&mut self, enabled: bool) -> &mut Self {
+        self.collect_stats = enabled;
+        self
+    }
+
+    /// Sets whether [`Preprocessor::next_pp_event()`] should report the start and end of every
+    /// included file with [`PpEvent::EnterFile`] and [`PpEvent::ExitFile`]. This is disabled by
+    /// default.
+    pub fn file_boundaries(&mut self, enabled: bool) -> &mut Self {
+        self.file_boundaries = enabled;
+        self
+    }
+
+    /// Sets the language being preprocessed, which determines the value of `__STDC_VERSION__`,
+    /// whether GNU builtin macros are available and which pedantic diagnostics are reported. The
+    /// default is [`LangOptions::default()`].
+    ///
+    /// This also replaces the tokenizer configuration with [`LangOptions::lexer_config()`], which
+    /// can be adjusted further by calling [`Self::lexer_config()`] afterwards.
+    pub fn lang_options(&mut self, lang: LangOptions) -> &mut Self {
+        self.processor_options.lang = lang;
+        self.processor_options.lexer_config = lang.lexer_config();
+        self
+    }
+
+    /// Sets the options used when tokenizing source files, such as whether digraphs and `//`
+    /// comments are recognized. The default is [`LexerConfig::default()`].
+    ///
+    /// If trigraphs are enabled, a warning is reported for every trigraph replaced.
+    pub fn lexer_config(&mut self, config: LexerConfig) -> &mut Self {
+        self.processor_options.lexer_config = config;
+        self
+    }
+
+    /// Sets whether comments should be preserved as [`TokenKind::Comment`] tokens, as with the `-C`
+    /// option. This is disabled by default.
+    ///
+    /// Comments inside directives are dropped along with the directive, and comments inside macro
+    /// invocations are treated as whitespace.
+    pub fn keep_comments(&mut self, keep: bool) -> &mut Self {
+        self.processor_options.keep_comments = keep;
+        self
+    }
+
+    /// Sets whether every token should record the source range of the whitespace, comments and
+    /// newlines preceding it in [`PpToken::leading_trivia_range`], so that the source can be
+    /// reconstructed exactly. This is disabled by default.
+    pub fn capture_trivia(&mut self, capture: bool) -> &mut Self {
+        self.processor_options.capture_trivia = capture;
+        self
+    }
+
+    /// Sets the level of the diagnostic reported by `#error` directives. The default is
+    /// [`Level::Error`], in which case preprocessing continues after the directive; pass
+    /// [`Level::Fatal`] to stop preprocessing instead.
+    ///
+    /// `#warning` directives always report warnings.
+    pub fn error_directive_level(&mut self, level: Level) -> &mut Self {
+        self.processor_options.error_directive_level = level;
+        self
+    }
+
+    /// Sets whether diagnostics for constructs that are not strictly conforming, such as defining
+    /// a macro whose name is reserved to the implementation, should be reported as errors instead
+    /// of warnings. This is disabled by default.
+    ///
+    /// Enabling this also enables the diagnostics of [`LangOptions::pedantic`].
+    pub fn pedantic_errors(&mut self, enabled: bool) -> &mut Self {
+        self.processor_options.pedantic_errors = enabled;
+        self
+    }
+
+    /// Sets the level of the diagnostic reported when the file named by an `#include` directive
+    /// cannot be found. The default is [`Level::Error`], in which case preprocessing continues as if
+    /// the directive were absent; pass [`Level::Fatal`] to stop preprocessing instead.
+    pub fn missing_include_level(&mut self, level: Level) -> &mut Self {
+        self.missing_include_level = level;
+        self
+    }
+
+    /// Sets the cache into which included files are loaded, which may already contain files
+    /// loaded by a previous preprocessor. The cache can be retrieved again with
+    /// [`Preprocessor::into_file_cache()`].
+    pub fn file_cache(&mut self, cache: FileCache) -> &mut Self {
+        self.file_cache = cache;
+        self
+    }
+
+    /// Defines a macro before preprocessing, as with the `-D` command-line option.
+    ///
+    /// `def` can be of the form `NAME` (defining `NAME` as `1`), `NAME=VALUE` or
+    /// `NAME(PARAMS)=VALUE`. Predefined macros may be overridden this way. Definitions and
+    /// [undefinitions](Self::undef()) are processed in the order in which they were added.
+    pub fn define(&mut self, def: impl Into<String>) -> &mut Self {
+        self.macro_actions.push(MacroAction::Define(def.into()));
+        self
+    }
+
+    /// Undefines the macro `name` before preprocessing, as with the `-U` command-line option.
+    ///
+    /// This can be used to remove predefined macros.
+    pub fn undef(&mut self, name: impl Into<String>) -> &mut Self {
+        self.macro_actions.push(MacroAction::Undef(name.into()));
+        self
+    }
+
+    /// Includes the file at `path` before the first line of the main file, as with the `-include`
+    /// command-line option.
+    ///
+    /// Files are included in the order in which they were added, after all macros have been
+    /// [defined](Self::define()) and [undefined](Self::undef()). Relative paths are resolved
+    /// against the current directory first, and then searched for like `#include "path"`.
+    pub fn include_file(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.forced_includes.push(path.into());
+        self
+    }
+
+    /// Sets the time used for the expansions of `__DATE__` and `__TIME__`. The default is the time
+    /// at which the preprocessor is built.
+    pub fn date_time(&mut self, time: SystemTime) -> &mut Self {
+        self.date_time = Some(time);
+        self
+    }
+
+    /// Sets the table of features reported by `__has_feature` and `__has_extension` in `#if`
+    /// expressions. By default, no features are reported.
+    pub fn features(&mut self, features: FeatureTable) -> &mut Self {
+        self.features = features;
+        self
+    }
+
+    /// Sets a token through which preprocessing can be cancelled.
+    ///
+    /// The token is checked periodically while tokens are produced and before every include, after
+    /// which [`Preprocessor::next_pp()`] returns [`PpError::Cancelled`].
+    pub fn cancellation_token(&mut self, token: CancellationToken) -> &mut Self {
+        self.cancellation_token = Some(token);
+        self
+    }
+
+    /// Registers `handler` to handle all pragmas whose first token is the identifier `name`,
+    /// replacing any handler previously registered for it.
+    ///
+    /// `#pragma once` is always handled by the preprocessor itself.
+    pub fn pragma_handler(
+        &mut self,
+        name: &str,
+        handler: impl PragmaHandler + 'static,
+    ) -> &mut Self {
+        let name = self.ctx.interner.intern(name);
+        self.pragma_handlers.insert(name, Box::new(handler));
+        self
+    }
+
+    /// Sets the strategy used to locate included files. The default is a [`DirectoryResolver`]
+    /// searching the [include directories](Self::include_dirs()) followed by the
+    /// [system include directories](Self::system_include_dirs()).
+    pub fn include_resolver(&mut self, resolver: impl IncludeResolver + 'static) -> &mut Self {
+        self.include_resolver = Some(Box::new(resolver));
+        self
+    }
+
+    /// Sets the loader used to read resources embedded with `#embed`. The default is
+    /// [`FsResourceLoader`].
+    pub fn resource_loader(&mut self, loader: impl ResourceLoader + 'static) -> &mut Self {
+        self.resource_loader = Some(Box::new(loader));
+        self
+    }
+
+    /// Registers `callbacks` to be notified of directives processed and macros expanded, replacing
+    /// any callbacks previously registered.
+    pub fn callbacks(&mut self, callbacks: impl PpCallbacks + 'static) -> &mut Self {
+        self.callbacks = Some(Box::new(callbacks));
+        self
+    }
+
     /// Constructs a new preprocessor using the options set on this builder.
     ///
     /// # Panics
     ///
     /// Panics if the provided `main_id` does not point into a file source.
     pub fn build(&mut self) -> Preprocessor {
+        let dir_resolver = DirectoryResolver::new(
+            mem::take(&mut self.include_dirs),
+            mem::take(&mut self.system_include_dirs),
+        );
+        let include_dirs = dir_resolver.search_dirs().to_vec();
+        let include_resolver = self
+            .include_resolver
+            .take()
+            .unwrap_or_else(|| Box::new(dir_resolver));
+
+        let mut macro_state = MacroState::new(
+            self.expansion_tracking,
+            self.trace_expansions,
+            self.record_expansion_sites,
+        );
+        if let Some(file) = self.ctx.smap.get_source(self.main_id).as_file() {
+            macro_state.set_base_file(file.filename.to_string());
+        }
+        if self.processor_options.lang.standard < LangStandard::C99 {
+            macro_state.set_empty_arg_level(self.processor_options.pedantic_level());
+        }
+        if self.processor_options.lang.standard < LangStandard::C23 {
+            macro_state.set_omitted_va_args_level(self.processor_options.pedantic_level());
+        }
+
         Preprocessor {
-            active_files: ActiveFiles::new(&self.ctx.smap, self.main_id, self.parent_dir.take()),
-            include_loader: IncludeLoader::new(mem::take(&mut self.include_dirs)),
-            macro_state: MacroState::new(),
+            active_files: ActiveFiles::new(self.ctx.smap, self.main_id, self.parent_dir.take()),
+            include_loader: IncludeLoader::new(
+                include_dirs,
+                mem::take(&mut self.file_cache),
+                include_resolver,
+                self.resource_loader
+                    .take()
+                    .unwrap_or_else(|| Box::new(FsResourceLoader)),
+            ),
+            embed: None,
+            macro_state,
+            file_boundaries: self.file_boundaries,
+            processor_options: self.processor_options,
+            missing_include_level: self.missing_include_level,
+            stats: StatsRecorder::new(self.collect_stats),
+            cancellation_token: self.cancellation_token.take(),
+            tokens_since_check: 0,
+            predefines: Some(Predefines {
+                time: self.date_time.unwrap_or_else(SystemTime::now),
+                actions: mem::take(&mut self.macro_actions),
+                includes: mem::take(&mut self.forced_includes),
+            }),
+            forced_includes: VecDeque::new(),
+            features: mem::take(&mut self.features),
+            pragma_handlers: mem::take(&mut self.pragma_handlers),
+            callbacks: self
+                .callbacks
+                .take()
+                .unwrap_or_else(|| Box::new(NoCallbacks)),
+            skipped_regions: Vec::new(),
         }
     }
 }
@@ -77,74 +434,569 @@ pub struct Preprocessor {
     active_files: ActiveFiles,
     include_loader: IncludeLoader,
     macro_state: MacroState,
+    file_boundaries: bool,
+    processor_options: ProcessorOptions,
+    missing_include_level: Level,
+    stats: StatsRecorder,
+    cancellation_token: Option<CancellationToken>,
+    tokens_since_check: u32,
+    predefines: Option<Predefines>,
+    /// Files forcibly included with [`PreprocessorBuilder::include_file()`] that have not yet been
+    /// entered.
+    forced_includes: VecDeque<PathBuf>,
+    features: FeatureTable,
+    pragma_handlers: FxHashMap<Symbol, Box<dyn PragmaHandler>>,
+    callbacks: Box<dyn PpCallbacks>,
+    skipped_regions: Vec<SourceRange>,
+    /// The remaining tokens replacing the last `#embed` directive.
+    embed: Option<EmbedTokens>,
+}
+
+/// An item produced by [`Preprocessor::next_pp_event()`].
+#[derive(Debug, Clone)]
+pub enum PpEvent {
+    /// A preprocessed token.
+    Tok(PpToken),
+    /// A pragma directive or `_Pragma` operator for which no handler has been registered.
+    Pragma(Pragma),
+    /// The start of the file with the specified source, which was included by a `#include`
+    /// directive. The location of the directive is recorded in the source map.
+    ///
+    /// This is only reported if enabled with [`PreprocessorBuilder::file_boundaries()`].
+    EnterFile(SourceId),
+    /// The end of the included file with the specified source. Subsequent tokens come from the
+    /// file containing the corresponding `#include` directive.
+    ///
+    /// This is only reported if enabled with [`PreprocessorBuilder::file_boundaries()`].
+    ExitFile(SourceId),
+}
+
+/// Predefined macros that have not yet been processed.
+struct Predefines {
+    time: SystemTime,
+    actions: Vec<MacroAction>,
+    includes: Vec<PathBuf>,
 }
 
 impl Preprocessor {
+    /// Consumes the preprocessor, returning the cache of files it has loaded.
+    pub fn into_file_cache(self) -> FileCache {
+        self.include_loader.into_cache()
+    }
+
+    /// Returns the paths of all files included and resources embedded so far, in the order in which
+    /// they were first loaded. The main file is not listed.
+    ///
+    /// Included files supplied by a custom [`IncludeResolver`] are only listed if they have a
+    /// [path](File::path).
+    pub fn dependencies(&self) -> impl Iterator<Item = &Path> {
+        self.include_loader.dependencies()
+    }
+
+    /// Returns the file system from which the preprocessor reads included files, as configured on its
+    /// [file cache](PreprocessorBuilder::file_cache()).
+    pub fn file_system(&self) -> &dyn FileSystem {
+        self.include_loader.file_system()
+    }
+
+    /// Returns statistics about the files loaded through the preprocessor's file cache, including
+    /// any loads performed before the cache was passed to the preprocessor.
+    pub fn file_cache_stats(&self) -> FileCacheStats {
+        self.include_loader.cache_stats()
+    }
+
+    /// Returns the trace of the macro replacements performed so far, if tracing was enabled with
+    /// [`PreprocessorBuilder::trace_expansions()`].
+    pub fn expansion_trace(&self) -> Option<&ExpansionTrace> {
+        self.macro_state.trace()
+    }
+
+    /// Returns the macro invocations expanded so far, in the order in which their expansion
+    /// started, if recording was enabled with [`PreprocessorBuilder::record_expansion_sites()`].
+    ///
+    /// Invocations produced by other expansions and those in `#if` conditions are included.
+    pub fn expansion_sites(&self) -> Option<&[ExpansionSite]> {
+        self.macro_state.sites()
+    }
+
+    /// Returns the timings and counters recorded so far, if collection was enabled with
+    /// [`PreprocessorBuilder::collect_stats()`].
+    pub fn stats(&self) -> Option<PpStats> {
+        self.stats.stats().map(|stats| PpStats {
+            macros_expanded: self.macro_state.expansion_count(),
+            ..stats
+        })
+    }
+
+    /// Returns the ranges of the lines skipped by conditional compilation so far, in the order in
+    /// which they were skipped.
+    ///
+    /// Each range starts after the directive that caused the skipping and ends before the directive
+    /// that ended it, or at the end of the file. The groups of a single conditional block that are
+    /// skipped in succession are covered by a single range, including the `#elif` and `#else`
+    /// directives between them.
+    pub fn skipped_regions(&self) -> &[SourceRange] {
+        &self.skipped_regions
+    }
+
+    /// Returns the current definition of the macro `name`, if it is defined.
+    pub fn macro_def(&self, name: Symbol) -> Option<&MacroDef> {
+        self.macro_state.lookup(name)
+    }
+
+    /// Checks whether `name` is currently defined as a macro.
+    pub fn is_macro_defined(&self, name: Symbol) -> bool {
+        self.macro_state.is_defined(name)
+    }
+
+    /// Returns all macros defined at this point, in the order in which they were defined.
+    ///
+    /// Predefined macros are defined when the first token is requested, and are included in the
+    /// returned definitions after that.
+    pub fn macro_defs(&self) -> impl Iterator<Item = &MacroDef> {
+        self.macro_state.defs()
+    }
+
     /// Lexes the next preprocessing token from the input, interpreting any preprocessing directives
     /// encountered.
     ///
     /// This method returns tokens with leading whitespace/newline information, which may be
     /// relevant to certain clients. If this auxiliary information is not needed, consider using
     /// [`Self::next()`] instead.
-    pub fn next_pp(&mut self, ctx: &mut LexCtx<'_, '_>) -> DResult<PpToken> {
-        let ppt = loop {
+    ///
+    /// Pragmas without a registered handler and file boundaries are skipped; use
+    /// [`Self::next_pp_event()`] to observe them.
+    ///
+    /// If a cancellation token was provided and has been cancelled, [`PpError::Cancelled`] may be
+    /// returned. The preprocessor is left in a consistent state, and its file cache can still be
+    /// retrieved.
+    pub fn next_pp(&mut self, ctx: &mut LexCtx<'_, '_>) -> PpResult<PpToken> {
+        loop {
+            if let PpEvent::Tok(ppt) = self.next_pp_event(ctx)? {
+                break Ok(ppt);
+            }
+        }
+    }
+
+    /// Lexes the next preprocessing token from the input like [`Self::next_pp()`], but also
+    /// returns pragmas that were not handled by a registered [`PragmaHandler`] and, if enabled,
+    /// the boundaries of included files.
+    ///
+    /// The end-of-file token of an included file is never returned; only that of the main file
+    /// is.
+    pub fn next_pp_event(&mut self, ctx: &mut LexCtx<'_, '_>) -> PpResult<PpEvent> {
+        if let Some(predefines) = self.predefines.take() {
+            let main_id = self.active_files.top().id();
+            self.check_encoding(ctx, main_id)?;
+            self.push_predefines(ctx, predefines)?;
+        }
+
+        if self.tokens_since_check == 0 {
+            self.check_cancelled()?;
+        }
+        self.tokens_since_check = (self.tokens_since_check + 1) % CANCELLATION_CHECK_INTERVAL;
+
+        let event = loop {
+            if let Some(embed) = &mut self.embed {
+                match embed.next() {
+                    Some(ppt) => {
+                        self.stats.count(|stats| stats.tokens += 1);
+                        break PpEvent::Tok(ppt);
+                    }
+                    None => self.embed = None,
+                }
+            }
+
             match self.top_file_event(ctx)? {
                 Event::Tok(ppt) => {
-                    if ppt.data() == TokenKind::Eof && self.active_files.has_includes() {
-                        self.active_files.pop_include();
-                    } else {
-                        break ppt;
+                    if ppt.data() != TokenKind::Eof {
+                        self.stats.count(|stats| stats.tokens += 1);
+                        break PpEvent::Tok(ppt);
+                    }
+                    if !self.active_files.has_includes() {
+                        break PpEvent::Tok(ppt);
+                    }
+
+                    // Forced includes are entered directly from the end of the command line buffer
+                    // rather than being spelled out in it, so that any path can be named.
+                    if self.active_files.top().macro_origin() == MacroOrigin::CommandLine {
+                        if let Some(filename) = self.forced_includes.pop_front() {
+                            let range = ppt.range();
+                            match self.handle_include(
+                                ctx,
+                                filename,
+                                IncludeKind::Quoted,
+                                false,
+                                range,
+                            )? {
+                                Some(id) if self.file_boundaries => break PpEvent::EnterFile(id),
+                                _ => continue,
+                            }
+                        }
+                    }
+
+                    let file = match self.active_files.pop_include() {
+                        Some(file) if file.macro_origin() == MacroOrigin::Source => file,
+                        _ => continue,
+                    };
+                    self.macro_state
+                        .set_include_level(self.active_files.include_depth());
+
+                    if let Some(name) = file.controlling_macro() {
+                        self.include_loader
+                            .set_guard(file.file(), IncludeGuard::Macro(name));
+                    }
+
+                    self.callbacks.file_exited(ctx, file.id())?;
+
+                    if self.file_boundaries {
+                        break PpEvent::ExitFile(file.id());
                     }
                 }
 
                 Event::Include {
                     filename,
                     kind,
+                    next,
                     range,
-                } => self.handle_include(ctx, filename, kind, range)?,
+                } => {
+                    self.check_cancelled()?;
+                    match self.handle_include(ctx, filename, kind, next, range)? {
+                        Some(id) if self.file_boundaries => break PpEvent::EnterFile(id),
+                        _ => {}
+                    }
+                }
+
+                Event::Embed {
+                    filename,
+                    kind,
+                    range,
+                    params,
+                } => {
+                    self.handle_embed(ctx, filename, kind, range, params)?;
+                }
+
+                Event::Pragma(pragma) => {
+                    if let Some(pragma) = self.handle_pragma(ctx, pragma)? {
+                        break PpEvent::Pragma(pragma);
+                    }
+                }
             }
         };
 
-        Ok(ppt)
+        Ok(event)
+    }
+
+    /// Defines the predefined macros, pushing buffers containing their definitions on top of the
+    /// main file.
+    fn push_predefines(&mut self, ctx: &mut LexCtx<'_, '_>, predefines: Predefines) -> DResult<()> {
+        let lang = self.processor_options.lang;
+        self.forced_includes = predefines.includes.into();
+        predef::define_dynamic_builtins(ctx, &mut self.macro_state, lang.gnu_extensions)?;
+
+        // The buffers are processed from the top of the stack down, so push the command line
+        // first to allow it to override builtin definitions.
+        let buffers = [
+            (
+                "command line",
+                predef::command_line_predefines(&predefines.actions),
+                MacroOrigin::CommandLine,
+            ),
+            (
+                "built-in",
+                predef::builtin_predefines(predefines.time, lang.standard),
+                MacroOrigin::Builtin,
+            ),
+        ];
+
+        for (name, contents, origin) in &buffers {
+            if self
+                .active_files
+                .push_predefines(ctx.smap, name, contents, *origin)
+                .is_err()
+            {
+                ctx.diags
+                    .report_anon(Level::Fatal, "translation unit too large")
+                    .emit()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns `PpError::Cancelled` if the cancellation token has been cancelled.
+    fn check_cancelled(&self) -> PpResult<()> {
+        cancel::check_cancelled(self.cancellation_token.as_ref())
     }
 
     /// Returns the next interesting event (either a new token or a new include) from the top of the
     /// active include stack.
-    fn top_file_event(&mut self, ctx: &mut LexCtx<'_, '_>) -> DResult<Event> {
-        self.active_files
-            .top()
-            .next_event(ctx, &mut self.macro_state)
+    fn top_file_event(&mut self, ctx: &mut LexCtx<'_, '_>) -> PpResult<Event> {
+        let next_from = self.include_next_from();
+        let file = self.active_files.top();
+        let event = file.next_event(
+            ctx,
+            &mut self.macro_state,
+            self.processor_options,
+            FileEnv {
+                callbacks: &mut *self.callbacks,
+                include_loader: &mut self.include_loader,
+                next_from,
+                features: &self.features,
+                stats: &self.stats,
+                cancellation_token: self.cancellation_token.as_ref(),
+            },
+        );
+
+        self.skipped_regions.extend(file.take_skipped_regions());
+        event
+    }
+
+    /// Handles `pragma` if it is `#pragma once` or a registered handler exists for it, returning
+    /// the pragma back if it was not handled.
+    fn handle_pragma(
+        &mut self,
+        ctx: &mut LexCtx<'_, '_>,
+        pragma: Pragma,
+    ) -> DResult<Option<Pragma>> {
+        let name = match pragma.name() {
+            Some(name) => name,
+            None => return Ok(Some(pragma)),
+        };
+
+        if &ctx.interner[name] == "once" {
+            self.handle_pragma_once(ctx, &pragma)?;
+            return Ok(None);
+        }
+
+        match self.pragma_handlers.get_mut(&name) {
+            Some(handler) => {
+                handler.handle(ctx, &pragma)?;
+                Ok(None)
+            }
+            None => Ok(Some(pragma)),
+        }
+    }
+
+    /// Marks the current file so that it will not be included again.
+    fn handle_pragma_once(&mut self, ctx: &mut LexCtx<'_, '_>, pragma: &Pragma) -> DResult<()> {
+        if let Some(extra) = pragma.tokens.get(1) {
+            ctx.reporter()
+                .warn(extra.range(), "extra tokens at end of '#pragma once'")
+                .set_id(DiagnosticId::ExtraTokens)
+                .emit()?;
+        }
+
+        if !self.active_files.has_includes() {
+            ctx.reporter()
+                .warn(pragma.range, "'#pragma once' in main file")
+                .set_id(DiagnosticId::OutsideHeader)
+                .emit()?;
+            return Ok(());
+        }
+
+        let file = Rc::clone(self.active_files.top().file());
+        self.include_loader.set_guard(&file, IncludeGuard::Once);
+        Ok(())
+    }
+
+    /// Returns the position in the search order at which `#include_next` directives in the current
+    /// file resume searching, or `None` in the main file.
+    fn include_next_from(&mut self) -> Option<usize> {
+        if !self.active_files.has_includes() {
+            return None;
+        }
+
+        // Resume the search after the directory containing the current file. Files found outside
+        // the search order resume from its beginning.
+        Some(
+            self.active_files
+                .top()
+                .search_pos()
+                .map_or(0, |pos| pos + 1),
+        )
     }
 
     /// Handles the loading and activation of an included file, reporting any errors encountered.
+    ///
+    /// Returns the ID of the source created for the file, if it was actually entered.
     fn handle_include(
+        &mut self,
+        ctx: &mut LexCtx<'_, '_>,
+        filename: PathBuf,
+        kind: IncludeKind,
+        next: bool,
+        range: SourceRange,
+    ) -> DResult<Option<SourceId>> {
+        let next_from = if next && !self.active_files.has_includes() {
+            ctx.reporter()
+                .warn(range, "'#include_next' in main file")
+                .set_id(DiagnosticId::OutsideHeader)
+                .emit()?;
+            None
+        } else if next {
+            self.include_next_from()
+        } else {
+            None
+        };
+
+        let includer = self.active_files.top().file();
+        let include_loader = &mut self.include_loader;
+        let resolved = match self.stats.time(StatsPhase::IncludeIo, || {
+            include_loader.load(&filename, kind, includer, next_from)
+        }) {
+            Ok(resolved) => resolved,
+            Err(IncludeError::NotFound) => {
+                ctx.reporter()
+                    .report(
+                        self.missing_include_level,
+                        range,
+                        Message::new("include '{file}' not found").arg("file", &*filename),
+                    )
+                    .emit()?;
+                return Ok(None);
+            }
+            Err(IncludeError::Io { full_path, error }) => {
+                let msg = Message::new("failed to read '{file}': {error}")
+                    .arg("file", full_path)
+                    .arg("error", error.to_string());
+                return Err(ctx.reporter().fatal(range, msg).emit().unwrap_err());
+            }
+        };
+
+        self.callbacks
+            .include_resolved(ctx, &filename, kind, range, &resolved.file)?;
+
+        // Avoid processing the file again if it is known to have no effect.
+        match self.include_loader.guard(&resolved.file) {
+            Some(IncludeGuard::Once) => return Ok(None),
+            Some(IncludeGuard::Macro(name)) if self.macro_state.is_defined(name) => {
+                return Ok(None)
+            }
+            _ => {}
+        }
+
+        let max_include_depth = ctx.limits.max_include_depth;
+        if self.active_files.include_depth() >= max_include_depth {
+            let msg = Message::new("#include nested too deeply (maximum depth is {max})")
+                .arg("max", max_include_depth);
+            return Err(ctx.reporter().fatal(range, msg).emit().unwrap_err());
+        }
+
+        match self
+            .active_files
+            .push_include(ctx.smap, filename, resolved, range.start())
+        {
+            Ok(id) => {
+                self.stats.count(|stats| stats.files_included += 1);
+                self.macro_state
+                    .set_include_level(self.active_files.include_depth());
+                self.check_encoding(ctx, id)?;
+                self.callbacks.file_entered(ctx, id)?;
+                Ok(Some(id))
+            }
+            Err(_) => {
+                ctx.reporter()
+                    .fatal(range, "translation unit too large")
+                    .emit()?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Warns at the first sequence in the file `id` that could not be decoded, if any.
+    fn check_encoding(&self, ctx: &mut LexCtx<'_, '_>, id: SourceId) -> DResult<()> {
+        let source = ctx.smap.get_source(id);
+        let info = match source.as_file() {
+            Some(file) => file.contents.decode_info(),
+            None => return Ok(()),
+        };
+
+        if let (Some(offset), Some(src_offset)) = (info.invalid_offset, info.invalid_src_offset) {
+            let range = SourceRange::new(source.range.start().offset(src_offset), 0.into());
+            let msg = Message::new(
+                "invalid {encoding} sequence at byte offset {offset}, replaced with U+FFFD",
+            )
+            .arg("encoding", info.encoding.name())
+            .arg("offset", offset);
+
+            ctx.reporter()
+                .warn(range, msg)
+                .set_id(DiagnosticId::InvalidSourceEncoding)
+                .emit()?;
+        }
+
+        Ok(())
+    }
+
+    /// Handles the loading of an embedded resource, queueing the tokens that replace the `#embed`
+    /// directive.
+    fn handle_embed(
         &mut self,
         ctx: &mut LexCtx<'_, '_>,
         filename: PathBuf,
         kind: IncludeKind,
         range: SourceRange,
+        params: EmbedParams,
     ) -> DResult<()> {
-        let file = self
-            .include_loader
-            .load(&filename, kind, self.active_files.top().file())
+        let max_embed_size = ctx.limits.max_embed_size;
+
+        // Read one byte more than the maximum so that oversized resources can be detected.
+        let max_len = params
+            .limit
+            .map_or(usize::MAX, |limit| {
+                usize::try_from(limit).unwrap_or(usize::MAX)
+            })
+            .min(max_embed_size.saturating_add(1));
+
+        let includer = self.active_files.top().file();
+        let include_loader = &mut self.include_loader;
+        let data = self
+            .stats
+            .time(StatsPhase::IncludeIo, || {
+                include_loader.load_resource(&filename, kind, includer, max_len)
+            })
             .map_err(|err| {
                 let msg = match err {
-                    IncludeError::NotFound => format!("include '{}' not found", filename.display()),
+                    IncludeError::NotFound => {
+                        Message::new("embedded resource '{file}' not found").arg("file", &*filename)
+                    }
                     IncludeError::Io { full_path, error } => {
-                        format!("failed to read '{}': {}", full_path.display(), error)
+                        Message::new("failed to read '{file}': {error}")
+                            .arg("file", full_path)
+                            .arg("error", error.to_string())
                     }
                 };
                 ctx.reporter().fatal(range, msg).emit().unwrap_err()
             })?;
 
-        if self
-            .active_files
-            .push_include(&mut ctx.smap, filename, file, range.start())
-            .is_err()
-        {
+        if data.len() > max_embed_size {
             ctx.reporter()
-                .fatal(range, "translation unit too large")
+                .error(
+                    range,
+                    Message::new(
+                        "embedded resource '{file}' is larger than the maximum of {max} bytes",
+                    )
+                    .arg("file", &*filename)
+                    .arg("max", max_embed_size),
+                )
                 .emit()?;
+            return Ok(());
+        }
+
+        match EmbedTokens::new(
+            ctx,
+            &filename.display().to_string(),
+            data,
+            params,
+            range.start(),
+        ) {
+            Ok(tokens) => self.embed = Some(tokens),
+            Err(_) => {
+                ctx.reporter()
+                    .fatal(range, "translation unit too large")
+                    .emit()?;
+            }
         }
 
         Ok(())
@@ -152,7 +1004,10 @@ impl Preprocessor {
 }
 
 impl Lex for Preprocessor {
+    /// Retrieves the next token, reporting cancellation as a fatal error.
     fn next(&mut self, ctx: &mut LexCtx<'_, '_>) -> DResult<Token> {
-        self.next_pp(ctx).map(|ppt| ppt.tok)
+        self.next_pp(ctx)
+            .map(|ppt| ppt.tok)
+            .map_err(|err| err.into_fatal(ctx))
     }
 }
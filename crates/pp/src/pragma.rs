@@ -0,0 +1,38 @@
+//! Support for `#pragma` directives (§6.10.6) and the `_Pragma` operator (§6.10.9).
+
+use lex::{LexCtx, Symbol, TokenKind};
+use source::{DResult, SourceRange};
+
+use crate::PpToken;
+
+/// A `#pragma` directive or `_Pragma` operator encountered during preprocessing.
+#[derive(Debug, Clone)]
+pub struct Pragma {
+    /// The range of the `pragma` keyword in the directive, or of the `_Pragma` operator.
+    pub range: SourceRange,
+    /// The (unexpanded) tokens following the `pragma` keyword, up to the end of the line. For the
+    /// `_Pragma` operator, these are the tokens lexed from its destringized operand.
+    pub tokens: Vec<PpToken>,
+}
+
+impl Pragma {
+    /// Returns the first token of the pragma if it is an identifier, which determines the handler
+    /// responsible for it.
+    pub fn name(&self) -> Option<Symbol> {
+        match self.tokens.first()?.data() {
+            TokenKind::Ident(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+/// A handler for pragmas starting with a specific identifier.
+///
+/// Handlers are registered with
+/// [`PreprocessorBuilder::pragma_handler()`](crate::PreprocessorBuilder::pragma_handler). Pragmas
+/// for which no handler is registered are returned from
+/// [`Preprocessor::next_pp_event()`](crate::Preprocessor::next_pp_event) instead.
+pub trait PragmaHandler {
+    /// Handles `pragma`, whose first token is the name this handler was registered with.
+    fn handle(&mut self, ctx: &mut LexCtx<'_, '_>, pragma: &Pragma) -> DResult<()>;
+}
@@ -0,0 +1,194 @@
+//! Predefined macros (§6.10.8) and macros defined when constructing the preprocessor.
+//!
+//! Most predefined macros are defined by preprocessing a synthesized `<built-in>` buffer before
+//! the main file, and macros requested through the [`PreprocessorBuilder`](crate::PreprocessorBuilder)
+//! are handled similarly with a `<command line>` buffer. `__FILE__` and `__LINE__` (and the GNU
+//! extensions `__COUNTER__`, `__BASE_FILE__` and `__INCLUDE_LEVEL__`) are special, as their
+//! expansions depend on where they are used.
+
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lex::{LexCtx, SyntheticSource, TokenKind};
+use source::DResult;
+
+use crate::expand::{BuiltinMacro, MacroDef, MacroDefKind, MacroOrigin, MacroState};
+use crate::LangStandard;
+
+/// A change to the set of defined macros requested before preprocessing starts.
+#[derive(Debug, Clone)]
+pub enum MacroAction {
+    /// Define a macro, specified as with `-D`: `NAME`, `NAME=VALUE` or `NAME(PARAMS)=VALUE`.
+    Define(String),
+    /// Undefine the named macro.
+    Undef(String),
+}
+
+/// Returns the contents of the `<built-in>` buffer, which defines the standard predefined macros
+/// with constant values.
+///
+/// `__STDC_VERSION__` is defined as appropriate for `standard`, and `__DATE__` and `__TIME__` are
+/// derived from `time`, in UTC.
+pub fn builtin_predefines(time: SystemTime, standard: LangStandard) -> String {
+    let (date, time) = format_date_time(time);
+
+    let mut buf = String::new();
+    writeln!(buf, "#define __STDC__ 1").unwrap();
+    if let Some(version) = standard.stdc_version() {
+        writeln!(buf, "#define __STDC_VERSION__ {}", version).unwrap();
+    }
+    for (name, value) in &[("__DATE__", date.as_str()), ("__TIME__", time.as_str())] {
+        writeln!(buf, "#define {} {}", name, value).unwrap();
+    }
+
+    buf
+}
+
+/// Returns the contents of the `<command line>` buffer, which performs `actions` in order.
+///
+/// As with GCC, each definition is truncated at its first newline, so that it cannot introduce
+/// further directives.
+pub fn command_line_predefines(actions: &[MacroAction]) -> String {
+    let mut buf = String::new();
+
+    for action in actions {
+        match action {
+            MacroAction::Define(def) => match first_line(def).split_once('=') {
+                Some((name, value)) => writeln!(buf, "#define {} {}", name, value),
+                None => writeln!(buf, "#define {} 1", first_line(def)),
+            },
+            MacroAction::Undef(name) => writeln!(buf, "#undef {}", first_line(name)),
+        }
+        .unwrap();
+    }
+
+    buf
+}
+
+/// Returns the portion of `s` preceding its first line break.
+fn first_line(s: &str) -> &str {
+    s.split(['\n', '\r']).next().unwrap_or_default()
+}
+
+/// Defines the builtin macros `__FILE__` and `__LINE__` in `macro_state`, along with
+/// `__COUNTER__`, `__BASE_FILE__` and `__INCLUDE_LEVEL__` if `gnu_extensions` is set.
+pub fn define_dynamic_builtins(
+    ctx: &mut LexCtx<'_, '_>,
+    macro_state: &mut MacroState,
+    gnu_extensions: bool,
+) -> DResult<()> {
+    let mut builtins = vec![
+        ("__FILE__", BuiltinMacro::File),
+        ("__LINE__", BuiltinMacro::Line),
+    ];
+    if gnu_extensions {
+        builtins.extend([
+            ("__COUNTER__", BuiltinMacro::Counter),
+            ("__BASE_FILE__", BuiltinMacro::BaseFile),
+            ("__INCLUDE_LEVEL__", BuiltinMacro::IncludeLevel),
+        ]);
+    }
+
+    let names = SyntheticSource::builder(ctx)
+        .name("built-in")
+        .tokens(builtins.iter().map(|&(name, _)| name))
+        .build()?;
+
+    for (tok, (_, builtin)) in names.into_tokens().into_iter().zip(builtins) {
+        let name_tok = tok
+            .maybe_map(|kind| match kind {
+                TokenKind::Ident(name) => Some(name),
+                _ => None,
+            })
+            .unwrap();
+
+        macro_state.define(
+            ctx,
+            MacroDef {
+                name_tok,
+                kind: MacroDefKind::Builtin(builtin),
+                origin: MacroOrigin::Builtin,
+            },
+        );
+    }
+
+    Ok(())
+}
+
+/// Formats `time` as the string literals expected in `__DATE__` and `__TIME__` (§6.10.8.1).
+fn format_date_time(time: SystemTime) -> (String, String) {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs_of_day = secs % 86400;
+
+    let date = format!("\"{} {:2} {}\"", MONTHS[month as usize - 1], day, year);
+    let time = format!(
+        "\"{:02}:{:02}:{:02}\"",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    );
+
+    (date, time)
+}
+
+/// Converts a number of days since 1970-01-01 to a (year, month, day) triple in the proleptic
+/// Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn date_time() {
+        let check = |secs, date: &str, time: &str| {
+            let (d, t) = format_date_time(UNIX_EPOCH + Duration::from_secs(secs));
+            assert_eq!(d, date);
+            assert_eq!(t, time);
+        };
+
+        check(0, "\"Jan  1 1970\"", "\"00:00:00\"");
+        check(951_782_400, "\"Feb 29 2000\"", "\"00:00:00\"");
+        check(1_791_976_545, "\"Oct 14 2026\"", "\"11:15:45\"");
+    }
+
+    #[test]
+    fn command_line() {
+        let actions = [
+            MacroAction::Define("A".into()),
+            MacroAction::Define("B=2".into()),
+            MacroAction::Define("F(x)=x+1".into()),
+            MacroAction::Undef("A".into()),
+            MacroAction::Define("X=1\n#error injected".into()),
+            MacroAction::Undef("Y\r\n#error injected".into()),
+        ];
+
+        assert_eq!(
+            command_line_predefines(&actions),
+            "#define A 1\n#define B 2\n#define F(x) x+1\n#undef A\n#define X 1\n#undef Y\n"
+        );
+    }
+}
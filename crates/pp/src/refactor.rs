@@ -0,0 +1,524 @@
+//! Source-level refactoring actions on macro definitions.
+//!
+//! The actions in this module do not modify any files themselves; instead, they produce their
+//! changes as [suggestions](RenderedSuggestion), in the same form used by the fix-its attached to
+//! diagnostics. The changes can then be applied with [`apply_edits()`].
+
+use std::borrow::Cow;
+
+use lex::raw::{split_lines, RawLine, RawToken, RawTokenKind};
+use lex::{LexCtx, PunctKind, TokenKind};
+use source::diag::{Level, Message, MessageArg, RawSubDiagnostic, RenderedSuggestion};
+use source::fixit::Fixits;
+use source::smap::{ExpansionKind, FileContents};
+use source::sync::Lrc;
+use source::{DResult, LocalOff, LocalRange, SourceId, SourceMap, SourceRange};
+
+use crate::{MacroOrigin, PpResult, Preprocessor};
+
+/// A macro definition that has been converted into a command-line flag by [`extract_define()`].
+#[derive(Debug, Clone)]
+pub struct ExtractedDefine {
+    /// The command-line argument equivalent to the definition, such as `-DNAME=VALUE`.
+    pub flag: String,
+    /// The edit removing the `#define` directive from the source.
+    pub edit: RenderedSuggestion,
+}
+
+/// Converts the definition of the macro `name` in the file `main_id` into an equivalent `-D`
+/// command-line flag, along with an edit removing the `#define` directive.
+///
+/// The flag is only equivalent if the definition is the one in effect for the rest of the
+/// translation unit, which is checked by running `pp` to the end of its input. `pp` should be
+/// freshly built for `main_id`.
+///
+/// An error is reported and `None` is returned if the file does not contain exactly one definition
+/// of `name`, if `name` appears before the definition or is later undefined, or if the definition
+/// is otherwise not in effect at the end of the translation unit (for example, because it lies in
+/// a skipped conditional block).
+pub fn extract_define(
+    ctx: &mut LexCtx<'_, '_>,
+    pp: &mut Preprocessor,
+    main_id: SourceId,
+    name: &str,
+) -> PpResult<Option<ExtractedDefine>> {
+    let (file_range, contents) = get_file(ctx.smap, main_id);
+    let lines = split_lines(contents.src());
+
+    let def = match find_unique_define(ctx, file_range, &lines, name)? {
+        Some(def) => def,
+        None => return Ok(None),
+    };
+
+    let name_range = file_range.subrange(def.name_range);
+    let mut failed = false;
+
+    // A definition on the command line would also be visible to these occurrences.
+    for line in lines
+        .iter()
+        .take_while(|line| line.full_range.start() < def.line_range.start())
+    {
+        for tok in line.significant() {
+            if tok.kind == RawTokenKind::Ident && tok.content.cleaned_str() == name {
+                ctx.reporter()
+                    .error(
+                        file_range.subrange(raw_token_range(tok)),
+                        Message::new("'{name}' is used before its definition")
+                            .arg("name", MessageArg::symbol(name)),
+                    )
+                    .emit()?;
+                failed = true;
+            }
+        }
+    }
+
+    for line in lines
+        .iter()
+        .skip_while(|line| line.full_range.start() <= def.line_range.start())
+    {
+        if directive_name(line).as_deref() != Some("undef") {
+            continue;
+        }
+
+        if let Some(tok) = line
+            .significant()
+            .nth(2)
+            .filter(|tok| tok.content.cleaned_str() == name)
+        {
+            ctx.reporter()
+                .error(
+                    file_range.subrange(raw_token_range(tok)),
+                    Message::new("macro '{name}' is undefined after its definition")
+                        .arg("name", MessageArg::symbol(name)),
+                )
+                .emit()?;
+            failed = true;
+        }
+    }
+
+    if failed {
+        return Ok(None);
+    }
+
+    while pp.next_pp(ctx)?.data() != TokenKind::Eof {}
+
+    let sym = ctx.interner.intern(name);
+    let in_effect = pp
+        .macro_def(sym)
+        .is_some_and(|def| def.origin == MacroOrigin::Source && def.name_tok.range == name_range);
+
+    if !in_effect {
+        ctx.reporter()
+            .error(
+                name_range,
+                Message::new(
+                    "definition of '{name}' is not in effect at the end of the translation unit",
+                )
+                .arg("name", MessageArg::symbol(name)),
+            )
+            .emit()?;
+        return Ok(None);
+    }
+
+    let mut flag = format!("-D{}", name);
+    if let Some(params) = &def.params {
+        flag.push_str(params);
+    }
+    flag.push('=');
+    flag.push_str(&def.body);
+
+    Ok(Some(ExtractedDefine {
+        flag,
+        edit: RenderedSuggestion::new_deletion(file_range.subrange(def.line_range)),
+    }))
+}
+
+/// Replaces every use of the object-like macro `name` in the file `main_id` with its replacement
+/// list, and removes its definition (along with any `#undef` directives for it).
+///
+/// Uses of the macro are found by running `pp` to the end of its input and tracing the tokens it
+/// produces back to the macro's replacement list through the source map. For the results to be
+/// accurate, `pp` should be freshly built for `main_id` with [`ExpansionTracking::Full`].
+///
+/// Every occurrence of `name` in the file must be such a use: an error is reported for any
+/// occurrence that could not be traced back to an expansion of the macro (for example, operands of
+/// `defined`, stringized arguments and names in skipped conditional blocks), as well as for uses
+/// outside the file. In that case, or if the file does not contain exactly one definition of
+/// `name`, `None` is returned.
+///
+/// [`ExpansionTracking::Full`]: crate::ExpansionTracking::Full
+pub fn inline_macro(
+    ctx: &mut LexCtx<'_, '_>,
+    pp: &mut Preprocessor,
+    main_id: SourceId,
+    name: &str,
+) -> PpResult<Option<Vec<RenderedSuggestion>>> {
+    let (file_range, contents) = get_file(ctx.smap, main_id);
+    let src = contents.src();
+    let lines = split_lines(src);
+
+    let def = match find_unique_define(ctx, file_range, &lines, name)? {
+        Some(def) => def,
+        None => return Ok(None),
+    };
+
+    let name_range = file_range.subrange(def.name_range);
+
+    if def.params.is_some() {
+        ctx.reporter()
+            .error(name_range, "only object-like macros can be inlined")
+            .emit()?;
+        return Ok(None);
+    }
+
+    let body_range = match def.body_range {
+        Some(range) => file_range.subrange(range),
+        None => {
+            ctx.reporter()
+                .error(
+                    name_range,
+                    "macros with empty replacement lists cannot be inlined",
+                )
+                .emit()?;
+            return Ok(None);
+        }
+    };
+
+    let mut uses = Vec::new();
+    loop {
+        let ppt = pp.next_pp(ctx)?;
+        if ppt.data() == TokenKind::Eof {
+            break;
+        }
+
+        if let Some(use_range) = find_use(ctx.smap, ppt.range(), body_range) {
+            uses.push(use_range);
+        }
+    }
+
+    uses.sort_by_key(|range| range.start());
+    uses.dedup();
+
+    let mut edits = vec![RenderedSuggestion::new_deletion(
+        file_range.subrange(def.line_range),
+    )];
+    let mut failed = false;
+
+    for line in &lines {
+        if line.full_range == def.line_range {
+            continue;
+        }
+
+        if directive_name(line).as_deref() == Some("undef") {
+            if line
+                .significant()
+                .nth(2)
+                .map(|tok| tok.content.cleaned_str())
+                == Some(name.into())
+            {
+                edits.push(RenderedSuggestion::new_deletion(
+                    file_range.subrange(line.full_range),
+                ));
+            }
+            continue;
+        }
+
+        for tok in line.significant() {
+            if tok.kind != RawTokenKind::Ident || tok.content.cleaned_str() != name {
+                continue;
+            }
+
+            let range = file_range.subrange(raw_token_range(tok));
+            match uses.iter().position(|&use_range| use_range == range) {
+                Some(idx) => {
+                    uses.remove(idx);
+                    edits.push(RenderedSuggestion::new(
+                        range,
+                        pad_replacement(src, raw_token_range(tok), &def.body),
+                    ));
+                }
+                None => {
+                    ctx.reporter()
+                        .error(
+                            range,
+                            Message::new("cannot inline this use of '{name}'")
+                                .arg("name", MessageArg::symbol(name)),
+                        )
+                        .emit()?;
+                    failed = true;
+                }
+            }
+        }
+    }
+
+    // Anything left over was not spelled in the file itself.
+    for use_range in uses {
+        ctx.reporter()
+            .error(
+                use_range,
+                Message::new("macro '{name}' is used outside of the file")
+                    .arg("name", MessageArg::symbol(name)),
+            )
+            .emit()?;
+        failed = true;
+    }
+
+    Ok(if failed { None } else { Some(edits) })
+}
+
+/// Applies `edits` to the contents of the file `id`, returning the modified contents.
+///
+/// # Panics
+///
+/// Panics if any of the edits do not lie within the file, or if they overlap.
+pub fn apply_edits(smap: &SourceMap, id: SourceId, edits: &[RenderedSuggestion]) -> String {
+    let mut fixits = Fixits::new();
+    for edit in edits {
+        fixits.add(smap, edit);
+    }
+
+    assert!(fixits.files().all(|file| file == id), "edit not in file");
+
+    let fixed = fixits.apply(smap, id);
+    assert!(fixed.conflicts.is_empty(), "overlapping edits");
+    fixed.contents
+}
+
+/// Returns the range and contents of the file `id`.
+fn get_file(smap: &SourceMap, id: SourceId) -> (SourceRange, Lrc<FileContents>) {
+    let source = smap.get_source(id);
+    let file = source
+        .as_file()
+        .expect("refactoring actions require a file");
+    (source.range, Lrc::clone(&file.contents))
+}
+
+/// If the token at `range` was produced by an expansion of the macro whose replacement list is
+/// spelled at `body_range`, returns the range of the name that was replaced, mapped to its spelling.
+fn find_use(smap: &SourceMap, range: SourceRange, body_range: SourceRange) -> Option<SourceRange> {
+    // The expansion may have been performed within another expansion (for example, if the macro
+    // was used in the body of another macro), and other macros in the replacement list may have
+    // been expanded further, so both the replacement and spelling chains need to be searched.
+    smap.get_replacement_chain(range)
+        .flat_map(|(_, range)| smap.get_spelling_chain(range.start()))
+        .find_map(|(id, _)| {
+            let exp = smap.get_source(id).as_expansion()?;
+            if exp.kind != ExpansionKind::Macro
+                || body_range.local_range(exp.spelling_range).is_none()
+            {
+                return None;
+            }
+
+            let name_range = exp.replacement_range;
+            Some(SourceRange::new(
+                smap.get_spelling_pos(name_range.start()),
+                name_range.len(),
+            ))
+        })
+}
+
+/// Returns `text`, surrounded by spaces where necessary to keep it from merging with the tokens
+/// adjacent to `range` in `src`.
+fn pad_replacement<'a>(src: &str, range: LocalRange, text: &'a str) -> Cow<'a, str> {
+    let before = src[..usize::from(range.start())].chars().next_back();
+    let after = src[usize::from(range.end())..].chars().next();
+
+    let pad_before = before.is_some_and(|c| may_merge(c, text.chars().next().unwrap()));
+    let pad_after = after.is_some_and(|c| may_merge(text.chars().next_back().unwrap(), c));
+
+    if !pad_before && !pad_after {
+        return Cow::Borrowed(text);
+    }
+
+    let mut padded = String::new();
+    if pad_before {
+        padded.push(' ');
+    }
+    padded.push_str(text);
+    if pad_after {
+        padded.push(' ');
+    }
+    Cow::Owned(padded)
+}
+
+/// Returns `true` if the characters `a` and `b` could be part of a single token when placed next to
+/// each other.
+///
+/// This errs on the side of caution, treating any two adjacent punctuation characters as mergeable.
+fn may_merge(a: char, b: char) -> bool {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let is_punct = |c: char| {
+        c.is_ascii_punctuation()
+            && !matches!(
+                c,
+                '(' | ')' | '[' | ']' | '{' | '}' | ',' | ';' | '"' | '\''
+            )
+    };
+
+    match (a, b) {
+        // Identifiers and preprocessing numbers.
+        _ if is_word(a) && (is_word(b) || b == '.') => true,
+        ('.', _) if b.is_ascii_digit() => true,
+        // Exponents in preprocessing numbers, such as `1e+5`.
+        ('e' | 'E' | 'p' | 'P', '+' | '-') => true,
+        // Encoding prefixes of string and character literals.
+        _ if is_word(a) && matches!(b, '"' | '\'') => true,
+        _ => is_punct(a) && is_punct(b),
+    }
+}
+
+/// A `#define` directive, found lexically in a file.
+struct DefineLine {
+    /// The range of the directive, including its terminating newline.
+    line_range: LocalRange,
+    /// The range of the macro name.
+    name_range: LocalRange,
+    /// The spelling of the parameter list of a function-like macro.
+    params: Option<String>,
+    /// The range of the replacement list, if it is not empty.
+    body_range: Option<LocalRange>,
+    /// The spelling of the replacement list, with whitespace and comments between tokens
+    /// collapsed into single spaces.
+    body: String,
+}
+
+/// Finds the single `#define` directive for `name` among `lines`, reporting an error if there is
+/// none or more than one.
+fn find_unique_define(
+    ctx: &mut LexCtx<'_, '_>,
+    file_range: SourceRange,
+    lines: &[RawLine<'_>],
+    name: &str,
+) -> DResult<Option<DefineLine>> {
+    let mut defs = lines.iter().filter_map(|line| parse_define(line, name));
+
+    let def = match defs.next() {
+        Some(def) => def,
+        None => {
+            ctx.diags
+                .report_anon(
+                    Level::Error,
+                    Message::new("macro '{name}' is not defined in this file")
+                        .arg("name", MessageArg::symbol(name)),
+                )
+                .emit()?;
+            return Ok(None);
+        }
+    };
+
+    if let Some(redef) = defs.next() {
+        ctx.reporter()
+            .error(
+                file_range.subrange(redef.name_range),
+                Message::new("macro '{name}' is defined more than once")
+                    .arg("name", MessageArg::symbol(name)),
+            )
+            .add_note(RawSubDiagnostic::new(
+                "previous definition is here",
+                file_range.subrange(def.name_range).into(),
+            ))
+            .emit()?;
+        return Ok(None);
+    }
+
+    Ok(Some(def))
+}
+
+/// Parses `line` as a definition of the macro `name`, returning `None` if it is something else.
+fn parse_define(line: &RawLine<'_>, name: &str) -> Option<DefineLine> {
+    if directive_name(line).as_deref() != Some("define") {
+        return None;
+    }
+
+    let name_idx = line
+        .tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, tok)| is_significant(tok))
+        .nth(2)?
+        .0;
+
+    let name_tok = &line.tokens[name_idx];
+    if name_tok.kind != RawTokenKind::Ident || name_tok.content.cleaned_str() != name {
+        return None;
+    }
+
+    let mut rest = &line.tokens[name_idx + 1..];
+
+    // A parenthesis immediately following the name starts a parameter list.
+    let params = match rest.first() {
+        Some(tok) if tok.kind == RawTokenKind::Punct(PunctKind::LParen) => {
+            let end = rest
+                .iter()
+                .position(|tok| tok.kind == RawTokenKind::Punct(PunctKind::RParen))?;
+            let params = join_spellings(&rest[..=end]);
+            rest = &rest[end + 1..];
+            Some(params)
+        }
+        _ => None,
+    };
+
+    let significant: Vec<_> = rest.iter().filter(|tok| is_significant(tok)).collect();
+    let body_range = match (significant.first(), significant.last()) {
+        (Some(first), Some(last)) => Some(LocalRange::new(
+            first.content.off,
+            raw_token_range(last).end(),
+        )),
+        _ => None,
+    };
+
+    Some(DefineLine {
+        line_range: line.full_range,
+        name_range: raw_token_range(name_tok),
+        params,
+        body_range,
+        body: join_spellings(rest),
+    })
+}
+
+/// Returns the (cleaned) name of the directive on `line`, if it is a directive.
+fn directive_name<'a>(line: &RawLine<'a>) -> Option<Cow<'a, str>> {
+    let mut significant = line.significant();
+
+    if significant.next()?.kind != RawTokenKind::Punct(PunctKind::Hash) {
+        return None;
+    }
+
+    let name = significant.next()?;
+    if name.kind != RawTokenKind::Ident {
+        return None;
+    }
+    Some(name.content.cleaned_str())
+}
+
+/// Concatenates the cleaned spellings of the significant tokens in `tokens`, separating tokens
+/// that were separated by whitespace or comments with a single space.
+fn join_spellings(tokens: &[RawToken<'_>]) -> String {
+    let mut text = String::new();
+    let mut pending_space = false;
+
+    for tok in tokens {
+        if is_significant(tok) {
+            if pending_space && !text.is_empty() {
+                text.push(' ');
+            }
+            text.push_str(&tok.content.cleaned_str());
+            pending_space = false;
+        } else {
+            pending_space = true;
+        }
+    }
+
+    text
+}
+
+fn is_significant(tok: &RawToken<'_>) -> bool {
+    !matches!(
+        tok.kind,
+        RawTokenKind::Ws | RawTokenKind::LineComment | RawTokenKind::BlockComment { .. }
+    )
+}
+
+fn raw_token_range(tok: &RawToken<'_>) -> LocalRange {
+    LocalRange::at(tok.content.off, LocalOff::of(tok.content.str))
+}
@@ -0,0 +1,223 @@
+//! Statistics gathered during preprocessing, used to guide performance work.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use lex::LexCtx;
+
+use crate::Preprocessor;
+
+/// Approximate peak heap memory usage of the different subsystems involved in preprocessing, in
+/// bytes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemoryStats {
+    /// Memory used by the source map, including the contents of every loaded file.
+    pub source_map: usize,
+    /// Memory used by the interner.
+    pub interner: usize,
+    /// Memory used by the macro definition table.
+    pub macro_table: usize,
+    /// Memory used by the token buffers of in-flight macro replacements.
+    pub replacements: usize,
+}
+
+impl MemoryStats {
+    /// Collects memory statistics for `pp`, which is using the structures in `ctx`.
+    ///
+    /// The source map and interner only grow, so their current sizes are reported as their peaks.
+    pub fn collect(ctx: &LexCtx<'_, '_>, pp: &Preprocessor) -> Self {
+        Self {
+            source_map: ctx.smap.heap_size(),
+            interner: ctx.interner.heap_size(),
+            macro_table: pp.macro_state.peak_macro_table_size(),
+            replacements: pp.macro_state.peak_replacements_size(),
+        }
+    }
+
+    /// Returns the sum of the individual subsystems' usage.
+    pub fn total(&self) -> usize {
+        self.source_map + self.interner + self.macro_table + self.replacements
+    }
+}
+
+impl fmt::Display for MemoryStats {
+    /// Formats a human-readable report of the statistics, one subsystem per line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "peak memory usage (approximate):")?;
+
+        for (name, bytes) in &[
+            ("source map", self.source_map),
+            ("interner", self.interner),
+            ("macro table", self.macro_table),
+            ("replacement buffers", self.replacements),
+            ("total", self.total()),
+        ] {
+            writeln!(f, "  {:<20} {:>12} bytes", name, bytes)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A part of preprocessing whose running time is measured in [`PpStats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsPhase {
+    /// Tokenizing source files, including lines skipped by conditional compilation.
+    Lexing,
+    /// Handling preprocessing directives, excluding the lexing and macro expansion they perform.
+    Directives,
+    /// Expanding macros, including the collection of their arguments.
+    MacroExpansion,
+    /// Locating and reading included files and embedded resources.
+    IncludeIo,
+}
+
+/// Timings and counters gathered while preprocessing, if enabled with
+/// [`PreprocessorBuilder::collect_stats()`](crate::PreprocessorBuilder::collect_stats()).
+///
+/// Time is attributed to the innermost phase running: for example, tokens lexed while collecting
+/// macro arguments count towards [`lexing`](Self::lexing), not
+/// [`macro_expansion`](Self::macro_expansion).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PpStats {
+    /// Time spent in [`StatsPhase::Lexing`].
+    pub lexing: Duration,
+    /// Time spent in [`StatsPhase::Directives`].
+    pub directives: Duration,
+    /// Time spent in [`StatsPhase::MacroExpansion`].
+    pub macro_expansion: Duration,
+    /// Time spent in [`StatsPhase::IncludeIo`].
+    pub include_io: Duration,
+    /// The number of tokens produced by the preprocessor, excluding the final end-of-file token.
+    pub tokens: u64,
+    /// The number of directives processed, excluding those in skipped groups.
+    pub directives_processed: u64,
+    /// The number of macro invocations expanded, including nested ones.
+    pub macros_expanded: u64,
+    /// The number of files entered by `#include` directives.
+    pub files_included: u64,
+}
+
+impl PpStats {
+    /// Returns the total time spent in all phases.
+    pub fn total_time(&self) -> Duration {
+        self.lexing + self.directives + self.macro_expansion + self.include_io
+    }
+
+    fn phase_time_mut(&mut self, phase: StatsPhase) -> &mut Duration {
+        match phase {
+            StatsPhase::Lexing => &mut self.lexing,
+            StatsPhase::Directives => &mut self.directives,
+            StatsPhase::MacroExpansion => &mut self.macro_expansion,
+            StatsPhase::IncludeIo => &mut self.include_io,
+        }
+    }
+}
+
+impl fmt::Display for PpStats {
+    /// Formats a human-readable report of the statistics, one phase or counter per line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "preprocessing time:")?;
+
+        for (name, time) in &[
+            ("lexing", self.lexing),
+            ("directives", self.directives),
+            ("macro expansion", self.macro_expansion),
+            ("include I/O", self.include_io),
+            ("total", self.total_time()),
+        ] {
+            writeln!(f, "  {:<20} {:>12.3} ms", name, time.as_secs_f64() * 1000.0)?;
+        }
+
+        writeln!(f, "preprocessing statistics:")?;
+
+        for (name, count) in &[
+            ("tokens produced", self.tokens),
+            ("directives", self.directives_processed),
+            ("macros expanded", self.macros_expanded),
+            ("files included", self.files_included),
+        ] {
+            writeln!(f, "  {:<20} {:>12}", name, count)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Accumulates [`PpStats`] while preprocessing, doing nothing if statistics are disabled.
+///
+/// Recording only requires a shared reference, so that the recorder can be reached from the
+/// different layers of the preprocessor at once.
+#[derive(Debug)]
+pub(crate) struct StatsRecorder {
+    recording: Option<RefCell<Recording>>,
+}
+
+#[derive(Debug)]
+struct Recording {
+    stats: PpStats,
+    /// The phases currently running, innermost last.
+    phases: Vec<StatsPhase>,
+    /// The time at which the innermost phase was entered or last resumed.
+    resumed: Instant,
+}
+
+impl StatsRecorder {
+    /// Creates a new recorder, which records statistics only if `enabled` is set.
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            recording: enabled.then(|| {
+                RefCell::new(Recording {
+                    stats: PpStats::default(),
+                    phases: Vec::new(),
+                    resumed: Instant::now(),
+                })
+            }),
+        }
+    }
+
+    /// Runs `f`, attributing the time it takes (minus that of any nested phases) to `phase`.
+    pub fn time<T>(&self, phase: StatsPhase, f: impl FnOnce() -> T) -> T {
+        let recording = match &self.recording {
+            Some(recording) => recording,
+            None => return f(),
+        };
+
+        recording.borrow_mut().switch(|phases| phases.push(phase));
+        let ret = f();
+        recording.borrow_mut().switch(|phases| {
+            phases.pop();
+        });
+
+        ret
+    }
+
+    /// Updates the counters in the recorded statistics with `f`.
+    pub fn count(&self, f: impl FnOnce(&mut PpStats)) {
+        if let Some(recording) = &self.recording {
+            f(&mut recording.borrow_mut().stats);
+        }
+    }
+
+    /// Returns the statistics recorded so far, if enabled.
+    pub fn stats(&self) -> Option<PpStats> {
+        self.recording
+            .as_ref()
+            .map(|recording| recording.borrow().stats)
+    }
+}
+
+impl Recording {
+    /// Charges the time elapsed since the last switch to the innermost running phase, then
+    /// updates the running phases with `f`.
+    fn switch(&mut self, f: impl FnOnce(&mut Vec<StatsPhase>)) {
+        let now = Instant::now();
+        if let Some(&phase) = self.phases.last() {
+            *self.stats.phase_time_mut(phase) += now - self.resumed;
+        }
+
+        f(&mut self.phases);
+        self.resumed = now;
+    }
+}
@@ -0,0 +1,154 @@
+//! Buffering of preprocessed tokens, for consumers that need to look ahead, backtrack or make
+//! several passes over the output of the preprocessor.
+
+use std::collections::VecDeque;
+
+use lex::{Lex, LexCtx, Token, TokenKind};
+use source::DResult;
+
+use crate::{PpResult, PpToken, Preprocessor};
+
+/// A position in a [`TokenStream`], which can later be returned to with [`TokenStream::rewind()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Checkpoint(usize);
+
+impl Checkpoint {
+    /// The position of the first token in the stream.
+    pub const START: Checkpoint = Checkpoint(0);
+}
+
+/// A buffering layer on top of a [`Preprocessor`], recording the tokens it produces so that they
+/// can be looked ahead at and replayed without preprocessing them again.
+///
+/// A stream created with [`Self::new()`] records every token, allowing it to be rewound to any
+/// earlier position (including the start, for multi-pass consumers). When only limited
+/// backtracking is needed, [`Self::with_window()`] bounds memory usage by discarding consumed
+/// tokens once they fall too far behind the current position.
+///
+/// Once the end-of-file token is reached, it is returned indefinitely and the stream does not
+/// advance past it.
+pub struct TokenStream<'p> {
+    pp: &'p mut Preprocessor,
+    /// The tokens retrieved from the preprocessor that are still buffered.
+    buf: VecDeque<PpToken>,
+    /// The index (counted from the start of the stream) of the first token in `buf`.
+    buf_start: usize,
+    /// The index of the next token to be returned.
+    pos: usize,
+    /// The number of consumed tokens to retain, if limited.
+    window: Option<usize>,
+}
+
+impl<'p> TokenStream<'p> {
+    /// Creates a stream reading tokens from `pp` and retaining all of them.
+    pub fn new(pp: &'p mut Preprocessor) -> Self {
+        Self {
+            pp,
+            buf: VecDeque::new(),
+            buf_start: 0,
+            pos: 0,
+            window: None,
+        }
+    }
+
+    /// Creates a stream reading tokens from `pp` that retains only the last `window` tokens
+    /// consumed, in addition to any tokens that have been looked ahead at.
+    ///
+    /// Only checkpoints at most `window` tokens behind the current position can be rewound to.
+    pub fn with_window(pp: &'p mut Preprocessor, window: usize) -> Self {
+        Self {
+            window: Some(window),
+            ..Self::new(pp)
+        }
+    }
+
+    /// Returns the next token and advances past it, retrieving it from the preprocessor if it
+    /// has not been buffered yet.
+    pub fn next_pp(&mut self, ctx: &mut LexCtx<'_, '_>) -> PpResult<PpToken> {
+        let ppt = self.peek_pp(ctx, 0)?;
+
+        if ppt.data() != TokenKind::Eof {
+            self.pos += 1;
+            self.trim();
+        }
+
+        Ok(ppt)
+    }
+
+    /// Returns the token `n` tokens ahead of the current position (with `0` referring to the next
+    /// token) without consuming anything.
+    ///
+    /// If the end of the input lies fewer than `n` tokens ahead, the end-of-file token is returned.
+    pub fn peek_pp(&mut self, ctx: &mut LexCtx<'_, '_>, n: usize) -> PpResult<PpToken> {
+        let idx = self.pos + n - self.buf_start;
+
+        while self.buf.len() <= idx {
+            match self.buf.back() {
+                Some(last) if last.data() == TokenKind::Eof => return Ok(*last),
+                _ => {
+                    let ppt = self.pp.next_pp(ctx)?;
+                    self.buf.push_back(ppt);
+                }
+            }
+        }
+
+        Ok(self.buf[idx])
+    }
+
+    /// Returns a checkpoint referring to the current position.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint(self.pos)
+    }
+
+    /// Checks whether the tokens following `checkpoint` are still buffered, meaning that the
+    /// stream can be rewound to it.
+    pub fn can_rewind(&self, checkpoint: Checkpoint) -> bool {
+        checkpoint.0 >= self.buf_start && checkpoint.0 <= self.buf_start + self.buf.len()
+    }
+
+    /// Moves the stream back to `checkpoint`, so that the tokens following it are returned again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the tokens following `checkpoint` are no longer buffered (see
+    /// [`Self::can_rewind()`]).
+    pub fn rewind(&mut self, checkpoint: Checkpoint) {
+        assert!(
+            self.can_rewind(checkpoint),
+            "tokens at checkpoint no longer buffered"
+        );
+        self.pos = checkpoint.0;
+    }
+
+    /// Moves the stream back to its first token, to start another pass over the input.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the stream was created with a window and has discarded its first token.
+    pub fn restart(&mut self) {
+        self.rewind(Checkpoint::START);
+    }
+
+    /// Returns the number of tokens currently buffered.
+    pub fn buffered_len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Discards consumed tokens that have fallen out of the window, if any.
+    fn trim(&mut self) {
+        if let Some(window) = self.window {
+            let discard = (self.pos - self.buf_start).saturating_sub(window);
+            self.buf.drain(..discard);
+            self.buf_start += discard;
+        }
+    }
+}
+
+impl Lex for TokenStream<'_> {
+    /// Retrieves the next token, reporting cancellation as a fatal error.
+    fn next(&mut self, ctx: &mut LexCtx<'_, '_>) -> DResult<Token> {
+        self.next_pp(ctx)
+            .map(|ppt| ppt.tok)
+            .map_err(|err| err.into_fatal(ctx))
+    }
+}
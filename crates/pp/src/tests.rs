@@ -0,0 +1,2794 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use itertools::Itertools;
+
+use lex::raw::LexerConfig;
+use lex::{Interner, LexCtx, SourceSpelling, Symbol, Token, TokenKind};
+use source::diag::{Level, RecordingSink, RenderedDiagnostic, RenderedSink, RenderedSuggestion};
+use source::limits::DEFAULT_MAX_BRACKET_DEPTH;
+use source::smap::{FileContents, FileName};
+use source::sync::Lrc;
+use source::DResult;
+use source::{DiagManager, FrontendLimits, SourceId, SourceMap, SourceRange};
+
+use crate::{
+    apply_edits, extract_define, inline_macro, CancellationToken, Checkpoint, ExpansionTracking,
+    FeatureTable, File, FileCache, FileCacheStats, InMemoryFileSystem, IncludeError, IncludeKind,
+    IncludeResolver, LangOptions, LangStandard, MacroDef, MacroOrigin, MemoryStats, PpCallbacks,
+    PpError, PpEvent, PpStats, PpToken, Pragma, PragmaHandler, Preprocessor, PreprocessorBuilder,
+    ResolvedInclude, ResourceLoader, TokenStream, DEFAULT_MAX_EMBED_SIZE,
+    DEFAULT_MAX_EXPANSION_DEPTH, DEFAULT_MAX_INCLUDE_DEPTH,
+};
+
+/// Preprocesses `src`, returning the spellings of the output tokens (separated by spaces, with
+/// line breaks preserved) and the diagnostics reported.
+fn preprocess(src: &str) -> (String, Vec<String>) {
+    preprocess_with(src, |_| {})
+}
+
+/// Like [`preprocess()`], but allows `configure` to set additional options on the preprocessor.
+fn preprocess_with(
+    src: &str,
+    configure: impl FnOnce(&mut PreprocessorBuilder<'_, '_, '_>),
+) -> (String, Vec<String>) {
+    preprocess_with_limits(src, FrontendLimits::default(), configure)
+}
+
+fn preprocess_with_limits(
+    src: &str,
+    limits: FrontendLimits,
+    configure: impl FnOnce(&mut PreprocessorBuilder<'_, '_, '_>),
+) -> (String, Vec<String>) {
+    let diags = RefCell::new(Vec::new());
+    let mut manager = DiagManager::new(RecordingSink(&diags), None);
+    let mut interner = Interner::new();
+    let mut smap = SourceMap::new();
+
+    let main_id = smap
+        .create_file(FileName::synth("test.c"), FileContents::new(src), None)
+        .unwrap();
+
+    let mut ctx = LexCtx::new(&mut interner, &mut manager, &mut smap);
+    ctx.limits = limits;
+
+    let mut builder = PreprocessorBuilder::new(&mut ctx, main_id);
+    configure(&mut builder);
+    let mut pp = builder.build();
+
+    let mut output = String::new();
+    while let Ok(ppt) = pp.next_pp(&mut ctx) {
+        if ppt.data() == TokenKind::Eof {
+            break;
+        }
+
+        if !output.is_empty() {
+            output.push(if ppt.line_start { '\n' } else { ' ' });
+        }
+        output.push_str(&ppt.tok.display(&ctx).to_string());
+    }
+
+    drop(manager);
+    (output, diags.into_inner())
+}
+
+fn check(src: &str, expected: &str) {
+    let (output, diags) = preprocess(src);
+    assert_eq!(output, expected);
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+}
+
+fn check_diags(src: &str, expected: &str, expected_diags: &[&str]) {
+    let (output, diags) = preprocess(src);
+    assert_eq!(output, expected);
+    assert_eq!(diags, expected_diags);
+}
+
+#[test]
+fn empty_define() {
+    check("#define A\nA x\n", "x");
+}
+
+#[test]
+fn empty_input() {
+    check("", "");
+    check("\n\n", "");
+    check("#\n#", "");
+    check("#define A\nA", "");
+    check("#define F()\nF()", "");
+}
+
+#[test]
+fn empty_macro_args() {
+    check("#define F(x) [x]\nF() F( ) F(F())", "[ ] [ ] [ [ ] ]");
+    check(
+        "#define G(x, y) [x|y]\nG(,) G(1,) G(,2)",
+        "[ | ] [ 1 | ] [ | 2 ]",
+    );
+    check("#define E\n#define F(x) [x]\nF(E)", "[ ]");
+    check(
+        "#define E\n#define F(x, y) [x y]\nF(E, E) F(E,) F(, E)",
+        "[ ] [ ] [ ]",
+    );
+    check("#define S(x) #x\nS() S( ) S(/* c */)", "\"\" \"\" \"\"");
+    check(
+        "#define V(x, ...) [x|__VA_ARGS__]\nV(,) V(1,) V(,,) V()",
+        "[ | ] [ 1 | ] [ | , ] [ | ]",
+    );
+    check(
+        "#define F(x, y) x y\n#define G(x, y) F(x, y)\nG(,) G(F(,), F(,)) G(1,) G(, 2)",
+        "1 2",
+    );
+    check_diags(
+        "#define F(x) x\n#define Z()\nF(,) Z(,)",
+        "",
+        &[
+            "error: too many arguments provided to macro invocation",
+            "error: too many arguments provided to macro invocation",
+        ],
+    );
+
+    let (output, diags) = preprocess_with("#define G(x, y) [x y]\nG(,) G(1,)", |builder| {
+        builder.lang_options(LangOptions {
+            standard: LangStandard::C89,
+            pedantic: true,
+            ..LangOptions::default()
+        });
+    });
+    assert_eq!(output, "[ ] [ 1 ]");
+    assert_eq!(
+        diags,
+        ["warning: empty macro arguments are a C99 feature"; 3]
+    );
+}
+
+#[test]
+fn omitted_va_args() {
+    let src = "#define G(a, ...) a\nG(1) G(1,) G()";
+    check(src, "1 1");
+
+    let pedantic = |standard| {
+        preprocess_with(src, |builder| {
+            builder.lang_options(LangOptions {
+                standard,
+                pedantic: true,
+                ..LangOptions::default()
+            });
+        })
+    };
+    assert_eq!(
+        pedantic(LangStandard::C11),
+        (
+            "1 1".to_owned(),
+            vec!["warning: omitting the variable macro arguments is a C23 feature".to_owned(); 2]
+        )
+    );
+    assert_eq!(pedantic(LangStandard::C23), ("1 1".to_owned(), vec![]));
+
+    let (_, diags) = pedantic(LangStandard::C89);
+    assert_eq!(
+        diags,
+        [
+            "warning: variadic macros are a C99 feature",
+            "warning: omitting the variable macro arguments is a C23 feature",
+            "warning: empty macro arguments are a C99 feature",
+            "warning: empty macro arguments are a C99 feature",
+            "warning: omitting the variable macro arguments is a C23 feature",
+        ]
+    );
+}
+
+#[test]
+fn placemarker_whitespace() {
+    let check_spelled = |src, expected| {
+        assert_eq!(preprocess_spelled(src, SourceSpelling::Cleaned), expected);
+    };
+
+    check_spelled("#define F(x) a x+\nF()", "\na +");
+    check_spelled("#define F(x) x;\na F() b", "\na ; b");
+    check_spelled("#define F(x, y) [x y]\nF(,) F(1,) F(,2)", "\n[ ] [1 ] [ 2]");
+    check_spelled("#define F(x, y) [x  y]\nF(,)", "\n[ ]");
+    check_spelled("#define S(x, y) x #y\nS(,)", "\n\"\"");
+    check_spelled("#define V(...) [__VA_OPT__(x)]\nV() V(1)", "\n[] [x]");
+    check_spelled(
+        "#define V(a, ...) a __VA_OPT__(, __VA_ARGS__)\nf(V(1)) f(V(1, 2))",
+        "\nf(1) f(1 , 2)",
+    );
+}
+
+#[test]
+fn self_reference_in_args() {
+    check("#define A F((A)\n#define F(x) x\nA)", "( A )");
+    check("#define A F(A\n#define F(x) [x]\nA)", "[ A ]");
+    check(
+        "#define f(x) g(x)\n#define g(x) f(x)\nf(f(f(1))) g(f(g(2)))",
+        "f ( f ( f ( 1 ) ) ) g ( f ( g ( 2 ) ) )",
+    );
+    check(
+        "#define f(x) x f\n#define g f(g)\ng(1)(2)",
+        "g f ( 1 ) ( 2 )",
+    );
+}
+
+#[test]
+fn expansion_depth_limit() {
+    // Nested invocations in macro arguments are expanded iteratively, so even the deepest ones should
+    // not need much native stack.
+    std::thread::Builder::new()
+        .stack_size(256 * 1024)
+        .spawn(check_expansion_depth_limit)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+fn check_expansion_depth_limit() {
+    let nested = |depth| {
+        format!(
+            "#define F(x) x\n{}0{}",
+            "F(".repeat(depth),
+            ")".repeat(depth)
+        )
+    };
+    let chain = |len| {
+        let defs: String = (0..len)
+            .map(|i| format!("#define A{} A{}\n", i, i + 1))
+            .collect();
+        format!("{}A0", defs)
+    };
+
+    check(&nested(DEFAULT_MAX_EXPANSION_DEPTH / 2), "0");
+    check(
+        &chain(DEFAULT_MAX_EXPANSION_DEPTH - 1),
+        &format!("A{}", DEFAULT_MAX_EXPANSION_DEPTH - 1),
+    );
+
+    for src in [
+        nested(DEFAULT_MAX_EXPANSION_DEPTH + 1),
+        chain(DEFAULT_MAX_EXPANSION_DEPTH + 1),
+    ] {
+        let (output, diags) = preprocess(&src);
+        assert_eq!(output, "");
+        assert_eq!(
+            diags,
+            [format!(
+                "fatal: macro expansion nested too deeply (maximum depth is {})",
+                DEFAULT_MAX_EXPANSION_DEPTH
+            )]
+        );
+    }
+
+    let with_depth = |src: &str, max_expansion_depth| {
+        let limits = FrontendLimits {
+            max_expansion_depth,
+            ..FrontendLimits::default()
+        };
+        preprocess_with_limits(src, limits, |_| {})
+    };
+
+    let (output, diags) = with_depth(&chain(1000), 1000);
+    assert_eq!(output, "A1000");
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+
+    let (output, diags) = with_depth(&nested(2000), 2000);
+    assert_eq!(output, "0");
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+
+    let limited = |src: &str| with_depth(src, 4);
+    assert_eq!(limited(&nested(4)), ("0".to_owned(), vec![]));
+
+    let (output, diags) = limited(&nested(5));
+    assert_eq!(output, "");
+    assert_eq!(
+        diags,
+        ["fatal: macro expansion nested too deeply (maximum depth is 4)"]
+    );
+}
+
+#[test]
+fn ifdef() {
+    check(
+        "#define A\n#ifdef A\na\n#else\nb\n#endif\n#ifndef A\nc\n#else\nd\n#endif\n",
+        "a\nd",
+    );
+}
+
+#[test]
+fn if_elif_else() {
+    check(
+        "#if 0\na\n#elif 0\nb\n#elif 0x10\nc\n#elif 1\nd\n#else\ne\n#endif\n",
+        "c",
+    );
+    check("#if 0\na\n#elif 0\nb\n#else\nc\n#endif\n", "c");
+}
+
+#[test]
+fn nested() {
+    check(
+        "#if 1\na\n#  if 0\nb\n#  else\nc\n#  endif\n#else\n#  if 1\nd\n#  endif\n#endif\n",
+        "a\nc",
+    );
+}
+
+#[test]
+fn skipped_groups_not_lexed() {
+    check("#if 0\nit's \"unterminated\n#bogus\n#endif\nx\n", "x");
+}
+
+#[test]
+fn skipped_elif_not_evaluated() {
+    check("#if 1\na\n#elif garbage(\nb\n#endif\n", "a");
+}
+
+#[test]
+fn mismatched() {
+    check_diags(
+        "#else\n#endif\n#elif 1\n",
+        "",
+        &[
+            "error: '#else' without '#if'",
+            "error: '#endif' without '#if'",
+            "error: '#elif' without '#if'",
+        ],
+    );
+    check_diags(
+        "#if 0\n#else\na\n#else\nb\n#endif\n",
+        "a",
+        &["error: '#else' after '#else'"],
+    );
+    check_diags(
+        "#if 1\na\n#else\nb\n#else\nc\n#endif\n",
+        "a",
+        &["error: '#else' after '#else'"],
+    );
+    check_diags(
+        "#if 0\n#else\na\n#elif 1\nb\n#endif\n",
+        "a",
+        &["error: '#elif' after '#else'"],
+    );
+}
+
+#[test]
+fn diagnostic_directives() {
+    check_diags(
+        "#error  bad   thing /* here */\n#warning it's \\\n  odd\n#error\nx\n",
+        "x",
+        &[
+            "error: bad   thing /* here */",
+            "warning: it's   odd",
+            "error: ",
+        ],
+    );
+
+    let (output, diags) = preprocess_with("#error stop\nx\n", |builder| {
+        builder.error_directive_level(Level::Fatal);
+    });
+    assert_eq!(output, "");
+    assert_eq!(diags, ["fatal: stop"]);
+}
+
+#[test]
+fn unterminated() {
+    check_diags(
+        "#if 1\n#ifdef A\n",
+        "",
+        &[
+            "error: unterminated conditional directive",
+            "error: unterminated conditional directive",
+        ],
+    );
+}
+
+/// Checks that the `#if` condition `cond` evaluates to `expected` without any diagnostics.
+fn check_cond(cond: &str, expected: bool) {
+    let src = format!("#if {}\ntrue\n#else\nfalse\n#endif\n", cond);
+    check(&src, if expected { "true" } else { "false" });
+}
+
+#[test]
+fn arithmetic() {
+    check_cond("1 + 2 * 3 == 7", true);
+    check_cond("(1 + 2) * 3 == 9", true);
+    check_cond("10 / 3 == 3 && 10 % 3 == 1", true);
+    check_cond("-7 / 2 == -3 && -7 % 2 == -1", true);
+    check_cond("~0 == -1 && !0 == 1 && +-1 == -1", true);
+    check_cond("(6 & 3) == 2 && (6 | 3) == 7 && (6 ^ 3) == 5", true);
+    check_cond("1 << 4 == 16 && -16 >> 2 == -4", true);
+    check_cond("1 - 1", false);
+}
+
+#[test]
+fn comparisons() {
+    check_cond("1 < 2 && 2 <= 2 && 3 > 2 && 3 >= 3 && 1 != 2", true);
+    check_cond("2 < 1 || 3 <= 2", false);
+}
+
+#[test]
+fn unsigned_conversions() {
+    check_cond("-1 < 0", true);
+    check_cond("-1 < 0u", false);
+    check_cond("-1 == 18446744073709551615u", true);
+    check_cond("0xffffffffffffffff > 0", true);
+    check_cond("(0 ? 1u : -1) > 0", true);
+}
+
+#[test]
+fn ternary() {
+    check_cond("1 ? 2 : 0", true);
+    check_cond("0 ? 2 : 0", false);
+    check_cond("0 ? 1 : 0 ? 1 : 2 == 2", true);
+}
+
+#[test]
+fn defined() {
+    check(
+        "#define A\n#if defined A && defined(A) && !defined B\na\n#endif\n",
+        "a",
+    );
+    check(
+        "#define A B\n#if defined(A)\na\n#endif\n#if defined A && !defined(B)\nb\n#endif\n",
+        "a\nb",
+    );
+}
+
+#[test]
+fn defined_in_expansion() {
+    check(
+        "#define A\n#define IS_A defined(A)\n#if IS_A\na\n#endif\n",
+        "a",
+    );
+}
+
+#[test]
+fn identifiers_are_zero() {
+    check_cond("UNDEFINED", false);
+    check_cond("UNDEFINED == 0", true);
+    check("#define ONE 1\n#if ONE + ONE == 2\na\n#endif\n", "a");
+}
+
+#[test]
+fn char_literals() {
+    check_cond("'a' == 97", true);
+    check_cond(
+        "'\\n' == 10 && '\\0' == 0 && '\\x41' == 'A' && '\\101' == 'A'",
+        true,
+    );
+    check_cond("'\\xff' < 0", true);
+    check_cond("L'\\xff' == 255", true);
+    check_cond("L'é' == 0xe9 && U'€' == 0x20ac", true);
+    check_diags(
+        "#if 'é' == 0xc3a9\na\n#endif\n",
+        "a",
+        &["warning: multi-character character constant"],
+    );
+}
+
+#[test]
+fn int_literal_suffixes() {
+    check_cond(
+        "10u == 10 && 10UL == 10 && 10llu == 10 && 0x10L == 16 && 010 == 8",
+        true,
+    );
+    check_diags(
+        "#if 10q\n#endif\n",
+        "",
+        &["error: invalid integer constant '10q'"],
+    );
+    check_diags(
+        "#if 1.5\n#endif\n",
+        "",
+        &["error: floating constant in preprocessor expression"],
+    );
+}
+
+#[test]
+fn division_by_zero() {
+    check_diags(
+        "#if 1 / 0\na\n#else\nb\n#endif\n",
+        "b",
+        &["error: division by zero in preprocessor expression"],
+    );
+    check_diags(
+        "#if 1 % (2 - 2)\n#endif\n",
+        "",
+        &["error: division by zero in preprocessor expression"],
+    );
+}
+
+#[test]
+fn short_circuit() {
+    check_cond("0 && 1 / 0", false);
+    check_cond("1 || 1 / 0", true);
+    check_cond("1 ? 1 : 1 / 0", true);
+    check_cond("0 ? 1 / 0 : 0", false);
+    check_cond("0 && (0x7fffffffffffffff + 1)", false);
+}
+
+#[test]
+fn overflow() {
+    check_diags(
+        "#if 0x7fffffffffffffff + 1 < 0\na\n#endif\n",
+        "a",
+        &["warning: integer overflow in preprocessor expression"],
+    );
+    check_cond("0xffffffffffffffffu + 1 == 0", true);
+    check_diags(
+        "#if 18446744073709551615 == -1\na\n#endif\n",
+        "a",
+        &["warning: integer constant is so large that it is unsigned"],
+    );
+}
+
+#[test]
+fn invalid_exprs() {
+    check_diags(
+        "#if\n#endif\n",
+        "",
+        &["error: expected value in expression"],
+    );
+    check_diags(
+        "#if 1 +\n#endif\n",
+        "",
+        &["error: expected value in expression"],
+    );
+    check_diags("#if (1\n#endif\n", "", &["error: expected a ')'"]);
+    check_diags(
+        "#if 1 2\n#endif\n",
+        "",
+        &["error: token is not a valid binary operator in a preprocessor subexpression"],
+    );
+    check_diags(
+        "#if \"str\"\n#endif\n",
+        "",
+        &["error: invalid token at start of a preprocessor expression"],
+    );
+    check_diags("#if defined(A\n#endif\n", "", &["error: expected a ')'"]);
+    check_diags(
+        "#if defined 1\n#endif\n",
+        "",
+        &["error: macro name must be an identifier"],
+    );
+    check_diags(
+        "#if 1 ? 2\n#endif\n",
+        "",
+        &["error: expected ':' in conditional expression"],
+    );
+}
+
+#[test]
+fn expr_depth_limit() {
+    let nested = |open: &str, inner: &str, close: &str, depth: usize| {
+        format!(
+            "#if {}{}{}\nyes\n#endif\nafter",
+            open.repeat(depth),
+            inner,
+            close.repeat(depth)
+        )
+    };
+    let too_deep = format!(
+        "fatal: expression nested too deeply (maximum depth is {})",
+        DEFAULT_MAX_BRACKET_DEPTH
+    );
+
+    check(
+        &nested("(", "1", ")", DEFAULT_MAX_BRACKET_DEPTH),
+        "yes\nafter",
+    );
+    check(
+        &nested("!", "0", "", DEFAULT_MAX_BRACKET_DEPTH - 1),
+        "yes\nafter",
+    );
+    check(
+        &nested("0 ? 0 : ", "1", "", DEFAULT_MAX_BRACKET_DEPTH),
+        "yes\nafter",
+    );
+
+    for src in [
+        nested("(", "1", ")", DEFAULT_MAX_BRACKET_DEPTH + 1),
+        nested("(", "1", ")", 10_000),
+        nested("- ", "1", "", 10_000),
+        nested("1 ? ", "1", " : 0", 10_000),
+        nested("0 ? 0 : ", "1", "", 10_000),
+    ] {
+        let (output, diags) = preprocess(&src);
+        assert_eq!(output, "");
+        assert_eq!(diags, [too_deep.as_str()]);
+    }
+}
+
+#[test]
+fn cancellation() {
+    let mut interner = Interner::new();
+    let mut manager = DiagManager::new_annotating(None);
+    let mut smap = SourceMap::new();
+
+    let main_id = smap
+        .create_file(
+            FileName::synth("test.c"),
+            FileContents::new(&"a ".repeat(1000)),
+            None,
+        )
+        .unwrap();
+
+    let mut ctx = LexCtx::new(&mut interner, &mut manager, &mut smap);
+    let token = CancellationToken::new();
+    let mut pp = PreprocessorBuilder::new(&mut ctx, main_id)
+        .cancellation_token(token.clone())
+        .build();
+
+    assert!(pp.next_pp(&mut ctx).is_ok());
+    token.cancel();
+
+    // The token is only checked periodically, so some tokens may still be produced.
+    let res = (0..).find_map(|_| pp.next_pp(&mut ctx).err());
+    assert!(matches!(res, Some(PpError::Cancelled)));
+}
+
+#[test]
+fn cancellation_without_tokens() {
+    let check_cancelled = |src: &str| {
+        let mut interner = Interner::new();
+        let mut manager = DiagManager::new_annotating(None);
+        let mut smap = SourceMap::new();
+
+        let main_id = smap
+            .create_file(FileName::synth("test.c"), FileContents::new(src), None)
+            .unwrap();
+
+        let mut ctx = LexCtx::new(&mut interner, &mut manager, &mut smap);
+        let token = CancellationToken::new();
+        let mut pp = PreprocessorBuilder::new(&mut ctx, main_id)
+            .cancellation_token(token.clone())
+            .build();
+
+        assert!(pp.next_pp(&mut ctx).is_ok());
+        token.cancel();
+
+        // No tokens are produced before the end, so cancellation must be noticed while skipping or
+        // expanding.
+        assert!(matches!(pp.next_pp(&mut ctx), Err(PpError::Cancelled)));
+    };
+
+    check_cancelled(&format!(
+        "a\n#if 0\n{}#endif\nb",
+        "#define X\n".repeat(1000)
+    ));
+    check_cancelled(&format!("#define E\na\n{}b", "E ".repeat(1000)));
+    check_cancelled(&format!("#define E\n#define M a {}b\nM", "E ".repeat(1000)));
+}
+
+/// Creates a preprocessor for `src` and passes it to `f` along with its context.
+fn with_pp<R>(src: &str, f: impl FnOnce(&mut LexCtx<'_, '_>, &mut Preprocessor) -> R) -> R {
+    let mut interner = Interner::new();
+    let mut manager = DiagManager::new_annotating(None);
+    let mut smap = SourceMap::new();
+
+    let main_id = smap
+        .create_file(FileName::synth("test.c"), FileContents::new(src), None)
+        .unwrap();
+
+    let mut ctx = LexCtx::new(&mut interner, &mut manager, &mut smap);
+    let mut pp = PreprocessorBuilder::new(&mut ctx, main_id).build();
+    f(&mut ctx, &mut pp)
+}
+
+/// Returns the spellings of the next `n` tokens in `stream`.
+fn stream_spellings(ctx: &mut LexCtx<'_, '_>, stream: &mut TokenStream<'_>, n: usize) -> String {
+    (0..n)
+        .map(|_| {
+            let ppt = stream.next_pp(ctx).unwrap();
+            ppt.display(ctx).to_string().trim().to_owned()
+        })
+        .join(" ")
+}
+
+#[test]
+fn token_stream_rewind() {
+    with_pp("#define A x y\na A b", |ctx, pp| {
+        let mut stream = TokenStream::new(pp);
+
+        assert_eq!(
+            stream.peek_pp(ctx, 2).unwrap().data(),
+            TokenKind::Ident(ctx.interner.intern("y"))
+        );
+        assert_eq!(stream_spellings(ctx, &mut stream, 1), "a");
+
+        let checkpoint = stream.checkpoint();
+        assert_eq!(stream_spellings(ctx, &mut stream, 3), "x y b");
+        assert_eq!(stream.next_pp(ctx).unwrap().data(), TokenKind::Eof);
+        assert_eq!(stream.next_pp(ctx).unwrap().data(), TokenKind::Eof);
+        assert_eq!(stream.peek_pp(ctx, 5).unwrap().data(), TokenKind::Eof);
+
+        stream.rewind(checkpoint);
+        assert_eq!(stream_spellings(ctx, &mut stream, 2), "x y");
+
+        stream.restart();
+        assert_eq!(stream_spellings(ctx, &mut stream, 4), "a x y b");
+        assert_eq!(stream.buffered_len(), 5);
+    });
+}
+
+#[test]
+fn token_stream_window() {
+    with_pp("a b c d e f", |ctx, pp| {
+        let mut stream = TokenStream::with_window(pp, 2);
+
+        assert_eq!(stream_spellings(ctx, &mut stream, 1), "a");
+        let start = stream.checkpoint();
+        assert_eq!(stream_spellings(ctx, &mut stream, 2), "b c");
+        assert!(stream.can_rewind(start));
+
+        stream.rewind(start);
+        assert_eq!(stream_spellings(ctx, &mut stream, 4), "b c d e");
+        assert!(!stream.can_rewind(start));
+        assert!(!stream.can_rewind(Checkpoint::START));
+        assert_eq!(stream.buffered_len(), 2);
+
+        assert_eq!(
+            stream.peek_pp(ctx, 0).unwrap().data(),
+            TokenKind::Ident(ctx.interner.intern("f"))
+        );
+        assert_eq!(stream.buffered_len(), 3);
+    });
+}
+
+#[test]
+fn line_and_file() {
+    check("a __LINE__\n\n__LINE__ __FILE__", "a 1\n3 \"<test.c>\"");
+    check("#define L __LINE__\n\nL", "3");
+    check("#define F(x) x __LINE__\nF(\n1\n)", "1 2");
+    check_diags(
+        "#ifdef __LINE__\na\n#endif\n#undef __LINE__\n__LINE__",
+        "a\n__LINE__",
+        &["warning: macro name '__LINE__' is reserved to the implementation"],
+    );
+}
+
+#[test]
+fn gnu_builtins() {
+    let (output, diags) = preprocess_with(
+        concat!(
+            "#define C __COUNTER__
+",
+            "__COUNTER__ C __COUNTER__
+",
+            "__BASE_FILE__ __INCLUDE_LEVEL__
+",
+            "#include <gen/a.h>\n",
+            "__INCLUDE_LEVEL__\n",
+        ),
+        |builder| {
+            builder
+                .lang_options(LangOptions {
+                    gnu_extensions: true,
+                    ..LangOptions::default()
+                })
+                .include_resolver(GeneratedHeaders::new(&[
+                    ("a.h", "__INCLUDE_LEVEL__ __COUNTER__\n#include <gen/b.h>\n"),
+                    ("b.h", "__INCLUDE_LEVEL__ __BASE_FILE__ __FILE__\n"),
+                ]));
+        },
+    );
+
+    assert_eq!(
+        output,
+        "0 1 2\n\"<test.c>\" 0\n1 3\n2 \"<test.c>\" \"gen/b.h\"\n0"
+    );
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+
+    check(
+        "__COUNTER__ __BASE_FILE__ __INCLUDE_LEVEL__",
+        "__COUNTER__ __BASE_FILE__ __INCLUDE_LEVEL__",
+    );
+}
+
+#[test]
+fn standard_predefines() {
+    check("__STDC__ __STDC_VERSION__", "1 201112L");
+
+    let (output, diags) = preprocess_with("__DATE__ __TIME__", |builder| {
+        builder.date_time(UNIX_EPOCH + Duration::from_secs(1_791_976_545));
+    });
+    assert_eq!(output, "\"Oct 14 2026\" \"11:15:45\"");
+    assert!(diags.is_empty());
+}
+
+#[test]
+fn command_line_defines() {
+    let (output, diags) = preprocess_with("A B F(3) C __STDC__ __STDC_VERSION__", |builder| {
+        builder
+            .define("A")
+            .define("B=2")
+            .define("F(x)=x+1")
+            .define("C")
+            .undef("C")
+            .undef("__STDC__")
+            .define("__STDC_VERSION__=199901L");
+    });
+    assert_eq!(output, "1 2 3 + 1 C __STDC__ 199901L");
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+
+    let (output, diags) = preprocess_with("#define A 2\nA", |builder| {
+        builder.define("A=1");
+    });
+    assert_eq!(output, "2");
+    assert_eq!(diags, ["error: redefinition of macro 'A'"]);
+
+    let (output, diags) = preprocess_with("X", |builder| {
+        builder.define("X=1\n#error injected");
+    });
+    assert_eq!(output, "1");
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+}
+
+#[test]
+fn forced_includes() {
+    let mut fs = InMemoryFileSystem::new();
+    fs.add_file("/inc/a.h", "#ifdef X\na X\n#endif\n#define A 1\n")
+        .add_file("/b.h", "b A\n");
+
+    let (output, diags) = preprocess_with("A main", |builder| {
+        builder
+            .include_file("a.h")
+            .include_file("/b.h")
+            .define("X=x")
+            .include_dirs(vec!["/inc".into()])
+            .file_cache(FileCache::with_file_system(fs));
+    });
+    assert_eq!(output, "a x\nb 1\n1 main");
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+
+    let (output, diags) = preprocess_with("x", |builder| {
+        builder.include_file("missing.h");
+    });
+    assert_eq!(output, "x");
+    assert_eq!(diags, ["error: include 'missing.h' not found"]);
+
+    let mut fs = InMemoryFileSystem::new();
+    fs.add_file("/a\"\n#error injected.h", "a\n");
+
+    let (output, diags) = preprocess_with("x", |builder| {
+        builder
+            .include_file("/a\"\n#error injected.h")
+            .file_cache(FileCache::with_file_system(fs));
+    });
+    assert_eq!(output, "a\nx");
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+}
+
+#[test]
+fn stringize() {
+    check("#define S(x) #x\nS(a)", "\"a\"");
+    check("#define S(x) #x\nS()", "\"\"");
+    check("#define S(x) #x\nS(  a  +\n  b  )", "\"a + b\"");
+    check("#define S(x) #x\nS(a+b)", "\"a+b\"");
+    check(
+        r#"#define S(x) #x
+S("a\n" 'b' '"')"#,
+        r#""\"a\\n\" 'b' '\"'""#,
+    );
+    check("#define S(x) # x\nS(f(1, 2))", "\"f(1, 2)\"");
+    check("#define S(x, y) #y #x\nS(1, 2)", "\"2\" \"1\"");
+}
+
+#[test]
+fn stringize_unexpanded() {
+    check("#define A 1\n#define S(x) #x x\nS(A)", "\"A\" 1");
+    check(
+        "#define S(x) #x\n#define T(x) S(x)\n#define A 1\nT(A)",
+        "\"1\"",
+    );
+}
+
+#[test]
+fn stringize_digraphs() {
+    check(
+        "#define S(x) #x\nS(<: :> <% %> %: %:%:)",
+        "\"<: :> <% %> %: %:%:\"",
+    );
+    check("#define S(x) %:x\nS([)", "\"[\"");
+    check("#define A <:\nA :>", "<: :>");
+}
+
+#[test]
+fn stringize_not_param() {
+    check_diags(
+        "#define S(x) #y\nS(1)",
+        "S ( 1 )",
+        &["error: '#' is not followed by a macro parameter"],
+    );
+    check("#define S #x\nS", "# x");
+}
+
+#[test]
+fn memory_stats() {
+    let mut interner = Interner::new();
+    let mut manager = DiagManager::new_annotating(None);
+    let mut smap = SourceMap::new();
+
+    let main_id = smap
+        .create_file(
+            FileName::synth("test.c"),
+            FileContents::new("#define A(x) x x\n#define B 1\n#undef B\nA(A(1))"),
+            None,
+        )
+        .unwrap();
+
+    let mut ctx = LexCtx::new(&mut interner, &mut manager, &mut smap);
+    let mut pp = PreprocessorBuilder::new(&mut ctx, main_id).build();
+    while pp.next_pp(&mut ctx).unwrap().data() != TokenKind::Eof {}
+
+    let stats = MemoryStats::collect(&ctx, &pp);
+    assert!(stats.source_map > 0);
+    assert!(stats.interner > 0);
+    assert!(stats.macro_table > 0);
+    assert!(stats.replacements > 0);
+    assert_eq!(
+        stats.total(),
+        stats.source_map + stats.interner + stats.macro_table + stats.replacements
+    );
+}
+
+#[test]
+fn pp_stats() {
+    fn collect(src: &str, enabled: bool) -> Option<PpStats> {
+        let mut interner = Interner::new();
+        let mut manager = DiagManager::new_annotating(None);
+        let mut smap = SourceMap::new();
+
+        let main_id = smap
+            .create_file(FileName::synth("test.c"), FileContents::new(src), None)
+            .unwrap();
+
+        let mut ctx = LexCtx::new(&mut interner, &mut manager, &mut smap);
+        let mut pp = PreprocessorBuilder::new(&mut ctx, main_id)
+            .include_resolver(GeneratedHeaders::new(&[("a.h", "#define B(x) x\nB(2)\n")]))
+            .collect_stats(enabled)
+            .build();
+        while pp.next_pp(&mut ctx).unwrap().data() != TokenKind::Eof {}
+
+        pp.stats()
+    }
+
+    let src = "#define A 1\n#include <gen/a.h>\nA B(A) B\n#if 0\n#error\n#endif";
+    assert!(collect(src, false).is_none());
+
+    // The predefined macros are also defined by directives, so only count the additional ones.
+    let baseline = collect("", true).unwrap();
+    let stats = collect(src, true).unwrap();
+    assert_eq!(stats.tokens, 4);
+    assert_eq!(stats.macros_expanded, 4);
+    assert_eq!(stats.files_included, 1);
+    assert_eq!(
+        stats.directives_processed - baseline.directives_processed,
+        4
+    );
+    assert_eq!(
+        stats.total_time(),
+        stats.lexing + stats.directives + stats.macro_expansion + stats.include_io
+    );
+}
+
+#[test]
+fn variadic() {
+    check(
+        "#define F(...) [__VA_ARGS__]\nF() F(1) F(1, (2, 3), 4)",
+        "[ ] [ 1 ] [ 1 , ( 2 , 3 ) , 4 ]",
+    );
+    check(
+        "#define F(a, ...) a: __VA_ARGS__\nF() F(1) F(1,) F(1, 2, 3)",
+        ": 1 : 1 : 1 : 2 , 3",
+    );
+    check("#define S(...) #__VA_ARGS__\nS(a,b,  c)", "\"a,b, c\"");
+    check("#define A 1\n#define F(...) __VA_ARGS__\nF(A, A)", "1 , 1");
+    check_diags(
+        "#define F(a, b, ...) a\nF(1)",
+        "",
+        &["error: too few arguments provided to macro invocation"],
+    );
+}
+
+#[test]
+fn redefinition() {
+    check("#define A 1  +/* c */2\n#define A   1 + 2 // c\nA", "1 + 2");
+    check(
+        "#define F(x, y) x + y\n#define F( x ,y )x  +  y\nF(a, b)",
+        "a + b",
+    );
+    check("#define E\n#define E \nE", "");
+
+    for src in &[
+        "#define A 1 + 2\n#define A 1+2\n",
+        "#define A 1\n#define A 2\n",
+        "#define A\n#define A()\n",
+        "#define A [\n#define A <:\n",
+        "#define A(x) x\n#define A(y) y\n",
+        "#define A(x) x\n#define A(x, ...) x\n",
+    ] {
+        check_diags(src, "", &["error: redefinition of macro 'A'"]);
+    }
+}
+
+#[test]
+fn reserved_macro_names() {
+    for (src, name) in &[
+        ("#define __foo 1\n", "__foo"),
+        ("#define _Foo 1\n", "_Foo"),
+        ("#undef __STDC__\n", "__STDC__"),
+        ("#define defined\n", "defined"),
+    ] {
+        check_diags(
+            src,
+            "",
+            &[&format!(
+                "warning: macro name '{}' is reserved to the implementation",
+                name
+            )],
+        );
+    }
+
+    check("#define _foo 1\n#define foo_ 2\n_foo foo_", "1 2");
+
+    let (_, diags) = preprocess_with("#define __foo 1\n", |builder| {
+        builder.pedantic_errors(true).define("__bar");
+    });
+    assert_eq!(
+        diags,
+        ["error: macro name '__foo' is reserved to the implementation"]
+    );
+}
+
+#[test]
+fn variadic_definitions() {
+    check("#define F(...) 1\n#define F(...) 1\nF()", "1");
+    check_diags(
+        "#define F(...) 1\n#define F(x) 1\n",
+        "",
+        &["error: redefinition of macro 'F'"],
+    );
+    check_diags(
+        "#define F(..., a)\n",
+        "",
+        &["error: expected a ')' after '...'"],
+    );
+    check_diags(
+        "#define F(__VA_ARGS__)\n",
+        "",
+        &["error: '__VA_ARGS__' can only appear in the expansion of a variadic macro"],
+    );
+    check_diags(
+        "#define A __VA_ARGS__\n#define F(x) __VA_OPT__(x)\n",
+        "",
+        &[
+            "warning: '__VA_ARGS__' can only appear in the expansion of a variadic macro",
+            "warning: '__VA_OPT__' can only appear in the expansion of a variadic macro",
+        ],
+    );
+}
+
+#[test]
+fn va_opt() {
+    let def = "#define F(a, ...) f(a __VA_OPT__(,) __VA_ARGS__)\n";
+    check(
+        &format!("{}F(1) F(1,) F(1, 2, 3)", def),
+        "f ( 1 ) f ( 1 ) f ( 1 , 2 , 3 )",
+    );
+    check(
+        "#define G(...) __VA_OPT__(g((__VA_ARGS__)))\nG() G(x)",
+        "g ( ( x ) )",
+    );
+    check_diags(
+        "#define F(...) __VA_OPT__ x\n",
+        "",
+        &["error: '__VA_OPT__' must be followed by '('"],
+    );
+    check_diags(
+        "#define F(...) __VA_OPT__(x\n",
+        "",
+        &["error: unterminated '__VA_OPT__'"],
+    );
+    check_diags(
+        "#define F(...) __VA_OPT__(__VA_OPT__())\n",
+        "",
+        &["error: '__VA_OPT__' cannot be nested"],
+    );
+
+    let src = "#define V(...) __VA_OPT__(x)\nV(1)";
+    let pedantic = |standard| {
+        preprocess_with(src, |builder| {
+            builder.lang_options(LangOptions {
+                standard,
+                pedantic: true,
+                ..LangOptions::default()
+            });
+        })
+    };
+    assert_eq!(
+        pedantic(LangStandard::C11),
+        (
+            "x".to_owned(),
+            vec!["warning: '__VA_OPT__' is a C23 feature".to_owned()]
+        )
+    );
+    assert_eq!(pedantic(LangStandard::C23), ("x".to_owned(), vec![]));
+}
+
+#[test]
+fn stringize_va_opt() {
+    let def = "#define S(a, ...) #__VA_OPT__(a   [__VA_ARGS__] #a)\n";
+    check(
+        &format!("{}S(1) S(1,) S(1, 2,  3)", def),
+        r#""" "" "1 [2, 3] \"1\"""#,
+    );
+    check(
+        "#define A x\n#define S(...) # __VA_OPT__(__VA_ARGS__)\nS(A) S()",
+        r#""x" """#,
+    );
+    check_diags(
+        "#define S(x) #__VA_OPT__(x)\nS(1)",
+        "S ( 1 )",
+        &[
+            "warning: '__VA_OPT__' can only appear in the expansion of a variadic macro",
+            "error: '#' is not followed by a macro parameter",
+        ],
+    );
+}
+
+/// A pragma handler that records the spellings of the pragmas it handles.
+struct RecordingHandler(Rc<RefCell<Vec<String>>>);
+
+impl PragmaHandler for RecordingHandler {
+    fn handle(&mut self, ctx: &mut LexCtx<'_, '_>, pragma: &Pragma) -> DResult<()> {
+        let spelling = pragma
+            .tokens
+            .iter()
+            .map(|ppt| ppt.tok.display(ctx).to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.0.borrow_mut().push(spelling);
+        Ok(())
+    }
+}
+
+#[test]
+fn pragmas() {
+    let mut interner = Interner::new();
+    let mut manager = DiagManager::new_annotating(None);
+    let mut smap = SourceMap::new();
+
+    let main_id = smap
+        .create_file(
+            FileName::synth("test.c"),
+            FileContents::new("#define A 1\n#pragma mine A(2)\n#pragma other A\n#pragma\nx"),
+            None,
+        )
+        .unwrap();
+
+    let handled = Rc::new(RefCell::new(Vec::new()));
+
+    let mut ctx = LexCtx::new(&mut interner, &mut manager, &mut smap);
+    let mut pp = PreprocessorBuilder::new(&mut ctx, main_id)
+        .pragma_handler("mine", RecordingHandler(Rc::clone(&handled)))
+        .build();
+
+    let mut unhandled = Vec::new();
+    loop {
+        match pp.next_pp_event(&mut ctx).unwrap() {
+            PpEvent::Tok(ppt) if ppt.data() == TokenKind::Eof => break,
+            PpEvent::Tok(ppt) => assert_eq!(ppt.tok.display(&ctx).to_string(), "x"),
+            PpEvent::Pragma(pragma) => unhandled.push(pragma.tokens.len()),
+            PpEvent::EnterFile(_) | PpEvent::ExitFile(_) => unreachable!(),
+        }
+    }
+
+    assert_eq!(*handled.borrow(), ["mine A ( 2 )"]);
+    assert_eq!(unhandled, [2, 0]);
+}
+
+#[test]
+fn pragma_operator() {
+    let mut interner = Interner::new();
+    let mut manager = DiagManager::new_annotating(None);
+    let mut smap = SourceMap::new();
+
+    let main_id = smap
+        .create_file(
+            FileName::synth("test.c"),
+            FileContents::new(concat!(
+                "#define DO_PRAGMA(x) _Pragma(#x)\n",
+                "#define P _Pragma\n",
+                "a _Pragma(\"mine \\\"s\\\" b\\\\c\") b\n",
+                "DO_PRAGMA(mine 1 + 2)\n",
+                "P(L\"other x\")\n",
+                "_Pragma(\n",
+                "\"mine end\") c\n",
+            )),
+            None,
+        )
+        .unwrap();
+
+    let handled = Rc::new(RefCell::new(Vec::new()));
+
+    let mut ctx = LexCtx::new(&mut interner, &mut manager, &mut smap);
+    let mut pp = PreprocessorBuilder::new(&mut ctx, main_id)
+        .pragma_handler("mine", RecordingHandler(Rc::clone(&handled)))
+        .build();
+
+    let mut output = Vec::new();
+    let mut unhandled = Vec::new();
+    loop {
+        match pp.next_pp_event(&mut ctx).unwrap() {
+            PpEvent::Tok(ppt) if ppt.data() == TokenKind::Eof => break,
+            PpEvent::Tok(ppt) => output.push(ppt.tok.display(&ctx).to_string()),
+            PpEvent::Pragma(pragma) => unhandled.push(pragma.tokens.len()),
+            PpEvent::EnterFile(_) | PpEvent::ExitFile(_) => unreachable!(),
+        }
+    }
+
+    assert_eq!(output, ["a", "b", "c"]);
+    assert_eq!(
+        *handled.borrow(),
+        [r#"mine "s" b \ c"#, "mine 1 + 2", "mine end"]
+    );
+    assert_eq!(unhandled, [2]);
+}
+
+#[test]
+fn malformed_pragma_operator() {
+    check_diags(
+        "_Pragma x",
+        "",
+        &["error: '_Pragma' takes a parenthesized string literal"],
+    );
+    check_diags(
+        "_Pragma(x) y",
+        ") y",
+        &["error: '_Pragma' takes a parenthesized string literal"],
+    );
+    check_diags("_Pragma(\"x\" y", "", &["error: expected a ')'"]);
+    check_diags(
+        "_Pragma(\n#define A\nA",
+        "",
+        &["error: '_Pragma' takes a parenthesized string literal"],
+    );
+}
+
+#[test]
+fn pragma_once() {
+    let dir = std::env::temp_dir().join(format!("mrcc-pp-pragma-once-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("once.h"), "#pragma once\nonce\n").unwrap();
+    fs::write(dir.join("multi.h"), "multi\n").unwrap();
+
+    let src =
+        "#include \"once.h\"\n#include \"multi.h\"\n#include \"once.h\"\n#include \"multi.h\"";
+    let (output, diags) = preprocess_with(src, |builder| {
+        builder.parent_dir(dir.clone());
+    });
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(output, "once\nmulti\nmulti");
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+
+    check_diags(
+        "#pragma once\nx",
+        "x",
+        &["warning: '#pragma once' in main file"],
+    );
+}
+
+#[test]
+fn missing_includes() {
+    let src = "#include <gen/a.h>\n#define A a\n#include \"b.h\"\nA\n#include <gen/c.h>\nc";
+    let configure = |builder: &mut PreprocessorBuilder<'_, '_, '_>| {
+        builder.include_resolver(GeneratedHeaders::new(&[("a.h", "x\n")]));
+    };
+
+    let (output, diags) = preprocess_with(src, configure);
+    assert_eq!(output, "x\na\nc");
+    assert_eq!(
+        diags,
+        [
+            "error: include 'b.h' not found",
+            "error: include 'gen/c.h' not found"
+        ]
+    );
+
+    let (output, diags) = preprocess_with(src, |builder| {
+        configure(builder);
+        builder.missing_include_level(Level::Fatal);
+    });
+    assert_eq!(output, "x");
+    assert_eq!(diags, ["fatal: include 'b.h' not found"]);
+}
+
+#[test]
+fn include_depth_limit() {
+    let (output, diags) = preprocess_with("#include <gen/self.h>\nx", |builder| {
+        builder.include_resolver(GeneratedHeaders::new(&[(
+            "self.h",
+            "s\n#include <gen/self.h>\n",
+        )]));
+    });
+    assert_eq!(output, vec!["s"; DEFAULT_MAX_INCLUDE_DEPTH].join("\n"));
+    assert_eq!(
+        diags,
+        [format!(
+            "fatal: #include nested too deeply (maximum depth is {})",
+            DEFAULT_MAX_INCLUDE_DEPTH
+        )]
+    );
+
+    /// A diagnostic sink that records the length of the include trace of every diagnostic.
+    struct IncludeTraceSink<'a>(&'a RefCell<Vec<usize>>);
+
+    impl RenderedSink for IncludeTraceSink<'_> {
+        fn report(&mut self, diag: &RenderedDiagnostic, _smap: Option<&SourceMap>) {
+            self.0.borrow_mut().push(diag.includes.len());
+        }
+    }
+
+    let preprocess_chain = |max_depth| {
+        let traces = RefCell::new(Vec::new());
+        let mut manager = DiagManager::new(IncludeTraceSink(&traces), None);
+        let mut interner = Interner::new();
+        let mut smap = SourceMap::new();
+        let main_id = smap
+            .create_file(
+                FileName::synth("test.c"),
+                FileContents::new("#include <gen/a.h>\nx"),
+                None,
+            )
+            .unwrap();
+        let mut ctx = LexCtx::new(&mut interner, &mut manager, &mut smap);
+        ctx.limits.max_include_depth = max_depth;
+
+        let mut pp = PreprocessorBuilder::new(&mut ctx, main_id)
+            .include_resolver(GeneratedHeaders::new(&[
+                ("a.h", "a\n#include <gen/b.h>\n"),
+                ("b.h", "b\n#include <gen/c.h>\n"),
+                ("c.h", "c\n"),
+            ]))
+            .build();
+
+        let mut output = Vec::new();
+        while let Ok(ppt) = pp.next_pp(&mut ctx) {
+            if ppt.data() == TokenKind::Eof {
+                break;
+            }
+            output.push(ppt.tok.display(&ctx).to_string());
+        }
+
+        drop(manager);
+        (output.join(" "), traces.into_inner())
+    };
+
+    assert_eq!(preprocess_chain(3), ("a b c x".to_owned(), vec![]));
+    // The error is reported at the `#include` in `b.h`, which is itself included through `a.h`.
+    assert_eq!(preprocess_chain(2), ("a b".to_owned(), vec![2]));
+    assert_eq!(preprocess_chain(0), ("".to_owned(), vec![0]));
+}
+
+/// Preprocesses `src` with file boundaries enabled, returning the spellings of the resulting tokens
+/// interleaved with the boundaries of included files.
+fn file_events(
+    src: &str,
+    configure: impl FnOnce(&mut PreprocessorBuilder<'_, '_, '_>),
+) -> Vec<String> {
+    let mut interner = Interner::new();
+    let mut manager = DiagManager::new_annotating(None);
+    let mut smap = SourceMap::new();
+    let main_id = smap
+        .create_file(FileName::synth("test.c"), FileContents::new(src), None)
+        .unwrap();
+
+    let mut ctx = LexCtx::new(&mut interner, &mut manager, &mut smap);
+    let mut builder = PreprocessorBuilder::new(&mut ctx, main_id);
+    builder.file_boundaries(true);
+    configure(&mut builder);
+    let mut pp = builder.build();
+
+    let mut events = Vec::new();
+    loop {
+        let event = match pp.next_pp_event(&mut ctx).unwrap() {
+            PpEvent::Tok(ppt) if ppt.data() == TokenKind::Eof => break,
+            PpEvent::Tok(ppt) => ppt.tok.display(&ctx).to_string(),
+            PpEvent::Pragma(_) => unreachable!(),
+            PpEvent::EnterFile(id) => format!(
+                "enter {}",
+                ctx.smap.get_source(id).as_file().unwrap().filename
+            ),
+            PpEvent::ExitFile(id) => format!(
+                "exit {}",
+                ctx.smap.get_source(id).as_file().unwrap().filename
+            ),
+        };
+        events.push(event);
+    }
+
+    events
+}
+
+#[test]
+fn file_boundaries() {
+    let events = file_events(
+        "a\n#include <gen/outer.h>\nd\n#include <gen/empty.h>\n",
+        |builder| {
+            builder.include_resolver(GeneratedHeaders::new(&[
+                ("outer.h", "b\n#include <gen/inner.h>\n"),
+                ("inner.h", "c\n"),
+                ("empty.h", ""),
+            ]));
+        },
+    );
+
+    assert_eq!(
+        events,
+        [
+            "a",
+            "enter gen/outer.h",
+            "b",
+            "enter gen/inner.h",
+            "c",
+            "exit gen/inner.h",
+            "exit gen/outer.h",
+            "d",
+            "enter gen/empty.h",
+            "exit gen/empty.h",
+        ]
+    );
+
+    let (output, _) = preprocess_with("#include <gen/a.h>\nx", |builder| {
+        builder.include_resolver(GeneratedHeaders::new(&[("a.h", "a\n")]));
+    });
+    assert_eq!(output, "a\nx");
+}
+
+/// Callbacks recording a description of every event into a shared log.
+struct RecordingCallbacks(Rc<RefCell<Vec<String>>>);
+
+impl RecordingCallbacks {
+    fn filename(ctx: &LexCtx<'_, '_>, id: SourceId) -> String {
+        ctx.smap
+            .get_source(id)
+            .as_file()
+            .unwrap()
+            .filename
+            .to_string()
+    }
+}
+
+impl PpCallbacks for RecordingCallbacks {
+    fn file_entered(&mut self, ctx: &mut LexCtx<'_, '_>, id: SourceId) -> DResult<()> {
+        let event = format!("enter {}", Self::filename(ctx, id));
+        self.0.borrow_mut().push(event);
+        Ok(())
+    }
+
+    fn file_exited(&mut self, ctx: &mut LexCtx<'_, '_>, id: SourceId) -> DResult<()> {
+        let event = format!("exit {}", Self::filename(ctx, id));
+        self.0.borrow_mut().push(event);
+        Ok(())
+    }
+
+    fn include_resolved(
+        &mut self,
+        _ctx: &mut LexCtx<'_, '_>,
+        filename: &Path,
+        _kind: IncludeKind,
+        _range: SourceRange,
+        _file: &File,
+    ) -> DResult<()> {
+        let event = format!("include {}", filename.display());
+        self.0.borrow_mut().push(event);
+        Ok(())
+    }
+
+    fn macro_defined(&mut self, ctx: &mut LexCtx<'_, '_>, def: &MacroDef) -> DResult<()> {
+        if def.origin == MacroOrigin::Source {
+            let event = format!("define {}", &ctx.interner[def.name_tok.data]);
+            self.0.borrow_mut().push(event);
+        }
+        Ok(())
+    }
+
+    fn macro_undefined(
+        &mut self,
+        ctx: &mut LexCtx<'_, '_>,
+        name_tok: Token<Symbol>,
+        prev: Option<&MacroDef>,
+    ) -> DResult<()> {
+        let event = format!(
+            "undef {} ({})",
+            &ctx.interner[name_tok.data],
+            if prev.is_some() {
+                "defined"
+            } else {
+                "undefined"
+            }
+        );
+        self.0.borrow_mut().push(event);
+        Ok(())
+    }
+
+    fn macro_expanded(
+        &mut self,
+        ctx: &mut LexCtx<'_, '_>,
+        name_tok: Token<Symbol>,
+        _def: &MacroDef,
+    ) -> DResult<()> {
+        let event = format!("expand {}", &ctx.interner[name_tok.data]);
+        self.0.borrow_mut().push(event);
+        Ok(())
+    }
+
+    fn region_skipped(&mut self, ctx: &mut LexCtx<'_, '_>, range: SourceRange) -> DResult<()> {
+        let event = format!("skip {:?}", ctx.smap.get_spelling(range));
+        self.0.borrow_mut().push(event);
+        Ok(())
+    }
+}
+
+#[test]
+fn callbacks() {
+    let src = "#define A 1\n\
+               #define F(x) x\n\
+               #include <gen/a.h>\n\
+               #if 0\n\
+               skipped\n\
+               #else\n\
+               F(A)\n\
+               #endif\n\
+               #undef A\n\
+               #undef B\n";
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let (output, _) = preprocess_with(src, |builder| {
+        builder
+            .include_resolver(GeneratedHeaders::new(&[("a.h", "h\n")]))
+            .callbacks(RecordingCallbacks(Rc::clone(&log)));
+    });
+    assert_eq!(output, "h\n1");
+
+    assert_eq!(
+        *log.borrow(),
+        [
+            "define A",
+            "define F",
+            "include gen/a.h",
+            "enter gen/a.h",
+            "exit gen/a.h",
+            "skip \"skipped\\n\"",
+            "expand F",
+            "expand A",
+            "undef A (defined)",
+            "undef B (undefined)",
+        ]
+    );
+}
+
+#[test]
+fn degenerate_corpus() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus/degenerate");
+
+    for entry in fs::read_dir(dir).unwrap() {
+        let src = fs::read_to_string(entry.unwrap().path()).unwrap();
+
+        // These inputs may well be erroneous; this only checks that they are handled gracefully.
+        for &tracking in &[
+            ExpansionTracking::None,
+            ExpansionTracking::Macros,
+            ExpansionTracking::Full,
+        ] {
+            preprocess_with(&src, |builder| {
+                builder.expansion_tracking(tracking);
+            });
+        }
+    }
+}
+
+#[test]
+fn line_directive() {
+    check("#line 10\n__LINE__\n__LINE__", "10\n11");
+    check(
+        "#line 5 \"foo.c\"\n__LINE__ __FILE__\n#line 20\n__FILE__ __LINE__",
+        "5 \"foo.c\"\n\"foo.c\" 20",
+    );
+    check(
+        "#define L 100\n#define F \"a\\\\\\\"b.c\"\n#line L F\n__LINE__ __FILE__",
+        r#"100 "a\\\"b.c""#,
+    );
+    check("a\n#line 1\n#define F(x) __LINE__\nF(\n)", "a\n2");
+}
+
+#[test]
+fn invalid_line_directives() {
+    check_diags(
+        "#line\n#line x\n__LINE__",
+        "3",
+        &[
+            "error: '#line' directive requires a positive integer argument",
+            "error: '#line' directive requires a positive integer argument",
+        ],
+    );
+    check_diags(
+        "#line 0x10\n#line 0\n#line 2147483648\n__LINE__",
+        "4",
+        &[
+            "error: '#line' directive requires a simple digit sequence",
+            "error: line number out of range in '#line' directive",
+            "error: line number out of range in '#line' directive",
+        ],
+    );
+    check_diags(
+        "#line 10 foo\n#line 20 u8\"foo\"\n__LINE__",
+        "3",
+        &[
+            "error: invalid filename for '#line' directive",
+            "error: invalid filename for '#line' directive",
+        ],
+    );
+    check_diags(
+        "#line 10 \"foo.c\" bar\n__LINE__",
+        "10",
+        &["warning: extra tokens after preprocessing directive"],
+    );
+    check_diags(
+        "#line 10 \"foo\u{e9}\n__LINE__",
+        "2",
+        &[
+            "error: unterminated string literal",
+            "error: invalid filename for '#line' directive",
+        ],
+    );
+}
+
+/// An include resolver serving generated headers from memory, with only angled includes of the
+/// form `<gen/NAME>` permitted.
+struct GeneratedHeaders(Vec<(&'static str, Rc<File>)>);
+
+impl GeneratedHeaders {
+    fn new(headers: &[(&'static str, &str)]) -> Self {
+        Self(
+            headers
+                .iter()
+                .map(|&(name, src)| (name, File::new(FileContents::new(src), None)))
+                .collect(),
+        )
+    }
+}
+
+impl IncludeResolver for GeneratedHeaders {
+    fn resolve(
+        &mut self,
+        filename: &Path,
+        kind: IncludeKind,
+        _includer: &File,
+        _next_from: Option<usize>,
+        _cache: &mut FileCache,
+    ) -> Result<ResolvedInclude, IncludeError> {
+        let name = filename
+            .strip_prefix("gen")
+            .ok()
+            .filter(|_| kind == IncludeKind::Angled)
+            .ok_or(IncludeError::NotFound)?;
+
+        self.0
+            .iter()
+            .find(|(header, _)| Path::new(header) == name)
+            .map(|(_, file)| ResolvedInclude {
+                file: Rc::clone(file),
+                search_pos: None,
+            })
+            .ok_or(IncludeError::NotFound)
+    }
+}
+
+#[test]
+fn include_guards() {
+    let headers = [
+        (
+            "guarded.h",
+            "// comment\n#ifndef G\n#define G\n#if 1\ng\n#endif\n#endif\n",
+        ),
+        ("trailing.h", "#ifndef T\n#define T\n#endif\nt\n"),
+        ("leading.h", "#define L\n#ifndef L\n#endif\n"),
+        ("else.h", "#ifndef E\n#define E\n#else\n#endif\n"),
+        ("ifdef.h", "#ifdef D\n#undef D\n#endif\n"),
+    ];
+
+    let generated = |builder: &mut PreprocessorBuilder<'_, '_, '_>| {
+        builder.include_resolver(GeneratedHeaders::new(&headers));
+    };
+
+    let events = file_events(
+        "#include <gen/guarded.h>\n#include <gen/guarded.h>\n#undef G\n#include <gen/guarded.h>\n",
+        generated,
+    );
+    assert_eq!(
+        events,
+        [
+            "enter gen/guarded.h",
+            "g",
+            "exit gen/guarded.h",
+            "enter gen/guarded.h",
+            "g",
+            "exit gen/guarded.h",
+        ]
+    );
+
+    for header in &["trailing.h", "leading.h", "else.h", "ifdef.h"] {
+        let src = format!("#include <gen/{0}>\n#include <gen/{0}>\n", header);
+        let entered = file_events(&src, generated)
+            .iter()
+            .filter(|event| event.starts_with("enter"))
+            .count();
+        assert_eq!(entered, 2, "{} should not be treated as guarded", header);
+    }
+}
+
+#[test]
+fn include_guards_by_path() {
+    let dir = std::env::temp_dir().join(format!("mrcc-pp-include-guards-{}", std::process::id()));
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("g.h"), "#ifndef G\n#define G\ng\n#endif\n").unwrap();
+
+    let events = file_events("#include \"g.h\"\n#include \"sub/../g.h\"\n", |builder| {
+        builder.parent_dir(dir.clone());
+    });
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(events, ["enter g.h", "g", "exit g.h"]);
+}
+
+#[test]
+fn include_resolver() {
+    let src = "#include <gen/a.h>\n#include <gen/once.h>\n#include <gen/once.h>\nA ONCE";
+    let (output, diags) = preprocess_with(src, |builder| {
+        builder.include_resolver(GeneratedHeaders::new(&[
+            ("a.h", "#define A a\n"),
+            ("once.h", "#pragma once\n#define ONCE once\nonce_body\n"),
+        ]));
+    });
+    assert_eq!(output, "once_body\na once");
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+
+    for src in &["#include \"gen/a.h\"", "#include <a.h>"] {
+        let (_, diags) = preprocess_with(src, |builder| {
+            builder
+                .include_dirs(vec![".".into()])
+                .include_resolver(GeneratedHeaders::new(&[("a.h", "")]));
+        });
+        assert_eq!(diags.len(), 1);
+        assert!(diags[0].starts_with("error: include '"), "{:?}", diags);
+    }
+}
+
+#[test]
+fn has_include() {
+    let src = concat!(
+        "#if __has_include(<gen/a.h>) && !__has_include(<gen/missing.h>)\n",
+        "a\n",
+        "#endif\n",
+        "#define H <gen/a.h>\n",
+        "#if __has_include(H) && !__has_include(\"gen/a.h\")\n",
+        "b\n",
+        "#endif\n",
+        "#if defined(__has_include) && defined __has_include_next\n",
+        "c\n",
+        "#endif\n",
+    );
+    let (output, diags) = preprocess_with(src, |builder| {
+        builder.include_resolver(GeneratedHeaders::new(&[("a.h", "")]));
+    });
+    assert_eq!(output, "a\nb\nc");
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+
+    let mut fs = InMemoryFileSystem::new();
+    fs.add_file(
+        "/a/x.h",
+        "#if __has_include_next(<x.h>)\nnext\n#endif\n#if __has_include_next(<z.h>)\nz\n#endif\n",
+    )
+    .add_file("/b/x.h", "")
+    .add_file("/a/z.h", "");
+
+    let (output, diags) = preprocess_with("#include <x.h>\n", |builder| {
+        builder
+            .include_dirs(vec!["/a".into(), "/b".into()])
+            .file_cache(FileCache::with_file_system(fs));
+    });
+    assert_eq!(output, "next");
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+
+    check_diags(
+        "#if __has_include_next(<x.h>)\n#endif",
+        "",
+        &["warning: '__has_include_next' in main file"],
+    );
+    check_diags(
+        "#if __has_include <x.h>\n#endif",
+        "",
+        &["error: expected a '('"],
+    );
+    check_diags(
+        "#if __has_include(<x.h>\n#endif",
+        "",
+        &["error: expected a ')'"],
+    );
+}
+
+#[test]
+fn has_feature() {
+    let src = concat!(
+        "#if __has_feature(foo) && __has_feature(__foo__) && __has_extension(foo)\n",
+        "a\n",
+        "#endif\n",
+        "#if !__has_feature(bar) && __has_extension(bar) && !__has_extension(baz)\n",
+        "b\n",
+        "#endif\n",
+    );
+    let (output, diags) = preprocess_with(src, |builder| {
+        let mut features = FeatureTable::new();
+        features.add_feature("foo").add_extension("bar");
+        builder.features(features);
+    });
+    assert_eq!(output, "a\nb");
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+
+    check(
+        "#if __has_feature(foo) || __has_extension(foo)\na\n#endif",
+        "",
+    );
+    check_diags(
+        "#if __has_feature(1)\n#endif",
+        "",
+        &["error: feature name must be an identifier"],
+    );
+}
+
+/// A resource loader serving the contents of in-memory files.
+struct MemoryResources(Vec<(&'static str, &'static [u8])>);
+
+impl ResourceLoader for MemoryResources {
+    fn load(&mut self, path: &Path, max_len: usize) -> io::Result<Vec<u8>> {
+        match self.0.iter().find(|(name, _)| Path::new(name) == path) {
+            Some((_, data)) => Ok(data[..data.len().min(max_len)].to_vec()),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+}
+
+fn preprocess_embed(src: &str, max_embed_size: usize) -> (String, Vec<String>) {
+    let limits = FrontendLimits {
+        max_embed_size,
+        ..Default::default()
+    };
+
+    preprocess_with_limits(src, limits, |builder| {
+        builder
+            .include_dirs(vec!["res".into()])
+            .resource_loader(MemoryResources(vec![
+                ("res/data.bin", &[1, 2, 255]),
+                ("res/empty.bin", &[]),
+            ]));
+    })
+}
+
+#[test]
+fn embed() {
+    let check_embed = |src, expected| {
+        let (output, diags) = preprocess_embed(src, DEFAULT_MAX_EMBED_SIZE);
+        assert_eq!(output, expected);
+        assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+    };
+
+    check_embed("#embed \"data.bin\"", "1 , 2 , 255");
+    check_embed(
+        "int x[] = {\n#embed <data.bin> limit(2) prefix(0,) suffix(, 9)\n};",
+        "int x [ ] = {\n0 , 1 , 2 , 9\n} ;",
+    );
+    check_embed(
+        "#define RES \"data.bin\"\n#define N 1\n#embed RES __limit__(N + 1)",
+        "1 , 2",
+    );
+    check_embed("#define RES <data.bin>\n#embed RES", "1 , 2 , 255");
+    check_embed(
+        "#embed \"empty.bin\" prefix(x) if_empty(42)\n#embed \"data.bin\" limit(0) if_empty(0)",
+        "42\n0",
+    );
+    check_embed("#embed \"empty.bin\" suffix(x)\ny", "y");
+}
+
+#[test]
+fn invalid_embeds() {
+    let check_embed_diags = |src, max_embed_size, expected, expected_diags: &[&str]| {
+        let (output, diags) = preprocess_embed(src, max_embed_size);
+        assert_eq!(output, expected);
+        assert_eq!(diags, expected_diags);
+    };
+
+    check_embed_diags(
+        "#embed \"data.bin\" foo(1)\n#embed \"data.bin\" limit(1) limit(2)\nx",
+        DEFAULT_MAX_EMBED_SIZE,
+        "x",
+        &[
+            "error: unknown embed parameter 'foo'",
+            "error: duplicate embed parameter 'limit'",
+        ],
+    );
+    check_embed_diags(
+        "#embed \"data.bin\" limit(-1)\n#embed \"data.bin\" prefix\n#embed \"data.bin\" suffix(()\nx",
+        DEFAULT_MAX_EMBED_SIZE,
+        "x",
+        &[
+            "error: the 'limit' parameter must not be negative",
+            "error: expected '(' after embed parameter 'prefix'",
+            "error: expected a ')'",
+        ],
+    );
+    check_embed_diags(
+        "#embed data.bin\n#embed \"data.bin\" 5\nx",
+        DEFAULT_MAX_EMBED_SIZE,
+        "x",
+        &[
+            "error: expected \"filename\" or <filename>",
+            "error: expected an embed parameter",
+        ],
+    );
+    check_embed_diags(
+        "#embed \"data.bin\"\n#embed \"data.bin\" limit(2)",
+        2,
+        "1 , 2",
+        &["error: embedded resource 'data.bin' is larger than the maximum of 2 bytes"],
+    );
+    check_embed_diags(
+        "#embed \"missing.bin\"\nx",
+        DEFAULT_MAX_EMBED_SIZE,
+        "",
+        &["fatal: embedded resource 'missing.bin' not found"],
+    );
+}
+
+/// Runs `action` on a file containing `src`, applying the returned edits.
+fn refactor(
+    src: &str,
+    action: impl FnOnce(&mut LexCtx<'_, '_>, SourceId) -> Option<Vec<RenderedSuggestion>>,
+) -> (Option<String>, Vec<String>) {
+    let diags = RefCell::new(Vec::new());
+    let mut manager = DiagManager::new(RecordingSink(&diags), None);
+    let mut interner = Interner::new();
+    let mut smap = SourceMap::new();
+
+    let main_id = smap
+        .create_file(FileName::synth("test.c"), FileContents::new(src), None)
+        .unwrap();
+
+    let mut ctx = LexCtx::new(&mut interner, &mut manager, &mut smap);
+    let output = action(&mut ctx, main_id).map(|edits| apply_edits(ctx.smap, main_id, &edits));
+
+    drop(manager);
+    (output, diags.into_inner())
+}
+
+fn extract(src: &str, name: &str) -> (Option<String>, Option<String>, Vec<String>) {
+    let mut flag = None;
+    let (output, diags) = refactor(src, |ctx, main_id| {
+        let mut pp = PreprocessorBuilder::new(ctx, main_id).build();
+        let extracted = extract_define(ctx, &mut pp, main_id, name).unwrap()?;
+        flag = Some(extracted.flag);
+        Some(vec![extracted.edit])
+    });
+    (flag, output, diags)
+}
+
+fn inline(src: &str, name: &str) -> (Option<String>, Vec<String>) {
+    refactor(src, |ctx, main_id| {
+        let mut pp = PreprocessorBuilder::new(ctx, main_id).build();
+        inline_macro(ctx, &mut pp, main_id, name).unwrap()
+    })
+}
+
+#[test]
+fn extract_define_flag() {
+    let check_extract = |src, name, expected_flag, expected_src| {
+        let (flag, output, diags) = extract(src, name);
+        assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+        assert_eq!(flag.as_deref(), Some(expected_flag));
+        assert_eq!(output.as_deref(), Some(expected_src));
+    };
+
+    check_extract("#define A 5\nA;\n", "A", "-DA=5", "A;\n");
+    check_extract("int x;\n#define A\nA", "A", "-DA=", "int x;\nA");
+    check_extract(
+        "# define F(a, b)  ((a) /* sum */+ \\\n b)\nF(1, 2)",
+        "F",
+        "-DF(a, b)=((a) + b)",
+        "F(1, 2)",
+    );
+}
+
+#[test]
+fn extract_define_errors() {
+    let check_error = |src, name, expected_diags: &[&str]| {
+        let (flag, output, diags) = extract(src, name);
+        assert_eq!(flag, None);
+        assert_eq!(output, None);
+        assert_eq!(diags, expected_diags);
+    };
+
+    check_error(
+        "#define A 1\nB",
+        "B",
+        &["error: macro 'B' is not defined in this file"],
+    );
+    check_error(
+        "#define A 1\n#undef A\n#define A 2",
+        "A",
+        &["error: macro 'A' is defined more than once"],
+    );
+    check_error(
+        "#ifndef A\n#define A 1\n#endif\nA",
+        "A",
+        &["error: 'A' is used before its definition"],
+    );
+    check_error(
+        "#define A 1\nA\n#undef A\nA",
+        "A",
+        &["error: macro 'A' is undefined after its definition"],
+    );
+    check_error(
+        "#if 0\n#define A 1\n#endif\n",
+        "A",
+        &["error: definition of 'A' is not in effect at the end of the translation unit"],
+    );
+}
+
+#[test]
+fn inline_object_macro() {
+    let (output, diags) = inline(
+        "#define N 4\nint a[N];\n-N;\nint b = N+1, c = N-N;\n#undef N\n",
+        "N",
+    );
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+    assert_eq!(
+        output.as_deref(),
+        Some("int a[4];\n-4;\nint b = 4+1, c = 4-4;\n")
+    );
+
+    let (output, diags) = inline("#define N -1\nint x = -N;\n", "N");
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+    assert_eq!(output.as_deref(), Some("int x = - -1;\n"));
+}
+
+#[test]
+fn inline_macro_nested() {
+    let (output, diags) = inline(
+        "#define M 2\n#define N (M * 3)\n#define F(x) x + N\nN; F(N);\n",
+        "N",
+    );
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+    assert_eq!(
+        output.as_deref(),
+        Some("#define M 2\n#define F(x) x + (M * 3)\n(M * 3); F((M * 3));\n")
+    );
+}
+
+#[test]
+fn inline_macro_untraceable_uses() {
+    let (output, diags) = inline(
+        "N;\n#define N 1\n#ifdef N\nN\n#endif\n#define S(x) #x\nS(N)\n",
+        "N",
+    );
+    assert_eq!(output, None);
+    assert_eq!(
+        diags,
+        [
+            "error: cannot inline this use of 'N'",
+            "error: cannot inline this use of 'N'",
+            "error: cannot inline this use of 'N'",
+        ]
+    );
+
+    let (output, diags) = inline("#define F(x) x\nF(1)", "F");
+    assert_eq!(output, None);
+    assert_eq!(diags, ["error: only object-like macros can be inlined"]);
+}
+
+/// Preprocesses `src` with expansion tracing enabled, returning a description of every recorded
+/// step.
+fn trace(src: &str) -> Vec<String> {
+    let mut steps = Vec::new();
+
+    let (_, diags) = refactor(src, |ctx, main_id| {
+        let mut pp = PreprocessorBuilder::new(ctx, main_id)
+            .trace_expansions(true)
+            .build();
+        while pp.next_pp(ctx).unwrap().data() != TokenKind::Eof {}
+
+        let ctx = &*ctx;
+        let spell = |toks: &[PpToken]| toks.iter().map(|tok| tok.tok.display(ctx)).join(" ");
+
+        for step in pp.expansion_trace().unwrap().steps() {
+            let mut desc = ctx.interner[step.name_tok.data()].to_owned();
+
+            if let Some(parent) = step.parent {
+                desc += &format!(" in {}:{:?}", parent.step, parent.stage);
+            }
+
+            for arg in &step.args {
+                desc += &format!(" {}=[{}]", &ctx.interner[arg.param], spell(&arg.tokens));
+                if let Some(pre_expanded) = &arg.pre_expanded {
+                    desc += &format!("->[{}]", spell(pre_expanded));
+                }
+            }
+
+            desc += &format!(" => [{}]", spell(&step.replacement));
+            if step.parent.is_none() {
+                desc += &format!(" = [{}]", spell(&step.result));
+            }
+
+            steps.push(desc);
+        }
+
+        None
+    });
+
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+    steps
+}
+
+#[test]
+fn trace_object_macros() {
+    assert_eq!(
+        trace("#define A B + 1\n#define B 2\n#define E\nA E;\n"),
+        [
+            "A => [B + 1] = [2 + 1]",
+            "B in 0:Rescan => [2]",
+            "E => [] = []",
+        ]
+    );
+}
+
+#[test]
+fn trace_function_macros() {
+    assert_eq!(
+        trace(
+            "#define ONE 1\n\
+             #define INC(x) (x + ONE)\n\
+             #define TWICE(f, x) f(f(x))\n\
+             TWICE(INC, ONE)\n"
+        ),
+        [
+            "TWICE f=[INC]->[INC] x=[ONE]->[1] => [INC ( INC ( 1 ) )] = [( ( 1 + 1 ) + 1 )]",
+            "ONE in 0:ArgPreExpansion(1) => [1]",
+            "INC in 0:Rescan x=[INC ( 1 )]->[( 1 + 1 )] => [( ( 1 + 1 ) + ONE )]",
+            "INC in 2:ArgPreExpansion(0) x=[1]->[1] => [( 1 + ONE )]",
+            "ONE in 3:Rescan => [1]",
+            "ONE in 2:Rescan => [1]",
+        ]
+    );
+}
+
+#[test]
+fn trace_stringized_args_not_pre_expanded() {
+    assert_eq!(
+        trace("#define A 1\n#define S(x) #x\nS(A)\n"),
+        [r#"S x=[A] => ["A"] = ["A"]"#]
+    );
+}
+
+#[test]
+fn trace_disabled_by_default() {
+    let (_, diags) = refactor("#define A 1\nA\n", |ctx, main_id| {
+        let mut pp = PreprocessorBuilder::new(ctx, main_id).build();
+        while pp.next_pp(ctx).unwrap().data() != TokenKind::Eof {}
+        assert!(pp.expansion_trace().is_none());
+        None
+    });
+    assert!(diags.is_empty());
+}
+
+fn macro_defs(
+    src: &str,
+    configure: impl FnOnce(&mut PreprocessorBuilder<'_, '_, '_>),
+) -> Vec<String> {
+    let mut defs = Vec::new();
+
+    let (_, diags) = refactor(src, |ctx, main_id| {
+        let mut builder = PreprocessorBuilder::new(ctx, main_id);
+        configure(&mut builder);
+        let mut pp = builder.build();
+        while pp.next_pp(ctx).unwrap().data() != TokenKind::Eof {}
+
+        defs = pp
+            .macro_defs()
+            .filter(|def| def.origin != MacroOrigin::Builtin)
+            .map(|def| def.display(ctx).to_string())
+            .collect();
+        None
+    });
+
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+    defs
+}
+
+#[test]
+fn list_macro_defs() {
+    let src =
+        "#define B 1 +  2\n#define A\n#define F(x, y) x##y  (x)\n#define V(...) f(__VA_ARGS__)\n\
+               #define W(a, ...) a\n#define G() g\n#define B 1 + 2\n#undef A\n#define A 3\n";
+
+    assert_eq!(
+        macro_defs(src, |builder| {
+            builder.define("CMD=x").define("GONE").undef("GONE");
+        }),
+        [
+            "#define CMD x",
+            "#define B 1 + 2",
+            "#define F(x,y) x##y (x)",
+            "#define V(...) f(__VA_ARGS__)",
+            "#define W(a,...) a",
+            "#define G() g",
+            "#define A 3",
+        ]
+    );
+}
+
+#[test]
+fn macro_introspection() {
+    let src = "#define A 1\n#define F(x) x + A\nF(A)\n#if A\n#endif\n#undef A\n#define A 2\nA\n";
+    let mut sites = Vec::new();
+
+    let (_, diags) = refactor(src, |ctx, main_id| {
+        let mut pp = PreprocessorBuilder::new(ctx, main_id)
+            .record_expansion_sites(true)
+            .build();
+        while pp.next_pp(ctx).unwrap().data() != TokenKind::Eof {}
+
+        let a = ctx.interner.get("A").unwrap();
+        assert!(pp.is_macro_defined(a));
+        assert_eq!(
+            pp.macro_def(a).unwrap().display(ctx).to_string(),
+            "#define A 2"
+        );
+        assert!(!pp.is_macro_defined(ctx.interner.get("x").unwrap()));
+
+        sites = pp
+            .expansion_sites()
+            .unwrap()
+            .iter()
+            .map(|site| {
+                let def_line = ctx
+                    .smap
+                    .get_interpreted_range(site.def_range)
+                    .start_linecol()
+                    .line;
+                format!("{} -> {}", &ctx.interner[site.name_tok.data], def_line + 1)
+            })
+            .collect();
+        None
+    });
+
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+    assert_eq!(sites, ["F -> 2", "A -> 1", "A -> 1", "A -> 1", "A -> 7"]);
+}
+
+#[test]
+fn skipped_regions() {
+    let src = "#if 0\na\n#elif 1\nb\n#else\nc\n#endif\n#ifdef X\nd\n#if 1\ne\n#endif\n#endif\nf\n";
+    let mut regions = Vec::new();
+
+    let (_, diags) = refactor(src, |ctx, main_id| {
+        let mut pp = PreprocessorBuilder::new(ctx, main_id).build();
+        while pp.next_pp(ctx).unwrap().data() != TokenKind::Eof {}
+
+        regions = pp
+            .skipped_regions()
+            .iter()
+            .map(|&range| ctx.smap.get_spelling(range).to_owned())
+            .collect();
+        None
+    });
+
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+    assert_eq!(regions, ["a\n", "c\n", "d\n#if 1\ne\n#endif\n"]);
+}
+
+#[test]
+fn dependencies() {
+    let dir = std::env::temp_dir().join(format!("mrcc-pp-dependencies-{}", std::process::id()));
+    fs::create_dir_all(dir.join("inc")).unwrap();
+    fs::write(dir.join("a.h"), "#pragma once\n#include <b.h>\n").unwrap();
+    fs::write(dir.join("inc/b.h"), "#embed \"data.bin\"\n").unwrap();
+    fs::write(dir.join("inc/data.bin"), "1").unwrap();
+
+    let src = "#include \"a.h\"\n#include \"a.h\"\n#include \"inc/b.h\"\n";
+    let mut deps = Vec::new();
+
+    let (_, diags) = refactor(src, |ctx, main_id| {
+        let mut pp = PreprocessorBuilder::new(ctx, main_id)
+            .parent_dir(dir.clone())
+            .include_dirs(vec![dir.join("inc")])
+            .build();
+        while pp.next_pp(ctx).unwrap().data() != TokenKind::Eof {}
+
+        deps = pp
+            .dependencies()
+            .map(|path| path.strip_prefix(&dir).unwrap().to_owned())
+            .collect();
+        None
+    });
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+    assert_eq!(
+        deps,
+        [
+            Path::new("a.h"),
+            Path::new("inc/b.h"),
+            Path::new("inc/data.bin")
+        ]
+    );
+}
+
+#[test]
+fn file_cache_sharing() {
+    let dir = std::env::temp_dir().join(format!("mrcc-pp-file-cache-{}", std::process::id()));
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("a.h"), "a\n").unwrap();
+
+    let src = "#include \"a.h\"\n#include \"a.h\"\n#include \"sub/../a.h\"\n";
+    let mut stats = None;
+
+    let (_, diags) = refactor(src, |ctx, main_id| {
+        let mut pp = PreprocessorBuilder::new(ctx, main_id)
+            .parent_dir(dir.clone())
+            .build();
+        while pp.next_pp(ctx).unwrap().data() != TokenKind::Eof {}
+
+        stats = Some(pp.file_cache_stats());
+        let mut cache = pp.into_file_cache();
+        let direct = cache.load(&dir.join("a.h")).unwrap();
+        let aliased = cache.load(&dir.join("sub/../a.h")).unwrap();
+        assert!(Lrc::ptr_eq(&direct.contents, &aliased.contents));
+        None
+    });
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+    assert_eq!(
+        stats,
+        Some(FileCacheStats {
+            hits: 1,
+            aliases: 1,
+            reads: 1,
+        })
+    );
+}
+
+#[test]
+fn file_cache_revalidate() {
+    let dir = std::env::temp_dir().join(format!("mrcc-pp-revalidate-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.h"), "a\n").unwrap();
+    fs::write(dir.join("b.h"), "b\n").unwrap();
+
+    let mut cache = FileCache::new();
+    cache.load(&dir.join("a.h")).unwrap();
+    cache.load(&dir.join("b.h")).unwrap();
+    assert_eq!(cache.revalidate(), 0);
+
+    fs::write(dir.join("a.h"), "changed\n").unwrap();
+    assert_eq!(cache.revalidate(), 1);
+    assert_eq!(cache.len(), 1);
+
+    let reloaded = cache.load(&dir.join("a.h")).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(reloaded.contents.src(), "changed\n");
+    assert_eq!(cache.stats().reads, 3);
+}
+
+#[test]
+fn in_memory_file_system() {
+    let mut fs = InMemoryFileSystem::new();
+    fs.add_file("/src/a.h", "#include <b.h>\n#embed \"data.bin\"\n")
+        .add_file("/inc/b.h", "b\n#include \"./../src/./c.h\"\n")
+        .add_file("/src/c.h", "c\n")
+        .add_file("/src/data.bin", "7");
+
+    let src = "#include \"a.h\"\n#include \"c.h\"\n#include \"missing.h\"\n";
+    let (output, diags) = preprocess_with(src, |builder| {
+        builder
+            .parent_dir("/src".into())
+            .include_dirs(vec!["/inc".into()])
+            .file_cache(FileCache::with_file_system(fs.clone()))
+            .resource_loader(fs.clone());
+    });
+
+    assert_eq!(output, "b\nc\n55\nc");
+    assert_eq!(diags, ["error: include 'missing.h' not found"]);
+}
+
+#[test]
+fn file_from_reader() {
+    let file = File::from_reader("#include \"a.h\"\n".as_bytes(), Some("".into())).unwrap();
+    assert_eq!(file.contents.src(), "#include \"a.h\"\n");
+    assert_eq!(file.path, None);
+
+    let mut fs = InMemoryFileSystem::new();
+    fs.add_file("a.h", "a\n");
+    let (output, diags) = preprocess_with(file.contents.src(), |builder| {
+        builder
+            .parent_dir(file.parent_dir.clone().unwrap())
+            .file_cache(FileCache::with_file_system(fs.clone()));
+    });
+    assert_eq!(output, "a");
+    assert!(diags.is_empty());
+
+    let file = File::from_reader(&b"\xef\xbb\xbfx\xff"[..], None).unwrap();
+    assert_eq!(file.contents.src(), "x\u{fffd}");
+}
+
+#[test]
+fn source_encoding() {
+    let mut fs = InMemoryFileSystem::new();
+    fs.add_file("bom.h", &b"\xef\xbb\xbfbom\n"[..])
+        .add_file("utf16.h", &b"\xff\xfeu\x001\x006\x00\n\x00"[..])
+        .add_file("invalid.h", &b"a\n\"\xc3\"\n"[..]);
+
+    let src = "#include \"bom.h\"\n#include \"utf16.h\"\n#include \"invalid.h\"\n";
+    let (output, diags) = preprocess_with(src, |builder| {
+        builder
+            .parent_dir("".into())
+            .file_cache(FileCache::with_file_system(fs.clone()));
+    });
+
+    assert_eq!(output, "bom\nu16\na\n\"\u{fffd}\"");
+    assert_eq!(
+        diags,
+        ["warning: invalid UTF-8 sequence at byte offset 3, replaced with U+FFFD"]
+    );
+}
+
+#[test]
+fn include_next() {
+    let dir = std::env::temp_dir().join(format!("mrcc-pp-include-next-{}", std::process::id()));
+    for (path, contents) in &[
+        ("a/x.h", "a\n#include_next <x.h>\n"),
+        ("b/x.h", "b\n#include_next \"x.h\"\n"),
+        ("sys/x.h", "sys\n"),
+        ("a/y.h", "user\n"),
+        ("sys/y.h", "system\n"),
+    ] {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    let (output, diags) = preprocess_with("#include <x.h>\n#include <y.h>\n", |builder| {
+        builder
+            .include_dirs(vec![dir.join("a"), dir.join("b")])
+            .system_include_dirs(vec![dir.join("sys")]);
+    });
+    assert_eq!(output, "a\nb\nsys\nuser");
+    assert!(diags.is_empty(), "unexpected diagnostics: {:?}", diags);
+
+    // Directories listed as both user and system directories are searched after the other user
+    // directories.
+    let (output, _) = preprocess_with("#include <y.h>\n", |builder| {
+        builder
+            .include_dirs(vec![dir.join("sys"), dir.join("a")])
+            .system_include_dirs(vec![dir.join("sys")]);
+    });
+    assert_eq!(output, "user");
+
+    let (output, diags) = preprocess_with("#include_next <y.h>\n", |builder| {
+        builder.system_include_dirs(vec![dir.join("sys")]);
+    });
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(output, "system");
+    assert_eq!(diags, ["warning: '#include_next' in main file"]);
+}
+
+#[test]
+fn header_names() {
+    let check_include = |src, expected, expected_diags: &[&str]| {
+        let (output, diags) = preprocess_with(src, |builder| {
+            builder.include_resolver(GeneratedHeaders::new(&[("a.h", "a\n"), ("a\\b.h", "b\n")]));
+        });
+        assert_eq!(output, expected);
+        assert_eq!(diags, expected_diags);
+    };
+
+    // Header names are lexed as a whole, without regard for comments, escapes or macros.
+    check_include(
+        "#define a b\n#include <gen//*a*/a.h>\nx",
+        "x",
+        &["error: include 'gen//*a*/a.h' not found"],
+    );
+    check_include("#include <gen/a\\b.h>\nx", "b\nx", &[]);
+    check_include(
+        "#include <gen/a.h\nx",
+        "a\nx",
+        &["error: unterminated header name"],
+    );
+    check_include(
+        "#include \"gen/a.h\nx",
+        "x",
+        &[
+            "error: unterminated header name",
+            "error: include 'gen/a.h' not found",
+        ],
+    );
+    check_include(
+        "#if __has_include(<gen/a.h) \n#endif\nx",
+        "x",
+        &["error: unterminated header name", "error: expected a ')'"],
+    );
+    check_include("#if __has_include( <gen/a.h> )\na\n#endif", "a", &[]);
+}
+
+#[test]
+fn computed_includes() {
+    let check_include = |src, expected, expected_diags: &[&str]| {
+        let (output, diags) = preprocess_with(src, |builder| {
+            builder.include_resolver(GeneratedHeaders::new(&[("a.h", "a\n"), ("b.h", "b\n")]));
+        });
+        assert_eq!(output, expected);
+        assert_eq!(diags, expected_diags);
+    };
+
+    check_include("#define H <gen/a.h>\n#include H\nx", "a\nx", &[]);
+    check_include("#define H(x) <gen/x.h>\n#include H(b)", "b", &[]);
+    check_include("#define E\n#include E <gen/a.h> E", "a", &[]);
+    check_include(
+        "#define S(x) #x\n#include S(gen/a.h)",
+        "",
+        &["error: include 'gen/a.h' not found"],
+    );
+    check_include(
+        "#define A gen\n#include <A/a.h>",
+        "",
+        &["error: include 'A/a.h' not found"],
+    );
+    check_include(
+        "#define H <gen/a.h> b\n#include H\nx",
+        "a\nx",
+        &["warning: extra tokens after preprocessing directive"],
+    );
+    check_include(
+        "#define E\n#include E\n#define H <gen/a.h\n#include H\nx",
+        "x",
+        &[
+            "error: expected \"filename\" or <filename>",
+            "error: expected a '>'",
+        ],
+    );
+    check_include(
+        "#define H \"a\u{e9}\n#include H\nx",
+        "x",
+        &[
+            "error: unterminated string literal",
+            "error: expected \"filename\" or <filename>",
+        ],
+    );
+}
+
+#[test]
+fn trigraphs() {
+    let src = "??=define ARR(x) x??(0??)\nARR(a) \"??/\"\"";
+
+    let (output, diags) = preprocess_with(src, |builder| {
+        builder.lexer_config(LexerConfig {
+            trigraphs: true,
+            ..LexerConfig::default()
+        });
+    });
+    assert_eq!(output, r#"a [ 0 ] "\"""#);
+    assert_eq!(
+        diags,
+        [
+            "warning: trigraph converted to '#' character",
+            "warning: trigraph converted to '[' character",
+            "warning: trigraph converted to ']' character",
+            "warning: trigraph converted to '\\' character",
+        ]
+    );
+
+    let (output, diags) = preprocess_with("??=x", |_| {});
+    assert_eq!(output, "? ? = x");
+    assert!(diags.is_empty());
+}
+
+#[test]
+fn lexer_config() {
+    let src = "a <: 0 :> // b\nc";
+
+    let (output, _) = preprocess_with(src, |_| {});
+    assert_eq!(output, "a <: 0 :>\nc");
+
+    let (output, _) = preprocess_with(src, |builder| {
+        builder.lexer_config(LexerConfig::c90());
+    });
+    assert_eq!(output, "a < : 0 : > / / b\nc");
+}
+
+#[test]
+fn lang_options() {
+    let c89 = LangOptions {
+        standard: LangStandard::C89,
+        ..LangOptions::default()
+    };
+    let pedantic_c89 = LangOptions {
+        pedantic: true,
+        ..c89
+    };
+    let src = "#define F(x, ...) x\nF(, 1) __STDC_VERSION__ // c\n#warning w\n";
+
+    let (output, diags) = preprocess_with(src, |builder| {
+        builder.lang_options(c89);
+    });
+    assert_eq!(output, "__STDC_VERSION__ / / c");
+    assert_eq!(diags, ["warning: w"]);
+
+    let (output, diags) = preprocess_with(src, |builder| {
+        builder.lang_options(LangOptions {
+            gnu_extensions: true,
+            ..c89
+        });
+    });
+    assert_eq!(output, "__STDC_VERSION__");
+    assert_eq!(diags, ["warning: w"]);
+
+    let (_, diags) = preprocess_with(src, |builder| {
+        builder.lang_options(pedantic_c89);
+    });
+    assert_eq!(
+        diags,
+        [
+            "warning: variadic macros are a C99 feature",
+            "warning: empty macro arguments are a C99 feature",
+            "warning: '#warning' is a C23 extension",
+            "warning: w",
+        ]
+    );
+
+    let (_, diags) = preprocess_with(src, |builder| {
+        builder.lang_options(c89).pedantic_errors(true);
+    });
+    assert_eq!(diags[0], "error: variadic macros are a C99 feature");
+
+    let (output, diags) = preprocess_with(src, |builder| {
+        builder.lang_options(LangOptions {
+            standard: LangStandard::C17,
+            ..pedantic_c89
+        });
+    });
+    assert_eq!(output, "201710L");
+    assert_eq!(
+        diags,
+        ["warning: '#warning' is a C23 extension", "warning: w"]
+    );
+}
+
+#[test]
+fn keep_comments() {
+    let src = "/* a */ x // b\n\
+               /* h */ #define F(p) p /* c */\n\
+               F(/* d */ y) /* e */\n\
+               #if 0 /* f */\n\
+               /* g */\n\
+               #endif\n\
+               z";
+
+    let (output, diags) = preprocess_with(src, |builder| {
+        builder.keep_comments(true);
+    });
+    assert_eq!(output, "/* a */ x // b\n/* h */\ny /* e */\nz");
+    assert!(diags.is_empty());
+
+    let (output, _) = preprocess_with(src, |_| {});
+    assert_eq!(output, "x\ny\nz");
+}
+
+/// Preprocesses `src` with trivia capture and trigraphs enabled, concatenating the tokens as
+/// displayed with their source spellings.
+fn preprocess_spelled(src: &str, spelling: SourceSpelling) -> String {
+    let diags = RefCell::new(Vec::new());
+    let mut manager = DiagManager::new(RecordingSink(&diags), None);
+    let mut interner = Interner::new();
+    let mut smap = SourceMap::new();
+    let main_id = smap
+        .create_file(FileName::synth("test.c"), FileContents::new(src), None)
+        .unwrap();
+    let mut ctx = LexCtx::new(&mut interner, &mut manager, &mut smap);
+
+    let mut pp = PreprocessorBuilder::new(&mut ctx, main_id)
+        .capture_trivia(true)
+        .lexer_config(LexerConfig {
+            trigraphs: true,
+            ..LexerConfig::default()
+        })
+        .build();
+
+    let mut output = String::new();
+    loop {
+        let ppt = pp.next_pp(&mut ctx).unwrap();
+        if ppt.data() == TokenKind::Eof {
+            break;
+        }
+        output.push_str(&ppt.display_spelled(&ctx, spelling).to_string());
+    }
+    output
+}
+
+#[test]
+fn display_spelled() {
+    let src =
+        "#define S(x) #x\n#define M caf\\u00e9\nf\\\noo  /* c */ <:??)\tM S(a) __LINE__\n  \\u00e9";
+
+    assert_eq!(
+        preprocess_spelled(src, SourceSpelling::Verbatim),
+        "\nf\\\noo    <:??)\tcaf\\u00e9 \"a\" 4\n  \\u00e9"
+    );
+    assert_eq!(
+        preprocess_spelled(src, SourceSpelling::Cleaned),
+        "\nfoo    <:??)\tcaf\\u00e9 \"a\" 4\n  \\u00e9"
+    );
+    assert_eq!(
+        preprocess_spelled("a \\\n b", SourceSpelling::Verbatim),
+        "a \\\n b"
+    );
+    assert_eq!(
+        preprocess_spelled("a \\\n b", SourceSpelling::Cleaned),
+        "a  b"
+    );
+}
+
+#[test]
+fn capture_trivia() {
+    let src = "#define M m\n\ta /* x */ b \\\n c\n\n// d\n#if 0\nskipped\n#endif\nM  e";
+
+    let diags = RefCell::new(Vec::new());
+    let mut manager = DiagManager::new(RecordingSink(&diags), None);
+    let mut interner = Interner::new();
+    let mut smap = SourceMap::new();
+    let main_id = smap
+        .create_file(FileName::synth("test.c"), FileContents::new(src), None)
+        .unwrap();
+    let mut ctx = LexCtx::new(&mut interner, &mut manager, &mut smap);
+
+    let mut pp = PreprocessorBuilder::new(&mut ctx, main_id)
+        .capture_trivia(true)
+        .build();
+
+    let mut tokens = Vec::new();
+    loop {
+        let ppt = pp.next_pp(&mut ctx).unwrap();
+        if ppt.data() == TokenKind::Eof {
+            break;
+        }
+
+        let trivia = ppt
+            .leading_trivia_range
+            .map(|range| ctx.smap.get_spelling(range).to_owned());
+        tokens.push((ppt.tok.display(&ctx).to_string(), trivia));
+    }
+
+    let expected = [
+        ("a", Some("\n\t")),
+        ("b", Some(" /* x */ ")),
+        ("c", Some(" \\\n ")),
+        ("m", Some("\n")),
+        ("e", Some("  ")),
+    ];
+    assert_eq!(
+        tokens,
+        expected.map(|(tok, trivia)| (tok.to_owned(), trivia.map(str::to_owned)))
+    );
+}
@@ -1,6 +1,7 @@
 use std::fmt;
 
-use lex::{LexCtx, PunctKind, Token, TokenKind};
+use lex::raw::{RawTokenKind, Tokenizer};
+use lex::{LexCtx, PunctKind, SourceSpelling, Spacing, Token, TokenKind};
 use source::SourceRange;
 
 /// A token with auxiliary data relevent to the preprocessor.
@@ -15,6 +16,14 @@ pub struct PpToken<D = TokenKind> {
     /// Indicates whether this token was separated from the previous token or newline by any
     /// whitespace or comments.
     pub leading_trivia: bool,
+
+    /// The source range of the whitespace, comments and newlines directly preceding this token in
+    /// its file, if trivia capture has been enabled with
+    /// [`PreprocessorBuilder::capture_trivia()`](crate::PreprocessorBuilder::capture_trivia()).
+    ///
+    /// This is `None` if there is no such trivia, and for tokens produced by macro expansion
+    /// other than the first, which inherits the trivia of the replaced token.
+    pub leading_trivia_range: Option<SourceRange>,
 }
 
 impl<D: Copy> PpToken<D> {
@@ -34,6 +43,7 @@ impl<D: Copy> PpToken<D> {
             tok: self.tok.map(f),
             line_start: self.line_start,
             leading_trivia: self.leading_trivia,
+            leading_trivia_range: self.leading_trivia_range,
         }
     }
 
@@ -51,6 +61,37 @@ impl PpToken {
         Display { ppt: self, ctx }
     }
 
+    /// Returns an object that implements `fmt::Display` for printing the token as it was written
+    /// in the source, as by [`Token::display_spelled()`].
+    ///
+    /// If the leading trivia of the token was captured (see
+    /// [`leading_trivia_range`](Self::leading_trivia_range)), it is reproduced with every comment
+    /// replaced by a single space, as per translation phase 3. Otherwise, leading trivia is
+    /// displayed as a single space character, as by [`Self::display()`].
+    pub fn display_spelled<'t, 'a, 'h>(
+        &'t self,
+        ctx: &'t LexCtx<'a, 'h>,
+        spelling: SourceSpelling,
+    ) -> DisplaySpelled<'t, 'a, 'h> {
+        DisplaySpelled {
+            ppt: self,
+            ctx,
+            spelling,
+        }
+    }
+
+    /// Returns the whitespace separating this token from the previous one, for use with
+    /// [`TokenWriter`](lex::TokenWriter).
+    pub fn spacing(&self) -> Spacing {
+        if self.line_start {
+            Spacing::Newline
+        } else if self.leading_trivia {
+            Spacing::Space
+        } else {
+            Spacing::Joint
+        }
+    }
+
     /// Returns whether this is a directive-start marker (a `#` at the start of a line).
     pub(crate) fn is_directive_start(&self) -> bool {
         self.line_start && self.data() == TokenKind::Punct(PunctKind::Hash)
@@ -71,3 +112,35 @@ impl fmt::Display for Display<'_, '_, '_> {
         write!(f, "{}", ppt.tok.display(self.ctx))
     }
 }
+
+pub struct DisplaySpelled<'t, 'a, 'h> {
+    ppt: &'t PpToken,
+    ctx: &'t LexCtx<'a, 'h>,
+    spelling: SourceSpelling,
+}
+
+impl fmt::Display for DisplaySpelled<'_, '_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ppt = self.ppt;
+
+        match ppt.leading_trivia_range {
+            Some(range) => {
+                let mut tokenizer = Tokenizer::new(self.ctx.smap.get_spelling(range));
+                loop {
+                    let raw = tokenizer.next_token();
+                    match raw.kind {
+                        RawTokenKind::Eof => break,
+                        RawTokenKind::LineComment | RawTokenKind::BlockComment { .. } => {
+                            write!(f, " ")?
+                        }
+                        _ => write!(f, "{}", self.spelling.apply(raw.content.str))?,
+                    }
+                }
+            }
+            None if ppt.leading_trivia => write!(f, " ")?,
+            None => {}
+        }
+
+        write!(f, "{}", ppt.tok.display_spelled(self.ctx, self.spelling))
+    }
+}
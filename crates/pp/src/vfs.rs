@@ -0,0 +1,216 @@
+//! File system abstraction used when loading included files.
+
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use rustc_hash::FxHashMap;
+
+use source::smap::FileContents;
+use source::sync::Lrc;
+
+use crate::ResourceLoader;
+
+/// The file system from which a [`FileCache`](crate::FileCache) loads files.
+///
+/// Replacing the file system allows code to be preprocessed without touching the disk, as is
+/// useful in editors and build systems that have their own view of the files involved.
+pub trait FileSystem {
+    /// Reads the entire contents of the file at `path`.
+    ///
+    /// An error of kind [`io::ErrorKind::NotFound`] indicates that the file does not exist.
+    fn read_file(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Loads the source code in the file at `path`, decoding it as with [`FileContents::decode()`].
+    ///
+    /// The default implementation reads the file with [`read_file()`](Self::read_file()), without
+    /// copying its contents unless they need to be re-encoded.
+    fn load_contents(&self, path: &Path) -> io::Result<Lrc<FileContents>> {
+        Ok(FileContents::decode(self.read_file(path)?))
+    }
+
+    /// Checks whether a file exists at `path`.
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Returns a stamp identifying the current version of the file at `path`, or `None` if it does
+    /// not exist or its versions cannot be told apart.
+    ///
+    /// [`FileCache::revalidate()`](crate::FileCache::revalidate()) uses this to detect files that
+    /// have changed since they were loaded. The default implementation always returns `None`, so
+    /// that files are assumed never to change.
+    fn stamp(&self, _path: &Path) -> Option<FileStamp> {
+        None
+    }
+
+    /// Returns the canonical form of `path`, which is the same for all paths referring to the same
+    /// file.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+}
+
+/// Identifies a version of a file, so that changes to it can be detected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    /// The time at which the file was last modified, if available.
+    pub modified: Option<SystemTime>,
+    /// The length of the file, in bytes.
+    pub len: u64,
+}
+
+/// The size above which [`RealFileSystem`] memory-maps files instead of reading them.
+#[cfg(feature = "mmap")]
+const MMAP_THRESHOLD: u64 = 64 * 1024;
+
+/// A [`FileSystem`] accessing the real file system through [`std::fs`].
+///
+/// Files are read into memory by default. With the `mmap` feature, a file system that
+/// memory-maps large files instead can be created with [`with_mmap()`](Self::with_mmap()).
+#[derive(Default)]
+pub struct RealFileSystem {
+    #[cfg(feature = "mmap")]
+    mmap: bool,
+}
+
+impl RealFileSystem {
+    /// Creates a file system that reads files into memory.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a file system that memory-maps large files rather than reading them into memory.
+    ///
+    /// # Safety
+    ///
+    /// No file loaded through the returned file system may be modified (by this or any other
+    /// process) while its contents are alive, including while they are retained in a
+    /// [`FileCache`](crate::FileCache). Truncating a mapped file can otherwise crash the process,
+    /// and other modifications make source code change underneath references to it.
+    #[cfg(feature = "mmap")]
+    pub unsafe fn with_mmap() -> Self {
+        Self { mmap: true }
+    }
+}
+
+impl FileSystem for RealFileSystem {
+    fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    #[cfg(feature = "mmap")]
+    fn load_contents(&self, path: &Path) -> io::Result<Lrc<FileContents>> {
+        if !self.mmap {
+            return Ok(FileContents::decode(self.read_file(path)?));
+        }
+
+        let mut file = fs::File::open(path)?;
+        if file.metadata()?.len() < MMAP_THRESHOLD {
+            let mut bytes = Vec::new();
+            io::Read::read_to_end(&mut file, &mut bytes)?;
+            return Ok(FileContents::decode(bytes));
+        }
+
+        // SAFETY: The creator of the file system has promised that the file will not be modified,
+        // as required by `with_mmap()`.
+        match unsafe { FileContents::map_file(&file) } {
+            // Files that are not UTF-8 need to be decoded into a copy.
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                self.read_file(path).map(FileContents::decode)
+            }
+            res => res,
+        }
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn stamp(&self, path: &Path) -> Option<FileStamp> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(FileStamp {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        })
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+}
+
+/// A [`FileSystem`] whose files are held in memory.
+///
+/// Paths are compared after resolving any `.` and `..` components, without consulting the real
+/// file system. Relative paths are not resolved against any working directory, so a file added
+/// with a relative path can only be found through relative paths.
+///
+/// The file system can also be used as a [`ResourceLoader`] so that resources embedded with
+/// `#embed` are read from memory as well.
+#[derive(Default, Clone)]
+pub struct InMemoryFileSystem {
+    files: FxHashMap<PathBuf, Vec<u8>>,
+}
+
+impl InMemoryFileSystem {
+    /// Creates a new, empty file system.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file with the specified contents at `path`, replacing any existing file.
+    pub fn add_file(&mut self, path: impl AsRef<Path>, contents: impl Into<Vec<u8>>) -> &mut Self {
+        self.files
+            .insert(lexically_normalize(path.as_ref()), contents.into());
+        self
+    }
+
+    fn get(&self, path: &Path) -> io::Result<&[u8]> {
+        self.files
+            .get(&lexically_normalize(path))
+            .map(|contents| contents.as_slice())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "file not found"))
+    }
+}
+
+impl FileSystem for InMemoryFileSystem {
+    fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.get(path).map(|contents| contents.to_vec())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.get(path).is_ok()
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.get(path)?;
+        Ok(lexically_normalize(path))
+    }
+}
+
+impl ResourceLoader for InMemoryFileSystem {
+    fn load(&mut self, path: &Path, max_len: usize) -> io::Result<Vec<u8>> {
+        let contents = self.get(path)?;
+        Ok(contents[..contents.len().min(max_len)].to_vec())
+    }
+}
+
+/// Removes `.` components from `path` and resolves `..` components against the preceding
+/// component, without accessing the file system.
+fn lexically_normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                _ => normalized.push(".."),
+            },
+            _ => normalized.push(component),
+        }
+    }
+
+    normalized
+}
@@ -0,0 +1,83 @@
+//! Benchmarks for line number lookups in large files, as performed when reporting diagnostics.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+use source::smap::FileContents;
+use source::LocalOff;
+
+/// Builds a source file of roughly `size` bytes, with lines of varying length.
+fn make_src(size: usize) -> String {
+    let mut src = String::with_capacity(size + 100);
+    let mut i = 0;
+    while src.len() < size {
+        src.push_str(&"x".repeat(i % 80));
+        src.push_str(";\n");
+        i += 1;
+    }
+    src
+}
+
+/// Returns `count` pseudo-random offsets into a source of length `len`.
+fn make_offsets(len: usize, count: usize) -> Vec<LocalOff> {
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    (0..count)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            LocalOff::from((state % len as u64) as u32)
+        })
+        .collect()
+}
+
+fn bench_line_table(c: &mut Criterion) {
+    let mut group = c.benchmark_group("line_table");
+
+    for &mb in &[1, 8] {
+        let src = make_src(mb << 20);
+        let offs = make_offsets(src.len(), 10_000);
+
+        group.throughput(Throughput::Bytes(src.len() as u64));
+        group.bench_function(format!("load/{}MB", mb), |b| {
+            b.iter(|| FileContents::new(black_box(&src)))
+        });
+        group.bench_function(format!("first_lookup/{}MB", mb), |b| {
+            b.iter_batched(
+                || FileContents::new(&src),
+                |contents| contents.get_linecol(black_box(offs[0])),
+                BatchSize::LargeInput,
+            )
+        });
+
+        let contents = FileContents::new(&src);
+        contents.get_linecol(0.into());
+
+        group.throughput(Throughput::Elements(offs.len() as u64));
+        group.bench_function(format!("lookup/{}MB", mb), |b| {
+            b.iter(|| {
+                offs.iter()
+                    .map(|&off| contents.get_linecol(off))
+                    .collect::<Vec<_>>()
+            })
+        });
+
+        let mut sorted_offs = offs.clone();
+        sorted_offs.sort();
+        group.bench_function(format!("sorted_lookup/{}MB", mb), |b| {
+            b.iter(|| {
+                sorted_offs
+                    .iter()
+                    .map(|&off| contents.get_linecol(off))
+                    .collect::<Vec<_>>()
+            })
+        });
+        group.bench_function(format!("batched_sorted_lookup/{}MB", mb), |b| {
+            b.iter(|| contents.get_linecols(black_box(&sorted_offs)))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_line_table);
+criterion_main!(benches);
@@ -18,19 +18,31 @@
 //! passed to sinks registered with [`Manager::new()`]. They can also be created manually from raw
 //! diagnostics using [`render()`].
 
+use std::collections::HashSet;
 use std::fmt;
 
-use crate::SourceMap;
-use crate::{FragmentedSourceRange, SourcePos, SourceRange};
+use crate::{FragmentedSourceRange, FrontendLimits, SourceMap, SourcePos, SourceRange};
 
-pub use annotating_sink::AnnotatingSink;
-pub use render::render;
+use render::{EXPANSION_NOTE_MSG, SKIPPED_EXPANSIONS_MSG};
+
+pub use annotating_sink::{AnnotatingSink, ColorChoice};
+pub use id::DiagnosticId;
+pub use line_cap::LineCappingSink;
+pub use message::{Message, MessageArg};
+#[cfg(any(test, feature = "test-util"))]
+pub use recording_sink::RecordingSink;
+pub use render::{render, render_with_backtrace_limit};
 
 mod annotating_sink;
+mod id;
+mod line_cap;
+mod message;
+#[cfg(any(test, feature = "test-util"))]
+mod recording_sink;
 mod render;
 
 /// Diagnostic severity level.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Level {
     Note,
     Warning,
@@ -63,6 +75,36 @@ pub struct FatalErrorEmitted;
 
 pub type Result<T> = std::result::Result<T, FatalErrorEmitted>;
 
+/// Indicates how confident a suggestion is, so that tools can decide whether to apply it
+/// automatically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Applicability {
+    /// The suggestion is definitely what the user intended and can be applied mechanically.
+    #[default]
+    MachineApplicable,
+    /// The suggestion may be what the user intended, but should be reviewed before applying.
+    MaybeIncorrect,
+    /// The suggestion contains placeholders that the user needs to fill in.
+    HasPlaceholders,
+}
+
+impl Applicability {
+    /// Returns a string representation of the applicability, as used in machine-readable output.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Applicability::MachineApplicable => "machine-applicable",
+            Applicability::MaybeIncorrect => "maybe-incorrect",
+            Applicability::HasPlaceholders => "has-placeholders",
+        }
+    }
+}
+
+impl fmt::Display for Applicability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
 /// Generic suggestion type indicating that a range of code should be replaced with new code.
 ///
 /// Insertions can be modeled by using an empty replacement range at the desired position.
@@ -73,17 +115,26 @@ pub struct Suggestion<R> {
     pub replacement_range: R,
     /// The new text to insert at `replacement_range`.
     pub insert_text: String,
+    /// How confident this suggestion is.
+    pub applicability: Applicability,
 }
 
 impl<R> Suggestion<R> {
-    /// Creates a new suggestion with the specified parameters.
+    /// Creates a new machine-applicable suggestion with the specified parameters.
     pub fn new(replacement_range: impl Into<R>, insert_text: impl Into<String>) -> Self {
         Suggestion {
             replacement_range: replacement_range.into(),
             insert_text: insert_text.into(),
+            applicability: Applicability::MachineApplicable,
         }
     }
 
+    /// Sets the applicability of this suggestion, returning it for chaining.
+    pub fn with_applicability(mut self, applicability: Applicability) -> Self {
+        self.applicability = applicability;
+        self
+    }
+
     /// Creates a new suggestion indicating that `range` should be deleted.
     pub fn new_deletion(range: impl Into<R>) -> Self {
         Self::new(range, "")
@@ -131,29 +182,29 @@ pub type RenderedRanges = Ranges<SourceRange>;
 #[derive(Debug, Clone)]
 pub struct SubDiagnostic<R> {
     /// The message of this subdiagnostic.
-    pub msg: String,
+    pub msg: Message,
     /// The ranges attached to this subdiagnostic, if any.
     pub ranges: Option<Ranges<R>>,
-    /// The suggestion attached to this subdiagnostic, if any.
-    pub suggestion: Option<Suggestion<R>>,
+    /// The suggestions attached to this subdiagnostic, in the order they were added.
+    pub suggestions: Vec<Suggestion<R>>,
 }
 
 impl<R> SubDiagnostic<R> {
     /// Creates a new subdiagnostic with the specified message and primary range.
-    pub fn new(msg: impl Into<String>, primary_range: R) -> Self {
+    pub fn new(msg: impl Into<Message>, primary_range: R) -> Self {
         Self {
             msg: msg.into(),
             ranges: Some(Ranges::new(primary_range)),
-            suggestion: None,
+            suggestions: Vec::new(),
         }
     }
 
     /// Creates a new subdiagnostic without any attached location information.
-    pub fn new_anon(msg: impl Into<String>) -> Self {
+    pub fn new_anon(msg: impl Into<Message>) -> Self {
         Self {
             msg: msg.into(),
             ranges: None,
-            suggestion: None,
+            suggestions: Vec::new(),
         }
     }
 
@@ -179,9 +230,9 @@ impl<R> SubDiagnostic<R> {
         self.add_labeled_range(range, "");
     }
 
-    /// Updates the suggestion of this subdiagnostic.
-    pub fn set_suggestion(&mut self, suggestion: Suggestion<R>) {
-        self.suggestion = Some(suggestion);
+    /// Adds a new suggestion to this subdiagnostic.
+    pub fn add_suggestion(&mut self, suggestion: Suggestion<R>) {
+        self.suggestions.push(suggestion);
     }
 
     /// Adds a new labeled subrange to this subdiagnostic, returning it for chaining.
@@ -204,9 +255,9 @@ impl<R> SubDiagnostic<R> {
         self
     }
 
-    /// Updates the suggestion of this subdiagnostic, returning it for chaining.
+    /// Adds a new suggestion to this subdiagnostic, returning it for chaining.
     pub fn with_suggestion(mut self, suggestion: Suggestion<R>) -> Self {
-        self.set_suggestion(suggestion);
+        self.add_suggestion(suggestion);
         self
     }
 }
@@ -219,6 +270,8 @@ impl<R> SubDiagnostic<R> {
 pub struct Diagnostic<D> {
     /// The severity of this diagnostic.
     pub level: Level,
+    /// The kind of this diagnostic, if it has been assigned one.
+    pub id: Option<DiagnosticId>,
     /// The main subdiagnostic of this diagnostic.
     pub main: D,
     /// The notes attached to this diagnostic.
@@ -233,6 +286,21 @@ pub type RawDiagnostic = Diagnostic<RawSubDiagnostic>;
 /// A rendered subdiagnostic, with contiguous ranges.
 pub type RenderedSubDiagnostic = SubDiagnostic<SourceRange>;
 
+impl RenderedSubDiagnostic {
+    /// Returns `true` if this subdiagnostic is a note tracing a macro expansion leading to the
+    /// subdiagnostic preceding it, as generated during rendering.
+    ///
+    /// This includes the anonymous note standing in for expansions omitted due to the macro
+    /// backtrace limit (see [`render_with_backtrace_limit()`]).
+    pub fn is_expansion_trace(&self) -> bool {
+        match self.msg.template() {
+            EXPANSION_NOTE_MSG => self.msg.args().is_empty(),
+            SKIPPED_EXPANSIONS_MSG => self.ranges.is_none(),
+            _ => false,
+        }
+    }
+}
+
 /// A rendered diagnostic, with expansion traces for every subdiagnostic and a top-level include
 /// trace.
 #[derive(Debug, Clone)]
@@ -249,6 +317,11 @@ impl RenderedDiagnostic {
         self.inner.level
     }
 
+    /// Returns the kind of this diagnostic, if it has been assigned one.
+    pub fn id(&self) -> Option<DiagnosticId> {
+        self.inner.id
+    }
+
     /// Returns the main subdiagnostic of this diagnostic.
     pub fn main(&self) -> &RenderedSubDiagnostic {
         &self.inner.main
@@ -270,6 +343,7 @@ impl RenderedDiagnostic {
 pub struct DiagnosticBuilder<'a, 'h> {
     diag: Box<RawDiagnostic>,
     smap: Option<&'a SourceMap>,
+    once: bool,
     manager: &'a mut Manager<'h>,
 }
 
@@ -277,17 +351,18 @@ impl<'a, 'h> DiagnosticBuilder<'a, 'h> {
     fn new(
         manager: &'a mut Manager<'h>,
         level: Level,
-        msg: String,
+        msg: Message,
         primary_range: Option<(FragmentedSourceRange, &'a SourceMap)>,
     ) -> Self {
         let main_diag = RawSubDiagnostic {
             msg,
             ranges: primary_range.map(|(range, _)| Ranges::new(range)),
-            suggestion: None,
+            suggestions: Vec::new(),
         };
 
         let diag = Box::new(RawDiagnostic {
             level,
+            id: None,
             main: main_diag,
             notes: Vec::new(),
         });
@@ -295,10 +370,18 @@ impl<'a, 'h> DiagnosticBuilder<'a, 'h> {
         DiagnosticBuilder {
             diag,
             smap: primary_range.map(|(_, smap)| smap),
+            once: false,
             manager,
         }
     }
 
+    /// Sets the kind of the diagnostic being built, allowing it to be disabled with
+    /// [`Manager::set_enabled()`].
+    pub fn set_id(mut self, id: DiagnosticId) -> Self {
+        self.diag.id = Some(id);
+        self
+    }
+
     /// Adds a labeled subrange to the diagnostic being built.
     ///
     /// # Panics
@@ -322,9 +405,9 @@ impl<'a, 'h> DiagnosticBuilder<'a, 'h> {
         self.add_labeled_range(range, "")
     }
 
-    /// Sets the suggestion on the diagnostic being built.
-    pub fn set_suggestion(mut self, suggestion: RawSuggestion) -> Self {
-        self.diag.main.set_suggestion(suggestion);
+    /// Adds a suggestion to the diagnostic being built.
+    pub fn add_suggestion(mut self, suggestion: RawSuggestion) -> Self {
+        self.diag.main.add_suggestion(suggestion);
         self
     }
 
@@ -339,7 +422,7 @@ impl<'a, 'h> DiagnosticBuilder<'a, 'h> {
     /// If this diagnostic caused a fatal error to be emitted, either directly or indirectly (e.g.
     /// through the error limit), returns `Err(FatalErrorEmitted)`. Otherwise, returns `Ok(())`.
     pub fn emit(self) -> Result<()> {
-        self.manager.emit(&self.diag, self.smap)
+        self.manager.emit(self.diag, self.smap, self.once)
     }
 }
 
@@ -359,24 +442,51 @@ pub trait RenderedSink {
     fn report(&mut self, diag: &RenderedDiagnostic, smap: Option<&SourceMap>);
 }
 
+impl<S: RenderedSink + ?Sized> RenderedSink for Box<S> {
+    fn report(&mut self, diag: &RenderedDiagnostic, smap: Option<&SourceMap>) {
+        (**self).report(diag, smap)
+    }
+}
+
+impl<S: RenderedSink + ?Sized> RenderedSink for &mut S {
+    fn report(&mut self, diag: &RenderedDiagnostic, smap: Option<&SourceMap>) {
+        (**self).report(diag, smap)
+    }
+}
+
 /// Adaptor that bridges between rendered diagnostic sinks and raw diagnostic sinks.
 struct RenderingSinkAdaptor<H> {
     rendered_sink: H,
+    macro_backtrace_limit: Option<u32>,
 }
 
 impl<H: RenderedSink> RawSink for RenderingSinkAdaptor<H> {
     fn report(&mut self, diag: &RawDiagnostic, smap: Option<&SourceMap>) {
-        self.rendered_sink.report(&render(diag, smap), smap);
+        let rendered = render_with_backtrace_limit(diag, smap, self.macro_backtrace_limit);
+        self.rendered_sink.report(&rendered, smap);
     }
 }
 
+/// The properties by which identical diagnostics are recognized: the level, ID, message and
+/// spelling position of the primary range.
+type DedupKey = (Level, Option<DiagnosticId>, Message, Option<SourcePos>);
+
 /// A top-level diagnostics engine.
 ///
-/// This structure is responsible for forwarding diagnostics to a sink, enforcing error limits
-/// and tracking statistics about emitted diagnostics.
+/// This structure is responsible for forwarding diagnostics to a sink, applying the warning policy,
+/// enforcing error limits and tracking statistics about emitted diagnostics.
 pub struct Manager<'h> {
     sink: Box<dyn RawSink + 'h>,
     error_limit: Option<u32>,
+    warnings_as_errors: bool,
+    ignore_warnings: bool,
+    disabled: HashSet<DiagnosticId>,
+    deduplicate: bool,
+    /// The diagnostics emitted so far, if deduplication is enabled.
+    emitted: HashSet<DedupKey>,
+    /// The IDs and spelling positions of the diagnostics reported with
+    /// [`Reporter::report_once()`].
+    reported_once: HashSet<(DiagnosticId, SourcePos)>,
     warning_count: u32,
     error_count: u32,
 }
@@ -390,15 +500,41 @@ impl<'h> Manager<'h> {
         Self::with_raw_sink(
             Box::new(RenderingSinkAdaptor {
                 rendered_sink: sink,
+                macro_backtrace_limit: None,
             }),
             error_limit,
         )
     }
 
-    /// Creates a new `Manager` with an [annotating sink](AnnotatingSink) and
-    /// the specified error limit.
+    /// Creates a new `Manager` with an [annotating sink](AnnotatingSink) writing to `stderr` and
+    /// the specified error limit, using colors if `stderr` is a terminal.
     pub fn new_annotating(error_limit: Option<u32>) -> Manager<'static> {
-        Manager::new(AnnotatingSink, error_limit)
+        Manager::new(AnnotatingSink::new(ColorChoice::Auto), error_limit)
+    }
+
+    /// Creates a new `Manager` with the specified sink, applying the diagnostic limits and warning
+    /// policy in `limits`.
+    ///
+    /// If [`max_diagnostics_per_line`](FrontendLimits::max_diagnostics_per_line) is set, `sink` is
+    /// wrapped in a [`LineCappingSink`]. Expansion traces are rendered with the
+    /// [`macro_backtrace_limit`](FrontendLimits::macro_backtrace_limit).
+    pub fn with_limits(sink: impl RenderedSink + 'h, limits: &FrontendLimits) -> Self {
+        let sink: Box<dyn RenderedSink + 'h> = match limits.max_diagnostics_per_line {
+            Some(max) => Box::new(LineCappingSink::new(sink, max)),
+            None => Box::new(sink),
+        };
+
+        let mut manager = Self::with_raw_sink(
+            Box::new(RenderingSinkAdaptor {
+                rendered_sink: sink,
+                macro_backtrace_limit: limits.macro_backtrace_limit,
+            }),
+            limits.error_limit,
+        );
+
+        manager.set_warnings_as_errors(limits.warnings_as_errors);
+        manager.set_ignore_warnings(limits.ignore_warnings);
+        manager
     }
 
     /// Creates a new `Manager` with the specified raw diagnostic sink and error limit.
@@ -406,11 +542,57 @@ impl<'h> Manager<'h> {
         Manager {
             sink,
             error_limit,
+            warnings_as_errors: false,
+            ignore_warnings: false,
+            disabled: HashSet::new(),
+            deduplicate: false,
+            emitted: HashSet::new(),
+            reported_once: HashSet::new(),
             warning_count: 0,
             error_count: 0,
         }
     }
 
+    /// Sets whether warnings should be emitted as errors, as with `-Werror`. They count towards
+    /// the error limit in that case.
+    pub fn set_warnings_as_errors(&mut self, warnings_as_errors: bool) {
+        self.warnings_as_errors = warnings_as_errors;
+    }
+
+    /// Sets whether warnings should be dropped instead of being emitted, as with `-w`. This takes
+    /// precedence over [`set_warnings_as_errors()`](Self::set_warnings_as_errors()).
+    pub fn set_ignore_warnings(&mut self, ignore_warnings: bool) {
+        self.ignore_warnings = ignore_warnings;
+    }
+
+    /// Sets whether diagnostics with the specified ID should be emitted, as with `-W<id>` and
+    /// `-Wno-<id>`. All IDs are enabled by default.
+    ///
+    /// Disabled diagnostics are dropped along with their notes, unless they are fatal.
+    pub fn set_enabled(&mut self, id: DiagnosticId, enabled: bool) {
+        if enabled {
+            self.disabled.remove(&id);
+        } else {
+            self.disabled.insert(id);
+        }
+    }
+
+    /// Sets whether diagnostics identical to ones already emitted should be dropped. This is
+    /// disabled by default.
+    ///
+    /// Diagnostics are identical if they have the same level, ID and message, and their primary
+    /// ranges start at the same spelling position. In particular, a diagnostic reported inside a
+    /// macro definition is emitted only for the first expansion of the macro. Fatal diagnostics are
+    /// never dropped.
+    pub fn set_deduplicate(&mut self, deduplicate: bool) {
+        self.deduplicate = deduplicate;
+    }
+
+    /// Checks whether diagnostics with the specified ID are emitted.
+    pub fn is_enabled(&self, id: DiagnosticId) -> bool {
+        !self.disabled.contains(&id)
+    }
+
     /// Creates a new reporter for reporting diagnostics with location information.
     pub fn reporter<'a>(&'a mut self, smap: &'a SourceMap) -> Reporter<'a, 'h> {
         Reporter {
@@ -420,8 +602,12 @@ impl<'h> Manager<'h> {
     }
 
     /// Reports a diagnostic with no location information, returning a diagnostic builder.
-    pub fn report_anon(&mut self, level: Level, msg: String) -> DiagnosticBuilder<'_, 'h> {
-        DiagnosticBuilder::new(self, level, msg, None)
+    pub fn report_anon(
+        &mut self,
+        level: Level,
+        msg: impl Into<Message>,
+    ) -> DiagnosticBuilder<'_, 'h> {
+        DiagnosticBuilder::new(self, level, msg.into(), None)
     }
 
     /// Returns the number of warnings emitted by this manager.
@@ -436,9 +622,36 @@ impl<'h> Manager<'h> {
 
     /// Emits the specified diagnostic.
     ///
-    /// Statistics are updated, and a fatal diagnostic is emitted if the error limit is reached.
-    fn emit(&mut self, diag: &RawDiagnostic, smap: Option<&SourceMap>) -> Result<()> {
-        self.sink.report(diag, smap);
+    /// Disabled diagnostics are dropped, and warnings are dropped or promoted to errors according to
+    /// the warning policy. Statistics are updated, and a fatal diagnostic is emitted if the error
+    /// limit is reached.
+    ///
+    /// If `once` is set, the diagnostic is dropped if one with the same ID has already been
+    /// reported once at the same spelling position.
+    fn emit(
+        &mut self,
+        mut diag: Box<RawDiagnostic>,
+        smap: Option<&SourceMap>,
+        once: bool,
+    ) -> Result<()> {
+        if diag.level != Level::Fatal && diag.id.is_some_and(|id| !self.is_enabled(id)) {
+            return Ok(());
+        }
+
+        if diag.level == Level::Warning {
+            if self.ignore_warnings {
+                return Ok(());
+            }
+            if self.warnings_as_errors {
+                diag.level = Level::Error;
+            }
+        }
+
+        if diag.level != Level::Fatal && self.is_duplicate(&diag, smap, once) {
+            return Ok(());
+        }
+
+        self.sink.report(&diag, smap);
 
         match diag.level {
             Level::Warning => self.warning_count += 1,
@@ -450,13 +663,34 @@ impl<'h> Manager<'h> {
         if let Some(limit) = self.error_limit {
             if self.error_count >= limit {
                 return self
-                    .report_anon(Level::Fatal, "too many errors emitted".to_owned())
+                    .report_anon(Level::Fatal, "too many errors emitted")
                     .emit();
             }
         }
 
         Ok(())
     }
+
+    /// Records `diag` as emitted, returning whether it should be dropped because it has already
+    /// been emitted according to the deduplication rules.
+    fn is_duplicate(&mut self, diag: &RawDiagnostic, smap: Option<&SourceMap>, once: bool) -> bool {
+        let pos = smap
+            .zip(diag.main.ranges.as_ref())
+            .and_then(|(smap, ranges)| smap.try_get_spelling_pos(ranges.primary_range.start).ok());
+
+        if once {
+            let id = diag.id.expect("diagnostics reported once must have an ID");
+            let pos = pos.expect("diagnostics reported once must have a location");
+            if !self.reported_once.insert((id, pos)) {
+                return true;
+            }
+        }
+
+        self.deduplicate
+            && !self
+                .emitted
+                .insert((diag.level, diag.id, diag.main.msg.clone(), pos))
+    }
 }
 
 /// Helper for reporting diagnostics with location information.
@@ -474,7 +708,7 @@ impl<'a, 'h> Reporter<'a, 'h> {
         &mut self,
         level: Level,
         primary_range: impl Into<FragmentedSourceRange>,
-        msg: impl Into<String>,
+        msg: impl Into<Message>,
     ) -> DiagnosticBuilder<'_, 'h> {
         DiagnosticBuilder::new(
             self.manager,
@@ -484,11 +718,29 @@ impl<'a, 'h> Reporter<'a, 'h> {
         )
     }
 
+    /// Reports a diagnostic with the specified ID at the specified location, unless one with the
+    /// same ID has already been reported once at the same spelling position. This is useful for
+    /// diagnostics that would otherwise be repeated for every expansion of a macro.
+    ///
+    /// A diagnostic builder is returned in either case, but emitting a repeated diagnostic has no
+    /// effect.
+    pub fn report_once(
+        &mut self,
+        level: Level,
+        id: DiagnosticId,
+        primary_range: impl Into<FragmentedSourceRange>,
+        msg: impl Into<Message>,
+    ) -> DiagnosticBuilder<'_, 'h> {
+        let mut builder = self.report(level, primary_range, msg).set_id(id);
+        builder.once = true;
+        builder
+    }
+
     /// Reports a warning at the specified location, returning a diagnostic builder.
     pub fn warn(
         &mut self,
         primary_range: impl Into<FragmentedSourceRange>,
-        msg: impl Into<String>,
+        msg: impl Into<Message>,
     ) -> DiagnosticBuilder<'_, 'h> {
         self.report(Level::Warning, primary_range, msg)
     }
@@ -497,7 +749,7 @@ impl<'a, 'h> Reporter<'a, 'h> {
     pub fn error(
         &mut self,
         primary_range: impl Into<FragmentedSourceRange>,
-        msg: impl Into<String>,
+        msg: impl Into<Message>,
     ) -> DiagnosticBuilder<'_, 'h> {
         self.report(Level::Error, primary_range, msg)
     }
@@ -506,7 +758,7 @@ impl<'a, 'h> Reporter<'a, 'h> {
     pub fn fatal(
         &mut self,
         primary_range: impl Into<FragmentedSourceRange>,
-        msg: impl Into<String>,
+        msg: impl Into<Message>,
     ) -> DiagnosticBuilder<'_, 'h> {
         self.report(Level::Fatal, primary_range, msg)
     }
@@ -520,6 +772,285 @@ impl<'a, 'h> Reporter<'a, 'h> {
         delim: char,
     ) -> DiagnosticBuilder<'_, 'h> {
         self.error(pos, format!("expected a '{}'", delim))
-            .set_suggestion(RawSuggestion::new(pos, delim.to_string()))
+            .add_suggestion(RawSuggestion::new(pos, delim.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use crate::smap::{ExpansionKind, FileContents, FileName};
+    use crate::LocalRange;
+
+    use super::*;
+
+    fn emit_warning_and_error(manager: &mut Manager<'_>) {
+        manager
+            .report_anon(Level::Warning, "warning")
+            .emit()
+            .unwrap();
+        manager.report_anon(Level::Error, "error").emit().unwrap();
+    }
+
+    #[test]
+    fn warnings_as_errors() {
+        let diags = RefCell::new(Vec::new());
+        let mut manager = Manager::new(RecordingSink(&diags), None);
+        manager.set_warnings_as_errors(true);
+        emit_warning_and_error(&mut manager);
+
+        assert_eq!(manager.warning_count(), 0);
+        assert_eq!(manager.error_count(), 2);
+        drop(manager);
+        assert_eq!(diags.into_inner(), ["error: warning", "error: error"]);
+    }
+
+    #[test]
+    fn ignore_warnings() {
+        let limits = FrontendLimits {
+            warnings_as_errors: true,
+            ignore_warnings: true,
+            ..Default::default()
+        };
+
+        let diags = RefCell::new(Vec::new());
+        let mut manager = Manager::with_limits(RecordingSink(&diags), &limits);
+        emit_warning_and_error(&mut manager);
+
+        assert_eq!(manager.warning_count(), 0);
+        assert_eq!(manager.error_count(), 1);
+        drop(manager);
+        assert_eq!(diags.into_inner(), ["error: error"]);
+    }
+
+    #[test]
+    fn disabled_ids() {
+        let diags = RefCell::new(Vec::new());
+        let mut manager = Manager::new(RecordingSink(&diags), None);
+        manager.set_enabled(DiagnosticId::Multichar, false);
+        manager.set_enabled(DiagnosticId::Trigraphs, false);
+        manager.set_enabled(DiagnosticId::Trigraphs, true);
+        assert!(!manager.is_enabled(DiagnosticId::Multichar));
+
+        for id in [DiagnosticId::Multichar, DiagnosticId::Trigraphs] {
+            manager
+                .report_anon(Level::Warning, id.to_string())
+                .set_id(id)
+                .emit()
+                .unwrap();
+        }
+        assert!(manager
+            .report_anon(Level::Fatal, "fatal")
+            .set_id(DiagnosticId::Multichar)
+            .emit()
+            .is_err());
+
+        assert_eq!(manager.warning_count(), 1);
+        drop(manager);
+        assert_eq!(diags.into_inner(), ["warning: trigraphs", "fatal: fatal"]);
+    }
+
+    /// Creates a source map containing a file with two expansions of the range `1..2`, returning
+    /// the ranges spelled at offset 1 in the file and in the two expansions.
+    fn expanded_ranges() -> (SourceMap, [SourceRange; 3]) {
+        let mut smap = SourceMap::new();
+        let file = smap
+            .create_file(FileName::synth("t.c"), FileContents::new("#a\nM M\n"), None)
+            .unwrap();
+        let file_range = smap.get_source(file).range;
+        let spelling = file_range.subrange(LocalRange::at(1.into(), 1.into()));
+
+        let mut ranges = [spelling; 3];
+        for (i, &off) in [3u32, 5].iter().enumerate() {
+            let exp = smap
+                .create_expansion(
+                    spelling,
+                    file_range.subrange(LocalRange::at(off.into(), 1.into())),
+                    ExpansionKind::Macro,
+                )
+                .unwrap();
+            ranges[i + 1] = smap.get_source(exp).range;
+        }
+
+        (smap, ranges)
+    }
+
+    #[test]
+    fn deduplicate() {
+        let (smap, [file, exp1, exp2]) = expanded_ranges();
+
+        let diags = RefCell::new(Vec::new());
+        let mut manager = Manager::new(RecordingSink(&diags), None);
+        manager.set_deduplicate(true);
+
+        let mut reporter = manager.reporter(&smap);
+        for range in [exp1, exp2, file] {
+            reporter.error(range, "bad").emit().unwrap();
+        }
+        reporter.error(exp2, "worse").emit().unwrap();
+        reporter.warn(exp2, "bad").emit().unwrap();
+        assert!(reporter.fatal(exp1, "bad").emit().is_err());
+        assert!(reporter.fatal(exp1, "bad").emit().is_err());
+
+        assert_eq!(manager.error_count(), 2);
+        drop(manager);
+        assert_eq!(
+            diags.into_inner(),
+            [
+                "error: bad",
+                "error: worse",
+                "warning: bad",
+                "fatal: bad",
+                "fatal: bad"
+            ]
+        );
+    }
+
+    #[test]
+    fn report_once() {
+        let (smap, [file, exp1, exp2]) = expanded_ranges();
+
+        let diags = RefCell::new(Vec::new());
+        let mut manager = Manager::new(RecordingSink(&diags), None);
+
+        let mut reporter = manager.reporter(&smap);
+        for (range, msg) in [(exp1, "first"), (exp2, "second"), (file, "third")] {
+            reporter
+                .report_once(Level::Warning, DiagnosticId::Multichar, range, msg)
+                .emit()
+                .unwrap();
+        }
+        reporter
+            .report_once(Level::Warning, DiagnosticId::Trigraphs, exp2, "other id")
+            .emit()
+            .unwrap();
+        reporter.warn(exp2, "not once").emit().unwrap();
+
+        drop(manager);
+        assert_eq!(
+            diags.into_inner(),
+            ["warning: first", "warning: other id", "warning: not once"]
+        );
+    }
+
+    #[test]
+    fn promoted_warnings_count_towards_error_limit() {
+        let limits = FrontendLimits {
+            error_limit: Some(2),
+            warnings_as_errors: true,
+            ..Default::default()
+        };
+
+        let diags = RefCell::new(Vec::new());
+        let mut manager = Manager::with_limits(RecordingSink(&diags), &limits);
+        manager
+            .report_anon(Level::Warning, "warning")
+            .emit()
+            .unwrap();
+        assert!(manager
+            .report_anon(Level::Warning, "warning")
+            .emit()
+            .is_err());
+        drop(manager);
+
+        assert_eq!(
+            diags.into_inner(),
+            [
+                "error: warning",
+                "error: warning",
+                "fatal: too many errors emitted"
+            ]
+        );
+    }
+
+    #[test]
+    fn render_out_of_bounds_ranges() {
+        let mut smap = SourceMap::new();
+        let file = smap
+            .create_file(FileName::real("t.c"), FileContents::new("x\n"), None)
+            .unwrap();
+        let file_range = smap.get_source(file).range;
+        let in_file = file_range.subrange(LocalRange::at(0.into(), 1.into()));
+        let stale = SourceRange::new(file_range.end().offset(10.into()), 1.into());
+
+        let mut main = RawSubDiagnostic::new("bad", in_file.into());
+        main.ranges
+            .as_mut()
+            .unwrap()
+            .subranges
+            .push((stale.into(), "stale".to_owned()));
+
+        let raw = RawDiagnostic {
+            level: Level::Error,
+            id: None,
+            main,
+            notes: vec![RawSubDiagnostic::new("stale note", stale.into())],
+        };
+
+        let rendered = render(&raw, Some(&smap));
+        let ranges = rendered.main().ranges.as_ref().unwrap();
+        assert_eq!(ranges.primary_range, in_file);
+        assert!(ranges.subranges.is_empty());
+        assert_eq!(rendered.notes().len(), 1);
+        assert!(rendered.notes()[0].ranges.is_none());
+    }
+
+    #[test]
+    fn macro_backtrace_limit() {
+        let mut smap = SourceMap::new();
+        let file = smap
+            .create_file(FileName::real("t.c"), FileContents::new("M\n"), None)
+            .unwrap();
+        let file_range = smap.get_source(file).range;
+        let spelling = file_range.subrange(LocalRange::at(0.into(), 1.into()));
+
+        // Expand `M` into itself 8 times, each expansion replacing the previous one.
+        let mut range = spelling;
+        for _ in 0..8 {
+            let exp = smap
+                .create_expansion(spelling, range, ExpansionKind::Macro)
+                .unwrap();
+            range = smap
+                .get_source(exp)
+                .range
+                .subrange(LocalRange::at(0.into(), 1.into()));
+        }
+
+        let raw = RawDiagnostic {
+            level: Level::Error,
+            id: None,
+            main: RawSubDiagnostic::new("bad", range.into()),
+            notes: vec![RawSubDiagnostic::new_anon("see here")],
+        };
+
+        let notes = |limit| {
+            render_with_backtrace_limit(&raw, Some(&smap), limit)
+                .notes()
+                .iter()
+                .map(|note| (note.msg.to_string(), note.is_expansion_trace()))
+                .collect::<Vec<_>>()
+        };
+        let trace = |msg: &str| (msg.to_owned(), true);
+
+        assert_eq!(notes(None).len(), 9);
+        assert_eq!(notes(Some(8)), notes(None));
+        assert_eq!(
+            notes(Some(3)),
+            [
+                trace("expanded from here"),
+                trace("expanded from here"),
+                trace("(skipping 5 expansions in backtrace)"),
+                trace("expanded from here"),
+                ("see here".to_owned(), false),
+            ]
+        );
+        assert_eq!(
+            notes(Some(0)),
+            [
+                trace("(skipping 8 expansions in backtrace)"),
+                ("see here".to_owned(), false),
+            ]
+        );
     }
 }
@@ -1,53 +1,204 @@
 use std::cmp;
 use std::collections::BTreeMap;
-use std::fmt;
+use std::fmt::{self, Write};
+use std::io::{self, IsTerminal};
 use std::iter;
 
 use crate::smap::{InterpretedFileRange, LineSnippet};
 use crate::{LocalRange, SourceMap, SourcePos};
 
 use super::{
-    Level, RenderedDiagnostic, RenderedRanges, RenderedSink, RenderedSubDiagnostic,
+    DiagnosticId, Level, RenderedDiagnostic, RenderedRanges, RenderedSink, RenderedSubDiagnostic,
     RenderedSuggestion,
 };
 
-/// A rendered diagnostic sink that emits messages and annotated code snippets to `stderr`.
-pub struct AnnotatingSink;
+/// The number of columns by which each level of an expansion trace is indented.
+const EXPANSION_INDENT: usize = 2;
 
-impl RenderedSink for AnnotatingSink {
+/// Controls whether an [`AnnotatingSink`] colors its output with ANSI escape sequences.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    /// Always use colors.
+    Always,
+    /// Use colors only if the output is a terminal.
+    #[default]
+    Auto,
+    /// Never use colors.
+    Never,
+}
+
+/// A rendered diagnostic sink that emits messages and annotated code snippets to `stderr` (or
+/// another writer).
+///
+/// Primary ranges are underlined with `^~~~`, and subranges with `---` followed by their labels.
+/// Suggestions are shown after a `help` line, as a preview of the affected lines with the
+/// suggestion applied.
+/// The notes tracing the macro expansions leading to a range are indented below it, one level
+/// deeper for every expansion. Diagnostics in included files are preceded by
+/// `In file included from` lines tracing the include chain, unless the chain is the same as that
+/// of the previous diagnostic.
+///
+/// When colors are enabled, levels and primary ranges are colored according to the severity of the
+/// diagnostic, while subranges and suggestions get colors of their own.
+pub struct AnnotatingSink<W = io::Stderr> {
+    out: W,
+    painter: Painter,
+    /// The include chain of the last diagnostic reported.
+    last_includes: Vec<SourcePos>,
+}
+
+impl AnnotatingSink {
+    /// Creates a new sink writing to `stderr`, using colors according to `color`.
+    pub fn new(color: ColorChoice) -> Self {
+        let out = io::stderr();
+        let colored = match color {
+            ColorChoice::Always => true,
+            ColorChoice::Auto => out.is_terminal(),
+            ColorChoice::Never => false,
+        };
+
+        Self {
+            out,
+            painter: Painter { colored },
+            last_includes: Vec::new(),
+        }
+    }
+}
+
+impl<W: io::Write> AnnotatingSink<W> {
+    /// Creates a new sink writing to `out`, using colors according to `color`.
+    ///
+    /// As there is no way to tell whether an arbitrary writer is a terminal, [`ColorChoice::Auto`]
+    /// is treated like [`ColorChoice::Never`].
+    pub fn with_writer(out: W, color: ColorChoice) -> Self {
+        Self {
+            out,
+            painter: Painter {
+                colored: color == ColorChoice::Always,
+            },
+            last_includes: Vec::new(),
+        }
+    }
+
+    /// Consumes the sink, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: io::Write> RenderedSink for AnnotatingSink<W> {
     fn report(&mut self, diag: &RenderedDiagnostic, smap: Option<&SourceMap>) {
-        let subdiags = iter::once(WrappedSubDiagnostic::from_main(diag))
-            .chain(diag.notes().iter().map(WrappedSubDiagnostic::from_note));
+        let mut out = String::new();
+        if let Some(smap) = smap {
+            if diag.includes != self.last_includes {
+                write_include_trace(&mut out, &diag.includes, smap).unwrap();
+                self.last_includes.clone_from(&diag.includes);
+            }
+        }
 
-        match smap {
-            Some(smap) => subdiags.for_each(|subdiag| print_annotated_subdiag(&subdiag, smap)),
-            None => subdiags.for_each(|subdiag| print_subdiag_msg(&subdiag)),
+        out.push_str(&format_diag(diag, smap, self.painter));
+        self.out
+            .write_all(out.as_bytes())
+            .expect("failed to write diagnostic");
+    }
+}
+
+/// The styles applied to different parts of the output when colors are enabled.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Style {
+    /// A level name, or a primary range in a diagnostic of that level.
+    Level(Level),
+    /// The message of a subdiagnostic.
+    Message,
+    /// Line numbers, gutters and file locations.
+    Gutter,
+    /// Subrange markers and labels.
+    Secondary,
+    /// The text of a suggestion.
+    Suggestion,
+}
+
+impl Style {
+    fn ansi_code(self) -> &'static str {
+        match self {
+            Style::Level(Level::Note) => "1;36",
+            Style::Level(Level::Warning) => "1;33",
+            Style::Level(Level::Error) | Style::Level(Level::Fatal) => "1;31",
+            Style::Message => "1",
+            Style::Gutter | Style::Secondary => "1;34",
+            Style::Suggestion => "32",
         }
+    }
+}
 
-        eprintln!();
+/// Applies styles to parts of the output, if colors are enabled.
+#[derive(Clone, Copy, Default)]
+struct Painter {
+    colored: bool,
+}
+
+impl Painter {
+    fn paint(self, style: Style, obj: impl fmt::Display) -> String {
+        if self.colored {
+            format!("\x1b[{}m{}\x1b[0m", style.ansi_code(), obj)
+        } else {
+            obj.to_string()
+        }
     }
 }
 
+/// Formats `diag` as it would be printed by [`AnnotatingSink`].
+fn format_diag(diag: &RenderedDiagnostic, smap: Option<&SourceMap>, painter: Painter) -> String {
+    let mut expansion_depth = 0;
+    let notes = diag.notes().iter().map(|note| {
+        // Expansion traces directly follow the subdiagnostic they belong to, each one pointing at
+        // the spelling of the range highlighted by the previous one.
+        if note.is_expansion_trace() {
+            expansion_depth += 1;
+        } else {
+            expansion_depth = 0;
+        }
+        WrappedSubDiagnostic::from_note(note, expansion_depth * EXPANSION_INDENT)
+    });
+
+    let subdiags = iter::once(WrappedSubDiagnostic::from_main(diag)).chain(notes);
+
+    let mut out = String::new();
+    for subdiag in subdiags {
+        match smap {
+            Some(smap) => write_annotated_subdiag(&mut out, &subdiag, smap, painter),
+            None => write_subdiag_msg(&mut out, &subdiag, painter),
+        }
+        .unwrap();
+    }
+
+    out.push('\n');
+    out
+}
+
 struct WrappedSubDiagnostic<'a> {
     level: Level,
-    includes: &'a [SourcePos],
+    id: Option<DiagnosticId>,
     diag: &'a RenderedSubDiagnostic,
+    indent: usize,
 }
 
 impl<'a> WrappedSubDiagnostic<'a> {
     fn from_main(diag: &'a RenderedDiagnostic) -> Self {
         Self {
             level: diag.level(),
-            includes: &diag.includes,
+            id: diag.id(),
             diag: diag.main(),
+            indent: 0,
         }
     }
 
-    fn from_note(note: &'a RenderedSubDiagnostic) -> Self {
+    fn from_note(note: &'a RenderedSubDiagnostic, indent: usize) -> Self {
         Self {
             level: Level::Note,
-            includes: &[],
+            id: None,
             diag: note,
+            indent,
         }
     }
 }
@@ -56,8 +207,7 @@ struct AnnotatedLine<'a> {
     line: &'a str,
     line_num: u32,
     primary_range: Option<LocalRange>,
-    subranges: Vec<LocalRange>,
-    suggestion: Option<(&'a str, u32)>,
+    subranges: Vec<(LocalRange, &'a str)>,
 }
 
 impl<'a> AnnotatedLine<'a> {
@@ -67,62 +217,121 @@ impl<'a> AnnotatedLine<'a> {
             line_num,
             primary_range: None,
             subranges: Vec::new(),
-            suggestion: None,
         }
     }
 }
 
-fn print_subdiag_msg(subdiag: &WrappedSubDiagnostic<'_>) {
-    eprintln!("{}: {}", subdiag.level, subdiag.diag.msg);
+/// Layout and style information shared by all lines printed for a single subdiagnostic.
+#[derive(Clone, Copy)]
+struct Margin {
+    indent: usize,
+    gutter_width: usize,
+    painter: Painter,
 }
 
-fn print_annotated_subdiag(subdiag: &WrappedSubDiagnostic<'_>, smap: &SourceMap) {
-    print_subdiag_msg(subdiag);
+fn write_subdiag_msg(
+    out: &mut String,
+    subdiag: &WrappedSubDiagnostic<'_>,
+    painter: Painter,
+) -> fmt::Result {
+    write!(
+        out,
+        "{:indent$}{} {}",
+        "",
+        painter.paint(
+            Style::Level(subdiag.level),
+            format_args!("{}:", subdiag.level)
+        ),
+        painter.paint(Style::Message, &subdiag.diag.msg),
+        indent = subdiag.indent
+    )?;
+
+    match subdiag.id {
+        Some(id) => writeln!(out, " [-W{}]", id),
+        None => writeln!(out),
+    }
+}
+
+fn write_annotated_subdiag(
+    out: &mut String,
+    subdiag: &WrappedSubDiagnostic<'_>,
+    smap: &SourceMap,
+    painter: Painter,
+) -> fmt::Result {
+    write_subdiag_msg(out, subdiag, painter)?;
 
     if let Some(ranges) = subdiag.diag.ranges.as_ref() {
-        let annotations = build_annotations(ranges, subdiag.diag.suggestion.as_ref(), smap);
+        let annotations = build_annotations(ranges, smap);
 
-        let gutter_width = match annotations.last() {
-            Some(last) => count_digits(last.line_num + 1),
-            None => return,
+        let margin = match annotations.last() {
+            Some(last) => Margin {
+                indent: subdiag.indent,
+                gutter_width: count_digits(last.line_num + 1),
+                painter,
+            },
+            None => return Ok(()),
         };
 
-        for &include in subdiag.includes {
-            print_file_loc(
-                &smap.get_interpreted_range(include.into()),
-                Some("includer"),
-                gutter_width,
-            );
-        }
-
-        print_file_loc(
+        write_file_loc(
+            out,
             &smap.get_interpreted_range(ranges.primary_range),
-            None,
-            gutter_width,
-        );
+            margin,
+        )?;
+
+        write_annotations(out, &annotations, Style::Level(subdiag.level), margin)?;
 
-        print_annotations(&annotations, gutter_width);
+        for suggestion in &subdiag.diag.suggestions {
+            write_suggestion(out, suggestion, smap, subdiag.indent, painter)?;
+        }
     }
+
+    Ok(())
 }
 
-fn print_file_loc(interp: &InterpretedFileRange<'_>, note: Option<&str>, gutter_width: usize) {
-    let note = note.map(|note| format!(" ({})", note)).unwrap_or_default();
-    let linecol = interp.start_linecol();
+fn write_file_loc(
+    out: &mut String,
+    interp: &InterpretedFileRange<'_>,
+    margin: Margin,
+) -> fmt::Result {
+    let linecol = interp.presumed_start_linecol();
 
-    eprintln!(
-        "{pad:width$}--> {}:{}:{}{}",
-        interp.filename(),
+    writeln!(
+        out,
+        "{pad:width$}{} {}:{}:{}",
+        margin.painter.paint(Style::Gutter, "-->"),
+        interp.presumed_filename(),
         linecol.line + 1,
         linecol.col + 1,
-        note,
         pad = "",
-        width = gutter_width
-    );
+        width = margin.indent + margin.gutter_width
+    )
+}
+
+/// Writes the include chain `includes`, listed from outermost to innermost, starting with the
+/// innermost includer as GCC and Clang do.
+fn write_include_trace(out: &mut String, includes: &[SourcePos], smap: &SourceMap) -> fmt::Result {
+    for (i, &include) in includes.iter().rev().enumerate() {
+        let interp = smap.get_interpreted_range(include.into());
+        let prefix = if i == 0 {
+            "In file included from"
+        } else {
+            "                 from"
+        };
+
+        writeln!(
+            out,
+            "{} {}:{}:",
+            prefix,
+            interp.presumed_filename(),
+            interp.presumed_start_linecol().line + 1
+        )?;
+    }
+
+    Ok(())
 }
 
 fn build_annotations<'a>(
-    ranges: &RenderedRanges,
-    suggestion: Option<&'a RenderedSuggestion>,
+    ranges: &'a RenderedRanges,
     smap: &'a SourceMap,
 ) -> Vec<AnnotatedLine<'a>> {
     fn get_line<'a, 'b>(
@@ -143,31 +352,31 @@ fn build_annotations<'a>(
         get_line(&mut line_map, &snippet).primary_range = Some(snippet.range);
     }
 
-    for &(subrange, _) in &ranges.subranges {
-        for snippet in smap.get_interpreted_range(subrange).line_snippets() {
-            get_line(&mut line_map, &snippet)
-                .subranges
-                .push(snippet.range);
-        }
-    }
-
-    if let Some(suggestion) = suggestion {
-        let linecol = smap
-            .get_interpreted_range(suggestion.replacement_range)
-            .start_linecol();
+    for (subrange, label) in &ranges.subranges {
+        let snippets: Vec<_> = smap
+            .get_interpreted_range(*subrange)
+            .line_snippets()
+            .collect();
+        let last_idx = snippets.len().saturating_sub(1);
 
-        // To avoid confusion, only display the suggestion if it's on a line we're highlighting
-        // anyway.
-        // TODO: find a better way to surface this.
-        if let Some(annotated_line) = line_map.get_mut(&linecol.line) {
-            annotated_line.suggestion = Some((&suggestion.insert_text, linecol.col));
+        for (i, snippet) in snippets.iter().enumerate() {
+            // Labels of multi-line subranges are attached to their last line.
+            let label = if i == last_idx { label.as_str() } else { "" };
+            get_line(&mut line_map, snippet)
+                .subranges
+                .push((snippet.range, label));
         }
     }
 
-    line_map.into_iter().map(|(_, line)| line).collect()
+    line_map.into_values().collect()
 }
 
-fn print_annotations(annotations: &[AnnotatedLine<'_>], gutter_width: usize) {
+fn write_annotations(
+    out: &mut String,
+    annotations: &[AnnotatedLine<'_>],
+    primary_style: Style,
+    margin: Margin,
+) -> fmt::Result {
     let mut last_line_num = None;
 
     for annotation in annotations {
@@ -176,52 +385,280 @@ fn print_annotations(annotations: &[AnnotatedLine<'_>], gutter_width: usize) {
             .is_some()
         {
             // Indicate skipped lines in the snippet.
-            eprintln!("...");
+            writeln!(
+                out,
+                "{:indent$}{}",
+                "",
+                margin.painter.paint(Style::Gutter, "..."),
+                indent = margin.indent
+            )?;
         }
 
         last_line_num = Some(annotation.line_num);
-        print_annotation(annotation, gutter_width);
+        write_annotation(out, annotation, primary_style, margin)?;
     }
+
+    Ok(())
 }
 
-fn print_annotation(annotation: &AnnotatedLine<'_>, gutter_width: usize) {
-    let highlight_line = build_highlight_line(annotation);
+fn write_annotation(
+    out: &mut String,
+    annotation: &AnnotatedLine<'_>,
+    primary_style: Style,
+    margin: Margin,
+) -> fmt::Result {
+    write_gutter(out, annotation.line_num + 1, margin)?;
+    writeln!(out, "{}", annotation.line)?;
 
-    print_gutter(annotation.line_num + 1, gutter_width);
-    eprintln!("{}", annotation.line);
+    for line in build_marker_lines(annotation, primary_style, margin.painter) {
+        write_gutter(out, "", margin)?;
+        writeln!(out, "{}", line)?;
+    }
 
-    print_gutter("", gutter_width);
-    eprintln!("{}", highlight_line);
+    Ok(())
+}
+
+/// Writes a `help` line describing `suggestion`, followed by a preview of the affected source
+/// lines with the suggestion applied.
+///
+/// Inserted text is marked with `+` if it is a pure insertion and with `~` if it replaces existing
+/// text. Suggestions that only delete text are previewed on the original lines instead, with the
+/// deleted text marked with `-`.
+fn write_suggestion(
+    out: &mut String,
+    suggestion: &RenderedSuggestion,
+    smap: &SourceMap,
+    indent: usize,
+    painter: Painter,
+) -> fmt::Result {
+    let snippets: Vec<_> = smap
+        .get_interpreted_range(suggestion.replacement_range)
+        .line_snippets()
+        .collect();
+    let (first, last) = match (snippets.first(), snippets.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Ok(()),
+    };
+
+    let removed = smap.get_spelling(suggestion.replacement_range);
+    let inserted = suggestion.insert_text.as_str();
+
+    let msg = if removed.contains('\n') || inserted.contains('\n') {
+        "apply this change".to_owned()
+    } else if removed.is_empty() {
+        format!("insert `{}`", inserted)
+    } else if inserted.is_empty() {
+        format!("remove `{}`", removed)
+    } else {
+        format!("replace `{}` with `{}`", removed, inserted)
+    };
+
+    writeln!(
+        out,
+        "{:indent$}{} {}",
+        "",
+        painter.paint(Style::Suggestion, "help:"),
+        painter.paint(Style::Message, msg),
+        indent = indent
+    )?;
+
+    let (lines, marker) = if inserted.is_empty() {
+        let lines: Vec<_> = snippets
+            .iter()
+            .map(|snippet| {
+                let range = snippet.range;
+                (
+                    snippet.line.to_owned(),
+                    range.start().into()..range.end().into(),
+                )
+            })
+            .collect();
+        (lines, '-')
+    } else {
+        let prefix = &first.line[..first.range.start().into()];
+        let suffix = &last.line[last.range.end().into()..];
+        let patched = format!("{}{}{}", prefix, inserted, suffix);
+
+        // Locate the inserted text within each line of the patched snippet.
+        let inserted_start = prefix.len();
+        let inserted_end = inserted_start + inserted.len();
+        let mut line_start = 0;
+        let lines: Vec<_> = patched
+            .split('\n')
+            .map(|line| {
+                let line_end = line_start + line.len();
+                let start = inserted_start.clamp(line_start, line_end) - line_start;
+                let end = inserted_end.clamp(line_start, line_end) - line_start;
+                line_start = line_end + 1;
+                (line.to_owned(), start..end)
+            })
+            .collect();
+
+        (lines, if removed.is_empty() { '+' } else { '~' })
+    };
+
+    let margin = Margin {
+        indent,
+        gutter_width: count_digits(first.line_num + lines.len() as u32),
+        painter,
+    };
 
-    if let Some((text, off)) = annotation.suggestion {
-        print_gutter("", gutter_width);
-        eprintln!("{pad:off$}{}", text, pad = "", off = off as usize);
+    for (line_num, (line, range)) in (first.line_num + 1..).zip(&lines) {
+        write_gutter(out, line_num, margin)?;
+        writeln!(out, "{}", line)?;
+
+        if !range.is_empty() {
+            let markers: String = iter::repeat_n(marker, range.len()).collect();
+            write_gutter(out, "", margin)?;
+            writeln!(
+                out,
+                "{:pad$}{}",
+                "",
+                painter.paint(Style::Suggestion, markers),
+                pad = range.start
+            )?;
+        }
     }
+
+    Ok(())
 }
 
 fn build_highlight_line(annotation: &AnnotatedLine<'_>) -> String {
     let mut highlight_line = " ".repeat(annotation.line.len() + 1);
 
-    for &subrange in &annotation.subranges {
-        add_highlight(&mut highlight_line, subrange, "-");
+    for &(subrange, _) in &annotation.subranges {
+        add_highlight(&mut highlight_line, subrange, '-', '-');
     }
 
     if let Some(primary_range) = annotation.primary_range {
-        add_highlight(&mut highlight_line, primary_range, "^");
+        add_highlight(&mut highlight_line, primary_range, '^', '~');
     }
 
     highlight_line
 }
 
-fn add_highlight(highlight_line: &mut String, range: LocalRange, marker: &str) {
+/// Builds the lines displayed below an annotated source line: the highlight line itself, followed
+/// by any subrange labels that could not be placed at its end.
+///
+/// The rightmost label is placed directly after the markers if nothing else is highlighted to its
+/// right. The remaining labels are placed on separate lines, from right to left, connected to
+/// their ranges with `|`.
+fn build_marker_lines(
+    annotation: &AnnotatedLine<'_>,
+    primary_style: Style,
+    painter: Painter,
+) -> Vec<String> {
+    let mut highlight_line = build_highlight_line(annotation);
+    highlight_line.truncate(highlight_line.trim_end().len());
+    let highlight_len = highlight_line.len();
+    highlight_line = paint_highlight_line(&highlight_line, primary_style, painter);
+
+    let mut labels: Vec<_> = annotation
+        .subranges
+        .iter()
+        .copied()
+        .filter(|(_, label)| !label.is_empty())
+        .collect();
+    labels.sort_by_key(|&(range, _)| range.start());
+
+    if let Some(&(range, label)) = labels.last() {
+        let end = usize::from(range.start()) + cmp::max(usize::from(range.len()), 1);
+        if end >= highlight_len {
+            highlight_line.push(' ');
+            highlight_line.push_str(&painter.paint(Style::Secondary, label));
+            labels.pop();
+        }
+    }
+
+    let mut lines = vec![highlight_line];
+    if labels.is_empty() {
+        return lines;
+    }
+
+    let connectors = |count: usize| {
+        let mut line = String::new();
+        for &(range, _) in &labels[..count] {
+            let col: usize = range.start().into();
+            if col >= line.len() {
+                line.push_str(&" ".repeat(col - line.len()));
+                line.push('|');
+            }
+        }
+        line
+    };
+
+    let mut label_lines = vec![connectors(labels.len())];
+    for i in (0..labels.len()).rev() {
+        let (range, label) = labels[i];
+        let mut line = connectors(i);
+        let col: usize = range.start().into();
+        // Labels starting in the same column as another range's connector are shifted past it.
+        let pad = col.saturating_sub(line.len());
+        line.push_str(&" ".repeat(pad));
+        if pad == 0 && !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(label);
+        label_lines.push(line);
+    }
+
+    lines.extend(
+        label_lines
+            .into_iter()
+            .map(|line| painter.paint(Style::Secondary, line)),
+    );
+    lines
+}
+
+/// Colors the markers in `highlight_line` according to the kind of range they belong to.
+fn paint_highlight_line(highlight_line: &str, primary_style: Style, painter: Painter) -> String {
+    if !painter.colored {
+        return highlight_line.to_owned();
+    }
+
+    let style_of = |c| match c {
+        '^' | '~' => Some(primary_style),
+        '-' => Some(Style::Secondary),
+        _ => None,
+    };
+
+    let mut painted = String::new();
+    let mut rest = highlight_line;
+    while let Some(c) = rest.chars().next() {
+        let style = style_of(c);
+        let run_len = rest.find(|c| style_of(c) != style).unwrap_or(rest.len());
+
+        let (run, next) = rest.split_at(run_len);
+        match style {
+            Some(style) => painted.push_str(&painter.paint(style, run)),
+            None => painted.push_str(run),
+        }
+        rest = next;
+    }
+
+    painted
+}
+
+fn add_highlight(highlight_line: &mut String, range: LocalRange, first: char, rest: char) {
     let start: usize = range.start().into();
     let len = cmp::max(range.len().into(), 1);
 
-    highlight_line.replace_range(start..start + len, &marker.repeat(len));
+    let markers: String = iter::once(first)
+        .chain(iter::repeat(rest))
+        .take(len)
+        .collect();
+    highlight_line.replace_range(start..start + len, &markers);
 }
 
-fn print_gutter(obj: impl fmt::Display, width: usize) {
-    eprint!("{:>1$} | ", obj, width);
+fn write_gutter(out: &mut String, obj: impl fmt::Display, margin: Margin) -> fmt::Result {
+    let gutter = format!("{:>width$} |", obj, width = margin.gutter_width);
+    write!(
+        out,
+        "{:indent$}{} ",
+        "",
+        margin.painter.paint(Style::Gutter, gutter),
+        indent = margin.indent
+    )
 }
 
 fn count_digits(mut val: u32) -> usize {
@@ -238,6 +675,9 @@ fn count_digits(mut val: u32) -> usize {
 mod tests {
     use super::*;
 
+    use crate::diag::{render, RawDiagnostic, RawSubDiagnostic, RawSuggestion};
+    use crate::smap::{ExpansionKind, FileContents, FileName};
+
     #[test]
     fn highlight_line() {
         let annotation = AnnotatedLine {
@@ -245,10 +685,9 @@ mod tests {
             line_num: 0,
             primary_range: Some(LocalRange::at(10.into(), 1.into())),
             subranges: vec![
-                LocalRange::at(8.into(), 1.into()),
-                LocalRange::at(12.into(), 1.into()),
+                (LocalRange::at(8.into(), 1.into()), ""),
+                (LocalRange::at(12.into(), 1.into()), ""),
             ],
-            suggestion: None,
         };
 
         assert_eq!(build_highlight_line(&annotation), "        - ^ -  ");
@@ -261,7 +700,6 @@ mod tests {
             line_num: 0,
             primary_range: Some(LocalRange::at(10.into(), 0.into())),
             subranges: Vec::new(),
-            suggestion: None,
         };
 
         assert_eq!(build_highlight_line(&annotation), "          ^    ");
@@ -274,7 +712,6 @@ mod tests {
             line_num: 0,
             primary_range: Some(LocalRange::at(16.into(), 0.into())),
             subranges: Vec::new(),
-            suggestion: None,
         };
 
         assert_eq!(build_highlight_line(&annotation), "                ^")
@@ -287,10 +724,9 @@ mod tests {
             line_num: 0,
             primary_range: None,
             subranges: vec![
-                LocalRange::at(8.into(), 1.into()),
-                LocalRange::at(12.into(), 1.into()),
+                (LocalRange::at(8.into(), 1.into()), ""),
+                (LocalRange::at(12.into(), 1.into()), ""),
             ],
-            suggestion: None,
         };
 
         assert_eq!(build_highlight_line(&annotation), "        -   -  ");
@@ -302,11 +738,314 @@ mod tests {
             line: "int x = A(1, ++);",
             line_num: 0,
             primary_range: Some(LocalRange::at(13.into(), 2.into())),
-            subranges: vec![LocalRange::at(8.into(), 8.into())],
-            suggestion: None,
+            subranges: vec![(LocalRange::at(8.into(), 8.into()), "")],
+        };
+
+        assert_eq!(build_highlight_line(&annotation), "        -----^~-  ");
+    }
+
+    #[test]
+    fn marker_lines_trailing_label() {
+        let annotation = AnnotatedLine {
+            line: "int x = 1 + 2;",
+            line_num: 0,
+            primary_range: Some(LocalRange::at(10.into(), 1.into())),
+            subranges: vec![(LocalRange::at(12.into(), 1.into()), "int")],
         };
 
-        assert_eq!(build_highlight_line(&annotation), "        -----^^-  ");
+        assert_eq!(
+            build_marker_lines(&annotation, Style::Level(Level::Error), Painter::default()),
+            ["          ^ - int"]
+        );
+    }
+
+    #[test]
+    fn marker_lines_multiple_labels() {
+        let annotation = AnnotatedLine {
+            line: "int x = f(a, b);",
+            line_num: 0,
+            primary_range: Some(LocalRange::at(8.into(), 1.into())),
+            subranges: vec![
+                (LocalRange::at(10.into(), 1.into()), "first"),
+                (LocalRange::at(13.into(), 1.into()), "second"),
+                (LocalRange::at(15.into(), 1.into()), ""),
+            ],
+        };
+
+        assert_eq!(
+            build_marker_lines(&annotation, Style::Level(Level::Error), Painter::default()),
+            [
+                "        ^ -  - -",
+                "          |  |",
+                "          |  second",
+                "          first",
+            ]
+        );
+    }
+
+    #[test]
+    fn expansion_trace_indented() {
+        let mut smap = SourceMap::new();
+        let file = smap
+            .create_file(
+                FileName::real("t.c"),
+                FileContents::new("#define A 1 +\nint x = A;\n"),
+                None,
+            )
+            .unwrap();
+        let file_range = smap.get_source(file).range;
+        let exp = smap
+            .create_expansion(
+                file_range.subrange(LocalRange::at(10.into(), 3.into())),
+                file_range.subrange(LocalRange::at(22.into(), 1.into())),
+                ExpansionKind::Macro,
+            )
+            .unwrap();
+        let exp_range = smap.get_source(exp).range;
+
+        let raw = RawDiagnostic {
+            level: Level::Error,
+            id: None,
+            main: RawSubDiagnostic::new(
+                "expected an expression",
+                exp_range
+                    .subrange(LocalRange::at(2.into(), 1.into()))
+                    .into(),
+            ),
+            notes: Vec::new(),
+        };
+
+        assert_eq!(
+            format_diag(&render(&raw, Some(&smap)), Some(&smap), Painter::default()),
+            "error: expected an expression\n\
+             \x20--> t.c:2:9\n\
+             2 | int x = A;\n\
+             \x20 |         ^\n\
+             \x20 note: expanded from here\n\
+             \x20  --> t.c:1:13\n\
+             \x20 1 | #define A 1 +\n\
+             \x20   |             ^\n\
+             \n"
+        );
+    }
+
+    #[test]
+    fn include_trace() {
+        let mut smap = SourceMap::new();
+        let main = smap
+            .create_file(
+                FileName::real("main.c"),
+                FileContents::new("x\n#include \"a.h\"\n"),
+                None,
+            )
+            .unwrap();
+        let main_range = smap.get_source(main).range;
+        let a = smap
+            .create_file(
+                FileName::real("a.h"),
+                FileContents::new("#include \"b.h\"\n"),
+                Some(main_range.subpos(11.into())),
+            )
+            .unwrap();
+        let b = smap
+            .create_file(
+                FileName::real("b.h"),
+                FileContents::new("y\n"),
+                Some(smap.get_source(a).range.subpos(10.into())),
+            )
+            .unwrap();
+        let b_range = smap.get_source(b).range;
+
+        let mut sink = AnnotatingSink::with_writer(Vec::new(), ColorChoice::Never);
+        for (range, msg) in [
+            (b_range, "first"),
+            (b_range, "second"),
+            (main_range, "main"),
+        ] {
+            let raw = RawDiagnostic {
+                level: Level::Error,
+                id: None,
+                main: RawSubDiagnostic::new(
+                    msg,
+                    range.subrange(LocalRange::at(0.into(), 1.into())).into(),
+                ),
+                notes: Vec::new(),
+            };
+            sink.report(&render(&raw, Some(&smap)), Some(&smap));
+        }
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+
+        assert_eq!(
+            output,
+            "In file included from a.h:1:\n\
+             \x20                from main.c:2:\n\
+             error: first\n\
+             \x20--> b.h:1:1\n\
+             1 | y\n\
+             \x20 | ^\n\
+             \n\
+             error: second\n\
+             \x20--> b.h:1:1\n\
+             1 | y\n\
+             \x20 | ^\n\
+             \n\
+             error: main\n\
+             \x20--> main.c:1:1\n\
+             1 | x\n\
+             \x20 | ^\n\
+             \n"
+        );
+    }
+
+    #[test]
+    fn diagnostic_id() {
+        let raw = RawDiagnostic {
+            level: Level::Warning,
+            id: Some(DiagnosticId::Multichar),
+            main: RawSubDiagnostic::new_anon("multi-character character literal"),
+            notes: vec![RawSubDiagnostic::new_anon("note")],
+        };
+
+        let mut sink = AnnotatingSink::with_writer(Vec::new(), ColorChoice::Never);
+        sink.report(&render(&raw, None), None);
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+
+        assert_eq!(
+            output,
+            "warning: multi-character character literal [-Wmultichar]\nnote: note\n\n"
+        );
+    }
+
+    #[test]
+    fn colored_output() {
+        let mut smap = SourceMap::new();
+        let file = smap
+            .create_file(
+                FileName::real("t.c"),
+                FileContents::new("int x = f(a)\n"),
+                None,
+            )
+            .unwrap();
+        let file_range = smap.get_source(file).range;
+
+        let raw = RawDiagnostic {
+            level: Level::Warning,
+            id: None,
+            main: RawSubDiagnostic::new(
+                "bad call",
+                file_range
+                    .subrange(LocalRange::at(8.into(), 1.into()))
+                    .into(),
+            )
+            .with_labeled_range(
+                file_range
+                    .subrange(LocalRange::at(10.into(), 1.into()))
+                    .into(),
+                "arg",
+            )
+            .with_suggestion(RawSuggestion::new(
+                file_range.subrange(LocalRange::at(12.into(), 0.into())),
+                ";",
+            )),
+            notes: Vec::new(),
+        };
+
+        let mut sink = AnnotatingSink::with_writer(Vec::new(), ColorChoice::Always);
+        sink.report(&render(&raw, Some(&smap)), Some(&smap));
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+
+        assert_eq!(
+            output,
+            format!(
+                "{y}warning:{r} {b}bad call{r}\n\
+                 \x20{u}-->{r} t.c:1:9\n\
+                 {u}1 |{r} int x = f(a)\n\
+                 {u}  |{r}         {y}^{r} {u}-{r} {u}arg{r}\n\
+                 {g}help:{r} {b}insert `;`{r}\n\
+                 {u}1 |{r} int x = f(a);\n\
+                 {u}  |{r}             {g}+{r}\n\
+                 \n",
+                y = "\x1b[1;33m",
+                b = "\x1b[1m",
+                u = "\x1b[1;34m",
+                g = "\x1b[32m",
+                r = "\x1b[0m",
+            )
+        );
+    }
+
+    /// Formats a warning at the start of the file containing `src`, suggesting that `len` bytes at
+    /// `off` be replaced with `text` for each `(off, len, text)` in `suggestions`.
+    fn format_suggestions(src: &str, suggestions: &[(u32, u32, &str)]) -> String {
+        let mut smap = SourceMap::new();
+        let file = smap
+            .create_file(FileName::real("t.c"), FileContents::new(src), None)
+            .unwrap();
+        let file_range = smap.get_source(file).range;
+
+        let mut main = RawSubDiagnostic::new(
+            "w",
+            file_range
+                .subrange(LocalRange::at(0.into(), 1.into()))
+                .into(),
+        );
+        for &(off, len, text) in suggestions {
+            main.add_suggestion(RawSuggestion::new(
+                file_range.subrange(LocalRange::at(off.into(), len.into())),
+                text,
+            ));
+        }
+
+        let raw = RawDiagnostic {
+            level: Level::Warning,
+            id: None,
+            main,
+            notes: Vec::new(),
+        };
+
+        format_diag(&render(&raw, Some(&smap)), Some(&smap), Painter::default())
+    }
+
+    #[test]
+    fn suggestion_preview() {
+        let header = "warning: w\n --> t.c:1:1\n1 | a = b\n  | ^\n";
+
+        assert_eq!(
+            format_suggestions("a = b\nc\n", &[(4, 1, "(b)")]),
+            header.to_owned()
+                + "help: replace `b` with `(b)`\n\
+                   1 | a = (b)\n\
+                   \x20 |     ~~~\n\n"
+        );
+        assert_eq!(
+            format_suggestions("a = b\nc\n", &[(1, 2, "")]),
+            header.to_owned()
+                + "help: remove ` =`\n\
+                   1 | a = b\n\
+                   \x20 |  --\n\n"
+        );
+        assert_eq!(
+            format_suggestions("a = b\nc\n", &[(5, 0, " {\n  d;\n}\n")]),
+            header.to_owned()
+                + "help: apply this change\n\
+                   1 | a = b {\n\
+                   \x20 |      ++\n\
+                   2 |   d;\n\
+                   \x20 | ++++\n\
+                   3 | }\n\
+                   \x20 | +\n\
+                   4 | \n\n"
+        );
+        assert_eq!(
+            format_suggestions("a = b\nc\n", &[(4, 1, "(b)"), (4, 1, "c")]),
+            header.to_owned()
+                + "help: replace `b` with `(b)`\n\
+                   1 | a = (b)\n\
+                   \x20 |     ~~~\n\
+                   help: replace `b` with `c`\n\
+                   1 | a = c\n\
+                   \x20 |     ~\n\n"
+        );
     }
 
     #[test]
@@ -0,0 +1,100 @@
+use std::fmt;
+
+macro_rules! diagnostic_ids {
+    ($($(#[doc = $doc:literal])* $name:literal => $id:ident,)*) => {
+        /// Identifies the kind of a diagnostic, so that it can be enabled or disabled by name and so
+        /// that sinks can group related diagnostics.
+        ///
+        /// The names follow the `-W` flags of GCC and Clang where there is an equivalent.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub enum DiagnosticId {
+            $($(#[doc = $doc])* $id,)*
+        }
+
+        impl DiagnosticId {
+            /// All diagnostic IDs, in declaration order.
+            pub const ALL: &'static [DiagnosticId] = &[$(Self::$id),*];
+
+            /// Returns the name of this ID, as used in `-Wno-<name>`.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Self::$id => $name,)*
+                }
+            }
+
+            /// Returns the ID named `name`, if any.
+            pub fn from_name(name: &str) -> Option<Self> {
+                let id = match name {
+                    $($name => Self::$id,)*
+                    _ => return None,
+                };
+
+                Some(id)
+            }
+        }
+    };
+}
+
+diagnostic_ids! {
+    /// A source file contains byte sequences that are invalid in its encoding.
+    "invalid-source-encoding" => InvalidSourceEncoding,
+    /// A string or character literal exceeds the configured length limit.
+    "long-literal" => LongLiteral,
+    /// A trigraph was replaced.
+    "trigraphs" => Trigraphs,
+    /// A decimal integer literal is too large for any signed type.
+    "implicitly-unsigned-literal" => ImplicitlyUnsignedLiteral,
+    /// A floating literal is too large for its type.
+    "literal-range" => LiteralRange,
+    /// A character literal contains several characters.
+    "multichar" => Multichar,
+    /// A character literal contains more characters than fit in its type.
+    "char-literal-too-long" => CharLiteralTooLong,
+    /// A literal contains an unrecognized escape sequence.
+    "unknown-escape-sequence" => UnknownEscapeSequence,
+    /// A `#warning` directive was encountered.
+    "#warnings" => PoundWarning,
+    /// A directive is followed by unexpected tokens.
+    "extra-tokens" => ExtraTokens,
+    /// A macro was redefined incompatibly.
+    "macro-redefined" => MacroRedefined,
+    /// A macro name reserved to the implementation is defined or undefined.
+    "reserved-macro-identifier" => ReservedMacroIdentifier,
+    /// An object-like macro name is not followed by whitespace.
+    "missing-macro-whitespace" => MissingMacroWhitespace,
+    /// `__VA_ARGS__` or `__VA_OPT__` appears outside a variadic macro.
+    "va-args-outside-variadic" => VaArgsOutsideVariadic,
+    /// `#pragma once` or `#include_next` is used in the main file.
+    "outside-header" => OutsideHeader,
+    /// The comma operator is used in a preprocessor expression.
+    "comma" => Comma,
+    /// A shift in a preprocessor expression has a negative count.
+    "shift-count-negative" => ShiftCountNegative,
+    /// A shift in a preprocessor expression has a count exceeding the width of its type.
+    "shift-count-overflow" => ShiftCountOverflow,
+    /// Arithmetic in a preprocessor expression overflows.
+    "integer-overflow" => IntegerOverflow,
+    /// A construct is not supported by the selected language standard.
+    "pedantic" => Pedantic,
+    /// A fix-it could not be applied because it conflicts with another one.
+    "fixit-conflict" => FixitConflict,
+}
+
+impl fmt::Display for DiagnosticId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for &id in DiagnosticId::ALL {
+            assert_eq!(DiagnosticId::from_name(id.name()), Some(id));
+        }
+        assert_eq!(DiagnosticId::from_name("no-such-warning"), None);
+    }
+}
@@ -0,0 +1,157 @@
+use rustc_hash::FxHashMap;
+
+use crate::{SourceId, SourceMap};
+
+use super::{Diagnostic, Level, RenderedDiagnostic, RenderedSink, RenderedSubDiagnostic};
+
+/// A rendered diagnostic sink that forwards at most a fixed number of diagnostics on every source
+/// line to another sink.
+///
+/// Diagnostics are attributed to the (physical) line containing their primary range. Once the
+/// limit for a line is reached, a single note is forwarded in place of the first suppressed
+/// diagnostic, and any further diagnostics on that line are dropped. Fatal diagnostics and
+/// diagnostics without location information are always forwarded.
+///
+/// Note that the suppressed diagnostics are still counted by the [`Manager`](super::Manager);
+/// sinks that should receive every diagnostic can simply be used without this wrapper.
+pub struct LineCappingSink<S> {
+    inner: S,
+    max_per_line: u32,
+    counts: FxHashMap<(SourceId, u32), u32>,
+}
+
+impl<S> LineCappingSink<S> {
+    /// Creates a new sink forwarding at most `max_per_line` diagnostics on each line to `inner`.
+    pub fn new(inner: S, max_per_line: u32) -> Self {
+        Self {
+            inner,
+            max_per_line,
+            counts: FxHashMap::default(),
+        }
+    }
+
+    /// Consumes the sink, returning the wrapped sink.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: RenderedSink> RenderedSink for LineCappingSink<S> {
+    fn report(&mut self, diag: &RenderedDiagnostic, smap: Option<&SourceMap>) {
+        let (smap, ranges) = match (smap, &diag.main().ranges) {
+            (Some(smap), Some(ranges)) if diag.level() != Level::Fatal => (smap, ranges),
+            _ => return self.inner.report(diag, smap),
+        };
+
+        let primary_range = ranges.primary_range;
+        let line = smap
+            .get_interpreted_range(primary_range)
+            .start_linecol()
+            .line;
+        let key = (smap.lookup_source_id(primary_range.start()), line);
+
+        let count = self.counts.entry(key).or_insert(0);
+        *count += 1;
+
+        if *count <= self.max_per_line {
+            self.inner.report(diag, Some(smap));
+        } else if *count == self.max_per_line + 1 {
+            let note = RenderedDiagnostic {
+                inner: Diagnostic {
+                    level: Level::Note,
+                    id: None,
+                    main: RenderedSubDiagnostic::new(
+                        format!(
+                            "too many diagnostics on this line; only the first {} are shown",
+                            self.max_per_line
+                        ),
+                        primary_range,
+                    ),
+                    notes: Vec::new(),
+                },
+                includes: diag.includes.clone(),
+            };
+
+            self.inner.report(&note, Some(smap));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use crate::diag::RecordingSink;
+    use crate::smap::{FileContents, FileName};
+    use crate::{DiagManager, FrontendLimits, SourceRange};
+
+    use super::*;
+
+    #[test]
+    fn caps_diagnostics_per_line() {
+        let mut smap = SourceMap::new();
+        let id = smap
+            .create_file(FileName::synth("test.c"), FileContents::new("ab\ncd"), None)
+            .unwrap();
+        let start = smap.get_source(id).range.start();
+        let at = |off: u32| SourceRange::new(start.offset(off.into()), 1.into());
+
+        let diags = RefCell::new(Vec::new());
+        let mut manager = DiagManager::new(LineCappingSink::new(RecordingSink(&diags), 2), None);
+
+        let mut reporter = manager.reporter(&smap);
+        for &off in &[0, 1, 0, 3, 1] {
+            reporter.error(at(off), format!("{}", off)).emit().unwrap();
+        }
+        assert!(reporter.fatal(at(0), "fatal").emit().is_err());
+
+        assert_eq!(manager.error_count(), 5);
+        drop(manager);
+
+        assert_eq!(
+            diags.into_inner(),
+            [
+                "error: 0",
+                "error: 1",
+                "note: too many diagnostics on this line; only the first 2 are shown",
+                "error: 3",
+                "fatal: fatal",
+            ]
+        );
+    }
+
+    #[test]
+    fn manager_with_limits() {
+        let mut smap = SourceMap::new();
+        let id = smap
+            .create_file(FileName::synth("test.c"), FileContents::new("ab\ncd"), None)
+            .unwrap();
+        let start = smap.get_source(id).range.start();
+        let at = |off: u32| SourceRange::new(start.offset(off.into()), 1.into());
+
+        let limits = FrontendLimits {
+            max_diagnostics_per_line: Some(1),
+            error_limit: Some(3),
+            ..Default::default()
+        };
+
+        let diags = RefCell::new(Vec::new());
+        let mut manager = DiagManager::with_limits(RecordingSink(&diags), &limits);
+
+        let mut reporter = manager.reporter(&smap);
+        reporter.error(at(0), "0").emit().unwrap();
+        reporter.error(at(1), "1").emit().unwrap();
+        assert!(reporter.error(at(3), "3").emit().is_err());
+        drop(manager);
+
+        assert_eq!(
+            diags.into_inner(),
+            [
+                "error: 0",
+                "note: too many diagnostics on this line; only the first 1 are shown",
+                "error: 3",
+                "fatal: too many errors emitted",
+            ]
+        );
+    }
+}
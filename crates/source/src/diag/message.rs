@@ -0,0 +1,221 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// A typed argument substituted into a diagnostic [`Message`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MessageArg {
+    /// The name of an identifier, such as a macro or parameter.
+    Symbol(String),
+    /// The path of a file.
+    Path(PathBuf),
+    /// An integer value.
+    Int(i128),
+    /// Any other text.
+    Str(String),
+}
+
+impl MessageArg {
+    /// Creates an argument holding the identifier `name`.
+    pub fn symbol(name: impl Into<String>) -> Self {
+        MessageArg::Symbol(name.into())
+    }
+}
+
+impl fmt::Display for MessageArg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageArg::Symbol(name) => f.write_str(name),
+            MessageArg::Path(path) => write!(f, "{}", path.display()),
+            MessageArg::Int(val) => write!(f, "{}", val),
+            MessageArg::Str(text) => f.write_str(text),
+        }
+    }
+}
+
+impl From<&Path> for MessageArg {
+    fn from(path: &Path) -> Self {
+        MessageArg::Path(path.to_owned())
+    }
+}
+
+impl From<PathBuf> for MessageArg {
+    fn from(path: PathBuf) -> Self {
+        MessageArg::Path(path)
+    }
+}
+
+impl From<&str> for MessageArg {
+    fn from(text: &str) -> Self {
+        MessageArg::Str(text.to_owned())
+    }
+}
+
+impl From<String> for MessageArg {
+    fn from(text: String) -> Self {
+        MessageArg::Str(text)
+    }
+}
+
+macro_rules! impl_int_arg {
+    ($($ty:ty),*) => {
+        $(
+            impl From<$ty> for MessageArg {
+                fn from(val: $ty) -> Self {
+                    MessageArg::Int(val.into())
+                }
+            }
+        )*
+    };
+}
+
+impl_int_arg!(i8, i16, i32, i64, u8, u16, u32, u64);
+
+impl From<usize> for MessageArg {
+    fn from(val: usize) -> Self {
+        MessageArg::Int(val as i128)
+    }
+}
+
+/// A diagnostic message, consisting of a template and named arguments substituted into it.
+///
+/// Templates refer to arguments by name, as in `macro '{name}' redefined`; literal braces are
+/// written as `{{` and `}}`. Keeping the arguments separate allows sinks to inspect them (for
+/// example, to emit them in machine-readable output), while the [`Display`](fmt::Display)
+/// implementation produces the fully-substituted text.
+///
+/// Plain strings convert to messages with no arguments, with any braces escaped.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Message {
+    template: String,
+    args: Vec<(&'static str, MessageArg)>,
+}
+
+impl Message {
+    /// Creates a new message with the specified template and no arguments.
+    pub fn new(template: impl Into<String>) -> Self {
+        Self {
+            template: template.into(),
+            args: Vec::new(),
+        }
+    }
+
+    /// Adds the argument `name` to this message, returning it for chaining.
+    pub fn arg(mut self, name: &'static str, val: impl Into<MessageArg>) -> Self {
+        self.args.push((name, val.into()));
+        self
+    }
+
+    /// Returns the template of this message.
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    /// Returns the arguments of this message, in the order in which they were added.
+    pub fn args(&self) -> &[(&'static str, MessageArg)] {
+        &self.args
+    }
+
+    /// Returns the value of the argument `name`, if any.
+    pub fn get_arg(&self, name: &str) -> Option<&MessageArg> {
+        self.args
+            .iter()
+            .find(|&&(arg_name, _)| arg_name == name)
+            .map(|(_, val)| val)
+    }
+}
+
+impl From<&str> for Message {
+    fn from(text: &str) -> Self {
+        Self::new(text.replace('{', "{{").replace('}', "}}"))
+    }
+}
+
+impl From<String> for Message {
+    fn from(text: String) -> Self {
+        if text.contains(['{', '}']) {
+            text.as_str().into()
+        } else {
+            Self::new(text)
+        }
+    }
+}
+
+impl From<&String> for Message {
+    fn from(text: &String) -> Self {
+        text.as_str().into()
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rest = self.template.as_str();
+
+        while let Some(idx) = rest.find(['{', '}']) {
+            f.write_str(&rest[..idx])?;
+            let brace = &rest[idx..idx + 1];
+            rest = &rest[idx + 1..];
+
+            if let Some(tail) = rest.strip_prefix(brace) {
+                f.write_str(brace)?;
+                rest = tail;
+                continue;
+            }
+
+            if brace == "}" {
+                f.write_str(brace)?;
+                continue;
+            }
+
+            match rest.find('}') {
+                Some(end) => {
+                    let name = &rest[..end];
+                    match self.get_arg(name) {
+                        Some(val) => write!(f, "{}", val)?,
+                        None => write!(f, "{{{}}}", name)?,
+                    }
+                    rest = &rest[end + 1..];
+                }
+                None => f.write_str(brace)?,
+            }
+        }
+
+        f.write_str(rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitute_args() {
+        let msg = Message::new("macro '{name}' takes {count} arguments")
+            .arg("name", MessageArg::symbol("FOO"))
+            .arg("count", 3u32);
+
+        assert_eq!(msg.to_string(), "macro 'FOO' takes 3 arguments");
+        assert_eq!(msg.template(), "macro '{name}' takes {count} arguments");
+        assert_eq!(msg.get_arg("count"), Some(&MessageArg::Int(3)));
+        assert_eq!(msg.get_arg("other"), None);
+    }
+
+    #[test]
+    fn paths() {
+        let msg = Message::new("include '{file}' not found").arg("file", Path::new("a/b.h"));
+        assert_eq!(msg.to_string(), "include 'a/b.h' not found");
+        assert_eq!(
+            msg.get_arg("file"),
+            Some(&MessageArg::Path(PathBuf::from("a/b.h")))
+        );
+    }
+
+    #[test]
+    fn escaped_braces() {
+        assert_eq!(Message::new("{{{x}}}").arg("x", 1u8).to_string(), "{1}");
+        assert_eq!(Message::new("{missing}").to_string(), "{missing}");
+
+        let msg = Message::from("expected '{' or '}'");
+        assert!(msg.args().is_empty());
+        assert_eq!(msg.to_string(), "expected '{' or '}'");
+    }
+}
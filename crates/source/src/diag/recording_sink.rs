@@ -0,0 +1,20 @@
+use std::cell::RefCell;
+
+use crate::SourceMap;
+
+use super::{RenderedDiagnostic, RenderedSink};
+
+/// A rendered diagnostic sink that records the level and main message of every diagnostic reported,
+/// formatted as `level: message`.
+///
+/// This sink is intended for tests checking the diagnostics reported by a component, and is only
+/// available with the `test-util` feature.
+pub struct RecordingSink<'a>(pub &'a RefCell<Vec<String>>);
+
+impl RenderedSink for RecordingSink<'_> {
+    fn report(&mut self, diag: &RenderedDiagnostic, _smap: Option<&SourceMap>) {
+        self.0
+            .borrow_mut()
+            .push(format!("{}: {}", diag.level(), diag.main().msg));
+    }
+}
@@ -9,11 +9,18 @@ use crate::smap::{ExpansionKind, SourceId};
 use crate::SourceMap;
 use crate::SourceRange;
 
-use super::{Diagnostic, RawDiagnostic, RenderedDiagnostic};
+use super::{Diagnostic, Message, RawDiagnostic, RenderedDiagnostic};
 use super::{Ranges, RawRanges, RenderedRanges};
 use super::{RawSubDiagnostic, RenderedSubDiagnostic};
 use super::{RawSuggestion, RenderedSuggestion};
 
+/// The message of the notes tracing the macro expansions leading to a diagnostic range.
+pub(crate) const EXPANSION_NOTE_MSG: &str = "expanded from here";
+
+/// The template of the note replacing the expansions omitted from a trace that exceeds the macro
+/// backtrace limit.
+pub(crate) const SKIPPED_EXPANSIONS_MSG: &str = "(skipping {count} expansions in backtrace)";
+
 /// Returns an iterator tracing through the expansions of `range`.
 ///
 /// This is almost like the caller chain, except that ranges in macro arguments are moved to point
@@ -63,13 +70,20 @@ fn get_spelling_range(smap: &SourceMap, range: SourceRange) -> SourceRange {
 
 /// Renders the provided ranges, returning the newly-rendered (outermost) ranges and a trace of the
 /// expansions leading up to them, ordered from outermost to innermost.
-fn render_ranges(ranges: &RawRanges, smap: &SourceMap) -> (RenderedRanges, Vec<RenderedRanges>) {
+///
+/// Returns `None` if the primary range does not lie within `smap`. Subranges that do not lie
+/// within `smap` are dropped.
+fn render_ranges(
+    ranges: &RawRanges,
+    smap: &SourceMap,
+) -> Option<(RenderedRanges, Vec<RenderedRanges>)> {
     type FxIndexMap<K, V> = IndexMap<K, V, BuildHasherDefault<FxHasher>>;
 
     // We always need a primary range, so arbitrarily fall back to the start if it spans multiple
     // files.
     let primary_range = smap
-        .get_unfragmented_range(ranges.primary_range)
+        .try_get_unfragmented_range(ranges.primary_range)
+        .ok()?
         .unwrap_or_else(|| ranges.primary_range.start.into());
 
     let mut expansion_map: FxIndexMap<_, _> = trace_expansions(primary_range, smap)
@@ -78,7 +92,9 @@ fn render_ranges(ranges: &RawRanges, smap: &SourceMap) -> (RenderedRanges, Vec<R
 
     for (range, label) in ranges.subranges.iter() {
         let expansions = smap
-            .get_unfragmented_range(*range)
+            .try_get_unfragmented_range(*range)
+            .ok()
+            .flatten()
             .into_iter()
             .flat_map(|range| trace_expansions(range, smap));
 
@@ -117,15 +133,15 @@ fn render_ranges(ranges: &RawRanges, smap: &SourceMap) -> (RenderedRanges, Vec<R
     let outermost = expansions.pop().unwrap();
     expansions.reverse();
 
-    (outermost, expansions)
+    Some((outermost, expansions))
 }
 
 /// Attemts to render the specified suggestion, returning `None` if there was no unambiguous or
 /// meaningful way to do so.
 fn render_suggestion(suggestion: &RawSuggestion, smap: &SourceMap) -> Option<RenderedSuggestion> {
     let range = suggestion.replacement_range;
-    let start_id = smap.lookup_source_id(range.start);
-    let end_id = smap.lookup_source_id(range.end);
+    let start_id = smap.try_lookup_source_id(range.start)?;
+    let end_id = smap.try_lookup_source_id(range.end)?;
 
     // Suggestions don't play very well with expansions - it is unclear exactly *where* along the
     // expansion stack the suggestion should be applied, and sometimes there is no good way to apply
@@ -137,6 +153,7 @@ fn render_suggestion(suggestion: &RawSuggestion, smap: &SourceMap) -> Option<Ren
     Some(RenderedSuggestion {
         replacement_range: SourceRange::new(range.start, range.end.offset_from(range.start)),
         insert_text: suggestion.insert_text.clone(),
+        applicability: suggestion.applicability,
     })
 }
 
@@ -145,7 +162,7 @@ fn render_anon_subdiag(raw: &RawSubDiagnostic) -> RenderedSubDiagnostic {
     RenderedSubDiagnostic {
         msg: raw.msg.clone(),
         ranges: None,
-        suggestion: None,
+        suggestions: Vec::new(),
     }
 }
 
@@ -154,6 +171,7 @@ fn render_anon_diag(raw: &RawDiagnostic) -> RenderedDiagnostic {
     RenderedDiagnostic {
         inner: Diagnostic {
             level: raw.level,
+            id: raw.id,
             main: render_anon_subdiag(&raw.main),
             notes: raw.notes.iter().map(render_anon_subdiag).collect(),
         },
@@ -161,38 +179,78 @@ fn render_anon_diag(raw: &RawDiagnostic) -> RenderedDiagnostic {
     }
 }
 
+/// Limits `expansions` to `limit` entries, keeping the outermost and innermost ones.
+///
+/// If any expansions were removed, returns the index at which they were removed along with their
+/// number. As with clang's `-fmacro-backtrace-limit`, the first half of the limit (rounded up) is
+/// taken from the start of the trace and the rest from its end.
+fn limit_expansions(
+    expansions: &mut Vec<RenderedRanges>,
+    limit: Option<u32>,
+) -> Option<(usize, usize)> {
+    let limit = limit.map(|limit| limit as usize)?;
+    if expansions.len() <= limit {
+        return None;
+    }
+
+    let skip_start = limit.div_ceil(2);
+    let skipped = expansions.len() - limit;
+    expansions.drain(skip_start..skip_start + skipped);
+    Some((skip_start, skipped))
+}
+
 /// Renders the provided subdiagnostic using the source map, returning the rendered primary
 /// subdiagnostic and any expansion subdiagnostics that may have been created.
+///
+/// If the expansion trace is longer than `macro_backtrace_limit`, expansions from its middle are
+/// replaced with a single note indicating how many were skipped.
 fn render_subdiag(
     raw: &RawSubDiagnostic,
     smap: &SourceMap,
+    macro_backtrace_limit: Option<u32>,
 ) -> (
     RenderedSubDiagnostic,
     impl Iterator<Item = RenderedSubDiagnostic>,
 ) {
-    let (main_subdiag, expansion_subdiags) = match &raw.ranges {
+    let rendered_ranges = raw
+        .ranges
+        .as_ref()
+        .and_then(|ranges| render_ranges(ranges, smap));
+
+    let (main_subdiag, expansion_subdiags) = match rendered_ranges {
         None => (render_anon_subdiag(raw), None),
-        Some(ranges) => {
-            let (primary_ranges, expansion_ranges) = render_ranges(ranges, smap);
-            let rendered_suggestion = raw
-                .suggestion
-                .as_ref()
-                .and_then(|sugg| render_suggestion(sugg, smap));
+        Some((primary_ranges, mut expansion_ranges)) => {
+            let rendered_suggestions = raw
+                .suggestions
+                .iter()
+                .filter_map(|sugg| render_suggestion(sugg, smap))
+                .collect();
 
             let main_subdiag = RenderedSubDiagnostic {
                 msg: raw.msg.clone(),
                 ranges: Some(primary_ranges),
-                suggestion: rendered_suggestion,
+                suggestions: rendered_suggestions,
             };
 
-            let expansion_subdiags =
-                expansion_ranges
-                    .into_iter()
-                    .map(|ranges| RenderedSubDiagnostic {
-                        msg: "expanded from here".into(),
-                        ranges: Some(ranges),
-                        suggestion: None,
-                    });
+            let skipped = limit_expansions(&mut expansion_ranges, macro_backtrace_limit);
+
+            let mut expansion_subdiags: Vec<_> = expansion_ranges
+                .into_iter()
+                .map(|ranges| RenderedSubDiagnostic {
+                    msg: EXPANSION_NOTE_MSG.into(),
+                    ranges: Some(ranges),
+                    suggestions: Vec::new(),
+                })
+                .collect();
+
+            if let Some((idx, count)) = skipped {
+                expansion_subdiags.insert(
+                    idx,
+                    RenderedSubDiagnostic::new_anon(
+                        Message::new(SKIPPED_EXPANSIONS_MSG).arg("count", count),
+                    ),
+                );
+            }
 
             (main_subdiag, Some(expansion_subdiags))
         }
@@ -202,8 +260,12 @@ fn render_subdiag(
 }
 
 /// Renders a diagnostic with location information.
-fn render_diag(raw: &RawDiagnostic, smap: &SourceMap) -> RenderedDiagnostic {
-    let (rendered_main, main_expansions) = render_subdiag(&raw.main, smap);
+fn render_diag(
+    raw: &RawDiagnostic,
+    smap: &SourceMap,
+    macro_backtrace_limit: Option<u32>,
+) -> RenderedDiagnostic {
+    let (rendered_main, main_expansions) = render_subdiag(&raw.main, smap, macro_backtrace_limit);
 
     let mut includes: Vec<_> = rendered_main
         .ranges
@@ -221,7 +283,8 @@ fn render_diag(raw: &RawDiagnostic, smap: &SourceMap) -> RenderedDiagnostic {
 
     let notes = main_expansions
         .chain(raw.notes.iter().flat_map(|note| {
-            let (rendered_note, note_expansions) = render_subdiag(note, smap);
+            let (rendered_note, note_expansions) =
+                render_subdiag(note, smap, macro_backtrace_limit);
             iter::once(rendered_note).chain(note_expansions)
         }))
         .collect();
@@ -229,6 +292,7 @@ fn render_diag(raw: &RawDiagnostic, smap: &SourceMap) -> RenderedDiagnostic {
     RenderedDiagnostic {
         inner: Diagnostic {
             level: raw.level,
+            id: raw.id,
             main: rendered_main,
             notes,
         },
@@ -240,11 +304,26 @@ fn render_diag(raw: &RawDiagnostic, smap: &SourceMap) -> RenderedDiagnostic {
 /// to resolve expansions and include traces.
 ///
 /// If no source map is provided, the rendered diagnostic will have no location information
-/// attached, even if the original did.
-///
-/// # Panics
-///
-/// This function may panic if any of the ranges in `raw` is invalid or malformed.
+/// attached, even if the original did. Similarly, subdiagnostics whose primary ranges do not lie
+/// within the source map are rendered without location information, and other ranges and
+/// suggestions that do not lie within it are dropped.
 pub fn render(raw: &RawDiagnostic, smap: Option<&SourceMap>) -> RenderedDiagnostic {
-    smap.map_or_else(|| render_anon_diag(raw), |smap| render_diag(raw, smap))
+    render_with_backtrace_limit(raw, smap, None)
+}
+
+/// Renders the provided raw diagnostic like [`render()`], but shows at most
+/// `macro_backtrace_limit` macro expansions in the trace of each subdiagnostic, if provided.
+///
+/// Longer traces keep their outermost and innermost expansions, with a note in place of the ones
+/// skipped in the middle (which is also considered
+/// [part of the trace](RenderedSubDiagnostic::is_expansion_trace)).
+pub fn render_with_backtrace_limit(
+    raw: &RawDiagnostic,
+    smap: Option<&SourceMap>,
+    macro_backtrace_limit: Option<u32>,
+) -> RenderedDiagnostic {
+    smap.map_or_else(
+        || render_anon_diag(raw),
+        |smap| render_diag(raw, smap, macro_backtrace_limit),
+    )
 }
@@ -0,0 +1,367 @@
+//! Collection and application of the suggestions attached to diagnostics.
+//!
+//! Suggestions are recorded in a [`Fixits`] set, usually by wrapping the diagnostic sink in a
+//! [`FixitSink`]. Once processing is complete, [`Fixits::apply()`] produces the patched contents of
+//! any file with suggested edits.
+
+use std::cell::RefCell;
+
+use indexmap::IndexMap;
+
+use crate::diag::{Applicability, RenderedDiagnostic, RenderedSink, RenderedSuggestion};
+use crate::{LocalRange, SourceId, SourceMap};
+
+/// An edit replacing a range of a file with new text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEdit {
+    /// The range within the file to replace. This is empty for pure insertions.
+    pub range: LocalRange,
+    /// The text to insert in place of `range`.
+    pub text: String,
+}
+
+impl FileEdit {
+    /// Checks whether this edit and `other` cannot both be applied.
+    ///
+    /// Edits conflict if they replace overlapping text, or if one inserts text strictly inside
+    /// the text replaced by the other. Distinct insertions at the same offset also conflict, as
+    /// there is no meaningful order in which to apply them.
+    fn conflicts_with(&self, other: &FileEdit) -> bool {
+        let (a, b) = (self.range, other.range);
+
+        (a.start() < b.end() && b.start() < a.end())
+            || (a.is_empty() && b.is_empty() && a.start() == b.start())
+    }
+}
+
+/// The result of applying the edits recorded for a file.
+#[derive(Debug, Clone)]
+pub struct FixedFile {
+    /// The patched contents of the file.
+    pub contents: String,
+    /// The number of edits applied.
+    pub applied: usize,
+    /// Edits that were not applied because they conflict with edits recorded before them.
+    pub conflicts: Vec<FileEdit>,
+}
+
+/// A set of edits to source files, collected from diagnostic suggestions.
+///
+/// Edits are grouped by file, in the order in which they were recorded. Duplicate edits (which
+/// commonly arise when the same diagnostic is reported several times) are only recorded once.
+#[derive(Debug, Default)]
+pub struct Fixits {
+    edits: IndexMap<SourceId, Vec<FileEdit>>,
+}
+
+impl Fixits {
+    /// Creates a new, empty set of edits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `true` if no edits have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    /// Returns the files for which edits have been recorded.
+    pub fn files(&self) -> impl Iterator<Item = SourceId> + '_ {
+        self.edits.keys().copied()
+    }
+
+    /// Returns the edits recorded for the file `id`, in the order in which they were recorded.
+    pub fn file_edits(&self, id: SourceId) -> &[FileEdit] {
+        self.edits.get(&id).map_or(&[], |edits| edits)
+    }
+
+    /// Records `suggestion`, using `smap` to map it to an offset range within its file.
+    ///
+    /// Rendered suggestions always lie within a single file, so this cannot fail.
+    pub fn add(&mut self, smap: &SourceMap, suggestion: &RenderedSuggestion) {
+        let range = suggestion.replacement_range;
+        let id = smap.lookup_source_id(range.start());
+        let file_range = smap.get_source(id).range;
+
+        let edit = FileEdit {
+            range: file_range
+                .local_range(range)
+                .expect("suggestion spans multiple sources"),
+            text: suggestion.insert_text.clone(),
+        };
+
+        let edits = self.edits.entry(id).or_default();
+        if !edits.contains(&edit) {
+            edits.push(edit);
+        }
+    }
+
+    /// Records the machine-applicable suggestions attached to `diag` and any of its notes.
+    ///
+    /// Suggestions with any other [`Applicability`] need to be reviewed by the user, and are never
+    /// recorded.
+    pub fn add_diag(&mut self, smap: &SourceMap, diag: &RenderedDiagnostic) {
+        let subdiags = std::iter::once(diag.main()).chain(diag.notes());
+        for suggestion in subdiags
+            .flat_map(|subdiag| &subdiag.suggestions)
+            .filter(|suggestion| suggestion.applicability == Applicability::MachineApplicable)
+        {
+            self.add(smap, suggestion);
+        }
+    }
+
+    /// Applies the edits recorded for the file `id`, returning its patched contents.
+    ///
+    /// Edits are considered in the order in which they were recorded; any edit conflicting with an
+    /// earlier one is skipped and reported in [`FixedFile::conflicts`].
+    ///
+    /// The edits are applied to the decoded source code of the file, so the result should only be
+    /// written back to the file with [`apply_verbatim()`](Self::apply_verbatim()).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` does not refer to a file.
+    pub fn apply(&self, smap: &SourceMap, id: SourceId) -> FixedFile {
+        let src = smap
+            .get_source(id)
+            .as_file()
+            .expect("fix-its can only be applied to files")
+            .contents
+            .src();
+
+        let mut accepted: Vec<&FileEdit> = Vec::new();
+        let mut conflicts = Vec::new();
+
+        for edit in self.file_edits(id) {
+            if accepted.iter().any(|prev| prev.conflicts_with(edit)) {
+                conflicts.push(edit.clone());
+            } else {
+                accepted.push(edit);
+            }
+        }
+
+        accepted.sort_by_key(|edit| (edit.range.start(), edit.range.end()));
+
+        let mut contents = String::with_capacity(src.len());
+        let mut last_end = 0;
+
+        for edit in &accepted {
+            let start: usize = edit.range.start().into();
+            contents.push_str(&src[last_end..start]);
+            contents.push_str(&edit.text);
+            last_end = edit.range.end().into();
+        }
+
+        contents.push_str(&src[last_end..]);
+
+        FixedFile {
+            contents,
+            applied: accepted.len(),
+            conflicts,
+        }
+    }
+
+    /// Applies the edits recorded for the file `id` like [`apply()`](Self::apply()), provided that
+    /// the result can be written back to the file without altering anything else in it.
+    ///
+    /// Returns `None` if the source code of the file differs from its raw contents (see
+    /// [`DecodeInfo::is_verbatim()`](crate::smap::DecodeInfo::is_verbatim())), as writing the
+    /// patched source code would also strip its byte order mark, normalize its line endings or
+    /// replace its invalid sequences.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` does not refer to a file.
+    pub fn apply_verbatim(&self, smap: &SourceMap, id: SourceId) -> Option<FixedFile> {
+        let file = smap
+            .get_source(id)
+            .as_file()
+            .expect("fix-its can only be applied to files");
+
+        if file.contents.decode_info().is_verbatim() {
+            Some(self.apply(smap, id))
+        } else {
+            None
+        }
+    }
+}
+
+/// A rendered diagnostic sink that records the suggestions of every diagnostic in a [`Fixits`]
+/// set before forwarding it to another sink.
+pub struct FixitSink<'a, S> {
+    inner: S,
+    fixits: &'a RefCell<Fixits>,
+}
+
+impl<'a, S> FixitSink<'a, S> {
+    /// Creates a new sink recording suggestions into `fixits` and forwarding diagnostics to
+    /// `inner`.
+    pub fn new(inner: S, fixits: &'a RefCell<Fixits>) -> Self {
+        Self { inner, fixits }
+    }
+}
+
+impl<S: RenderedSink> RenderedSink for FixitSink<'_, S> {
+    fn report(&mut self, diag: &RenderedDiagnostic, smap: Option<&SourceMap>) {
+        if let Some(smap) = smap {
+            self.fixits.borrow_mut().add_diag(smap, diag);
+        }
+        self.inner.report(diag, smap);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diag::{Level, RawSubDiagnostic, RawSuggestion};
+    use crate::smap::{FileContents, FileName};
+    use crate::{DiagManager, SourceRange};
+
+    use super::*;
+
+    struct NullSink;
+
+    impl RenderedSink for NullSink {
+        fn report(&mut self, _diag: &RenderedDiagnostic, _smap: Option<&SourceMap>) {}
+    }
+
+    fn file(smap: &mut SourceMap, src: &str) -> (SourceId, impl Fn(u32, u32) -> SourceRange) {
+        let id = smap
+            .create_file(FileName::synth("test.c"), FileContents::new(src), None)
+            .unwrap();
+        let start = smap.get_source(id).range.start();
+        (id, move |off: u32, len: u32| {
+            SourceRange::new(start.offset(off.into()), len.into())
+        })
+    }
+
+    #[test]
+    fn applies_edits_in_order() {
+        let mut smap = SourceMap::new();
+        let (id, at) = file(&mut smap, "int x = f(a)\nint y\n");
+
+        let mut fixits = Fixits::new();
+        fixits.add(&smap, &RenderedSuggestion::new(at(18, 0), ";"));
+        fixits.add(&smap, &RenderedSuggestion::new(at(12, 0), ";"));
+        fixits.add(&smap, &RenderedSuggestion::new(at(8, 1), "g"));
+        fixits.add(&smap, &RenderedSuggestion::new_deletion(at(0, 4)));
+
+        let fixed = fixits.apply(&smap, id);
+        assert_eq!(fixed.contents, "x = g(a);\nint y;\n");
+        assert_eq!(fixed.applied, 4);
+        assert!(fixed.conflicts.is_empty());
+    }
+
+    #[test]
+    fn skips_conflicts() {
+        let mut smap = SourceMap::new();
+        let (id, at) = file(&mut smap, "abcdef");
+
+        let mut fixits = Fixits::new();
+        fixits.add(&smap, &RenderedSuggestion::new(at(1, 3), "X"));
+        // Duplicates are ignored.
+        fixits.add(&smap, &RenderedSuggestion::new(at(1, 3), "X"));
+        // Overlapping replacements conflict.
+        fixits.add(&smap, &RenderedSuggestion::new(at(3, 2), "Y"));
+        // So do insertions inside replaced text.
+        fixits.add(&smap, &RenderedSuggestion::new(at(2, 0), "Z"));
+        // Insertions at either end of a replacement are fine.
+        fixits.add(&smap, &RenderedSuggestion::new(at(1, 0), "<"));
+        fixits.add(&smap, &RenderedSuggestion::new(at(4, 0), ">"));
+        // But two different insertions at the same offset are ambiguous.
+        fixits.add(&smap, &RenderedSuggestion::new(at(4, 0), "!"));
+
+        let fixed = fixits.apply(&smap, id);
+        assert_eq!(fixed.contents, "a<X>ef");
+        assert_eq!(fixed.applied, 3);
+        assert_eq!(
+            fixed.conflicts,
+            [
+                FileEdit {
+                    range: LocalRange::at(3.into(), 2.into()),
+                    text: "Y".into()
+                },
+                FileEdit {
+                    range: LocalRange::at(2.into(), 0.into()),
+                    text: "Z".into()
+                },
+                FileEdit {
+                    range: LocalRange::at(4.into(), 0.into()),
+                    text: "!".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn refuses_lossy_files() {
+        let mut smap = SourceMap::new();
+        let mut add_file = |raw: &[u8]| {
+            let id = smap
+                .create_file(
+                    FileName::synth("test.c"),
+                    FileContents::decode(raw.to_vec()),
+                    None,
+                )
+                .unwrap();
+            (id, smap.get_source(id).range.start())
+        };
+
+        let files = [
+            add_file(b"int x\n"),
+            add_file(b"int x\r\nint y\r\n"),
+            add_file(b"\xef\xbb\xbfint x\n"),
+            add_file(b"int x\n// \xff\n"),
+        ];
+
+        let mut fixits = Fixits::new();
+        for &(_, start) in &files {
+            fixits.add(
+                &smap,
+                &RenderedSuggestion::new(SourceRange::new(start.offset(5.into()), 0.into()), ";"),
+            );
+        }
+
+        let (verbatim, _) = files[0];
+        assert_eq!(
+            fixits.apply_verbatim(&smap, verbatim).unwrap().contents,
+            "int x;\n"
+        );
+        for &(id, _) in &files[1..] {
+            assert!(fixits.apply_verbatim(&smap, id).is_none());
+        }
+    }
+
+    #[test]
+    fn sink_collects_suggestions() {
+        let mut smap = SourceMap::new();
+        let (id, at) = file(&mut smap, "int x\n");
+
+        let fixits = RefCell::new(Fixits::new());
+        let mut manager = DiagManager::new(FixitSink::new(NullSink, &fixits), None);
+
+        manager
+            .reporter(&smap)
+            .error(at(5, 0), "expected ';'")
+            .add_suggestion(RawSuggestion::new(at(5, 0), ";"))
+            .add_suggestion(
+                RawSuggestion::new(at(5, 0), " = 0;")
+                    .with_applicability(Applicability::MaybeIncorrect),
+            )
+            .add_note(
+                RawSubDiagnostic::new("or remove this", at(4, 1).into())
+                    .with_suggestion(RawSuggestion::new_deletion(at(3, 2))),
+            )
+            .emit()
+            .unwrap();
+        manager
+            .report_anon(Level::Warning, "no location")
+            .emit()
+            .unwrap();
+        drop(manager);
+
+        let fixits = fixits.into_inner();
+        assert_eq!(fixits.files().collect::<Vec<_>>(), [id]);
+        assert_eq!(fixits.file_edits(id).len(), 2);
+        assert_eq!(fixits.apply(&smap, id).contents, "int;\n");
+    }
+}
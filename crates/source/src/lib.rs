@@ -3,10 +3,14 @@
 //! A library for managing source files, locations and diagnostics.
 
 pub mod diag;
+pub mod fixit;
+pub mod limits;
 pub mod smap;
+pub mod sync;
 
 mod pos;
 
 pub use diag::{Manager as DiagManager, Reporter as DiagReporter, Result as DResult};
+pub use limits::FrontendLimits;
 pub use pos::*;
 pub use smap::{SourceId, SourceMap};
@@ -0,0 +1,91 @@
+//! Resource limits shared by the different stages of the frontend.
+
+/// The default value of [`FrontendLimits::max_literal_len`].
+pub const DEFAULT_MAX_LITERAL_LEN: usize = 1 << 20;
+
+/// The default value of [`FrontendLimits::max_embed_size`].
+pub const DEFAULT_MAX_EMBED_SIZE: usize = 16 << 20;
+
+/// The default value of [`FrontendLimits::macro_backtrace_limit`], matching clang.
+pub const DEFAULT_MACRO_BACKTRACE_LIMIT: u32 = 6;
+
+/// The default value of [`FrontendLimits::max_bracket_depth`], matching clang's `-fbracket-depth`.
+pub const DEFAULT_MAX_BRACKET_DEPTH: usize = 256;
+
+/// The default value of [`FrontendLimits::max_expansion_depth`], matching clang's default
+/// `-fbracket-depth`.
+pub const DEFAULT_MAX_EXPANSION_DEPTH: usize = 256;
+
+/// The default value of [`FrontendLimits::max_include_depth`], matching clang's default.
+pub const DEFAULT_MAX_INCLUDE_DEPTH: usize = 200;
+
+/// Limits on the resources consumed while processing a translation unit.
+///
+/// A single instance of this structure is usually constructed by the driver and handed to the
+/// different components: the diagnostic limits and warning policy are applied by [`DiagManager::with_limits()`], and
+/// the remaining limits are read from the [`LexCtx`](../../lex/struct.LexCtx.html) passed to
+/// lexers and the preprocessor.
+///
+/// [`DiagManager::with_limits()`]: crate::DiagManager::with_limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrontendLimits {
+    /// The maximum length, in bytes, of string and character literals whose spellings will be
+    /// interned.
+    ///
+    /// Longer literals are interned as a short, truncated preview instead (with a warning), to
+    /// avoid copying huge blobs into the interner.
+    pub max_literal_len: usize,
+    /// The maximum size of a resource embedded with `#embed`, in bytes. Larger resources are
+    /// reported unless a `limit` parameter truncates them.
+    pub max_embed_size: usize,
+    /// The maximum number of tokens to output before stopping, if any.
+    pub token_limit: Option<u64>,
+    /// The maximum number of diagnostics to show for any single source line, if any.
+    pub max_diagnostics_per_line: Option<u32>,
+    /// The number of errors after which processing is aborted with a fatal error, if any.
+    pub error_limit: Option<u32>,
+    /// The maximum number of macro expansions to show in the trace of any diagnostic, if any.
+    ///
+    /// Longer traces show their outermost and innermost expansions, with a note indicating how many
+    /// were skipped in between.
+    pub macro_backtrace_limit: Option<u32>,
+    /// The maximum nesting depth of parenthesized subexpressions and operands of prefix operators
+    /// in an expression, as well as of declarators, initializer lists and statements.
+    ///
+    /// These are parsed recursively, so deeper nesting is reported as a fatal error instead of
+    /// exhausting the stack.
+    pub max_bracket_depth: usize,
+    /// The maximum number of macro replacements (including pre-expanded macro arguments) that may
+    /// be in flight at once.
+    ///
+    /// Every level of invocations nested in macro arguments keeps the pre-expansion of its
+    /// argument in flight, so this bounds the memory used by pathological inputs. Deeper expansions
+    /// are reported as a fatal error.
+    pub max_expansion_depth: usize,
+    /// The maximum number of nested includes. Including a file beyond this depth is reported as a
+    /// fatal error, which stops runaway recursive includes.
+    pub max_include_depth: usize,
+    /// Whether warnings should be reported as errors.
+    pub warnings_as_errors: bool,
+    /// Whether warnings should be suppressed entirely. This takes precedence over
+    /// [`warnings_as_errors`](Self::warnings_as_errors).
+    pub ignore_warnings: bool,
+}
+
+impl Default for FrontendLimits {
+    fn default() -> Self {
+        Self {
+            max_literal_len: DEFAULT_MAX_LITERAL_LEN,
+            max_embed_size: DEFAULT_MAX_EMBED_SIZE,
+            token_limit: None,
+            max_diagnostics_per_line: None,
+            error_limit: None,
+            macro_backtrace_limit: Some(DEFAULT_MACRO_BACKTRACE_LIMIT),
+            max_bracket_depth: DEFAULT_MAX_BRACKET_DEPTH,
+            max_expansion_depth: DEFAULT_MAX_EXPANSION_DEPTH,
+            max_include_depth: DEFAULT_MAX_INCLUDE_DEPTH,
+            warnings_as_errors: false,
+            ignore_warnings: false,
+        }
+    }
+}
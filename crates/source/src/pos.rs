@@ -1,21 +1,36 @@
 pub use text_size::{TextRange as LocalRange, TextSize as LocalOff};
 
+/// The integer type underlying [`SourcePos`].
+///
+/// This is `u32` by default, which limits the combined size of all sources in a
+/// [`crate::SourceMap`] to 4 GiB. Translation units with heavy macro expansion can exceed this;
+/// enabling the `wide-positions` feature makes it `u64` instead, at the cost of larger positions
+/// and ranges. Individual sources are still limited to 4 GiB either way.
+#[cfg(not(feature = "wide-positions"))]
+pub type RawPos = u32;
+/// The integer type underlying [`SourcePos`].
+///
+/// This is `u64` as the `wide-positions` feature is enabled.
+#[cfg(feature = "wide-positions")]
+pub type RawPos = u64;
+
 /// An opaque type representing a position in the source code managed by a
 /// [`crate::SourceMap`].
 ///
 /// This can be resolved back to file/line/column/expansion information using the appropriate
 /// methods on `SourceMap`.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
-pub struct SourcePos(u32);
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SourcePos(RawPos);
 
 impl SourcePos {
     #[inline]
-    pub(crate) fn from_raw(raw: u32) -> Self {
+    pub(crate) fn from_raw(raw: RawPos) -> Self {
         SourcePos(raw)
     }
 
     #[inline]
-    pub(crate) fn to_raw(self) -> u32 {
+    pub(crate) fn to_raw(self) -> RawPos {
         self.0
     }
 
@@ -25,7 +40,7 @@ impl SourcePos {
     /// containing `self` does not contain at least `offset` more bytes.
     #[inline]
     pub fn offset(self, offset: LocalOff) -> Self {
-        SourcePos(self.0 + u32::from(offset))
+        SourcePos(self.0 + RawPos::from(u32::from(offset)))
     }
 
     /// Returns the distance in bytes between `self` and `rhs`, assuming that `rhs` lies before
@@ -37,10 +52,25 @@ impl SourcePos {
     #[inline]
     pub fn offset_from(self, rhs: SourcePos) -> LocalOff {
         assert!(rhs <= self);
-        (self.to_raw() - rhs.to_raw()).into()
+        raw_distance_to_local(self.to_raw() - rhs.to_raw())
     }
 }
 
+/// Converts the distance between two positions in the same source to a local offset.
+#[cfg(not(feature = "wide-positions"))]
+#[inline]
+fn raw_distance_to_local(distance: RawPos) -> LocalOff {
+    distance.into()
+}
+
+/// Converts the distance between two positions in the same source to a local offset.
+#[cfg(feature = "wide-positions")]
+#[inline]
+fn raw_distance_to_local(distance: RawPos) -> LocalOff {
+    // Positions within a single source are never more than `u32::MAX` bytes apart.
+    (distance as u32).into()
+}
+
 /// Represents a contiguous byte range within a single [source](crate::smap#sources).
 ///
 /// Contrast with [`FragmentedSourceRange`], which can represent ranges whose endpoints lie within
@@ -51,6 +81,7 @@ impl SourcePos {
 /// `SourceRange` is also useful when displaying diagnostics, where one wants to indicate actual
 /// ranges in the source code.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SourceRange(SourcePos, LocalOff);
 
 impl SourceRange {
@@ -161,6 +192,7 @@ impl From<SourcePos> for SourceRange {
 /// can be used to try to convert a fragmented range to a contiguous range covering both its
 /// endpoints (possibly after macro expansion).
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FragmentedSourceRange {
     /// The starting position of the range.
     pub start: SourcePos,
@@ -194,6 +226,7 @@ impl From<SourceRange> for FragmentedSourceRange {
 
 /// Represents a simple line-column number pair.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LineCol {
     /// Zero-based line number.
     pub line: u32,
@@ -48,18 +48,29 @@
 //!
 //! Spelling ranges can also point into expansions when macros pass arguments to other macros.
 
-use std::cmp;
+use std::cmp::{self, Ordering};
 use std::convert::TryFrom;
+use std::fmt;
 use std::iter;
+use std::mem;
 use std::option::Option;
-use std::rc::Rc;
 use std::vec::Vec;
 
+pub use self::id_map::{SourceIdMap, SourceIdVec};
 pub use self::source::{
-    ExpansionKind, ExpansionSourceInfo, FileContents, FileName, FileSourceInfo, Source, SourceInfo,
+    DecodeInfo, ExpansionKind, ExpansionSourceInfo, FileContents, FileName, FileSourceInfo, Source,
+    SourceEncoding, SourceInfo,
 };
-use crate::{FragmentedSourceRange, LineCol, LocalOff, LocalRange, SourcePos, SourceRange};
+use rustc_hash::FxHashSet;
 
+use crate::sync::Lrc;
+use crate::{FragmentedSourceRange, LineCol, LocalOff, LocalRange, RawPos, SourcePos, SourceRange};
+
+mod id_map;
+#[cfg(test)]
+mod invariants;
+#[cfg(feature = "serde")]
+mod persist;
 mod source;
 
 #[cfg(test)]
@@ -67,6 +78,7 @@ mod tests;
 
 /// An opaque identifier representing a source in a [`SourceMap`].
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SourceId(usize);
 
 /// A structure representing a line of source code with a highlighted range.
@@ -96,6 +108,18 @@ impl<'f> InterpretedFileRange<'f> {
         &self.file.filename
     }
 
+    /// Returns the presumed filename at the start of the interpreted range, which may have been
+    /// changed by a `#line` directive.
+    pub fn presumed_filename(&self) -> &FileName {
+        self.file.presumed_linecol(self.start_linecol()).0
+    }
+
+    /// Returns the presumed line-column pair at which the range starts, which may have been
+    /// changed by a `#line` directive.
+    pub fn presumed_start_linecol(&self) -> LineCol {
+        self.file.presumed_linecol(self.start_linecol()).1
+    }
+
     /// Returns the include position of the interpreted range's file, if any.
     pub fn include_pos(&self) -> Option<SourcePos> {
         self.file.include_pos
@@ -122,9 +146,16 @@ impl<'f> InterpretedFileRange<'f> {
         let start_linecol = self.start_linecol();
         let end_linecol = self.end_linecol();
 
-        self.contents()
-            .get_lines(start_linecol.line, end_linecol.line)
-            .lines()
+        let lines = self
+            .contents()
+            .get_lines(start_linecol.line, end_linecol.line);
+
+        // Don't use `str::lines()` here, as it yields nothing for an empty last line (such as
+        // the one containing the end of a file that ends in a newline).
+        lines
+            .strip_suffix('\n')
+            .unwrap_or(lines)
+            .split('\n')
             .zip(0..)
             .map(move |(line, idx)| {
                 let last_line = end_linecol.line - start_linecol.line;
@@ -145,11 +176,113 @@ impl<'f> InterpretedFileRange<'f> {
     }
 }
 
+/// A position within a file, resolved to its filename and line/column information.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileLocation {
+    /// The ID of the file source containing the position.
+    pub source: SourceId,
+    /// The position itself.
+    pub pos: SourcePos,
+    /// The (zero-based) line and column at which the position lies in the file.
+    pub linecol: LineCol,
+    /// The presumed filename at the position, which may have been changed by a `#line` directive.
+    pub presumed_filename: FileName,
+    /// The presumed line and column at the position, which may have been changed by a `#line`
+    /// directive.
+    pub presumed_linecol: LineCol,
+}
+
+/// A single level of the macro expansions leading to a position, as reported by
+/// [`SourceMap::describe_location()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpansionLocation {
+    /// The ID of the expansion source at this level.
+    pub source: SourceId,
+    /// The kind of expansion performed.
+    pub kind: ExpansionKind,
+    /// The location at which the code at this level was spelled.
+    pub spelling: FileLocation,
+    /// The location at which the expansion was performed, as spelled in the code.
+    pub replacement: FileLocation,
+}
+
+/// A full description of where a position came from, as returned by
+/// [`SourceMap::describe_location()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocationDescription {
+    /// The file location at which the position ends up once all expansions have been performed.
+    pub file: FileLocation,
+    /// The expansions leading to the position, from innermost to outermost. This is empty if the
+    /// position points directly into a file.
+    pub expansions: Vec<ExpansionLocation>,
+}
+
 /// Error type indicating that a source could not be added because there were not enough unused
 /// positions to cover it.
 #[derive(Debug)]
 pub struct SourcesTooLargeError;
 
+/// Error type describing why a checked lookup in a [`SourceMap`] failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LookupError {
+    /// A position does not lie within any source in the map.
+    OutOfBounds,
+    /// A range does not lie within a single source.
+    CrossesSources,
+    /// A range was required to point into a file, but points into an expansion.
+    NotFile,
+    /// A range extends past the end of the contents of the file in which it is spelled.
+    PastContents,
+}
+
+impl fmt::Display for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            LookupError::OutOfBounds => "position does not lie within the source map",
+            LookupError::CrossesSources => "range does not lie within a single source",
+            LookupError::NotFile => "range does not point into a file",
+            LookupError::PastContents => "range extends past the end of the file contents",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for LookupError {}
+
+/// Statistics describing the sources in a [`SourceMap`], as returned by [`SourceMap::stats()`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SourceMapStats {
+    /// The number of file sources. Files included several times are counted once per inclusion.
+    pub file_sources: usize,
+    /// The number of expansion sources.
+    pub expansion_sources: usize,
+    /// The total length of all file sources, in bytes.
+    pub file_bytes: u64,
+    /// The total length of all expansion sources, in bytes.
+    pub expansion_bytes: u64,
+    /// The total number of positions allocated to sources, including the sentinel position at the
+    /// end of each.
+    pub mapped_bytes: u64,
+}
+
+impl fmt::Display for SourceMapStats {
+    /// Formats a human-readable report of the statistics, one per line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "source map contents:")?;
+        writeln!(
+            f,
+            "  {:<20} {:>12} ({} bytes)",
+            "file sources", self.file_sources, self.file_bytes
+        )?;
+        writeln!(
+            f,
+            "  {:<20} {:>12} ({} bytes)",
+            "expansion sources", self.expansion_sources, self.expansion_bytes
+        )?;
+        writeln!(f, "  {:<20} {:>12} bytes", "mapped", self.mapped_bytes)
+    }
+}
+
 /// A structure holding the source code used in a compilation.
 ///
 /// See the module-level documentation for a higher-level explanation of the `SourceMap`'s
@@ -160,13 +293,25 @@ pub struct SourcesTooLargeError;
 /// Unless otherwise specified, all methods taking a [`SourcePos`] or [`SourceRange`] will panic if
 /// provided an invalid value (i.e. one that does not lie in the map, or, in the case of ranges, one
 /// that crosses source boundaries).
+///
+/// # Checked Lookups
+///
+/// The panicking methods are intended for use within the compiler itself, where every position
+/// handed to the map is known to have come from it. Embedders that hold on to positions or IDs
+/// whose validity they cannot guarantee (for example, across incremental edits that rebuild the
+/// map) should use the `try_` variants instead. The basic lookups return `None` on invalid input:
+/// [`Self::try_get_source()`], [`Self::try_lookup_source_id()`],
+/// [`Self::try_lookup_source_off()`] and [`Self::try_lookup_source_range()`]. Higher-level queries
+/// report why they failed with a [`LookupError`]: [`Self::try_get_spelling_pos()`],
+/// [`Self::try_get_spelling()`], [`Self::try_get_interpreted_range()`] and
+/// [`Self::try_get_unfragmented_range()`].
 #[derive(Default)]
 pub struct SourceMap {
     /// A flat list of the sources in the map. These are stored in order of increasing starting
     /// position, to enable binary search for position-based lookup.
     sources: Vec<Source>,
     /// The next offset available for use as a starting position.
-    next_offset: u32,
+    next_offset: RawPos,
 }
 
 impl SourceMap {
@@ -193,13 +338,15 @@ impl SourceMap {
         let len = len.checked_add(1).ok_or(SourcesTooLargeError)?;
 
         let off = self.next_offset;
-        self.next_offset = off.checked_add(len).ok_or(SourcesTooLargeError)?;
+        self.next_offset = off
+            .checked_add(RawPos::from(len))
+            .ok_or(SourcesTooLargeError)?;
 
         let range = SourceRange::new(SourcePos::from_raw(off), len.into());
 
         let id = SourceId(self.sources.len());
         self.sources.push(Source {
-            info: Box::new(ctor()),
+            info: ctor(),
             range,
         });
 
@@ -224,7 +371,7 @@ impl SourceMap {
     pub fn create_file(
         &mut self,
         filename: FileName,
-        contents: Rc<FileContents>,
+        contents: Lrc<FileContents>,
         include_pos: Option<SourcePos>,
     ) -> Result<SourceId, SourcesTooLargeError> {
         #[cfg(debug_assertions)]
@@ -234,10 +381,16 @@ impl SourceMap {
             assert!(self.lookup_source_off(pos).0.is_file());
         }
 
-        let len = u32::try_from(contents.src.len()).map_err(|_| SourcesTooLargeError)?;
+        let len = u32::try_from(contents.src().len()).map_err(|_| SourcesTooLargeError)?;
 
         self.add_source(
-            || SourceInfo::File(FileSourceInfo::new(filename, contents, include_pos)),
+            || {
+                SourceInfo::File(Box::new(FileSourceInfo::new(
+                    filename,
+                    contents,
+                    include_pos,
+                )))
+            },
             len,
         )
     }
@@ -282,6 +435,117 @@ impl SourceMap {
         )
     }
 
+    /// Creates an expansion source of kind `kind` into `replacement_range` for every range in
+    /// `spelling_ranges`, as when the (possibly discontiguous) tokens of a macro argument are
+    /// expanded.
+    ///
+    /// If there is enough room in the map for all of the expansions, returns the IDs of the
+    /// newly-created sources, in the order of `spelling_ranges`. Otherwise, returns a
+    /// [`SourcesTooLargeError`] without creating any of them.
+    ///
+    /// This is equivalent to calling [`Self::create_expansion()`] for each spelling range, but
+    /// validates `replacement_range` and reserves room in the map only once.
+    ///
+    /// # Panics
+    ///
+    /// This function may panic if any of the ranges is invalid or empty.
+    pub fn create_expansions(
+        &mut self,
+        spelling_ranges: &[SourceRange],
+        replacement_range: SourceRange,
+        kind: ExpansionKind,
+    ) -> Result<Vec<SourceId>, SourcesTooLargeError> {
+        assert!(!replacement_range.is_empty());
+
+        if cfg!(debug_assertions) {
+            // See `create_expansion`.
+            self.lookup_source_range(replacement_range);
+            for &spelling_range in spelling_ranges {
+                self.lookup_source_range(spelling_range);
+            }
+        }
+
+        // Make sure that all of the sources fit before creating any of them, including the
+        // sentinel position of each.
+        let total_len = spelling_ranges
+            .iter()
+            .try_fold(0 as RawPos, |total, range| {
+                assert!(!range.is_empty());
+                let len = RawPos::from(u32::from(range.len())).checked_add(1)?;
+                total.checked_add(len)
+            })
+            .ok_or(SourcesTooLargeError)?;
+        self.next_offset
+            .checked_add(total_len)
+            .ok_or(SourcesTooLargeError)?;
+
+        self.sources.reserve(spelling_ranges.len());
+
+        Ok(spelling_ranges
+            .iter()
+            .map(|&spelling_range| {
+                self.add_source(
+                    || {
+                        SourceInfo::Expansion(ExpansionSourceInfo::new(
+                            spelling_range,
+                            replacement_range,
+                            kind,
+                        ))
+                    },
+                    spelling_range.len().into(),
+                )
+                .expect("room for expansions was checked")
+            })
+            .collect())
+    }
+
+    /// Returns an estimate of the number of bytes of heap memory used by the map, including the
+    /// contents of every file it references.
+    ///
+    /// Contents shared by several file sources (such as a header included multiple times) are only
+    /// counted once.
+    pub fn heap_size(&self) -> usize {
+        let mut seen_contents = FxHashSet::default();
+
+        let file_size: usize = self
+            .sources
+            .iter()
+            .filter_map(Source::as_file)
+            .map(|file| {
+                let contents_size = if seen_contents.insert(Lrc::as_ptr(&file.contents)) {
+                    file.contents.heap_size()
+                } else {
+                    0
+                };
+
+                mem::size_of::<FileSourceInfo>() + contents_size
+            })
+            .sum();
+
+        self.sources.capacity() * mem::size_of::<Source>() + file_size
+    }
+
+    /// Makes the line starting at `pos` presumed to be line number `line` (zero-based), and to come
+    /// from `filename` if it is provided. This is used to implement `#line` directives.
+    ///
+    /// Diagnostics and other consumers of presumed locations will use the new line numbers for all
+    /// following lines in the file, but spellings are still retrieved from the physical source.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pos` does not point into a file source, or if it precedes a line override
+    /// already added to the file.
+    pub fn add_line_override(&mut self, pos: SourcePos, line: u32, filename: Option<FileName>) {
+        let id = self.lookup_source_id(pos);
+        let source = &mut self.sources[id.0];
+        let off = source.local_off(pos);
+
+        match &mut source.info {
+            SourceInfo::File(file) => file.add_line_override(off, line, filename),
+            SourceInfo::Expansion(_) => panic!("line overrides require a file position"),
+        }
+    }
+
     /// Gets a source by its ID.
     ///
     /// # Panics
@@ -293,16 +557,44 @@ impl SourceMap {
         &self.sources[id.0]
     }
 
+    /// Gets a source by its ID, returning `None` if the map does not contain a source with the
+    /// specified ID.
+    #[inline]
+    pub fn try_get_source(&self, id: SourceId) -> Option<&Source> {
+        self.sources.get(id.0)
+    }
+
+    /// Returns the number of sources in the map.
+    #[inline]
+    pub fn source_count(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// Returns an iterator over the IDs of all sources in the map, in the order in which they were
+    /// created.
+    pub fn source_ids(&self) -> impl ExactSizeIterator<Item = SourceId> + DoubleEndedIterator {
+        (0..self.sources.len()).map(SourceId)
+    }
+
     /// Looks up the ID of the source containing `pos`.
     pub fn lookup_source_id(&self, pos: SourcePos) -> SourceId {
-        let last = self.sources.last().unwrap();
-        assert!(pos <= last.range.end());
+        self.try_lookup_source_id(pos)
+            .expect("position does not lie within the source map")
+    }
+
+    /// Looks up the ID of the source containing `pos`, returning `None` if it does not lie within
+    /// the map.
+    pub fn try_lookup_source_id(&self, pos: SourcePos) -> Option<SourceId> {
+        let last = self.sources.last()?;
+        if pos > last.range.end() {
+            return None;
+        }
 
-        SourceId(
+        Some(SourceId(
             self.sources
                 .binary_search_by_key(&pos, |source| source.range.start())
                 .unwrap_or_else(|i| i - 1),
-        )
+        ))
     }
 
     /// Looks up the source containing `pos` and the offset at which `pos` lies within it.
@@ -312,6 +604,14 @@ impl SourceMap {
         (source, off)
     }
 
+    /// Looks up the source containing `pos` and the offset at which `pos` lies within it, returning
+    /// `None` if `pos` does not lie within the map.
+    pub fn try_lookup_source_off(&self, pos: SourcePos) -> Option<(&Source, LocalOff)> {
+        let source = self.get_source(self.try_lookup_source_id(pos)?);
+        let off = source.range.local_off(pos)?;
+        Some((source, off))
+    }
+
     /// Looks up the source containing `range` and local range that `range` occupies within it.
     pub fn lookup_source_range(&self, range: SourceRange) -> (&Source, LocalRange) {
         let source = self.get_source(self.lookup_source_id(range.start()));
@@ -319,6 +619,103 @@ impl SourceMap {
         (source, local_range)
     }
 
+    /// Looks up the source containing `range` and local range that `range` occupies within it,
+    /// returning `None` if `range` does not lie within the map or crosses source boundaries.
+    pub fn try_lookup_source_range(&self, range: SourceRange) -> Option<(&Source, LocalRange)> {
+        let source = self.get_source(self.try_lookup_source_id(range.start())?);
+        let local_range = source.range.local_range(range)?;
+        Some((source, local_range))
+    }
+
+    /// Looks up the source containing `range` like [`Self::try_lookup_source_range()`], but reports
+    /// why the lookup failed.
+    fn checked_lookup_source_range(
+        &self,
+        range: SourceRange,
+    ) -> Result<(&Source, LocalRange), LookupError> {
+        let id = self
+            .try_lookup_source_id(range.start())
+            .ok_or(LookupError::OutOfBounds)?;
+        let source = self.get_source(id);
+        let local_range = source
+            .range
+            .local_range(range)
+            .ok_or(LookupError::CrossesSources)?;
+        Ok((source, local_range))
+    }
+
+    /// Returns an iterator over all file sources in the map and their IDs, in the order in which
+    /// they were created.
+    ///
+    /// A file included several times has a separate source for every inclusion, all of which are
+    /// listed.
+    pub fn file_sources(&self) -> impl DoubleEndedIterator<Item = (SourceId, &FileSourceInfo)> {
+        self.sources
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, source)| Some((SourceId(idx), source.as_file()?)))
+    }
+
+    /// Computes statistics describing the sources in the map.
+    ///
+    /// This walks every source in the map, so it should not be called in performance-sensitive
+    /// code.
+    pub fn stats(&self) -> SourceMapStats {
+        let mut stats = SourceMapStats::default();
+
+        for source in &self.sources {
+            stats.mapped_bytes += u64::from(u32::from(source.range.len()));
+            match &source.info {
+                SourceInfo::File(file) => {
+                    stats.file_sources += 1;
+                    stats.file_bytes += file.contents.src().len() as u64;
+                }
+                SourceInfo::Expansion(exp) => {
+                    stats.expansion_sources += 1;
+                    stats.expansion_bytes += u64::from(u32::from(exp.spelling_range.len()));
+                }
+            }
+        }
+
+        stats
+    }
+
+    /// Returns an iterator over the IDs of the file sources named `filename`, in the order in which
+    /// they were created.
+    ///
+    /// A file included several times has a separate source for every inclusion, all of which are
+    /// listed. Names are compared exactly, without normalizing paths.
+    pub fn file_sources_named<'a>(
+        &'a self,
+        filename: &'a FileName,
+    ) -> impl Iterator<Item = SourceId> + 'a {
+        self.file_sources()
+            .filter(move |(_, file)| &file.filename == filename)
+            .map(|(id, _)| id)
+    }
+
+    /// Finds the position at the physical (zero-based) line and column `linecol` in the file
+    /// source `id`.
+    ///
+    /// Returns `None` if `id` does not refer to a file source, or if `linecol` does not lie within
+    /// the file (see [`FileContents::try_get_off()`]).
+    pub fn lookup_file_pos(&self, id: SourceId, linecol: LineCol) -> Option<SourcePos> {
+        let source = self.try_get_source(id)?;
+        let off = source.as_file()?.contents.try_get_off(linecol)?;
+        Some(source.range.subpos(off))
+    }
+
+    /// Finds the position at the physical (zero-based) line and column `linecol` in the file named
+    /// `filename`, as for a user-supplied location.
+    ///
+    /// If the file was included several times, the position within its first inclusion is
+    /// returned; use [`Self::file_sources_named()`] and [`Self::lookup_file_pos()`] to find the
+    /// positions in all of them.
+    pub fn lookup_pos(&self, filename: &FileName, linecol: LineCol) -> Option<SourcePos> {
+        let id = self.file_sources_named(filename).next()?;
+        self.lookup_file_pos(id, linecol)
+    }
+
     /// Creates an iterator listing the includer chain of the file containing `pos`, from innermost
     /// to outermost.
     ///
@@ -380,6 +777,14 @@ impl SourceMap {
         self.get_spelling_chain(pos).last().unwrap().1
     }
 
+    /// Gets the outermost spelling position corresponding to `pos` like
+    /// [`Self::get_spelling_pos()`], returning an error if `pos` does not lie within the map.
+    pub fn try_get_spelling_pos(&self, pos: SourcePos) -> Result<SourcePos, LookupError> {
+        self.try_lookup_source_id(pos)
+            .ok_or(LookupError::OutOfBounds)?;
+        Ok(self.get_spelling_pos(pos))
+    }
+
     /// Retrieves the source code snippet indicated by `range`.
     ///
     /// See also `lex::get_cleaned_spelling()`,
@@ -394,6 +799,23 @@ impl SourceMap {
         file.contents.get_snippet(LocalRange::at(off, range.len()))
     }
 
+    /// Retrieves the source code snippet indicated by `range` like [`Self::get_spelling()`],
+    /// returning an error if `range` does not lie within a single source or its spelling extends
+    /// past the end of the file containing it.
+    pub fn try_get_spelling(&self, range: SourceRange) -> Result<&str, LookupError> {
+        self.checked_lookup_source_range(range)?;
+
+        let (source, off) = self.lookup_source_off(self.get_spelling_pos(range.start()));
+        let contents = &source.as_file().unwrap().contents;
+
+        let local_range = LocalRange::at(off, range.len());
+        if usize::from(local_range.end()) > contents.src().len() {
+            return Err(LookupError::PastContents);
+        }
+
+        Ok(contents.get_snippet(local_range))
+    }
+
     /// If `range` points into an expansion, returns the recoreded replacement range.
     ///
     /// If `range` points into a file, returns `None`.
@@ -496,6 +918,127 @@ impl SourceMap {
         }
     }
 
+    /// Interprets the specified file range like [`Self::get_interpreted_range()`], returning an
+    /// error if `range` does not lie within a single source or does not point into a file.
+    pub fn try_get_interpreted_range(
+        &self,
+        range: SourceRange,
+    ) -> Result<InterpretedFileRange<'_>, LookupError> {
+        let (source, local_range) = self.checked_lookup_source_range(range)?;
+
+        Ok(InterpretedFileRange {
+            file: source.as_file().ok_or(LookupError::NotFile)?,
+            range: local_range,
+        })
+    }
+
+    /// Describes the file location of `pos` along with every macro expansion leading to it.
+    ///
+    /// This resolves the same information used when rendering diagnostics, but in a structured
+    /// form suitable for other tools: the file location is the one at which `pos` ends up after
+    /// expansion, and each expansion level records where its code was spelled and where it was
+    /// expanded.
+    pub fn describe_location(&self, pos: SourcePos) -> LocationDescription {
+        let file = self.get_file_location(self.get_replacement_range(pos.into()).start());
+
+        let expansions = self
+            .get_replacement_chain(pos.into())
+            .filter_map(|(id, range)| {
+                let exp = self.get_source(id).as_expansion()?;
+                Some(ExpansionLocation {
+                    source: id,
+                    kind: exp.kind,
+                    spelling: self.get_file_location(self.get_spelling_pos(range.start())),
+                    replacement: self
+                        .get_file_location(self.get_spelling_pos(exp.replacement_range.start())),
+                })
+            })
+            .collect();
+
+        LocationDescription { file, expansions }
+    }
+
+    /// Describes the location of `pos` like [`Self::describe_location()`], returning an error if
+    /// `pos` does not lie within the map.
+    pub fn try_describe_location(
+        &self,
+        pos: SourcePos,
+    ) -> Result<LocationDescription, LookupError> {
+        self.try_lookup_source_id(pos)
+            .ok_or(LookupError::OutOfBounds)?;
+        Ok(self.describe_location(pos))
+    }
+
+    /// Resolves `pos`, which must point into a file, to a [`FileLocation`].
+    fn get_file_location(&self, pos: SourcePos) -> FileLocation {
+        let interp = self.get_interpreted_range(pos.into());
+
+        FileLocation {
+            source: self.lookup_source_id(pos),
+            pos,
+            linecol: interp.start_linecol(),
+            presumed_filename: interp.presumed_filename().clone(),
+            presumed_linecol: interp.presumed_start_linecol(),
+        }
+    }
+
+    /// Compares `a` and `b` by their order in the translation unit, with all includes and macro
+    /// expansions performed.
+    ///
+    /// Unlike comparing the positions directly (which reflects the order in which sources were
+    /// created), this places the contents of an included file after everything preceding its
+    /// include position and before everything following it, so that a file included several times
+    /// is ordered separately at each point of inclusion. Positions in expansions are similarly
+    /// placed at their replacement ranges.
+    ///
+    /// An include or replacement position is itself ordered before the contents of the file or
+    /// expansion placed there. Positions in sources with no common includer or replacement are
+    /// ordered by the creation order of their outermost sources.
+    pub fn tu_order(&self, a: SourcePos, b: SourcePos) -> Ordering {
+        let chain_a: Vec<_> = self.get_tu_parent_chain(a).collect();
+        let chain_b: Vec<_> = self.get_tu_parent_chain(b).collect();
+
+        // Walk down from the outermost sources for as long as the chains agree. Positions in
+        // different sources are compared by creation order, as sources are allocated in order of
+        // increasing position.
+        let mut levels = chain_a.iter().rev().zip(chain_b.iter().rev());
+        loop {
+            match levels.next() {
+                Some((&(id_a, pos_a), &(id_b, pos_b))) => {
+                    if id_a != id_b || pos_a != pos_b {
+                        return pos_a.cmp(&pos_b);
+                    }
+                }
+                // One of the chains is a prefix of the other, so the deeper position lies in a
+                // file or expansion placed at the shallower one.
+                None => return chain_a.len().cmp(&chain_b.len()),
+            }
+        }
+    }
+
+    /// Returns `true` if `a` comes strictly before `b` in translation unit order.
+    ///
+    /// See [`Self::tu_order()`] for details.
+    pub fn is_before_in_tu(&self, a: SourcePos, b: SourcePos) -> bool {
+        self.tu_order(a, b) == Ordering::Less
+    }
+
+    /// Creates an iterator listing the chain of include and replacement positions leading to `pos`,
+    /// from innermost to outermost.
+    fn get_tu_parent_chain(
+        &self,
+        pos: SourcePos,
+    ) -> impl Iterator<Item = (SourceId, SourcePos)> + '_ {
+        get_location_chain(
+            pos,
+            move |pos| self.lookup_source_id(pos),
+            move |id, _| match &self.get_source(id).info {
+                SourceInfo::File(file) => file.include_pos,
+                SourceInfo::Expansion(exp) => Some(exp.replacement_range.start()),
+            },
+        )
+    }
+
     fn get_replacement_pos_chain<'a, F>(
         &'a self,
         pos: SourcePos,
@@ -550,6 +1093,21 @@ impl SourceMap {
         let (start_pos, end_pos) = (cmp::min(start_pos, end_pos), cmp::max(start_pos, end_pos));
         Some(SourceRange::new(start_pos, end_pos.offset_from(start_pos)))
     }
+
+    /// Attempts to find a contiguous range covering both endpoints of `range` like
+    /// [`Self::get_unfragmented_range()`], returning an error if either endpoint does not lie
+    /// within the map.
+    pub fn try_get_unfragmented_range(
+        &self,
+        range: FragmentedSourceRange,
+    ) -> Result<Option<SourceRange>, LookupError> {
+        for pos in [range.start, range.end] {
+            self.try_lookup_source_id(pos)
+                .ok_or(LookupError::OutOfBounds)?;
+        }
+
+        Ok(self.get_unfragmented_range(range))
+    }
 }
 
 /// Creates an iterator that repeatedly invokes `lookup_id` and `next` until `None` is returned.
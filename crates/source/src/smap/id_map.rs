@@ -0,0 +1,218 @@
+//! Side tables associating client data with the sources of a [`SourceMap`].
+//!
+//! Source IDs are dense indices into the map, so these tables are backed by plain vectors rather
+//! than hash maps. As sources are only ever appended to a map, a table built for a map remains
+//! valid as the map grows; it simply needs to be extended to cover the new sources.
+
+use std::iter::FromIterator;
+use std::ops::{Index, IndexMut};
+
+use super::{Source, SourceId, SourceMap};
+
+/// A table holding a value for every source in a [`SourceMap`], such as a per-file flag computed
+/// for all sources up front.
+///
+/// Use [`Self::sync()`] to compute values for sources added to the map after the table was
+/// created.
+///
+/// # Panics
+///
+/// Indexing panics if the table has not been extended to cover the requested source, or if the ID
+/// came from a different map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceIdVec<T> {
+    values: Vec<T>,
+}
+
+impl<T> SourceIdVec<T> {
+    /// Creates a table holding `f(id, source)` for every source in `smap`.
+    pub fn from_fn(smap: &SourceMap, f: impl FnMut(SourceId, &Source) -> T) -> Self {
+        let mut vec = Self { values: Vec::new() };
+        vec.sync(smap, f);
+        vec
+    }
+
+    /// Extends the table to cover sources added to `smap` since it was last synced, storing
+    /// `f(id, source)` for each of them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the table covers more sources than `smap` contains, which indicates that it was
+    /// created for a different map.
+    pub fn sync(&mut self, smap: &SourceMap, mut f: impl FnMut(SourceId, &Source) -> T) {
+        assert!(
+            self.values.len() <= smap.source_count(),
+            "table covers sources not in the map"
+        );
+
+        self.values.extend(
+            smap.source_ids()
+                .skip(self.values.len())
+                .map(|id| f(id, smap.get_source(id))),
+        );
+    }
+
+    /// Returns the number of sources covered by the table.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns `true` if the table does not cover any sources.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Returns the value stored for `id`, or `None` if the table does not cover it.
+    pub fn get(&self, id: SourceId) -> Option<&T> {
+        self.values.get(id.0)
+    }
+
+    /// Returns a mutable reference to the value stored for `id`, or `None` if the table does not
+    /// cover it.
+    pub fn get_mut(&mut self, id: SourceId) -> Option<&mut T> {
+        self.values.get_mut(id.0)
+    }
+
+    /// Returns an iterator over the sources covered by the table and their values, in order of
+    /// creation.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (SourceId, &T)> + DoubleEndedIterator {
+        self.values
+            .iter()
+            .enumerate()
+            .map(|(idx, val)| (SourceId(idx), val))
+    }
+}
+
+impl<T> Index<SourceId> for SourceIdVec<T> {
+    type Output = T;
+
+    fn index(&self, id: SourceId) -> &T {
+        &self.values[id.0]
+    }
+}
+
+impl<T> IndexMut<SourceId> for SourceIdVec<T> {
+    fn index_mut(&mut self, id: SourceId) -> &mut T {
+        &mut self.values[id.0]
+    }
+}
+
+/// A table holding values for some of the sources in a [`SourceMap`], such as include guards
+/// detected for certain files.
+///
+/// The table grows as needed when values are inserted, so it never needs to be explicitly kept in
+/// sync with the map. Lookups of sources for which no value was inserted (including sources added
+/// after the table was last modified) return `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceIdMap<T> {
+    values: Vec<Option<T>>,
+    len: usize,
+}
+
+impl<T> Default for SourceIdMap<T> {
+    fn default() -> Self {
+        Self {
+            values: Vec::new(),
+            len: 0,
+        }
+    }
+}
+
+impl<T> SourceIdMap<T> {
+    /// Creates an empty table.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns the number of values in the table.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the table contains no values.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns `true` if the table contains a value for `id`.
+    pub fn contains(&self, id: SourceId) -> bool {
+        self.get(id).is_some()
+    }
+
+    /// Returns the value stored for `id`, if any.
+    pub fn get(&self, id: SourceId) -> Option<&T> {
+        self.values.get(id.0)?.as_ref()
+    }
+
+    /// Returns a mutable reference to the value stored for `id`, if any.
+    pub fn get_mut(&mut self, id: SourceId) -> Option<&mut T> {
+        self.values.get_mut(id.0)?.as_mut()
+    }
+
+    /// Stores `val` for `id`, returning the previous value if there was one.
+    pub fn insert(&mut self, id: SourceId, val: T) -> Option<T> {
+        let old = self.slot(id).replace(val);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    /// Removes the value stored for `id`, returning it if there was one.
+    pub fn remove(&mut self, id: SourceId) -> Option<T> {
+        let old = self.values.get_mut(id.0)?.take();
+        if old.is_some() {
+            self.len -= 1;
+        }
+        old
+    }
+
+    /// Returns a mutable reference to the value stored for `id`, inserting the result of `f` if
+    /// there is none.
+    pub fn get_or_insert_with(&mut self, id: SourceId, f: impl FnOnce() -> T) -> &mut T {
+        if !self.contains(id) {
+            self.insert(id, f());
+        }
+
+        self.values[id.0].as_mut().unwrap()
+    }
+
+    /// Returns an iterator over the sources that have values in the table, along with those values,
+    /// in order of creation.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (SourceId, &T)> {
+        self.values
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, val)| Some((SourceId(idx), val.as_ref()?)))
+    }
+
+    /// Removes every value from the table.
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.len = 0;
+    }
+
+    /// Returns the slot holding the value for `id`, growing the table if necessary.
+    fn slot(&mut self, id: SourceId) -> &mut Option<T> {
+        if id.0 >= self.values.len() {
+            self.values.resize_with(id.0 + 1, || None);
+        }
+        &mut self.values[id.0]
+    }
+}
+
+impl<T> FromIterator<(SourceId, T)> for SourceIdMap<T> {
+    fn from_iter<I: IntoIterator<Item = (SourceId, T)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<T> Extend<(SourceId, T)> for SourceIdMap<T> {
+    fn extend<I: IntoIterator<Item = (SourceId, T)>>(&mut self, iter: I) {
+        for (id, val) in iter {
+            self.insert(id, val);
+        }
+    }
+}
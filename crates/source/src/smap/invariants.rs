@@ -0,0 +1,224 @@
+//! Property-based checks of the invariants upheld by [`SourceMap`] for arbitrary (but valid)
+//! combinations of files and expansions.
+
+use proptest::prelude::*;
+use proptest::sample::Index;
+
+use super::*;
+
+/// A single step in the construction of a source map.
+///
+/// Sources and offsets are chosen by index among those available when the step is performed, so
+/// that every step describes a valid operation regardless of the steps preceding it.
+#[derive(Debug, Clone)]
+enum Step {
+    /// Creates a file with the specified contents, included from some existing file if requested.
+    File {
+        contents: String,
+        include: Option<(Index, Index)>,
+    },
+    /// Creates an expansion with spelling and replacement ranges chosen from existing sources.
+    Expansion {
+        spelling: (Index, Index, Index),
+        replacement: (Index, Index, Index),
+        kind: ExpansionKind,
+    },
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    let kind = prop_oneof![
+        Just(ExpansionKind::Macro),
+        Just(ExpansionKind::MacroArg),
+        Just(ExpansionKind::Synth),
+    ];
+
+    prop_oneof![
+        1 => ("[a-z\n]{1,24}", any::<Option<(Index, Index)>>())
+            .prop_map(|(contents, include)| Step::File { contents, include }),
+        3 => (any::<(Index, Index, Index)>(), any::<(Index, Index, Index)>(), kind).prop_map(
+            |(spelling, replacement, kind)| Step::Expansion {
+                spelling,
+                replacement,
+                kind,
+            }
+        ),
+    ]
+}
+
+/// Returns the number of positions in `source` holding actual contents, excluding its sentinel.
+fn content_len(source: &Source) -> u32 {
+    u32::from(source.range.len()) - 1
+}
+
+/// Chooses a nonempty range within the contents of one of the sources in `sm`.
+fn choose_range(sm: &SourceMap, (source, start, len): (Index, Index, Index)) -> SourceRange {
+    let source = source.get(&sm.sources);
+    let content_len = content_len(source) as usize;
+
+    let start = start.index(content_len);
+    let len = 1 + len.index(content_len - start);
+    source
+        .range
+        .subrange(LocalRange::at((start as u32).into(), (len as u32).into()))
+}
+
+/// Builds a source map containing a main file followed by the sources described by `steps`.
+fn build(steps: &[Step]) -> SourceMap {
+    let mut sm = SourceMap::new();
+    sm.create_file(
+        FileName::synth("main.c"),
+        FileContents::new("int x;\n"),
+        None,
+    )
+    .unwrap();
+
+    for step in steps {
+        match step {
+            Step::File { contents, include } => {
+                let include_pos = include.map(|(file, off)| {
+                    let files: Vec<_> = sm.file_sources().map(|(id, _)| id).collect();
+                    let file = sm.get_source(*file.get(&files));
+                    file.range
+                        .subpos((off.index(content_len(file) as usize + 1) as u32).into())
+                });
+
+                sm.create_file(
+                    FileName::synth("file.h"),
+                    FileContents::new(contents),
+                    include_pos,
+                )
+                .unwrap();
+            }
+
+            &Step::Expansion {
+                spelling,
+                replacement,
+                kind,
+            } => {
+                let spelling_range = choose_range(&sm, spelling);
+                let replacement_range = choose_range(&sm, replacement);
+                sm.create_expansion(spelling_range, replacement_range, kind)
+                    .unwrap();
+            }
+        }
+    }
+
+    sm
+}
+
+/// Returns every valid position in `sm`, including source sentinels.
+fn all_positions(sm: &SourceMap) -> impl Iterator<Item = SourcePos> {
+    (0..sm.next_offset).map(SourcePos::from_raw)
+}
+
+/// Generates the steps building a source map, together with indices from which to sample ranges or
+/// pairs of positions in it.
+fn steps_and_samples() -> impl Strategy<Value = (Vec<Step>, Vec<(Index, Index, Index)>)> {
+    (
+        prop::collection::vec(step(), 0..16),
+        prop::collection::vec(any::<(Index, Index, Index)>(), 1..32),
+    )
+}
+
+proptest! {
+    #[test]
+    fn lookup_finds_containing_source((steps, _) in steps_and_samples()) {
+        let sm = build(&steps);
+
+        for id in sm.source_ids() {
+            let range = sm.get_source(id).range;
+            for off in 0..u32::from(range.len()) {
+                let pos = range.subpos(off.into());
+                prop_assert_eq!(sm.try_lookup_source_id(pos), Some(id));
+                prop_assert_eq!(sm.lookup_source_off(pos).1, LocalOff::from(off));
+            }
+        }
+
+        let past_end = SourcePos::from_raw(sm.next_offset + 1);
+        prop_assert_eq!(sm.try_lookup_source_id(past_end), None);
+        prop_assert!(sm.try_lookup_source_off(past_end).is_none());
+    }
+
+    #[test]
+    fn spelling_chains_terminate_in_files((steps, _) in steps_and_samples()) {
+        let sm = build(&steps);
+
+        for pos in all_positions(&sm) {
+            let chain: Vec<_> = sm.get_spelling_chain(pos).collect();
+            let ids: Vec<_> = chain.iter().map(|(id, _)| id.0).collect();
+
+            // Every expansion is spelled in a source created before it, so the chain must strictly
+            // decrease and can never be longer than the number of sources.
+            prop_assert!(chain.len() <= sm.source_count());
+            prop_assert!(ids.windows(2).all(|w| w[1] < w[0]));
+
+            let &(last_id, last_pos) = chain.last().unwrap();
+            prop_assert!(sm.get_source(last_id).is_file());
+            prop_assert_eq!(sm.get_spelling_pos(pos), last_pos);
+
+            for ids in [
+                sm.get_replacement_chain(pos.into()).map(|(id, _)| id.0).collect::<Vec<_>>(),
+                sm.get_caller_chain(pos.into()).map(|(id, _)| id.0).collect(),
+            ] {
+                prop_assert!(ids.windows(2).all(|w| w[1] < w[0]));
+                prop_assert!(sm.sources[*ids.last().unwrap()].is_file());
+            }
+        }
+    }
+
+    #[test]
+    fn subranges_round_trip((steps, samples) in steps_and_samples()) {
+        let sm = build(&steps);
+        for sample in samples {
+            let range = choose_range(&sm, sample);
+            let (source, local_range) = sm.lookup_source_range(range);
+
+            prop_assert_eq!(source.range.subrange(local_range), range);
+            prop_assert_eq!(local_range.len(), range.len());
+            prop_assert_eq!(sm.try_lookup_source_range(range).map(|(_, r)| r), Some(local_range));
+
+            // Spellings retrieved through any number of expansions keep the length of the range.
+            prop_assert_eq!(sm.get_spelling(range).len(), u32::from(range.len()) as usize);
+
+            if let Some(exp) = source.as_expansion() {
+                prop_assert_eq!(exp.spelling_range(local_range).len(), range.len());
+                let caller = exp.caller_range(local_range);
+                prop_assert!(sm.try_lookup_source_range(caller).is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn unfragmented_range_in_lca((steps, samples) in steps_and_samples()) {
+        let sm = build(&steps);
+        let positions: Vec<_> = all_positions(&sm).collect();
+
+        for (a, b, _) in samples {
+            let (a, b) = (*a.get(&positions), *b.get(&positions));
+            let chain_a: Vec<_> = sm.get_replacement_chain(a.into()).map(|(id, _)| id).collect();
+            let chain_b: Vec<_> = sm.get_replacement_chain(b.into()).map(|(id, _)| id).collect();
+
+            match sm.get_unfragmented_range(FragmentedSourceRange::new(a, b)) {
+                Some(range) => {
+                    let (source, _) = sm.try_lookup_source_range(range).unwrap();
+                    let id = sm.lookup_source_id(source.range.start());
+                    prop_assert!(chain_a.contains(&id) && chain_b.contains(&id));
+                    prop_assert!(range.start() <= range.end());
+                }
+                None => prop_assert_ne!(chain_a.last(), chain_b.last()),
+            }
+        }
+    }
+
+    #[test]
+    fn tu_order_is_antisymmetric((steps, samples) in steps_and_samples()) {
+        let sm = build(&steps);
+        let positions: Vec<_> = all_positions(&sm).collect();
+
+        for (a, b, _) in samples {
+            let (a, b) = (*a.get(&positions), *b.get(&positions));
+            prop_assert_eq!(sm.tu_order(a, b), sm.tu_order(b, a).reverse());
+            prop_assert_eq!(sm.tu_order(a, b) == Ordering::Equal, a == b);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! (De)serialization of source maps, enabled by the `serde` feature.
+//!
+//! A serialized map holds the contents of every distinct file once, followed by the information
+//! describing each source. Sources are recreated in their original order when the map is
+//! deserialized, so [`SourceId`]s and [`SourcePos`]es referring to the original map remain valid.
+
+use rustc_hash::FxHashMap;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::source::LineOverride;
+use super::{
+    ExpansionSourceInfo, FileContents, FileName, SourceInfo, SourceMap, SourcePos,
+    SourcesTooLargeError,
+};
+use crate::sync::Lrc;
+
+#[derive(Serialize)]
+struct SerializedMap<'a> {
+    contents: Vec<&'a FileContents>,
+    sources: Vec<SerializedSource<'a>>,
+}
+
+#[derive(Serialize)]
+enum SerializedSource<'a> {
+    File {
+        filename: &'a FileName,
+        /// The index of the file's contents in the map's list of contents.
+        contents: usize,
+        include_pos: Option<SourcePos>,
+        line_overrides: &'a [LineOverride],
+    },
+    Expansion(&'a ExpansionSourceInfo),
+}
+
+#[derive(Deserialize)]
+struct DeserializedMap {
+    contents: Vec<FileContents>,
+    sources: Vec<DeserializedSource>,
+}
+
+#[derive(Deserialize)]
+enum DeserializedSource {
+    File {
+        filename: FileName,
+        contents: usize,
+        include_pos: Option<SourcePos>,
+        line_overrides: Vec<LineOverride>,
+    },
+    Expansion(ExpansionSourceInfo),
+}
+
+impl Serialize for SourceMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut contents = Vec::new();
+        let mut content_indices = FxHashMap::default();
+
+        let sources = self
+            .sources
+            .iter()
+            .map(|source| match &source.info {
+                SourceInfo::File(file) => {
+                    let idx = *content_indices
+                        .entry(Lrc::as_ptr(&file.contents))
+                        .or_insert_with(|| {
+                            contents.push(&*file.contents);
+                            contents.len() - 1
+                        });
+
+                    SerializedSource::File {
+                        filename: &file.filename,
+                        contents: idx,
+                        include_pos: file.include_pos,
+                        line_overrides: &file.line_overrides,
+                    }
+                }
+                SourceInfo::Expansion(exp) => SerializedSource::Expansion(exp),
+            })
+            .collect();
+
+        SerializedMap { contents, sources }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SourceMap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let serialized = DeserializedMap::deserialize(deserializer)?;
+        let contents: Vec<_> = serialized.contents.into_iter().map(Lrc::new).collect();
+
+        let mut map = SourceMap::new();
+        let too_large = |_: SourcesTooLargeError| D::Error::custom("source map too large");
+
+        // Validate everything up front, as the source map's constructors panic on invalid input.
+        for source in serialized.sources {
+            match source {
+                DeserializedSource::File {
+                    filename,
+                    contents: idx,
+                    include_pos,
+                    line_overrides,
+                } => {
+                    let contents = contents
+                        .get(idx)
+                        .ok_or_else(|| D::Error::custom("invalid file contents index"))?;
+
+                    if let Some(pos) = include_pos {
+                        if !map
+                            .try_lookup_source_off(pos)
+                            .is_some_and(|(source, _)| source.is_file())
+                        {
+                            return Err(D::Error::custom("invalid include position"));
+                        }
+                    }
+
+                    let lines_valid = line_overrides
+                        .iter()
+                        .all(|ov| ov.physical_line < contents.line_count());
+                    let lines_ordered = line_overrides
+                        .windows(2)
+                        .all(|pair| pair[0].physical_line <= pair[1].physical_line);
+                    if !lines_valid || !lines_ordered {
+                        return Err(D::Error::custom("invalid line overrides"));
+                    }
+
+                    let id = map
+                        .create_file(filename, Lrc::clone(contents), include_pos)
+                        .map_err(too_large)?;
+
+                    if let SourceInfo::File(file) = &mut map.sources[id.0].info {
+                        file.line_overrides = line_overrides;
+                    }
+                }
+                DeserializedSource::Expansion(exp) => {
+                    let ranges_valid =
+                        [exp.spelling_range, exp.replacement_range]
+                            .iter()
+                            .all(|&range| {
+                                !range.is_empty() && map.try_lookup_source_range(range).is_some()
+                            });
+                    if !ranges_valid {
+                        return Err(D::Error::custom("invalid expansion ranges"));
+                    }
+
+                    map.create_expansion(exp.spelling_range, exp.replacement_range, exp.kind)
+                        .map_err(too_large)?;
+                }
+            }
+        }
+
+        Ok(map)
+    }
+}
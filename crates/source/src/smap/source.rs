@@ -1,7 +1,7 @@
 use std::fmt;
 use std::path::PathBuf;
-use std::rc::Rc;
 
+use crate::sync::{Lrc, OnceCell};
 use crate::{LineCol, LocalOff, LocalRange, SourcePos, SourceRange};
 use line_table::LineTable;
 
@@ -14,6 +14,7 @@ mod tests;
 ///
 /// Synthesized names are used for the source code created by a token paste, for example.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FileName {
     Real(PathBuf),
     Synth(String),
@@ -45,40 +46,232 @@ impl fmt::Display for FileName {
     }
 }
 
+/// The encoding of a source file, as detected by [`FileContents::decode()`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SourceEncoding {
+    /// UTF-8, with or without a byte order mark.
+    #[default]
+    Utf8,
+    /// Little-endian UTF-16, detected by its byte order mark.
+    Utf16Le,
+    /// Big-endian UTF-16, detected by its byte order mark.
+    Utf16Be,
+}
+
+impl SourceEncoding {
+    /// Returns the name of the encoding, for use in diagnostics.
+    pub fn name(self) -> &'static str {
+        match self {
+            SourceEncoding::Utf8 => "UTF-8",
+            SourceEncoding::Utf16Le | SourceEncoding::Utf16Be => "UTF-16",
+        }
+    }
+}
+
+/// Describes how the contents of a file were decoded from its raw bytes.
+///
+/// Contents that were not created with [`FileContents::decode()`] report UTF-8 without a byte
+/// order mark.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecodeInfo {
+    /// The encoding of the file.
+    pub encoding: SourceEncoding,
+    /// Whether a byte order mark was stripped from the start of the file.
+    pub bom: bool,
+    /// Whether any `\r\n` line endings were normalized to `\n`.
+    pub crlf: bool,
+    /// The offset in the raw bytes of the first sequence that was invalid in `encoding`, if any.
+    /// Every invalid sequence was replaced with U+FFFD.
+    pub invalid_offset: Option<usize>,
+    /// The offset in the decoded source code of the U+FFFD replacing the first invalid sequence,
+    /// if any.
+    pub invalid_src_offset: Option<LocalOff>,
+}
+
+impl DecodeInfo {
+    /// Returns `true` if the decoded source code is identical to the raw bytes of the file.
+    ///
+    /// Edits made to the source code of other files cannot be written back without also altering
+    /// their encoding, byte order mark, line endings or invalid sequences.
+    pub fn is_verbatim(&self) -> bool {
+        self.encoding == SourceEncoding::Utf8
+            && !self.bom
+            && !self.crlf
+            && self.invalid_offset.is_none()
+    }
+}
+
+/// The storage backing the source code of a [`FileContents`].
+enum Text {
+    Owned(String),
+    Static(&'static str),
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap),
+}
+
 /// Represents the contents of a loaded source file.
+///
+/// The source code is always exposed with normalized line endings. Contents can be created without
+/// copying the source when it is already normalized: see [`from_string()`](Self::from_string()),
+/// [`from_static()`](Self::from_static()) and (with the `mmap` feature) `map_file()`.
+///
+/// Raw bytes read from a file should be passed to [`decode()`](Self::decode()), which handles
+/// byte order marks and invalid encodings.
 pub struct FileContents {
     /// The source code in the file.
-    pub src: String,
-    /// A table used to look up line numbers by file offset.
-    line_table: LineTable,
+    text: Text,
+    /// How the source code was decoded from the raw file.
+    decode_info: DecodeInfo,
+    /// A table used to look up line numbers by file offset, built the first time it is needed.
+    line_table: OnceCell<LineTable>,
 }
 
 impl FileContents {
     /// Creates a new `FileContents` with the specified source.
     ///
     /// Line endings in the source are normalized.
-    pub fn new(src: &str) -> Rc<Self> {
-        let normalized_src = src.replace("\r\n", "\n");
-        let line_table = LineTable::new_for_src(&normalized_src);
+    pub fn new(src: &str) -> Lrc<Self> {
+        Lrc::new(Self::with_text(Text::Owned(src.replace("\r\n", "\n"))))
+    }
+
+    /// Creates a new `FileContents` taking ownership of `src`.
+    ///
+    /// Line endings are normalized in place, so no additional copy of the source is made.
+    pub fn from_string(src: String) -> Lrc<Self> {
+        Lrc::new(Self::from_owned(src))
+    }
+
+    /// Creates a new `FileContents` referencing `src`, which is only copied if its line endings
+    /// need to be normalized.
+    pub fn from_static(src: &'static str) -> Lrc<Self> {
+        let text = if src.contains("\r\n") {
+            Text::Owned(src.replace("\r\n", "\n"))
+        } else {
+            Text::Static(src)
+        };
+
+        Lrc::new(Self::with_text(text))
+    }
+
+    /// Creates a new `FileContents` by decoding the raw contents of a file.
+    ///
+    /// A leading byte order mark is stripped, and is used to detect UTF-16 files; other files are
+    /// assumed to be UTF-8. Invalid sequences are replaced with U+FFFD rather than causing an
+    /// error, and the offset of the first one is recorded in [`decode_info()`](Self::decode_info())
+    /// so that it can be diagnosed.
+    pub fn decode(bytes: Vec<u8>) -> Lrc<Self> {
+        let (src, mut decode_info) = decode_bytes(bytes);
+
+        decode_info.crlf = src.contains("\r\n");
+        if let Some(off) = &mut decode_info.invalid_src_offset {
+            // Account for the line endings normalized before the replacement character.
+            let crs = src[..usize::from(*off)].matches("\r\n").count();
+            *off -= LocalOff::from(crs as u32);
+        }
 
-        Rc::new(FileContents {
-            src: normalized_src,
-            line_table,
+        Lrc::new(FileContents {
+            decode_info,
+            ..Self::from_owned(src)
         })
     }
 
+    /// Creates a new `FileContents` by memory-mapping `file`, which must contain UTF-8.
+    ///
+    /// The mapping is used directly unless the file starts with a byte order mark or contains line
+    /// endings needing normalization, in which case its contents are copied. Files that are not
+    /// valid UTF-8 should be read and passed to [`decode()`](Self::decode()) instead.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified (by this or any other process) while the returned contents
+    /// are alive, as the source code would change underneath any references to it.
+    #[cfg(feature = "mmap")]
+    pub unsafe fn map_file(file: &std::fs::File) -> std::io::Result<Lrc<Self>> {
+        use std::io;
+
+        let map = memmap2::Mmap::map(file)?;
+        let src = std::str::from_utf8(&map)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let unmarked = src.strip_prefix(UTF8_BOM);
+        let decode_info = DecodeInfo {
+            bom: unmarked.is_some(),
+            crlf: src.contains("\r\n"),
+            ..Default::default()
+        };
+
+        let text = match unmarked {
+            Some(src) => Text::Owned(src.replace("\r\n", "\n")),
+            None if src.contains("\r\n") => Text::Owned(src.replace("\r\n", "\n")),
+            None => Text::Mapped(map),
+        };
+
+        Ok(Lrc::new(FileContents {
+            decode_info,
+            ..Self::with_text(text)
+        }))
+    }
+
+    fn from_owned(src: String) -> Self {
+        Self::with_text(Text::Owned(normalize_line_endings(src)))
+    }
+
+    fn with_text(text: Text) -> Self {
+        FileContents {
+            text,
+            decode_info: DecodeInfo::default(),
+            line_table: OnceCell::new(),
+        }
+    }
+
+    /// Returns information about how the source code was decoded from the raw file.
+    pub fn decode_info(&self) -> DecodeInfo {
+        self.decode_info
+    }
+
+    /// Returns the (normalized) source code in the file.
+    pub fn src(&self) -> &str {
+        match &self.text {
+            Text::Owned(src) => src,
+            Text::Static(src) => src,
+            // SAFETY: Mappings are validated as UTF-8 when created, and `map_file()` requires that
+            // the file not be modified afterwards.
+            #[cfg(feature = "mmap")]
+            Text::Mapped(map) => unsafe { std::str::from_utf8_unchecked(map) },
+        }
+    }
+
+    /// Returns the line table for the source, building it if this is the first lookup.
+    fn line_table(&self) -> &LineTable {
+        self.line_table
+            .get_or_init(|| LineTable::new_for_src(self.src()))
+    }
+
     /// Retrieves the specified portion of the source code.
     ///
     /// # Panics
     ///
     /// Panics if the range does not lie within the source.
     pub fn get_snippet(&self, range: LocalRange) -> &str {
-        &self.src[range]
+        &self.src()[range]
+    }
+
+    /// Returns the number of bytes of heap memory used by the contents, including the line table
+    /// if it has been built.
+    ///
+    /// Source code that is borrowed or memory-mapped is not counted.
+    pub fn heap_size(&self) -> usize {
+        let src_size = match &self.text {
+            Text::Owned(src) => src.capacity(),
+            _ => 0,
+        };
+
+        src_size + self.line_table.get().map_or(0, LineTable::heap_size)
     }
 
     /// Returns the number of lines in the source.
     pub fn line_count(&self) -> u32 {
-        self.line_table.line_count()
+        self.line_table().line_count()
     }
 
     /// Computes the line and column numbers for the specified position.
@@ -87,8 +280,45 @@ impl FileContents {
     ///
     /// Panics if the offset is longer than the source.
     pub fn get_linecol(&self, off: LocalOff) -> LineCol {
-        assert!(off <= LocalOff::of(&self.src));
-        self.line_table.get_linecol(off)
+        assert!(off <= LocalOff::of(self.src()));
+        self.line_table().get_linecol(off)
+    }
+
+    /// Computes the line and column numbers for each of the specified positions, returning them in
+    /// the same order.
+    ///
+    /// This is equivalent to calling [`get_linecol()`](Self::get_linecol()) for every offset, but
+    /// offsets given in increasing order (such as the ranges of a diagnostic) are resolved in a
+    /// single forward pass over the line table.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any offset is longer than the source.
+    pub fn get_linecols(&self, offs: &[LocalOff]) -> Vec<LineCol> {
+        let len = LocalOff::of(self.src());
+        assert!(offs.iter().all(|&off| off <= len));
+        self.line_table().get_linecols(offs)
+    }
+
+    /// Computes the offset within the source of the specified line and column (both zero-based,
+    /// with the column counted in bytes).
+    ///
+    /// Returns `None` if the line is out of range, or if the column lies beyond the end of the line
+    /// or inside a multibyte character. The column of the line's terminating newline is accepted.
+    pub fn try_get_off(&self, linecol: LineCol) -> Option<LocalOff> {
+        if linecol.line >= self.line_count() {
+            return None;
+        }
+
+        let start = self.get_line_start(linecol.line);
+        let end = self.get_line_end(linecol.line);
+        let off = start + LocalOff::from(linecol.col);
+
+        if off > end || !self.src().is_char_boundary(off.into()) {
+            return None;
+        }
+
+        Some(off)
     }
 
     /// Obtains the starting offset within the source of the specified (zero-based) line number.
@@ -97,7 +327,7 @@ impl FileContents {
     ///
     /// Panics if the line number is out of range.
     pub fn get_line_start(&self, line: u32) -> LocalOff {
-        self.line_table.get_line_start(line)
+        self.line_table().get_line_start(line)
     }
 
     /// Obtains the ending offset within the source of the specified (zero-based) line number.
@@ -109,9 +339,9 @@ impl FileContents {
         assert!(line < self.line_count());
 
         if line == self.line_count() - 1 {
-            LocalOff::of(&self.src)
+            LocalOff::of(self.src())
         } else {
-            self.line_table.get_line_start(line + 1) - LocalOff::from(1)
+            self.line_table().get_line_start(line + 1) - LocalOff::from(1)
         }
     }
 
@@ -138,6 +368,137 @@ impl FileContents {
     }
 }
 
+/// File contents are serialized as their source code alone; the line table is rebuilt when they are
+/// deserialized.
+#[cfg(feature = "serde")]
+impl serde::Serialize for FileContents {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.src().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for FileContents {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let src = String::deserialize(deserializer)?;
+        Ok(Self::from_owned(src))
+    }
+}
+
+/// The byte order mark, as it appears at the start of a UTF-8 file.
+const UTF8_BOM: &str = "\u{feff}";
+
+/// Decodes the raw contents of a file as described in [`FileContents::decode()`].
+fn decode_bytes(mut bytes: Vec<u8>) -> (String, DecodeInfo) {
+    let encoding = match bytes.get(..2) {
+        Some(b"\xff\xfe") => SourceEncoding::Utf16Le,
+        Some(b"\xfe\xff") => SourceEncoding::Utf16Be,
+        _ => SourceEncoding::Utf8,
+    };
+
+    if encoding != SourceEncoding::Utf8 {
+        let (src, invalid) = decode_utf16(&bytes[2..], encoding == SourceEncoding::Utf16Le);
+        let info = DecodeInfo {
+            encoding,
+            bom: true,
+            invalid_offset: invalid.map(|(off, _)| off + 2),
+            invalid_src_offset: invalid.map(|(_, src_off)| src_off),
+            ..Default::default()
+        };
+        return (src, info);
+    }
+
+    let bom = bytes.starts_with(UTF8_BOM.as_bytes());
+    if bom {
+        bytes.drain(..UTF8_BOM.len());
+    }
+
+    match String::from_utf8(bytes) {
+        Ok(src) => (
+            src,
+            DecodeInfo {
+                encoding,
+                bom,
+                ..Default::default()
+            },
+        ),
+        Err(err) => {
+            let bom_len = if bom { UTF8_BOM.len() } else { 0 };
+            let valid_len = err.utf8_error().valid_up_to();
+            let info = DecodeInfo {
+                encoding,
+                bom,
+                invalid_offset: Some(bom_len + valid_len),
+                invalid_src_offset: Some(LocalOff::from(valid_len as u32)),
+                ..Default::default()
+            };
+            (String::from_utf8_lossy(err.as_bytes()).into_owned(), info)
+        }
+    }
+}
+
+/// Decodes the UTF-16 code units in `bytes`, replacing invalid sequences (including a trailing odd
+/// byte) with U+FFFD. The offsets of the first invalid sequence in `bytes` and of its replacement in
+/// the decoded string are returned as well.
+fn decode_utf16(bytes: &[u8], little_endian: bool) -> (String, Option<(usize, LocalOff)>) {
+    let pairs = bytes.chunks_exact(2);
+    let odd_byte = !pairs.remainder().is_empty();
+    let units = pairs.map(|pair| {
+        let pair = [pair[0], pair[1]];
+        if little_endian {
+            u16::from_le_bytes(pair)
+        } else {
+            u16::from_be_bytes(pair)
+        }
+    });
+
+    let mut src = String::with_capacity(bytes.len());
+    let mut invalid = None;
+    let mut off = 0;
+
+    for c in char::decode_utf16(units) {
+        match c {
+            Ok(c) => {
+                src.push(c);
+                off += 2 * c.len_utf16();
+            }
+            Err(_) => {
+                invalid.get_or_insert((off, LocalOff::of(&src)));
+                src.push(char::REPLACEMENT_CHARACTER);
+                off += 2;
+            }
+        }
+    }
+
+    if odd_byte {
+        invalid.get_or_insert((off, LocalOff::of(&src)));
+        src.push(char::REPLACEMENT_CHARACTER);
+    }
+
+    (src, invalid)
+}
+
+/// Replaces every `\r\n` in `src` with `\n`, reusing its buffer.
+fn normalize_line_endings(src: String) -> String {
+    if !src.contains("\r\n") {
+        return src;
+    }
+
+    let mut bytes = src.into_bytes();
+    let mut len = 0;
+    for i in 0..bytes.len() {
+        if bytes[i] == b'\r' && bytes.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        bytes[len] = bytes[i];
+        len += 1;
+    }
+    bytes.truncate(len);
+
+    // Only ASCII carriage returns were removed, so the result is still UTF-8.
+    String::from_utf8(bytes).unwrap()
+}
+
 /// Holds information about a file [source](super#sources).
 #[derive(Clone)]
 pub struct FileSourceInfo {
@@ -145,28 +506,92 @@ pub struct FileSourceInfo {
     pub filename: FileName,
     /// The contents of the file. Multiple file sources may share the same contents (e.g. when the
     /// same file is included multiple times).
-    pub contents: Rc<FileContents>,
+    pub contents: Lrc<FileContents>,
     /// The position at which this file was included, if any.
     pub include_pos: Option<SourcePos>,
+    /// Overrides of the presumed line number and filename introduced by `#line` directives, in
+    /// order of increasing offset.
+    pub(crate) line_overrides: Vec<LineOverride>,
+}
+
+/// A change to the presumed location of the lines in a file, starting at a specific line.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct LineOverride {
+    /// The (zero-based) physical line at which the override starts.
+    pub(crate) physical_line: u32,
+    /// The presumed (zero-based) line number of `physical_line`.
+    pub(crate) presumed_line: u32,
+    /// The presumed filename from `physical_line` onwards.
+    pub(crate) filename: FileName,
 }
 
 impl FileSourceInfo {
     /// Creates a new `FileSourceInfo`.
     pub fn new(
         filename: FileName,
-        contents: Rc<FileContents>,
+        contents: Lrc<FileContents>,
         include_pos: Option<SourcePos>,
     ) -> Self {
         Self {
             filename,
             contents,
             include_pos,
+            line_overrides: Vec::new(),
+        }
+    }
+
+    /// Makes the line starting at `off` presumed to be line number `line` (zero-based), as with a
+    /// `#line` directive. If `filename` is provided, the lines are also presumed to come from a
+    /// file with that name from then on.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `off` lies beyond the end of the file, or before the start of a line that has
+    /// already been overridden.
+    pub fn add_line_override(&mut self, off: LocalOff, line: u32, filename: Option<FileName>) {
+        let physical_line = self.contents.get_linecol(off).line;
+
+        let last = self.line_overrides.last();
+        assert!(
+            last.is_none_or(|last| last.physical_line <= physical_line),
+            "line overrides must be added in order"
+        );
+
+        let filename = filename
+            .or_else(|| last.map(|last| last.filename.clone()))
+            .unwrap_or_else(|| self.filename.clone());
+
+        self.line_overrides.push(LineOverride {
+            physical_line,
+            presumed_line: line,
+            filename,
+        });
+    }
+
+    /// Returns the presumed filename and line-column pair of the physical location `linecol`,
+    /// taking `#line` directives into account.
+    pub fn presumed_linecol(&self, linecol: LineCol) -> (&FileName, LineCol) {
+        let idx = self
+            .line_overrides
+            .partition_point(|ov| ov.physical_line <= linecol.line);
+
+        match idx.checked_sub(1).map(|idx| &self.line_overrides[idx]) {
+            Some(ov) => (
+                &ov.filename,
+                LineCol {
+                    line: ov.presumed_line + (linecol.line - ov.physical_line),
+                    col: linecol.col,
+                },
+            ),
+            None => (&self.filename, linecol),
         }
     }
 }
 
 /// The different kinds of expansions that can be tracked by an expansion source.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ExpansionKind {
     /// An ordinary macro expansion.
     Macro,
@@ -178,6 +603,7 @@ pub enum ExpansionKind {
 
 /// Holds information about an expansion [source](super#sources).
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExpansionSourceInfo {
     /// The expansion's spelling range. The length of this range is always one less than that of the
     /// enclosing source (it does not contain a sentinel position).
@@ -204,11 +630,14 @@ impl ExpansionSourceInfo {
 
     /// Returns the position at which the byte at the specified offset was spelled.
     ///
+    /// The sentinel offset past the end of the expansion maps to the end of the spelling range.
+    ///
     /// # Panics
     ///
-    /// Panics if `off` lies beyond the bounds of the spelling range.
+    /// Panics if `off` lies beyond the sentinel offset.
     pub fn spelling_pos(&self, off: LocalOff) -> SourcePos {
-        self.spelling_range.subpos(off)
+        assert!(off <= self.spelling_range.len());
+        self.spelling_range.start().offset(off)
     }
 
     /// Returns the source range at which the specified range within the expansion was spelled.
@@ -234,9 +663,12 @@ impl ExpansionSourceInfo {
 }
 
 /// Information held by a source, which can be either a file or an expansion.
+///
+/// File information is boxed to keep expansions, which are far more numerous, small enough to be
+/// stored inline without a separate allocation.
 #[derive(Clone)]
 pub enum SourceInfo {
-    File(FileSourceInfo),
+    File(Box<FileSourceInfo>),
     Expansion(ExpansionSourceInfo),
 }
 
@@ -244,7 +676,7 @@ pub enum SourceInfo {
 #[derive(Clone)]
 pub struct Source {
     /// The attached (file or expansion) information.
-    pub info: Box<SourceInfo>,
+    pub info: SourceInfo,
     /// The range spanned by this source. This is one byte longer than the source's true "range",
     /// for disambiguation purposes. This range should thus almost never be used directly - take
     /// subranges as appropriate.
@@ -277,7 +709,7 @@ impl Source {
     /// If this source contains a file, returns a reference to the contained file information.
     /// Otherwise, returns `None`.
     pub fn as_file(&self) -> Option<&FileSourceInfo> {
-        match *self.info {
+        match self.info {
             SourceInfo::File(ref file) => Some(file),
             _ => None,
         }
@@ -286,7 +718,7 @@ impl Source {
     /// If this source contains an expansion, returns a reference to the contained expansion
     /// information. Otherwise, returns `None`.
     pub fn as_expansion(&self) -> Option<&ExpansionSourceInfo> {
-        match *self.info {
+        match self.info {
             SourceInfo::Expansion(ref exp) => Some(exp),
             _ => None,
         }
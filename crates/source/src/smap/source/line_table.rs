@@ -1,4 +1,5 @@
 use std::convert::TryFrom;
+use std::mem;
 use std::vec::Vec;
 
 use crate::{LineCol, LocalOff};
@@ -35,6 +36,38 @@ impl LineTable {
         }
     }
 
+    /// Looks up all offsets in `offs`. If the offsets are sorted, each one is found by galloping
+    /// forward from the line of the previous one, which is cheaper than a full binary search when
+    /// the offsets are close together.
+    pub fn get_linecols(&self, offs: &[LocalOff]) -> Vec<LineCol> {
+        if !offs.windows(2).all(|pair| pair[0] <= pair[1]) {
+            return offs.iter().map(|&off| self.get_linecol(off)).collect();
+        }
+
+        let mut line = 0;
+        let mut linecols = Vec::with_capacity(offs.len());
+
+        for &off in offs {
+            // Invariant: `rest[bound / 2]` starts at or before `off`.
+            let rest = &self.line_offsets[line..];
+            let mut bound = 1;
+            while bound < rest.len() && rest[bound] <= off {
+                bound *= 2;
+            }
+
+            let lower = bound / 2;
+            let upper = bound.min(rest.len());
+            line += lower + rest[lower..upper].partition_point(|&start| start <= off) - 1;
+
+            linecols.push(LineCol {
+                line: line as u32,
+                col: (off - self.line_offsets[line]).into(),
+            });
+        }
+
+        linecols
+    }
+
     pub fn line_count(&self) -> u32 {
         self.line_offsets.len() as u32
     }
@@ -42,6 +75,10 @@ impl LineTable {
     pub fn get_line_start(&self, line: u32) -> LocalOff {
         self.line_offsets[line as usize]
     }
+
+    pub fn heap_size(&self) -> usize {
+        self.line_offsets.capacity() * mem::size_of::<LocalOff>()
+    }
 }
 
 #[cfg(test)]
@@ -63,6 +100,24 @@ mod tests {
         assert_eq!(table.get_linecol(16.into()), LineCol { line: 3, col: 5 });
     }
 
+    #[test]
+    fn batched_lookup() {
+        let table = create_line_table();
+        let offs: Vec<LocalOff> = [16, 0, 8, 10, 2, 8, 5]
+            .iter()
+            .map(|&off: &u32| off.into())
+            .collect();
+
+        let expected: Vec<_> = offs.iter().map(|&off| table.get_linecol(off)).collect();
+        assert_eq!(table.get_linecols(&offs), expected);
+
+        let mut sorted = offs.clone();
+        sorted.sort();
+        let expected: Vec<_> = sorted.iter().map(|&off| table.get_linecol(off)).collect();
+        assert_eq!(table.get_linecols(&sorted), expected);
+        assert_eq!(table.get_linecols(&[]), []);
+    }
+
     #[test]
     fn line_count() {
         let table = create_line_table();
@@ -24,7 +24,7 @@ fn filename_to_string() {
 fn file_contents_normalized() {
     let src = "line\r\nline\nline";
     let contents = FileContents::new(src);
-    assert_eq!(contents.src, "line\nline\nline");
+    assert_eq!(contents.src(), "line\nline\nline");
 }
 
 #[test]
@@ -85,7 +85,7 @@ fn source_file() {
     let contents = FileContents::new("source");
     let file = FileSourceInfo::new(filename.clone(), contents, None);
     let source = Source {
-        info: Box::new(SourceInfo::File(file)),
+        info: SourceInfo::File(Box::new(file)),
         range: SourceRange::new(SourcePos::from_raw(0), 5.into()),
     };
 
@@ -94,7 +94,7 @@ fn source_file() {
 
     let unwrapped = source.as_file().unwrap();
     assert_eq!(unwrapped.filename, filename);
-    assert_eq!(unwrapped.contents.src, "source");
+    assert_eq!(unwrapped.contents.src(), "source");
 }
 
 #[test]
@@ -104,7 +104,7 @@ fn source_expansion() {
 
     let exp = ExpansionSourceInfo::new(spelling_range, replacement_range, ExpansionKind::Macro);
     let source = Source {
-        info: Box::new(SourceInfo::Expansion(exp)),
+        info: SourceInfo::Expansion(exp),
         range: SourceRange::new(SourcePos::from_raw(40), 5.into()),
     };
 
@@ -116,3 +116,122 @@ fn source_expansion() {
     assert_eq!(unwrapped.replacement_range, replacement_range);
     assert_eq!(unwrapped.kind, ExpansionKind::Macro);
 }
+
+#[test]
+fn file_contents_try_get_off() {
+    let contents = FileContents::new("ab\n\u{e9}c\n");
+    let off = |line, col| contents.try_get_off(LineCol { line, col }).map(u32::from);
+
+    assert_eq!(off(0, 0), Some(0));
+    assert_eq!(off(0, 2), Some(2));
+    assert_eq!(off(0, 3), None);
+    assert_eq!(off(1, 1), None);
+    assert_eq!(off(1, 2), Some(5));
+    assert_eq!(off(2, 0), Some(7));
+    assert_eq!(off(3, 0), None);
+}
+
+#[test]
+fn file_contents_from_string() {
+    let contents = FileContents::from_string("a\r\nb\r\rc\r\n".to_owned());
+    assert_eq!(contents.src(), "a\nb\r\rc\n");
+    assert_eq!(contents.get_linecol(3.into()), LineCol { line: 1, col: 1 });
+}
+
+#[test]
+fn file_contents_from_static() {
+    let contents = FileContents::from_static("line 1\nline 2");
+    assert_eq!(contents.heap_size(), 0);
+    assert_eq!(contents.get_line(1), "line 2");
+
+    let contents = FileContents::from_static("line 1\r\nline 2");
+    assert_eq!(contents.src(), "line 1\nline 2");
+}
+
+#[test]
+fn file_contents_decode() {
+    let contents = FileContents::decode(b"\xef\xbb\xbfint x;\r\n".to_vec());
+    assert_eq!(contents.src(), "int x;\n");
+    assert_eq!(
+        contents.decode_info(),
+        DecodeInfo {
+            encoding: SourceEncoding::Utf8,
+            bom: true,
+            crlf: true,
+            invalid_offset: None,
+            invalid_src_offset: None,
+        }
+    );
+    assert!(!contents.decode_info().is_verbatim());
+
+    let contents = FileContents::decode(b"a\xffb\xfe".to_vec());
+    assert_eq!(contents.src(), "a\u{fffd}b\u{fffd}");
+    assert_eq!(contents.decode_info().invalid_offset, Some(1));
+    assert_eq!(contents.decode_info().invalid_src_offset, Some(1.into()));
+    assert!(!contents.decode_info().bom);
+    assert!(!contents.decode_info().is_verbatim());
+
+    let contents = FileContents::decode(b"\xef\xbb\xbfa\r\nb\r\n\xff".to_vec());
+    assert_eq!(contents.src(), "a\nb\n\u{fffd}");
+    assert_eq!(contents.decode_info().invalid_offset, Some(9));
+    assert_eq!(contents.decode_info().invalid_src_offset, Some(4.into()));
+
+    let contents = FileContents::decode(b"int x;\n".to_vec());
+    assert!(contents.decode_info().is_verbatim());
+
+    let contents = FileContents::decode(b"\xff\xfex\x00\r\x00\n\x00\x00\xd8y".to_vec());
+    assert_eq!(contents.src(), "x\n\u{fffd}\u{fffd}");
+    assert_eq!(
+        contents.decode_info(),
+        DecodeInfo {
+            encoding: SourceEncoding::Utf16Le,
+            bom: true,
+            crlf: true,
+            invalid_offset: Some(8),
+            invalid_src_offset: Some(2.into()),
+        }
+    );
+
+    let contents = FileContents::decode(b"\xfe\xff\x00x\xd8\x3d\xde\x00".to_vec());
+    assert_eq!(contents.src(), "x\u{1f600}");
+    assert_eq!(contents.decode_info().encoding, SourceEncoding::Utf16Be);
+    assert_eq!(contents.decode_info().invalid_offset, None);
+
+    assert_eq!(
+        FileContents::new("int x;").decode_info(),
+        DecodeInfo::default()
+    );
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn file_contents_map_file() {
+    use std::fs;
+
+    let path = std::env::temp_dir().join(format!("source-map-file-{}.c", std::process::id()));
+
+    fs::write(&path, "int x;\nint y;\n").unwrap();
+    let contents = unsafe { FileContents::map_file(&fs::File::open(&path).unwrap()) }.unwrap();
+    assert_eq!(contents.heap_size(), 0);
+    assert_eq!(contents.src(), "int x;\nint y;\n");
+    assert_eq!(contents.get_line(1), "int y;");
+    drop(contents);
+
+    fs::write(&path, "int x;\r\n").unwrap();
+    let contents = unsafe { FileContents::map_file(&fs::File::open(&path).unwrap()) }.unwrap();
+    assert_eq!(contents.src(), "int x;\n");
+    assert!(contents.decode_info().crlf);
+    drop(contents);
+
+    fs::write(&path, "\u{feff}int x;\n").unwrap();
+    let contents = unsafe { FileContents::map_file(&fs::File::open(&path).unwrap()) }.unwrap();
+    assert_eq!(contents.src(), "int x;\n");
+    assert!(contents.decode_info().bom);
+    drop(contents);
+
+    fs::write(&path, b"\xff").unwrap();
+    let err = unsafe { FileContents::map_file(&fs::File::open(&path).unwrap()) }.err();
+    assert_eq!(err.unwrap().kind(), std::io::ErrorKind::InvalidData);
+
+    fs::remove_file(&path).unwrap();
+}
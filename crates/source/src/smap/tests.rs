@@ -48,6 +48,65 @@ fn create_expansion() {
     assert_eq!(exp.kind, ExpansionKind::Macro);
 }
 
+#[test]
+fn create_expansions() {
+    let mut sm = SourceMap::new();
+
+    let file_id = sm
+        .create_file(
+            FileName::real("file.c"),
+            FileContents::new("#define A(x) x\nA(1 + 2);"),
+            None,
+        )
+        .unwrap();
+
+    let file_range = sm.get_source(file_id).range;
+    let replacement_range = file_range.subrange(LocalRange::at(15.into(), 8.into()));
+    let spelling_ranges = [
+        file_range.subrange(LocalRange::at(17.into(), 1.into())),
+        file_range.subrange(LocalRange::at(21.into(), 1.into())),
+    ];
+
+    let ids = sm
+        .create_expansions(&spelling_ranges, replacement_range, ExpansionKind::MacroArg)
+        .unwrap();
+    assert_eq!(ids.len(), 2);
+
+    for (&id, &spelling_range) in ids.iter().zip(&spelling_ranges) {
+        let exp_source = sm.get_source(id);
+        let exp = exp_source.as_expansion().unwrap();
+
+        assert_eq!(exp.spelling_range, spelling_range);
+        assert_eq!(exp.replacement_range, replacement_range);
+        assert_eq!(exp.kind, ExpansionKind::MacroArg);
+        assert_eq!(sm.lookup_source_id(exp_source.range.start()), id);
+    }
+}
+
+#[test]
+fn create_expansions_too_large() {
+    let mut sm = SourceMap::new();
+
+    let file_id = sm
+        .create_file(FileName::real("file.c"), FileContents::new("ab"), None)
+        .unwrap();
+    let file_range = sm.get_source(file_id).range;
+    let spelling_range = file_range.subrange(LocalRange::at(0.into(), 1.into()));
+
+    // Leave room for only one of the expansions.
+    sm.next_offset = RawPos::MAX - 3;
+
+    assert!(sm
+        .create_expansions(
+            &[spelling_range, spelling_range],
+            spelling_range,
+            ExpansionKind::MacroArg,
+        )
+        .is_err());
+    assert_eq!(sm.next_offset, RawPos::MAX - 3);
+    assert_eq!(sm.sources.len(), 1);
+}
+
 #[test]
 #[should_panic]
 fn include_pos_non_file() {
@@ -484,3 +543,649 @@ fn unfragmented_range_cross_file() {
     );
     assert_eq!(sm.get_unfragmented_range(fragmented), None);
 }
+
+#[test]
+fn describe_location() {
+    let mut sm = SourceMap::new();
+    let (file_range, exp_a_range, exp_b_range, exp_b_x_range) = populate_sm(&mut sm);
+    let file_id = sm.lookup_source_id(file_range.start());
+    let contents = &sm.get_source(file_id).as_file().unwrap().contents;
+    let loc = |off: u32| {
+        let linecol = contents.get_linecol(off.into());
+        FileLocation {
+            source: file_id,
+            pos: file_range.subpos(off.into()),
+            linecol,
+            presumed_filename: FileName::real("file.c"),
+            presumed_linecol: linecol,
+        }
+    };
+
+    let in_file = file_range.subpos(44.into());
+    assert_eq!(
+        sm.describe_location(in_file),
+        LocationDescription {
+            file: loc(44),
+            expansions: Vec::new(),
+        }
+    );
+
+    // The `5` in `B(5 * 2)`, as substituted for `x` in the expansion of `B` within that of `A`.
+    let desc = sm.describe_location(exp_b_x_range.start());
+    assert_eq!(desc.file, loc(48));
+    assert_eq!(
+        desc.expansions
+            .iter()
+            .map(|exp| (
+                sm.get_source(exp.source).range,
+                exp.kind,
+                exp.spelling.pos,
+                exp.replacement.pos
+            ))
+            .collect::<Vec<_>>(),
+        [
+            (
+                exp_b_x_range,
+                ExpansionKind::MacroArg,
+                file_range.subpos(33.into()),
+                file_range.subpos(14.into())
+            ),
+            (
+                exp_b_range,
+                ExpansionKind::Macro,
+                file_range.subpos(14.into()),
+                file_range.subpos(31.into())
+            ),
+            (
+                exp_a_range,
+                ExpansionKind::Macro,
+                file_range.subpos(31.into()),
+                file_range.subpos(48.into())
+            ),
+        ]
+    );
+    assert_eq!(
+        desc.expansions[2].replacement.linecol,
+        LineCol { line: 2, col: 8 }
+    );
+
+    assert_eq!(
+        sm.try_describe_location(SourcePos::from_raw(sm.next_offset + 1)),
+        Err(LookupError::OutOfBounds)
+    );
+}
+
+#[test]
+fn try_get_source() {
+    let mut sm = SourceMap::new();
+    let id = sm
+        .create_file(FileName::real("file"), FileContents::new(""), None)
+        .unwrap();
+
+    assert!(sm.try_get_source(id).is_some());
+    assert!(SourceMap::new().try_get_source(id).is_none());
+}
+
+#[test]
+fn try_lookup_pos() {
+    let mut sm = SourceMap::new();
+    assert_eq!(sm.try_lookup_source_id(SourcePos::from_raw(0)), None);
+
+    let id = sm
+        .create_file(FileName::real("file"), FileContents::new("abc"), None)
+        .unwrap();
+    let range = sm.get_source(id).range;
+
+    assert_eq!(sm.try_lookup_source_id(range.start()), Some(id));
+    assert_eq!(
+        sm.try_lookup_source_off(range.subpos(2.into()))
+            .map(|(_, off)| off),
+        Some(2.into())
+    );
+    assert!(sm
+        .try_lookup_source_id(range.start().offset(10.into()))
+        .is_none());
+    assert!(sm
+        .try_lookup_source_off(range.start().offset(10.into()))
+        .is_none());
+}
+
+#[test]
+fn try_lookup_range() {
+    let mut sm = SourceMap::new();
+    let (file_range, exp_a_range, ..) = populate_sm(&mut sm);
+
+    let in_file = file_range.subrange(LocalRange::at(3.into(), 4.into()));
+    assert_eq!(
+        sm.try_lookup_source_range(in_file)
+            .map(|(_, local_range)| local_range),
+        Some(LocalRange::at(3.into(), 4.into()))
+    );
+
+    // Extend one byte past the end of the file source into the expansion.
+    let start = file_range.subpos(3.into());
+    let crossing = SourceRange::new(
+        start,
+        exp_a_range.start().offset_from(start) + LocalOff::from(1),
+    );
+    assert!(sm.try_lookup_source_range(crossing).is_none());
+}
+
+#[test]
+fn checked_queries() {
+    let mut sm = SourceMap::new();
+    let (file_range, exp_a_range, ..) = populate_sm(&mut sm);
+    let past_end = SourcePos::from_raw(sm.next_offset + 1);
+
+    let in_a = exp_a_range.subrange(LocalRange::at(2.into(), 5.into()));
+    assert_eq!(sm.try_get_spelling(in_a), Ok("5 * 2"));
+    assert_eq!(
+        sm.try_get_spelling_pos(in_a.start()),
+        Ok(file_range.subpos(33.into()))
+    );
+    assert_eq!(
+        sm.try_get_spelling_pos(past_end),
+        Err(LookupError::OutOfBounds)
+    );
+    assert_eq!(
+        sm.try_get_spelling(file_range),
+        Err(LookupError::PastContents)
+    );
+
+    let in_file = file_range.subrange(LocalRange::at(44.into(), 5.into()));
+    assert_eq!(
+        sm.try_get_interpreted_range(in_file)
+            .unwrap()
+            .start_linecol(),
+        LineCol { line: 2, col: 4 }
+    );
+    assert_eq!(
+        sm.try_get_interpreted_range(in_a).err(),
+        Some(LookupError::NotFile)
+    );
+    assert_eq!(
+        sm.try_get_interpreted_range(SourceRange::new(file_range.start(), 100.into()))
+            .err(),
+        Some(LookupError::CrossesSources)
+    );
+
+    assert_eq!(
+        sm.try_get_unfragmented_range(FragmentedSourceRange::new(in_file.start(), in_a.end())),
+        Ok(Some(in_file))
+    );
+    assert_eq!(
+        sm.try_get_unfragmented_range(FragmentedSourceRange::new(in_a.start(), past_end)),
+        Err(LookupError::OutOfBounds)
+    );
+}
+
+#[test]
+fn empty_file() {
+    let mut sm = SourceMap::new();
+
+    let empty_id = sm
+        .create_file(FileName::real("empty.c"), FileContents::new(""), None)
+        .unwrap();
+    let next_id = sm
+        .create_file(FileName::real("next.c"), FileContents::new("x"), None)
+        .unwrap();
+
+    // The sentinel position keeps the empty file distinct from its successor.
+    let empty_range = sm.get_source(empty_id).range;
+    assert_eq!(u32::from(empty_range.len()), 1);
+    assert_eq!(sm.lookup_source_id(empty_range.start()), empty_id);
+    assert_eq!(
+        sm.lookup_source_id(sm.get_source(next_id).range.start()),
+        next_id
+    );
+
+    let eof = SourceRange::new(empty_range.start(), 0.into());
+    assert_eq!(sm.get_spelling(eof), "");
+
+    let interp = sm.get_interpreted_range(eof);
+    assert_eq!(interp.filename(), &FileName::real("empty.c"));
+    assert_eq!(interp.start_linecol(), LineCol { line: 0, col: 0 });
+    assert_eq!(interp.end_linecol(), LineCol { line: 0, col: 0 });
+    assert_eq!(
+        interp.line_snippets().collect::<Vec<_>>(),
+        vec![LineSnippet {
+            line: "",
+            line_num: 0,
+            range: LocalRange::at(0.into(), 0.into())
+        }]
+    );
+}
+
+#[test]
+fn empty_range_at_end() {
+    let mut sm = SourceMap::new();
+    let (file_range, ..) = populate_sm(&mut sm);
+
+    // The position just past the last character of the file is its sentinel.
+    let end = file_range.subpos((u32::from(file_range.len()) - 1).into());
+    let eof = SourceRange::new(end, 0.into());
+
+    assert_eq!(sm.get_spelling(eof), "");
+    assert_eq!(sm.get_replacement_range(eof), eof);
+    assert_eq!(
+        sm.get_unfragmented_range(FragmentedSourceRange::new(end, end)),
+        Some(eof)
+    );
+
+    let interp = sm.get_interpreted_range(eof);
+    assert_eq!(interp.start_linecol(), interp.end_linecol());
+    assert_eq!(interp.line_snippets().count(), 1);
+}
+
+#[test]
+fn line_overrides() {
+    let mut sm = SourceMap::new();
+    let id = sm
+        .create_file(
+            FileName::real("file.c"),
+            FileContents::new("a\n#line 10\nb\nc\n#line 20 \"other.c\"\nd\n#line 30\ne"),
+            None,
+        )
+        .unwrap();
+    let range = sm.get_source(id).range;
+
+    sm.add_line_override(range.subpos(11.into()), 9, None);
+    sm.add_line_override(range.subpos(34.into()), 19, Some(FileName::real("other.c")));
+    sm.add_line_override(range.subpos(45.into()), 29, None);
+
+    let presumed = |off: u32| {
+        let interp = sm.get_interpreted_range(SourceRange::new(range.subpos(off.into()), 0.into()));
+        (
+            interp.presumed_filename().to_string(),
+            interp.presumed_start_linecol(),
+        )
+    };
+
+    assert_eq!(presumed(0), ("file.c".into(), LineCol { line: 0, col: 0 }));
+    assert_eq!(presumed(11), ("file.c".into(), LineCol { line: 9, col: 0 }));
+    assert_eq!(
+        presumed(13),
+        ("file.c".into(), LineCol { line: 10, col: 0 })
+    );
+    assert_eq!(
+        presumed(34),
+        ("other.c".into(), LineCol { line: 19, col: 0 })
+    );
+    assert_eq!(
+        presumed(46),
+        ("other.c".into(), LineCol { line: 29, col: 1 })
+    );
+
+    // Spellings and physical locations are unaffected.
+    let interp = sm.get_interpreted_range(SourceRange::new(range.subpos(13.into()), 1.into()));
+    assert_eq!(interp.filename(), &FileName::real("file.c"));
+    assert_eq!(interp.start_linecol(), LineCol { line: 3, col: 0 });
+}
+
+#[test]
+fn lookup_pos_by_name() {
+    let mut sm = SourceMap::new();
+    let main = sm
+        .create_file(
+            FileName::real("main.c"),
+            FileContents::new("#include \"a.h\"\n#include \"a.h\"\n"),
+            None,
+        )
+        .unwrap();
+    let main_range = sm.get_source(main).range;
+
+    let header = FileContents::new("int a;\nint b;");
+    let first = sm
+        .create_file(
+            FileName::real("a.h"),
+            Lrc::clone(&header),
+            Some(main_range.subpos(0.into())),
+        )
+        .unwrap();
+    let second = sm
+        .create_file(
+            FileName::real("a.h"),
+            header,
+            Some(main_range.subpos(15.into())),
+        )
+        .unwrap();
+
+    let name = FileName::real("a.h");
+    assert_eq!(
+        sm.file_sources_named(&name).collect::<Vec<_>>(),
+        [first, second]
+    );
+    assert_eq!(sm.file_sources_named(&FileName::real("b.h")).count(), 0);
+
+    let linecol = LineCol { line: 1, col: 4 };
+    assert_eq!(
+        sm.lookup_pos(&name, linecol),
+        Some(sm.get_source(first).range.subpos(11.into()))
+    );
+    assert_eq!(
+        sm.lookup_file_pos(second, linecol),
+        Some(sm.get_source(second).range.subpos(11.into()))
+    );
+    assert_eq!(
+        sm.get_interpreted_range(sm.lookup_pos(&name, linecol).unwrap().into())
+            .start_linecol(),
+        linecol
+    );
+
+    assert_eq!(sm.lookup_pos(&name, LineCol { line: 2, col: 0 }), None);
+    assert_eq!(sm.lookup_pos(&name, LineCol { line: 0, col: 8 }), None);
+    assert_eq!(
+        sm.lookup_pos(&FileName::real("b.h"), LineCol { line: 0, col: 0 }),
+        None
+    );
+}
+
+#[test]
+fn tu_order_includes() {
+    let mut sm = SourceMap::new();
+
+    let main_id = sm
+        .create_file(
+            FileName::real("main.c"),
+            FileContents::new("a\n#include \"h.h\"\nb\n#include \"h.h\"\nc"),
+            None,
+        )
+        .unwrap();
+    let main_range = sm.get_source(main_id).range;
+    let main_pos = |off: u32| main_range.subpos(off.into());
+
+    // `h.h` is included twice, and includes `g.h` every time.
+    let header = FileContents::new("x\n#include \"g.h\"\ny");
+    let inner = FileContents::new("z");
+
+    let mut include = |include_pos| {
+        let header_id = sm
+            .create_file(
+                FileName::real("h.h"),
+                Lrc::clone(&header),
+                Some(include_pos),
+            )
+            .unwrap();
+        let header_range = sm.get_source(header_id).range;
+        let inner_id = sm
+            .create_file(
+                FileName::real("g.h"),
+                Lrc::clone(&inner),
+                Some(header_range.subpos(11.into())),
+            )
+            .unwrap();
+        (header_range, sm.get_source(inner_id).range)
+    };
+
+    let (first_header, first_inner) = include(main_pos(11));
+    let (second_header, second_inner) = include(main_pos(28));
+
+    let ordered = [
+        main_pos(0),
+        main_pos(11),
+        first_header.subpos(0.into()),
+        first_header.subpos(11.into()),
+        first_inner.subpos(0.into()),
+        first_header.subpos(17.into()),
+        main_pos(17),
+        main_pos(28),
+        second_header.subpos(0.into()),
+        second_inner.subpos(0.into()),
+        second_header.subpos(17.into()),
+        main_pos(34),
+    ];
+
+    for (i, &a) in ordered.iter().enumerate() {
+        for (j, &b) in ordered.iter().enumerate() {
+            assert_eq!(sm.tu_order(a, b), i.cmp(&j), "{} vs. {}", i, j);
+            assert_eq!(sm.is_before_in_tu(a, b), i < j);
+        }
+    }
+
+    // Raw positions would place every header after all of the main file.
+    assert!(first_header.subpos(0.into()) > main_pos(34));
+}
+
+#[test]
+fn tu_order_expansions() {
+    let mut sm = SourceMap::new();
+    let (file_range, exp_a_range, exp_b_range, exp_b_x_range) = populate_sm(&mut sm);
+
+    // `int x = A;`, where `A` expands to `B(5 * 2)` and then to `(5 * 2 + 3)`.
+    let ordered = [
+        file_range.subpos(44.into()),
+        exp_a_range.subpos(0.into()),
+        exp_b_range.subpos(0.into()),
+        exp_b_x_range.subpos(0.into()),
+        exp_b_x_range.subpos(4.into()),
+        exp_b_range.subpos(3.into()),
+        file_range.subpos(49.into()),
+    ];
+
+    for (i, &a) in ordered.iter().enumerate() {
+        for (j, &b) in ordered.iter().enumerate() {
+            assert_eq!(sm.tu_order(a, b), i.cmp(&j), "{} vs. {}", i, j);
+        }
+    }
+
+    // A position inside an expansion is ordered after the replacement position itself.
+    assert!(sm.is_before_in_tu(file_range.subpos(48.into()), exp_a_range.subpos(0.into())));
+}
+
+#[test]
+fn tu_order_unrelated_sources() {
+    let mut sm = SourceMap::new();
+
+    let first = sm
+        .create_file(FileName::real("a.c"), FileContents::new("aaa"), None)
+        .unwrap();
+    let second = sm
+        .create_file(FileName::real("b.c"), FileContents::new("b"), None)
+        .unwrap();
+
+    let first_pos = sm.get_source(first).range.subpos(2.into());
+    let second_pos = sm.get_source(second).range.subpos(0.into());
+
+    assert_eq!(sm.tu_order(first_pos, second_pos), Ordering::Less);
+    assert_eq!(sm.tu_order(second_pos, first_pos), Ordering::Greater);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_round_trip() {
+    let mut sm = SourceMap::new();
+    let (file_range, exp_a_range, _, exp_b_x_range) = populate_sm(&mut sm);
+
+    let header = FileContents::new("int a;\nint b;");
+    for _ in 0..2 {
+        sm.create_file(
+            FileName::real("a.h"),
+            Lrc::clone(&header),
+            Some(file_range.subpos(0.into())),
+        )
+        .unwrap();
+    }
+    sm.add_line_override(file_range.subpos(21.into()), 9, Some(FileName::real("b.c")));
+
+    let json = serde_json::to_string(&sm).unwrap();
+    let restored: SourceMap = serde_json::from_str(&json).unwrap();
+
+    assert_eq!(restored.sources.len(), sm.sources.len());
+    for (orig, restored) in sm.sources.iter().zip(&restored.sources) {
+        assert_eq!(orig.range, restored.range);
+    }
+
+    // Contents shared between sources are serialized once and remain shared.
+    let headers: Vec<_> = restored
+        .file_sources_named(&FileName::real("a.h"))
+        .map(|id| Lrc::clone(&restored.get_source(id).as_file().unwrap().contents))
+        .collect();
+    assert_eq!(headers.len(), 2);
+    assert!(Lrc::ptr_eq(&headers[0], &headers[1]));
+    assert_eq!(headers[0].src(), header.src());
+
+    let pos = exp_b_x_range.subpos(0.into());
+    assert_eq!(restored.get_spelling_pos(pos), sm.get_spelling_pos(pos));
+    assert_eq!(
+        restored.get_replacement_range(exp_a_range.subrange(LocalRange::at(0.into(), 1.into()))),
+        file_range.subrange(LocalRange::at(48.into(), 1.into()))
+    );
+
+    let interp = restored.get_interpreted_range(file_range.subpos(48.into()).into());
+    assert_eq!(interp.presumed_filename(), &FileName::real("b.c"));
+    assert_eq!(
+        interp.presumed_start_linecol(),
+        LineCol { line: 10, col: 8 }
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_invalid() {
+    let invalid = [
+        r#"{"contents": [], "sources": [{"File": {"filename": {"Real": "a.c"}, "contents": 0, "include_pos": null, "line_overrides": []}}]}"#,
+        r#"{"contents": ["a"], "sources": [{"File": {"filename": {"Real": "a.c"}, "contents": 0, "include_pos": 5, "line_overrides": []}}]}"#,
+        r#"{"contents": ["a"], "sources": [{"Expansion": {"spelling_range": [0, 1], "replacement_range": [0, 5], "kind": "Macro"}}]}"#,
+    ];
+
+    for json in &invalid {
+        assert!(serde_json::from_str::<SourceMap>(json).is_err(), "{}", json);
+    }
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn source_map_is_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<SourceMap>();
+    assert_send_sync::<Lrc<FileContents>>();
+}
+
+#[test]
+fn offset_space_exhaustion() {
+    let mut sm = SourceMap::new();
+    sm.next_offset = RawPos::from(u32::MAX - 10);
+
+    let result = sm.create_file(
+        FileName::real("file"),
+        FileContents::new("0123456789abcdef"),
+        None,
+    );
+
+    if cfg!(feature = "wide-positions") {
+        let id = result.unwrap();
+        let range = sm.get_source(id).range;
+        assert_eq!(sm.lookup_source_id(range.end()), id);
+        assert_eq!(range.end().offset_from(range.start()), 17.into());
+        assert_eq!(
+            sm.get_spelling(SourceRange::new(range.start().offset(10.into()), 6.into())),
+            "abcdef"
+        );
+    } else {
+        assert!(result.is_err());
+    }
+}
+
+#[test]
+fn source_ids() {
+    let mut sm = SourceMap::new();
+    assert_eq!(sm.source_count(), 0);
+    assert_eq!(sm.source_ids().next(), None);
+
+    let (file_range, ..) = populate_sm(&mut sm);
+    let file_id = sm.lookup_source_id(file_range.start());
+
+    assert_eq!(sm.source_count(), 4);
+    let ids: Vec<_> = sm.source_ids().collect();
+    assert_eq!(ids.len(), 4);
+    assert_eq!(ids[0], file_id);
+    assert!(ids.iter().all(|&id| sm.try_get_source(id).is_some()));
+}
+
+#[test]
+fn source_id_vec_sync() {
+    let mut sm = SourceMap::new();
+    let (file_range, ..) = populate_sm(&mut sm);
+
+    let mut is_file = SourceIdVec::from_fn(&sm, |_, source| source.is_file());
+    assert_eq!(is_file.len(), 4);
+    assert!(is_file[sm.lookup_source_id(file_range.start())]);
+    assert_eq!(is_file.iter().filter(|&(_, &file)| file).count(), 1);
+
+    let other = sm
+        .create_file(FileName::real("other.c"), FileContents::new("x"), None)
+        .unwrap();
+    assert_eq!(is_file.get(other), None);
+
+    is_file.sync(&sm, |_, source| source.is_file());
+    assert_eq!(is_file.len(), 5);
+    assert!(is_file[other]);
+}
+
+#[test]
+fn source_id_map() {
+    let mut sm = SourceMap::new();
+    let (file_range, ..) = populate_sm(&mut sm);
+    let file_id = sm.lookup_source_id(file_range.start());
+
+    let mut guards = SourceIdMap::new();
+    assert!(guards.is_empty());
+    assert_eq!(guards.get(file_id), None);
+
+    let other = sm
+        .create_file(FileName::real("other.h"), FileContents::new("x"), None)
+        .unwrap();
+    assert_eq!(guards.insert(other, "OTHER_H"), None);
+    assert_eq!(guards.insert(other, "OTHER_H_"), Some("OTHER_H"));
+    assert_eq!(guards.len(), 1);
+    assert!(!guards.contains(file_id));
+
+    *guards.get_or_insert_with(file_id, || "FILE_H") = "MAIN_H";
+    assert_eq!(
+        guards.iter().collect::<Vec<_>>(),
+        [(file_id, &"MAIN_H"), (other, &"OTHER_H_")]
+    );
+
+    assert_eq!(guards.remove(other), Some("OTHER_H_"));
+    assert_eq!(guards.remove(other), None);
+    assert_eq!(guards.len(), 1);
+}
+
+#[test]
+fn file_sources_and_stats() {
+    let mut sm = SourceMap::new();
+    assert_eq!(sm.stats(), SourceMapStats::default());
+
+    let (file_range, ..) = populate_sm(&mut sm);
+    let file_id = sm.lookup_source_id(file_range.start());
+    let header_id = sm
+        .create_file(
+            FileName::real("header.h"),
+            FileContents::new("int y;\n"),
+            Some(file_range.start()),
+        )
+        .unwrap();
+
+    let files: Vec<_> = sm
+        .file_sources()
+        .map(|(id, file)| (id, file.filename.to_string()))
+        .collect();
+    assert_eq!(
+        files,
+        [
+            (file_id, "file.c".to_owned()),
+            (header_id, "header.h".to_owned())
+        ]
+    );
+
+    assert_eq!(
+        sm.stats(),
+        SourceMapStats {
+            file_sources: 2,
+            expansion_sources: 3,
+            file_bytes: 57,
+            expansion_bytes: 20,
+            mapped_bytes: 82,
+        }
+    );
+}
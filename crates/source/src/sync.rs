@@ -0,0 +1,21 @@
+//! Shared ownership for data referenced by the source map.
+//!
+//! By default, [`Lrc`] is [`Rc`](std::rc::Rc). Enabling the `sync` feature makes it
+//! [`Arc`](std::sync::Arc) instead, so that a [`SourceMap`](crate::SourceMap) and the file contents
+//! it references can be sent to and shared with other threads.
+
+#[cfg(not(feature = "sync"))]
+pub use std::rc::Rc as Lrc;
+
+#[cfg(feature = "sync")]
+pub use std::sync::Arc as Lrc;
+
+/// A cell initialized at most once, used for data computed lazily from shared file contents.
+///
+/// This is [`std::cell::OnceCell`] by default, and [`std::sync::OnceLock`] when the `sync` feature
+/// is enabled.
+#[cfg(not(feature = "sync"))]
+pub use std::cell::OnceCell;
+
+#[cfg(feature = "sync")]
+pub use std::sync::OnceLock as OnceCell;
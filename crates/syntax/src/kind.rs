@@ -1,52 +1,6 @@
 use lex::Interner;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Keyword {
-    Alignof,
-    Auto,
-    Break,
-    Case,
-    Char,
-    Const,
-    Continue,
-    Default,
-    Do,
-    Double,
-    Else,
-    Enum,
-    Extern,
-    Float,
-    For,
-    Goto,
-    If,
-    Inline,
-    Int,
-    Long,
-    Register,
-    Restrict,
-    Return,
-    Short,
-    Signed,
-    Sizeof,
-    Static,
-    Struct,
-    Switch,
-    Typedef,
-    Union,
-    Unsigned,
-    Void,
-    Volatile,
-    While,
-    Alignas,
-    Atomic,
-    Bool,
-    Complex,
-    Generic,
-    Imaginary,
-    Noreturn,
-    StaticAssert,
-    ThreadLocal,
-}
+pub use lex::Keyword;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TokenKind {
@@ -61,55 +15,10 @@ impl TokenKind {
             _ => return Self::Plain(plain),
         };
 
-        let kw = match &interner[ident] {
-            "alignof" => Keyword::Alignof,
-            "auto" => Keyword::Auto,
-            "break" => Keyword::Break,
-            "case" => Keyword::Case,
-            "char" => Keyword::Char,
-            "const" => Keyword::Const,
-            "continue" => Keyword::Continue,
-            "default" => Keyword::Default,
-            "do" => Keyword::Do,
-            "double" => Keyword::Double,
-            "else" => Keyword::Else,
-            "enum" => Keyword::Enum,
-            "extern" => Keyword::Extern,
-            "float" => Keyword::Float,
-            "for" => Keyword::For,
-            "goto" => Keyword::Goto,
-            "if" => Keyword::If,
-            "inline" => Keyword::Inline,
-            "int" => Keyword::Int,
-            "long" => Keyword::Long,
-            "register" => Keyword::Register,
-            "restrict" => Keyword::Restrict,
-            "return" => Keyword::Return,
-            "short" => Keyword::Short,
-            "signed" => Keyword::Signed,
-            "sizeof" => Keyword::Sizeof,
-            "static" => Keyword::Static,
-            "struct" => Keyword::Struct,
-            "switch" => Keyword::Switch,
-            "typedef" => Keyword::Typedef,
-            "union" => Keyword::Union,
-            "unsigned" => Keyword::Unsigned,
-            "void" => Keyword::Void,
-            "volatile" => Keyword::Volatile,
-            "while" => Keyword::While,
-            "_Alignas" => Keyword::Alignas,
-            "_Atomic" => Keyword::Atomic,
-            "_Bool" => Keyword::Bool,
-            "_Complex" => Keyword::Complex,
-            "_Generic" => Keyword::Generic,
-            "_Imaginary" => Keyword::Imaginary,
-            "_Noreturn" => Keyword::Noreturn,
-            "_Static_assert" => Keyword::StaticAssert,
-            "_Thread_local" => Keyword::ThreadLocal,
-            _ => return Self::Plain(plain),
-        };
-
-        Self::Keyword(kw)
+        match Keyword::from_symbol(ident, interner) {
+            Some(kw) => Self::Keyword(kw),
+            None => Self::Plain(plain),
+        }
     }
 }
 
@@ -153,10 +62,15 @@ pub enum NodeKind {
     // Declarators
     IdentDeclarator,
     ParenDeclarator,
+    PtrDeclarator,
     ArrayDeclarator,
     FunctionDeclarator,
 
     ParamList,
+    ParamDecl,
+
+    // Type Names
+    TypeName,
 
     // Initializers
     StructInitList,
@@ -210,6 +124,9 @@ pub enum NodeKind {
     AssignmentExpr,
 
     ArgList,
+
+    // Tokens skipped while recovering from a syntax error
+    Error,
 }
 
 #[cfg(test)]
@@ -236,18 +153,18 @@ mod tests {
             );
         }
 
-        let mut interner = Interner::new();
+        for mut interner in [Interner::new(), lex::keyword_interner()] {
+            check_plain(lex::TokenKind::Unknown, &interner);
+            check_plain(lex::TokenKind::Eof, &interner);
+            check_plain(lex::TokenKind::Punct(PunctKind::Comma), &interner);
 
-        check_plain(lex::TokenKind::Unknown, &interner);
-        check_plain(lex::TokenKind::Eof, &interner);
-        check_plain(lex::TokenKind::Punct(PunctKind::Comma), &interner);
+            check_plain(lex::TokenKind::Number(interner.intern("3")), &interner);
+            check_plain(lex::TokenKind::Str(interner.intern(r#""hi""#)), &interner);
+            check_plain(lex::TokenKind::Char(interner.intern("'c'")), &interner);
 
-        check_plain(lex::TokenKind::Number(interner.intern("3")), &interner);
-        check_plain(lex::TokenKind::Str(interner.intern(r#""hi""#)), &interner);
-        check_plain(lex::TokenKind::Char(interner.intern("'c'")), &interner);
-
-        check_kw("if", Keyword::If, &mut interner);
-        check_kw("while", Keyword::While, &mut interner);
-        check_kw("for", Keyword::For, &mut interner);
+            check_kw("if", Keyword::If, &mut interner);
+            check_kw("while", Keyword::While, &mut interner);
+            check_kw("for", Keyword::For, &mut interner);
+        }
     }
 }
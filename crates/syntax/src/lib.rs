@@ -2,9 +2,10 @@
 
 use source::FragmentedSourceRange;
 
-pub use builder::TreeBuilder;
+pub use builder::{Checkpoint, TreeBuilder};
 pub use kind::*;
 
+#[allow(dead_code)] // Not wired up to the parser yet.
 mod ast;
 mod builder;
 mod kind;
@@ -0,0 +1,100 @@
+//! Preprocesses arbitrary input, split at NUL bytes into a main file and headers that it can
+//! include as `"0.h"`, `"1.h"` and so on (or embed).
+
+#![no_main]
+
+use std::path::Path;
+
+use libfuzzer_sys::fuzz_target;
+
+use lex::{Interner, LexCtx, TokenKind};
+use pp::{
+    DirectoryResolver, File, FileCache, InMemoryFileSystem, IncludeError, IncludeKind,
+    IncludeResolver, PreprocessorBuilder, ResolvedInclude,
+};
+use source::diag::{RenderedDiagnostic, RenderedSink};
+use source::smap::{FileContents, FileName};
+use source::{DiagManager, FrontendLimits, SourceMap};
+
+/// The maximum number of include directives to resolve, so that recursive includes do not exhaust
+/// memory.
+const MAX_INCLUDES: u32 = 64;
+
+/// A sink discarding all diagnostics after they have been rendered.
+struct NullSink;
+
+impl RenderedSink for NullSink {
+    fn report(&mut self, _diag: &RenderedDiagnostic, _smap: Option<&SourceMap>) {}
+}
+
+/// A resolver that stops finding files after [`MAX_INCLUDES`] includes.
+struct BoundedResolver {
+    inner: DirectoryResolver,
+    remaining: u32,
+}
+
+impl IncludeResolver for BoundedResolver {
+    fn resolve(
+        &mut self,
+        filename: &Path,
+        kind: IncludeKind,
+        includer: &File,
+        next_from: Option<usize>,
+        cache: &mut FileCache,
+    ) -> Result<ResolvedInclude, IncludeError> {
+        if self.remaining == 0 {
+            return Err(IncludeError::NotFound);
+        }
+
+        self.remaining -= 1;
+        self.inner
+            .resolve(filename, kind, includer, next_from, cache)
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut parts = data.split(|&b| b == 0);
+    let main = parts.next().unwrap_or_default();
+
+    let mut fs = InMemoryFileSystem::new();
+    for (i, header) in parts.enumerate() {
+        fs.add_file(format!("{}.h", i), header);
+    }
+
+    let mut diags = DiagManager::new(NullSink, None);
+    let mut interner = Interner::new();
+    let mut smap = SourceMap::new();
+    let main_id = match smap.create_file(
+        FileName::synth("fuzz.c"),
+        FileContents::decode(main.to_vec()),
+        None,
+    ) {
+        Ok(id) => id,
+        Err(_) => return,
+    };
+
+    let mut ctx = LexCtx::new(&mut interner, &mut diags, &mut smap);
+    ctx.limits = FrontendLimits {
+        token_limit: Some(100_000),
+        max_embed_size: 1 << 16,
+        ..FrontendLimits::default()
+    };
+
+    let mut pp = PreprocessorBuilder::new(&mut ctx, main_id)
+        .parent_dir("".into())
+        .include_dirs(vec!["".into()])
+        .file_cache(FileCache::with_file_system(fs.clone()))
+        .include_resolver(BoundedResolver {
+            inner: DirectoryResolver::new(vec!["".into()], Vec::new()),
+            remaining: MAX_INCLUDES,
+        })
+        .resource_loader(fs)
+        .build();
+
+    while let Ok(ppt) = pp.next_pp(&mut ctx) {
+        if ppt.data() == TokenKind::Eof {
+            break;
+        }
+        let _ = ppt.tok.display(&ctx).to_string();
+    }
+});
@@ -0,0 +1,48 @@
+//! Tokenizes arbitrary input with every lexer configuration, checking that the tokens cover the
+//! input exactly and that skipping to directives agrees with tokenizing.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use lex::raw::{Dialect, LexerConfig, RawTokenKind, Tokenizer};
+
+fuzz_target!(|data: &[u8]| {
+    let (flags, src) = match data.split_first() {
+        Some((&flags, rest)) => (flags, String::from_utf8_lossy(rest)),
+        None => return,
+    };
+
+    let config = LexerConfig {
+        dialect: if flags & 1 != 0 {
+            Dialect::Cpp
+        } else {
+            Dialect::C
+        },
+        trigraphs: flags & 2 != 0,
+        digraphs: flags & 4 != 0,
+        line_comments: flags & 8 != 0,
+        u8_char_literals: flags & 16 != 0,
+    };
+
+    let mut tokenizer = Tokenizer::with_config(&src, config);
+    let mut end = 0;
+    loop {
+        let tok = tokenizer.next_token();
+        assert_eq!(usize::from(tok.content.off), end);
+        end += tok.content.str.len();
+
+        let _ = tok.content.cleaned_str();
+        if tok.kind == RawTokenKind::Eof {
+            break;
+        }
+    }
+    assert_eq!(end, src.len());
+
+    let mut tokenizer = Tokenizer::with_config(&src, config);
+    let mut line_start = true;
+    while let Some(off) = tokenizer.skip_to_directive(&mut line_start) {
+        assert!(usize::from(off) < src.len());
+        line_start = false;
+    }
+});